@@ -206,6 +206,16 @@ pub fn recv_nb(channel: &str) -> CResult<Option<Vec<u8>>> {
     res
 }
 
+/// How many messages are sitting on a channel waiting to be received
+pub fn pending(channel: &str) -> u32 {
+    let channel = String::from(channel);
+    if !(*CONN).exists(&channel) {
+        return 0;
+    }
+    let num = (*CONN).ensure(&channel).num_messages();
+    if num < 0 { 0 } else { num as u32 }
+}
+
 /// Returns the number of active channels
 pub fn count() -> u32 {
     (*CONN).count()
@@ -240,6 +250,18 @@ mod tests {
         assert_eq!(next, None);
     }
 
+    #[test]
+    fn counts_pending() {
+        assert_eq!(pending("pending"), 0);
+        send_string("pending", String::from("one")).unwrap();
+        send_string("pending", String::from("two")).unwrap();
+        assert_eq!(pending("pending"), 2);
+        recv_nb("pending").unwrap();
+        assert_eq!(pending("pending"), 1);
+        recv("pending").unwrap();
+        assert_eq!(pending("pending"), 0);
+    }
+
     #[test]
     fn recv_blocking() {
         let handle = thread::spawn(move || {
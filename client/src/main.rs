@@ -92,12 +92,17 @@ fn main() {
     if env::var("TURTL_CONFIG_FILE").is_err() {
         env::set_var("TURTL_CONFIG_FILE", "../config.yaml");
     }
-    let handle = turtl_core::start(String::from(r#"{"messaging":{"reqres_append_mid":false}}"#));
+    let safe_mode = env::args().any(|x| x == "--safe-mode");
+    let runtime_config = format!(r#"{{"messaging":{{"reqres_append_mid":false}},"safe_mode":{}}}"#, safe_mode);
+    let handle = turtl_core::start(runtime_config);
 
     sleep(1000);
     println!("");
     println!("");
     println!("Welcome to the Turtl Client.");
+    if safe_mode {
+        println!("(running in safe mode: no sync, no search)");
+    }
     println!("");
     match repl() {
         Ok(_) => {},
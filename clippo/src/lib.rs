@@ -1,3 +1,4 @@
+extern crate ego_tree;
 extern crate fern;
 extern crate jedi;
 #[macro_use]
@@ -21,7 +22,9 @@ use ::error::{CResult, CError};
 use ::std::env;
 use ::std::io::Read;
 use ::url::Url;
-use ::scraper::{Html, Selector};
+use ::scraper::{Html, Selector, ElementRef};
+use ::scraper::node::Node;
+use ::ego_tree::{NodeId, NodeRef};
 use ::regex::Regex;
 use ::std::path::PathBuf;
 use ::std::fs::File;
@@ -113,6 +116,14 @@ impl ClipResult {
     }
 }
 
+/// A helpful function to parse CSS selectors and convert them to CResult
+/// objects. we can't really implement From::from() for selector errors
+/// since the error objects are just (), so we localize the conversion here.
+fn parse_selector(sel: &str) -> CResult<Selector> {
+    Selector::parse(sel)
+        .map_err(|_| CError::Selector(format!("cannot parse selector {}", sel)))
+}
+
 /// Convert a URL to HTML
 fn grab_url(url: &String, proxy: Option<String>) -> CResult<String> {
     let mut client_builder = reqwest::blocking::Client::builder();
@@ -154,14 +165,6 @@ fn grab_url(url: &String, proxy: Option<String>) -> CResult<String> {
 pub fn clip(url: &String, parsers: &Vec<CustomParser>, proxy: Option<String>) -> CResult<ClipResult> {
     let html = grab_url(url, proxy)?;
 
-    /// A helpful function to parse CSS selectors and convert them to CResult
-    /// objects. we can't really implement From::from() for selector errors
-    /// since the error objects are just (), so we localize the conversion here.
-    fn parse_selector(sel: &str) -> CResult<Selector> {
-        Selector::parse(sel)
-            .map_err(|_| CError::Selector(format!("cannot parse selector {}", sel)))
-    }
-
     // set up our final return objects
     let mut title = None;
     let mut desc = None;
@@ -343,6 +346,129 @@ pub fn clip(url: &String, parsers: &Vec<CustomParser>, proxy: Option<String>) ->
     Ok(ClipResult::new(title, desc, img))
 }
 
+/// The main text of a web page, minus the navigation, ads, comments, etc
+#[derive(Serialize, Debug)]
+pub struct Article {
+    /// The article's title, if we found one
+    pub title: Option<String>,
+    /// The article body as plain text, with paragraphs separated by blank lines
+    /// (and headings, lists, and code blocks marked up like markdown)
+    pub text: String,
+}
+
+/// Text under these tags is never part of the article
+const SKIP_TAGS: &'static [&'static str] = &["nav", "aside", "header", "footer", "form", "script", "style", "noscript", "figcaption", "button"];
+
+/// The elements we pull text out of, in the order they appear
+const BLOCK_TAGS: &'static [&'static str] = &["p", "h1", "h2", "h3", "h4", "h5", "h6", "li", "pre", "blockquote"];
+
+/// Paragraphs shorter than this (in bytes) don't count toward picking where
+/// the article is. Bylines, captions, "share this" links...
+const MIN_PARAGRAPH: usize = 25;
+
+/// Grab an element's text, with runs of whitespace squashed into one space
+fn element_text(el: &ElementRef) -> String {
+    el.text().collect::<String>().split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Whether an element sits inside something that's never part of an article
+fn in_skipped(node: &NodeRef<Node>) -> bool {
+    node.ancestors()
+        .filter_map(ElementRef::wrap)
+        .any(|x| SKIP_TAGS.contains(&x.value().name()))
+}
+
+/// Pull the article text out of a page's HTML. This is a stripped-down version
+/// of what readability does: every decent-sized paragraph gives points to its
+/// parent (and half as many to its grandparent), and the element with the most
+/// points is where the article lives. Paragraphs that are mostly links (menus,
+/// "related articles") don't count.
+pub fn parse_article(html: &str) -> CResult<Article> {
+    let doc = Html::parse_document(html);
+    let sel_p = parse_selector("p")?;
+    let sel_a = parse_selector("a")?;
+    let sel_blocks = parse_selector(BLOCK_TAGS.join(", ").as_str())?;
+
+    // scored in the order we first see them, so ties go to the earlier element
+    let mut scores: Vec<(NodeId, usize)> = Vec::new();
+    {
+        let mut add_score = |id: NodeId, score: usize| {
+            match scores.iter_mut().find(|x| x.0 == id) {
+                Some(entry) => entry.1 += score,
+                None => scores.push((id, score)),
+            }
+        };
+        for p in doc.select(&sel_p) {
+            if in_skipped(&p) { continue; }
+            let text = element_text(&p);
+            if text.len() < MIN_PARAGRAPH { continue; }
+            let link_len: usize = p.select(&sel_a).map(|a| element_text(&a).len()).sum();
+            if link_len * 2 > text.len() { continue; }
+            if let Some(parent) = p.parent() {
+                add_score(parent.id(), text.len());
+                if let Some(grandparent) = parent.parent() {
+                    add_score(grandparent.id(), text.len() / 2);
+                }
+            }
+        }
+    }
+    let mut best: Option<(NodeId, usize)> = None;
+    for &(id, score) in &scores {
+        if best.map(|x| score > x.1).unwrap_or(true) { best = Some((id, score)); }
+    }
+    let root = match best.and_then(|x| doc.tree.get(x.0)).and_then(ElementRef::wrap) {
+        Some(x) => x,
+        None => doc.root_element(),
+    };
+
+    let mut blocks: Vec<String> = Vec::new();
+    for el in root.select(&sel_blocks) {
+        if in_skipped(&el) { continue; }
+        // a paragraph inside a list item or quote was already taken along
+        // with its parent
+        let nested = el.ancestors()
+            .take_while(|x| x.id() != root.id())
+            .filter_map(ElementRef::wrap)
+            .any(|x| BLOCK_TAGS.contains(&x.value().name()));
+        if nested { continue; }
+        let name = el.value().name();
+        if name == "pre" {
+            let code = el.text().collect::<String>();
+            let code = code.trim_matches('\n');
+            if code.trim() != "" { blocks.push(format!("```\n{}\n```", code)); }
+            continue;
+        }
+        let text = element_text(&el);
+        if text == "" { continue; }
+        let block = match name {
+            "h1" | "h2" => format!("## {}", text),
+            "h3" | "h4" | "h5" | "h6" => format!("### {}", text),
+            "li" => format!("- {}", text),
+            "blockquote" => format!("> {}", text),
+            _ => text,
+        };
+        blocks.push(block);
+    }
+
+    let sel_og_title = parse_selector("meta[property=\"og:title\"]")?;
+    let sel_title = parse_selector("head title")?;
+    let title = doc.select(&sel_og_title)
+        .filter_map(|x| x.value().attr("content"))
+        .map(|x| String::from(x.trim()))
+        .chain(doc.select(&sel_title).map(|x| element_text(&x)))
+        .find(|x| x != "");
+    Ok(Article {
+        title: title,
+        text: blocks.join("\n\n"),
+    })
+}
+
+/// Download a page and pull the article out of it
+pub fn extract_article(url: &String, proxy: Option<String>) -> CResult<Article> {
+    let html = grab_url(url, proxy)?;
+    parse_article(html.as_str())
+}
+
 
 #[cfg(test)]
 mod tests {
@@ -360,4 +486,31 @@ mod tests {
         assert_eq!(res.description, Some(String::from("1.I\'m In Your Mind ")));
         assert_eq!(res.image_url, Some(String::from("https://img.youtube.com/vi/1KfaQ6pmv18/hqdefault.jpg")));
     }
+
+    #[test]
+    fn extracts_articles() {
+        let html = r#"<html><head><title>Fallback title</title><meta property="og:title" content="Turtles, ranked"></head><body>
+            <nav><p>Home | About | <a href="/contact">Contact us about anything at all</a></p></nav>
+            <div id="sidebar"><p><a href="/1">A related article you might like</a> <a href="/2">and another</a></p></div>
+            <article>
+                <h1>Turtles, ranked</h1>
+                <p>The box turtle is, by most accounts, the finest turtle there is.</p>
+                <p>Sea turtles are a <b>close</b> second, mostly on account of the swimming.</p>
+                <ul><li><p>Snapping turtles</p></li><li>Mud turtles</li></ul>
+                <pre>let turtle = Turtle::new();
+println!("{}", turtle);</pre>
+                <footer><p>Share this article with everyone you have ever met!</p></footer>
+            </article>
+        </body></html>"#;
+        let article = parse_article(html).unwrap();
+        assert_eq!(article.title, Some(String::from("Turtles, ranked")));
+        assert_eq!(article.text, vec![
+            "## Turtles, ranked",
+            "The box turtle is, by most accounts, the finest turtle there is.",
+            "Sea turtles are a close second, mostly on account of the swimming.",
+            "- Snapping turtles",
+            "- Mud turtles",
+            "```\nlet turtle = Turtle::new();\nprintln!(\"{}\", turtle);\n```",
+        ].join("\n\n"));
+    }
 }
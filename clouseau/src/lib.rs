@@ -10,6 +10,7 @@ extern crate rusqlite;
 
 use ::std::error::Error;
 use ::std::mem;
+use ::std::time::Duration;
 
 use ::rusqlite::{Connection, NO_PARAMS};
 
@@ -97,6 +98,8 @@ type CResult<T> = Result<T, CError>;
 pub struct Clouseau {
     /// Holds our sqlite connection DUUHHHHH
     pub conn: Connection,
+    /// If we're backed by a file (as opposed to living in memory), its path
+    location: Option<String>,
 }
 
 impl Clouseau {
@@ -105,11 +108,47 @@ impl Clouseau {
     pub fn new() -> CResult<Clouseau> {
         let conn = Connection::open_in_memory()?;
         conn.execute("CREATE VIRTUAL TABLE objects USING fts4 (id VARCHAR(64) PRIMARY KEY, content TEXT)", NO_PARAMS)?;
+        conn.execute("CREATE VIRTUAL TABLE objects_terms USING fts4aux (objects)", NO_PARAMS)?;
         Ok(Clouseau {
             conn: conn,
+            location: None,
         })
     }
 
+    /// Open a file-backed index that's memory-mapped instead of held on the
+    /// heap. Pages get mapped in as queries touch them, so resident memory
+    /// stays low even for big indexes. We use WAL mode so writes are appends,
+    /// and let fts4 automerge its segments as we go.
+    pub fn open(location: &str, mmap_size: i64) -> CResult<Clouseau> {
+        let conn = Connection::open(location)?;
+        conn.query_row("PRAGMA journal_mode = WAL", NO_PARAMS, |row| row.get::<_, String>(0))?;
+        conn.query_row(&format!("PRAGMA mmap_size = {}", mmap_size), NO_PARAMS, |row| row.get::<_, i64>(0))?;
+        conn.execute("CREATE VIRTUAL TABLE IF NOT EXISTS objects USING fts4 (id VARCHAR(64) PRIMARY KEY, content TEXT)", NO_PARAMS)?;
+        conn.execute("CREATE VIRTUAL TABLE IF NOT EXISTS objects_terms USING fts4aux (objects)", NO_PARAMS)?;
+        conn.execute("INSERT INTO objects (objects) VALUES ('automerge=8')", NO_PARAMS)?;
+        Ok(Clouseau {
+            conn: conn,
+            location: Some(String::from(location)),
+        })
+    }
+
+    /// Where this index lives on disk (None if in-memory)
+    pub fn location(&self) -> Option<&String> {
+        self.location.as_ref()
+    }
+
+    /// Compact a file-backed index: merge some of the fts segments and fold
+    /// the WAL back into the main file. This opens its own connection so it
+    /// can be run from a background thread while the index is in use.
+    pub fn compact_file(location: &str) -> CResult<()> {
+        let conn = Connection::open(location)?;
+        conn.busy_timeout(Duration::from_secs(5))?;
+        conn.execute("INSERT INTO objects (objects) VALUES ('merge=200,8')", NO_PARAMS)?;
+        conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", NO_PARAMS, |row| row.get::<_, i64>(0))?;
+        conn.close()?;
+        Ok(())
+    }
+
     /// Index an object
     pub fn index(&self, id: &String, body: &String) -> CResult<()> {
         self.conn.execute("INSERT OR REPLACE INTO objects (id, content) VALUES (?, ?)", &[id, body])?;
@@ -133,6 +172,23 @@ impl Clouseau {
         Ok(ids)
     }
 
+    /// How many distinct terms are in the index
+    pub fn term_count(&self) -> CResult<i64> {
+        Ok(self.conn.query_row("SELECT COUNT(*) FROM objects_terms WHERE col = '*'", NO_PARAMS, |row| row.get(0))?)
+    }
+
+    /// How many bytes the index takes up on disk (0 if in-memory)
+    pub fn disk_size(&self) -> u64 {
+        let location = match self.location.as_ref() {
+            Some(x) => x,
+            None => return 0,
+        };
+        ["", "-wal"].iter()
+            .filter_map(|ext| ::std::fs::metadata(format!("{}{}", location, ext)).ok())
+            .map(|meta| meta.len())
+            .sum()
+    }
+
     /// Close this Clouseau instance
     pub fn close(&mut self) -> CResult<()> {
         let mut conn = Connection::open_in_memory()?;
@@ -145,12 +201,45 @@ impl Clouseau {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use ::std::env;
+    use ::std::process;
+    use ::std::time::{SystemTime, UNIX_EPOCH};
 
     #[test]
     fn sqlite_has_ft() {
         Clouseau::new().unwrap();
     }
 
+    #[test]
+    fn counts_terms() {
+        let search = Clouseau::new().unwrap();
+        assert_eq!(search.term_count().unwrap(), 0);
+        search.index(&String::from("1234"), &String::from("some say your nose")).unwrap();
+        search.index(&String::from("2222"), &String::from("some say your toes")).unwrap();
+        assert_eq!(search.term_count().unwrap(), 5);
+        assert_eq!(search.disk_size(), 0);
+    }
+
+    #[test]
+    fn searches_mmapped_index() {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().subsec_nanos();
+        let location = env::temp_dir().join(format!("clouseau-mmap-{}-{}.idx", process::id(), nanos));
+        let location = String::from(location.to_string_lossy());
+        let location = location.as_str();
+        let mut search = Clouseau::open(location, 1024 * 1024).unwrap();
+        assert_eq!(search.location(), Some(&String::from(location)));
+        search.index(&String::from("1234"), &String::from("some say your nose")).unwrap();
+        search.index(&String::from("2222"), &String::from("some say your toes")).unwrap();
+        Clouseau::compact_file(location).unwrap();
+        assert_eq!(search.find(&String::from("some say")).unwrap(), vec![String::from("1234"), String::from("2222")]);
+        search.unindex(&String::from("1234")).unwrap();
+        assert_eq!(search.find(&String::from("nose")).unwrap().len(), 0);
+        search.close().unwrap();
+        for ext in &["", "-wal", "-shm"] {
+            let _ = ::std::fs::remove_file(format!("{}{}", location, ext));
+        }
+    }
+
     #[test]
     fn searches_things() {
         let search = Clouseau::new().unwrap();
@@ -104,6 +104,35 @@ TURTL_EXPORT const uint8_t* TURTL_CONV turtlc_recv_event(uint8_t, size_t*);
 // by calling `turtlc_free()` on them.
 TURTL_EXPORT int32_t TURTL_CONV turtlc_free(const uint8_t*, size_t);
 
+// -----------------------------------------------------------------------------
+// turtlc_start_direct(json_config, msg_cb) -> i32
+//   json_config:
+//     a C string (null-terminated) holding JSON configuration
+//   msg_cb(channel, msg_bytes, msg_len):
+//     called with each response/event the core sends out, along with the
+//     channel it would have gone out on (responses go on
+//     "<messaging.reqres>-core-out", events on "<messaging.events>")
+//   -> returns 0 on success
+// -----------------------------------------------------------------------------
+// Starts the core without its messaging thread, for platforms (iOS) that don't
+// want a thread blocking on `turtlc_recv()`. Send messages in with
+// `turtlc_send_direct()`. The pointers passed to `msg_cb` belong to the core
+// and are only good until it returns, so copy anything you want to keep.
+// `msg_cb` can be called from any thread.
+TURTL_EXPORT int32_t TURTL_CONV turtlc_start_direct(const char*, void (*)(const char*, const uint8_t*, size_t));
+
+// -----------------------------------------------------------------------------
+// turtlc_send_direct(msg_bytes, msg_len) -> i32
+//   msg_bytes:
+//     a pointer to a block of u8 binary data holding a message to turtl
+//   msg_len:
+//     the length in bytes of `message_bytes`
+//   -> returns 0 on success
+// -----------------------------------------------------------------------------
+// Same as `turtlc_send()`, but for a core started with `turtlc_start_direct()`.
+// The response comes back through `msg_cb`.
+TURTL_EXPORT int32_t TURTL_CONV turtlc_send_direct(const uint8_t*, size_t);
+
 // -----------------------------------------------------------------------------
 // turtlc_lasterr() -> char*
 //   -> returns a pointer to a null-terminated string of the last error that
@@ -136,6 +136,19 @@ fn find_protected_fields<'a>(body: &'a syn::Body, field_type: &str, restrict: bo
     }
 }
 
+/// Grab all of a struct's fields
+fn find_all_fields<'a>(body: &'a syn::Body) -> Vec<&'a syn::Ident> {
+    match body {
+        &syn::Body::Struct(ref data) => {
+            data.fields()
+                .into_iter()
+                .map(|x| x.ident.as_ref().expect("protected_derive::find_all_fields() -- failed to grab ident ref"))
+                .collect()
+        },
+        _ => panic!("You can only use #[derive(Protected)] on Structs"),
+    }
+}
+
 fn get_struct_modeltype(attrs: &Vec<::syn::Attribute>) -> Option<String> {
     // [Attribute {
     //      style: Outer,
@@ -186,6 +199,7 @@ fn impl_protected(ast: &syn::MacroInput) -> quote::Tokens {
     let private_fields_only2 = private_fields_only.clone();
     let private_fields_rename1 = match_rename_fields(&rename_field_map, private_fields1.clone());
     let private_only_fields_rename2 = match_rename_fields(&rename_field_map, private_fields_only);
+    let known_fields_rename = match_rename_fields(&rename_field_map, find_all_fields(&ast.body));
     let submodel_fields1: Vec<&syn::Ident> = find_protected_fields(&ast.body, "submodel", false);
     let submodel_fields2 = submodel_fields1.clone();
     let submodel_fields3 = submodel_fields1.clone();
@@ -280,6 +294,24 @@ fn impl_protected(ast: &syn::MacroInput) -> quote::Tokens {
                 ]
             }
 
+            fn known_fields(&self) -> Vec<&'static str> {
+                vec![
+                    #( #known_fields_rename, )*
+                ]
+            }
+
+            fn unknown_fields(&self) -> &::jedi::Map<String, ::jedi::Value> {
+                &self._unknown
+            }
+
+            fn unknown_public(&self) -> Option<&Vec<String>> {
+                self._unknown_public.as_ref()
+            }
+
+            fn set_unknown_public(&mut self, public: Option<Vec<String>>) {
+                self._unknown_public = public;
+            }
+
             fn submodel_fields(&self) -> Vec<&'static str> {
                 vec![
                     #( #submodel_fields_rename1, )*
@@ -407,6 +439,15 @@ fn impl_protected(ast: &syn::MacroInput) -> quote::Tokens {
                         _ => {},
                     }
                 })*
+                // hang onto anything we don't know about
+                if let Some(obj) = data.as_object() {
+                    let known = self.known_fields();
+                    for (field, val) in obj {
+                        if !known.contains(&field.as_str()) {
+                            self._unknown.insert(field.clone(), val.clone());
+                        }
+                    }
+                }
                 Ok(())
             }
         }
@@ -0,0 +1,420 @@
+//! The search analyzer. Note text goes through here before it's handed to the
+//! full-text index (and so do queries, so they line up). It lowercases and
+//! splits text into words, drops stopwords, and runs a light stemmer for the
+//! profile's language so "running" finds "run" and "chats" finds "chat".
+//!
+//! The stemmers are intentionally light (mostly plural/verb-ending stripping).
+//! They're less aggressive than a full Snowball stemmer, but they're also much
+//! less likely to mash unrelated words together.
+
+use ::jedi;
+use ::error::{TResult, TError};
+use ::storage::Storage;
+
+/// The kv key we keep the profile's analyzer config under
+const CONFIG_KEY: &'static str = "search:analyzer";
+
+/// Languages we know how to analyze
+pub const LANGUAGES: &'static [&'static str] = &["none", "en", "es", "fr", "de", "it", "pt"];
+
+/// How the analyzer should treat text
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AnalyzerConfig {
+    /// One of `LANGUAGES`. "none" just lowercases and splits words.
+    pub language: String,
+    #[serde(default = "default_true")]
+    pub stopwords: bool,
+    #[serde(default = "default_true")]
+    pub stemming: bool,
+    /// Any extra words the user wants ignored
+    #[serde(default)]
+    pub extra_stopwords: Vec<String>,
+}
+
+fn default_true() -> bool { true }
+
+impl Default for AnalyzerConfig {
+    fn default() -> Self {
+        AnalyzerConfig {
+            language: String::from("none"),
+            stopwords: true,
+            stemming: true,
+            extra_stopwords: Vec::new(),
+        }
+    }
+}
+
+/// Load the profile's analyzer config (or the default if none is set)
+pub fn load_config(db: &Storage) -> TResult<AnalyzerConfig> {
+    match db.kv_get(CONFIG_KEY)? {
+        Some(x) => Ok(jedi::parse(&x)?),
+        None => Ok(AnalyzerConfig::default()),
+    }
+}
+
+/// Save the profile's analyzer config. Takes effect the next time the index is
+/// built.
+pub fn save_config(db: &Storage, config: &AnalyzerConfig) -> TResult<()> {
+    if !LANGUAGES.contains(&config.language.as_str()) {
+        return TErr!(TError::BadValue(format!("unsupported search language: {}", config.language)));
+    }
+    db.kv_set(CONFIG_KEY, &jedi::stringify(config)?)
+}
+
+const STOP_EN: &'static [&'static str] = &["a", "an", "and", "are", "as", "at", "be", "but", "by", "for", "if", "in", "into", "is", "it", "no", "not", "of", "on", "or", "such", "that", "the", "their", "then", "there", "these", "they", "this", "to", "was", "will", "with"];
+const STOP_ES: &'static [&'static str] = &["a", "al", "con", "de", "del", "el", "en", "es", "la", "las", "lo", "los", "no", "o", "para", "por", "que", "se", "su", "un", "una", "y"];
+const STOP_FR: &'static [&'static str] = &["au", "aux", "avec", "ce", "ces", "dans", "de", "des", "du", "elle", "en", "est", "et", "il", "je", "la", "le", "les", "leur", "lui", "ne", "ou", "par", "pas", "pour", "que", "qui", "sa", "se", "son", "sur", "un", "une"];
+const STOP_DE: &'static [&'static str] = &["aber", "als", "am", "an", "auf", "aus", "bei", "das", "dem", "den", "der", "des", "die", "ein", "eine", "einen", "einer", "es", "für", "im", "in", "ist", "mit", "nicht", "oder", "sie", "und", "von", "zu"];
+const STOP_IT: &'static [&'static str] = &["a", "al", "che", "con", "da", "del", "della", "di", "e", "gli", "i", "il", "in", "la", "le", "lo", "non", "per", "su", "un", "una", "uno"];
+const STOP_PT: &'static [&'static str] = &["a", "ao", "as", "com", "da", "das", "de", "do", "dos", "e", "em", "na", "no", "nos", "o", "os", "para", "por", "que", "se", "um", "uma"];
+
+/// Strip the first matching suffix, as long as we leave at least `min` chars
+fn strip_suffix(word: &str, suffixes: &[(&str, &str)], min: usize) -> Option<String> {
+    for &(suffix, replacement) in suffixes {
+        if !word.ends_with(suffix) { continue; }
+        if word.chars().count() - suffix.chars().count() + replacement.chars().count() >= min {
+            let stem = &word[0..(word.len() - suffix.len())];
+            return Some(format!("{}{}", stem, replacement));
+        }
+    }
+    None
+}
+
+fn stem_en(word: &str) -> String {
+    if word.ends_with("ss") { return String::from(word); }
+    let word = strip_suffix(word, &[("sses", "ss"), ("ies", "y"), ("xes", "x"), ("ches", "ch"), ("shes", "sh"), ("s", "")], 3)
+        .unwrap_or(String::from(word));
+    let stripped = strip_suffix(&word, &[("ingly", ""), ("edly", ""), ("ing", ""), ("ed", ""), ("ly", "")], 3);
+    match stripped {
+        // running -> runn -> run
+        Some(stem) => {
+            let chars = stem.chars().collect::<Vec<_>>();
+            let len = chars.len();
+            if len >= 2 && chars[len - 1] == chars[len - 2] && !"lsz".contains(chars[len - 1]) {
+                chars[0..(len - 1)].iter().collect()
+            } else {
+                stem
+            }
+        }
+        None => word,
+    }
+}
+
+fn stem_romance(word: &str, language: &str) -> String {
+    let plural: &[(&str, &str)] = match language {
+        "fr" => &[("aux", "al"), ("x", ""), ("s", "")],
+        "pt" => &[("ões", "ão"), ("ães", "ão"), ("is", "l"), ("es", ""), ("s", "")],
+        "es" => &[("ces", "z"), ("es", ""), ("s", "")],
+        _ => &[],
+    };
+    let word = strip_suffix(word, plural, 3).unwrap_or(String::from(word));
+    // drop the final (gender/number) vowel
+    strip_suffix(&word, &[("a", ""), ("e", ""), ("i", ""), ("o", "")], 3).unwrap_or(word)
+}
+
+fn stem_de(word: &str) -> String {
+    let word = word.replace("ß", "ss");
+    strip_suffix(&word, &[("ern", ""), ("em", ""), ("en", ""), ("er", ""), ("es", ""), ("e", ""), ("n", ""), ("s", "")], 3)
+        .unwrap_or(word)
+}
+
+/// A number or date we pulled out of some text
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypedValue {
+    Number(f64),
+    /// A (possibly partial) date as the inclusive range of days it covers, in
+    /// YYYYMMDD form. "2024-03" is (20240301, 20240331).
+    Date(i32, i32),
+}
+
+impl TypedValue {
+    /// The (low, high) range this value covers
+    pub fn range(&self) -> (f64, f64) {
+        match *self {
+            TypedValue::Number(x) => (x, x),
+            TypedValue::Date(start, end) => (start as f64, end as f64),
+        }
+    }
+
+    /// The kind of value we are (what the search index calls us)
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            TypedValue::Number(_) => "number",
+            TypedValue::Date(..) => "date",
+        }
+    }
+}
+
+/// Strip the punctuation that tends to surround numbers/dates in prose
+fn trim_value(word: &str) -> &str {
+    word.trim_start_matches(|c| "([{\"'".contains(c))
+        .trim_end_matches(|c| ".,;:!?)]}\"'%".contains(c))
+}
+
+/// Parse a date in YYYY-MM-DD or YYYY-MM form (slashes work too, and any time
+/// after a "T" is ignored), returning the range of days it covers
+pub fn parse_date(word: &str) -> Option<(i32, i32)> {
+    let word = trim_value(word);
+    let word = match word.find('T') {
+        Some(idx) if idx >= 7 => &word[0..idx],
+        _ => word,
+    };
+    let parts = word.split(|c| c == '-' || c == '/').collect::<Vec<_>>();
+    if parts.len() < 2 || parts.len() > 3 { return None; }
+    if parts[0].len() != 4 || parts[1].len() != 2 { return None; }
+    if !parts.iter().all(|p| p.len() > 0 && p.chars().all(|c| c.is_ascii_digit())) { return None; }
+    let year: i32 = parts[0].parse().ok()?;
+    let month: i32 = parts[1].parse().ok()?;
+    if month < 1 || month > 12 { return None; }
+    let base = (year * 100 + month) * 100;
+    if parts.len() == 2 { return Some((base + 1, base + 31)); }
+    if parts[2].len() != 2 { return None; }
+    let day: i32 = parts[2].parse().ok()?;
+    if day < 1 || day > 31 { return None; }
+    Some((base + day, base + day))
+}
+
+/// Parse a number, allowing a leading sign or currency symbol, thousands
+/// separators, and a decimal part: "-12", "$1,234.50", "3.5"
+pub fn parse_number(word: &str) -> Option<f64> {
+    let word = trim_value(word);
+    let (negative, word) = if word.starts_with('-') { (true, &word[1..]) } else { (false, word) };
+    let word = word.trim_start_matches(|c| "$€£¥".contains(c));
+    let (int, frac) = match word.find('.') {
+        Some(idx) => (&word[0..idx], Some(&word[(idx + 1)..])),
+        None => (word, None),
+    };
+    if int.len() == 0 || !int.chars().next().unwrap().is_ascii_digit() { return None; }
+    let groups = int.split(',').collect::<Vec<_>>();
+    if !groups.iter().all(|g| g.len() > 0 && g.chars().all(|c| c.is_ascii_digit())) { return None; }
+    if groups.len() > 1 && (groups[0].len() > 3 || groups[1..].iter().any(|g| g.len() != 3)) { return None; }
+    let mut num = groups.concat();
+    if let Some(frac) = frac {
+        if frac.len() == 0 || !frac.chars().all(|c| c.is_ascii_digit()) { return None; }
+        num.push('.');
+        num.push_str(frac);
+    }
+    let val: f64 = num.parse().ok()?;
+    Some(if negative { -val } else { val })
+}
+
+/// Pull a number or date out of a single word
+pub fn typed_value(word: &str) -> Option<TypedValue> {
+    if let Some((start, end)) = parse_date(word) {
+        return Some(TypedValue::Date(start, end));
+    }
+    parse_number(word).map(|x| TypedValue::Number(x))
+}
+
+/// The normalized index token(s) for a typed value. Dates get a token for each
+/// precision they have ("d2024", "d202403", "d20240315") so a query for a
+/// month finds every day in it. Numbers are written without separators or
+/// trailing zeros ("$1,234.50" -> "1234p5"), so whole numbers come out the same
+/// as someone typing them plainly.
+fn typed_tokens(val: &TypedValue) -> Vec<String> {
+    match *val {
+        TypedValue::Date(start, end) => {
+            let mut tokens = vec![format!("d{}", start / 10000), format!("d{}", start / 100)];
+            if start == end { tokens.push(format!("d{}", start)); }
+            tokens
+        }
+        TypedValue::Number(x) => {
+            let num = format!("{}", x.abs());
+            let num = num.replace(".", "p");
+            vec![format!("{}{}", if x < 0.0 { "m" } else { "" }, num)]
+        }
+    }
+}
+
+/// The token a query for a typed value should search on (the most precise one)
+fn typed_query_token(val: &TypedValue) -> String {
+    typed_tokens(val).pop().unwrap_or(String::new())
+}
+
+/// Turns text into index-ready tokens
+#[derive(Debug, Clone, Default)]
+pub struct Analyzer {
+    config: AnalyzerConfig,
+}
+
+impl Analyzer {
+    pub fn new(config: AnalyzerConfig) -> Analyzer {
+        Analyzer { config: config }
+    }
+
+    pub fn config(&self) -> &AnalyzerConfig {
+        &self.config
+    }
+
+    fn is_stopword(&self, word: &str) -> bool {
+        if !self.config.stopwords { return false; }
+        let list = match self.config.language.as_ref() {
+            "en" => STOP_EN,
+            "es" => STOP_ES,
+            "fr" => STOP_FR,
+            "de" => STOP_DE,
+            "it" => STOP_IT,
+            "pt" => STOP_PT,
+            _ => &[],
+        };
+        list.contains(&word) || self.config.extra_stopwords.iter().any(|x| x == word)
+    }
+
+    fn stem(&self, word: &str) -> String {
+        if !self.config.stemming { return String::from(word); }
+        match self.config.language.as_ref() {
+            "en" => stem_en(word),
+            "es" | "fr" | "it" | "pt" => stem_romance(word, &self.config.language),
+            "de" => stem_de(word),
+            _ => String::from(word),
+        }
+    }
+
+    /// Analyze a single (already lowercased) word. None means the word should
+    /// be dropped.
+    fn analyze_word(&self, word: &str) -> Option<String> {
+        if word == "" || self.is_stopword(word) { return None; }
+        Some(self.stem(word))
+    }
+
+    /// Split text into words and analyze them. Numbers and dates are kept as
+    /// their raw pieces *and* a normalized token, so "2024-03-15" can be found
+    /// by searching for "2024" or "2024-03".
+    pub fn tokens(&self, text: &str) -> Vec<String> {
+        let mut tokens = Vec::new();
+        for word in text.split_whitespace() {
+            tokens.extend(word.split(|c: char| !c.is_alphanumeric())
+                .map(|x| x.to_lowercase())
+                .filter_map(|x| self.analyze_word(&x)));
+            if let Some(val) = typed_value(word) {
+                tokens.append(&mut typed_tokens(&val));
+            }
+        }
+        tokens
+    }
+
+    /// Grab all the numbers and dates in some text
+    pub fn typed_values(&self, text: &str) -> Vec<TypedValue> {
+        text.split_whitespace()
+            .filter_map(|x| typed_value(x))
+            .collect()
+    }
+
+    /// Analyze text for the index
+    pub fn analyze(&self, text: &str) -> String {
+        self.tokens(text).join(" ")
+    }
+
+    /// Analyze a full-text query. This keeps the query syntax the index
+    /// understands (OR/NOT operators, (groups), "quoted phrases", prefix*
+    /// searches, -exclusions) and only runs the words themselves through the
+    /// analyzer.
+    pub fn analyze_query(&self, query: &str) -> String {
+        let mut out: Vec<String> = Vec::new();
+        // syntax that was attached to a word we dropped, waiting for the next
+        // word we keep
+        let mut pending = String::new();
+        for piece in query.split_whitespace() {
+            if piece == "OR" || piece == "AND" || piece == "NOT" {
+                out.push(String::from(piece));
+                continue;
+            }
+            let lead_len = piece.chars().take_while(|c| "(\"-".contains(*c)).count();
+            let (lead, rest) = piece.split_at(lead_len);
+            let trail_len = rest.chars().rev().take_while(|c| ")\"*".contains(*c)).count();
+            let (core, trail) = rest.split_at(rest.len() - trail_len);
+            let prefix = trail.contains('*');
+            if !prefix {
+                if let Some(val) = typed_value(core) {
+                    out.push(format!("{}{}{}{}", pending, lead, typed_query_token(&val), trail));
+                    pending.clear();
+                    continue;
+                }
+            }
+            let analyzed = core.split(|c: char| !c.is_alphanumeric())
+                .filter(|x| x.len() > 0)
+                .map(|x| x.to_lowercase())
+                // don't stem prefixes, or "runn*" would turn into "run*"
+                .filter_map(|w| if prefix { Some(w) } else { self.analyze_word(&w) })
+                .collect::<Vec<_>>();
+            if analyzed.len() == 0 {
+                pending.push_str(&lead.replace("-", ""));
+                for c in trail.chars().filter(|c| *c != '*') {
+                    let opener = if c == ')' { '(' } else { '"' };
+                    match pending.rfind(opener) {
+                        // opened and closed on dropped words. drop it all.
+                        Some(idx) => { pending.remove(idx); }
+                        None => {
+                            if let Some(last) = out.last_mut() { last.push(c); }
+                        }
+                    }
+                }
+                continue;
+            }
+            out.push(format!("{}{}{}{}", pending, lead, analyzed.join(" "), trail));
+            pending.clear();
+        }
+        out.join(" ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn analyzer(language: &str) -> Analyzer {
+        let mut config = AnalyzerConfig::default();
+        config.language = String::from(language);
+        Analyzer::new(config)
+    }
+
+    #[test]
+    fn analyzes_english() {
+        let en = analyzer("en");
+        assert_eq!(en.analyze("The cats were RUNNING into the boxes, happily"), "cat were run box happi");
+        assert_eq!(en.analyze("classes"), en.analyze("class"));
+        assert_eq!(en.analyze_query("running OR \"the boxes\" walk*"), "run OR \"box\" walk*");
+        assert_eq!(en.analyze_query("(cats OR \"boxes the\") -dogs (the)"), "(cat OR \"box\") -dog");
+        assert_eq!(analyzer("none").analyze_query("(penis OR \"icy hearts\")"), "(penis OR \"icy hearts\")");
+    }
+
+    #[test]
+    fn analyzes_other_languages() {
+        assert_eq!(analyzer("es").analyze("los gatos y el gato"), "gat gat");
+        assert_eq!(analyzer("fr").analyze("les chevaux et le cheval"), "cheval cheval");
+        assert_eq!(analyzer("de").analyze("die Katzen und die Katze"), "katz katz");
+        // no language still lowercases and splits
+        assert_eq!(analyzer("none").analyze("The Cats, the dogs"), "the cats the dogs");
+    }
+
+    #[test]
+    fn finds_numbers_and_dates() {
+        assert_eq!(parse_date("2024-03-15"), Some((20240315, 20240315)));
+        assert_eq!(parse_date("(2024/03)."), Some((20240301, 20240331)));
+        assert_eq!(parse_date("2024-03-15T10:00:00Z"), Some((20240315, 20240315)));
+        assert_eq!(parse_date("2024-13"), None);
+        assert_eq!(parse_date("24-03-15"), None);
+        assert_eq!(parse_number("$1,234.50,"), Some(1234.5));
+        assert_eq!(parse_number("-12"), Some(-12.0));
+        assert_eq!(parse_number("12,34"), None);
+        assert_eq!(parse_number("abc"), None);
+        assert_eq!(parse_number("1.2.3"), None);
+
+        let en = analyzer("en");
+        assert_eq!(en.analyze("paid $1,234.50 on 2024-03-15"), "paid 1 234 50 1234p5 2024 03 15 d2024 d202403 d20240315");
+        assert_eq!(en.analyze_query("2024-03"), "d202403");
+        assert_eq!(en.analyze_query("1,450.00 -3.0"), "1450 -3");
+        assert_eq!(en.analyze_query("\"paid 1234.5\" OR 2024*"), "\"paid 1234p5\" OR 2024*");
+        assert_eq!(en.typed_values("it was 3.5 on 2024-03"), vec![TypedValue::Number(3.5), TypedValue::Date(20240301, 20240331)]);
+    }
+
+    #[test]
+    fn can_turn_things_off() {
+        let mut config = AnalyzerConfig::default();
+        config.language = String::from("en");
+        config.stemming = false;
+        config.extra_stopwords = vec![String::from("lol")];
+        let en = Analyzer::new(config);
+        assert_eq!(en.analyze("the cats lol"), "cats");
+    }
+}
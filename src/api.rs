@@ -2,13 +2,17 @@
 //! our user authentication.
 
 use ::std::sync::{RwLock, Mutex};
-use ::std::io::Read;
-use ::std::time::Duration;
+use ::std::cell::Cell;
+use ::std::cmp;
+use ::std::io::{Read, ErrorKind};
+use ::std::time::{Duration, Instant};
 use ::std::collections::HashMap;
 use ::config;
 use ::jedi::{self, Value, DeserializeOwned, Serialize};
 use ::error::{TResult, TError};
 use ::crypto;
+use ::messaging;
+use ::sync::metrics;
 use ::reqwest::{self, blocking::RequestBuilder, blocking::Client, Url, Proxy};
 pub use ::reqwest::Method;
 pub use ::reqwest::StatusCode;
@@ -20,9 +24,89 @@ lazy_static! {
     /// A hash table that holds HTTP clients. we used to just create/destroy
     /// clients on each request, but that exhausts connections so it's better to
     /// cache the clients and let them use their internal connection pool.
+    ///
+    /// timeouts are set per-request, so everything (syncers, user commands,
+    /// file transfers) shares one client (and one pool) unless the proxy/ssl
+    /// config changes out from under us.
     static ref CLIENTS: Mutex<HashMap<String, Client>> = Mutex::new(HashMap::new());
 }
 
+/// How long a call gets if it doesn't ask for a timeout (reqwest's default)
+const DEFAULT_TIMEOUT: u64 = 30;
+
+thread_local! {
+    /// When the command running on this thread needs to be done by. Every api
+    /// call the command makes has to fit inside it.
+    static DEADLINE: Cell<Option<Instant>> = Cell::new(None);
+}
+
+/// Find the deadline for a command, starting now. Configured via the
+/// `deadlines` section (0 means no deadline).
+pub fn command_deadline(cmd: &str) -> Option<Instant> {
+    let secs: u64 = config::get(&["deadlines", "commands", cmd])
+        .or_else(|_| config::get(&["deadlines", "command"]))
+        .unwrap_or(0);
+    if secs == 0 { return None; }
+    Some(Instant::now() + Duration::new(secs, 0))
+}
+
+/// Set (or clear) the deadline for whatever's running on this thread
+pub fn set_deadline(deadline: Option<Instant>) {
+    DEADLINE.with(|x| x.set(deadline));
+}
+
+/// Grab the shared HTTP client for our current api config, building it if
+/// need be.
+pub fn client() -> TResult<Client> {
+    let mut cachekey: Vec<String> = Vec::with_capacity(4);
+    let mut client_builder = Client::builder();
+    match config::get::<Option<String>>(&["api", "proxy"]) {
+        Ok(x) => {
+            if let Some(proxy_cfg) = x {
+                debug!("api::client() -- using proxy: {}", proxy_cfg);
+                let proxystr = format!("{}", proxy_cfg);
+                cachekey.push(format!("proxy-{}", proxystr));
+                client_builder = client_builder.proxy(Proxy::all(proxystr.as_str())?);
+            }
+        }
+        Err(_) => {}
+    }
+    match config::get::<Option<bool>>(&["api", "allow_invalid_ssl"]) {
+        Ok(x) => {
+            if let Some(allow_invalid_ssl) = x {
+                if allow_invalid_ssl {
+                    debug!("api::client() -- allow invalid ssl");
+                    cachekey.push(String::from("allow-invalid-ssl"));
+                    client_builder = client_builder.danger_accept_invalid_certs(true);
+                }
+            }
+        }
+        Err(_) => {}
+    }
+    let connect_timeout: u64 = config::get(&["api", "pool", "connect_timeout"]).unwrap_or(10);
+    let max_connections: usize = config::get(&["api", "pool", "max_connections"]).unwrap_or(8);
+    let http2: bool = config::get(&["api", "pool", "http2_prior_knowledge"]).unwrap_or(false);
+    cachekey.push(format!("pool-{}-{}-{}", connect_timeout, max_connections, http2));
+    client_builder = client_builder
+        .connect_timeout(Duration::new(connect_timeout, 0))
+        .max_idle_per_host(max_connections);
+    if http2 {
+        client_builder = client_builder.http2_prior_knowledge();
+    }
+    let cachekey_string: String = cachekey.join("///");
+    let mut client_guard = lock!((*CLIENTS));
+    if !client_guard.contains_key(&cachekey_string) {
+        let client = client_builder.build()?;
+        debug!("api::client() -- creating new client with cachekey {}", cachekey_string);
+        // any clients built for an old config are dead weight now
+        client_guard.clear();
+        client_guard.insert(cachekey_string.clone(), client);
+    }
+    // notice we clone here...the client lets us clone without messing up the
+    // pooling. very nice!
+    Ok(client_guard.get(&cachekey_string).unwrap().clone())
+}
+
 /// Holds our Api configuration. This consists of any mutable fields the Api
 /// needs to build URLs or make decisions.
 struct ApiConfig {
@@ -99,59 +183,49 @@ impl ApiCaller {
     }
 
     pub fn call_opt_impl<T: DeserializeOwned>(self, builder_maybe: Option<ApiReq>) -> TResult<T> {
-        let mut cachekey: Vec<String> = Vec::with_capacity(2);
-        let mut client_builder = Client::builder();
-        if let Some(builder) = builder_maybe {
-            let ApiReq { timeout } = builder;
-            client_builder = client_builder.timeout(timeout);
-            cachekey.push(format!("timeout-{}", timeout.as_secs()));
-        }
-        match config::get::<Option<String>>(&["api", "proxy"]) {
-            Ok(x) => {
-                if let Some(proxy_cfg) = x {
-                    debug!("api::call() -- req: using proxy: {}", proxy_cfg);
-                    let proxystr = format!("{}", proxy_cfg);
-                    cachekey.push(format!("proxy-{}", proxystr));
-                    client_builder = client_builder.proxy(Proxy::all(proxystr.as_str())?);
-                }
+        let client = client()?;
+        let ApiCaller { req: reqb } = self;
+        let reqb = match builder_maybe {
+            Some(ApiReq { timeout }) => reqb.timeout(timeout),
+            None => reqb,
+        };
+        let mut req = reqb.build()?;
+        if let Some(deadline) = DEADLINE.with(|x| x.get()) {
+            let now = Instant::now();
+            if now >= deadline {
+                return TErr!(TError::Timeout(String::from("command"), format!("ran out of time before calling {} {}", req.method(), req.url())));
             }
-            Err(_) => {}
+            let timeout = req.timeout().cloned().unwrap_or(Duration::new(DEFAULT_TIMEOUT, 0));
+            *req.timeout_mut() = Some(cmp::min(timeout, deadline - now));
         }
-        match config::get::<Option<bool>>(&["api", "allow_invalid_ssl"]) {
-            Ok(x) => {
-                if let Some(allow_invalid_ssl) = x {
-                    if allow_invalid_ssl {
-                        debug!("api::call() -- req: allow invalid ssl");
-                        cachekey.push(String::from("allow-invalid-ssl"));
-                        client_builder = client_builder.danger_accept_invalid_certs(true);
-                    }
-                }
-            }
-            Err(_) => {}
+        if let Some(bytes) = req.body().and_then(|b| b.as_bytes()) {
+            metrics::bytes_up(bytes.len() as u64);
         }
-        let cachekey_string: String = cachekey.join("///");
-        let client = {
-            let mut client_guard = lock!((*CLIENTS));
-            if !client_guard.contains_key(&cachekey_string) {
-                let client = client_builder.build()?;
-                debug!("api::call() -- creating new client with cachekey {}", cachekey_string);
-                client_guard.insert(cachekey_string.clone(), client);
-            }
-            // notice we clone here...the client lets us clone without messing
-            // up the pooling. very nice!
-            client_guard.get(&cachekey_string).unwrap().clone()
-        };
-        let ApiCaller { req: reqb } = self;
-        let req = reqb.build()?;
+        // a 401 on a call we made with our auth (other than logging in) means
+        // the server doesn't take our credentials anymore: the password got
+        // changed on another device, the account got suspended, etc.
+        let watch_auth = req.headers().contains_key("Authorization") && !req.url().path().ends_with("/auth");
         let callinfo = CallInfo::new(req.method().clone(), String::from(req.url().as_str()));
         debug!("api::call() -- req: {} {}", req.method(), req.url());
         let res = client.execute(req);
         res
-            .map_err(|e| { toterr!(e) })
+            .map_err(|e| {
+                if e.is_timeout() {
+                    twrap!(TError::Timeout(String::from("api"), format!("{} {}", &callinfo.method, &callinfo.resource)))
+                } else {
+                    toterr!(e)
+                }
+            })
             .and_then(|mut res| {
                 let mut out = String::new();
                 let str_res = res.read_to_string(&mut out)
-                    .map_err(|e| toterr!(e))
+                    .map_err(|e| {
+                        if e.kind() == ErrorKind::TimedOut {
+                            twrap!(TError::Timeout(String::from("api:body"), format!("{} {}", &callinfo.method, &callinfo.resource)))
+                        } else {
+                            toterr!(e)
+                        }
+                    })
                     .and_then(move |_| Ok(out));
                 if !res.status().is_success() {
                     let errstr = match str_res {
@@ -165,12 +239,17 @@ impl ApiCaller {
                         Ok(x) => x,
                         Err(_) => Value::String(errstr),
                     };
+                    if watch_auth && res.status() == StatusCode::UNAUTHORIZED {
+                        messaging::app_event("user:auth-invalid", &())
+                            .unwrap_or_else(|e| error!("api::call() -- error sending auth-invalid app event: {}", e));
+                    }
                     return TErr!(TError::Api(res.status(), val));
                 }
                 str_res.map(move |x| (x, res))
             })
             .map(|(out, res)| {
                 info!("api::call() -- res({}): {:?} {} {}", out.len(), res.status().as_u16(), &callinfo.method, &callinfo.resource);
+                metrics::bytes_down(out.len() as u64);
                 trace!("  api::call() -- body: {}", out);
                 out
             })
@@ -270,7 +349,7 @@ impl Api {
     pub fn req(&self, method: Method, resource: &str) -> TResult<ApiCaller> {
         debug!("api::req() -- begin: {} {}", method, resource);
         let url = self.build_url(resource)?;
-        let req = Client::builder().build()?.request(method, Url::parse(url.as_str())?);
+        let req = client()?.request(method, Url::parse(url.as_str())?);
         trace!("api::req() -- made client, got req: {:?}", req);
         Ok(ApiCaller::from_req(self.set_standard_headers(req)))
     }
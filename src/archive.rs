@@ -0,0 +1,405 @@
+//! The archive is a cold storage tier for notes nobody has touched in a long
+//! time.
+//!
+//! Cold notes get packed into batches, compressed, encrypted with the user's
+//! key, and stored as a single blob in the `archive` table. Each archived note
+//! is left in the `notes` table as a stub that only holds its title/tags (so it
+//! still shows up in the search index) along with an `archived` field pointing
+//! at its blob. Loading an archived note rehydrates it from its blob, so the
+//! rest of the app never has to know the archive exists.
+//!
+//! The stubs are local-only. If a newer version of an archived note comes in
+//! via sync, it simply replaces the stub (dropping the `archived` marker) and
+//! the copy in the blob gets pruned.
+
+use ::std::io::prelude::*;
+use ::std::collections::HashMap;
+use ::flate2::Compression;
+use ::flate2::write::DeflateEncoder;
+use ::flate2::read::DeflateDecoder;
+use ::jedi::{self, Value};
+use ::time;
+use ::config;
+use ::error::TResult;
+use ::crypto::{self, Key, CryptoOp};
+use ::storage::Storage;
+use ::turtl::Turtl;
+use ::models::model::{self, Model};
+use ::models::protected::{self, Protected};
+use ::models::note::Note;
+use ::dispatch::registry::Registry;
+
+/// The table our archive blobs live in
+const ARCHIVE_TABLE: &'static str = "archive";
+
+/// A batch of archived notes
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ArchiveBlob {
+    pub id: String,
+    /// The ids of the notes packed into this blob
+    pub note_ids: Vec<String>,
+    /// The notes themselves: compressed, encrypted, and base64'd
+    pub body: String,
+    /// How many bytes the notes took up in storage before archiving
+    pub raw_size: u64,
+    /// How many bytes they take up now (blob + stubs)
+    pub stored_size: u64,
+}
+
+/// Tells the UI how much the archive is saving us
+#[derive(Serialize, Debug, Default)]
+pub struct ArchiveStats {
+    /// How many archive blobs we have
+    pub blobs: u64,
+    /// How many notes are in the archive
+    pub notes: u64,
+    /// How many bytes the archived notes used before archiving
+    pub raw_size: u64,
+    /// How many bytes the archived notes use now
+    pub stored_size: u64,
+    /// raw_size - stored_size
+    pub saved: u64,
+}
+
+/// Grab the time (unix seconds) a note was last touched
+fn last_touched(note: &Note) -> Option<i64> {
+    match note.mod_ {
+        Some(x) => Some(x),
+        None => note.id().and_then(|id| model::id_timestamp(id).ok()).map(|x| x / 1000),
+    }
+}
+
+/// Deflate some data
+pub fn compress(data: &[u8]) -> TResult<Vec<u8>> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+/// Inflate some data
+pub fn decompress(data: &[u8]) -> TResult<Vec<u8>> {
+    let mut decoder = DeflateDecoder::new(data);
+    let mut out = Vec::new();
+    decoder.read_to_end(&mut out)?;
+    Ok(out)
+}
+
+/// Grab the current user's key. Archive blobs are encrypted with it.
+fn user_key(turtl: &Turtl) -> TResult<Key> {
+    let user_guard = lockr!(turtl.user);
+    user_guard.key_or_else()
+}
+
+/// Pack a set of notes into a blob body
+fn pack(key: &Key, notes: &Vec<Value>) -> TResult<String> {
+    let json = jedi::stringify(notes)?;
+    let compressed = compress(json.as_bytes())?;
+    let encrypted = crypto::encrypt(key, compressed, CryptoOp::new("chacha20poly1305")?)?;
+    Ok(crypto::to_base64(&encrypted)?)
+}
+
+/// Unpack a blob body into its notes
+fn unpack(key: &Key, body: &String) -> TResult<Vec<Value>> {
+    let encrypted = crypto::from_base64(body)?;
+    let compressed = crypto::decrypt(key, encrypted)?;
+    let json = decompress(compressed.as_slice())?;
+    Ok(jedi::parse_bytes(json.as_slice())?)
+}
+
+/// Encrypt a note and write it to the db as-is (no sync records, no mem
+/// updates). If `archived` is given, the note is marked as a stub pointing at
+/// that blob. Returns the number of bytes stored.
+fn store_note(turtl: &Turtl, db: &Storage, note: &mut Note, archived: Option<&String>) -> TResult<u64> {
+    turtl.find_model_key(note)?;
+    let serialized = Protected::serialize(note)?;
+    note.merge_fields(&serialized)?;
+    let mut data = note.data_for_storage()?;
+    if let Some(blob_id) = archived {
+        jedi::set(&["archived"], &mut data, blob_id)?;
+    }
+    db.store_value("notes", &data)?;
+    Ok(jedi::stringify(&data)?.len() as u64)
+}
+
+/// Archive any notes that haven't been touched in `archive.cold_after_days`.
+/// Returns the number of notes archived.
+pub fn archive_cold_notes(turtl: &Turtl) -> TResult<u64> {
+    let cold_after = config::get::<i64>(&["archive", "cold_after_days"]).unwrap_or(365);
+    let batch_size = config::get::<usize>(&["archive", "batch_size"]).unwrap_or(100).max(1);
+    let cutoff = time::get_time().sec - (cold_after * 86400);
+
+    // find our cold notes, skipping anything that's already archived
+    let (mut notes, sizes) = {
+        let raw: Vec<Value> = with_db!{ db, turtl.db, db.all_values("notes")? };
+        let mut notes: Vec<Note> = Vec::new();
+        let mut sizes: HashMap<String, u64> = HashMap::new();
+        for val in raw {
+            if jedi::get_opt::<String>(&["archived"], &val).is_some() { continue; }
+            let size = jedi::stringify(&val)?.len() as u64;
+            let note: Note = match jedi::from_val(val) {
+                Ok(x) => x,
+                Err(_) => continue,
+            };
+            if last_touched(&note).map(|x| x >= cutoff).unwrap_or(true) { continue; }
+            sizes.insert(note.id_or_else()?, size);
+            notes.push(note);
+        }
+        (notes, sizes)
+    };
+    if notes.len() == 0 { return Ok(0); }
+
+    turtl.find_models_keys(&mut notes)?;
+    let notes: Vec<Note> = protected::map_deserialize(turtl, notes)?;
+    let key = user_key(turtl)?;
+    let mut archived = 0;
+    for batch in notes.chunks(batch_size) {
+        let mut blob = ArchiveBlob::default();
+        blob.id = model::cid()?;
+        let mut packed: Vec<Value> = Vec::with_capacity(batch.len());
+        for note in batch {
+            let mut data = note.data()?;
+            // the body gets rebuilt from the private fields on rehydrate
+            jedi::remove(&["body"], &mut data)?;
+            packed.push(data);
+            let note_id = note.id_or_else()?;
+            blob.raw_size += sizes.get(&note_id).map(|x| x.clone()).unwrap_or(0);
+            blob.note_ids.push(note_id);
+        }
+        blob.body = pack(&key, &packed)?;
+        blob.stored_size = blob.body.len() as u64;
+
+        with_db!{ db, turtl.db,
+            // write the blob before touching the notes so we never end up with
+            // a stub pointing at a blob that doesn't exist
+            db.store_value(ARCHIVE_TABLE, &jedi::to_val(&blob)?)?;
+            for note in batch {
+                let mut stub = note.clone()?;
+                stub.text = None;
+                stub.url = None;
+                stub.username = None;
+                stub.password = None;
+                stub.embed = None;
+                blob.stored_size += store_note(turtl, db, &mut stub, Some(&blob.id))?;
+            }
+            db.store_value(ARCHIVE_TABLE, &jedi::to_val(&blob)?)?;
+        };
+        info!("archive::archive_cold_notes() -- archived {} notes ({} -> {} bytes)", batch.len(), blob.raw_size, blob.stored_size);
+        archived += batch.len() as u64;
+    }
+    Ok(archived)
+}
+
+/// Find which of the given notes are archived stubs, grouped by the blob they
+/// point at
+fn stubs_by_blob(db: &Storage, note_ids: &Vec<String>) -> TResult<HashMap<String, Vec<String>>> {
+    let mut by_blob: HashMap<String, Vec<String>> = HashMap::new();
+    for val in db.values_by_id("notes", note_ids)? {
+        let blob_id = match jedi::get_opt::<String>(&["archived"], &val) {
+            Some(x) => x,
+            None => continue,
+        };
+        let note_id = match jedi::get_opt::<String>(&["id"], &val) {
+            Some(x) => x,
+            None => continue,
+        };
+        by_blob.entry(blob_id).or_insert_with(Vec::new).push(note_id);
+    }
+    Ok(by_blob)
+}
+
+/// Given a set of note ids, rehydrate any that are archived stubs so that
+/// loading them from the db gets the full note. Returns the ids of the notes
+/// that were rehydrated.
+///
+/// Takes the db directly since it's called while loading notes.
+pub fn rehydrate(turtl: &Turtl, db: &Storage, note_ids: &Vec<String>) -> TResult<Vec<String>> {
+    let by_blob = stubs_by_blob(db, note_ids)?;
+    if by_blob.len() == 0 { return Ok(Vec::new()); }
+
+    let key = user_key(turtl)?;
+    let mut rehydrated = Vec::new();
+    for (blob_id, ids) in by_blob {
+        let mut blob: ArchiveBlob = match db.values_by_id(ARCHIVE_TABLE, &vec![blob_id.clone()])?.pop() {
+            Some(x) => jedi::from_val(x)?,
+            None => {
+                warn!("archive::rehydrate() -- missing archive blob {} for notes {:?}", blob_id, ids);
+                continue;
+            }
+        };
+        let packed = unpack(&key, &blob.body)?;
+        let mut remaining: Vec<Value> = Vec::with_capacity(packed.len());
+        for data in packed {
+            let note_id = jedi::get_opt::<String>(&["id"], &data).unwrap_or(String::from(""));
+            if !ids.contains(&note_id) {
+                remaining.push(data);
+                continue;
+            }
+            let mut note: Note = jedi::from_val(data)?;
+            store_note(turtl, db, &mut note, None)?;
+            rehydrated.push(note_id);
+        }
+
+        // shrink (or remove) the blob now that some of its notes are hot again
+        blob.note_ids.retain(|id| !ids.contains(id));
+        if remaining.len() == 0 || blob.note_ids.len() == 0 {
+            db.delete_value(ARCHIVE_TABLE, &blob.id)?;
+        } else {
+            let ratio = blob.note_ids.len() as f64 / (blob.note_ids.len() + ids.len()) as f64;
+            blob.raw_size = (blob.raw_size as f64 * ratio) as u64;
+            blob.body = pack(&key, &remaining)?;
+            blob.stored_size = (blob.stored_size as f64 * ratio) as u64;
+            db.store_value(ARCHIVE_TABLE, &jedi::to_val(&blob)?)?;
+        }
+    }
+    debug!("archive::rehydrate() -- rehydrated {} notes", rehydrated.len());
+    Ok(rehydrated)
+}
+
+/// Fill in any archived stubs in a set of (decrypted) notes with the full notes
+/// from their blobs, leaving the archive alone. This is for reading the whole
+/// profile at once (exports, backups) where rehydrating everything would undo
+/// the archive.
+pub fn fill_stubs(turtl: &Turtl, db: &Storage, notes: &mut Vec<Note>) -> TResult<()> {
+    let note_ids = notes.iter()
+        .filter_map(|x| x.id().cloned())
+        .collect::<Vec<_>>();
+    let by_blob = stubs_by_blob(db, &note_ids)?;
+    if by_blob.len() == 0 { return Ok(()); }
+
+    let key = user_key(turtl)?;
+    let mut full: HashMap<String, Value> = HashMap::new();
+    for (blob_id, ids) in by_blob {
+        let blob: ArchiveBlob = match db.values_by_id(ARCHIVE_TABLE, &vec![blob_id.clone()])?.pop() {
+            Some(x) => jedi::from_val(x)?,
+            None => {
+                warn!("archive::fill_stubs() -- missing archive blob {} for notes {:?}", blob_id, ids);
+                continue;
+            }
+        };
+        for data in unpack(&key, &blob.body)? {
+            let note_id = jedi::get_opt::<String>(&["id"], &data).unwrap_or(String::from(""));
+            if ids.contains(&note_id) {
+                full.insert(note_id, data);
+            }
+        }
+    }
+    for note in notes.iter_mut() {
+        let data = match note.id().and_then(|id| full.remove(id)) {
+            Some(x) => x,
+            None => continue,
+        };
+        note.merge_fields(&data)?;
+    }
+    Ok(())
+}
+
+/// Drop any notes from our blobs whose stubs are gone (deleted, or replaced by
+/// a newer version via sync) and remove blobs that end up empty.
+pub fn prune(turtl: &Turtl) -> TResult<()> {
+    let key = user_key(turtl)?;
+    with_db!{ db, turtl.db,
+        for val in db.all_values(ARCHIVE_TABLE)? {
+            let mut blob: ArchiveBlob = jedi::from_val(val)?;
+            let live: Vec<String> = db.values_by_id("notes", &blob.note_ids)?.into_iter()
+                .filter(|x| jedi::get_opt::<String>(&["archived"], x).as_ref() == Some(&blob.id))
+                .filter_map(|x| jedi::get_opt::<String>(&["id"], &x))
+                .collect::<Vec<_>>();
+            if live.len() == blob.note_ids.len() { continue; }
+            if live.len() == 0 {
+                db.delete_value(ARCHIVE_TABLE, &blob.id)?;
+                continue;
+            }
+            let packed = unpack(&key, &blob.body)?.into_iter()
+                .filter(|x| jedi::get_opt::<String>(&["id"], x).map(|id| live.contains(&id)).unwrap_or(false))
+                .collect::<Vec<_>>();
+            let ratio = live.len() as f64 / blob.note_ids.len() as f64;
+            blob.note_ids = live;
+            blob.raw_size = (blob.raw_size as f64 * ratio) as u64;
+            blob.stored_size = (blob.stored_size as f64 * ratio) as u64;
+            blob.body = pack(&key, &packed)?;
+            db.store_value(ARCHIVE_TABLE, &jedi::to_val(&blob)?)?;
+        }
+    };
+    Ok(())
+}
+
+/// Tally up our archive
+pub fn stats(turtl: &Turtl) -> TResult<ArchiveStats> {
+    let blobs: Vec<Value> = with_db!{ db, turtl.db, db.all_values(ARCHIVE_TABLE)? };
+    let mut stats = ArchiveStats::default();
+    for val in blobs {
+        let blob: ArchiveBlob = jedi::from_val(val)?;
+        stats.blobs += 1;
+        stats.notes += blob.note_ids.len() as u64;
+        stats.raw_size += blob.raw_size;
+        stats.stored_size += blob.stored_size;
+    }
+    stats.saved = stats.raw_size.saturating_sub(stats.stored_size);
+    Ok(stats)
+}
+
+/// Registers our archive commands
+pub fn register(reg: &mut Registry) {
+    reg.add("profile:archive", |turtl, _args| {
+        prune(turtl)?;
+        let archived = archive_cold_notes(turtl)?;
+        Ok(json!({"archived": archived}))
+    });
+    reg.add("profile:archive-stats", |turtl, _args| {
+        let archive_stats = stats(turtl)?;
+        Ok(jedi::to_val(&archive_stats)?)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::profile::Profile;
+
+    #[test]
+    fn packs_and_unpacks() {
+        let key = Key::random().unwrap();
+        let notes = vec![
+            json!({"id": "1234", "title": "old news", "text": "la la la la la la la la la la la la"}),
+            json!({"id": "5678", "title": "older news", "tags": ["old"]}),
+        ];
+        let body = pack(&key, &notes).unwrap();
+        assert_eq!(unpack(&key, &body).unwrap(), notes);
+        assert!(unpack(&Key::random().unwrap(), &body).is_err());
+    }
+
+    #[test]
+    fn exports_archived_notes() {
+        let turtl = ::turtl::tests::with_test(true);
+        let key = Key::random().unwrap();
+        let mut note = Note::new();
+        note.generate_id().unwrap();
+        note.space_id = String::from("015bac22440a4944baee41b88207731eaeb7e2cc5c955fb8a05b028c1409aaf55024f5d26fa3001e");
+        note.user_id = turtl.user_id().unwrap();
+        note.title = Some(String::from("old news"));
+        note.text = Some(String::from("la la la la la la la la la la la la"));
+        note.mod_ = Some(1000);
+        note.set_key(Some(key.clone()));
+        let note_id = note.id().unwrap().clone();
+        lockw!(turtl.profile).keychain.upsert_key(&turtl, &note_id, &key, &String::from("note")).unwrap();
+        {
+            let db_guard = lock!(turtl.db);
+            store_note(&turtl, db_guard.as_ref().unwrap(), &mut note, None).unwrap();
+        }
+        assert_eq!(archive_cold_notes(&turtl).unwrap(), 1);
+
+        let export = jedi::to_val(&Profile::export(&turtl, None).unwrap()).unwrap();
+        assert_eq!(jedi::get::<String>(&["notes", "0", "id"], &export).unwrap(), note_id);
+        assert_eq!(jedi::get::<String>(&["notes", "0", "text"], &export).unwrap(), "la la la la la la la la la la la la");
+        // reading the notes for an export doesn't take them out of the archive
+        assert_eq!(stats(&turtl).unwrap().notes, 1);
+    }
+
+    #[test]
+    fn compresses() {
+        let data = "lol ".repeat(1000);
+        let compressed = compress(data.as_bytes()).unwrap();
+        assert!(compressed.len() < data.len());
+        assert_eq!(decompress(compressed.as_slice()).unwrap(), data.as_bytes().to_vec());
+    }
+}
@@ -0,0 +1,936 @@
+//! Encrypted profile snapshots, written somewhere the user picks (usually a
+//! folder Dropbox/Syncthing/etc keeps in the cloud) so a lost device doesn't
+//! mean a lost profile, even without a Turtl server.
+//!
+//! Each snapshot is two files on the target:
+//!
+//! - `turtl-<id>.snapshot`: the profile export (or, for incremental
+//!   snapshots, just what changed since the snapshot before it), compressed
+//!   and encrypted with the user's key.
+//! - `turtl-<id>.manifest.json`: what's in the snapshot, the hash of its
+//!   encrypted body, and a link (id and hash) to the manifest of the snapshot
+//!   before it. Manifests are MACed with the user's key, so they can't be
+//!   edited or swapped without us noticing.
+//!
+//! Snapshots form a chain back to a full snapshot. Every `backup.full_every`th
+//! snapshot is a full one, and the ones in between only hold the items that
+//! changed (plus the ids of the ones that went away). Each snapshot also holds
+//! a hash of every item in the profile at the time, which is how the next one
+//! knows what changed, and how we check that replaying a chain got us the
+//! profile we backed up. Restoring verifies every link (MACs, parent hashes,
+//! snapshot hashes) before touching the profile.
+//!
+//! `backup:restore-at` rebuilds the profile as of some point in time into a
+//! restore point: a separate (still encrypted) copy kept on this device, which
+//! the UI can look through without touching the real profile.
+//!
+//! Backups run every `backup.interval` hours once a target is set (see
+//! `backup:set-target`). Targets are kept per-device, since a folder on one
+//! device means nothing on another.
+
+use ::std::fs;
+use ::std::collections::{HashMap, BTreeMap};
+use ::std::path::PathBuf;
+use ::std::sync::Arc;
+use ::std::sync::atomic::{AtomicBool, Ordering};
+use ::std::sync::RwLock;
+use ::std::thread;
+use ::std::time::Duration;
+use ::jedi::{self, Value, Serialize, DeserializeOwned};
+use ::time;
+use ::config;
+use ::util::paths;
+use ::error::{TResult, TError};
+use ::crypto::{self, Key, CryptoOp};
+use ::archive;
+use ::messaging;
+use ::jobs;
+use ::turtl::Turtl;
+use ::profile::{Profile, Export, ImportMode, ImportResult};
+use ::models::protected::Protected;
+use ::dispatch::registry::Registry;
+
+/// The kv key we keep this device's backup settings under
+const SETTINGS_KEY: &'static str = "backup";
+
+/// The kv key we keep this device's restore points under
+const RESTORE_POINTS_KEY: &'static str = "backup:restore-points";
+
+/// The parts of an export that hold items, in the order they get restored
+const ITEM_TYPES: &'static [&'static str] = &["spaces", "boards", "notes", "files"];
+
+/// Bump when the manifest format changes in a way older cores can't read
+const MANIFEST_VERSION: u16 = 1;
+
+const MANIFEST_SUFFIX: &'static str = ".manifest.json";
+const SNAPSHOT_SUFFIX: &'static str = ".snapshot";
+
+lazy_static! {
+    /// Tells the currently-running scheduler thread (if any) to keep going
+    static ref WATCHER: RwLock<Option<Arc<AtomicBool>>> = RwLock::new(None);
+}
+
+/// Somewhere we can keep backups. Names are flat (no directories).
+pub trait BackupTarget {
+    /// Where this target is, for logging/the UI
+    fn describe(&self) -> String;
+
+    /// Write a file, replacing any existing one. A reader should never see a
+    /// half-written file.
+    fn put(&self, name: &str, data: &[u8]) -> TResult<()>;
+
+    /// Read a file
+    fn get(&self, name: &str) -> TResult<Vec<u8>>;
+
+    /// List the files we have
+    fn list(&self) -> TResult<Vec<String>>;
+
+    /// Remove a file (if it's there)
+    fn delete(&self, name: &str) -> TResult<()>;
+}
+
+/// A folder on disk. Anything that syncs a folder to the cloud works.
+pub struct FolderTarget {
+    directory: PathBuf,
+}
+
+impl FolderTarget {
+    pub fn new(directory: &String) -> TResult<FolderTarget> {
+        fs::create_dir_all(directory)?;
+        let directory = PathBuf::from(directory);
+        if !directory.is_dir() {
+            return TErr!(TError::BadValue(format!("{} isn't a directory", directory.display())));
+        }
+        Ok(FolderTarget { directory: directory })
+    }
+
+    fn path(&self, name: &str) -> TResult<PathBuf> {
+        if name.contains('/') || name.contains('\\') || name.starts_with('.') {
+            return TErr!(TError::BadValue(format!("bad backup file name: {}", name)));
+        }
+        Ok(self.directory.join(name))
+    }
+}
+
+impl BackupTarget for FolderTarget {
+    fn describe(&self) -> String {
+        format!("{}", self.directory.display())
+    }
+
+    fn put(&self, name: &str, data: &[u8]) -> TResult<()> {
+        let path = self.path(name)?;
+        // write then rename, so sync clients never pick up a partial file
+        let tmp = self.directory.join(format!(".{}.tmp", name));
+        fs::write(&tmp, data)?;
+        fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+
+    fn get(&self, name: &str) -> TResult<Vec<u8>> {
+        Ok(fs::read(self.path(name)?)?)
+    }
+
+    fn list(&self) -> TResult<Vec<String>> {
+        let mut names = Vec::new();
+        for entry in fs::read_dir(&self.directory)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() { continue; }
+            if let Some(name) = entry.file_name().to_str() {
+                names.push(String::from(name));
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    fn delete(&self, name: &str) -> TResult<()> {
+        let path = self.path(name)?;
+        if path.exists() { fs::remove_file(path)?; }
+        Ok(())
+    }
+}
+
+/// Where this device writes its backups
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type")]
+pub enum TargetConfig {
+    #[serde(rename = "folder")]
+    Folder {
+        directory: String,
+    },
+}
+
+impl TargetConfig {
+    pub fn open(&self) -> TResult<Box<BackupTarget>> {
+        match *self {
+            TargetConfig::Folder { ref directory } => Ok(Box::new(FolderTarget::new(directory)?)),
+        }
+    }
+}
+
+/// This device's backup settings
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BackupSettings {
+    pub target: TargetConfig,
+    /// When we last made a snapshot (unix seconds)
+    #[serde(default)]
+    pub last_run: Option<i64>,
+}
+
+/// What's in a snapshot
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum SnapshotKind {
+    /// The whole profile
+    #[serde(rename = "full")]
+    Full,
+    /// What changed since the parent snapshot
+    #[serde(rename = "incremental")]
+    Incremental,
+}
+
+/// How many of each thing a snapshot holds
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SnapshotCounts {
+    pub spaces: u64,
+    pub boards: u64,
+    pub notes: u64,
+    pub files: u64,
+}
+
+/// Describes a snapshot, and links it to the one before it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Manifest {
+    pub version: u16,
+    pub id: String,
+    pub kind: SnapshotKind,
+    /// When the snapshot was made (unix seconds)
+    pub created: i64,
+    /// Which account the snapshot belongs to (a tag derived from the user's
+    /// key, so we don't leak the user id into their cloud folder)
+    pub account: String,
+    /// The snapshot before this one, and the sha256 of its manifest file
+    pub parent: Option<String>,
+    pub parent_hash: Option<String>,
+    /// The sha256 of the encrypted snapshot, and its size
+    pub snapshot_hash: String,
+    pub size: u64,
+    /// How many items the profile had at the time
+    pub counts: SnapshotCounts,
+    /// How many snapshots back the full snapshot this one builds on is (0 for
+    /// full snapshots)
+    #[serde(default)]
+    pub depth: u32,
+    /// How many items were added/changed and removed since the parent
+    #[serde(default)]
+    pub changed: u64,
+    #[serde(default)]
+    pub removed: u64,
+}
+
+/// What goes inside a (decrypted) snapshot
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct SnapshotBody {
+    /// The profile export, or for incremental snapshots, an export holding
+    /// only the items that changed
+    export: Value,
+    /// Every item in the profile as of this snapshot ("notes/<id>") and the
+    /// hash of its contents
+    index: HashMap<String, String>,
+    /// The items (same keys as the index) removed since the parent
+    #[serde(default)]
+    removed: Vec<String>,
+}
+
+/// A snapshot rebuilt onto this device, for looking through
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RestorePoint {
+    pub id: String,
+    /// The snapshot we rebuilt, and when it was taken
+    pub snapshot_id: String,
+    pub created: i64,
+    /// When we rebuilt it
+    pub restored: i64,
+    pub counts: SnapshotCounts,
+}
+
+fn manifest_name(id: &str) -> String {
+    format!("turtl-{}{}", id, MANIFEST_SUFFIX)
+}
+
+fn snapshot_name(id: &str) -> String {
+    format!("turtl-{}{}", id, SNAPSHOT_SUFFIX)
+}
+
+fn sha256_hex(data: &[u8]) -> TResult<String> {
+    Ok(crypto::to_hex(&crypto::sha256(data)?)?)
+}
+
+/// MAC a manifest (minus its `mac` field) with the user's key. Values keep
+/// their keys sorted, so this comes out the same no matter who wrote it.
+fn manifest_mac(key: &Key, manifest: &Value) -> TResult<String> {
+    let mut unsigned = manifest.clone();
+    if let Value::Object(ref mut obj) = unsigned {
+        obj.remove("mac");
+    }
+    let mac = crypto::hmac(key.data().as_slice(), jedi::stringify(&unsigned)?.as_bytes())?;
+    Ok(crypto::to_hex(&mac)?)
+}
+
+/// Turn a manifest into the bytes we write out
+fn seal_manifest(key: &Key, manifest: &Manifest) -> TResult<Vec<u8>> {
+    let mut val = jedi::to_val(manifest)?;
+    let mac = manifest_mac(key, &val)?;
+    if let Value::Object(ref mut obj) = val {
+        obj.insert(String::from("mac"), Value::String(mac));
+    }
+    Ok(jedi::stringify(&val)?.into_bytes())
+}
+
+/// Read a manifest file, making sure it's ours and hasn't been messed with
+fn open_manifest(key: &Key, data: &[u8]) -> TResult<Manifest> {
+    let val: Value = jedi::parse_bytes(data)?;
+    let given: String = jedi::get_opt(&["mac"], &val).unwrap_or(String::new());
+    let expected = manifest_mac(key, &val)?;
+    let given_bytes = crypto::from_hex(&given).unwrap_or(Vec::new());
+    let expected_bytes = crypto::from_hex(&expected)?;
+    if !crypto::secure_compare(given_bytes.as_slice(), expected_bytes.as_slice())? {
+        return TErr!(TError::PermissionDenied(String::from("manifest MAC doesn't match (wrong account, or it's been tampered with)")));
+    }
+    let manifest: Manifest = jedi::from_val(val)?;
+    if manifest.version > MANIFEST_VERSION {
+        return TErr!(TError::Incompatible(format!("manifest version {} is newer than we understand ({})", manifest.version, MANIFEST_VERSION)));
+    }
+    Ok(manifest)
+}
+
+/// A tag that tells apart this account's backups from anyone else's
+fn account_tag(key: &Key) -> TResult<String> {
+    let tag = crypto::to_hex(&crypto::hmac(key.data().as_slice(), b"turtl-backup:account")?)?;
+    Ok(String::from(&tag[0..16]))
+}
+
+fn user_key(turtl: &Turtl) -> TResult<Key> {
+    let user_guard = lockr!(turtl.user);
+    user_guard.key_or_else()
+}
+
+/// Grab this device's backup settings
+pub fn get_settings(turtl: &Turtl) -> TResult<Option<BackupSettings>> {
+    let settings = with_db!{ db, turtl.db, db.kv_get(SETTINGS_KEY)? };
+    match settings {
+        Some(x) => Ok(Some(jedi::parse(&x)?)),
+        None => Ok(None),
+    }
+}
+
+fn save_settings(turtl: &Turtl, settings: Option<&BackupSettings>) -> TResult<()> {
+    match settings {
+        Some(settings) => {
+            let serialized = jedi::stringify(settings)?;
+            with_db!{ db, turtl.db, db.kv_set(SETTINGS_KEY, &serialized)? };
+        }
+        None => {
+            with_db!{ db, turtl.db, db.kv_delete(SETTINGS_KEY)? };
+        }
+    }
+    Ok(())
+}
+
+/// Set (or with None, clear) where this device backs up to
+pub fn set_target(turtl: &Turtl, target: Option<TargetConfig>) -> TResult<Option<BackupSettings>> {
+    let target = match target {
+        Some(x) => x,
+        None => {
+            stop_watcher();
+            save_settings(turtl, None)?;
+            return Ok(None);
+        }
+    };
+    // make sure we can actually use it
+    target.open()?;
+    let last_run = get_settings(turtl)?.and_then(|x| x.last_run);
+    let settings = BackupSettings { target: target, last_run: last_run };
+    save_settings(turtl, Some(&settings))?;
+    start_watcher();
+    Ok(Some(settings))
+}
+
+fn open_target(turtl: &Turtl) -> TResult<(BackupSettings, Box<BackupTarget>)> {
+    let settings = match get_settings(turtl)? {
+        Some(x) => x,
+        None => return TErr!(TError::MissingData(String::from("no backup target set (see backup:set-target)"))),
+    };
+    let target = settings.target.open()?;
+    Ok((settings, target))
+}
+
+/// Load every manifest on the target that belongs to us (and checks out),
+/// oldest first
+fn load_manifests(key: &Key, target: &BackupTarget) -> TResult<Vec<Manifest>> {
+    let account = account_tag(key)?;
+    let mut manifests = Vec::new();
+    for name in target.list()? {
+        if !name.starts_with("turtl-") || !name.ends_with(MANIFEST_SUFFIX) { continue; }
+        let data = target.get(&name)?;
+        match open_manifest(key, data.as_slice()) {
+            Ok(manifest) => {
+                if manifest.account == account { manifests.push(manifest); }
+            }
+            // most likely someone else's backup in the same folder
+            Err(e) => debug!("backup::load_manifests() -- skipping {}: {}", name, e),
+        }
+    }
+    manifests.sort_by(|a, b| a.created.cmp(&b.created).then(a.id.cmp(&b.id)));
+    Ok(manifests)
+}
+
+/// List our snapshots, newest first
+pub fn list(turtl: &Turtl) -> TResult<Vec<Manifest>> {
+    let key = user_key(turtl)?;
+    let (_, target) = open_target(turtl)?;
+    let mut manifests = load_manifests(&key, target.as_ref())?;
+    manifests.reverse();
+    Ok(manifests)
+}
+
+/// Walk a snapshot's chain back to the full snapshot it starts from, checking
+/// every link along the way. Returns the chain, full snapshot first.
+fn verify_chain(key: &Key, target: &BackupTarget, snapshot_id: &String) -> TResult<Vec<Manifest>> {
+    let mut chain: Vec<Manifest> = Vec::new();
+    let mut next = Some(snapshot_id.clone());
+    let mut expected_hash: Option<String> = None;
+    while let Some(id) = next {
+        if chain.len() > 10000 || chain.iter().any(|m| m.id == id) {
+            return TErr!(TError::BadValue(format!("backup chain for {} loops", snapshot_id)));
+        }
+        let data = match target.get(&manifest_name(&id)) {
+            Ok(x) => x,
+            Err(e) => return TErr!(TError::NotFound(format!("backup chain is broken: missing manifest for {} ({})", id, e))),
+        };
+        if let Some(hash) = expected_hash.as_ref() {
+            if &sha256_hex(data.as_slice())? != hash {
+                return TErr!(TError::BadValue(format!("backup chain is broken: manifest for {} doesn't match the hash its child has for it", id)));
+            }
+        }
+        let manifest = open_manifest(key, data.as_slice())?;
+        if manifest.id != id {
+            return TErr!(TError::BadValue(format!("manifest for {} says it's for {}", id, manifest.id)));
+        }
+        let snapshot = match target.get(&snapshot_name(&id)) {
+            Ok(x) => x,
+            Err(e) => return TErr!(TError::NotFound(format!("backup chain is broken: missing snapshot {} ({})", id, e))),
+        };
+        if sha256_hex(snapshot.as_slice())? != manifest.snapshot_hash {
+            return TErr!(TError::BadValue(format!("snapshot {} doesn't match its manifest (corrupted?)", id)));
+        }
+        next = match manifest.kind {
+            SnapshotKind::Full => None,
+            SnapshotKind::Incremental => match manifest.parent.as_ref() {
+                Some(parent) => Some(parent.clone()),
+                None => return TErr!(TError::BadValue(format!("incremental snapshot {} has no parent", id))),
+            },
+        };
+        expected_hash = manifest.parent_hash.clone();
+        chain.push(manifest);
+    }
+    chain.reverse();
+    Ok(chain)
+}
+
+/// Check that a snapshot (and everything it builds on) is intact
+pub fn verify(turtl: &Turtl, snapshot_id: &String) -> TResult<Vec<Manifest>> {
+    let key = user_key(turtl)?;
+    let (_, target) = open_target(turtl)?;
+    verify_chain(&key, target.as_ref(), snapshot_id)
+}
+
+fn seal_snapshot<T: Serialize>(key: &Key, body: &T) -> TResult<Vec<u8>> {
+    let compressed = archive::compress(jedi::stringify(body)?.as_bytes())?;
+    Ok(crypto::encrypt(key, compressed, CryptoOp::new("chacha20poly1305")?)?)
+}
+
+fn open_snapshot<T: DeserializeOwned>(key: &Key, data: Vec<u8>) -> TResult<T> {
+    let compressed = crypto::decrypt(key, data)?;
+    let json = archive::decompress(compressed.as_slice())?;
+    Ok(jedi::parse_bytes(json.as_slice())?)
+}
+
+/// Every item in an export, keyed by type and id ("notes/<id>")
+fn items(export: &Value) -> Vec<(String, &Value)> {
+    let mut items = Vec::new();
+    for ty in ITEM_TYPES {
+        let list = match export.get(ty).and_then(|x| x.as_array()) {
+            Some(x) => x,
+            None => continue,
+        };
+        for item in list {
+            if let Some(id) = item.get("id").and_then(|x| x.as_str()) {
+                items.push((format!("{}/{}", ty, id), item));
+            }
+        }
+    }
+    items
+}
+
+/// Hash every item in an export
+fn index(export: &Value) -> TResult<HashMap<String, String>> {
+    let mut index = HashMap::new();
+    for (key, item) in items(export) {
+        let hash = sha256_hex(jedi::stringify(item)?.as_bytes())?;
+        index.insert(key, String::from(&hash[0..32]));
+    }
+    Ok(index)
+}
+
+/// Build an export holding only what changed between `parent` and `current`
+/// (indexes), and list what was removed
+fn diff(export: &Value, current: &HashMap<String, String>, parent: &HashMap<String, String>) -> (Value, Vec<String>) {
+    let mut changed = json!({"schema_version": export.get("schema_version")});
+    for ty in ITEM_TYPES {
+        changed[*ty] = json!([]);
+    }
+    for (key, item) in items(export) {
+        if parent.get(&key) == current.get(&key) { continue; }
+        let ty = key.splitn(2, '/').next().unwrap_or("");
+        if let Some(list) = changed[ty].as_array_mut() {
+            list.push(item.clone());
+        }
+    }
+    let mut removed = parent.keys()
+        .filter(|k| !current.contains_key(*k))
+        .cloned()
+        .collect::<Vec<_>>();
+    removed.sort();
+    (changed, removed)
+}
+
+fn count(index: &HashMap<String, String>) -> SnapshotCounts {
+    let len = |ty: &str| {
+        let prefix = format!("{}/", ty);
+        index.keys().filter(|k| k.starts_with(&prefix)).count() as u64
+    };
+    SnapshotCounts {
+        spaces: len("spaces"),
+        boards: len("boards"),
+        notes: len("notes"),
+        files: len("files"),
+    }
+}
+
+/// Replay a (verified) chain, full snapshot first, into the export it
+/// describes. The result is checked against the last snapshot's index, so a
+/// chain that doesn't add up is an error rather than a quietly wrong profile.
+fn rebuild(key: &Key, target: &BackupTarget, chain: &Vec<Manifest>) -> TResult<Value> {
+    let mut state: BTreeMap<String, Value> = BTreeMap::new();
+    let mut schema_version = Value::Null;
+    let mut last_index = HashMap::new();
+    for (i, manifest) in chain.iter().enumerate() {
+        jobs::check_cancelled()?;
+        let body: SnapshotBody = open_snapshot(key, target.get(&snapshot_name(&manifest.id))?)?;
+        if i == 0 && manifest.kind != SnapshotKind::Full {
+            return TErr!(TError::BadValue(format!("backup chain starts with incremental snapshot {}", manifest.id)));
+        }
+        for item_key in &body.removed {
+            state.remove(item_key);
+        }
+        for (item_key, item) in items(&body.export) {
+            state.insert(item_key, item.clone());
+        }
+        schema_version = body.export.get("schema_version").cloned().unwrap_or(Value::Null);
+        last_index = body.index;
+    }
+    let mut export = json!({"schema_version": schema_version});
+    for ty in ITEM_TYPES {
+        export[*ty] = json!([]);
+    }
+    for (item_key, item) in state {
+        let ty = item_key.splitn(2, '/').next().unwrap_or("");
+        if let Some(list) = export[ty].as_array_mut() {
+            list.push(item);
+        }
+    }
+    if index(&export)? != last_index {
+        return TErr!(TError::BadValue(String::from("rebuilt backup doesn't match the snapshot's index (corrupted chain?)")));
+    }
+    Ok(export)
+}
+
+/// Drop old snapshots, keeping the last `backup.keep` full snapshots (and
+/// everything that builds on them)
+fn prune(target: &BackupTarget, manifests: &Vec<Manifest>) -> TResult<()> {
+    let keep: usize = config::get(&["backup", "keep"]).unwrap_or(10);
+    if keep == 0 { return Ok(()); }
+    let fulls = manifests.iter()
+        .filter(|m| m.kind == SnapshotKind::Full)
+        .collect::<Vec<_>>();
+    if fulls.len() <= keep { return Ok(()); }
+    let oldest_kept = fulls[fulls.len() - keep].created;
+    for manifest in manifests.iter().filter(|m| m.created < oldest_kept) {
+        info!("backup::prune() -- removing snapshot {}", manifest.id);
+        // manifest first, so a half-pruned snapshot isn't mistaken for a whole one
+        target.delete(&manifest_name(&manifest.id))?;
+        target.delete(&snapshot_name(&manifest.id))?;
+    }
+    Ok(())
+}
+
+/// Find the snapshot (and its index) the next one can build on, if any. Every
+/// `backup.full_every` snapshots we start over with a full one, and we also do
+/// when the last chain doesn't check out.
+fn incremental_base(key: &Key, target: &BackupTarget, manifests: &Vec<Manifest>) -> Option<(Manifest, HashMap<String, String>)> {
+    let full_every: u32 = config::get(&["backup", "full_every"]).unwrap_or(7);
+    let parent = manifests.last()?;
+    if parent.depth + 1 >= full_every { return None; }
+    let res = verify_chain(key, target, &parent.id)
+        .and_then(|_| target.get(&snapshot_name(&parent.id)))
+        .and_then(|data| open_snapshot::<SnapshotBody>(key, data));
+    match res {
+        Ok(body) => Some((parent.clone(), body.index)),
+        Err(e) => {
+            warn!("backup::incremental_base() -- can't build on {}, taking a full snapshot: {}", parent.id, e);
+            None
+        }
+    }
+}
+
+/// Write a snapshot of `export` to the target, linked to `parent`. With a
+/// `base` (the parent's index) the snapshot is incremental, otherwise it's
+/// full.
+fn write_snapshot(key: &Key, target: &BackupTarget, parent: Option<&Manifest>, base: Option<&HashMap<String, String>>, export: Value, created: i64) -> TResult<Manifest> {
+    let current = index(&export)?;
+    let counts = count(&current);
+    let (kind, depth, body) = match (parent, base) {
+        (Some(parent), Some(base)) => {
+            let (changed, removed) = diff(&export, &current, base);
+            let body = SnapshotBody { export: changed, index: current, removed: removed };
+            (SnapshotKind::Incremental, parent.depth + 1, body)
+        }
+        _ => (SnapshotKind::Full, 0, SnapshotBody { export: export, index: current, removed: Vec::new() }),
+    };
+    let changed = items(&body.export).len() as u64;
+    let removed = body.removed.len() as u64;
+    let sealed = seal_snapshot(key, &body)?;
+    let parent_hash = match parent {
+        Some(parent) => Some(sha256_hex(target.get(&manifest_name(&parent.id))?.as_slice())?),
+        None => None,
+    };
+    let manifest = Manifest {
+        version: MANIFEST_VERSION,
+        id: format!("{}-{}", created, &crypto::random_hash()?[0..8]),
+        kind: kind,
+        created: created,
+        account: account_tag(key)?,
+        parent: parent.map(|x| x.id.clone()),
+        parent_hash: parent_hash,
+        snapshot_hash: sha256_hex(sealed.as_slice())?,
+        size: sealed.len() as u64,
+        counts: counts,
+        depth: depth,
+        changed: changed,
+        removed: removed,
+    };
+    // snapshot first: a manifest means the snapshot it describes is there
+    target.put(&snapshot_name(&manifest.id), sealed.as_slice())?;
+    target.put(&manifest_name(&manifest.id), seal_manifest(key, &manifest)?.as_slice())?;
+    Ok(manifest)
+}
+
+/// Take a snapshot of the profile and write it to the target
+pub fn run(turtl: &Turtl) -> TResult<Manifest> {
+    let key = user_key(turtl)?;
+    let (mut settings, target) = open_target(turtl)?;
+    info!("backup::run() -- backing up to {}", target.describe());
+    let mut manifests = load_manifests(&key, target.as_ref())?;
+    let export = jedi::to_val(&Profile::export(turtl, None)?)?;
+    jobs::check_cancelled()?;
+    let base = incremental_base(&key, target.as_ref(), &manifests);
+    let created = time::get_time().sec;
+    let manifest = write_snapshot(&key, target.as_ref(), manifests.last(), base.as_ref().map(|x| &x.1), export, created)?;
+    manifests.push(manifest.clone());
+    match prune(target.as_ref(), &manifests) {
+        Ok(_) => {}
+        Err(e) => warn!("backup::run() -- problem pruning old snapshots: {}", e),
+    }
+    settings.last_run = Some(created);
+    save_settings(turtl, Some(&settings))?;
+    messaging::ui_event("backup:complete", &manifest)?;
+    Ok(manifest)
+}
+
+/// Back up if we have a target and it's been long enough since the last one.
+/// Called by the "backup:run" app event.
+pub fn run_scheduled(turtl: &Turtl) {
+    let settings = match get_settings(turtl) {
+        Ok(Some(x)) => x,
+        Ok(None) => return,
+        Err(e) => {
+            error!("backup::run_scheduled() -- {}", e);
+            return;
+        }
+    };
+    let interval: i64 = config::get(&["backup", "interval"]).unwrap_or(24);
+    let due = settings.last_run.map(|x| time::get_time().sec - x >= interval * 3600).unwrap_or(true);
+    if !due { return; }
+    match run(turtl) {
+        Ok(_) => {}
+        Err(e) => {
+            error!("backup::run_scheduled() -- {}", e);
+            messaging::ui_event("backup:failed", &json!({"error": format!("{}", e)}))
+                .unwrap_or_else(|e| error!("backup::run_scheduled() -- error sending ui event: {}", e));
+        }
+    }
+}
+
+/// Restore a snapshot into the profile, once its chain checks out
+pub fn restore(turtl: &Turtl, snapshot_id: &String, mode: ImportMode) -> TResult<ImportResult> {
+    let key = user_key(turtl)?;
+    let (_, target) = open_target(turtl)?;
+    let chain = verify_chain(&key, target.as_ref(), snapshot_id)?;
+    info!("backup::restore() -- restoring {} (chain of {})", snapshot_id, chain.len());
+    let export: Export = jedi::from_val(rebuild(&key, target.as_ref(), &chain)?)?;
+    Profile::import(turtl, mode, export)
+}
+
+/// Where restore points live on this device
+fn restore_points_target() -> TResult<FolderTarget> {
+    let dir = paths::data_path(&["backup-restores"])?.to_string_lossy().into_owned();
+    FolderTarget::new(&dir)
+}
+
+/// List this device's restore points, newest first
+pub fn restore_points(turtl: &Turtl) -> TResult<Vec<RestorePoint>> {
+    let points = with_db!{ db, turtl.db, db.kv_get(RESTORE_POINTS_KEY)? };
+    match points {
+        Some(x) => Ok(jedi::parse(&x)?),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn save_restore_points(turtl: &Turtl, points: &Vec<RestorePoint>) -> TResult<()> {
+    let serialized = jedi::stringify(points)?;
+    with_db!{ db, turtl.db, db.kv_set(RESTORE_POINTS_KEY, &serialized)? };
+    Ok(())
+}
+
+/// Rebuild the profile as it was at `timestamp` (unix seconds) from the last
+/// snapshot taken before then, into a restore point. The real profile isn't
+/// touched.
+pub fn restore_at(turtl: &Turtl, timestamp: i64) -> TResult<RestorePoint> {
+    let key = user_key(turtl)?;
+    let (_, target) = open_target(turtl)?;
+    let manifest = match load_manifests(&key, target.as_ref())?.into_iter().filter(|m| m.created <= timestamp).last() {
+        Some(x) => x,
+        None => return TErr!(TError::NotFound(format!("no backups from before {}", timestamp))),
+    };
+    let chain = verify_chain(&key, target.as_ref(), &manifest.id)?;
+    info!("backup::restore_at() -- rebuilding {} (chain of {}) for {}", manifest.id, chain.len(), timestamp);
+    let export = rebuild(&key, target.as_ref(), &chain)?;
+    let point = RestorePoint {
+        id: format!("{}-{}", manifest.created, &crypto::random_hash()?[0..8]),
+        snapshot_id: manifest.id.clone(),
+        created: manifest.created,
+        restored: time::get_time().sec,
+        counts: manifest.counts.clone(),
+    };
+    // kept encrypted, same as on the target
+    restore_points_target()?.put(&snapshot_name(&point.id), seal_snapshot(&key, &export)?.as_slice())?;
+    let mut points = restore_points(turtl)?;
+    points.insert(0, point.clone());
+    save_restore_points(turtl, &points)?;
+    Ok(point)
+}
+
+/// Grab a restore point's profile (in the same shape as `profile:export`)
+pub fn get_restore_point(turtl: &Turtl, id: &String) -> TResult<Value> {
+    if !restore_points(turtl)?.iter().any(|p| &p.id == id) {
+        return TErr!(TError::NotFound(format!("no restore point {}", id)));
+    }
+    let key = user_key(turtl)?;
+    open_snapshot(&key, restore_points_target()?.get(&snapshot_name(id))?)
+}
+
+/// Throw out a restore point
+pub fn delete_restore_point(turtl: &Turtl, id: &String) -> TResult<()> {
+    let mut points = restore_points(turtl)?;
+    points.retain(|p| &p.id != id);
+    restore_points_target()?.delete(&snapshot_name(id))?;
+    save_restore_points(turtl, &points)
+}
+
+/// Start checking whether a backup is due every `backup.check_interval`
+/// seconds (see `run_scheduled()`, which the "backup:run" app event calls)
+pub fn start_watcher() {
+    stop_watcher();
+    let running = Arc::new(AtomicBool::new(true));
+    {
+        let mut guard = lockw!(*WATCHER);
+        *guard = Some(running.clone());
+    }
+    let interval: u64 = config::get(&["backup", "check_interval"]).unwrap_or(3600);
+    let spawned = thread::Builder::new().name(String::from("backup")).spawn(move || {
+        loop {
+            if !running.load(Ordering::SeqCst) { break; }
+            match messaging::app_event("backup:run", &()) {
+                Ok(_) => {}
+                Err(e) => error!("backup::watcher -- error sending run event: {}", e),
+            }
+            thread::sleep(Duration::new(interval, 0));
+        }
+    });
+    match spawned {
+        Ok(_) => {}
+        Err(e) => error!("backup::start_watcher() -- error spawning watcher: {}", e),
+    }
+}
+
+/// Stop checking for due backups
+pub fn stop_watcher() {
+    let mut guard = lockw!(*WATCHER);
+    if let Some(running) = guard.take() {
+        running.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Start the scheduler if this device has a backup target. Called once the
+/// profile is loaded.
+pub fn start_if_enabled(turtl: &Turtl) {
+    match get_settings(turtl) {
+        Ok(Some(_)) => start_watcher(),
+        Ok(None) => {}
+        Err(e) => error!("backup::start_if_enabled() -- {}", e),
+    }
+}
+
+/// Registers our backup commands
+pub fn register(reg: &mut Registry) {
+    reg.add("backup:get-target", |turtl, _args| {
+        Ok(jedi::to_val(&get_settings(turtl)?)?)
+    });
+    reg.add("backup:set-target", |turtl, args| {
+        let target: Option<TargetConfig> = args.get_opt(2);
+        Ok(jedi::to_val(&set_target(turtl, target)?)?)
+    });
+    reg.add("backup:run", |turtl, _args| {
+        Ok(jedi::to_val(&run(turtl)?)?)
+    });
+    reg.add("backup:list", |turtl, _args| {
+        Ok(jedi::to_val(&list(turtl)?)?)
+    });
+    reg.add("backup:verify", |turtl, args| {
+        let snapshot_id: String = args.get(2)?;
+        Ok(jedi::to_val(&verify(turtl, &snapshot_id)?)?)
+    });
+    reg.add("backup:restore", |turtl, args| {
+        let snapshot_id: String = args.get(2)?;
+        let mode: ImportMode = args.get_opt(3).unwrap_or(ImportMode::Restore);
+        Ok(jedi::to_val(&restore(turtl, &snapshot_id, mode)?)?)
+    });
+    reg.add("backup:restore-at", |turtl, args| {
+        let timestamp: i64 = args.get(2)?;
+        Ok(jedi::to_val(&restore_at(turtl, timestamp)?)?)
+    });
+    reg.add("backup:restore-points:list", |turtl, _args| {
+        Ok(jedi::to_val(&restore_points(turtl)?)?)
+    });
+    reg.add("backup:restore-points:get", |turtl, args| {
+        let id: String = args.get(2)?;
+        get_restore_point(turtl, &id)
+    });
+    reg.add("backup:restore-points:delete", |turtl, args| {
+        let id: String = args.get(2)?;
+        delete_restore_point(turtl, &id)?;
+        Ok(json!({}))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::std::collections::HashMap;
+    use ::std::sync::Mutex;
+
+    /// Keeps "files" in memory
+    struct MemTarget {
+        files: Mutex<HashMap<String, Vec<u8>>>,
+    }
+
+    impl BackupTarget for MemTarget {
+        fn describe(&self) -> String { String::from("memory") }
+        fn put(&self, name: &str, data: &[u8]) -> TResult<()> {
+            lock!(self.files).insert(String::from(name), Vec::from(data));
+            Ok(())
+        }
+        fn get(&self, name: &str) -> TResult<Vec<u8>> {
+            match lock!(self.files).get(name) {
+                Some(x) => Ok(x.clone()),
+                None => TErr!(TError::NotFound(String::from(name))),
+            }
+        }
+        fn list(&self) -> TResult<Vec<String>> {
+            Ok(lock!(self.files).keys().cloned().collect())
+        }
+        fn delete(&self, name: &str) -> TResult<()> {
+            lock!(self.files).remove(name);
+            Ok(())
+        }
+    }
+
+    fn export(notes: Vec<Value>) -> Value {
+        json!({"schema_version": 2, "spaces": [{"id": "s1"}], "boards": [], "notes": notes, "files": []})
+    }
+
+    #[test]
+    fn verifies_chains() {
+        let key = Key::random().unwrap();
+        let target = MemTarget { files: Mutex::new(HashMap::new()) };
+        let export1 = export(vec![json!({"id": "n1"})]);
+        let first = write_snapshot(&key, &target, None, None, export1.clone(), 100).unwrap();
+        let second = write_snapshot(&key, &target, Some(&first), None, export1.clone(), 200).unwrap();
+        assert_eq!(second.counts.notes, 1);
+        assert_eq!(second.kind, SnapshotKind::Full);
+
+        let chain = verify_chain(&key, &target, &second.id).unwrap();
+        assert_eq!(chain.iter().map(|m| m.id.clone()).collect::<Vec<_>>(), vec![second.id.clone()]);
+        assert_eq!(rebuild(&key, &target, &chain).unwrap(), export1);
+        assert_eq!(load_manifests(&key, &target).unwrap().len(), 2);
+
+        // someone else's key can't read (or forge) our manifests
+        let other = Key::random().unwrap();
+        assert!(verify_chain(&other, &target, &second.id).is_err());
+        assert_eq!(load_manifests(&other, &target).unwrap().len(), 0);
+
+        // a tampered manifest or snapshot fails verification
+        let mut tampered: Value = jedi::parse_bytes(target.get(&manifest_name(&second.id)).unwrap().as_slice()).unwrap();
+        tampered["created"] = json!(999);
+        target.put(&manifest_name(&second.id), jedi::stringify(&tampered).unwrap().as_bytes()).unwrap();
+        assert!(verify_chain(&key, &target, &second.id).is_err());
+        let mut snapshot = target.get(&snapshot_name(&first.id)).unwrap();
+        snapshot[40] ^= 1;
+        target.put(&snapshot_name(&first.id), snapshot.as_slice()).unwrap();
+        assert!(verify_chain(&key, &target, &first.id).is_err());
+    }
+
+    #[test]
+    fn replays_incrementals() {
+        let key = Key::random().unwrap();
+        let target = MemTarget { files: Mutex::new(HashMap::new()) };
+        let export1 = export(vec![json!({"id": "n1", "title": "one"}), json!({"id": "n2", "title": "two"})]);
+        let export2 = export(vec![json!({"id": "n1", "title": "one!"}), json!({"id": "n3", "title": "three"})]);
+        let full = write_snapshot(&key, &target, None, None, export1.clone(), 100).unwrap();
+        let base = index(&export1).unwrap();
+        let inc = write_snapshot(&key, &target, Some(&full), Some(&base), export2.clone(), 200).unwrap();
+        assert_eq!(inc.kind, SnapshotKind::Incremental);
+        assert_eq!((inc.depth, inc.changed, inc.removed), (1, 2, 1));
+        assert_eq!(inc.counts.notes, 2);
+
+        let chain = verify_chain(&key, &target, &inc.id).unwrap();
+        assert_eq!(chain.len(), 2);
+        assert_eq!(rebuild(&key, &target, &chain).unwrap(), export2);
+        let chain = verify_chain(&key, &target, &full.id).unwrap();
+        assert_eq!(rebuild(&key, &target, &chain).unwrap(), export1);
+
+        // an incremental is only as good as what it builds on
+        target.delete(&snapshot_name(&full.id)).unwrap();
+        assert!(verify_chain(&key, &target, &inc.id).is_err());
+    }
+}
@@ -0,0 +1,225 @@
+//! Profile benchmarks (only built with the `bench` feature). `app:benchmark`
+//! builds a synthetic profile (see `synthetic`) in a scratch Turtl and times
+//! the things that get slow as profiles grow: saving, loading, searching, and
+//! applying incoming sync. Profiles are generated from a seed, so two runs with the same options
+//! (on different builds, say) are measuring the same work.
+//!
+//! For a quick run outside of the app:
+//!
+//!     cargo test --features bench profile_benchmark -- --ignored --nocapture
+
+use ::std::fs;
+use ::std::time::Instant;
+use ::jedi::{self, Value};
+use ::error::{TResult, TError};
+use ::turtl::Turtl;
+use ::profile::Profile;
+use ::search::Query;
+use ::jobs;
+use ::models::file::FileData;
+use ::models::sync_record::{SyncAction, SyncType, SyncRecord};
+use ::synthetic::{self, Rng, GenerateOptions};
+use ::sync::incoming;
+use ::dispatch::registry::Registry;
+
+/// What to generate and how many times to run each measurement
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct BenchOptions {
+    pub spaces: u32,
+    /// Notes in total (spread over the spaces)
+    pub notes: u32,
+    /// How many of the notes get a file attached
+    pub attachments: u32,
+    /// Size of each attachment, in bytes
+    pub attachment_size: usize,
+    pub iterations: u32,
+    pub seed: u64,
+}
+
+impl Default for BenchOptions {
+    fn default() -> BenchOptions {
+        BenchOptions {
+            spaces: 2,
+            notes: 200,
+            attachments: 10,
+            attachment_size: 64 * 1024,
+            iterations: 5,
+            seed: 1,
+        }
+    }
+}
+
+/// The timings for one operation
+#[derive(Serialize, Debug)]
+pub struct Measurement {
+    pub name: &'static str,
+    /// How many items each iteration handles
+    pub items: u64,
+    pub iterations: u32,
+    pub mean_ms: f64,
+    pub median_ms: f64,
+    pub min_ms: f64,
+    pub max_ms: f64,
+    /// Items per second, going by the median
+    pub throughput: f64,
+}
+
+/// What `app:benchmark` hands back
+#[derive(Serialize, Debug)]
+pub struct BenchReport {
+    pub core_version: &'static str,
+    pub options: BenchOptions,
+    pub measurements: Vec<Measurement>,
+}
+
+/// Run something `iterations` times (after one untimed warmup run) and
+/// summarize how long it took
+fn measure<F>(name: &'static str, items: u64, iterations: u32, mut run: F) -> TResult<Measurement>
+    where F: FnMut() -> TResult<()>
+{
+    if iterations > 1 { run()?; }
+    let mut times = Vec::with_capacity(iterations as usize);
+    for _ in 0..iterations {
+        jobs::check_cancelled()?;
+        let start = Instant::now();
+        run()?;
+        let elapsed = start.elapsed();
+        times.push((elapsed.as_secs() as f64 * 1000.0) + (elapsed.subsec_nanos() as f64 / 1000000.0));
+    }
+    Ok(summarize(name, items, times))
+}
+
+/// Turn a list of timings (ms) into a measurement
+fn summarize(name: &'static str, items: u64, mut times: Vec<f64>) -> Measurement {
+    times.sort_by(|a, b| a.partial_cmp(b).unwrap_or(::std::cmp::Ordering::Equal));
+    let count = times.len();
+    let median = match count {
+        0 => 0.0,
+        x if x % 2 == 0 => (times[x / 2 - 1] + times[x / 2]) / 2.0,
+        x => times[x / 2],
+    };
+    let mean = if count == 0 { 0.0 } else { times.iter().sum::<f64>() / count as f64 };
+    Measurement {
+        name: name,
+        items: items,
+        iterations: count as u32,
+        mean_ms: mean,
+        median_ms: median,
+        min_ms: times.first().cloned().unwrap_or(0.0),
+        max_ms: times.last().cloned().unwrap_or(0.0),
+        throughput: if median > 0.0 { (items as f64) / (median / 1000.0) } else { 0.0 },
+    }
+}
+
+/// Generate a profile and time our profile operations against it
+pub fn run(options: BenchOptions) -> TResult<BenchReport> {
+    let turtl = Turtl::new_scratch()?;
+    let mut measurements = Vec::new();
+    let items = (options.spaces * 2 + options.notes) as u64;
+    let generate_options = GenerateOptions {
+        spaces: options.spaces,
+        boards_per_space: 1,
+        notes: options.notes,
+        attachments: options.attachments,
+        attachment_size: options.attachment_size,
+        seed: Some(options.seed),
+    };
+
+    // saving only happens once (saving more would mean a bigger profile)
+    let start = Instant::now();
+    let space_ids = synthetic::generate(&turtl, &generate_options)?.space_ids;
+    let elapsed = start.elapsed();
+    let save_ms = (elapsed.as_secs() as f64 * 1000.0) + (elapsed.subsec_nanos() as f64 / 1000000.0);
+    measurements.push(summarize("save", items, vec![save_ms]));
+
+    measurements.push(measure("load", items, options.iterations, || {
+        *lockw!(turtl.profile) = Profile::new();
+        turtl.load_profile()?;
+        turtl.index_notes()
+    })?);
+
+    let mut rng = Rng::new(options.seed);
+    let queries = (0..20)
+        .map(|i| -> TResult<Query> {
+            let space_id = &space_ids[i % space_ids.len()];
+            Ok(jedi::from_val(json!({"space_id": space_id, "text": rng.word()}))?)
+        })
+        .collect::<TResult<Vec<_>>>()?;
+    measurements.push(measure("search", queries.len() as u64, options.iterations, || {
+        let search_guard = lock!(turtl.search);
+        let search = match search_guard.as_ref() {
+            Some(x) => x,
+            None => return TErr!(TError::MissingField(String::from("Turtl.search"))),
+        };
+        for query in &queries {
+            search.find(query)?;
+        }
+        Ok(())
+    })?);
+
+    // replay every note as if it just came in from the server
+    let notes: Vec<Value> = {
+        let db_guard = lock!(turtl.db);
+        match db_guard.as_ref() {
+            Some(db) => db.all_values("notes")?,
+            None => return TErr!(TError::MissingField(String::from("Turtl.db"))),
+        }
+    };
+    let user_id = turtl.user_id()?;
+    measurements.push(measure("sync_apply", notes.len() as u64, options.iterations, || {
+        let queue = lockr!(turtl.sync_config).incoming_sync.clone();
+        for note in &notes {
+            let mut record = SyncRecord::default();
+            record.action = SyncAction::Edit;
+            record.ty = SyncType::Note;
+            record.item_id = jedi::get(&["id"], note)?;
+            record.user_id = user_id.clone();
+            record.data = Some(note.clone());
+            queue.push(record);
+        }
+        incoming::process_incoming_sync(&turtl)
+    })?);
+
+    // attachments are the only part of a scratch profile that hits the disk
+    for file in FileData::file_finder_all(Some(&user_id), None)? {
+        fs::remove_file(&file)?;
+    }
+
+    Ok(BenchReport {
+        core_version: env!("CARGO_PKG_VERSION"),
+        options: options,
+        measurements: measurements,
+    })
+}
+
+/// Registers our benchmark command
+pub fn register(reg: &mut Registry) {
+    reg.add("app:benchmark", |_turtl, args| {
+        let options: BenchOptions = args.get_opt(2).unwrap_or(Default::default());
+        Ok(jedi::to_val(&run(options)?)?)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::turtl;
+
+    #[test]
+    fn summarizes_timings() {
+        let measurement = summarize("test", 100, vec![40.0, 10.0, 30.0, 20.0]);
+        assert_eq!(measurement.median_ms, 25.0);
+        assert_eq!(measurement.mean_ms, 25.0);
+        assert_eq!((measurement.min_ms, measurement.max_ms), (10.0, 40.0));
+        assert_eq!(measurement.throughput, 4000.0);
+    }
+
+    #[test]
+    #[ignore]
+    fn profile_benchmark() {
+        let _turtl = turtl::tests::with_test(false);
+        let report = run(Default::default()).unwrap();
+        println!("{}", jedi::stringify(&report).unwrap());
+    }
+}
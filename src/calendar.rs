@@ -0,0 +1,176 @@
+//! Exports notes with due dates as an iCalendar (ICS) feed, so people can see
+//! their Turtl tasks on top of whatever calendar they already use. Each note
+//! becomes an all-day event on its due date.
+//!
+//! The feed holds decrypted note titles/text, so where it goes is up to the
+//! user: we hand it back to the UI, or write it to a file they picked.
+
+use ::std::fs;
+use ::time;
+use ::jedi::{self, Value};
+use ::error::{TResult, TError};
+use ::turtl::Turtl;
+use ::models::note::Note;
+use ::search::DateRange;
+use ::analyzer;
+use ::render;
+use ::dispatch::registry::Registry;
+
+/// ICS lines are supposed to be folded at 75 octets
+const MAX_LINE: usize = 75;
+
+/// What an export came out to
+#[derive(Serialize, Debug)]
+pub struct CalendarExport {
+    /// The ICS data (empty if we wrote it to a file)
+    pub ics: String,
+    /// The file we wrote, if any
+    pub file: Option<String>,
+    pub events: usize,
+}
+
+/// Escape text for an ICS property value
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace('\n', "\\n")
+}
+
+/// Fold a content line into chunks of at most 75 bytes (not splitting any
+/// characters), with continuation lines starting with a space
+fn fold(line: &str) -> String {
+    let mut out = String::with_capacity(line.len() + 8);
+    let mut len = 0;
+    for c in line.chars() {
+        if len + c.len_utf8() > MAX_LINE {
+            out.push_str("\r\n ");
+            len = 1;
+        }
+        out.push(c);
+        len += c.len_utf8();
+    }
+    out.push_str("\r\n");
+    out
+}
+
+/// Turn a note into a VEVENT, or None if it doesn't have a (valid) due date
+fn event(note: &Note, stamp: &str) -> Option<String> {
+    let due = match note.due.as_ref().and_then(|x| analyzer::parse_date(x)) {
+        Some((low, high)) if low == high => low,
+        _ => return None,
+    };
+    let id = note.id.as_ref()?;
+    let summary = note.title.as_ref()
+        .map(|x| String::from(x.trim()))
+        .filter(|x| x != "")
+        .or_else(|| {
+            note.text.as_ref()
+                .and_then(|x| x.lines().map(|l| l.trim()).find(|l| l != &""))
+                .map(String::from)
+        })
+        .unwrap_or(String::from("Untitled note"));
+    let mut lines = vec![
+        String::from("BEGIN:VEVENT"),
+        format!("UID:{}@turtl", id),
+        format!("DTSTAMP:{}", stamp),
+        format!("DTSTART;VALUE=DATE:{}", due),
+        format!("SUMMARY:{}", escape(summary.as_str())),
+    ];
+    let description = render::plaintext(note);
+    if description != "" {
+        lines.push(format!("DESCRIPTION:{}", escape(description.as_str())));
+    }
+    if let Some(ref url) = note.url {
+        if url.trim() != "" { lines.push(format!("URL:{}", url.trim())); }
+    }
+    lines.push(String::from("END:VEVENT"));
+    Some(lines.iter().map(|x| fold(x)).collect::<Vec<_>>().join(""))
+}
+
+/// Build an ICS calendar out of a set of notes
+pub fn to_ics(notes: &Vec<Note>) -> (String, usize) {
+    let stamp = time::now_utc().strftime("%Y%m%dT%H%M%SZ")
+        .map(|x| x.to_string())
+        .unwrap_or(String::from("19700101T000000Z"));
+    let events = notes.iter()
+        .filter_map(|x| event(x, stamp.as_str()))
+        .collect::<Vec<_>>();
+    let mut ics = String::new();
+    ics.push_str(&fold("BEGIN:VCALENDAR"));
+    ics.push_str(&fold("VERSION:2.0"));
+    ics.push_str(&fold("PRODID:-//Turtl//Turtl Core//EN"));
+    ics.push_str(&fold("CALSCALE:GREGORIAN"));
+    ics.push_str(&fold("X-WR-CALNAME:Turtl"));
+    for ev in &events { ics.push_str(ev); }
+    ics.push_str(&fold("END:VCALENDAR"));
+    (ics, events.len())
+}
+
+/// Export the notes due within the given range (open ends are fine). If given
+/// a file path, the feed is written there instead of being returned.
+pub fn export(turtl: &Turtl, range: &DateRange, file: Option<String>) -> TResult<CalendarExport> {
+    let (low, high) = range.bounds()?;
+    let note_ids = {
+        let search_guard = lock!(turtl.search);
+        match search_guard.as_ref() {
+            Some(x) => x.find_due(low, high)?,
+            None => return TErr!(TError::MissingField(String::from("turtl.search"))),
+        }
+    };
+    let notes = turtl.load_notes(&note_ids)?;
+    let (ics, events) = to_ics(&notes);
+    info!("calendar::export() -- exporting {} events", events);
+    match file {
+        Some(path) => {
+            fs::write(&path, ics.as_bytes())?;
+            Ok(CalendarExport { ics: String::new(), file: Some(path), events: events })
+        }
+        None => Ok(CalendarExport { ics: ics, file: None, events: events }),
+    }
+}
+
+/// Registers our calendar export command
+pub fn register(reg: &mut Registry) {
+    reg.add("profile:export-calendar", |turtl, args| {
+        let range: DateRange = match args.get_opt(2) {
+            Some(Value::Null) | None => Default::default(),
+            Some(x) => match jedi::from_val(x) {
+                Ok(x) => x,
+                Err(e) => return TErr!(TError::BadValue(format!("error deserializing date range: {}", e))),
+            },
+        };
+        let file: Option<String> = args.get_opt(3);
+        let result = export(turtl, &range, file)?;
+        Ok(jedi::to_val(&result)?)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::jedi;
+
+    #[test]
+    fn builds_ics() {
+        let note1: Note = jedi::parse(&String::from(r#"{"id":"1111","space_id":"4455","user_id":69,"type":"text","title":"pay rent, or else","text":"- [ ] transfer\n- [ ] call landlord","due":"2024-03-01"}"#)).unwrap();
+        let note2: Note = jedi::parse(&String::from(r#"{"id":"2222","space_id":"4455","user_id":69,"type":"text","text":"no due date"}"#)).unwrap();
+        let note3: Note = jedi::parse(&String::from(r#"{"id":"3333","space_id":"4455","user_id":69,"type":"text","text":"\nbuy milk","due":"2024-03-02T09:00"}"#)).unwrap();
+        let (ics, events) = to_ics(&vec![note1, note2, note3]);
+        assert_eq!(events, 2);
+        assert!(ics.starts_with("BEGIN:VCALENDAR\r\n"));
+        assert!(ics.ends_with("END:VCALENDAR\r\n"));
+        assert!(ics.contains("UID:1111@turtl\r\n"));
+        assert!(ics.contains("DTSTART;VALUE=DATE:20240301\r\n"));
+        assert!(ics.contains("SUMMARY:pay rent\\, or else\r\n"));
+        assert!(ics.contains("SUMMARY:buy milk\r\n"));
+        assert!(ics.contains("DTSTART;VALUE=DATE:20240302\r\n"));
+        assert!(!ics.contains("2222"));
+
+        let long = format!("SUMMARY:{}", "é".repeat(60));
+        let folded = fold(long.as_str());
+        assert!(folded.split("\r\n").all(|x| x.len() <= MAX_LINE));
+        assert_eq!(folded.replace("\r\n ", ""), format!("{}\r\n", long));
+    }
+}
@@ -4,6 +4,8 @@
 mod error;
 mod low;
 mod key;
+#[cfg(test)]
+mod vectors;
 
 pub use ::crypto::error::{
     CResult,
@@ -16,6 +18,8 @@ pub use ::crypto::low::{
     from_hex,
     to_base64,
     from_base64,
+    hmac,
+    secure_compare,
     HMAC_KEYLEN,
     KEYGEN_SALT_LEN,
     KEYGEN_OPS_DEFAULT,
@@ -27,7 +31,7 @@ pub use ::crypto::key::Key;
 
 /// Stores our current crypto version. This gets encoded into a header in the
 /// ciphertext and lets the crypto module know how to handle the message.
-const CRYPTO_VERSION: u16 = 6;
+pub const CRYPTO_VERSION: u16 = 6;
 
 /// Stores the available algorithms for symmetric crypto.
 const SYM_ALGORITHM: [&'static str; 1] = ["chacha20poly1305"];
@@ -174,6 +178,16 @@ pub fn deserialize(mut serialized: Vec<u8>) -> CResult<CryptoData> {
     Ok(CryptoData::new(version, desc_struct, nonce, ciphertext))
 }
 
+/// Grab the serialization version from a serialized message without having to
+/// deserialize the whole thing. Handy for checking if a payload was encrypted
+/// with an older version of the format and should be upgraded.
+pub fn payload_version(serialized: &[u8]) -> CResult<u16> {
+    if serialized.len() < 2 {
+        return Err(CryptoError::BadData(format!("crypto::payload_version() -- bad data length while reading version")));
+    }
+    Ok(((serialized[0] as u16) << 8) + (serialized[1] as u16))
+}
+
 /// Serialize a CryptoData container into a raw header vector. This is useful
 /// for extracting authentication data.
 pub fn serialize_header(data: &CryptoData) -> CResult<Vec<u8>> {
@@ -391,6 +405,15 @@ mod tests {
         assert_eq!(enc_str, "AAYBAAzGNuOg4N1zkQ2BlAiBbjNiYibICOs1NW18Jh/QfvdS+fR70+5kMnNCjXUSND05fU3m/FrcFZKPd3yQAl5gsP+4hWqkbWd+6/ip6HISeEz0NPBNTCWedSVgKYiEdnORSoiunl4l61vBmsyzQGnQl8fCYuerTLeGpq6j6Y5fBVmqmjWbmc5zeKqmg+LTfFUq9iNg5HoUPVKfjVm1aYlFG/fjMSk25j5zIgecFHAJOlQqtHXXPPCxwYLBoHBPsZE3kMu8jzE1QO8SAPOPyp2o3pD8fX1OhvqRHL/W34dqQzasmrscgvdvAy69l6nwbByOsjwvNSm2jWiNWGqFqxLgLXLy00r8A3E3hBDtQur4uo6Vs9ZSYn4mfLjEAyhyUsZeaoti8pKK5FVcJA9a//Blztbdmd8SPysXxks/6RvHIjy+aRCVxs/8Bw2Mv+AiSZ59dohNN4OUoVy3hNXk0RfdCDakw5AVq7xocAwmMLZeoWUgUt+Nb8ntt5W8KpfZVGMuxqIQoJoRMG7kf6TEHpL4vBOmosV0MwtLWkXwyXsx+zkP3GRw9mIcCkm5wEWpELYYzrOLmVQs4QHMetWsmyfTFOFlzVFPl7ctKlKuUOfbKETmrafvCNmoeOAWn58CXeEsD06ejrlg9zuPf5Vc3eIMSJ+EKIy8/eMLLFIDEzYkutqOfZoG6LJgevbgivLV7oXnG4kBF5pGVvwnpED4fTUFCFnc+MWATCN9aIJ58aLIdmF7TLYQwwXwNyyo9MvTJn/sEVjsbX/kpYrtknW1pjJ44e11du2Q5GpJXA4630g7BOOxooYTQgumoo/P3pPJnLjt9TJWPw7Q2h5rb2tqJowhltN19upncbOwMl1HPJcCqtOZOmttskMiDZGAjytiGOuD15TnfDUoZu3b97x0O6Nzm3RxGGBg4kQjC0q0RW0700EGGeCaiq9XAfUFIsS5XQ==");
     }
 
+    #[test]
+    fn reads_payload_version() {
+        let key = Key::random().unwrap();
+        let enc = encrypt(&key, Vec::from("get a job".as_bytes()), CryptoOp::new("chacha20poly1305").unwrap()).unwrap();
+        assert_eq!(payload_version(enc.as_slice()).unwrap(), CRYPTO_VERSION);
+        assert_eq!(payload_version(&[0, 4, 1, 0]).unwrap(), 4);
+        assert!(payload_version(&[6]).is_err());
+    }
+
     #[test]
     fn can_gen_random_keys() {
         // test a number of hashes
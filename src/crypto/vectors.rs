@@ -0,0 +1,130 @@
+//! Fixed test vectors for our crypto serialization format.
+//!
+//! Each vector has a known key/nonce/plaintext and the exact payload the JS
+//! client spits out for that input. If any of these break, it means the core
+//! and the other clients can no longer read each other's data, so think long
+//! and hard before "fixing" one of these.
+//!
+//! When adding a new format version, add vectors for it here (and keep the old
+//! ones around, since we still need to decrypt them).
+
+use ::crypto::*;
+
+/// A known-good symmetric encryption
+struct SymVector {
+    /// The serialization version the payload was created with
+    version: u16,
+    /// base64 key
+    key: &'static str,
+    /// base64 nonce
+    nonce: &'static str,
+    plaintext: &'static str,
+    /// base64 serialized payload
+    payload: &'static str,
+}
+
+const SYM_VECTORS: [SymVector; 4] = [
+    SymVector {
+        version: 6,
+        key: "2gtrzmvEQkfK9Lq+0eGqLjDrmlKBabp7T212Zdv35T0=",
+        nonce: "xjbjoODdc5ENgZQI",
+        plaintext: r#"{"title":"libertarian quotes","body":"Moreover, the institution of child labor is an honorable one, with a long and glorious history of good works. And the villains of the piece are not the employers, but rather those who prohibit the free market in child labor. These do-gooders are responsible for the untold immiseration of those who are thus forced out of employment. Although the harm done was greater in the past, when great poverty made widespread child labor necessary, there are still people in dire straits today. Present prohibitions of child labor are thus an unconscionable interference with their lives.","tags":["moron"],"mod":1468007942,"created":1468007942.493,"keys":[]}"#,
+        payload: "AAYBAAzGNuOg4N1zkQ2BlAiBbjNiYibICOs1NW18Jh/QfvdS+fR70+5kMnNCjXUSND05fU3m/FrcFZKPd3yQAl5gsP+4hWqkbWd+6/ip6HISeEz0NPBNTCWedSVgKYiEdnORSoiunl4l61vBmsyzQGnQl8fCYuerTLeGpq6j6Y5fBVmqmjWbmc5zeKqmg+LTfFUq9iNg5HoUPVKfjVm1aYlFG/fjMSk25j5zIgecFHAJOlQqtHXXPPCxwYLBoHBPsZE3kMu8jzE1QO8SAPOPyp2o3pD8fX1OhvqRHL/W34dqQzasmrscgvdvAy69l6nwbByOsjwvNSm2jWiNWGqFqxLgLXLy00r8A3E3hBDtQur4uo6Vs9ZSYn4mfLjEAyhyUsZeaoti8pKK5FVcJA9a//Blztbdmd8SPysXxks/6RvHIjy+aRCVxs/8Bw2Mv+AiSZ59dohNN4OUoVy3hNXk0RfdCDakw5AVq7xocAwmMLZeoWUgUt+Nb8ntt5W8KpfZVGMuxqIQoJoRMG7kf6TEHpL4vBOmosV0MwtLWkXwyXsx+zkP3GRw9mIcCkm5wEWpELYYzrOLmVQs4QHMetWsmyfTFOFlzVFPl7ctKlKuUOfbKETmrafvCNmoeOAWn58CXeEsD06ejrlg9zuPf5Vc3eIMSJ+EKIy8/eMLLFIDEzYkutqOfZoG6LJgevbgivLV7oXnG4kBF5pGVvwnpED4fTUFCFnc+MWATCN9aIJ58aLIdmF7TLYQwwXwNyyo9MvTJn/sEVjsbX/kpYrtknW1pjJ44e11du2Q5GpJXA4630g7BOOxooYTQgumoo/P3pPJnLjt9TJWPw7Q2h5rb2tqJowhltN19upncbOwMl1HPJcCqtOZOmttskMiDZGAjytiGOuD15TnfDUoZu3b97x0O6Nzm3RxGGBg4kQjC0q0RW0700EGGeCaiq9XAfUFIsS5XQ==",
+    },
+    SymVector {
+        version: 6,
+        key: "jlz71VUIns1xM3Hq0fETZT98dxzhlqUxqb0VXYq1KtQ=",
+        nonce: "AAECAwQFBgcICQoL",
+        plaintext: "",
+        payload: "AAYBAAwAAQIDBAUGBwgJCgvB51AtXXAkiKr2hAavwiSB",
+    },
+    SymVector {
+        version: 6,
+        key: "VAkQBuwoPXAQdDOIHZ/ItNWL0xZh+qBT5GKtj92HZ/8=",
+        nonce: "sVe1jLIfCeNqeosF",
+        plaintext: "hello, turtl",
+        payload: "AAYBAAyxV7WMsh8J42p6iwUWk6L153ZWBnwXGkphFqxE7a6h4mHUPcY8kSYd",
+    },
+    SymVector {
+        version: 6,
+        key: "smZYz6J4INwhVxJF5XcUyeojdOlKV0o7jKm4C2tJ7V0=",
+        nonce: "L892pMPHWx+1KI2D",
+        plaintext: r#"{"title":"ünïcødé, 日本語, and emoji 🐢"}"#,
+        payload: "AAYBAAwvz3akw8dbH7UojYMrJ+BlkSE7H6Ra+IpQrTwCjEK7rdACfPUOoCm+XqVgOPykA6OMR5aRmznG4g99M+S9fPskY9EB6SI71BhTPFQFJjY=",
+    },
+];
+
+fn b64(data: &str) -> Vec<u8> {
+    from_base64(&String::from(data)).unwrap()
+}
+
+#[test]
+fn vectors_cover_current_version() {
+    assert!(SYM_VECTORS.iter().any(|v| v.version == CRYPTO_VERSION));
+}
+
+#[test]
+fn decrypts_sym_vectors() {
+    for vec in SYM_VECTORS.iter() {
+        let payload = b64(vec.payload);
+        assert_eq!(payload_version(payload.as_slice()).unwrap(), vec.version);
+        let plain = decrypt(&Key::new(b64(vec.key)), payload).unwrap();
+        assert_eq!(String::from_utf8(plain).unwrap(), vec.plaintext);
+    }
+}
+
+#[test]
+fn encrypts_sym_vectors() {
+    // we can only encrypt the current version, so only check those
+    for vec in SYM_VECTORS.iter().filter(|v| v.version == CRYPTO_VERSION) {
+        let op = CryptoOp::new_with_nonce("chacha20poly1305", b64(vec.nonce)).unwrap();
+        let enc = encrypt(&Key::new(b64(vec.key)), Vec::from(vec.plaintext.as_bytes()), op).unwrap();
+        assert_eq!(to_base64(&enc).unwrap(), vec.payload);
+    }
+}
+
+#[test]
+fn sym_vectors_round_trip() {
+    for vec in SYM_VECTORS.iter() {
+        let key = Key::new(b64(vec.key));
+        let enc = encrypt(&key, Vec::from(vec.plaintext.as_bytes()), CryptoOp::new("chacha20poly1305").unwrap()).unwrap();
+        let dec = decrypt(&key, enc).unwrap();
+        assert_eq!(String::from_utf8(dec).unwrap(), vec.plaintext);
+    }
+}
+
+#[test]
+fn sym_vectors_reject_tampering() {
+    for vec in SYM_VECTORS.iter() {
+        let key = Key::new(b64(vec.key));
+        // flip a bit in the last byte of the tag/ciphertext
+        let mut payload = b64(vec.payload);
+        let last = payload.len() - 1;
+        payload[last] ^= 1;
+        assert!(decrypt(&key, payload).is_err());
+        // mess with the header (which is used as auth data)
+        let mut payload = b64(vec.payload);
+        payload[5] ^= 1;
+        assert!(decrypt(&key, payload).is_err());
+    }
+}
+
+#[test]
+fn decrypts_asym_vectors() {
+    // (pubkey, privkey, payload, plaintext)
+    let vectors = vec![
+        (
+            "3KhS3n3QlT/w7rE8hwwq/HNnVxlgzkphsqYKRAzbNGg=",
+            "ZZN2wHM5T7tUugDGUpMbMB6lI/o5S9AVxjntFjdO+/0=",
+            "A3eNneAydRaXiMB0886wo3sTTAxHcyM7JpaLN4z2rqQRyxUPq/eKrWHyF2/1wC9gfmw5t7lQ6KhT+tSbYTAHQb2EJ3NvwGRyeQ5SXId7RYSAeaoizSyT8JfEI91hyRde3sC5C00xYn60LYjt",
+            "and if you ever put your god damn hands on my wife again...",
+        ),
+    ];
+    for (pk, sk, payload, plaintext) in vectors {
+        let payload = b64(payload);
+        // asym payloads have a single version byte
+        assert_eq!(payload[0], 3);
+        let plain = asym::decrypt(&Key::new(b64(pk)), &Key::new(b64(sk)), payload).unwrap();
+        assert_eq!(String::from_utf8(plain).unwrap(), plaintext);
+    }
+}
@@ -0,0 +1,112 @@
+//! Request cancellation. The UI can give up on a request by sending
+//!
+//!     ["<message id>", "cancel:<mid of the request to cancel>"]
+//!
+//! which flags the request. Handlers don't get interrupted, but the slow ones
+//! (searching, loading notes, syncing, anything that checks
+//! `jobs::check_cancelled()`) look at the flag every so often and bail with a
+//! `cancelled` error.
+
+use ::std::cell::RefCell;
+use ::std::collections::HashMap;
+use ::std::sync::RwLock;
+use ::error::{TResult, TError};
+
+/// The prefix for cancellation messages
+pub const PREFIX: &'static str = "cancel:";
+
+lazy_static! {
+    /// Requests that are running right now, and whether they've been cancelled
+    static ref INFLIGHT: RwLock<HashMap<String, bool>> = RwLock::new(HashMap::new());
+}
+
+thread_local! {
+    /// The request being handled on this thread, if any
+    static CURRENT: RefCell<Option<String>> = RefCell::new(None);
+}
+
+/// Note that we've started handling a request on this thread
+pub fn begin(mid: &String) {
+    lockw!(*INFLIGHT).insert(mid.clone(), false);
+    CURRENT.with(|x| *x.borrow_mut() = Some(mid.clone()));
+}
+
+/// Note that we're done handling this thread's request
+pub fn end() {
+    if let Some(mid) = CURRENT.with(|x| x.borrow_mut().take()) {
+        lockw!(*INFLIGHT).remove(&mid);
+    }
+}
+
+/// Finish a request from outside the thread handling it (say, because we
+/// gave up on it). If its handler ever calls `check()`, it finds out it was
+/// cancelled.
+pub fn finish(mid: &String) {
+    lockw!(*INFLIGHT).remove(mid);
+}
+
+/// The id of the request being handled on this thread, if any
+pub fn current() -> Option<String> {
+    CURRENT.with(|x| x.borrow().clone())
+}
+
+/// How many requests are running right now
+pub fn running() -> usize {
+    lockr!(*INFLIGHT).len()
+}
+
+/// Flag a request as cancelled. Returns false if the request isn't running
+/// (it already finished, or never existed).
+pub fn request(mid: &String) -> bool {
+    match lockw!(*INFLIGHT).get_mut(mid) {
+        Some(cancelled) => {
+            *cancelled = true;
+            true
+        }
+        None => false,
+    }
+}
+
+/// Returns an error if the request running on this thread was cancelled
+pub fn check() -> TResult<()> {
+    let mid = match current() {
+        Some(x) => x,
+        None => return Ok(()),
+    };
+    match lockr!(*INFLIGHT).get(&mid) {
+        Some(&false) => Ok(()),
+        // cancelled, or finished out from under us
+        _ => TErr!(TError::Cancelled(format!("request {} was cancelled", mid))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cancels_requests() {
+        let mid = String::from("req-1");
+        assert!(!request(&mid));
+        begin(&mid);
+        assert!(check().is_ok());
+        assert!(request(&mid));
+        assert!(check().is_err());
+        end();
+        assert!(check().is_ok());
+        assert!(!request(&mid));
+    }
+
+    #[test]
+    fn finishes_requests_elsewhere() {
+        let mid = String::from("req-2");
+        begin(&mid);
+        let mid2 = mid.clone();
+        ::std::thread::spawn(move || finish(&mid2)).join().unwrap();
+        // no longer running, and its handler should give up
+        assert!(!request(&mid));
+        assert!(check().is_err());
+        end();
+        assert!(check().is_ok());
+    }
+}
@@ -0,0 +1,186 @@
+//! Hooks that wrap every command. Before-hooks run ahead of the command and can
+//! refuse it by returning an error (which becomes the command's response).
+//! After-hooks see the command's result and how long it took, and are good for
+//! logging and metrics.
+//!
+//! Hooks run in the order they were added, and are named so they can be taken
+//! back out again with `remove()`.
+
+use ::std::sync::RwLock;
+use ::std::sync::atomic::{AtomicBool, Ordering};
+use ::jedi::Value;
+use ::error::{TResult, TError};
+use ::turtl::Turtl;
+use ::messaging;
+use ::dispatch::registry::{Registry, Args};
+
+/// Runs before a command
+pub type Before = fn(&Turtl, &str, &Args) -> TResult<()>;
+
+/// Runs after a command, with its result and how long it took (in ms)
+pub type After = fn(&Turtl, &str, &TResult<Value>, u64);
+
+/// Our lists of hooks
+pub struct Hooks {
+    before: Vec<(&'static str, Before)>,
+    after: Vec<(&'static str, After)>,
+}
+
+impl Hooks {
+    pub fn new() -> Hooks {
+        Hooks { before: Vec::new(), after: Vec::new() }
+    }
+
+    /// Add a hook that runs before each command
+    pub fn add_before(&mut self, name: &'static str, hook: Before) {
+        self.before.push((name, hook));
+    }
+
+    /// Add a hook that runs after each command
+    pub fn add_after(&mut self, name: &'static str, hook: After) {
+        self.after.push((name, hook));
+    }
+
+    /// Remove any hooks with the given name
+    pub fn remove(&mut self, name: &str) {
+        self.before.retain(|x| x.0 != name);
+        self.after.retain(|x| x.0 != name);
+    }
+
+    /// Run our before-hooks, stopping at the first one that refuses
+    pub fn run_before(&self, turtl: &Turtl, cmd: &str, args: &Args) -> TResult<()> {
+        for &(_, hook) in &self.before {
+            hook(turtl, cmd, args)?;
+        }
+        Ok(())
+    }
+
+    /// Run our after-hooks
+    pub fn run_after(&self, turtl: &Turtl, cmd: &str, res: &TResult<Value>, elapsed: u64) {
+        for &(_, hook) in &self.after {
+            hook(turtl, cmd, res, elapsed);
+        }
+    }
+}
+
+lazy_static! {
+    static ref LOCKED: AtomicBool = AtomicBool::new(false);
+}
+
+/// Commands that change the profile but don't start with one of the prefixes
+/// in `LOCKED_PREFIXES`
+const LOCKED_COMMANDS: &'static [&'static str] = &[
+    "backup:restore",
+    "sync:delete-item",
+    "sync:unfreeze-item",
+    "user:change-password",
+    "user:delete-account",
+    "user:join-migrate",
+];
+
+/// Command prefixes that touch the profile
+const LOCKED_PREFIXES: &'static [&'static str] = &["profile:", "note:", "board:", "edit:"];
+
+/// Commands under `LOCKED_PREFIXES` that only read, so they still work while
+/// the app is locked
+const LOCKED_READ_ONLY: &'static [&'static str] = &[
+    "edit:history",
+    "note:comment:list",
+    "note:find-text",
+    "note:get-time-entries",
+    "note:merge-preview",
+    "note:plaintext",
+    "note:render",
+    "note:seen-by",
+    "note:spellcheck",
+    "note:timer:get",
+    "profile:archive-stats",
+    "profile:contacts",
+    "profile:export",
+    "profile:export-calendar",
+    "profile:find-by-url",
+    "profile:find-notes",
+    "profile:find-tags",
+    "profile:folder-sync:get",
+    "profile:get-notes",
+    "profile:load",
+    "profile:markdown-export:get",
+    "profile:note:get-file",
+    "profile:note:get-file-range",
+    "profile:quick-capture:get",
+    "profile:recurrences:list",
+    "profile:spellcheck:dictionary",
+    "profile:time-report",
+    "profile:webhook:list",
+    "profile:webhook:log",
+];
+
+/// Whether a command changes the user's profile
+fn mutates_profile(cmd: &str) -> bool {
+    if LOCKED_COMMANDS.contains(&cmd) { return true; }
+    LOCKED_PREFIXES.iter().any(|x| cmd.starts_with(x)) && !LOCKED_READ_ONLY.contains(&cmd)
+}
+
+/// Refuses anything that changes the profile while the app is locked
+pub fn check_locked(_turtl: &Turtl, cmd: &str, _args: &Args) -> TResult<()> {
+    if LOCKED.load(Ordering::SeqCst) && mutates_profile(cmd) {
+        return TErr!(TError::PermissionDenied(format!("{} is disabled while the app is locked", cmd)));
+    }
+    Ok(())
+}
+
+/// Logs each command along with how long it took
+pub fn log_timing(_turtl: &Turtl, cmd: &str, res: &TResult<Value>, elapsed: u64) {
+    match res {
+        Ok(_) => debug!("dispatch::hooks -- {} ok ({}ms)", cmd, elapsed),
+        Err(e) => info!("dispatch::hooks -- {} failed ({}ms): {}", cmd, elapsed, e),
+    }
+}
+
+/// Lock or unlock the app
+pub fn set_locked(locked: bool) -> TResult<()> {
+    let was_locked = LOCKED.swap(locked, Ordering::SeqCst);
+    if was_locked != locked {
+        messaging::ui_event("app:locked", &locked)?;
+    }
+    Ok(())
+}
+
+/// Registers our app lock commands
+pub fn register(reg: &mut Registry) {
+    reg.add("app:lock", |_turtl, _args| {
+        set_locked(true)?;
+        Ok(Value::Bool(true))
+    });
+    reg.add("app:unlock", |_turtl, _args| {
+        set_locked(false)?;
+        Ok(Value::Bool(false))
+    });
+    reg.add("app:locked", |_turtl, _args| {
+        Ok(Value::Bool(LOCKED.load(Ordering::SeqCst)))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_commands() {
+        assert!(mutates_profile("profile:sync:model"));
+        assert!(mutates_profile("note:react"));
+        assert!(mutates_profile("user:delete-account"));
+        assert!(!mutates_profile("profile:load"));
+        assert!(!mutates_profile("note:render"));
+        assert!(!mutates_profile("sync:status"));
+        assert!(!mutates_profile("app:unlock"));
+
+        let mut hooks = Hooks::new();
+        hooks.add_before("locked", check_locked);
+        hooks.add_after("timing", log_timing);
+        hooks.add_after("timing2", log_timing);
+        assert_eq!((hooks.before.len(), hooks.after.len()), (1, 2));
+        hooks.remove("timing");
+        assert_eq!((hooks.before.len(), hooks.after.len()), (1, 1));
+    }
+}
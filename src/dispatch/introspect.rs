@@ -0,0 +1,334 @@
+//! Describes our commands for `app:commands`, so UI developers don't have to go
+//! digging through the source to find out what a command wants.
+//!
+//! Arguments are listed in order, starting from index 2 of the message
+//! (`["<message id>", "<command>", arg1, arg2, ...]`), as `name: type`. A `?`
+//! after the name means the argument can be left out, `[type]` is an array,
+//! `{...}` is an object with the given fields, and capitalized types are
+//! objects that match the core struct of the same name.
+//!
+//! With `strict_args` on in the config, every command's arguments are checked
+//! against its description before it runs (see `check_args()`).
+
+use ::jedi::Value;
+use ::config;
+use ::error::{TResult, TError};
+use ::turtl::Turtl;
+use ::dispatch::registry::Args;
+
+/// What a command looks like from the outside
+#[derive(Serialize, Debug)]
+pub struct Description {
+    pub name: &'static str,
+    pub args: Vec<&'static str>,
+    pub login: bool,
+}
+
+/// Each command's arguments
+const ARGS: &'static [(&'static str, &'static [&'static str])] = &[
+    ("app:api:get-config", &[]),
+    ("app:api:info", &["options?: {force?: bool}"]),
+    ("app:api:set-config", &["api_config: any"]),
+    ("app:benchmark", &["options?: {spaces?: number, notes?: number, attachments?: number, attachment_size?: number, iterations?: number, seed?: number}"]),
+    ("app:commands", &[]),
+    ("app:connected", &[]),
+    ("app:first-run", &[]),
+    ("app:first-run:complete", &[]),
+    ("app:get-config", &[]),
+    ("app:get-limits", &[]),
+    ("app:get-locale", &[]),
+    ("app:get-log", &["lines: number"]),
+    ("app:goodbye", &[]),
+    ("app:hello", &["version: number"]),
+    ("app:lock", &[]),
+    ("app:locked", &[]),
+    ("app:metrics", &[]),
+    ("app:replay-journal", &["path: string"]),
+    ("app:set-locale", &["locale: string", "catalog?: {string: string}"]),
+    ("app:shutdown", &[]),
+    ("app:storage:check", &[]),
+    ("app:storage:force-unlock", &["user_id?: string"]),
+    ("app:storage:recover", &[]),
+    ("app:unlock", &[]),
+    ("app:wipe-app-data", &[]),
+    ("app:wipe-cache", &[]),
+    ("app:wipe-local-data", &[]),
+    ("app:wipe-user-data", &["user_id?: string"]),
+    ("backup:get-target", &[]),
+    ("backup:list", &[]),
+    ("backup:restore", &["snapshot_id: string", "mode?: ImportMode"]),
+    ("backup:restore-at", &["timestamp: number"]),
+    ("backup:restore-points:delete", &["id: string"]),
+    ("backup:restore-points:get", &["id: string"]),
+    ("backup:restore-points:list", &[]),
+    ("backup:run", &[]),
+    ("backup:set-target", &["target?: TargetConfig"]),
+    ("backup:verify", &["snapshot_id: string"]),
+    ("batch:run", &["messages: [any]", "options?: {parallel?: bool}"]),
+    ("board:move-note-column", &["board_id: string", "note_id: string", "column_id: string", "position?: number"]),
+    ("board:move-to-space", &["board_id: string", "space_id: string"]),
+    ("clip", &["url: string", "custom_parsers: [CustomParser]"]),
+    ("debug:generate-profile", &["options?: {spaces?: number, boards_per_space?: number, notes?: number, attachments?: number, attachment_size?: number, seed?: number}"]),
+    ("edit:history", &[]),
+    ("edit:redo", &[]),
+    ("edit:undo", &[]),
+    ("events:subscribe", &["patterns: [string]"]),
+    ("events:unsubscribe", &["patterns?: [string]"]),
+    ("feedback:send", &["feedback: Feedback"]),
+    ("job:cancel", &["job_id: string"]),
+    ("job:list", &[]),
+    ("job:start", &["command: string", "args...: any"]),
+    ("job:status", &["job_id: string"]),
+    ("note:comment:add", &["note_id: string", "body: string"]),
+    ("note:comment:delete", &["comment_id: string"]),
+    ("note:comment:list", &["note_id: string"]),
+    ("note:extract-article", &["note_id: string"]),
+    ("note:find-text", &["note_id: string", "query: string", "options?: FindTextOptions"]),
+    ("note:get-time-entries", &["note_id: string"]),
+    ("note:mark-seen", &["note_id: string"]),
+    ("note:merge-apply", &["note_id: string", "text: string", "force?: bool"]),
+    ("note:merge-preview", &["note_id: string", "versions: {base: string, mine: string, theirs?: string}"]),
+    ("note:plaintext", &["note_id: string"]),
+    ("note:react", &["note_id: string", "emoji: string"]),
+    ("note:render", &["note_id: string", "format?: string"]),
+    ("note:seen-by", &["note_id: string"]),
+    ("note:set-read", &["note_id: string", "read?: bool"]),
+    ("note:spellcheck", &["note_id: string"]),
+    ("note:split", &["note_id: string", "level: number", "options?: {dry_run?: bool}"]),
+    ("note:timer:get", &[]),
+    ("note:timer:start", &["note_id: string", "description?: string"]),
+    ("note:timer:stop", &[]),
+    ("note:unreact", &["note_id: string", "emoji: string"]),
+    ("note:unwatch", &["note_id: string"]),
+    ("note:watch", &["note_id: string"]),
+    ("ping", &["t?: number"]),
+    ("profile:accept-invite", &["invite: Invite", "passphrase?: string"]),
+    ("profile:archive", &[]),
+    ("profile:archive-stats", &[]),
+    ("profile:board:delete-invite", &["board_id: string", "invite_id: string"]),
+    ("profile:board:delete-member", &["board_id: string", "user_id: string"]),
+    ("profile:board:send-invite", &["board_id: string", "request: InviteRequest"]),
+    ("profile:contacts", &["search?: string"]),
+    ("profile:delete-invite", &["invite_id: string"]),
+    ("profile:export", &["query?: Query"]),
+    ("profile:export-calendar", &["range?: DateRange", "file?: string"]),
+    ("profile:find-by-url", &["url: string"]),
+    ("profile:find-notes", &["query: Query"]),
+    ("profile:find-tags", &["query: Query", "options?: {sort?: string}"]),
+    ("profile:folder-sync:disable", &[]),
+    ("profile:folder-sync:enable", &["board_id: string", "directory: string"]),
+    ("profile:folder-sync:get", &[]),
+    ("profile:folder-sync:scan", &[]),
+    ("profile:get-notes", &["note_ids: [string]", "options?: {sort?: string, stream?: bool}"]),
+    ("profile:import", &["mode: ImportMode", "export: Export"]),
+    ("profile:import-csv", &["request: CsvImportRequest"]),
+    ("profile:import-mail", &["request: MailImportRequest"]),
+    ("profile:load", &["options?: {counts_only?: bool, sort?: string, ids?: [string], stream?: bool}"]),
+    ("profile:markdown-export:get", &[]),
+    ("profile:markdown-export:set-directory", &["directory?: string"]),
+    ("profile:merge-notes", &["note_ids: [string]"]),
+    ("profile:note:get-file", &["note_id: string"]),
+    ("profile:note:get-file-range", &["note_id: string", "offset?: number", "length?: number"]),
+    ("profile:quick-capture", &["text?: string", "url?: string"]),
+    ("profile:quick-capture:get", &[]),
+    ("profile:quick-capture:set-board", &["board_id?: string"]),
+    ("profile:recurrences:add", &["rule: Recurrence"]),
+    ("profile:recurrences:delete", &["rule_id: string"]),
+    ("profile:recurrences:edit", &["rule: Recurrence"]),
+    ("profile:recurrences:list", &[]),
+    ("profile:recurrences:run", &[]),
+    ("profile:reindex", &["space_id?: string"]),
+    ("profile:remove-contact", &["contact_id: string"]),
+    ("profile:replace-text", &["query: Query", "find: string", "replace: string", "options?: {regex?: bool, case_sensitive?: bool, confirm?: string}"]),
+    ("profile:seed-sample-content", &[]),
+    ("profile:space:delete-invite", &["space_id: string", "invite_id: string"]),
+    ("profile:space:delete-member", &["space_id: string", "user_id: string"]),
+    ("profile:space:edit-invite", &["invite: Invite"]),
+    ("profile:space:edit-member", &["member: SpaceMember"]),
+    ("profile:space:leave", &["space_id: string"]),
+    ("profile:space:send-invite", &["request: InviteRequest"]),
+    ("profile:space:set-owner", &["space_id: string", "user_id: string"]),
+    ("profile:spellcheck:add-word", &["word: string"]),
+    ("profile:spellcheck:dictionary", &[]),
+    ("profile:spellcheck:remove-word", &["word: string"]),
+    ("profile:sync:model", &["action: SyncAction", "type: SyncType", "data: any"]),
+    ("profile:time-report", &["range?: DateRange"]),
+    ("profile:webhook:delete", &["webhook_id: string"]),
+    ("profile:webhook:list", &[]),
+    ("profile:webhook:log", &[]),
+    ("profile:webhook:save", &["hook: Webhook"]),
+    ("search:explain", &["note_id: string", "query: Query"]),
+    ("search:get-language", &[]),
+    ("search:set-language", &["config: AnalyzerConfig"]),
+    ("search:stats", &[]),
+    ("sync:delete-item", &["sync_id: string"]),
+    ("sync:get-pending", &[]),
+    ("sync:pause", &[]),
+    ("sync:peer:start", &[]),
+    ("sync:peer:status", &[]),
+    ("sync:peer:stop", &[]),
+    ("sync:peer:sync", &["peer: string"]),
+    ("sync:reconcile", &["options?: {dry_run?: bool}"]),
+    ("sync:resume", &[]),
+    ("sync:shutdown", &["wait?: bool"]),
+    ("sync:start", &[]),
+    ("sync:status", &["options?: {detailed?: bool}"]),
+    ("sync:unfreeze-item", &["sync_id: string"]),
+    ("user:can-migrate", &["old_username: string", "old_password: string"]),
+    ("user:change-password", &["current_username: string", "current_password: string", "new_username: string", "new_password: string"]),
+    ("user:delete-account", &[]),
+    ("user:email-gateway:disable", &[]),
+    ("user:email-gateway:enable", &["board_id: string"]),
+    ("user:email-gateway:get", &[]),
+    ("user:email-gateway:rotate", &[]),
+    ("user:find-by-email", &["email: string"]),
+    ("user:get-login-token", &["confirmation?: string"]),
+    ("user:join", &["username: string", "password: string"]),
+    ("user:join-migrate", &["old_username: string", "old_password: string", "new_username: string", "new_password: string"]),
+    ("user:login", &["username: string", "password: string"]),
+    ("user:login-from-saved", &["user_id: string", "key: string"]),
+    ("user:login-from-token", &["token: string"]),
+    ("user:logout", &["clear_cookie?: bool"]),
+    ("user:migrate-auth-debug", &["old_username: string", "old_password: string"]),
+    ("user:reauth", &["password: string", "username?: string"]),
+    ("user:resend-confirmation", &[]),
+    ("user:save-login", &[]),
+    ("util:new-id", &[]),
+];
+
+/// Commands that work without a logged-in user (along with everything under
+/// `app:`)
+const NO_LOGIN: &'static [&'static str] = &[
+    "batch:run",
+    "clip",
+    "events:subscribe",
+    "events:unsubscribe",
+    "job:cancel",
+    "job:list",
+    "job:start",
+    "job:status",
+    "ping",
+    "user:can-migrate",
+    "user:join",
+    "user:join-migrate",
+    "user:login",
+    "user:login-from-saved",
+    "user:login-from-token",
+    "user:logout",
+    "user:migrate-auth-debug",
+];
+
+/// Describe a command
+pub fn describe(name: &'static str) -> Description {
+    let args = args(name).map(|x| x.to_vec()).unwrap_or(Vec::new());
+    let login = !name.starts_with("app:") && !NO_LOGIN.contains(&name);
+    Description {
+        name: name,
+        args: args,
+        login: login,
+    }
+}
+
+/// A command's argument list, if we have one
+pub fn args(name: &str) -> Option<&'static [&'static str]> {
+    ARGS.iter().find(|x| x.0 == name).map(|x| x.1)
+}
+
+/// Whether we have a description for a command
+pub fn is_described(name: &str) -> bool {
+    args(name).is_some()
+}
+
+/// Whether a value fits one of our type names. Capitalized (core struct) types
+/// only get a rough check, since some of them are strings (enums, keys).
+fn fits(ty: &str, val: &Value) -> bool {
+    match (ty, val) {
+        ("any", _) => true,
+        ("string", &Value::String(_)) => true,
+        ("bool", &Value::Bool(_)) => true,
+        ("number", &Value::Number(_)) => true,
+        (_, &Value::Array(_)) => ty.starts_with("["),
+        (_, &Value::Object(_)) => ty.starts_with("{") || ty.starts_with(char::is_uppercase),
+        (_, &Value::String(_)) => ty.starts_with(char::is_uppercase),
+        _ => false,
+    }
+}
+
+/// A short name for a value's type, for error messages
+fn type_of(val: &Value) -> &'static str {
+    match *val {
+        Value::Null => "null",
+        Value::Bool(_) => "bool",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+/// Check a message's arguments against the command's description, returning
+/// everything that's wrong with them. Commands we don't have a description
+/// for are let through.
+pub fn check_args(name: &str, msg: &Value) -> Vec<String> {
+    let spec = match args(name) {
+        Some(x) => x,
+        None => return Vec::new(),
+    };
+    let empty = Vec::new();
+    let args = match *msg {
+        Value::Array(ref x) if x.len() > 2 => &x[2..],
+        _ => &empty[..],
+    };
+    let mut problems = Vec::new();
+    for (i, arg) in spec.iter().enumerate() {
+        let mut parts = arg.splitn(2, ": ");
+        let argname = parts.next().unwrap_or("");
+        let ty = parts.next().unwrap_or("any");
+        if argname.ends_with("...") { return problems; }
+        let optional = argname.ends_with("?");
+        match args.get(i) {
+            None | Some(&Value::Null) if optional => {}
+            None => problems.push(format!("arg {} (`{}`) is missing", i + 2, argname)),
+            Some(val) => {
+                if !fits(ty, val) {
+                    problems.push(format!("arg {} (`{}`) should be {}, got {}", i + 2, argname, ty, type_of(val)));
+                }
+            }
+        }
+    }
+    if args.len() > spec.len() {
+        problems.push(format!("takes {} args, got {} (extra args start at {})", spec.len(), args.len(), spec.len() + 2));
+    }
+    problems
+}
+
+/// Refuses commands whose arguments don't match their description, if
+/// `strict_args` is on
+pub fn check_strict(_turtl: &Turtl, cmd: &str, args: &Args) -> TResult<()> {
+    if !config::get(&["strict_args"]).unwrap_or(false) { return Ok(()); }
+    let problems = check_args(cmd, args.raw());
+    if !problems.is_empty() {
+        return TErr!(TError::BadValue(format!("{}: {}", cmd, problems.join("; "))));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_args() {
+        assert!(check_args("user:login", &json!(["1", "user:login", "andrew", "pass"])).is_empty());
+        assert_eq!(check_args("user:login", &json!(["1", "user:login", "andrew", 12, "extra"])), vec![
+            "arg 3 (`password`) should be string, got number",
+            "takes 2 args, got 3 (extra args start at 4)",
+        ]);
+        assert_eq!(check_args("user:login", &json!(["1", "user:login"])).len(), 2);
+        assert!(check_args("profile:load", &json!(["1", "profile:load"])).is_empty());
+        assert!(check_args("profile:load", &json!(["1", "profile:load", null])).is_empty());
+        assert_eq!(check_args("profile:load", &json!(["1", "profile:load", "x"])).len(), 1);
+        assert!(check_args("job:start", &json!(["1", "job:start", "sync:reconcile", {}, 1, 2])).is_empty());
+        assert!(check_args("not:a-command", &json!(["1", "not:a-command", 1])).is_empty());
+    }
+}
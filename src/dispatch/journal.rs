@@ -0,0 +1,201 @@
+//! The command journal. With `journal.enabled` on, every message the UI sends
+//! us gets appended to a file (one JSON object per line), with passwords,
+//! tokens, and keys blanked out. A developer can then feed the journal back
+//! through dispatch with
+//!
+//!     ["<message id>", "app:replay-journal", "/path/to/journal.jsonl"]
+//!
+//! to reproduce whatever the UI did. Replays run against a scratch profile
+//! (see `Turtl::new_scratch()`), never the real one.
+
+use ::std::fs;
+use ::std::io::{Write, BufRead, BufReader};
+use ::std::sync::Mutex;
+use ::jedi::{self, Value};
+use ::time;
+use ::config;
+use ::error::{TResult, TError};
+use ::util::paths;
+use ::turtl::Turtl;
+use ::jobs;
+use ::dispatch::{self, introspect, cancel};
+use ::dispatch::registry::Registry;
+
+/// What we put in place of a sensitive argument
+const REDACTED: &'static str = "<redacted>";
+
+/// Argument names that hold secrets
+const SECRET_ARGS: &'static [&'static str] = &["password", "token", "key"];
+
+/// Commands we don't replay. Auth/account commands either can't work (their
+/// secrets were redacted) or would go after the real account, sync commands
+/// would talk to the server, and app commands would act on the real app
+/// (wiping data, changing config).
+const REPLAY_SKIPPED: &'static [&'static str] = &["app:", "user:", "sync:", "feedback:", "batch:run", "job:start", cancel::PREFIX];
+
+lazy_static! {
+    /// Keeps concurrent requests from writing over each other's lines
+    static ref WRITE_LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// One line of the journal
+#[derive(Serialize, Deserialize, Debug)]
+struct Entry {
+    /// When we got the message (unix ms)
+    t: i64,
+    /// The message itself
+    msg: Value,
+}
+
+/// How a replayed message went
+#[derive(Serialize, Debug)]
+struct Replayed {
+    id: String,
+    cmd: String,
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<Value>,
+}
+
+/// Whether we're keeping a journal
+fn enabled() -> bool {
+    config::get(&["journal", "enabled"]).unwrap_or(false)
+}
+
+/// Where the journal goes
+fn location() -> String {
+    let file: String = config::get(&["journal", "file"]).unwrap_or(String::from("journal.jsonl"));
+    paths::resolve(&file)
+}
+
+/// Whether an argument holds something we shouldn't write to disk
+fn is_secret(spec: &str) -> bool {
+    let name = spec.split(':').next().unwrap_or("");
+    SECRET_ARGS.iter().any(|x| name.contains(x))
+}
+
+/// Blank out a message's secrets. `job:start` messages are redacted according
+/// to the command they start, and `batch:run` messages have each of the
+/// messages in their batch redacted.
+fn redact(msg: &Value) -> Value {
+    let mut msg = msg.clone();
+    let cmd: String = jedi::get_opt(&["1"], &msg).unwrap_or(String::new());
+    let (cmd, offset) = if cmd == "job:start" {
+        (jedi::get_opt(&["2"], &msg).unwrap_or(String::new()), 3)
+    } else {
+        (cmd, 2)
+    };
+    if cmd == "batch:run" {
+        if let Value::Array(ref mut parts) = msg {
+            if let Some(&mut Value::Array(ref mut batch)) = parts.get_mut(offset) {
+                for sub in batch.iter_mut() {
+                    *sub = redact(sub);
+                }
+            }
+        }
+        return msg;
+    }
+    let spec = match introspect::args(&cmd) {
+        Some(x) => x,
+        None => return msg,
+    };
+    if let Value::Array(ref mut parts) = msg {
+        for (i, arg) in spec.iter().enumerate() {
+            if !is_secret(arg) { continue; }
+            if let Some(val) = parts.get_mut(i + offset) {
+                *val = Value::String(String::from(REDACTED));
+            }
+        }
+    }
+    msg
+}
+
+/// Add a message from the UI to the journal (if it's on). A journal that can't
+/// be written to shouldn't take the command down with it, so errors are just
+/// logged.
+pub fn record(msg: &Value) {
+    if !enabled() { return; }
+    let write = || -> TResult<()> {
+        let ts = time::get_time();
+        let entry = Entry {
+            t: (ts.sec * 1000) + (ts.nsec / 1000000) as i64,
+            msg: redact(msg),
+        };
+        let line = jedi::stringify(&entry)?;
+        let _guard = lock!(*WRITE_LOCK);
+        let mut file = fs::OpenOptions::new().create(true).append(true).open(location())?;
+        writeln!(file, "{}", line)?;
+        Ok(())
+    };
+    match write() {
+        Ok(_) => {}
+        Err(e) => warn!("dispatch::journal::record() -- error writing to journal: {}", e),
+    }
+}
+
+/// Run the messages in a journal file against a scratch profile, and report how
+/// each one went
+fn replay(path: &String) -> TResult<Vec<Replayed>> {
+    let file = fs::File::open(path)?;
+    let scratch = Turtl::new_scratch()?;
+    let mut results = Vec::new();
+    for line in BufReader::new(file).lines() {
+        jobs::check_cancelled()?;
+        let line = line?;
+        if line.trim().is_empty() { continue; }
+        let entry: Entry = jedi::parse(&line)?;
+        let id: String = jedi::get_opt(&["0"], &entry.msg).unwrap_or(String::new());
+        let cmd: String = match jedi::get_opt(&["1"], &entry.msg) {
+            Some(x) => x,
+            None => return TErr!(TError::BadValue(format!("journal entry is missing its command: {}", line))),
+        };
+        let skip = REPLAY_SKIPPED.iter().any(|x| cmd == *x || (x.ends_with(':') && cmd.starts_with(x)));
+        let replayed = if skip {
+            Replayed { id: id, cmd: cmd, status: "skipped", error: None }
+        } else {
+            match dispatch::dispatch(&cmd, &scratch, entry.msg) {
+                Ok(_) => Replayed { id: id, cmd: cmd, status: "ok", error: None },
+                Err(e) => Replayed { id: id, cmd: cmd, status: "error", error: Some(Turtl::error_value(&e)?) },
+            }
+        };
+        results.push(replayed);
+    }
+    Ok(results)
+}
+
+/// Registers our journal commands
+pub fn register(reg: &mut Registry) {
+    reg.add("app:replay-journal", |_turtl, args| {
+        let path: String = args.get(2)?;
+        Ok(jedi::to_val(&replay(&path)?)?)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacts_secrets() {
+        let login = json!(["1", "user:login", "andrew@turtlapp.com", "hunter2"]);
+        assert_eq!(redact(&login), json!(["1", "user:login", "andrew@turtlapp.com", REDACTED]));
+        let change = json!(["2", "user:change-password", "a", "old", "b", "new"]);
+        assert_eq!(redact(&change), json!(["2", "user:change-password", "a", REDACTED, "b", REDACTED]));
+        let job = json!(["3", "job:start", "user:login-from-token", "abc123"]);
+        assert_eq!(redact(&job), json!(["3", "job:start", "user:login-from-token", REDACTED]));
+        let load = json!(["4", "profile:load"]);
+        assert_eq!(redact(&load), load);
+        let batch = json!(["5", "batch:run", [
+            ["5.1", "user:login", "andrew@turtlapp.com", "hunter2"],
+            ["5.2", "user:reauth", "hunter2"],
+            ["5.3", "user:change-password", "a", "old", "b", "new"],
+            ["5.4", "profile:load"],
+        ], {"parallel": false}]);
+        assert_eq!(redact(&batch), json!(["5", "batch:run", [
+            ["5.1", "user:login", "andrew@turtlapp.com", REDACTED],
+            ["5.2", "user:reauth", REDACTED],
+            ["5.3", "user:change-password", "a", REDACTED, "b", REDACTED],
+            ["5.4", "profile:load"],
+        ], {"parallel": false}]));
+    }
+}
@@ -0,0 +1,1218 @@
+//! Dispatch takes messages sent from our wonderful UI and runs the needed core
+//! code to generate the response. Essentially, it's the RPC endpoint for core.
+//!
+//! Each message sent in is in the following format (JSON):
+//! 
+//!     ["<message id>", "<command>", arg1, arg2, ...]
+//!
+//! where the arg\* can be any valid JSON object. The Message ID is passed in
+//! when responding so the client knows which request we are responding to.
+//!
+//! Commands are looked up in a registry (see `registry`) that the various parts
+//! of the app add their commands to.
+//!
+//! UIs should open with `app:hello` to agree on a protocol version (see
+//! `protocol`).
+//!
+//! `cancel:<message id>` is reserved for cancelling a request that's still
+//! running (see `cancel`).
+
+pub mod registry;
+pub mod cancel;
+pub mod hooks;
+pub mod introspect;
+pub mod journal;
+pub mod protocol;
+pub mod stream;
+pub mod watchdog;
+
+use ::jedi::{self, Value};
+use ::error::{TResult, TError};
+use ::config;
+use ::api;
+use ::util::{self, logger, i18n};
+use ::turtl::Turtl;
+use ::search::{self, Query};
+use ::archive;
+use ::render;
+use ::markdown;
+use ::calendar;
+use ::merge;
+use ::replace;
+use ::split;
+use ::folder_sync;
+use ::backup;
+use ::recurrence;
+use ::quick_capture;
+use ::read_later;
+use ::spellcheck;
+use ::server_info;
+use ::ordering::{self, Order};
+use ::jobs;
+use ::undo;
+use ::webhook;
+use ::import::mail::{self, MailImportRequest};
+use ::import::csv::{self, CsvImportRequest};
+use ::profile::{Profile, Export, ImportMode};
+use ::models::model::Model;
+use ::models::protected::Protected;
+use ::models::user::User;
+use ::models::space::Space;
+use ::models::board::Board;
+use ::models::space_member::SpaceMember;
+use ::models::note::{Note, FindTextOptions};
+use ::models::invite::{Invite, InviteRequest};
+use ::models::contact::Contact;
+use ::models::comment::Comment;
+use ::models::reaction::Reaction;
+use ::models::receipt::Receipt;
+use ::models::time_entry;
+use ::models::email_gateway;
+use ::models::file;
+use ::models::sync_record::{SyncAction, SyncType, SyncRecord};
+use ::models::feedback::Feedback;
+use ::clippo::{self, CustomParser};
+use ::sync::sync_model;
+use ::sync::connectivity::Connectivity;
+use ::sync;
+use ::messaging::{self, Event, Incoming, Response};
+use ::messaging::heartbeat;
+use self::registry::{Registry, Args, Handler};
+use self::hooks::Hooks;
+use self::stream::Stream;
+use ::migrate;
+use ::crypto::Key;
+use ::std::panic;
+use ::std::sync::{Arc, RwLock};
+use ::std::time::{Duration, Instant};
+use ::crossbeam;
+use ::std::collections::HashMap;
+
+/// The commands we allow in safe mode: logging in/out, loading the profile
+/// (without sync or search), exporting, and the app-level repair tools.
+const SAFE_MODE_COMMANDS: &'static [&'static str] = &[
+    "user:login",
+    "user:login-from-token",
+    "user:login-from-saved",
+    "user:reauth",
+    "user:logout",
+    "app:connected",
+    "app:commands",
+    "app:hello",
+    "app:goodbye",
+    "events:subscribe",
+    "events:unsubscribe",
+    "batch:run",
+    "app:wipe-user-data",
+    "app:wipe-cache",
+    "app:wipe-app-data",
+    "app:wipe-local-data",
+    "app:get-locale",
+    "app:storage:force-unlock",
+    "app:storage:check",
+    "app:storage:recover",
+    "app:api:get-config",
+    "app:get-config",
+    "app:get-log",
+    "app:shutdown",
+    "sync:start",
+    "sync:status",
+    "sync:shutdown",
+    "sync:get-pending",
+    "sync:unfreeze-item",
+    "sync:delete-item",
+    "profile:load",
+    "profile:get-notes",
+    "profile:note:get-file",
+    "profile:export",
+    "job:status",
+    "job:cancel",
+    "job:list",
+    "feedback:send",
+    "ping",
+];
+
+/// Make sure a command is allowed to run (only matters in safe mode)
+fn check_safe_mode(cmd: &str) -> TResult<()> {
+    if util::safe_mode() && !SAFE_MODE_COMMANDS.contains(&cmd) {
+        return TErr!(TError::PermissionDenied(format!("{} is disabled in safe mode", cmd)));
+    }
+    Ok(())
+}
+
+/// Remember someone we shared with. The share itself already went through, so
+/// failing to save the contact shouldn't fail the whole command.
+fn touch_contact(turtl: &Turtl, email: &String, contact_user_id: Option<String>) {
+    match Contact::touch(turtl, email, contact_user_id) {
+        Ok(_) => {}
+        Err(e) => warn!("dispatch::touch_contact() -- error saving contact: {}", e),
+    }
+}
+
+lazy_static! {
+    /// Every command we know how to run
+    static ref REGISTRY: Registry = {
+        let mut reg = Registry::new();
+        register(&mut reg);
+        sync::register(&mut reg);
+        sync::peer::register(&mut reg);
+        search::register(&mut reg);
+        file::register(&mut reg);
+        jobs::register(&mut reg);
+        undo::register(&mut reg);
+        quick_capture::register(&mut reg);
+        read_later::register(&mut reg);
+        time_entry::register(&mut reg);
+        webhook::register(&mut reg);
+        archive::register(&mut reg);
+        calendar::register(&mut reg);
+        markdown::register(&mut reg);
+        folder_sync::register(&mut reg);
+        recurrence::register(&mut reg);
+        backup::register(&mut reg);
+        merge::register(&mut reg);
+        replace::register(&mut reg);
+        split::register(&mut reg);
+        render::register(&mut reg);
+        hooks::register(&mut reg);
+        protocol::register(&mut reg);
+        journal::register(&mut reg);
+        spellcheck::register(&mut reg);
+        server_info::register(&mut reg);
+        #[cfg(feature = "bench")]
+        ::bench::register(&mut reg);
+        #[cfg(feature = "debug-commands")]
+        ::synthetic::register(&mut reg);
+        reg
+    };
+
+    /// The hooks that wrap every command. Add your own with
+    /// `lockw!(dispatch::HOOKS).add_before(...)`.
+    pub static ref HOOKS: RwLock<Hooks> = {
+        let mut hooks = Hooks::new();
+        hooks.add_before("protocol", protocol::check_client);
+        hooks.add_before("safe_mode", |_turtl, cmd, _args| check_safe_mode(cmd));
+        hooks.add_before("locked", hooks::check_locked);
+        hooks.add_before("strict_args", introspect::check_strict);
+        hooks.add_after("timing", hooks::log_timing);
+        RwLock::new(hooks)
+    };
+}
+
+/// The commands we can run right now (in safe mode, that's not many)
+fn commands() -> Vec<&'static str> {
+    let mut names = REGISTRY.list();
+    // handled by process(), not the registry
+    names.push("job:start");
+    names.push("app:goodbye");
+    names.push("events:subscribe");
+    names.push("events:unsubscribe");
+    names.sort();
+    names.into_iter()
+        .filter(|x| !util::safe_mode() || SAFE_MODE_COMMANDS.contains(x))
+        .collect()
+}
+
+/// Registers the commands that don't belong to any one part of the app (users,
+/// spaces, boards, notes, and the app itself)
+fn register(reg: &mut Registry) {
+    reg.add("user:login", |turtl, args| {
+        let username: String = args.get(2)?;
+        let password: String = args.get(3)?;
+        turtl.login(username, password)?;
+        let user_guard = lockr!(turtl.user);
+        user_guard.data()
+    });
+    reg.add("user:login-from-token", |turtl, args| {
+        let token: String = args.get(2)?;
+        turtl.login_token(token)?;
+        let user_guard = lockr!(turtl.user);
+        user_guard.data()
+    });
+    reg.add("user:login-from-saved", |turtl, args| {
+        let user_id: String = args.get(2)?;
+        let key: Key = args.get(3)?;
+        let token = User::restore_login(user_id, key)?;
+        turtl.login_token(token)?;
+        let user_guard = lockr!(turtl.user);
+        user_guard.data()
+    });
+    reg.add("user:join", |turtl, args| {
+        let username: String = args.get(2)?;
+        let password: String = args.get(3)?;
+        turtl.join(username, password)?;
+        let user_guard = lockr!(turtl.user);
+        user_guard.data()
+    });
+    reg.add("user:can-migrate", |_turtl, args| {
+        let old_username: String = args.get(2)?;
+        let old_password: String = args.get(3)?;
+        match migrate::check_login(&old_username, &old_password) {
+            Ok(x) => {
+                match x {
+                    Some(_) => Ok(json!(true)),
+                    None => Ok(json!(false)),
+                }
+            }
+            Err(_) => Ok(json!(false)),
+        }
+    });
+    reg.add("user:join-migrate", |turtl, args| {
+        let old_username: String = args.get(2)?;
+        let old_password: String = args.get(3)?;
+        let new_username: String = args.get(4)?;
+        let new_password: String = args.get(5)?;
+        turtl.join_migrate(old_username, old_password, new_username, new_password)?;
+        let user_guard = lockr!(turtl.user);
+        user_guard.data()
+    });
+    reg.add("user:migrate-auth-debug", |_turtl, args| {
+        let old_username: String = args.get(2)?;
+        let old_password: String = args.get(3)?;
+        let result_v0 = ::migrate::user::generate_auth_debug(&old_username, &old_password, 0)?;
+        let result_v1 = ::migrate::user::generate_auth_debug(&old_username, &old_password, 1)?;
+        Ok(json!({
+            "v0": result_v0.1,
+            "v1": result_v1.1,
+        }))
+    });
+    reg.add("user:reauth", |turtl, args| {
+        let password: String = args.get(2)?;
+        let username: Option<String> = args.get_opt(3);
+        turtl.reauth(username, password)?;
+        let user_guard = lockr!(turtl.user);
+        user_guard.data()
+    });
+    reg.add("user:logout", |turtl, args| {
+        let clear_cookie: bool = match args.get(2) {
+            Ok(x) => x,
+            Err(_) => true,
+        };
+        if clear_cookie {
+            messaging::ui_event("user:logout:clear-cookie", &Value::Null)
+                .unwrap_or_else(|e| error!("dispatch::dispatch() -- error sending ui event: {}", e));
+        }
+        turtl.logout()?;
+        util::sleep(1000);
+        Ok(json!({}))
+    });
+    reg.add("user:change-password", |turtl, args| {
+        let current_username: String = args.get(2)?;
+        let current_password: String = args.get(3)?;
+        let new_username: String = args.get(4)?;
+        let new_password: String = args.get(5)?;
+        turtl.change_user_password(current_username, current_password, new_username, new_password)?;
+        Ok(json!({}))
+    });
+    reg.add("user:delete-account", |turtl, _args| {
+        messaging::ui_event("user:logout:clear-cookie", &Value::Null)
+            .unwrap_or_else(|e| error!("dispatch::dispatch() -- error sending ui event: {}", e));
+        turtl.delete_account()?;
+        Ok(json!({}))
+    });
+    reg.add("user:resend-confirmation", |turtl, _args| {
+        User::resend_confirmation(turtl)?;
+        Ok(json!({}))
+    });
+    reg.add("user:get-login-token", |turtl, args| {
+        let confirmation: String = args.get_opt(2).unwrap_or(String::from("WRONG"));
+        if confirmation != "I understand this token contains the user's master key and their account may be compromised if the token is misplaced." {
+            return TErr!(TError::PermissionDenied(String::from("Please send the confirmation string to get the token")));
+        }
+        let token = User::get_login_token(turtl)?;
+        Ok(Value::String(token))
+    });
+    reg.add("user:save-login", |turtl, _args| {
+        let key = User::save_login(turtl)?;
+        Ok(json!({"user_id": turtl.user_id()?, "key": key}))
+    });
+    reg.add("user:email-gateway:enable", |turtl, args| {
+        let board_id: String = args.get(2)?;
+        let settings = email_gateway::enable(turtl, &board_id)?;
+        Ok(jedi::to_val(&settings)?)
+    });
+    reg.add("user:email-gateway:rotate", |turtl, _args| {
+        let settings = email_gateway::rotate(turtl)?;
+        Ok(jedi::to_val(&settings)?)
+    });
+    reg.add("user:email-gateway:disable", |turtl, _args| {
+        email_gateway::disable(turtl)?;
+        Ok(json!({}))
+    });
+    reg.add("user:email-gateway:get", |turtl, _args| {
+        let settings = email_gateway::get_settings(turtl)?;
+        Ok(jedi::to_val(&settings)?)
+    });
+    reg.add("user:find-by-email", |turtl, args| {
+        let email: String = args.get(2)?;
+        let user = User::find_by_email(turtl, &email)?;
+        Ok(jedi::to_val(&user)?)
+    });
+    reg.add("app:connected", |turtl, _args| {
+        let connguard = lockr!(turtl.connected);
+        let connected: bool = *connguard;
+        drop(connguard);
+        Ok(Value::Bool(connected))
+    });
+    reg.add("app:wipe-user-data", |turtl, args| {
+        let user_id: Option<String> = args.get_opt(2);
+        match user_id {
+            Some(ref user_id) if Some(user_id) != lockr!(turtl.user_id).as_ref() => {
+                turtl.wipe_user_data_for(user_id)?;
+            }
+            _ => {
+                messaging::ui_event("user:logout:clear-cookie", &Value::Null)
+                    .unwrap_or_else(|e| error!("dispatch::dispatch() -- error sending ui event: {}", e));
+                turtl.wipe_user_data()?;
+            }
+        }
+        Ok(json!({}))
+    });
+    reg.add("app:wipe-cache", |turtl, _args| {
+        turtl.wipe_cache()?;
+        Ok(json!({}))
+    });
+    let wipe_app_data: Handler = |turtl, _args| {
+        messaging::ui_event("user:logout:clear-cookie", &Value::Null)
+            .unwrap_or_else(|e| error!("dispatch::dispatch() -- error sending ui event: {}", e));
+        let report = turtl.wipe_app_data()?;
+        Ok(jedi::to_val(&report)?)
+    };
+    reg.add("app:wipe-app-data", wipe_app_data);
+    reg.add("app:wipe-local-data", wipe_app_data);
+    reg.add("app:first-run", |turtl, _args| {
+        Ok(Value::Bool(turtl.is_first_run()?))
+    });
+    reg.add("app:first-run:complete", |turtl, _args| {
+        turtl.set_first_run_complete()?;
+        Ok(json!({}))
+    });
+    reg.add("app:set-locale", |_turtl, args| {
+        let locale: String = args.get(2)?;
+        let catalog: Option<HashMap<String, String>> = args.get_opt(3);
+        i18n::set_locale(&locale, catalog)?;
+        Ok(Value::String(i18n::get_locale()))
+    });
+    reg.add("app:get-locale", |_turtl, _args| {
+        Ok(Value::String(i18n::get_locale()))
+    });
+    reg.add("app:storage:force-unlock", |turtl, args| {
+        let user_id: Option<String> = args.get_opt(2);
+        let unlocked = turtl.force_unlock_storage(user_id.as_ref())?;
+        Ok(json!({"unlocked": unlocked}))
+    });
+    reg.add("app:storage:check", |turtl, _args| {
+        let problem = turtl.check_storage()?;
+        Ok(json!({"ok": problem.is_none(), "problem": problem}))
+    });
+    reg.add("app:storage:recover", |turtl, _args| {
+        turtl.recover_storage()?;
+        Ok(json!({}))
+    });
+    reg.add("app:api:set-config", |_turtl, args| {
+        let api_config: Value = args.get(2)?;
+        let config_merge = json!({
+            "api": api_config,
+        });
+        config::merge(&config_merge)?;
+        Ok(json!(config::get::<Value>(&["api"])?))
+    });
+    reg.add("app:api:get-config", |_turtl, _args| {
+        Ok(config::get::<Value>(&["api"])?)
+    });
+    reg.add("app:get-config", |_turtl, _args| {
+        Ok(config::dump()?)
+    });
+    reg.add("app:get-limits", |_turtl, _args| {
+        Ok(json!({
+            "note": sync_model::size_limit("note"),
+            "file": sync_model::size_limit("file"),
+        }))
+    });
+    reg.add("app:metrics", |_turtl, _args| {
+        Ok(json!({
+            "sync": sync::metrics::totals(),
+        }))
+    });
+    reg.add("app:get-log", |_turtl, args| {
+        let lines: i32 = args.get(2)?;
+        let contents = logger::read_log(lines)?;
+        Ok(Value::String(contents))
+    });
+    reg.add("app:shutdown", |turtl, _args| {
+        turtl.sync_shutdown(false)?;
+        messaging::stop();
+        Ok(json!({}))
+    });
+    reg.add("profile:load", |turtl, args| {
+        let counts_only: bool = args.get_in_opt(&["2", "counts_only"]).unwrap_or(false);
+        if counts_only {
+            let counts = Profile::counts(turtl)?;
+            let user_guard = lockr!(turtl.user);
+            return Ok(json!({
+                "user": &user_guard.as_ref(),
+                "counts": counts,
+            }));
+        }
+        let order = ordering::order_for("profile:load", args.get_in_opt(&["2", "sort"]), Order::Created);
+        let user_order: Vec<String> = args.get_in_opt(&["2", "ids"]).unwrap_or(Vec::new());
+        let user_guard = lockr!(turtl.user);
+        let profile_guard = lockr!(turtl.profile);
+        let mut spaces = profile_guard.spaces.iter().collect::<Vec<_>>();
+        let mut boards = profile_guard.boards.iter().collect::<Vec<_>>();
+        ordering::sort(&mut spaces, order, &user_order);
+        ordering::sort(&mut boards, order, &user_order);
+        if let Some(mut stream) = Stream::open(args.get_in_opt(&["2", "stream"]).unwrap_or(false)) {
+            stream.send("spaces", &spaces)?;
+            stream.send("boards", &boards)?;
+            return Ok(stream.finish(json!({
+                "user": &user_guard.as_ref(),
+                "invites": &profile_guard.invites,
+            })));
+        }
+        let profile_data = json!({
+            "user": &user_guard.as_ref(),
+            "spaces": &spaces,
+            "boards": &boards,
+            "invites": &profile_guard.invites,
+        });
+        Ok(profile_data)
+    });
+    reg.add("profile:sync:model", |turtl, args| {
+        let action: SyncAction = match args.get(2) {
+            Ok(action) => action,
+            Err(e) => return TErr!(TError::BadValue(format!("bad sync action: {}", e))),
+        };
+        let ty: SyncType = args.get(3)?;
+        let modeldata: Value = args.get(4)?;
+        // construct a sync record and hand to our sync dispatcher
+        let mut sync_record = SyncRecord::default();
+        sync_record.action = action;
+        sync_record.ty = ty;
+        sync_record.data = Some(modeldata);
+        undo::dispatch(turtl, sync_record)
+    });
+    reg.add("profile:space:set-owner", |turtl, args| {
+        let space_id = args.get(2)?;
+        let user_id = args.get(3)?;
+        let mut profile_guard = lockw!(turtl.profile);
+        let space = match Profile::finder(&mut profile_guard.spaces, &space_id) {
+            Some(s) => s,
+            None => return TErr!(TError::MissingData(format!("couldn't find space {}", space_id))),
+        };
+        space.set_owner(turtl, &user_id)?;
+        Ok(space.data()?)
+    });
+    reg.add("profile:space:edit-member", |turtl, args| {
+        let mut member: SpaceMember = args.get(2)?;
+        let mut profile_guard = lockw!(turtl.profile);
+        let space = match Profile::finder(&mut profile_guard.spaces, &member.space_id) {
+            Some(s) => s,
+            None => return TErr!(TError::MissingData(format!("couldn't find space {}", member.space_id))),
+        };
+        space.edit_member(turtl, &mut member)?;
+        Ok(space.data()?)
+    });
+    reg.add("profile:space:delete-member", |turtl, args| {
+        let space_id: String = args.get(2)?;
+        let user_id: String = args.get(3)?;
+        let mut profile_guard = lockw!(turtl.profile);
+        let space = match Profile::finder(&mut profile_guard.spaces, &space_id) {
+            Some(s) => s,
+            None => return TErr!(TError::MissingData(format!("couldn't find space {}", space_id))),
+        };
+        space.delete_member(turtl, &user_id)?;
+        Ok(space.data()?)
+    });
+    reg.add("profile:space:leave", |turtl, args| {
+        let space_id: String = args.get(2)?;
+        let mut profile_guard = lockw!(turtl.profile);
+        let space = match Profile::finder(&mut profile_guard.spaces, &space_id) {
+            Some(s) => s,
+            None => return TErr!(TError::MissingData(format!("couldn't find space {}", space_id))),
+        };
+        space.leave(turtl)?;
+        Ok(space.data()?)
+    });
+    reg.add("profile:space:send-invite", |turtl, args| {
+        let req: InviteRequest = args.get(2)?;
+        let to_user = req.to_user.clone();
+        let spacedata = {
+            let mut profile_guard = lockw!(turtl.profile);
+            let space = match Profile::finder(&mut profile_guard.spaces, &req.space_id) {
+                Some(s) => s,
+                None => return TErr!(TError::MissingData(format!("couldn't find space {}", req.space_id))),
+            };
+            space.send_invite(turtl, req)?;
+            space.data()?
+        };
+        touch_contact(turtl, &to_user, None);
+        Ok(spacedata)
+    });
+    reg.add("profile:space:edit-invite", |turtl, args| {
+        let mut invite: Invite = args.get(2)?;
+        let mut profile_guard = lockw!(turtl.profile);
+        let space = match Profile::finder(&mut profile_guard.spaces, &invite.space_id) {
+            Some(s) => s,
+            None => return TErr!(TError::MissingData(format!("couldn't find space {}", invite.space_id))),
+        };
+        space.edit_invite(turtl, &mut invite)?;
+        Ok(space.data()?)
+    });
+    reg.add("profile:space:delete-invite", |turtl, args| {
+        let space_id: String = args.get(2)?;
+        let invite_id: String = args.get(3)?;
+        let mut profile_guard = lockw!(turtl.profile);
+        let space = match Profile::finder(&mut profile_guard.spaces, &space_id) {
+            Some(s) => s,
+            None => return TErr!(TError::MissingData(format!("couldn't find space {}", space_id))),
+        };
+        space.delete_invite(turtl, &invite_id)?;
+        Ok(space.data()?)
+    });
+    reg.add("profile:board:send-invite", |turtl, args| {
+        let board_id: String = args.get(2)?;
+        let req: InviteRequest = args.get(3)?;
+        let to_user = req.to_user.clone();
+        let board = Board::send_invite(turtl, &board_id, req)?;
+        touch_contact(turtl, &to_user, None);
+        Ok(board.data()?)
+    });
+    reg.add("profile:board:delete-invite", |turtl, args| {
+        let board_id: String = args.get(2)?;
+        let invite_id: String = args.get(3)?;
+        let board = Board::delete_invite(turtl, &board_id, &invite_id)?;
+        Ok(board.data()?)
+    });
+    reg.add("profile:board:delete-member", |turtl, args| {
+        let board_id: String = args.get(2)?;
+        let user_id: String = args.get(3)?;
+        let board = Board::delete_member(turtl, &board_id, &user_id)?;
+        Ok(board.data()?)
+    });
+    reg.add("profile:accept-invite", |turtl, args| {
+        let mut invite: Invite = args.get(2)?;
+        let passphrase: Option<String> = args.get_opt(3);
+        let inviter = (invite.from_username.clone(), invite.from_user_id.clone());
+        let val = if invite.board_id.is_some() {
+            Board::accept_invite(turtl, &mut invite, passphrase)?.data()?
+        } else {
+            Space::accept_invite(turtl, &mut invite, passphrase)?.data()?
+        };
+        touch_contact(turtl, &inviter.0, Some(inviter.1));
+        Ok(val)
+    });
+    reg.add("profile:contacts", |turtl, args| {
+        let search: Option<String> = args.get_opt(2);
+        let contacts = Contact::find(turtl, search)?;
+        Ok(jedi::to_val(&contacts)?)
+    });
+    reg.add("profile:remove-contact", |turtl, args| {
+        let contact_id: String = args.get(2)?;
+        sync_model::delete_model::<Contact>(turtl, &contact_id, false)?;
+        Ok(json!({}))
+    });
+    reg.add("profile:delete-invite", |turtl, args| {
+        let invite_id: String = args.get(2)?;
+        Invite::delete_user_invite(turtl, &invite_id)?;
+        Ok(json!({}))
+    });
+    reg.add("board:move-to-space", |turtl, args| {
+        let board_id: String = args.get(2)?;
+        let space_id: String = args.get(3)?;
+        let board = Board::move_to_space(turtl, &board_id, space_id)?;
+        Ok(board.data()?)
+    });
+    reg.add("board:move-note-column", |turtl, args| {
+        let board_id: String = args.get(2)?;
+        let note_id: String = args.get(3)?;
+        let column_id: String = args.get(4)?;
+        let position: usize = args.get_opt(5).unwrap_or(::std::usize::MAX);
+        let notes = Board::move_note_column(turtl, &board_id, &note_id, &column_id, position)?;
+        Ok(jedi::to_val(&notes)?)
+    });
+    reg.add("profile:get-notes", |turtl, args| {
+        let note_ids = args.get(2)?;
+        let order = ordering::order_for("profile:get-notes", args.get_in_opt(&["3", "sort"]), Order::User);
+        let mut notes: Vec<Note> = turtl.load_notes(&note_ids)?;
+        ordering::sort(&mut notes, order, &note_ids);
+        if let Some(mut stream) = Stream::open(args.get_in_opt(&["3", "stream"]).unwrap_or(false)) {
+            let chunk_size = stream.chunk_size();
+            for chunk in notes.chunks(chunk_size) {
+                cancel::check()?;
+                let vals = Reaction::attach(turtl, &chunk.to_vec())?;
+                let count = vals.len();
+                stream.send_vals("notes", Value::Array(vals), count)?;
+            }
+            return Ok(stream.finish(json!({})));
+        }
+        Ok(jedi::to_val(&Reaction::attach(turtl, &notes)?)?)
+    });
+    reg.add("note:watch", |turtl, args| {
+        let note_id: String = args.get(2)?;
+        let watched = Note::set_watch(turtl, &note_id, true)?;
+        Ok(jedi::to_val(&watched)?)
+    });
+    reg.add("note:unwatch", |turtl, args| {
+        let note_id: String = args.get(2)?;
+        let watched = Note::set_watch(turtl, &note_id, false)?;
+        Ok(jedi::to_val(&watched)?)
+    });
+    reg.add("note:comment:add", |turtl, args| {
+        let note_id: String = args.get(2)?;
+        let body: String = args.get(3)?;
+        let comment = Comment::add(turtl, &note_id, body)?;
+        Ok(comment.data()?)
+    });
+    reg.add("note:comment:list", |turtl, args| {
+        let note_id: String = args.get(2)?;
+        let comments = Comment::list(turtl, &note_id)?;
+        Ok(jedi::to_val(&comments)?)
+    });
+    reg.add("note:comment:delete", |turtl, args| {
+        let comment_id: String = args.get(2)?;
+        Comment::delete(turtl, &comment_id)?;
+        Ok(json!({}))
+    });
+    reg.add("note:react", |turtl, args| {
+        let note_id: String = args.get(2)?;
+        let emoji: String = args.get(3)?;
+        Reaction::react(turtl, &note_id, emoji)?;
+        let notes: Vec<Note> = turtl.load_notes(&vec![note_id])?;
+        Ok(jedi::to_val(&Reaction::attach(turtl, &notes)?)?)
+    });
+    reg.add("note:unreact", |turtl, args| {
+        let note_id: String = args.get(2)?;
+        let emoji: String = args.get(3)?;
+        Reaction::unreact(turtl, &note_id, &emoji)?;
+        let notes: Vec<Note> = turtl.load_notes(&vec![note_id])?;
+        Ok(jedi::to_val(&Reaction::attach(turtl, &notes)?)?)
+    });
+    reg.add("note:mark-seen", |turtl, args| {
+        let note_id: String = args.get(2)?;
+        Receipt::mark_seen(turtl, &note_id)?;
+        Ok(json!({}))
+    });
+    reg.add("note:seen-by", |turtl, args| {
+        let note_id: String = args.get(2)?;
+        let seen = Receipt::seen_by(turtl, &note_id)?;
+        Ok(jedi::to_val(&seen)?)
+    });
+    reg.add("note:find-text", |turtl, args| {
+        let note_id: String = args.get(2)?;
+        let query: String = args.get(3)?;
+        let options: FindTextOptions = args.get_opt(4).unwrap_or(Default::default());
+        let notes: Vec<Note> = turtl.load_notes(&vec![note_id.clone()])?;
+        let note = match notes.get(0) {
+            Some(x) => x,
+            None => return TErr!(TError::NotFound(format!("note {} wasn't found", note_id))),
+        };
+        Ok(jedi::to_val(&note.find_text(&query, &options)?)?)
+    });
+    reg.add("profile:import-mail", |turtl, args| {
+        let req: MailImportRequest = args.get(2)?;
+        let summary = mail::import_mail(turtl, req)?;
+        Ok(jedi::to_val(&summary)?)
+    });
+    reg.add("profile:import-csv", |turtl, args| {
+        let req: CsvImportRequest = args.get(2)?;
+        let result = csv::import_csv(turtl, req)?;
+        Ok(jedi::to_val(&result)?)
+    });
+    reg.add("profile:seed-sample-content", |turtl, _args| {
+        let result = Profile::seed_sample_content(turtl)?;
+        Ok(jedi::to_val(&result)?)
+    });
+    reg.add("profile:export", |turtl, args| {
+        let query: Option<Query> = match args.get_opt(2) {
+            Some(Value::Null) | None => None,
+            Some(x) => match jedi::from_val(x) {
+                Ok(x) => Some(x),
+                Err(e) => return TErr!(TError::BadValue(format!("error deserializing search query: {}", e))),
+            },
+        };
+        let export = Profile::export(turtl, query)?;
+        Ok(jedi::to_val(&export)?)
+    });
+    reg.add("profile:import", |turtl, args| {
+        let mode: ImportMode = args.get(2)?;
+        let export: Export = args.get(3)?;
+        let result = Profile::import(turtl, mode, export)?;
+        Ok(jedi::to_val(&result)?)
+    });
+    reg.add("feedback:send", |turtl, args| {
+        let feedback: Feedback = args.get(2)?;
+        feedback.send(turtl)?;
+        Ok(json!({}))
+    });
+    reg.add("clip", |_turtl, args| {
+        let url: String = args.get(2)?;
+        let custom_parsers: Vec<CustomParser> = args.get(3)?;
+        let proxy_cfg: Option<String> = config::get(&["api", "proxy"])
+            .unwrap_or(None);
+        let res = clippo::clip(&url, &custom_parsers, proxy_cfg)?;
+        Ok(jedi::to_val(&res)?)
+    });
+    reg.add("ping", |_turtl, args| {
+        info!("ping!");
+        messaging::ui_event("pong", &Value::Null)?;
+        // UIs send their clock along to time the round trip
+        match args.get_opt::<i64>(2) {
+            Some(t) => Ok(json!({"t": t, "core_t": heartbeat::now(), "heartbeat": heartbeat::seq()})),
+            None => Ok(Value::String(String::from("pong"))),
+        }
+    });
+    reg.add("util:new-id", |turtl, _args| {
+        let id = with_db!{ db, turtl.db, db.new_id()? };
+        Ok(Value::String(id))
+    });
+    reg.add("batch:run", |turtl, args| {
+        let msgs: Vec<Value> = args.get(2)?;
+        let parallel: bool = args.get_in_opt(&["3", "parallel"]).unwrap_or(false);
+        Ok(jedi::to_val(&run_batch(turtl, msgs, parallel))?)
+    });
+    reg.add("app:commands", |_turtl, _args| {
+        let descriptions = commands().into_iter()
+            .map(introspect::describe)
+            .collect::<Vec<_>>();
+        Ok(jedi::to_val(&descriptions)?)
+    });
+}
+
+/// Does our actual message dispatching
+fn dispatch(cmd: &String, turtl: &Turtl, data: Value) -> TResult<Value> {
+    let args = Args::new(&data);
+    let handler = match REGISTRY.get(cmd.as_str()) {
+        Some(x) => x,
+        None => return TErr!(TError::MissingCommand(cmd.clone())),
+    };
+    // don't hold the hooks lock while the command runs, or a command that adds
+    // a hook would deadlock
+    lockr!(*HOOKS).run_before(turtl, cmd, &args)?;
+    let start = Instant::now();
+    let res = handler(turtl, &args);
+    let elapsed = start.elapsed();
+    let elapsed_ms = (elapsed.as_secs() * 1000) + (elapsed.subsec_nanos() / 1000000) as u64;
+    lockr!(*HOOKS).run_after(turtl, cmd, &res, elapsed_ms);
+    res
+}
+
+/// Event dispatching. This acts as a way for parts of the app that don't have
+/// access to the Turtl object to trigger events.
+fn dispatch_event(cmd: &String, turtl: &Turtl, data: Value) -> TResult<()> {
+    info!("dispatch::dispatch_event() -- {}", cmd);
+    match cmd.as_ref() {
+        "sync:connected" => {
+            let yesno: bool = jedi::from_val(data)?;
+            let mut connguard = lockw!(turtl.connected);
+            let cur_yesno = *connguard;
+            *connguard = yesno;
+            if cur_yesno != yesno {
+                // only send the ui event if we've changed state
+                messaging::ui_event("sync:connected", &yesno)
+                    .unwrap_or_else(|e| error!("dispatch::dispatch_event() -- error sending connected UI event: {}", e));
+            }
+        }
+        "sync:connectivity" => {
+            let connectivity: Connectivity = jedi::from_val(data)?;
+            let mut guard = lockw!(turtl.connectivity);
+            if *guard != connectivity {
+                *guard = connectivity;
+                messaging::ui_event("sync:connectivity", &connectivity)
+                    .unwrap_or_else(|e| error!("dispatch::dispatch_event() -- error sending connectivity UI event: {}", e));
+            }
+        }
+        "sync:incoming" => {
+            sync::incoming::process_incoming_sync(turtl)?;
+            markdown::after_sync(turtl);
+            folder_sync::run(turtl);
+        }
+        "folder-sync:scan" => {
+            folder_sync::run(turtl);
+        }
+        "recurrence:run" => {
+            match recurrence::run(turtl) {
+                Ok(_) => {}
+                Err(e) => error!("dispatch::dispatch_event() -- error running recurrences: {}", e),
+            }
+        }
+        "backup:run" => {
+            backup::run_scheduled(turtl);
+        }
+        "notes:upgrade" => {
+            let note_ids: Vec<String> = jedi::from_val(data)?;
+            Note::upgrade(turtl, &note_ids)?;
+        }
+        "user:auth-invalid" => {
+            turtl.auth_invalidated()?;
+        }
+        "search:reindex" => {
+            turtl.reindex(None)?;
+        }
+        "user:edit" => {
+            let mut user_guard = lockw!(turtl.user);
+            user_guard.merge_fields(&data)?;
+        }
+        "user:change-password:logout" => {
+            messaging::ui_event("user:change-password:logout", &json!({}))?;
+            util::sleep(3000);
+            turtl.logout()?;
+        }
+        "space:delete" => {
+            let space_id: String = jedi::get(&["0"], &data)?;
+            let skip_remote_sync: bool = match jedi::get_opt(&["1"], &data) {
+                Some(x) => x,
+                None => false,
+            };
+            sync_model::delete_model::<Space>(turtl, &space_id, skip_remote_sync)?;
+        }
+        _ => {
+            warn!("dispatch_event() -- encountered unknown event: {}", cmd);
+        }
+    }
+    Ok(())
+}
+
+/// Start a job. We answer right away with the job, then run the command it
+/// wraps on this thread:
+///
+///     ["<message id>", "job:start", "<command>", arg1, arg2, ...]
+///
+/// runs `<command>` as if it had been sent with `arg1, arg2, ...`.
+fn start_job(turtl: &Turtl, client: Option<&String>, mid: &String, data: Value) -> TResult<()> {
+    let mut args: Vec<Value> = jedi::from_val(data)?;
+    if args.len() < 3 {
+        return TErr!(TError::MissingField(String::from("missing job command (2)")));
+    }
+    // ["mid", "job:start", "cmd", ...] -> ["mid", "cmd", ...]
+    args.remove(1);
+    let cmd: String = jedi::from_val(args[1].clone())?;
+    check_safe_mode(&cmd)?;
+    let job = jobs::create(&cmd)?;
+    turtl.msg_success(client, mid, jedi::to_val(&job)?)?;
+    info!("dispatch::start_job() -- job {}: {}", job.id, cmd);
+    jobs::run(&job.id, || dispatch(&cmd, turtl, Value::Array(args)));
+    Ok(())
+}
+
+/// Commands that can't be run from inside a batch
+const BATCH_EXCLUDED: &'static [&'static str] = &["batch:run", "job:start"];
+
+/// Run one command out of a batch, returning its response
+fn run_batched(turtl: &Turtl, msg: Value) -> Response {
+    let mid: String = jedi::get_opt(&["0"], &msg).unwrap_or(String::new());
+    let res = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+        let cmd: String = match jedi::get(&["1"], &msg) {
+            Ok(x) => x,
+            Err(_) => return TErr!(TError::MissingField(String::from("missing cmd (1)"))),
+        };
+        if BATCH_EXCLUDED.contains(&cmd.as_str()) {
+            return TErr!(TError::BadValue(format!("{} can't be run in a batch", cmd)));
+        }
+        info!("dispatch::run_batched({}): {}", mid, cmd);
+        api::set_deadline(api::command_deadline(&cmd));
+        let res = dispatch(&cmd, turtl, msg.clone());
+        api::set_deadline(None);
+        res
+    }));
+    let res = match res {
+        Ok(x) => x,
+        Err(e) => {
+            let err = e.downcast::<String>().unwrap_or(Box::new(String::from("no information available")));
+            error!("dispatch::run_batched() -- panic: {}", err);
+            Err(TError::Panic(format!("dispatch panic: {}", err)))
+        }
+    };
+    match res {
+        Ok(val) => Response::new_w_id(mid, 0, val),
+        Err(e) => {
+            let errval = Turtl::error_value(&e).unwrap_or(Value::String(format!("{}", e)));
+            Response::new_w_id(mid, 1, errval).with_code(e.code())
+        }
+    }
+}
+
+/// Run a set of commands in one go, answering with all of their responses (in
+/// the same order):
+///
+///     ["<message id>", "batch:run", [["<mid>", "<cmd>", arg1, ...], ...], {"parallel": false}]
+///
+/// One command failing doesn't stop the others. Commands run one after the
+/// other unless `parallel` is set, in which case they each get a thread and
+/// shouldn't depend on each other.
+fn run_batch(turtl: &Turtl, msgs: Vec<Value>, parallel: bool) -> Vec<Response> {
+    if !parallel || msgs.len() < 2 {
+        return msgs.into_iter()
+            .map(|msg| run_batched(turtl, msg))
+            .collect();
+    }
+    crossbeam::scope(|scope| {
+        let handles = msgs.into_iter()
+            .map(|msg| scope.spawn(move || run_batched(turtl, msg)))
+            .collect::<Vec<_>>();
+        handles.into_iter()
+            .map(|handle| handle.join())
+            .collect()
+    })
+}
+
+/// Answer a message we aren't going to run (say, because we're shutting down)
+/// with an error, so the UI isn't left waiting on it
+pub fn reject(turtl: &Turtl, msg: &[u8], err: TError) -> TResult<()> {
+    let (client, data) = match messaging::parse_incoming(msg)? {
+        Incoming::AppEvent(Event {e, ..}) => {
+            info!("dispatch::reject() -- dropping app event {}", e);
+            return Ok(());
+        }
+        Incoming::Request(client, x) => (client, x),
+    };
+    let mid: String = match jedi::get(&["0"], &data) {
+        Ok(x) => x,
+        Err(_) => return TErr!(TError::MissingField(String::from("missing mid (0)"))),
+    };
+    info!("dispatch::reject({}) -- {}", mid, err);
+    turtl.msg_error(client.as_ref(), &mid, &err)
+}
+
+/// How long a command gets to answer before we answer for it. Configured via
+/// the `timeouts` section (0 means it can take as long as it likes).
+fn command_timeout(cmd: &str) -> Option<Duration> {
+    let secs: u64 = config::get(&["timeouts", "commands", cmd])
+        .or_else(|_| config::get(&["timeouts", "command"]))
+        .unwrap_or(0);
+    if secs == 0 { return None; }
+    Some(Duration::new(secs, 0))
+}
+
+/// Run a command, giving its api calls a deadline
+fn run_command(turtl: &Turtl, cmd: &String, data: Value) -> TResult<Value> {
+    api::set_deadline(api::command_deadline(cmd));
+    let res = dispatch(cmd, turtl, data);
+    api::set_deadline(None);
+    res
+}
+
+/// Run a command (via `run`) and hand its result to `respond`. Jobs are
+/// allowed to take their time (they can be cancelled instead), but regular
+/// commands get a deadline their api calls have to meet, and a timeout for
+/// the command as a whole.
+///
+/// The command runs right here, and the watchdog keeps time. We can't stop a
+/// handler that's stuck on a lock, but once its time is up the UI gets a
+/// `timeout` error for it and the request is finished (so it bails if it ever
+/// gets around to checking). The handler keeps its pipeline slot until it
+/// actually returns, so stuck handlers can't pile up past our concurrency
+/// limit, and whatever it comes up with gets dropped.
+fn run_with_timeout<R, F>(turtl: &Turtl, cmd: &String, mid: &String, data: Value, run: R, respond: F)
+    where R: FnOnce(&Turtl, Value) -> TResult<Value>,
+          F: Fn(TResult<Value>) + Send + Sync + 'static
+{
+    let timeout = match command_timeout(cmd) {
+        Some(x) => x,
+        None => return respond(run(turtl, data)),
+    };
+    let respond = Arc::new(respond);
+    let respond2 = respond.clone();
+    let cmd2 = cmd.clone();
+    let mid2 = mid.clone();
+    let watch = watchdog::watch(timeout, Box::new(move || {
+        error!("dispatch::run_with_timeout() -- {} ({}) timed out after {}s", cmd2, mid2, timeout.as_secs());
+        cancel::finish(&mid2);
+        respond2(TErr!(TError::Timeout(String::from("command"), format!("{} took longer than {}s", cmd2, timeout.as_secs()))));
+    }));
+    let res = panic::catch_unwind(panic::AssertUnwindSafe(|| run(turtl, data)));
+    if !watchdog::unwatch(watch) {
+        warn!("dispatch::run_with_timeout() -- {} ({}) finished after timing out", cmd, mid);
+        return;
+    }
+    match res {
+        Ok(res) => respond(res),
+        Err(e) => panic::resume_unwind(e),
+    }
+}
+
+/// process a message from the messaging system. this is the main communication
+/// heart of turtl core.
+pub fn process(turtl: &Arc<Turtl>, msg: &[u8]) -> TResult<()> {
+    let (client, data) = match messaging::parse_incoming(msg)? {
+        Incoming::AppEvent(Event {e, d}) => return dispatch_event(&e, turtl, d),
+        Incoming::Request(client, x) => (client, x),
+    };
+
+    // grab the request id from the data
+    let mid: String = match jedi::get(&["0"], &data) {
+        Ok(x) => x,
+        Err(_) => return TErr!(TError::MissingField(String::from("missing mid (0)"))),
+    };
+    // grab the command from the data
+    let cmd: String = match jedi::get(&["1"], &data) {
+        Ok(x) => x,
+        Err(_) => return TErr!(TError::MissingField(String::from("missing cmd (1)"))),
+    };
+
+    info!("dispatch({}): {}", mid, cmd);
+    journal::record(&data);
+
+    let client = client.as_ref();
+    if let Some(client) = client {
+        messaging::add_client(client);
+    }
+
+    if cmd.starts_with(cancel::PREFIX) {
+        let cancelled = cancel::request(&String::from(&cmd[cancel::PREFIX.len()..]));
+        return turtl.msg_success(client, &mid, json!({"cancelled": cancelled}));
+    }
+    // handled here, since commands don't know who sent them
+    if cmd == "app:goodbye" {
+        let removed = client.map(|x| messaging::remove_client(x)).unwrap_or(false);
+        return turtl.msg_success(client, &mid, Value::Bool(removed));
+    }
+    if cmd == "events:subscribe" || cmd == "events:unsubscribe" {
+        let res = match cmd.as_str() {
+            "events:subscribe" => {
+                jedi::get::<Vec<String>>(&["2"], &data)
+                    .map_err(|e| toterr!(e))
+                    .and_then(|patterns| messaging::subscribe(client, &patterns))
+            }
+            _ => {
+                let patterns: Option<Vec<String>> = jedi::get_opt(&["2"], &data);
+                Ok(messaging::unsubscribe(client, patterns.as_ref()))
+            }
+        };
+        return match res {
+            Ok(subscriptions) => turtl.msg_success(client, &mid, json!({"subscriptions": subscriptions})),
+            Err(e) => turtl.msg_error(client, &mid, &e),
+        };
+    }
+
+    let res = panic::catch_unwind(|| {
+        if cmd == "job:start" {
+            match start_job(turtl, client, &mid, data) {
+                Err(e) => {
+                    match turtl.msg_error(client, &mid, &e) {
+                        Err(e) => error!("dispatch:process() -- problem sending (error) response (mod {}): {}", mid, e),
+                        _ => {},
+                    }
+                }
+                _ => {}
+            }
+            return;
+        }
+        cancel::begin(&mid);
+        let cmd2 = cmd.clone();
+        let run = move |turtl: &Turtl, data: Value| run_command(turtl, &cmd2, data);
+        // the watchdog might answer for us, so this needs its own copies
+        let turtl2 = turtl.clone();
+        let client2 = client.cloned();
+        let mid2 = mid.clone();
+        run_with_timeout(turtl, &cmd, &mid, data, run, move |res| {
+            let client = client2.as_ref();
+            let mid = &mid2;
+            match res {
+                Ok(val) => {
+                    match turtl2.msg_success(client, mid, val) {
+                        Err(e) => error!("dispatch::process() -- problem sending response (mid {}): {}", mid, e),
+                        _ => {},
+                    }
+                },
+                Err(e) => {
+                    match turtl2.msg_error(client, mid, &e) {
+                        Err(e) => error!("dispatch:process() -- problem sending (error) response (mod {}): {}", mid, e),
+                        _ => {},
+                    }
+                },
+            }
+        });
+    });
+    // done with the request whether it panicked or not
+    cancel::end();
+    match res {
+        Ok(..) => {}
+        Err(e) => {
+            let err = e.downcast::<String>().unwrap_or(Box::new(String::from("no information available")));
+            error!("dispatch::process() -- panic: {}", err);
+            match turtl.msg_error(client, &mid, &TError::Panic(format!("dispatch panic: {}", err))) {
+                Err(e) => error!("dispatch:process() -- problem sending (panic) response (mod {}): {}", mid, e),
+                _ => {},
+            }
+        }
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::std::sync::mpsc;
+    use ::std::thread;
+
+    #[test]
+    fn describes_every_command() {
+        let names = commands();
+        for name in &names {
+            assert!(introspect::is_described(name), "missing description for {}", name);
+        }
+        assert!(!introspect::describe("user:login").login);
+        assert!(introspect::describe("profile:load").login);
+        assert_eq!(introspect::describe("user:login").args, vec!["username: string", "password: string"]);
+    }
+
+    #[test]
+    fn gives_up_on_slow_commands() {
+        use ::std::sync::Mutex;
+        use ::std::sync::atomic::{AtomicBool, Ordering};
+        use ::error::ErrorCode;
+
+        let turtl = Arc::new(::turtl::tests::with_test(false));
+        config::set(&["timeouts", "commands", "test:sleep"], &1).unwrap();
+        let cmd = String::from("test:sleep");
+        let mid = String::from("slow-1");
+        let (done_tx, done_rx) = mpsc::channel();
+        let run = move |_turtl: &Turtl, _data: Value| -> TResult<Value> {
+            thread::sleep(Duration::from_millis(3000));
+            let cancelled = cancel::check().is_err();
+            done_tx.send(cancelled).unwrap();
+            Ok(json!("too late"))
+        };
+        let responses = Arc::new(Mutex::new(Vec::new()));
+        let responses2 = responses.clone();
+        let returned = Arc::new(AtomicBool::new(false));
+        let returned2 = returned.clone();
+        let mid2 = mid.clone();
+        // stands in for a pipeline thread
+        let handle = thread::spawn(move || {
+            cancel::begin(&mid2);
+            run_with_timeout(&turtl, &cmd, &mid2, json!([]), run, move |res| lock!(responses2).push(res));
+            cancel::end();
+            returned2.store(true, Ordering::SeqCst);
+        });
+
+        // we answer once time's up, without waiting on the handler
+        thread::sleep(Duration::from_millis(2000));
+        {
+            let responses = lock!(responses);
+            assert_eq!(responses.len(), 1);
+            assert_eq!(responses[0].as_ref().unwrap_err().code(), ErrorCode::Timeout);
+        }
+        // and the request is done as far as anyone else is concerned...
+        assert!(!cancel::request(&mid));
+        // ...but the handler keeps its slot until it comes back
+        assert!(!returned.load(Ordering::SeqCst));
+
+        // the handler finishes on its own, sees it was cancelled, and nobody
+        // hears about its result
+        assert_eq!(done_rx.recv().unwrap(), true);
+        handle.join().unwrap();
+        assert!(returned.load(Ordering::SeqCst));
+        assert_eq!(lock!(responses).len(), 1);
+
+        // no timeout, no watchdog: we just run it
+        config::set(&["timeouts", "commands", "test:quick"], &0).unwrap();
+        let responses = Arc::new(Mutex::new(Vec::new()));
+        let responses2 = responses.clone();
+        let turtl = Arc::new(::turtl::tests::with_test(false));
+        run_with_timeout(&turtl, &String::from("test:quick"), &String::from("quick-1"), json!([]), |_turtl, _data| Ok(json!("hi")), move |res| lock!(responses2).push(res));
+        assert_eq!(lock!(responses)[0].as_ref().unwrap(), &json!("hi"));
+    }
+}
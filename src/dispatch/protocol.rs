@@ -0,0 +1,134 @@
+//! Protocol version negotiation. A UI starts with
+//!
+//!     ["<message id>", "app:hello", <protocol version>]
+//!
+//! and gets back our protocol version along with what this build of the core
+//! can do (file sync, websockets, etc). A UI that's too old for us gets told
+//! so, and after that its commands are refused up front instead of failing in
+//! strange ways halfway through. UIs that never say hello are treated as
+//! speaking our version.
+
+use ::std::sync::atomic::{AtomicUsize, Ordering};
+use ::jedi;
+use ::config;
+use ::util;
+use ::error::{TResult, TError};
+use ::turtl::Turtl;
+use ::dispatch::registry::{Registry, Args};
+
+/// The version of the dispatch protocol we speak. Bump this when commands or
+/// their arguments/responses change in ways a UI would notice.
+pub const PROTOCOL_VERSION: usize = 1;
+
+/// The oldest UI protocol version we still understand
+pub const MIN_PROTOCOL_VERSION: usize = 1;
+
+lazy_static! {
+    /// The protocol version the UI said hello with (0 if it hasn't)
+    static ref CLIENT_VERSION: AtomicUsize = AtomicUsize::new(0);
+}
+
+/// What we tell the UI about ourselves
+#[derive(Serialize, Debug)]
+pub struct Hello {
+    pub version: usize,
+    pub min_version: usize,
+    pub core_version: &'static str,
+    pub compatible: bool,
+    pub capabilities: Capabilities,
+}
+
+/// The optional parts of the core, and whether they're on in this build/config
+#[derive(Serialize, Debug)]
+pub struct Capabilities {
+    pub file_sync: bool,
+    pub websockets: bool,
+    pub msgpack: bool,
+    pub jobs: bool,
+    pub cancel: bool,
+    pub batch: bool,
+    pub strict_args: bool,
+    pub safe_mode: bool,
+    pub heartbeat: bool,
+    pub streaming: bool,
+}
+
+impl Capabilities {
+    fn current() -> Capabilities {
+        let enabled = |path: &[&str]| config::get(path).unwrap_or(false);
+        let setting = |path: &[&str]| config::get::<String>(path).unwrap_or(String::new());
+        Capabilities {
+            file_sync: enabled(&["sync", "enable_files_incoming"]) || enabled(&["sync", "enable_files_outgoing"]),
+            websockets: setting(&["messaging", "transport"]) == "websocket",
+            msgpack: setting(&["messaging", "format"]) == "msgpack",
+            jobs: true,
+            cancel: true,
+            batch: true,
+            strict_args: enabled(&["strict_args"]),
+            safe_mode: util::safe_mode(),
+            heartbeat: config::get::<u64>(&["messaging", "heartbeat", "interval"]).unwrap_or(5000) > 0,
+            streaming: true,
+        }
+    }
+}
+
+/// Whether we can talk to a UI speaking the given protocol version. UIs newer
+/// than us are expected to talk down to our version.
+fn compatible(version: usize) -> bool {
+    version >= MIN_PROTOCOL_VERSION
+}
+
+/// The protocol version we're speaking with the UI: the older of theirs and
+/// ours.
+pub fn negotiated() -> usize {
+    match CLIENT_VERSION.load(Ordering::SeqCst) {
+        0 => PROTOCOL_VERSION,
+        x => ::std::cmp::min(x, PROTOCOL_VERSION),
+    }
+}
+
+/// Refuses commands from a UI whose protocol version we don't understand (it
+/// can always say hello again)
+pub fn check_client(_turtl: &Turtl, cmd: &str, _args: &Args) -> TResult<()> {
+    let version = CLIENT_VERSION.load(Ordering::SeqCst);
+    if version == 0 || compatible(version) || cmd == "app:hello" { return Ok(()); }
+    TErr!(TError::Incompatible(format!("UI protocol version {} is too old (we speak {}, and need at least {})", version, PROTOCOL_VERSION, MIN_PROTOCOL_VERSION)))
+}
+
+/// Registers our handshake command
+pub fn register(reg: &mut Registry) {
+    reg.add("app:hello", |_turtl, args| {
+        let version: usize = args.get(2)?;
+        if version == 0 {
+            return TErr!(TError::BadValue(String::from("protocol version must be 1 or higher")));
+        }
+        CLIENT_VERSION.store(version, Ordering::SeqCst);
+        if !compatible(version) {
+            warn!("dispatch::protocol -- UI speaks protocol version {}, which we don't support", version);
+        }
+        let hello = Hello {
+            version: PROTOCOL_VERSION,
+            min_version: MIN_PROTOCOL_VERSION,
+            core_version: env!("CARGO_PKG_VERSION"),
+            compatible: compatible(version),
+            capabilities: Capabilities::current(),
+        };
+        Ok(jedi::to_val(&hello)?)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiates_versions() {
+        assert!(!compatible(MIN_PROTOCOL_VERSION - 1));
+        assert!(compatible(PROTOCOL_VERSION));
+        assert!(compatible(PROTOCOL_VERSION + 1));
+        assert_eq!(negotiated(), PROTOCOL_VERSION);
+        CLIENT_VERSION.store(PROTOCOL_VERSION + 3, Ordering::SeqCst);
+        assert_eq!(negotiated(), PROTOCOL_VERSION);
+        CLIENT_VERSION.store(0, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,120 @@
+//! The command registry. Rather than one giant match, each part of the app
+//! registers the commands it owns (see the `register()` functions scattered
+//! around, and `dispatch::REGISTRY` for where they're all collected).
+//!
+//! A handler gets the Turtl object and the message's arguments:
+//!
+//!     ["<message id>", "<command>", arg1, arg2, ...]
+//!
+//! so `args.get(2)` is `arg1`, and so on.
+
+use ::std::collections::HashMap;
+use ::jedi::{self, Value, DeserializeOwned};
+use ::error::TResult;
+use ::turtl::Turtl;
+
+/// Runs a command and returns its response
+pub type Handler = fn(&Turtl, &Args) -> TResult<Value>;
+
+/// Typed access to a command's arguments
+pub struct Args<'a> {
+    data: &'a Value,
+}
+
+impl<'a> Args<'a> {
+    pub fn new(data: &'a Value) -> Args<'a> {
+        Args { data: data }
+    }
+
+    /// Grab the argument at the given index
+    pub fn get<T: DeserializeOwned>(&self, idx: usize) -> TResult<T> {
+        Ok(jedi::get(&[idx.to_string().as_str()], self.data)?)
+    }
+
+    /// Grab the argument at the given index, or None if it's missing (or isn't
+    /// the type we want)
+    pub fn get_opt<T: DeserializeOwned>(&self, idx: usize) -> Option<T> {
+        jedi::get_opt(&[idx.to_string().as_str()], self.data)
+    }
+
+    /// The whole message, command and all
+    pub fn raw(&self) -> &'a Value {
+        self.data
+    }
+
+    /// Grab a value nested inside the arguments, ie `&["2", "dry_run"]`
+    pub fn get_in<T: DeserializeOwned>(&self, path: &[&str]) -> TResult<T> {
+        Ok(jedi::get(path, self.data)?)
+    }
+
+    /// Like `get_in()`, but None if the value is missing
+    pub fn get_in_opt<T: DeserializeOwned>(&self, path: &[&str]) -> Option<T> {
+        jedi::get_opt(path, self.data)
+    }
+}
+
+/// Maps command names to their handlers
+pub struct Registry {
+    commands: HashMap<&'static str, Handler>,
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry { commands: HashMap::new() }
+    }
+
+    /// Register a command. Two parts of the app claiming the same command is a
+    /// bug, so we complain loudly.
+    pub fn add(&mut self, name: &'static str, handler: Handler) {
+        if self.commands.insert(name, handler).is_some() {
+            panic!("dispatch::registry -- command {} registered twice", name);
+        }
+    }
+
+    /// Find the handler for a command
+    pub fn get(&self, name: &str) -> Option<Handler> {
+        self.commands.get(name).map(|x| *x)
+    }
+
+    /// List our commands, sorted
+    pub fn list(&self) -> Vec<&'static str> {
+        let mut names = self.commands.keys().map(|x| *x).collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ping(_turtl: &Turtl, args: &Args) -> TResult<Value> {
+        let name: String = args.get_opt(2).unwrap_or(String::from("pong"));
+        Ok(Value::String(name))
+    }
+
+    #[test]
+    fn registers_commands() {
+        let mut reg = Registry::new();
+        reg.add("ping", ping);
+        reg.add("app:ping", |_turtl, args| Ok(Value::Bool(args.get_in_opt(&["2", "yes"]).unwrap_or(false))));
+        assert_eq!(reg.list(), vec!["app:ping", "ping"]);
+        assert!(reg.get("ping").is_some());
+        assert!(reg.get("pong").is_none());
+
+        let data: Value = jedi::parse(&String::from(r#"["1","ping","hello",{"dry_run":true}]"#)).unwrap();
+        let args = Args::new(&data);
+        assert_eq!(args.get::<String>(2).unwrap(), "hello");
+        assert_eq!(args.get_in::<bool>(&["3", "dry_run"]).unwrap(), true);
+        assert!(args.get::<String>(5).is_err());
+        assert_eq!(args.get_opt::<u64>(2), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn rejects_duplicates() {
+        let mut reg = Registry::new();
+        reg.add("ping", ping);
+        reg.add("ping", ping);
+    }
+}
@@ -0,0 +1,109 @@
+//! Streaming responses, for commands whose answers can get big
+//! (`profile:load`, `profile:get-notes`). A UI that passes `"stream": true` in
+//! the command's options gets the bulk of the answer as a series of events
+//!
+//!     {"e": "stream:<message id>:chunk", "d": {"seq": 0, "key": "notes", "items": [...]}}
+//!
+//! followed by the normal response, which holds whatever wasn't streamed along
+//! with a `stream` summary (`{"chunks": 3, "items": 250}`). All the chunks go
+//! out before the response does, so by the time the response shows up the UI
+//! has everything.
+//!
+//! Each chunk is serialized on its own, so the core never builds the full
+//! answer in memory. Commands that aren't running as a request of their own
+//! (inside a job or a batch) answer all at once, as if streaming wasn't asked
+//! for.
+
+use ::jedi::{self, Value, Serialize};
+use ::config;
+use ::error::TResult;
+use ::messaging::Messenger;
+use ::dispatch::cancel;
+
+/// Sends a response out in pieces
+pub struct Stream {
+    mid: String,
+    chunk_size: usize,
+    chunks: usize,
+    items: usize,
+}
+
+/// The name of the event a request's chunks come in on
+pub fn event_name(mid: &str) -> String {
+    format!("stream:{}:chunk", mid)
+}
+
+impl Stream {
+    /// Open a stream for the request running on this thread, if the UI asked
+    /// for one (and there is such a request)
+    pub fn open(wanted: bool) -> Option<Stream> {
+        if !wanted { return None; }
+        let mid = cancel::current()?;
+        let chunk_size: usize = config::get(&["messaging", "stream", "chunk_size"]).unwrap_or(100);
+        Some(Stream {
+            mid: mid,
+            chunk_size: if chunk_size == 0 { 100 } else { chunk_size },
+            chunks: 0,
+            items: 0,
+        })
+    }
+
+    /// Send a list of items out, `chunk_size` at a time. `key` tells the UI
+    /// which part of the response they belong to.
+    pub fn send<T: Serialize>(&mut self, key: &str, items: &[T]) -> TResult<()> {
+        for chunk in items.chunks(self.chunk_size) {
+            cancel::check()?;
+            self.send_vals(key, jedi::to_val(&chunk)?, chunk.len())?;
+        }
+        Ok(())
+    }
+
+    /// Send one chunk of already-serialized items out
+    pub fn send_vals(&mut self, key: &str, items: Value, count: usize) -> TResult<()> {
+        let chunk = json!({
+            "seq": self.chunks,
+            "key": key,
+            "items": items,
+        });
+        Messenger::event(event_name(&self.mid).as_str(), chunk)?;
+        self.chunks += 1;
+        self.items += count;
+        Ok(())
+    }
+
+    /// How many items go in each chunk
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    /// Wrap up, adding our summary to the final response (which should be an
+    /// object)
+    pub fn finish(self, mut response: Value) -> Value {
+        let summary = json!({"chunks": self.chunks, "items": self.items});
+        match response {
+            Value::Object(ref mut obj) => { obj.insert(String::from("stream"), summary); }
+            _ => return json!({"stream": summary, "data": response}),
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_streams_requests() {
+        assert!(Stream::open(true).is_none());
+        let mid = String::from("stream-1");
+        cancel::begin(&mid);
+        assert!(Stream::open(false).is_none());
+        let stream = Stream::open(true).unwrap();
+        assert_eq!(stream.chunk_size(), 100);
+        let res = stream.finish(json!({"user": null}));
+        assert_eq!(res, json!({"user": null, "stream": {"chunks": 0, "items": 0}}));
+        cancel::end();
+        assert!(Stream::open(true).is_none());
+        assert_eq!(event_name("12"), "stream:12:chunk");
+    }
+}
@@ -0,0 +1,102 @@
+//! Keeps an eye on commands that have a timeout (see `timeouts` in the config).
+//! Commands run on whatever thread picked them up, and one watchdog thread
+//! calls a command's `expire` callback if it's still running once its time is
+//! up, so we don't need a thread per command just to time it.
+
+use ::std::thread;
+use ::std::time::{Duration, Instant};
+use ::std::sync::{Mutex, Condvar};
+
+/// Called when a command runs out of time
+pub type Expire = Box<Fn() + Send>;
+
+struct Watch {
+    id: u64,
+    deadline: Instant,
+    expire: Expire,
+}
+
+struct State {
+    next_id: u64,
+    watches: Vec<Watch>,
+    started: bool,
+}
+
+lazy_static! {
+    static ref STATE: Mutex<State> = Mutex::new(State { next_id: 0, watches: Vec::new(), started: false });
+
+    /// Pokes the watchdog when a new deadline comes in
+    static ref WAKE: Condvar = Condvar::new();
+}
+
+/// Call `expire` if we haven't been `unwatch()`ed within `timeout`. Returns
+/// the id to `unwatch()` with.
+pub fn watch(timeout: Duration, expire: Expire) -> u64 {
+    let mut state = lock!(*STATE);
+    state.next_id += 1;
+    let id = state.next_id;
+    state.watches.push(Watch { id: id, deadline: Instant::now() + timeout, expire: expire });
+    if !state.started {
+        match thread::Builder::new().name(String::from("dispatch:watchdog")).spawn(run) {
+            Ok(_) => state.started = true,
+            // we'll try again with the next command
+            Err(e) => error!("dispatch::watchdog::watch() -- error spawning thread: {}", e),
+        }
+    }
+    WAKE.notify_one();
+    id
+}
+
+/// Stop watching. Returns false if it's too late (`expire` was already
+/// called, or is being called right now).
+pub fn unwatch(id: u64) -> bool {
+    let mut state = lock!(*STATE);
+    let before = state.watches.len();
+    state.watches.retain(|x| x.id != id);
+    state.watches.len() < before
+}
+
+/// The watchdog's loop. Expired watches come out of the list before we call
+/// them, so whoever `unwatch()`es first wins.
+fn run() {
+    loop {
+        let expired = {
+            let mut state = lock!(*STATE);
+            loop {
+                let now = Instant::now();
+                let (expired, watching) = state.watches.drain(..).partition::<Vec<_>, _>(|x| x.deadline <= now);
+                state.watches = watching;
+                if !expired.is_empty() { break expired; }
+                let wait = state.watches.iter().map(|x| x.deadline - now).min();
+                state = match wait {
+                    Some(wait) => do_lock!(WAKE.wait_timeout(state, wait)).0,
+                    None => do_lock!(WAKE.wait(state)),
+                };
+            }
+        };
+        for watch in expired {
+            (watch.expire)();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::std::sync::Arc;
+    use ::std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn expires_what_it_watches() {
+        let expired = Arc::new(AtomicUsize::new(0));
+        let expired2 = expired.clone();
+        let slow = watch(Duration::from_millis(100), Box::new(move || { expired2.fetch_add(1, Ordering::SeqCst); }));
+        let expired3 = expired.clone();
+        let fast = watch(Duration::from_millis(100), Box::new(move || { expired3.fetch_add(10, Ordering::SeqCst); }));
+        assert!(unwatch(fast));
+        thread::sleep(Duration::from_millis(500));
+        assert_eq!(expired.load(Ordering::SeqCst), 1);
+        // too late
+        assert!(!unwatch(slow));
+    }
+}
@@ -99,6 +99,18 @@ quick_error! {
             description("io error")
             display("{}", quick_error_obj!("io_error", err))
         }
+        DiskFull(msg: String) {
+            description(msg)
+            display("{}", quick_error_obj!("disk_full", msg))
+        }
+        ProfileLocked(msg: String) {
+            description(msg)
+            display("{}", quick_error_obj!("profile_locked", msg))
+        }
+        StorageCorrupt(msg: String) {
+            description(msg)
+            display("{}", quick_error_obj!("storage_corrupt", msg))
+        }
         Api(status: StatusCode, msg: Value) {
             description("API error")
             display("{}", json!({"type": "api", "subtype": status.canonical_reason().unwrap_or("unknown"), "message": msg}))
@@ -111,6 +123,30 @@ quick_error! {
             description("Parse error")
             display("{}", quick_error_obj!("parse_error", msg))
         }
+        Timeout(stage: String, msg: String) {
+            description("timeout")
+            display("{}", json!({"type": "timeout", "subtype": stage, "message": msg}))
+        }
+        TooLarge(what: String, limit: u64, size: u64) {
+            description("too large")
+            display("{}", json!({"type": "too_large", "subtype": what, "message": format!("{} is {} bytes (the limit is {})", what, size, limit), "limit": limit, "size": size}))
+        }
+        Cancelled(msg: String) {
+            description("cancelled")
+            display("{}", quick_error_obj!("cancelled", msg))
+        }
+        Busy(msg: String) {
+            description(msg)
+            display("{}", quick_error_obj!("busy", msg))
+        }
+        Quarantined(msg: String) {
+            description(msg)
+            display("{}", quick_error_obj!("quarantined", msg))
+        }
+        Incompatible(msg: String) {
+            description(msg)
+            display("{}", quick_error_obj!("incompatible", msg))
+        }
         TryAgain {
             description("try again")
             display("{}", json!({"type": "try_again"}))
@@ -139,6 +175,146 @@ impl TError {
             _ => self,
         }
     }
+
+    /// The stable code for this error (see `ErrorCode`)
+    pub fn code(&self) -> ErrorCode {
+        match *self {
+            TError::Wrapped(_, _, _, ref err) => err.code(),
+            TError::BadValue(..) | TError::MissingField(..) | TError::Validation(..) |
+                TError::JSON(..) | TError::ParseError(..) => ErrorCode::BadRequest,
+            TError::MissingCommand(..) => ErrorCode::UnknownCommand,
+            TError::NotFound(..) | TError::MissingData(..) => ErrorCode::NotFound,
+            TError::PermissionDenied(..) => ErrorCode::PermissionDenied,
+            TError::ConnectionRequired => ErrorCode::Offline,
+            TError::Crypto(..) => ErrorCode::Crypto,
+            TError::DiskFull(..) => ErrorCode::DiskFull,
+            TError::ProfileLocked(..) => ErrorCode::Locked,
+            TError::StorageCorrupt(..) => ErrorCode::StorageCorrupt,
+            TError::Api(ref status, _) | TError::Http(ref status, _) => ErrorCode::from_status(status.as_u16()),
+            TError::Timeout(..) => ErrorCode::Timeout,
+            TError::TooLarge(..) => ErrorCode::TooLarge,
+            TError::Cancelled(..) => ErrorCode::Cancelled,
+            TError::Busy(..) => ErrorCode::Busy,
+            TError::Quarantined(..) => ErrorCode::Quarantined,
+            TError::Incompatible(..) => ErrorCode::Incompatible,
+            TError::TryAgain => ErrorCode::TryAgain,
+            TError::NotImplemented => ErrorCode::NotImplemented,
+            TError::Boxed(..) | TError::Msg(..) | TError::Panic(..) | TError::Dumpy(..) |
+                TError::Clippo(..) | TError::Migrate(..) | TError::Io(..) => ErrorCode::Internal,
+        }
+    }
+}
+
+/// Stable codes for errors, so the UI can decide what to do about one without
+/// picking apart its message. Every code has a number and a name, and neither
+/// changes once it's out there: new codes get new numbers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorCode {
+    Internal,
+    BadRequest,
+    UnknownCommand,
+    NotFound,
+    PermissionDenied,
+    AuthFailed,
+    Conflict,
+    Offline,
+    RateLimited,
+    Timeout,
+    Cancelled,
+    Busy,
+    TooLarge,
+    DiskFull,
+    Locked,
+    StorageCorrupt,
+    Crypto,
+    Incompatible,
+    Quarantined,
+    TryAgain,
+    NotImplemented,
+    ServerError,
+}
+
+/// How an `ErrorCode` goes out to the UI
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct CodeValue {
+    pub num: u32,
+    pub name: &'static str,
+}
+
+impl ErrorCode {
+    pub fn num(&self) -> u32 {
+        match *self {
+            ErrorCode::Internal => 1,
+            ErrorCode::BadRequest => 2,
+            ErrorCode::UnknownCommand => 3,
+            ErrorCode::NotFound => 4,
+            ErrorCode::PermissionDenied => 5,
+            ErrorCode::AuthFailed => 6,
+            ErrorCode::Conflict => 7,
+            ErrorCode::Offline => 8,
+            ErrorCode::RateLimited => 9,
+            ErrorCode::Timeout => 10,
+            ErrorCode::Cancelled => 11,
+            ErrorCode::Busy => 12,
+            ErrorCode::TooLarge => 13,
+            ErrorCode::DiskFull => 14,
+            ErrorCode::Locked => 15,
+            ErrorCode::StorageCorrupt => 16,
+            ErrorCode::Crypto => 17,
+            ErrorCode::Incompatible => 18,
+            ErrorCode::Quarantined => 19,
+            ErrorCode::TryAgain => 20,
+            ErrorCode::NotImplemented => 21,
+            ErrorCode::ServerError => 22,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ErrorCode::Internal => "internal",
+            ErrorCode::BadRequest => "bad_request",
+            ErrorCode::UnknownCommand => "unknown_command",
+            ErrorCode::NotFound => "not_found",
+            ErrorCode::PermissionDenied => "permission_denied",
+            ErrorCode::AuthFailed => "auth_failed",
+            ErrorCode::Conflict => "conflict",
+            ErrorCode::Offline => "offline",
+            ErrorCode::RateLimited => "rate_limited",
+            ErrorCode::Timeout => "timeout",
+            ErrorCode::Cancelled => "cancelled",
+            ErrorCode::Busy => "busy",
+            ErrorCode::TooLarge => "too_large",
+            ErrorCode::DiskFull => "disk_full",
+            ErrorCode::Locked => "locked",
+            ErrorCode::StorageCorrupt => "storage_corrupt",
+            ErrorCode::Crypto => "crypto",
+            ErrorCode::Incompatible => "incompatible",
+            ErrorCode::Quarantined => "quarantined",
+            ErrorCode::TryAgain => "try_again",
+            ErrorCode::NotImplemented => "not_implemented",
+            ErrorCode::ServerError => "server_error",
+        }
+    }
+
+    pub fn value(&self) -> CodeValue {
+        CodeValue { num: self.num(), name: self.name() }
+    }
+
+    /// The code for an HTTP status the server (or someone else's) sent back
+    pub fn from_status(status: u16) -> ErrorCode {
+        match status {
+            401 => ErrorCode::AuthFailed,
+            403 => ErrorCode::PermissionDenied,
+            404 | 410 => ErrorCode::NotFound,
+            408 | 504 => ErrorCode::Timeout,
+            409 | 412 => ErrorCode::Conflict,
+            413 => ErrorCode::TooLarge,
+            429 => ErrorCode::RateLimited,
+            503 => ErrorCode::Busy,
+            x if x >= 500 => ErrorCode::ServerError,
+            _ => ErrorCode::BadRequest,
+        }
+    }
 }
 
 /// Define a macro that, if and when the time is right, returns a static string
@@ -284,6 +460,9 @@ from_err!(::glob::GlobError);
 from_err!(::log::SetLoggerError);
 from_err!(::reqwest::Error);
 from_err!(::url::ParseError);
+from_err!(::tungstenite::Error);
+from_err!(::rmp_serde::encode::Error);
+from_err!(::rmp_serde::decode::Error);
 
 pub type BoxFuture<T, E> = Box<dyn (::futures::Future<Item = T, Error = E>) + Send>;
 pub type TResult<T> = Result<T, TError>;
@@ -329,3 +508,33 @@ macro_rules! ftry {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_errors() {
+        let err = twrap!(TError::NotFound(String::from("nope")));
+        assert_eq!(err.code(), ErrorCode::NotFound);
+        assert_eq!(err.code().value(), CodeValue { num: 4, name: "not_found" });
+        assert_eq!(TError::MissingField(String::from("id")).code(), ErrorCode::BadRequest);
+        assert_eq!(TError::Timeout(String::from("command"), String::from("slow")).code(), ErrorCode::Timeout);
+        assert_eq!(TError::Msg(String::from("hmm")).code(), ErrorCode::Internal);
+        let api = TError::Api(StatusCode::from_u16(429).unwrap(), Value::Null);
+        assert_eq!(api.code(), ErrorCode::RateLimited);
+        let http = twrap!(TError::Http(StatusCode::from_u16(401).unwrap(), Value::Null));
+        assert_eq!(http.code(), ErrorCode::AuthFailed);
+    }
+
+    #[test]
+    fn codes_statuses() {
+        assert_eq!(ErrorCode::from_status(403), ErrorCode::PermissionDenied);
+        assert_eq!(ErrorCode::from_status(410), ErrorCode::NotFound);
+        assert_eq!(ErrorCode::from_status(412), ErrorCode::Conflict);
+        assert_eq!(ErrorCode::from_status(429), ErrorCode::RateLimited);
+        assert_eq!(ErrorCode::from_status(503), ErrorCode::Busy);
+        assert_eq!(ErrorCode::from_status(504), ErrorCode::Timeout);
+        assert_eq!(ErrorCode::from_status(502), ErrorCode::ServerError);
+        assert_eq!(ErrorCode::from_status(400), ErrorCode::BadRequest);
+    }
+}
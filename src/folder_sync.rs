@@ -0,0 +1,607 @@
+//! EXPERIMENTAL: two-way sync between one board and a folder on disk. Notes in
+//! the board are written to the folder as markdown, and the folder is polled
+//! for changes: edited files update their notes, new files become new notes,
+//! and deleted files delete their notes.
+//!
+//! Deletes are the one thing we can't take back, so we're careful with them. A
+//! file has to be missing for `folder_sync.delete_after_misses` scans in a row
+//! before its note goes, and if a big chunk of the folder disappears at once
+//! (`folder_sync.max_vanish_fraction`, think an unmounted drive or a sync tool
+//! swapping the folder out) we don't delete anything and tell the UI instead.
+//!
+//! If a file and its note both change between scans, we don't try to merge.
+//! The note wins, the file's version is kept next to it as a `.conflict-*.md`
+//! copy, and the UI gets a `folder-sync:conflict` event.
+//!
+//! Like the markdown export, this writes plaintext notes to disk, so it's only
+//! ever on when the user asks for it.
+
+use ::std::collections::HashMap;
+use ::std::fs;
+use ::std::path::{Path, PathBuf};
+use ::std::sync::Arc;
+use ::std::sync::atomic::{AtomicBool, Ordering};
+use ::std::sync::RwLock;
+use ::std::thread;
+use ::std::time::Duration;
+use ::time;
+use ::jedi;
+use ::error::{TResult, TError};
+use ::config;
+use ::crypto;
+use ::messaging;
+use ::markdown;
+use ::import::{self, ImportNote};
+use ::turtl::Turtl;
+use ::models::model::Model;
+use ::models::board::Board;
+use ::models::note::Note;
+use ::models::storable::Storable;
+use ::models::sync_record::SyncAction;
+use ::sync::sync_model;
+use ::lib_permissions::Permission;
+use ::dispatch::registry::Registry;
+
+/// The kv key we keep our folder sync state under
+const STATE_KEY: &'static str = "folder_sync";
+
+lazy_static! {
+    /// Tells the currently-running watcher thread (if any) to keep going
+    static ref WATCHER: RwLock<Option<Arc<AtomicBool>>> = RwLock::new(None);
+}
+
+/// What we know about a file we're tracking
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TrackedFile {
+    pub filename: String,
+    /// The note's `mod` when we last synced it with the file
+    #[serde(rename = "mod")]
+    pub mod_: i64,
+    /// The hash of the file's contents when we last synced it with the note
+    pub hash: String,
+    /// How many scans in a row the file has been missing for
+    #[serde(default)]
+    pub missed: u32,
+}
+
+/// Which board goes to which folder, and what's in it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FolderSync {
+    pub board_id: String,
+    pub directory: String,
+    /// note id -> file
+    #[serde(default)]
+    pub files: HashMap<String, TrackedFile>,
+}
+
+/// What happened during a scan of the folder
+#[derive(Serialize, Debug, Default)]
+pub struct ScanResult {
+    pub created: u64,
+    pub updated: u64,
+    pub deleted: u64,
+    pub conflicts: u64,
+    /// Files that are gone, but whose notes we haven't deleted (yet)
+    pub missing: u64,
+}
+
+fn hash(contents: &str) -> TResult<String> {
+    Ok(crypto::to_hex(&crypto::sha256(contents.as_bytes())?)?)
+}
+
+fn now() -> i64 {
+    time::get_time().sec as i64
+}
+
+/// Grab our folder sync settings, if folder sync is on
+pub fn get(turtl: &Turtl) -> TResult<Option<FolderSync>> {
+    let state = with_db!{ db, turtl.db, db.kv_get(STATE_KEY)? };
+    match state {
+        Some(x) => Ok(Some(jedi::parse(&x)?)),
+        None => Ok(None),
+    }
+}
+
+fn save(turtl: &Turtl, state: &FolderSync) -> TResult<()> {
+    let serialized = jedi::stringify(state)?;
+    with_db!{ db, turtl.db, db.kv_set(STATE_KEY, &serialized)? };
+    Ok(())
+}
+
+/// Map a board to a folder. Any existing mapping is replaced.
+pub fn enable(turtl: &Turtl, board_id: &String, directory: &String) -> TResult<FolderSync> {
+    let space_id = import::board_space(turtl, board_id)?;
+    Board::permission_check(turtl, &space_id, Some(board_id), &Permission::EditNote)?;
+    fs::create_dir_all(directory)?;
+    if !Path::new(directory).is_dir() {
+        return TErr!(TError::BadValue(format!("{} isn't a directory", directory)));
+    }
+    let state = FolderSync {
+        board_id: board_id.clone(),
+        directory: directory.clone(),
+        files: HashMap::new(),
+    };
+    save(turtl, &state)?;
+    write_out(turtl)?;
+    start_watcher();
+    match get(turtl)? {
+        Some(x) => Ok(x),
+        None => TErr!(TError::MissingData(String::from("folder sync state went missing"))),
+    }
+}
+
+/// Stop syncing. Files already in the folder are left alone.
+pub fn disable(turtl: &Turtl) -> TResult<()> {
+    stop_watcher();
+    with_db!{ db, turtl.db, db.kv_delete(STATE_KEY)? };
+    Ok(())
+}
+
+/// Grab the `mod` of every note in a board without decrypting anything
+fn board_mods(turtl: &Turtl, board_id: &String) -> TResult<HashMap<String, i64>> {
+    let notes: Vec<Note> = with_db!{ db, turtl.db, db.find(Note::tablename(), "board_id", &vec![board_id.clone()])? };
+    let mut mods = HashMap::with_capacity(notes.len());
+    for note in notes {
+        mods.insert(note.id_or_else()?, note.mod_.unwrap_or(0));
+    }
+    Ok(mods)
+}
+
+/// Write a note into the folder and track it
+fn write_note(state: &mut FolderSync, note: &Note) -> TResult<()> {
+    let note_id = note.id_or_else()?;
+    // keep whatever filename we already use so we don't pull files out from
+    // under people's editors
+    let filename = match state.files.get(&note_id) {
+        Some(x) => x.filename.clone(),
+        None => markdown::note_filename(note)?,
+    };
+    let contents = markdown::note_to_markdown(note)?;
+    markdown::write_atomic(&Path::new(&state.directory).join(&filename), &contents)?;
+    state.files.insert(note_id, TrackedFile {
+        filename: filename,
+        mod_: note.mod_.unwrap_or(0),
+        hash: hash(&contents)?,
+        missed: 0,
+    });
+    Ok(())
+}
+
+/// Write any notes that changed since we last synced them out to the folder,
+/// and remove the files of notes that left the board.
+pub fn write_out(turtl: &Turtl) -> TResult<u64> {
+    let mut state = match get(turtl)? {
+        Some(x) => x,
+        None => return Ok(0),
+    };
+    let mods = board_mods(turtl, &state.board_id)?;
+    let gone = state.files.keys()
+        .filter(|id| !mods.contains_key(*id))
+        .map(|x| x.clone())
+        .collect::<Vec<_>>();
+    for note_id in gone {
+        if let Some(tracked) = state.files.remove(&note_id) {
+            match fs::remove_file(Path::new(&state.directory).join(&tracked.filename)) {
+                Ok(_) => {}
+                Err(e) => warn!("folder_sync::write_out() -- error removing {}: {}", tracked.filename, e),
+            }
+        }
+    }
+    let changed = mods.iter()
+        .filter(|&(id, modified)| state.files.get(id).map(|t| t.mod_ != *modified).unwrap_or(true))
+        .map(|(id, _)| id.clone())
+        .collect::<Vec<_>>();
+    let mut written = 0;
+    for note in turtl.load_notes(&changed)? {
+        write_note(&mut state, &note)?;
+        written += 1;
+    }
+    save(turtl, &state)?;
+    Ok(written)
+}
+
+/// Apply an edited file to its note
+fn update_note(turtl: &Turtl, state: &mut FolderSync, note_id: &String, filename: &String, contents: &String) -> TResult<()> {
+    let parsed = markdown::parse_markdown(contents);
+    let mut note = match turtl.load_notes(&vec![note_id.clone()])?.pop() {
+        Some(x) => x,
+        None => return TErr!(TError::NotFound(format!("note {} wasn't found", note_id))),
+    };
+    Board::permission_check(turtl, &note.space_id, note.board_id.as_ref(), &Permission::EditNote)?;
+    note.title = parsed.title;
+    note.url = parsed.url;
+    note.tags = Some(parsed.tags);
+    note.text = Some(parsed.text);
+    note.touch();
+    sync_model::save_model(SyncAction::Edit, turtl, &mut note, false)?;
+    state.files.insert(note_id.clone(), TrackedFile {
+        filename: filename.clone(),
+        mod_: note.mod_.unwrap_or(0),
+        hash: hash(contents)?,
+        missed: 0,
+    });
+    Ok(())
+}
+
+/// Turn a new file into a note, then write the note back out so the file picks
+/// up its id
+fn create_note(turtl: &Turtl, state: &mut FolderSync, filename: &String, contents: &String) -> TResult<()> {
+    let parsed = markdown::parse_markdown(contents);
+    let space_id = import::board_space(turtl, &state.board_id)?;
+    let mut note = ImportNote::default();
+    note.title = parsed.title.or_else(|| {
+        Path::new(filename).file_stem().map(|x| String::from(x.to_string_lossy()))
+    });
+    note.text = Some(parsed.text);
+    note.tags = parsed.tags;
+    note.url = parsed.url;
+    let note_id = import::save_note(turtl, &space_id, &state.board_id, note)?;
+    let note = match turtl.load_notes(&vec![note_id.clone()])?.pop() {
+        Some(x) => x,
+        None => return TErr!(TError::NotFound(format!("note {} wasn't found", note_id))),
+    };
+    state.files.insert(note_id, TrackedFile {
+        filename: filename.clone(),
+        mod_: 0,
+        hash: String::new(),
+        missed: 0,
+    });
+    write_note(state, &note)
+}
+
+/// The note and the file both changed. Keep the note, stash the file.
+fn conflict(turtl: &Turtl, state: &mut FolderSync, note_id: &String, filename: &String, contents: &String) -> TResult<()> {
+    let path = Path::new(&state.directory).join(filename);
+    let stem = path.file_stem().map(|x| String::from(x.to_string_lossy())).unwrap_or(String::from("note"));
+    let conflict_name = format!("{}.conflict-{}.md", stem, now());
+    markdown::write_atomic(&Path::new(&state.directory).join(&conflict_name), contents)?;
+    match turtl.load_notes(&vec![note_id.clone()])?.pop() {
+        Some(note) => write_note(state, &note)?,
+        None => {}
+    }
+    messaging::ui_event("folder-sync:conflict", &json!({
+        "note_id": note_id,
+        "file": filename,
+        "conflict_file": conflict_name,
+    }))?;
+    Ok(())
+}
+
+/// Is this a file we should be looking at?
+fn is_note_file(path: &PathBuf) -> bool {
+    let name = match path.file_name() {
+        Some(x) => x.to_string_lossy(),
+        None => return false,
+    };
+    name.ends_with(".md") && !name.contains(".conflict-") && !name.starts_with(".")
+}
+
+/// Look for changes in the folder and apply them to our notes
+pub fn scan(turtl: &Turtl) -> TResult<ScanResult> {
+    let mut result = ScanResult::default();
+    let mut state = match get(turtl)? {
+        Some(x) => x,
+        None => return Ok(result),
+    };
+    let mods = board_mods(turtl, &state.board_id)?;
+    let mut by_filename: HashMap<String, String> = state.files.iter()
+        .map(|(id, t)| (t.filename.clone(), id.clone()))
+        .collect();
+
+    let mut present: Vec<String> = Vec::new();
+    for entry in fs::read_dir(&state.directory)? {
+        let path = entry?.path();
+        if !path.is_file() || !is_note_file(&path) { continue; }
+        let filename = match path.file_name() {
+            Some(x) => String::from(x.to_string_lossy()),
+            None => continue,
+        };
+        present.push(filename.clone());
+        let contents = match fs::read_to_string(&path) {
+            Ok(x) => x,
+            Err(e) => {
+                warn!("folder_sync::scan() -- error reading {}: {}", filename, e);
+                continue;
+            }
+        };
+        let file_hash = hash(&contents)?;
+        let note_id = match by_filename.get(&filename) {
+            Some(x) => Some(x.clone()),
+            // a file we don't know by name might be one of ours that got
+            // renamed, so check its id
+            None => markdown::parse_markdown(&contents).id
+                .and_then(|id| state.files.get(&id).map(|t| (id.clone(), t.filename.clone())))
+                .and_then(|(id, old_filename)| {
+                    if present.contains(&old_filename) || Path::new(&state.directory).join(&old_filename).exists() {
+                        None
+                    } else {
+                        Some(id)
+                    }
+                }),
+        };
+        let res = match note_id {
+            Some(note_id) => {
+                let (tracked_hash, tracked_mod) = match state.files.get(&note_id) {
+                    Some(t) => (t.hash.clone(), t.mod_),
+                    None => (String::new(), 0),
+                };
+                by_filename.insert(filename.clone(), note_id.clone());
+                if tracked_hash == file_hash {
+                    // unchanged, but it may have been renamed
+                    if let Some(t) = state.files.get_mut(&note_id) { t.filename = filename.clone(); }
+                    continue;
+                }
+                match mods.get(&note_id) {
+                    // the note is gone (deleted elsewhere, moved boards), so the
+                    // edited file becomes a new note
+                    None => {
+                        state.files.remove(&note_id);
+                        result.created += 1;
+                        create_note(turtl, &mut state, &filename, &contents)
+                    }
+                    Some(modified) if *modified > tracked_mod => {
+                        result.conflicts += 1;
+                        if let Some(t) = state.files.get_mut(&note_id) { t.filename = filename.clone(); }
+                        conflict(turtl, &mut state, &note_id, &filename, &contents)
+                    }
+                    Some(_) => {
+                        result.updated += 1;
+                        update_note(turtl, &mut state, &note_id, &filename, &contents)
+                    }
+                }
+            }
+            None => {
+                result.created += 1;
+                create_note(turtl, &mut state, &filename, &contents)
+            }
+        };
+        match res {
+            Ok(_) => {}
+            Err(e) => warn!("folder_sync::scan() -- error syncing {}: {}", filename, e),
+        }
+    }
+
+    // files that went away take their notes with them, once they've been gone
+    // long enough that we're sure they aren't coming back
+    for tracked in state.files.values_mut() {
+        if present.contains(&tracked.filename) { tracked.missed = 0; }
+    }
+    let missing = state.files.iter()
+        .filter(|&(_, t)| !present.contains(&t.filename))
+        .map(|(id, _)| id.clone())
+        .collect::<Vec<_>>();
+    let delete_after: u32 = config::get(&["folder_sync", "delete_after_misses"]).unwrap_or(3);
+    let max_vanish: f64 = config::get(&["folder_sync", "max_vanish_fraction"]).unwrap_or(0.5);
+    let tracked = state.files.len();
+    if missing.len() > 1 && (missing.len() as f64) > (tracked as f64) * max_vanish {
+        warn!("folder_sync::scan() -- {} of {} files are missing from {}, not deleting anything", missing.len(), tracked, state.directory);
+        result.missing = missing.len() as u64;
+        messaging::ui_event("folder-sync:missing", &json!({
+            "directory": state.directory,
+            "missing": missing.len(),
+            "tracked": tracked,
+        }))?;
+    } else {
+        for note_id in missing {
+            // the note is already gone, nothing to delete
+            if !mods.contains_key(&note_id) {
+                state.files.remove(&note_id);
+                continue;
+            }
+            let missed = match state.files.get_mut(&note_id) {
+                Some(t) => { t.missed += 1; t.missed }
+                None => continue,
+            };
+            if missed < delete_after {
+                result.missing += 1;
+                continue;
+            }
+            let res = import::board_space(turtl, &state.board_id)
+                .and_then(|space_id| Board::permission_check(turtl, &space_id, Some(&state.board_id), &Permission::DeleteNote))
+                .and_then(|_| sync_model::delete_model::<Note>(turtl, &note_id, false));
+            match res {
+                Ok(_) => {
+                    state.files.remove(&note_id);
+                    result.deleted += 1;
+                }
+                Err(e) => warn!("folder_sync::scan() -- error deleting note {}: {}", note_id, e),
+            }
+        }
+    }
+    save(turtl, &state)?;
+    if result.created + result.updated + result.deleted + result.conflicts > 0 {
+        messaging::ui_event("folder-sync:scanned", &result)?;
+    }
+    Ok(result)
+}
+
+/// Scan the folder, then write out anything that changed on our end. Errors
+/// are logged, not returned, so a bad folder never breaks syncing.
+pub fn run(turtl: &Turtl) {
+    match scan(turtl).and_then(|_| write_out(turtl)) {
+        Ok(_) => {}
+        Err(e) => error!("folder_sync::run() -- {}", e),
+    }
+}
+
+/// Start polling the folder for changes. The thread doesn't have access to
+/// Turtl, so it just pokes the dispatch thread with an app event every so
+/// often, which does the actual scan.
+pub fn start_watcher() {
+    stop_watcher();
+    let running = Arc::new(AtomicBool::new(true));
+    {
+        let mut guard = lockw!(*WATCHER);
+        *guard = Some(running.clone());
+    }
+    let interval: u64 = config::get(&["folder_sync", "scan_interval"]).unwrap_or(5);
+    let spawned = thread::Builder::new().name(String::from("folder-sync")).spawn(move || {
+        loop {
+            thread::sleep(Duration::new(interval, 0));
+            if !running.load(Ordering::SeqCst) { break; }
+            match messaging::app_event("folder-sync:scan", &()) {
+                Ok(_) => {}
+                Err(e) => error!("folder_sync::watcher -- error sending scan event: {}", e),
+            }
+        }
+    });
+    match spawned {
+        Ok(_) => {}
+        Err(e) => error!("folder_sync::start_watcher() -- error spawning watcher: {}", e),
+    }
+}
+
+/// Stop polling the folder
+pub fn stop_watcher() {
+    let mut guard = lockw!(*WATCHER);
+    if let Some(running) = guard.take() {
+        running.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Start watching if folder sync is on. Called once the profile is loaded.
+pub fn start_if_enabled(turtl: &Turtl) {
+    match get(turtl) {
+        Ok(Some(_)) => start_watcher(),
+        Ok(None) => {}
+        Err(e) => error!("folder_sync::start_if_enabled() -- {}", e),
+    }
+}
+
+/// Registers our folder sync commands
+pub fn register(reg: &mut Registry) {
+    reg.add("profile:folder-sync:enable", |turtl, args| {
+        let board_id: String = args.get(2)?;
+        let directory: String = args.get(3)?;
+        let state = enable(turtl, &board_id, &directory)?;
+        Ok(json!({"board_id": state.board_id, "directory": state.directory}))
+    });
+    reg.add("profile:folder-sync:disable", |turtl, _args| {
+        disable(turtl)?;
+        Ok(json!({}))
+    });
+    reg.add("profile:folder-sync:get", |turtl, _args| {
+        let state = get(turtl)?;
+        Ok(json!(state.map(|x| json!({"board_id": x.board_id, "directory": x.directory}))))
+    });
+    reg.add("profile:folder-sync:scan", |turtl, _args| {
+        let result = scan(turtl)?;
+        write_out(turtl)?;
+        Ok(jedi::to_val(&result)?)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::std::env;
+    use ::turtl::tests::{with_test, load_test_profile};
+
+    const BOARD_ID: &'static str = "015bac2244ea4944baee41b88207731eaeb7e2cc5c955fb8a05b028c1409aaf55024f5d26fa30034";
+    const NOTE_ID: &'static str = "015ce7ea7f742af6297cf0cc29180f9cc45f4c80e5b30238581f845367f9c404ef3fb8fb0a5a00aa";
+
+    /// Sync our test board to a fresh temp folder
+    fn synced_folder(turtl: &Turtl) -> PathBuf {
+        let dir = env::temp_dir().join(format!("turtl-folder-sync-{}", crypto::random_hash().unwrap()));
+        enable(turtl, &String::from(BOARD_ID), &String::from(dir.to_string_lossy())).unwrap();
+        stop_watcher();
+        dir
+    }
+
+    fn tracked_file(turtl: &Turtl, note_id: &str) -> PathBuf {
+        let state = get(turtl).unwrap().unwrap();
+        Path::new(&state.directory).join(&state.files.get(note_id).unwrap().filename)
+    }
+
+    fn note_text(turtl: &Turtl, note_id: &str) -> String {
+        turtl.load_notes(&vec![String::from(note_id)]).unwrap().pop().unwrap().text.unwrap_or(String::new())
+    }
+
+    #[test]
+    fn syncs_changes_both_ways() {
+        let turtl = with_test(true);
+        load_test_profile(&turtl);
+        let dir = synced_folder(&turtl);
+        let files = || fs::read_dir(&dir).unwrap().filter(|x| is_note_file(&x.as_ref().unwrap().path())).count();
+        assert_eq!(files(), 2);
+
+        // new file -> new note
+        fs::write(dir.join("groceries.md"), "milk\neggs\n").unwrap();
+        let res = scan(&turtl).unwrap();
+        assert_eq!((res.created, res.updated, res.deleted, res.conflicts), (1, 0, 0, 0));
+        assert_eq!(board_mods(&turtl, &String::from(BOARD_ID)).unwrap().len(), 3);
+
+        // edited file -> edited note
+        let path = tracked_file(&turtl, NOTE_ID);
+        fs::write(&path, format!("---\nid: {}\n---\nedited on disk\n", NOTE_ID)).unwrap();
+        let res = scan(&turtl).unwrap();
+        assert_eq!((res.created, res.updated, res.deleted, res.conflicts), (0, 1, 0, 0));
+        assert_eq!(note_text(&turtl, NOTE_ID).trim(), "edited on disk");
+        assert_eq!(scan(&turtl).unwrap().updated, 0);
+
+        // both changed -> the note wins, the file gets stashed
+        let mut note = turtl.load_notes(&vec![String::from(NOTE_ID)]).unwrap().pop().unwrap();
+        note.text = Some(String::from("edited in the app"));
+        note.mod_ = Some(now() + 60);
+        sync_model::save_model(SyncAction::Edit, &turtl, &mut note, false).unwrap();
+        fs::write(&path, format!("---\nid: {}\n---\nedited on disk again\n", NOTE_ID)).unwrap();
+        let res = scan(&turtl).unwrap();
+        assert_eq!((res.created, res.updated, res.deleted, res.conflicts), (0, 0, 0, 1));
+        assert_eq!(note_text(&turtl, NOTE_ID), "edited in the app");
+        let conflicts = fs::read_dir(&dir).unwrap()
+            .map(|x| x.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|x| x.contains(".conflict-"))
+            .collect::<Vec<_>>();
+        assert_eq!(conflicts.len(), 1);
+        assert!(fs::read_to_string(dir.join(&conflicts[0])).unwrap().contains("edited on disk again"));
+        assert!(fs::read_to_string(&path).unwrap().contains("edited in the app"));
+
+        // deleted file -> deleted note, but only after it stays gone
+        fs::remove_file(&path).unwrap();
+        for _ in 1..3 {
+            let res = scan(&turtl).unwrap();
+            assert_eq!((res.deleted, res.missing), (0, 1));
+        }
+        let res = scan(&turtl).unwrap();
+        assert_eq!((res.deleted, res.missing), (1, 0));
+        assert!(turtl.load_notes(&vec![String::from(NOTE_ID)]).unwrap().is_empty());
+        assert_eq!(files(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn files_that_come_back_are_forgiven() {
+        let turtl = with_test(true);
+        load_test_profile(&turtl);
+        let dir = synced_folder(&turtl);
+        let path = tracked_file(&turtl, NOTE_ID);
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(scan(&turtl).unwrap().missing, 1);
+        assert_eq!(scan(&turtl).unwrap().missing, 1);
+        fs::write(&path, &contents).unwrap();
+        assert_eq!(scan(&turtl).unwrap().missing, 0);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(scan(&turtl).unwrap().missing, 1);
+        assert_eq!(scan(&turtl).unwrap().deleted, 0);
+        assert_eq!(turtl.load_notes(&vec![String::from(NOTE_ID)]).unwrap().len(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn an_empty_folder_deletes_nothing() {
+        let turtl = with_test(true);
+        load_test_profile(&turtl);
+        let dir = synced_folder(&turtl);
+        for entry in fs::read_dir(&dir).unwrap() {
+            fs::remove_file(entry.unwrap().path()).unwrap();
+        }
+        for _ in 0..5 {
+            let res = scan(&turtl).unwrap();
+            assert_eq!((res.deleted, res.missing), (0, 2));
+        }
+        assert_eq!(board_mods(&turtl, &String::from(BOARD_ID)).unwrap().len(), 2);
+        assert_eq!(get(&turtl).unwrap().unwrap().files.values().map(|x| x.missed).max(), Some(0));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,123 @@
+//! Geohashes and distances for searching notes by where they were taken. The
+//! search index stores a geohash for each located note, and a radius search
+//! first narrows things down by geohash prefix (cheap, uses the index) and then
+//! checks the real distance on whatever's left.
+
+const BASE32: &'static [u8] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+/// The longest geohash we bother storing (cells are a few cm across)
+pub const MAX_PRECISION: usize = 12;
+
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// Encode a point as a geohash with `precision` characters
+pub fn encode(lat: f64, lon: f64, precision: usize) -> String {
+    let (mut lat_lo, mut lat_hi) = (-90.0, 90.0);
+    let (mut lon_lo, mut lon_hi) = (-180.0, 180.0);
+    let mut hash = String::with_capacity(precision);
+    let mut even = true;
+    let mut bits = 0;
+    let mut ch = 0;
+    while hash.len() < precision {
+        // bits alternate lon, lat, lon, lat...
+        let (lo, hi, val) = if even {
+            (&mut lon_lo, &mut lon_hi, lon)
+        } else {
+            (&mut lat_lo, &mut lat_hi, lat)
+        };
+        let mid = (*lo + *hi) / 2.0;
+        ch <<= 1;
+        if val >= mid {
+            ch |= 1;
+            *lo = mid;
+        } else {
+            *hi = mid;
+        }
+        even = !even;
+        bits += 1;
+        if bits == 5 {
+            hash.push(BASE32[ch] as char);
+            bits = 0;
+            ch = 0;
+        }
+    }
+    hash
+}
+
+/// The size (lat degrees, lon degrees) of a geohash cell at a given precision
+fn cell_size(precision: usize) -> (f64, f64) {
+    let bits = (precision * 5) as i32;
+    let lon_bits = (bits + 1) / 2;
+    let lat_bits = bits / 2;
+    (180.0 / 2f64.powi(lat_bits), 360.0 / 2f64.powi(lon_bits))
+}
+
+/// Great-circle distance between two points, in km
+pub fn distance_km(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let dlat = (lat2 - lat1).to_radians();
+    let dlon = (lon2 - lon1).to_radians();
+    let a = (dlat / 2.0).sin().powi(2) + lat1.to_radians().cos() * lat2.to_radians().cos() * (dlon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().min(1.0).asin()
+}
+
+/// Whether a lat/lon pair is a real place
+pub fn valid(lat: f64, lon: f64) -> bool {
+    lat >= -90.0 && lat <= 90.0 && lon >= -180.0 && lon <= 180.0
+}
+
+/// Find a set of geohash prefixes whose cells cover everything within `km` of
+/// a point. We pick the longest prefix whose cells are at least as big as the
+/// radius' bounding box is wide (in both directions), then take the cells
+/// under a 3x3 grid of points across that box: with cells that big, any cell
+/// touching the box has one of those points in it.
+pub fn covering_prefixes(lat: f64, lon: f64, km: f64) -> Vec<String> {
+    let dlat = (km / EARTH_RADIUS_KM).to_degrees();
+    let coslat = lat.to_radians().cos();
+    // near the poles the box wraps all the way around
+    let dlon = if coslat < 1e-6 { 360.0 } else { (dlat / coslat).min(360.0) };
+    let mut precision = 0;
+    while precision < MAX_PRECISION {
+        let (cell_lat, cell_lon) = cell_size(precision + 1);
+        if cell_lat < dlat || cell_lon < dlon { break; }
+        precision += 1;
+    }
+    // no prefix will do, so everything's a candidate
+    if precision == 0 { return vec![String::new()]; }
+    let mut prefixes: Vec<String> = Vec::with_capacity(9);
+    for &y in &[-1.0, 0.0, 1.0] {
+        for &x in &[-1.0, 0.0, 1.0] {
+            let plat = (lat + (y * dlat)).max(-90.0).min(90.0);
+            let mut plon = lon + (x * dlon);
+            if plon < -180.0 { plon += 360.0; }
+            if plon > 180.0 { plon -= 360.0; }
+            let hash = encode(plat, plon, precision);
+            if !prefixes.contains(&hash) { prefixes.push(hash); }
+        }
+    }
+    prefixes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashes_and_covers() {
+        assert_eq!(encode(57.64911, 10.40744, 11), "u4pruydqqvj");
+        assert_eq!(encode(-25.382708, -49.265506, 8), "6gkzwgjz");
+        let dist = distance_km(51.5074, -0.1278, 48.8566, 2.3522);
+        assert!(dist > 340.0 && dist < 345.0);
+        assert!(!valid(91.0, 0.0));
+        assert!(valid(-90.0, 180.0));
+
+        // a point just under 1km away has to fall under one of the prefixes
+        let (lat, lon) = (45.5231, -122.6765);
+        let (lat2, lon2) = (45.5231 + 0.0089, -122.6765 + 0.0001);
+        assert!(distance_km(lat, lon, lat2, lon2) < 1.0);
+        let prefixes = covering_prefixes(lat, lon, 1.0);
+        let hash = encode(lat2, lon2, MAX_PRECISION);
+        assert!(prefixes.iter().any(|p| hash.starts_with(p.as_str())));
+        assert!(prefixes.iter().all(|p| p.len() > 3));
+        assert_eq!(covering_prefixes(0.0, 0.0, 30000.0), vec![String::new()]);
+    }
+}
@@ -0,0 +1,279 @@
+//! Imports notes from a CSV file. The UI tells us which columns map to which
+//! note fields, and we stream the file a row at a time so even very large
+//! spreadsheets import without being loaded into memory. Rows that fail don't
+//! stop the import; they're collected and reported back.
+
+use ::std::collections::HashMap;
+use ::std::fs;
+use ::std::io::{BufRead, BufReader};
+use ::error::{TResult, TError};
+use ::messaging;
+use ::jobs;
+use ::turtl::Turtl;
+use ::models::model::Model;
+use ::import::{self, ImportNote, ImportOptions, Deduper, Saved};
+
+/// How often (in rows) we let the UI know how far along we are
+const PROGRESS_EVERY: u64 = 100;
+
+/// The note fields a column can map to
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub enum CsvField {
+    #[serde(rename = "title")]
+    Title,
+    #[serde(rename = "body")]
+    Body,
+    #[serde(rename = "tags")]
+    Tags,
+    #[serde(rename = "url")]
+    Url,
+    #[serde(rename = "board")]
+    Board,
+}
+
+/// What the UI sends us to kick off an import
+#[derive(Deserialize, Debug)]
+pub struct CsvImportRequest {
+    /// Path to the CSV file. The first row must be a header row.
+    pub path: String,
+    /// Maps column names (from the header row) to note fields
+    pub mapping: HashMap<String, CsvField>,
+    /// Where rows without a board column (or with an empty one) go
+    #[serde(default)]
+    pub board_id: Option<String>,
+    /// How to handle rows that match notes we already have
+    #[serde(default)]
+    pub options: ImportOptions,
+}
+
+/// An error for a single row
+#[derive(Serialize, Debug)]
+pub struct CsvRowError {
+    /// The row number (1 is the header row)
+    pub row: u64,
+    pub error: String,
+}
+
+/// What happened during the import
+#[derive(Serialize, Debug, Default)]
+pub struct CsvImportResult {
+    pub imported: u64,
+    /// Rows we skipped because we already had a matching note
+    pub duplicates: u64,
+    pub replaced: u64,
+    pub errors: Vec<CsvRowError>,
+}
+
+/// Reads CSV records (RFC4180-ish) one at a time. Quoted fields may contain
+/// commas, doubled quotes, and newlines.
+struct CsvReader<R: BufRead> {
+    reader: R,
+    done: bool,
+}
+
+impl<R: BufRead> CsvReader<R> {
+    fn new(reader: R) -> Self {
+        CsvReader { reader: reader, done: false }
+    }
+}
+
+impl<R: BufRead> Iterator for CsvReader<R> {
+    type Item = TResult<Vec<String>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done { return None; }
+        let mut fields: Vec<String> = Vec::new();
+        let mut field = String::new();
+        let mut in_quotes = false;
+        let mut read_any = false;
+        loop {
+            let mut line = String::new();
+            match self.reader.read_line(&mut line) {
+                Ok(0) => {
+                    self.done = true;
+                    if !read_any { return None; }
+                    if in_quotes {
+                        return Some(TErr!(TError::BadValue(String::from("unterminated quoted field"))));
+                    }
+                    fields.push(field);
+                    return Some(Ok(fields));
+                }
+                Ok(_) => read_any = true,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(From::from(e)));
+                }
+            }
+            let mut chars = line.chars().peekable();
+            while let Some(c) = chars.next() {
+                if in_quotes {
+                    if c == '"' {
+                        if chars.peek() == Some(&'"') {
+                            field.push('"');
+                            chars.next();
+                        } else {
+                            in_quotes = false;
+                        }
+                    } else {
+                        field.push(c);
+                    }
+                    continue;
+                }
+                match c {
+                    '"' if field.len() == 0 => in_quotes = true,
+                    ',' => fields.push(::std::mem::replace(&mut field, String::new())),
+                    '\r' if chars.peek() == Some(&'\n') => {}
+                    '\n' => {
+                        fields.push(field);
+                        return Some(Ok(fields));
+                    }
+                    _ => field.push(c),
+                }
+            }
+            // if we're still in quotes, the record continues on the next line
+        }
+    }
+}
+
+/// Split a tags cell into tags. Accepts commas or semicolons.
+fn split_tags(cell: &str) -> Vec<String> {
+    cell.split(|c| c == ',' || c == ';')
+        .map(|x| x.trim())
+        .filter(|x| x.len() > 0)
+        .map(|x| String::from(x))
+        .collect()
+}
+
+/// Turn a row into a note (and the board it goes in, if the row names one)
+fn build_note(columns: &Vec<Option<CsvField>>, row: Vec<String>) -> TResult<(ImportNote, Option<String>)> {
+    if row.len() != columns.len() {
+        return TErr!(TError::BadValue(format!("expected {} columns, got {}", columns.len(), row.len())));
+    }
+    let mut note = ImportNote::default();
+    let mut board = None;
+    for (field, cell) in columns.iter().zip(row.into_iter()) {
+        let cell = String::from(cell.trim());
+        if cell == "" { continue; }
+        match *field {
+            Some(CsvField::Title) => note.title = Some(cell),
+            Some(CsvField::Body) => note.text = Some(cell),
+            Some(CsvField::Tags) => note.tags.append(&mut split_tags(&cell)),
+            Some(CsvField::Url) => note.url = Some(cell),
+            Some(CsvField::Board) => board = Some(cell),
+            None => {}
+        }
+    }
+    if note.title.is_none() && note.text.is_none() && note.url.is_none() {
+        return TErr!(TError::MissingData(String::from("row has no title, body, or url")));
+    }
+    Ok((note, board))
+}
+
+/// Look up a board by id or (case-insensitive) title, returning its id and
+/// space id
+fn find_board(turtl: &Turtl, board: &String) -> TResult<(String, String)> {
+    let lower = board.to_lowercase();
+    let found = {
+        let profile_guard = lockr!(turtl.profile);
+        profile_guard.boards.iter()
+            .find(|b| {
+                b.id() == Some(board) ||
+                    b.title.as_ref().map(|t| t.to_lowercase() == lower).unwrap_or(false)
+            })
+            .and_then(|b| b.id().map(|id| (id.clone(), b.space_id.clone())))
+    };
+    match found {
+        Some(x) => Ok(x),
+        None => TErr!(TError::MissingData(format!("board {} not found", board))),
+    }
+}
+
+/// Import a CSV file as notes
+pub fn import_csv(turtl: &Turtl, req: CsvImportRequest) -> TResult<CsvImportResult> {
+    let default_board = match req.board_id.as_ref() {
+        Some(board_id) => Some((board_id.clone(), import::board_space(turtl, board_id)?)),
+        None => None,
+    };
+    let reader = BufReader::new(fs::File::open(&req.path)?);
+    let mut records = CsvReader::new(reader);
+    let header = match records.next() {
+        Some(x) => x?,
+        None => return TErr!(TError::BadValue(format!("{} is empty", req.path))),
+    };
+    let columns = header.iter()
+        .map(|name| req.mapping.get(name.trim()).map(|x| x.clone()))
+        .collect::<Vec<_>>();
+    for name in req.mapping.keys() {
+        if !header.iter().any(|h| h.trim() == name) {
+            return TErr!(TError::BadValue(format!("column {} isn't in the header row", name)));
+        }
+    }
+
+    let mut result = CsvImportResult::default();
+    // board names -> (board_id, space_id), so we don't search for every row
+    let mut board_cache: HashMap<String, (String, String)> = HashMap::new();
+    let mut deduper = Deduper::new(req.options.clone());
+    let mut rownum: u64 = 1;
+    for record in records {
+        rownum += 1;
+        let res = record
+            .and_then(|row| build_note(&columns, row))
+            .and_then(|(note, board)| {
+                let (board_id, space_id) = match board {
+                    Some(name) => {
+                        if !board_cache.contains_key(&name) {
+                            let found = find_board(turtl, &name)?;
+                            board_cache.insert(name.clone(), found);
+                        }
+                        board_cache.get(&name).unwrap().clone()
+                    }
+                    None => match default_board.as_ref() {
+                        Some(x) => x.clone(),
+                        None => return TErr!(TError::MissingData(String::from("row has no board and no default board was given"))),
+                    },
+                };
+                deduper.save(turtl, &space_id, &board_id, note)
+            });
+        match res {
+            Ok(Saved::Created(_)) => result.imported += 1,
+            Ok(Saved::Replaced(_)) => result.replaced += 1,
+            Ok(Saved::Skipped(_)) => result.duplicates += 1,
+            Err(e) => result.errors.push(CsvRowError { row: rownum, error: format!("{}", e) }),
+        }
+        if rownum % PROGRESS_EVERY == 0 {
+            messaging::ui_event("profile:import-csv:progress", &json!({"rows": rownum - 1}))?;
+            jobs::progress(rownum - 1, None);
+            jobs::check_cancelled()?;
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::std::io::Cursor;
+
+    #[test]
+    fn reads_csv_records() {
+        let data = "title,body,tags\r\nhello,\"multi\nline, with \"\"quotes\"\"\",\"a, b\"\nbye,,c";
+        let rows = CsvReader::new(Cursor::new(data.as_bytes()))
+            .map(|x| x.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0], vec!["title", "body", "tags"]);
+        assert_eq!(rows[1], vec!["hello", "multi\nline, with \"quotes\"", "a, b"]);
+        assert_eq!(rows[2], vec!["bye", "", "c"]);
+    }
+
+    #[test]
+    fn builds_notes_from_rows() {
+        let columns = vec![Some(CsvField::Title), None, Some(CsvField::Tags)];
+        let (note, board) = build_note(&columns, vec![String::from("hi"), String::from("ignored"), String::from("a; b,c")]).unwrap();
+        assert_eq!(note.title, Some(String::from("hi")));
+        assert_eq!(note.tags, vec!["a", "b", "c"]);
+        assert_eq!(board, None);
+        assert!(build_note(&columns, vec![String::from("hi")]).is_err());
+        assert!(build_note(&columns, vec![String::new(), String::new(), String::from("a")]).is_err());
+    }
+}
@@ -0,0 +1,679 @@
+//! Imports mail from a local mbox file or maildir folder as notes. Each message
+//! becomes a note (with its headers at the top of the text), attachments are
+//! carried over as note files, and messages are deduplicated by Message-ID so
+//! the same archive can be imported more than once without doubling up.
+//!
+//! This is a deliberately small MIME parser. It understands multipart bodies,
+//! base64/quoted-printable transfer encodings, charsets, and encoded-word
+//! headers, which covers the vast majority of real-world mail.
+
+use ::std::collections::HashSet;
+use ::std::fs;
+use ::std::io::{BufRead, BufReader};
+use ::std::path::{Path, PathBuf};
+use ::encoding_rs::Encoding;
+use ::jedi;
+use ::error::{TResult, TError};
+use ::crypto;
+use ::messaging;
+use ::jobs;
+use ::turtl::Turtl;
+use ::import::{self, ImportFile, ImportNote, ImportOptions, Deduper, Saved};
+
+/// The kv key we keep the Message-IDs we've already imported under
+const SEEN_KEY: &'static str = "import:mail:seen";
+
+/// What the UI sends us to kick off an import
+#[derive(Deserialize, Debug)]
+pub struct MailImportRequest {
+    /// Path to an mbox file or a maildir folder
+    pub path: String,
+    /// The board to put the imported notes in
+    pub board_id: String,
+    /// If given, only import messages with these Message-IDs
+    #[serde(default)]
+    pub select: Option<Vec<String>>,
+    /// How to handle messages whose content matches notes we already have
+    #[serde(default)]
+    pub options: ImportOptions,
+}
+
+/// What happened to a single message
+#[derive(Serialize, Debug, PartialEq)]
+pub enum MailImportStatus {
+    #[serde(rename = "imported")]
+    Imported,
+    #[serde(rename = "duplicate")]
+    Duplicate,
+    #[serde(rename = "replaced")]
+    Replaced,
+    #[serde(rename = "skipped")]
+    Skipped,
+    #[serde(rename = "error")]
+    Error,
+}
+
+/// Summary of what happened to a single message
+#[derive(Serialize, Debug)]
+pub struct MailImportEntry {
+    pub message_id: String,
+    pub subject: Option<String>,
+    pub status: MailImportStatus,
+    pub note_ids: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The per-message results of an import, along with some totals
+#[derive(Serialize, Debug, Default)]
+pub struct MailImportSummary {
+    pub messages: Vec<MailImportEntry>,
+    pub imported: u64,
+    /// Messages we skipped because we already had them
+    pub duplicates: u64,
+    pub replaced: u64,
+    pub errors: u64,
+}
+
+/// A MIME part: headers plus a raw (still transfer-encoded) body
+struct Part {
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+/// A parsed message, ready to be turned into notes
+#[derive(Default, Debug)]
+struct ParsedMail {
+    message_id: String,
+    subject: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+    date: Option<String>,
+    text: Vec<String>,
+    html: Vec<String>,
+    attachments: Vec<(String, String, Vec<u8>)>,
+}
+
+/// Find where the headers end and the body starts
+fn split_headers(raw: &[u8]) -> (&[u8], &[u8]) {
+    let mut i = 0;
+    while i < raw.len() {
+        if raw[i] == b'\n' {
+            if raw.get(i + 1) == Some(&b'\n') {
+                return (&raw[0..i], &raw[i + 2..]);
+            }
+            if raw.get(i + 1) == Some(&b'\r') && raw.get(i + 2) == Some(&b'\n') {
+                return (&raw[0..i], &raw[i + 3..]);
+            }
+        }
+        i += 1;
+    }
+    (raw, &raw[raw.len()..])
+}
+
+/// Parse (and unfold) a header block
+fn parse_headers(raw: &[u8]) -> Vec<(String, String)> {
+    let text = String::from_utf8_lossy(raw);
+    let mut headers: Vec<(String, String)> = Vec::new();
+    for line in text.lines() {
+        let line = line.trim_end_matches('\r');
+        if line.starts_with(' ') || line.starts_with('\t') {
+            if let Some(last) = headers.last_mut() {
+                last.1.push(' ');
+                last.1.push_str(line.trim());
+            }
+            continue;
+        }
+        let idx = match line.find(':') {
+            Some(x) => x,
+            None => continue,
+        };
+        headers.push((line[0..idx].trim().to_lowercase(), String::from(line[idx + 1..].trim())));
+    }
+    headers
+}
+
+fn parse_part(raw: &[u8]) -> Part {
+    let (headers, body) = split_headers(raw);
+    Part {
+        headers: parse_headers(headers),
+        body: Vec::from(body),
+    }
+}
+
+fn header<'a>(headers: &'a Vec<(String, String)>, name: &str) -> Option<&'a String> {
+    headers.iter()
+        .filter(|h| h.0 == name)
+        .map(|h| &h.1)
+        .next()
+}
+
+/// Grab a parameter (`boundary`, `charset`, `filename`, ...) out of a header
+/// value like `multipart/mixed; boundary="abc"`
+fn header_param(value: &str, name: &str) -> Option<String> {
+    for piece in value.split(';').skip(1) {
+        let mut kv = piece.splitn(2, '=');
+        let key = kv.next().unwrap_or("").trim().to_lowercase();
+        if key != name { continue; }
+        let val = kv.next().unwrap_or("").trim().trim_matches('"');
+        return Some(String::from(val));
+    }
+    None
+}
+
+fn decode_charset(bytes: &[u8], charset: Option<&str>) -> String {
+    let encoding = charset
+        .and_then(|c| Encoding::for_label(c.trim().as_bytes()))
+        .unwrap_or(::encoding_rs::UTF_8);
+    let (decoded, _, _) = encoding.decode(bytes);
+    decoded.into_owned()
+}
+
+fn hex_val(c: u8) -> Option<u8> {
+    match c {
+        b'0'...b'9' => Some(c - b'0'),
+        b'a'...b'f' => Some(c - b'a' + 10),
+        b'A'...b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Decode quoted-printable. `underscores` turns `_` into spaces (which is how
+/// Q-encoded header words work).
+fn decode_qp(input: &[u8], underscores: bool) -> Vec<u8> {
+    let mut out = Vec::with_capacity(input.len());
+    let mut i = 0;
+    while i < input.len() {
+        let c = input[i];
+        if c == b'=' {
+            // soft line break
+            if input.get(i + 1) == Some(&b'\n') { i += 2; continue; }
+            if input.get(i + 1) == Some(&b'\r') && input.get(i + 2) == Some(&b'\n') { i += 3; continue; }
+            let hi = input.get(i + 1).and_then(|x| hex_val(*x));
+            let lo = input.get(i + 2).and_then(|x| hex_val(*x));
+            if let (Some(hi), Some(lo)) = (hi, lo) {
+                out.push(hi * 16 + lo);
+                i += 3;
+                continue;
+            }
+        }
+        if underscores && c == b'_' {
+            out.push(b' ');
+        } else {
+            out.push(c);
+        }
+        i += 1;
+    }
+    out
+}
+
+fn decode_base64(input: &[u8]) -> TResult<Vec<u8>> {
+    let cleaned = input.iter()
+        .filter(|c| !(**c as char).is_whitespace())
+        .map(|c| *c as char)
+        .collect::<String>();
+    Ok(crypto::from_base64(&cleaned)?)
+}
+
+/// Decode a part's body according to its Content-Transfer-Encoding
+fn decode_body(part: &Part) -> TResult<Vec<u8>> {
+    let encoding = header(&part.headers, "content-transfer-encoding")
+        .map(|x| x.to_lowercase())
+        .unwrap_or(String::from("7bit"));
+    match encoding.as_ref() {
+        "base64" => decode_base64(&part.body),
+        "quoted-printable" => Ok(decode_qp(&part.body, false)),
+        _ => Ok(part.body.clone()),
+    }
+}
+
+/// Decode RFC2047 encoded words (`=?utf-8?B?...?=`) in a header value
+fn decode_words(value: &str) -> String {
+    let mut out = String::new();
+    let mut rest = value;
+    let mut last_was_word = false;
+    while let Some(start) = rest.find("=?") {
+        let (before, after) = rest.split_at(start);
+        let parsed = {
+            let inner = &after[2..];
+            let mut pieces = inner.splitn(3, '?');
+            let charset = pieces.next();
+            let enc = pieces.next();
+            let text_and_rest = pieces.next();
+            match (charset, enc, text_and_rest) {
+                (Some(charset), Some(enc), Some(text_and_rest)) => {
+                    match text_and_rest.find("?=") {
+                        Some(end) => {
+                            let text = &text_and_rest[0..end];
+                            let bytes = match enc.to_lowercase().as_ref() {
+                                "b" => decode_base64(text.as_bytes()).ok(),
+                                "q" => Some(decode_qp(text.as_bytes(), true)),
+                                _ => None,
+                            };
+                            let consumed = 2 + charset.len() + 1 + enc.len() + 1 + end + 2;
+                            bytes.map(|b| (decode_charset(&b, Some(charset)), consumed))
+                        }
+                        None => None,
+                    }
+                }
+                _ => None,
+            }
+        };
+        match parsed {
+            Some((decoded, consumed)) => {
+                // whitespace between two encoded words is dropped
+                if !(last_was_word && before.trim() == "") {
+                    out.push_str(before);
+                }
+                out.push_str(&decoded);
+                rest = &after[consumed..];
+                last_was_word = true;
+            }
+            None => {
+                out.push_str(before);
+                out.push_str("=?");
+                rest = &after[2..];
+                last_was_word = false;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Split a multipart body on its boundary
+fn split_multipart(body: &[u8], boundary: &str) -> Vec<Vec<u8>> {
+    let delim = format!("--{}", boundary);
+    let text = body;
+    let mut parts: Vec<Vec<u8>> = Vec::new();
+    let mut current: Option<Vec<u8>> = None;
+    for line in text.split(|c| *c == b'\n') {
+        let trimmed = String::from_utf8_lossy(line);
+        let trimmed = trimmed.trim_end();
+        if trimmed == delim || trimmed == format!("{}--", delim) {
+            if let Some(mut part) = current.take() {
+                // the newline before the boundary belongs to the boundary
+                if part.ends_with(b"\r\n") { let len = part.len(); part.truncate(len - 2); }
+                else if part.ends_with(b"\n") { let len = part.len(); part.truncate(len - 1); }
+                parts.push(part);
+            }
+            if trimmed != delim { break; }
+            current = Some(Vec::new());
+            continue;
+        }
+        if let Some(ref mut part) = current {
+            part.extend_from_slice(line);
+            part.push(b'\n');
+        }
+    }
+    parts
+}
+
+/// Walk a MIME tree, pulling out text and attachments
+fn walk(part: &Part, mail: &mut ParsedMail, depth: usize) -> TResult<()> {
+    // mail bombs are a thing
+    if depth > 16 { return Ok(()); }
+    let content_type = header(&part.headers, "content-type")
+        .map(|x| x.clone())
+        .unwrap_or(String::from("text/plain"));
+    let mime = content_type.split(';').next().unwrap_or("").trim().to_lowercase();
+    if mime.starts_with("multipart/") {
+        let boundary = match header_param(&content_type, "boundary") {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+        for raw in split_multipart(&part.body, &boundary) {
+            walk(&parse_part(&raw), mail, depth + 1)?;
+        }
+        return Ok(());
+    }
+    let disposition = header(&part.headers, "content-disposition").map(|x| x.clone()).unwrap_or(String::new());
+    let filename = header_param(&disposition, "filename")
+        .or_else(|| header_param(&content_type, "name"))
+        .map(|x| decode_words(&x));
+    let is_attachment = disposition.to_lowercase().starts_with("attachment") || filename.is_some();
+    let body = decode_body(part)?;
+    if is_attachment {
+        let name = filename.unwrap_or(String::from("attachment"));
+        mail.attachments.push((name, mime, body));
+        return Ok(());
+    }
+    let charset = header_param(&content_type, "charset");
+    match mime.as_ref() {
+        "text/plain" => mail.text.push(decode_charset(&body, charset.as_ref().map(|x| x.as_str()))),
+        "text/html" => mail.html.push(decode_charset(&body, charset.as_ref().map(|x| x.as_str()))),
+        "message/rfc822" => walk(&parse_part(&body), mail, depth + 1)?,
+        _ => mail.attachments.push((String::from("attachment"), mime, body)),
+    }
+    Ok(())
+}
+
+/// Parse a raw message
+fn parse_mail(raw: &[u8]) -> TResult<ParsedMail> {
+    let part = parse_part(raw);
+    let mut mail = ParsedMail::default();
+    let get = |name: &str| header(&part.headers, name).map(|x| decode_words(x));
+    mail.message_id = match header(&part.headers, "message-id") {
+        Some(x) => String::from(x.trim().trim_start_matches('<').trim_end_matches('>')),
+        // no message id? make one up from the message itself so we can still
+        // dedupe it
+        None => crypto::to_hex(&crypto::sha256(raw)?)?,
+    };
+    mail.subject = get("subject");
+    mail.from = get("from");
+    mail.to = get("to");
+    mail.date = get("date");
+    walk(&part, &mut mail, 0)?;
+    Ok(mail)
+}
+
+/// Strip tags out of an html body. Only used when a message has no plain
+/// text part.
+fn html_to_text(html: &str) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            _ if !in_tag => out.push(c),
+            _ => {}
+        }
+    }
+    out.replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&amp;", "&")
+}
+
+/// Build the note text: headers first, then the body
+fn note_text(mail: &ParsedMail) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    if let Some(ref x) = mail.from { lines.push(format!("From: {}", x)); }
+    if let Some(ref x) = mail.to { lines.push(format!("To: {}", x)); }
+    if let Some(ref x) = mail.date { lines.push(format!("Date: {}", x)); }
+    let body = if mail.text.len() > 0 {
+        mail.text.join("\n\n")
+    } else {
+        mail.html.iter().map(|x| html_to_text(x)).collect::<Vec<_>>().join("\n\n")
+    };
+    format!("{}\n\n{}", lines.join("\n"), body.trim())
+}
+
+/// Reads messages out of an mbox file one at a time, so we never have to hold
+/// the whole archive in memory.
+struct MboxReader<R: BufRead> {
+    reader: R,
+    pending: Option<Vec<u8>>,
+    done: bool,
+}
+
+impl<R: BufRead> MboxReader<R> {
+    fn new(reader: R) -> Self {
+        MboxReader { reader: reader, pending: None, done: false }
+    }
+}
+
+impl<R: BufRead> Iterator for MboxReader<R> {
+    type Item = TResult<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done { return None; }
+        let mut message: Vec<u8> = self.pending.take().unwrap_or(Vec::new());
+        loop {
+            let mut line: Vec<u8> = Vec::new();
+            match self.reader.read_until(b'\n', &mut line) {
+                Ok(0) => {
+                    self.done = true;
+                    return if message.len() > 0 { Some(Ok(message)) } else { None };
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(From::from(e)));
+                }
+            }
+            if line.starts_with(b"From ") {
+                // envelope line: starts a new message
+                if message.len() > 0 {
+                    return Some(Ok(message));
+                }
+                continue;
+            }
+            // un-escape ">From " lines
+            if line.starts_with(b">") {
+                let unquoted = line.iter().skip_while(|c| **c == b'>').map(|c| *c).collect::<Vec<_>>();
+                if unquoted.starts_with(b"From ") {
+                    message.extend_from_slice(&line[1..]);
+                    continue;
+                }
+            }
+            message.extend_from_slice(&line);
+        }
+    }
+}
+
+/// List the message files in a maildir (cur/ + new/), oldest first
+fn maildir_files(path: &Path) -> TResult<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = Vec::new();
+    let mut dirs = vec![path.join("cur"), path.join("new")];
+    if !dirs.iter().any(|d| d.is_dir()) {
+        dirs = vec![PathBuf::from(path)];
+    }
+    for dir in dirs {
+        if !dir.is_dir() { continue; }
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                files.push(entry.path());
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn load_seen(turtl: &Turtl) -> TResult<HashSet<String>> {
+    let seen = with_db!{ db, turtl.db, db.kv_get(SEEN_KEY)? };
+    match seen {
+        Some(x) => Ok(jedi::parse::<Vec<String>>(&x)?.into_iter().collect()),
+        None => Ok(HashSet::new()),
+    }
+}
+
+fn save_seen(turtl: &Turtl, seen: &HashSet<String>) -> TResult<()> {
+    let serialized = jedi::stringify(&seen.iter().collect::<Vec<_>>())?;
+    with_db!{ db, turtl.db, db.kv_set(SEEN_KEY, &serialized)? };
+    Ok(())
+}
+
+/// Turn one raw message into notes
+fn import_one(turtl: &Turtl, space_id: &String, req: &MailImportRequest, raw: &[u8], seen: &mut HashSet<String>, deduper: &mut Deduper) -> MailImportEntry {
+    let mail = match parse_mail(raw) {
+        Ok(x) => x,
+        Err(e) => {
+            return MailImportEntry {
+                message_id: String::new(),
+                subject: None,
+                status: MailImportStatus::Error,
+                note_ids: Vec::new(),
+                error: Some(format!("{}", e)),
+            };
+        }
+    };
+    let mut entry = MailImportEntry {
+        message_id: mail.message_id.clone(),
+        subject: mail.subject.clone(),
+        status: MailImportStatus::Imported,
+        note_ids: Vec::new(),
+        error: None,
+    };
+    if let Some(ref select) = req.select {
+        if !select.contains(&mail.message_id) {
+            entry.status = MailImportStatus::Skipped;
+            return entry;
+        }
+    }
+    if seen.contains(&mail.message_id) {
+        entry.status = MailImportStatus::Duplicate;
+        return entry;
+    }
+    let text = note_text(&mail);
+    let ParsedMail { subject, attachments, .. } = mail;
+    let mut attachments = attachments.into_iter();
+    let mut note = ImportNote::default();
+    note.title = subject.clone();
+    note.text = Some(text);
+    note.tags = vec![String::from("email")];
+    note.file = attachments.next().map(|(name, mime, data)| ImportFile { name: name, mime: mime, data: data });
+    let mut res = match deduper.save(turtl, space_id, &req.board_id, note) {
+        Ok(Saved::Created(id)) => Ok(entry.note_ids.push(id)),
+        Ok(Saved::Replaced(id)) => {
+            entry.status = MailImportStatus::Replaced;
+            Ok(entry.note_ids.push(id))
+        }
+        // we've got this message under a different Message-ID. remember this
+        // one too so we don't bother parsing it next time.
+        Ok(Saved::Skipped(id)) => {
+            entry.status = MailImportStatus::Duplicate;
+            entry.note_ids.push(id);
+            seen.insert(entry.message_id.clone());
+            return entry;
+        }
+        Err(e) => Err(e),
+    };
+    // notes only hold one file, so any other attachments get their own notes
+    for (name, mime, data) in attachments {
+        if res.is_err() { break; }
+        let mut note = ImportNote::default();
+        note.title = Some(match subject {
+            Some(ref x) => format!("{} - {}", x, name),
+            None => name.clone(),
+        });
+        note.tags = vec![String::from("email")];
+        note.file = Some(ImportFile { name: name, mime: mime, data: data });
+        res = deduper.save(turtl, space_id, &req.board_id, note).map(|saved| {
+            match saved {
+                Saved::Created(id) | Saved::Replaced(id) => entry.note_ids.push(id),
+                Saved::Skipped(_) => {}
+            }
+        });
+    }
+    match res {
+        Ok(_) => { seen.insert(entry.message_id.clone()); }
+        Err(e) => {
+            entry.status = MailImportStatus::Error;
+            entry.error = Some(format!("{}", e));
+        }
+    }
+    entry
+}
+
+/// Import an mbox file or maildir as notes, returning a per-message summary
+pub fn import_mail(turtl: &Turtl, req: MailImportRequest) -> TResult<MailImportSummary> {
+    let space_id = import::board_space(turtl, &req.board_id)?;
+    let path = PathBuf::from(&req.path);
+    if !path.exists() {
+        return TErr!(TError::NotFound(format!("{} doesn't exist", req.path)));
+    }
+    let mut seen = load_seen(turtl)?;
+    let mut deduper = Deduper::new(req.options.clone());
+    let mut summary = MailImportSummary::default();
+    {
+        let mut handle = |raw: TResult<Vec<u8>>| -> TResult<()> {
+            let entry = match raw {
+                Ok(raw) => import_one(turtl, &space_id, &req, &raw, &mut seen, &mut deduper),
+                Err(e) => MailImportEntry {
+                    message_id: String::new(),
+                    subject: None,
+                    status: MailImportStatus::Error,
+                    note_ids: Vec::new(),
+                    error: Some(format!("{}", e)),
+                },
+            };
+            match entry.status {
+                MailImportStatus::Imported => summary.imported += 1,
+                MailImportStatus::Duplicate => summary.duplicates += 1,
+                MailImportStatus::Replaced => summary.replaced += 1,
+                MailImportStatus::Error => summary.errors += 1,
+                MailImportStatus::Skipped => {}
+            }
+            summary.messages.push(entry);
+            messaging::ui_event("profile:import-mail:progress", &json!({"done": summary.messages.len()}))?;
+            jobs::progress(summary.messages.len() as u64, None);
+            jobs::check_cancelled()?;
+            Ok(())
+        };
+        if path.is_dir() {
+            for file in maildir_files(&path)? {
+                handle(fs::read(&file).map_err(|e| From::from(e)))?;
+            }
+        } else {
+            let reader = BufReader::new(fs::File::open(&path)?);
+            for raw in MboxReader::new(reader) {
+                handle(raw)?;
+            }
+        }
+    }
+    save_seen(turtl, &seen)?;
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::std::io::Cursor;
+
+    const MULTIPART: &'static str = "From: =?utf-8?B?QW5kcsOp?= <andre@example.com>\r\n\
+To: me@example.com\r\n\
+Subject: =?utf-8?Q?caf=C3=A9?=\r\n  time\r\n\
+Message-ID: <abc123@example.com>\r\n\
+Content-Type: multipart/mixed; boundary=\"XYZ\"\r\n\
+\r\n\
+preamble\r\n\
+--XYZ\r\n\
+Content-Type: text/plain; charset=utf-8\r\n\
+Content-Transfer-Encoding: quoted-printable\r\n\
+\r\n\
+hello =\r\nthere caf=C3=A9\r\n\
+--XYZ\r\n\
+Content-Type: application/pdf; name=\"doc.pdf\"\r\n\
+Content-Disposition: attachment; filename=\"doc.pdf\"\r\n\
+Content-Transfer-Encoding: base64\r\n\
+\r\n\
+aGVsbG8g\r\nd29ybGQ=\r\n\
+--XYZ--\r\n";
+
+    #[test]
+    fn parses_multipart_mail() {
+        let mail = parse_mail(MULTIPART.as_bytes()).unwrap();
+        assert_eq!(mail.message_id, "abc123@example.com");
+        assert_eq!(mail.subject, Some(String::from("café time")));
+        assert_eq!(mail.from, Some(String::from("André <andre@example.com>")));
+        assert_eq!(mail.text, vec![String::from("hello there café")]);
+        assert_eq!(mail.attachments.len(), 1);
+        assert_eq!(mail.attachments[0].0, "doc.pdf");
+        assert_eq!(mail.attachments[0].1, "application/pdf");
+        assert_eq!(mail.attachments[0].2, Vec::from("hello world".as_bytes()));
+    }
+
+    #[test]
+    fn reads_mbox() {
+        let mbox = "From a@b.com Mon Jan  1 00:00:00 2018\nSubject: one\n\nbody\n>From the top\n\nFrom c@d.com Mon Jan  1 00:00:00 2018\nSubject: two\n\nbody two\n";
+        let messages = MboxReader::new(Cursor::new(mbox.as_bytes()))
+            .map(|x| String::from_utf8(x.unwrap()).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0], "Subject: one\n\nbody\nFrom the top\n\n");
+        assert_eq!(messages[1], "Subject: two\n\nbody two\n");
+    }
+
+    #[test]
+    fn makes_up_message_ids() {
+        let mail1 = parse_mail(b"Subject: hi\n\nyo").unwrap();
+        let mail2 = parse_mail(b"Subject: hi\n\nyo").unwrap();
+        assert_eq!(mail1.message_id.len(), 64);
+        assert_eq!(mail1.message_id, mail2.message_id);
+    }
+}
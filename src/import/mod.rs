@@ -0,0 +1,328 @@
+//! Importers that turn outside data (mail archives, spreadsheets, etc) into
+//! notes. Everything here goes through the normal sync dispatcher, so imported
+//! notes get the same permission checks/encryption/syncing as notes the user
+//! creates by hand.
+
+pub mod mail;
+pub mod csv;
+
+use ::std::collections::{HashMap, HashSet};
+use ::std::collections::hash_map::DefaultHasher;
+use ::std::hash::{Hash, Hasher};
+use ::jedi::{self, Value};
+use ::error::{TResult, TError};
+use ::crypto;
+use ::turtl::Turtl;
+use ::models::model::Model;
+use ::models::board::Board;
+use ::models::note::Note;
+use ::models::protected;
+use ::models::storable::Storable;
+use ::models::sync_record::{SyncRecord, SyncAction, SyncType};
+use ::sync::sync_model;
+
+/// A file to attach to an imported note
+pub struct ImportFile {
+    pub name: String,
+    pub mime: String,
+    pub data: Vec<u8>,
+}
+
+/// The pieces of a note we know how to import
+#[derive(Default)]
+pub struct ImportNote {
+    pub title: Option<String>,
+    pub text: Option<String>,
+    pub tags: Vec<String>,
+    pub url: Option<String>,
+    pub file: Option<ImportFile>,
+}
+
+/// What to do when an imported note matches one we already have
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub enum DuplicatePolicy {
+    /// Don't import it
+    #[serde(rename = "skip")]
+    Skip,
+    /// Overwrite the existing note with the imported one
+    #[serde(rename = "replace")]
+    Replace,
+    /// Import it anyway
+    #[serde(rename = "duplicate")]
+    Duplicate,
+}
+
+impl Default for DuplicatePolicy {
+    fn default() -> Self { DuplicatePolicy::Skip }
+}
+
+fn default_similarity() -> f64 { 0.9 }
+
+/// Options shared by all of our importers
+#[derive(Deserialize, Debug, Clone)]
+pub struct ImportOptions {
+    #[serde(default)]
+    pub duplicates: DuplicatePolicy,
+    /// How similar (0-1) two notes have to be to count as duplicates. 1 means
+    /// only exact matches count.
+    #[serde(default = "default_similarity")]
+    pub similarity: f64,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        ImportOptions {
+            duplicates: DuplicatePolicy::default(),
+            similarity: default_similarity(),
+        }
+    }
+}
+
+/// What happened when we tried to save an imported note
+#[derive(Debug, PartialEq)]
+pub enum Saved {
+    Created(String),
+    /// We overwrote this existing note
+    Replaced(String),
+    /// We skipped the note since it duplicates this existing note
+    Skipped(String),
+}
+
+/// The (normalized) content of a note that we compare against
+struct Fingerprint {
+    hash: String,
+    shingles: HashSet<u64>,
+}
+
+impl Fingerprint {
+    fn new(title: Option<&String>, text: Option<&String>, url: Option<&String>, file: Option<(&str, u64)>) -> TResult<Fingerprint> {
+        let mut content = String::new();
+        for piece in &[title, text, url] {
+            if let Some(x) = *piece {
+                content.push_str(x);
+                content.push('\n');
+            }
+        }
+        if let Some((name, size)) = file {
+            content.push_str(&format!("{}:{}\n", name, size));
+        }
+        let words = content.split_whitespace()
+            .map(|x| x.to_lowercase())
+            .collect::<Vec<_>>();
+        let normalized = words.join(" ");
+        // word trigrams. short notes just get one shingle with everything.
+        let mut shingles = HashSet::new();
+        if words.len() < 3 {
+            shingles.insert(hash_shingle(&words[..]));
+        } else {
+            for window in words.windows(3) {
+                shingles.insert(hash_shingle(window));
+            }
+        }
+        Ok(Fingerprint {
+            hash: crypto::to_hex(&crypto::sha256(normalized.as_bytes())?)?,
+            shingles: shingles,
+        })
+    }
+
+    fn from_note(note: &Note) -> TResult<Fingerprint> {
+        let file = note.file.as_ref()
+            .and_then(|f| f.name.as_ref().map(|n| (n.as_str(), f.size.unwrap_or(0))));
+        Fingerprint::new(note.title.as_ref(), note.text.as_ref(), note.url.as_ref(), file)
+    }
+
+    fn from_import(note: &ImportNote) -> TResult<Fingerprint> {
+        let file = note.file.as_ref().map(|f| (f.name.as_str(), f.data.len() as u64));
+        Fingerprint::new(note.title.as_ref(), note.text.as_ref(), note.url.as_ref(), file)
+    }
+
+    /// Jaccard similarity of our shingles
+    fn similarity(&self, other: &Fingerprint) -> f64 {
+        let union = self.shingles.union(&other.shingles).count();
+        if union == 0 { return 1.0; }
+        self.shingles.intersection(&other.shingles).count() as f64 / union as f64
+    }
+}
+
+fn hash_shingle(words: &[String]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    words.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Saves imported notes, checking each one against the notes already in its
+/// space (and the notes imported so far) and applying our duplicate policy.
+pub struct Deduper {
+    options: ImportOptions,
+    /// space id -> [(note id, fingerprint)]. loaded lazily, once per space.
+    spaces: HashMap<String, Vec<(String, Fingerprint)>>,
+}
+
+impl Deduper {
+    pub fn new(options: ImportOptions) -> Deduper {
+        Deduper {
+            options: options,
+            spaces: HashMap::new(),
+        }
+    }
+
+    /// Load and fingerprint the notes in a space
+    fn load_space(turtl: &Turtl, space_id: &String) -> TResult<Vec<(String, Fingerprint)>> {
+        let mut notes: Vec<Note> = with_db!{ db, turtl.db, db.find(Note::tablename(), "space_id", &vec![space_id.clone()])? };
+        turtl.find_models_keys(&mut notes)?;
+        let notes: Vec<Note> = protected::map_deserialize(turtl, notes)?;
+        let mut prints = Vec::with_capacity(notes.len());
+        for note in &notes {
+            prints.push((note.id_or_else()?, Fingerprint::from_note(note)?));
+        }
+        Ok(prints)
+    }
+
+    /// Find an existing note that duplicates the given fingerprint
+    fn find_match(&self, space_id: &String, print: &Fingerprint) -> Option<String> {
+        let existing = self.spaces.get(space_id)?;
+        if let Some(&(ref id, _)) = existing.iter().find(|&&(_, ref p)| p.hash == print.hash) {
+            return Some(id.clone());
+        }
+        if self.options.similarity >= 1.0 { return None; }
+        let threshold = self.options.similarity;
+        let len = print.shingles.len() as f64;
+        existing.iter()
+            // two sets can't be more similar than the ratio of their sizes, so
+            // skip anything that's way bigger/smaller
+            .filter(|&&(_, ref p)| {
+                let other = p.shingles.len() as f64;
+                len.min(other) / len.max(other).max(1.0) >= threshold
+            })
+            .find(|&&(_, ref p)| p.similarity(print) >= threshold)
+            .map(|&(ref id, _)| id.clone())
+    }
+
+    /// Save a note, applying our duplicate policy
+    pub fn save(&mut self, turtl: &Turtl, space_id: &String, board_id: &String, note: ImportNote) -> TResult<Saved> {
+        if self.options.duplicates == DuplicatePolicy::Duplicate {
+            return Ok(Saved::Created(save_note(turtl, space_id, board_id, note)?));
+        }
+        if !self.spaces.contains_key(space_id) {
+            let prints = Deduper::load_space(turtl, space_id)?;
+            self.spaces.insert(space_id.clone(), prints);
+        }
+        let print = Fingerprint::from_import(&note)?;
+        let saved = match self.find_match(space_id, &print) {
+            Some(existing_id) => {
+                if self.options.duplicates == DuplicatePolicy::Skip {
+                    return Ok(Saved::Skipped(existing_id));
+                }
+                replace_note(turtl, &existing_id, note)?;
+                if let Some(prints) = self.spaces.get_mut(space_id) {
+                    prints.retain(|&(ref id, _)| id != &existing_id);
+                }
+                Saved::Replaced(existing_id)
+            }
+            None => Saved::Created(save_note(turtl, space_id, board_id, note)?),
+        };
+        // imported notes can duplicate each other too
+        let id = match saved {
+            Saved::Created(ref id) | Saved::Replaced(ref id) | Saved::Skipped(ref id) => id.clone(),
+        };
+        if let Some(prints) = self.spaces.get_mut(space_id) {
+            prints.push((id, print));
+        }
+        Ok(saved)
+    }
+}
+
+/// Find the space a board lives in
+pub fn board_space(turtl: &Turtl, board_id: &String) -> TResult<String> {
+    match Board::get_space_id(turtl, board_id) {
+        Some(x) => Ok(x),
+        None => TErr!(TError::MissingData(format!("board {} not found", board_id))),
+    }
+}
+
+/// Turn an imported note into note data the dispatcher can save
+fn note_data(space_id: &String, board_id: &String, note: ImportNote) -> TResult<Value> {
+    let ImportNote { title, text, tags, url, file } = note;
+    let ty = if file.as_ref().map(|f| f.mime.starts_with("image/")).unwrap_or(false) {
+        "image"
+    } else if file.is_some() {
+        "file"
+    } else if url.is_some() && text.is_none() {
+        "link"
+    } else {
+        "text"
+    };
+    let mut data = json!({
+        "space_id": space_id,
+        "board_id": board_id,
+        "type": ty,
+        "title": title,
+        "text": text,
+        "tags": tags,
+        "url": url,
+    });
+    if let Some(file) = file {
+        let ImportFile { name, mime, data: bytes } = file;
+        jedi::set(&["file"], &mut data, &json!({
+            "name": name,
+            "type": mime,
+            "size": bytes.len(),
+            "filedata": {"data": crypto::to_base64(&bytes)?},
+        }))?;
+    }
+    Ok(data)
+}
+
+/// Save an imported note into the given board, returning the new note's id
+pub fn save_note(turtl: &Turtl, space_id: &String, board_id: &String, note: ImportNote) -> TResult<String> {
+    let data = note_data(space_id, board_id, note)?;
+    let mut sync_record = SyncRecord::default();
+    sync_record.action = SyncAction::Add;
+    sync_record.ty = SyncType::Note;
+    sync_record.data = Some(data);
+    let saved: Value = sync_model::dispatch(turtl, sync_record)?;
+    Ok(jedi::get(&["id"], &saved)?)
+}
+
+/// Overwrite an existing note (in place) with an imported one
+fn replace_note(turtl: &Turtl, note_id: &String, note: ImportNote) -> TResult<()> {
+    let (space_id, board_id) = {
+        let existing: Option<Note> = with_db!{ db, turtl.db, db.get(Note::tablename(), note_id)? };
+        match existing {
+            Some(x) => (x.space_id.clone(), x.board_id.clone()),
+            None => return TErr!(TError::NotFound(format!("note {} wasn't found", note_id))),
+        }
+    };
+    let mut data = note_data(&space_id, &String::new(), note)?;
+    jedi::set(&["id"], &mut data, note_id)?;
+    // the note stays where it is
+    jedi::set(&["board_id"], &mut data, &board_id)?;
+    let mut sync_record = SyncRecord::default();
+    sync_record.action = SyncAction::Edit;
+    sync_record.ty = SyncType::Note;
+    sync_record.data = Some(data);
+    sync_model::dispatch(turtl, sync_record)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn print(text: &str) -> Fingerprint {
+        Fingerprint::new(None, Some(&String::from(text)), None, None).unwrap()
+    }
+
+    #[test]
+    fn fingerprints_notes() {
+        let a = print("the quick brown fox jumps over the lazy dog");
+        let b = print("The  quick brown fox\njumps over the lazy dog");
+        let c = print("the quick brown fox jumps over the lazy cat");
+        let d = print("something else entirely, with no overlap at all");
+        assert_eq!(a.hash, b.hash);
+        assert!(a.hash != c.hash);
+        let sim = a.similarity(&c);
+        assert!(sim > 0.7 && sim < 1.0);
+        assert_eq!(a.similarity(&d), 0.0);
+    }
+}
@@ -0,0 +1,276 @@
+//! Jobs let long-running commands (imports, exports, migration, reindexing)
+//! answer right away with a job id instead of tying up a request until they're
+//! done. The UI sends `job:start` with the command it wants run, then follows
+//! along with `job:progress`/`job:finished` events or polls with `job:status`.
+//!
+//! A job runs on the thread that started it, so the code doing the work can
+//! report progress and check for cancellation without being handed anything.
+//! Results hang around (in memory) until they're cleared on logout or pushed
+//! out by newer jobs.
+
+use ::std::cell::RefCell;
+use ::std::collections::HashMap;
+use ::std::sync::RwLock;
+use ::std::sync::atomic::{AtomicUsize, Ordering};
+use ::time;
+use ::jedi::{self, Value};
+use ::error::{TResult, TError};
+use ::messaging;
+use ::dispatch::cancel;
+use ::dispatch::registry::Registry;
+
+/// Commands that can be run as jobs
+pub const JOB_COMMANDS: &'static [&'static str] = &[
+    "note:extract-article",
+    "profile:export",
+    "profile:import",
+    "profile:import-mail",
+    "profile:import-csv",
+    "profile:recurrences:run",
+    "profile:reindex",
+    "sync:reconcile",
+    "user:join-migrate",
+];
+
+/// How many finished jobs we hold onto
+const MAX_FINISHED: usize = 50;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+/// A job and (once it's done) its result
+#[derive(Serialize, Debug, Clone)]
+pub struct Job {
+    pub id: String,
+    pub command: String,
+    pub state: JobState,
+    pub done: u64,
+    pub total: Option<u64>,
+    pub result: Option<Value>,
+    pub error: Option<Value>,
+    pub started: i64,
+    pub finished: Option<i64>,
+    /// Set when someone asks us to stop. The job notices the next time it
+    /// checks.
+    pub cancel_requested: bool,
+}
+
+lazy_static! {
+    static ref JOBS: RwLock<HashMap<String, Job>> = RwLock::new(HashMap::new());
+    static ref COUNTER: AtomicUsize = AtomicUsize::new(0);
+}
+
+thread_local! {
+    /// The job running on this thread, if any
+    static CURRENT: RefCell<Option<String>> = RefCell::new(None);
+}
+
+fn current() -> Option<String> {
+    CURRENT.with(|x| x.borrow().clone())
+}
+
+/// Register a new job for the given command, returning it
+pub fn create(command: &String) -> TResult<Job> {
+    if !JOB_COMMANDS.contains(&command.as_str()) {
+        return TErr!(TError::BadValue(format!("{} can't be run as a job", command)));
+    }
+    let now = time::get_time().sec;
+    let job = Job {
+        id: format!("{:x}-{:x}", now, COUNTER.fetch_add(1, Ordering::SeqCst)),
+        command: command.clone(),
+        state: JobState::Running,
+        done: 0,
+        total: None,
+        result: None,
+        error: None,
+        started: now,
+        finished: None,
+        cancel_requested: false,
+    };
+    let mut jobs_guard = lockw!(*JOBS);
+    jobs_guard.insert(job.id.clone(), job.clone());
+    Ok(job)
+}
+
+/// Forget the oldest finished jobs once we have too many
+fn trim(jobs: &mut HashMap<String, Job>) {
+    let mut finished = jobs.values()
+        .filter(|j| j.state != JobState::Running)
+        .map(|j| (j.finished.unwrap_or(0), j.id.clone()))
+        .collect::<Vec<_>>();
+    if finished.len() <= MAX_FINISHED { return; }
+    finished.sort();
+    let remove = finished.len() - MAX_FINISHED;
+    for (_, id) in finished.into_iter().take(remove) {
+        jobs.remove(&id);
+    }
+}
+
+/// Whether an error came from a job noticing it was cancelled
+fn is_cancelled(err: &TError) -> bool {
+    match *err {
+        TError::Cancelled(_) => true,
+        TError::Wrapped(_, _, _, ref inner) => is_cancelled(inner),
+        _ => false,
+    }
+}
+
+/// Run a job on this thread, recording how it went
+pub fn run<F>(job_id: &String, runner: F)
+    where F: FnOnce() -> TResult<Value>
+{
+    CURRENT.with(|x| *x.borrow_mut() = Some(job_id.clone()));
+    let res = runner();
+    CURRENT.with(|x| *x.borrow_mut() = None);
+    let job = {
+        let mut jobs_guard = lockw!(*JOBS);
+        let job = match jobs_guard.get_mut(job_id) {
+            Some(x) => x,
+            None => return,
+        };
+        match res {
+            Ok(val) => {
+                job.state = JobState::Done;
+                job.result = Some(val);
+            }
+            Err(e) => {
+                job.state = if is_cancelled(&e) { JobState::Cancelled } else { JobState::Failed };
+                job.error = Some(::util::json_or_string(format!("{}", e)));
+            }
+        }
+        job.finished = Some(time::get_time().sec);
+        let job = job.clone();
+        trim(&mut jobs_guard);
+        job
+    };
+    match messaging::ui_event("job:finished", &job) {
+        Ok(_) => {}
+        Err(e) => error!("jobs::run() -- error sending finished event: {}", e),
+    }
+}
+
+/// Let the job running on this thread (if any) report its progress
+pub fn progress(done: u64, total: Option<u64>) {
+    let job_id = match current() {
+        Some(x) => x,
+        None => return,
+    };
+    {
+        let mut jobs_guard = lockw!(*JOBS);
+        if let Some(job) = jobs_guard.get_mut(&job_id) {
+            job.done = done;
+            job.total = total;
+        }
+    }
+    match messaging::ui_event("job:progress", &json!({"id": job_id, "done": done, "total": total})) {
+        Ok(_) => {}
+        Err(e) => error!("jobs::progress() -- error sending progress event: {}", e),
+    }
+}
+
+/// Returns an error if the job (or request, see `dispatch::cancel`) running on
+/// this thread was cancelled. Long loops should call this every so often.
+pub fn check_cancelled() -> TResult<()> {
+    cancel::check()?;
+    let job_id = match current() {
+        Some(x) => x,
+        None => return Ok(()),
+    };
+    let jobs_guard = lockr!(*JOBS);
+    match jobs_guard.get(&job_id) {
+        Some(job) if job.cancel_requested => TErr!(TError::Cancelled(format!("job {} was cancelled", job_id))),
+        _ => Ok(()),
+    }
+}
+
+pub fn status(job_id: &String) -> TResult<Job> {
+    let jobs_guard = lockr!(*JOBS);
+    match jobs_guard.get(job_id) {
+        Some(x) => Ok(x.clone()),
+        None => TErr!(TError::NotFound(format!("job {} wasn't found", job_id))),
+    }
+}
+
+/// Ask a job to stop. Jobs stop at their next cancellation check, so the job
+/// may still be running when this returns.
+pub fn cancel(job_id: &String) -> TResult<Job> {
+    let mut jobs_guard = lockw!(*JOBS);
+    match jobs_guard.get_mut(job_id) {
+        Some(job) => {
+            if job.state == JobState::Running {
+                job.cancel_requested = true;
+            }
+            Ok(job.clone())
+        }
+        None => TErr!(TError::NotFound(format!("job {} wasn't found", job_id))),
+    }
+}
+
+/// List our jobs, newest first
+pub fn list() -> Vec<Job> {
+    let jobs_guard = lockr!(*JOBS);
+    let mut jobs = jobs_guard.values().map(|x| x.clone()).collect::<Vec<_>>();
+    jobs.sort_by(|a, b| (b.started, &b.id).cmp(&(a.started, &a.id)));
+    jobs
+}
+
+/// Forget all finished jobs (running jobs are asked to stop). Results can hold
+/// decrypted data, so we do this on logout.
+pub fn clear() {
+    let mut jobs_guard = lockw!(*JOBS);
+    jobs_guard.retain(|_, job| job.state == JobState::Running);
+    for job in jobs_guard.values_mut() {
+        job.cancel_requested = true;
+    }
+}
+
+/// Registers the job commands (`job:start` is handled by dispatch itself)
+pub fn register(reg: &mut Registry) {
+    reg.add("job:status", |_turtl, args| {
+        let job_id: String = args.get(2)?;
+        Ok(jedi::to_val(&status(&job_id)?)?)
+    });
+    reg.add("job:cancel", |_turtl, args| {
+        let job_id: String = args.get(2)?;
+        Ok(jedi::to_val(&cancel(&job_id)?)?)
+    });
+    reg.add("job:list", |_turtl, _args| {
+        Ok(jedi::to_val(&list())?)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_and_cancels_jobs() {
+        let job = create(&String::from("profile:reindex")).unwrap();
+        assert!(create(&String::from("user:login")).is_err());
+        run(&job.id, || {
+            progress(5, Some(10));
+            Ok(json!({"notes": 10}))
+        });
+        let finished = status(&job.id).unwrap();
+        assert_eq!(finished.state, JobState::Done);
+        assert_eq!(finished.done, 5);
+        assert_eq!(finished.result, Some(json!({"notes": 10})));
+
+        let job = create(&String::from("profile:export")).unwrap();
+        cancel(&job.id).unwrap();
+        run(&job.id, || {
+            check_cancelled()?;
+            Ok(Value::Null)
+        });
+        assert_eq!(status(&job.id).unwrap().state, JobState::Cancelled);
+        assert!(list().iter().any(|j| j.id == job.id));
+        // no job on this thread anymore, so nothing to cancel
+        assert!(check_cancelled().is_ok());
+    }
+}
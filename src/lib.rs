@@ -9,6 +9,7 @@ extern crate crossbeam;
 extern crate dumpy;
 extern crate encoding_rs;
 extern crate fern;
+extern crate flate2;
 extern crate fs2;
 extern crate futures;
 extern crate futures_cpupool;
@@ -22,6 +23,7 @@ extern crate lib_permissions;
 extern crate log;
 extern crate log_panics;
 extern crate migrate;
+extern crate net2;
 extern crate num_cpus;
 #[macro_use]
 extern crate protected_derive;
@@ -29,6 +31,7 @@ extern crate protected_derive;
 extern crate quick_error;
 extern crate regex;
 extern crate reqwest;
+extern crate rmp_serde;
 #[macro_use]
 extern crate rusqlite;
 extern crate serde;
@@ -38,6 +41,7 @@ extern crate serde_derive;
 extern crate serde_json;
 extern crate sodiumoxide;
 extern crate time;
+extern crate tungstenite;
 extern crate url;
 
 #[macro_use]
@@ -54,7 +58,36 @@ mod models;
 mod profile;
 mod storage;
 mod search;
+mod analyzer;
+mod geo;
+mod archive;
+mod calendar;
+mod render;
+mod sanitize;
+mod spellcheck;
+mod scan;
+mod server_info;
+mod webhook;
+mod import;
+mod markdown;
+mod merge;
+mod replace;
+mod split;
+mod quick_capture;
+mod read_later;
+mod folder_sync;
+mod backup;
+mod recurrence;
+mod ordering;
+mod jobs;
+mod undo;
+mod throttle;
+mod wipe;
 mod dispatch;
+#[cfg(feature = "bench")]
+mod bench;
+#[cfg(any(feature = "bench", feature = "debug-commands"))]
+mod synthetic;
 mod schema;
 mod turtl;
 
@@ -63,7 +96,7 @@ use ::std::sync::Arc;
 use ::std::env;
 use ::std::fs;
 use ::jedi::Value;
-use ::error::TResult;
+use ::error::{TResult, TError};
 use ::fs2::FileExt;
 
 /// Init any state/logging/etc the app needs
@@ -87,14 +120,12 @@ pub fn init(config_str: String) -> TResult<()> {
     }
 
     // create our data_folder
-    let data_folder = config::get::<String>(&["data_folder"])?;
-    if data_folder != ":memory:" {
-        util::create_dir(&data_folder)
-            .map_err(|e| {
-                println!("turtl::init() -- error creating data_folder: {}: {}", data_folder, e);
-                e
-            })?;
-    }
+    let data_folder = util::paths::raw_data_folder()?;
+    util::paths::set_data_folder(&data_folder)
+        .map_err(|e| {
+            println!("turtl::init() -- error setting up data_folder: {}: {}", data_folder, e);
+            e
+        })?;
 
     // set up the logger now that we have our config and data folder set up
     match util::logger::setup_logger() {
@@ -112,12 +143,55 @@ pub fn init(config_str: String) -> TResult<()> {
     // exist before we set up logging, so this is why things are in this order
     // (in case the logger wants to use a logfile, which by default lives in the
     // data_folder).
-    if data_folder != ":memory:" {
+    if !util::paths::is_memory() {
         info!("main::init() -- created data folder: {}", data_folder);
     }
+    if util::safe_mode() {
+        warn!("main::init() -- starting in safe mode (sync and search disabled)");
+    }
     Ok(())
 }
 
+/// Lock our data folder, so two instances of the app don't end up writing to
+/// the same database. The lock lasts as long as the returned file does.
+fn lock_data_dir() -> TResult<Option<fs::File>> {
+    if util::paths::is_memory() { return Ok(None); }
+    let lockfile_path = util::paths::data_path(&["run.lock"])?.to_string_lossy().into_owned();
+    info!("main::lock_data_dir() -- locking data dir: {}", lockfile_path);
+    let lockfile = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(lockfile_path.as_str())?;
+    match lockfile.try_lock_exclusive() {
+        Ok(_) => {}
+        Err(e) => {
+            error!("main::lock_data_dir() -- cannot lock {} ...another instance of turtl is likely running", lockfile_path);
+            return Err(toterr!(e));
+        }
+    }
+    Ok(Some(lockfile))
+}
+
+/// Set up the pipeline our messages run through. Commands can take a while
+/// (network, crypto) so each one gets its own thread, up to the limits in
+/// `messaging.pipeline`.
+fn pipeline(turtl: Arc<turtl::Turtl>) -> messaging::pipeline::Pipeline {
+    let turtl_reject = turtl.clone();
+    messaging::pipeline::Pipeline::new(move |msg: Vec<u8>| {
+        match dispatch::process(&turtl, &msg) {
+            Ok(..) => {},
+            Err(e) => error!("dispatch::process() -- error processing: {}", e),
+        }
+    }, move |msg: Vec<u8>, err: TError| {
+        match dispatch::reject(turtl_reject.as_ref(), &msg, err) {
+            Ok(..) => {},
+            Err(e) => error!("dispatch::reject() -- error rejecting message: {}", e),
+        }
+    })
+}
+
 /// Start our app...spawns all our worker/helper threads, including our comm
 /// system that listens for external messages.
 ///
@@ -134,45 +208,20 @@ pub fn start() -> thread::JoinHandle<()> {
     let handle = thread::Builder::new().name(String::from("turtl-main")).spawn(move || {
         let runner = move || -> TResult<()> {
             // acquire our datadir lock
-            let data_folder = config::get::<String>(&["data_folder"])?;
-            let lockfile = if data_folder != ":memory:" {
-                let lockfile_path = format!("{}/run.lock", data_folder);
-                info!("main::start() -- locking data dir: {}", lockfile_path);
-                let lockfile = fs::OpenOptions::new()
-                    .read(true)
-                    .write(true)
-                    .create(true)
-                    .truncate(false)
-                    .open(lockfile_path.as_str())?;
-                match lockfile.try_lock_exclusive() {
-                    Ok(_) => {}
-                    Err(e) => {
-                        error!("main::start() -- cannot lock {} ...another instance of turtl is likely running", lockfile_path);
-                        return Err(toterr!(e));
-                    }
-                }
-                Some(lockfile)
-            } else {
-                None
-            };
+            let lockfile = lock_data_dir()?;
 
             // create our turtl object
             let turtl = Arc::new(turtl::Turtl::new()?);
 
             // start our messaging thread
-            let msg_res = messaging::start(move |msg: String| {
-                let turtl2 = turtl.clone();
-                // spawn a new thread for each message. this lets us process
-                // multiple messages at once without blocking.
-                let res = thread::Builder::new().name(String::from("dispatch:msg")).spawn(move || {
-                    match dispatch::process(turtl2.as_ref(), &msg) {
-                        Ok(..) => {},
-                        Err(e) => error!("dispatch::process() -- error processing: {}", e),
-                    }
-                });
-                match res {
+            let turtl_reject = turtl.clone();
+            let pipeline = pipeline(turtl);
+            let msg_res = messaging::start(move |msg: Vec<u8>| {
+                pipeline.submit(msg);
+            }, move |msg: Vec<u8>| {
+                match dispatch::reject(turtl_reject.as_ref(), &msg, TError::Cancelled(String::from("the core is shutting down"))) {
                     Ok(..) => {},
-                    Err(e) => error!("main::start() -- message processor: error spawning thread: {}", e),
+                    Err(e) => error!("dispatch::reject() -- error rejecting message: {}", e),
                 }
             });
             match msg_res {
@@ -194,6 +243,27 @@ pub fn start() -> thread::JoinHandle<()> {
     handle
 }
 
+/// Start our app without the messaging thread, for hosts that would rather
+/// call us than sit on a thread waiting for our messages (see
+/// `messaging::direct`). Messages go in with `send_direct()`, and everything
+/// we send back goes to `callback`. Stop with `app:shutdown`, same as always.
+pub fn start_direct(callback: messaging::direct::Callback) -> TResult<()> {
+    info!("main::start_direct() -- begin");
+    let lockfile = lock_data_dir()?;
+    let turtl = Arc::new(turtl::Turtl::new()?);
+    let pipeline = pipeline(turtl);
+    messaging::direct::start(move |msg: Vec<u8>| {
+        // keeps the data dir locked until direct mode stops and drops us
+        let _lock = &lockfile;
+        pipeline.submit(msg);
+    }, callback)
+}
+
+/// Hand a message to the core, when started with `start_direct()`
+pub fn send_direct(msg: Vec<u8>) -> TResult<()> {
+    messaging::direct::send(msg)
+}
+
 /// Send a message into turtl's dispatcher
 pub fn send(msg: String) -> TResult<()> {
     let channel: String = format!("{}-core-in", config::get::<String>(&["messaging", "reqres"])?);
@@ -214,7 +284,7 @@ fn recv_impl(event: bool, msg_id: Option<&str>) -> TResult<String> {
             }
         }
     };
-    let msg = carrier::recv(channel.as_str())?;
+    let msg = messaging::compress::decompress(carrier::recv(channel.as_str())?)?;
     Ok(String::from_utf8(msg)?)
 }
 
@@ -233,7 +303,7 @@ fn recv_nb_impl(event: bool, msg_id: Option<&str>) -> TResult<Option<String>> {
     };
     let msg = carrier::recv_nb(channel.as_str())?;
     let mapped = match msg {
-        Some(x) => Some(String::from_utf8(x)?),
+        Some(x) => Some(String::from_utf8(messaging::compress::decompress(x)?)?),
         None => None,
     };
     Ok(mapped)
@@ -330,6 +400,70 @@ pub mod c_api {
         }
     }
 
+    /// Start the core without its messaging thread (see `start_direct()`).
+    /// `msg_cb` gets each outgoing message, along with the channel it would
+    /// have gone out on (`<reqres>-core-out[:suffix]` for responses, `<events>`
+    /// for events). The pointers are only good until the callback returns.
+    /// Send messages in with `turtlc_send_direct()`.
+    #[no_mangle]
+    pub extern fn turtlc_start_direct(config_c: *const c_char, msg_cb: Option<extern fn(*const c_char, *const u8, usize)>) -> i32 {
+        let res = panic::catch_unwind(|| -> i32 {
+            if config_c.is_null() { return -1; }
+            let msg_cb = match msg_cb {
+                Some(x) => x,
+                None => return -1,
+            };
+            let config = match unsafe { CStr::from_ptr(config_c).to_str() } {
+                Ok(x) => x,
+                Err(e) => {
+                    cerror!("turtlc_start_direct() -- error: parsing config: {}", e);
+                    return -3;
+                },
+            };
+            match init(String::from(config)) {
+                Ok(_) => (),
+                Err(e) => {
+                    cerror!("turtlc_start_direct() -- error: init(): {}", e);
+                    return -3;
+                },
+            }
+            let callback = Box::new(move |channel: &str, msg: &[u8]| {
+                match CString::new(channel) {
+                    Ok(channel_c) => msg_cb(channel_c.as_ptr(), msg.as_ptr(), msg.len()),
+                    Err(e) => error!("turtlc_start_direct() -- bad channel: {}", e),
+                }
+            });
+            match start_direct(callback) {
+                Ok(_) => 0,
+                Err(e) => {
+                    cerror!("turtlc_start_direct() -- error: start_direct(): {}", e);
+                    -4
+                },
+            }
+        });
+        match res {
+            Ok(x) => x,
+            Err(e) => {
+                cerror!("turtlc_start_direct() -- panic: {:?}", e);
+                return -5;
+            },
+        }
+    }
+
+    /// Hand a message to a core started with `turtlc_start_direct()`
+    #[no_mangle]
+    pub extern fn turtlc_send_direct(message_bytes: *const u8, message_len: usize) -> i32 {
+        if message_bytes.is_null() { return -1; }
+        let msg = unsafe { ::std::slice::from_raw_parts(message_bytes, message_len) }.to_vec();
+        match send_direct(msg) {
+            Ok(_) => 0,
+            Err(e) => {
+                cerror!("turtlc_send_direct() -- error: {}", e);
+                -3
+            },
+        }
+    }
+
     #[no_mangle]
     pub extern fn turtlc_send(message_bytes: *const u8, message_len: usize) -> i32 {
         let channel: String = match config::get(&["messaging", "reqres"]) {
@@ -432,6 +566,82 @@ pub mod c_api {
         unsafe { CString::from_raw(lasterr) };
         0
     }
+
+    /// Lets the embedding app turn rendered notes into PDFs. `render_cb` gets
+    /// the HTML and returns a buffer it owns (setting `out_len`), which we copy
+    /// and then hand back to `free_cb`. Pass null callbacks to unset.
+    #[no_mangle]
+    pub extern fn turtlc_set_pdf_renderer(render_cb: Option<extern fn(*const u8, usize, *mut usize) -> *mut u8>, free_cb: Option<extern fn(*mut u8, usize)>) -> i32 {
+        let (render_cb, free_cb) = match (render_cb, free_cb) {
+            (Some(r), Some(f)) => (r, f),
+            _ => {
+                ::render::set_pdf_renderer(None);
+                return 0;
+            }
+        };
+        ::render::set_pdf_renderer(Some(Box::new(move |html: &String| -> ::error::TResult<Vec<u8>> {
+            let mut out_len: usize = 0;
+            let out = render_cb(html.as_ptr(), html.len(), &mut out_len);
+            if out.is_null() {
+                return TErr!(::error::TError::Msg(String::from("turtlc_set_pdf_renderer() -- renderer returned null")));
+            }
+            let pdf = unsafe { ::std::slice::from_raw_parts(out, out_len) }.to_vec();
+            free_cb(out, out_len);
+            Ok(pdf)
+        })));
+        0
+    }
+
+    /// Lets the embedding app check spelling (see `note:spellcheck`). `check_cb`
+    /// gets one word at a time and returns null if it's spelled right, or a JSON
+    /// array of suggestions in a buffer it owns (setting `out_len`), which we
+    /// copy and then hand back to `free_cb`. Pass null callbacks to unset.
+    #[no_mangle]
+    pub extern fn turtlc_set_spellchecker(check_cb: Option<extern fn(*const u8, usize, *mut usize) -> *mut u8>, free_cb: Option<extern fn(*mut u8, usize)>) -> i32 {
+        let (check_cb, free_cb) = match (check_cb, free_cb) {
+            (Some(c), Some(f)) => (c, f),
+            _ => {
+                ::spellcheck::set_spellchecker(None);
+                return 0;
+            }
+        };
+        ::spellcheck::set_spellchecker(Some(Box::new(move |word: &str| -> ::error::TResult<Option<Vec<String>>> {
+            let mut out_len: usize = 0;
+            let out = check_cb(word.as_ptr(), word.len(), &mut out_len);
+            if out.is_null() { return Ok(None); }
+            let json = unsafe { ::std::slice::from_raw_parts(out, out_len) }.to_vec();
+            free_cb(out, out_len);
+            let suggestions: Vec<String> = ::jedi::parse(&::util::decode_text(json.as_slice())?)?;
+            Ok(Some(suggestions))
+        })));
+        0
+    }
+
+    /// Lets the embedding app scan attachments for viruses. `scan_cb` gets the
+    /// file's contents and its name (empty if it doesn't have one) and returns
+    /// null if the file is clean, or the name of what it found in a buffer it
+    /// owns (setting `out_len`), which we copy and then hand back to `free_cb`.
+    /// Pass null callbacks to unset.
+    #[no_mangle]
+    pub extern fn turtlc_set_scanner(scan_cb: Option<extern fn(*const u8, usize, *const u8, usize, *mut usize) -> *mut u8>, free_cb: Option<extern fn(*mut u8, usize)>) -> i32 {
+        let (scan_cb, free_cb) = match (scan_cb, free_cb) {
+            (Some(s), Some(f)) => (s, f),
+            _ => {
+                ::scan::set_scanner(None);
+                return 0;
+            }
+        };
+        ::scan::set_scanner(Some(Box::new(move |data: &[u8], name: Option<&str>| -> ::error::TResult<Option<String>> {
+            let name = name.unwrap_or("");
+            let mut out_len: usize = 0;
+            let out = scan_cb(data.as_ptr(), data.len(), name.as_ptr(), name.len(), &mut out_len);
+            if out.is_null() { return Ok(None); }
+            let threat = unsafe { ::std::slice::from_raw_parts(out, out_len) }.to_vec();
+            free_cb(out, out_len);
+            Ok(Some(::util::decode_text(threat.as_slice())?))
+        })));
+        0
+    }
 }
 
 // -----------------------------------------------------------------------------
@@ -0,0 +1,334 @@
+//! Writes notes out as markdown files. The user picks a directory, and after
+//! every successful sync we write any notes that changed since the last run
+//! into it (and remove the files of notes that were deleted). This makes it
+//! easy to feed notes into static site generators, git repos, etc.
+//!
+//! NOTE: this writes *plaintext* note data to disk, outside of our encrypted
+//! storage. It's off until the user explicitly picks a directory.
+
+use ::std::collections::HashMap;
+use ::std::fs;
+use ::std::path::{Path, PathBuf};
+use ::jedi::{self, Value};
+use ::error::{TResult, TError};
+use ::crypto;
+use ::turtl::Turtl;
+use ::models::model::Model;
+use ::models::note::Note;
+use ::models::storable::Storable;
+use ::dispatch::registry::Registry;
+
+/// The kv key we keep our export state under
+const STATE_KEY: &'static str = "markdown_export";
+
+/// Tracks where we export to and what we've already written
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ExportState {
+    /// The directory we export into
+    pub directory: Option<String>,
+    /// The highest note `mod` we've exported so far
+    #[serde(default)]
+    pub watermark: i64,
+    /// note id -> the file we wrote it to
+    #[serde(default)]
+    pub files: HashMap<String, String>,
+}
+
+/// Quote a value for use in the front matter. A JSON string is a valid YAML
+/// string, so we let jedi do the escaping for us.
+fn yaml_str(val: &str) -> TResult<String> {
+    Ok(jedi::stringify(&Value::String(String::from(val)))?)
+}
+
+/// Turn a title into something that's safe to use in a filename
+fn slug(title: &str) -> String {
+    let mut slug = String::with_capacity(title.len());
+    let mut dash = false;
+    for c in title.chars().flat_map(|c| c.to_lowercase()) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+            dash = false;
+        } else if !dash && slug.len() > 0 {
+            slug.push('-');
+            dash = true;
+        }
+        if slug.len() >= 64 { break; }
+    }
+    String::from(slug.trim_end_matches('-'))
+}
+
+/// Build a (stable, unique) filename for a note
+pub fn note_filename(note: &Note) -> TResult<String> {
+    let note_id = note.id_or_else()?;
+    let hash = crypto::to_hex(&crypto::sha256(note_id.as_bytes())?)?;
+    let name = slug(note.title.as_ref().map(|x| x.as_str()).unwrap_or(""));
+    let name = if name == "" { String::from("note") } else { name };
+    Ok(format!("{}-{}.md", name, &hash[0..8]))
+}
+
+/// Render a note as markdown with YAML front matter
+pub fn note_to_markdown(note: &Note) -> TResult<String> {
+    let mut out = String::from("---\n");
+    out.push_str(&format!("id: {}\n", yaml_str(&note.id_or_else()?)?));
+    if let Some(ref title) = note.title {
+        out.push_str(&format!("title: {}\n", yaml_str(title)?));
+    }
+    if let Some(ref board_id) = note.board_id {
+        out.push_str(&format!("board_id: {}\n", yaml_str(board_id)?));
+    }
+    if let Some(ref url) = note.url {
+        out.push_str(&format!("url: {}\n", yaml_str(url)?));
+    }
+    if let Some(ref tags) = note.tags {
+        if tags.len() > 0 {
+            out.push_str("tags:\n");
+            for tag in tags {
+                out.push_str(&format!("  - {}\n", yaml_str(tag)?));
+            }
+        }
+    }
+    if let Some(ref file) = note.file {
+        if let Some(ref name) = file.name {
+            out.push_str(&format!("file: {}\n", yaml_str(name)?));
+        }
+    }
+    if let Some(modified) = note.mod_ {
+        out.push_str(&format!("mod: {}\n", modified));
+    }
+    out.push_str("---\n\n");
+    if let Some(ref text) = note.text {
+        out.push_str(text);
+        if !text.ends_with("\n") { out.push('\n'); }
+    }
+    Ok(out)
+}
+
+/// The pieces of a note we can read back out of a markdown file
+#[derive(Debug, Default, PartialEq)]
+pub struct ParsedMarkdown {
+    pub id: Option<String>,
+    pub title: Option<String>,
+    pub url: Option<String>,
+    pub tags: Vec<String>,
+    pub text: String,
+}
+
+/// Read a front matter value, which is either a JSON-style quoted string (what
+/// we write) or a bare string (what people type).
+fn front_matter_value(val: &str) -> String {
+    let val = val.trim();
+    if val.starts_with('"') {
+        if let Ok(x) = jedi::parse::<String>(&String::from(val)) {
+            return x;
+        }
+    }
+    String::from(val)
+}
+
+/// Parse a markdown file (as written by `note_to_markdown()`, or by hand) back
+/// into note fields. Front matter is optional, and any keys we don't know about
+/// are ignored.
+pub fn parse_markdown(md: &str) -> ParsedMarkdown {
+    let mut parsed = ParsedMarkdown::default();
+    let md = md.replace("\r\n", "\n");
+    let body = if md.starts_with("---\n") {
+        match md[4..].find("\n---\n") {
+            Some(end) => {
+                let mut in_tags = false;
+                for line in md[4..(4 + end)].lines() {
+                    if in_tags && line.trim_start().starts_with("- ") {
+                        parsed.tags.push(front_matter_value(&line.trim_start()[2..]));
+                        continue;
+                    }
+                    in_tags = false;
+                    let idx = match line.find(':') {
+                        Some(x) => x,
+                        None => continue,
+                    };
+                    let val = &line[(idx + 1)..];
+                    match line[0..idx].trim() {
+                        "id" => parsed.id = Some(front_matter_value(val)),
+                        "title" => parsed.title = Some(front_matter_value(val)),
+                        "url" => parsed.url = Some(front_matter_value(val)),
+                        "tags" => in_tags = true,
+                        _ => {}
+                    }
+                }
+                String::from(&md[(4 + end + 5)..])
+            }
+            None => md.clone(),
+        }
+    } else {
+        md.clone()
+    };
+    let body = if body.starts_with("\n") { &body[1..] } else { &body[..] };
+    let body = if body.ends_with("\n") { &body[0..(body.len() - 1)] } else { body };
+    parsed.text = String::from(body);
+    parsed
+}
+
+/// Write a file by way of a temp file so readers never see half a note
+pub fn write_atomic(path: &Path, contents: &str) -> TResult<()> {
+    let mut tmp = PathBuf::from(path);
+    tmp.set_extension("md.tmp");
+    fs::write(&tmp, contents.as_bytes())?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+pub fn get_state(turtl: &Turtl) -> TResult<ExportState> {
+    let state = with_db!{ db, turtl.db, db.kv_get(STATE_KEY)? };
+    match state {
+        Some(x) => Ok(jedi::parse(&x)?),
+        None => Ok(ExportState::default()),
+    }
+}
+
+fn save_state(turtl: &Turtl, state: &ExportState) -> TResult<()> {
+    let serialized = jedi::stringify(state)?;
+    with_db!{ db, turtl.db, db.kv_set(STATE_KEY, &serialized)? };
+    Ok(())
+}
+
+/// Set (or clear) the directory we export to. Setting a directory kicks off a
+/// full export.
+pub fn set_directory(turtl: &Turtl, directory: Option<String>) -> TResult<u64> {
+    let mut state = ExportState::default();
+    match directory {
+        Some(directory) => {
+            fs::create_dir_all(&directory)?;
+            if !Path::new(&directory).is_dir() {
+                return TErr!(TError::BadValue(format!("{} isn't a directory", directory)));
+            }
+            state.directory = Some(directory);
+            save_state(turtl, &state)?;
+            run(turtl)
+        }
+        None => {
+            save_state(turtl, &state)?;
+            Ok(0)
+        }
+    }
+}
+
+/// Export everything that changed since our last run. Returns how many notes
+/// were written.
+pub fn run(turtl: &Turtl) -> TResult<u64> {
+    let mut state = get_state(turtl)?;
+    let directory = match state.directory.as_ref() {
+        Some(x) => PathBuf::from(x),
+        None => return Ok(0),
+    };
+    // only look at public fields here so we don't have to decrypt notes that
+    // haven't changed
+    let raw = with_db!{ db, turtl.db, db.all_values(Note::tablename())? };
+    let mut live: HashMap<String, i64> = HashMap::with_capacity(raw.len());
+    for val in raw {
+        let id: String = match jedi::get_opt(&["id"], &val) {
+            Some(x) => x,
+            None => continue,
+        };
+        live.insert(id, jedi::get_opt(&["mod"], &val).unwrap_or(0));
+    }
+
+    // clean up after deleted notes
+    let deleted = state.files.keys()
+        .filter(|id| !live.contains_key(*id))
+        .map(|x| x.clone())
+        .collect::<Vec<_>>();
+    for note_id in deleted {
+        if let Some(filename) = state.files.remove(&note_id) {
+            match fs::remove_file(directory.join(&filename)) {
+                Ok(_) => {}
+                Err(e) => warn!("markdown::run() -- error removing {}: {}", filename, e),
+            }
+        }
+    }
+
+    let changed = live.iter()
+        .filter(|&(id, modified)| *modified > state.watermark || !state.files.contains_key(id))
+        .map(|(id, _)| id.clone())
+        .collect::<Vec<_>>();
+    let mut written = 0;
+    for note in turtl.load_notes(&changed)? {
+        let note_id = note.id_or_else()?;
+        let filename = note_filename(&note)?;
+        write_atomic(&directory.join(&filename), &note_to_markdown(&note)?)?;
+        // the title changed, so the old file has to go
+        if let Some(old) = state.files.insert(note_id, filename.clone()) {
+            if old != filename {
+                match fs::remove_file(directory.join(&old)) {
+                    Ok(_) => {}
+                    Err(e) => warn!("markdown::run() -- error removing {}: {}", old, e),
+                }
+            }
+        }
+        written += 1;
+    }
+    state.watermark = live.values().fold(state.watermark, |acc, x| if *x > acc { *x } else { acc });
+    save_state(turtl, &state)?;
+    if written > 0 {
+        info!("markdown::run() -- exported {} notes to {:?}", written, directory);
+    }
+    Ok(written)
+}
+
+/// Run our export after a sync, logging (not returning) errors. A broken export
+/// directory shouldn't break syncing.
+pub fn after_sync(turtl: &Turtl) {
+    match run(turtl) {
+        Ok(_) => {}
+        Err(e) => error!("markdown::after_sync() -- error exporting notes: {}", e),
+    }
+}
+
+/// Registers our markdown export commands
+pub fn register(reg: &mut Registry) {
+    reg.add("profile:markdown-export:set-directory", |turtl, args| {
+        let directory: Option<String> = args.get_opt(2);
+        let written = set_directory(turtl, directory)?;
+        Ok(json!({"written": written}))
+    });
+    reg.add("profile:markdown-export:get", |turtl, _args| {
+        let state = get_state(turtl)?;
+        Ok(json!({"directory": state.directory, "exported": state.files.len()}))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_markdown() {
+        let mut note = Note::new();
+        note.id = Some(String::from("1234"));
+        note.title = Some(String::from("Hello: \"world\""));
+        note.tags = Some(vec![String::from("a"), String::from("b")]);
+        note.text = Some(String::from("# hi"));
+        note.mod_ = Some(69);
+        let md = note_to_markdown(&note).unwrap();
+        assert_eq!(md, "---\nid: \"1234\"\ntitle: \"Hello: \\\"world\\\"\"\ntags:\n  - \"a\"\n  - \"b\"\nmod: 69\n---\n\n# hi\n");
+        let filename = note_filename(&note).unwrap();
+        assert!(filename.starts_with("hello-world-"));
+        assert!(filename.ends_with(".md"));
+        assert_eq!(slug("  ...  "), "");
+
+        let parsed = parse_markdown(&md);
+        assert_eq!(parsed.id, Some(String::from("1234")));
+        assert_eq!(parsed.title, Some(String::from("Hello: \"world\"")));
+        assert_eq!(parsed.tags, vec!["a", "b"]);
+        assert_eq!(parsed.text, "# hi");
+    }
+
+    #[test]
+    fn parses_hand_written_markdown() {
+        let parsed = parse_markdown("---\r\ntitle: my note\r\ntags:\r\n- work\r\n---\r\n\r\nhello\r\nthere\r\n");
+        assert_eq!(parsed.title, Some(String::from("my note")));
+        assert_eq!(parsed.tags, vec!["work"]);
+        assert_eq!(parsed.text, "hello\nthere");
+        let parsed = parse_markdown("just some text");
+        assert_eq!(parsed.id, None);
+        assert_eq!(parsed.text, "just some text");
+    }
+}
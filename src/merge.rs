@@ -0,0 +1,350 @@
+//! Helps the user put a note back together when two edits collide. Given the
+//! version both edits started from (base), ours (mine), and the one that came
+//! in (theirs), we do a line-based three-way merge. Changes that don't overlap
+//! merge cleanly; the ones that do get git-style conflict markers so the user
+//! can sort them out in the editor and hand us the result.
+//!
+//! This is also where whole notes get merged (`profile:merge-notes`): the
+//! oldest note soaks up the others' text, tags, and attachment, and the others
+//! get deleted, all as a single change that `edit:undo` can take back.
+
+use ::std::cmp;
+use ::jedi::{self, Value};
+use ::error::{TResult, TError};
+use ::turtl::Turtl;
+use ::models::model::{self, Model};
+use ::models::protected::Protected;
+use ::models::note::Note;
+use ::models::file::FileData;
+use ::models::board::Board;
+use ::models::sync_record::{SyncAction, SyncType};
+use ::sync::sync_model;
+use ::lib_permissions::Permission;
+use ::undo::{self, Op, UndoEntry};
+use ::dispatch::registry::Registry;
+
+const MARKER_MINE: &'static str = "<<<<<<< mine";
+const MARKER_BASE: &'static str = "||||||| base";
+const MARKER_SPLIT: &'static str = "=======";
+const MARKER_THEIRS: &'static str = ">>>>>>> theirs";
+
+/// Goes between the bodies of merged notes
+const NOTE_SEPARATOR: &'static str = "\n\n---\n\n";
+
+/// What a merge came out to
+#[derive(Serialize, Debug, PartialEq)]
+pub struct MergeResult {
+    /// The merged text, with conflict markers if there were conflicts
+    pub text: String,
+    /// How many conflicting sections we couldn't merge
+    pub conflicts: u64,
+}
+
+/// Map each line of `base` to the line in `other` it lines up with (if it
+/// survived), using the longest common subsequence. We trim the common
+/// start/end first, since most edits touch a small part of a note.
+fn match_lines(base: &[&str], other: &[&str]) -> Vec<Option<usize>> {
+    let mut matched = vec![None; base.len()];
+    let mut pre = 0;
+    while pre < base.len() && pre < other.len() && base[pre] == other[pre] {
+        matched[pre] = Some(pre);
+        pre += 1;
+    }
+    let mut suf = 0;
+    while suf < base.len() - pre && suf < other.len() - pre && base[base.len() - 1 - suf] == other[other.len() - 1 - suf] {
+        matched[base.len() - 1 - suf] = Some(other.len() - 1 - suf);
+        suf += 1;
+    }
+    let base_mid = &base[pre..(base.len() - suf)];
+    let other_mid = &other[pre..(other.len() - suf)];
+    let (n, m) = (base_mid.len(), other_mid.len());
+    let mut lcs = vec![vec![0u32; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if base_mid[i] == other_mid[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                cmp::max(lcs[i + 1][j], lcs[i][j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if base_mid[i] == other_mid[j] {
+            matched[pre + i] = Some(pre + j);
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    matched
+}
+
+/// Three-way merge `mine` and `theirs`, starting from `base`
+pub fn merge3(base: &str, mine: &str, theirs: &str) -> MergeResult {
+    let base_lines = base.split('\n').collect::<Vec<_>>();
+    let mine_lines = mine.split('\n').collect::<Vec<_>>();
+    let theirs_lines = theirs.split('\n').collect::<Vec<_>>();
+    let in_mine = match_lines(&base_lines, &mine_lines);
+    let in_theirs = match_lines(&base_lines, &theirs_lines);
+
+    let mut out: Vec<&str> = Vec::with_capacity(cmp::max(mine_lines.len(), theirs_lines.len()));
+    let mut conflicts = 0;
+    let (mut i, mut a, mut b) = (0, 0, 0);
+    loop {
+        // find the next base line that both sides left alone. everything
+        // between here and there is a chunk one (or both) sides changed.
+        let stable = (i..base_lines.len())
+            .filter_map(|j| match (in_mine[j], in_theirs[j]) {
+                (Some(x), Some(y)) => Some((j, x, y)),
+                _ => None,
+            })
+            .next();
+        let (base_end, mine_end, theirs_end) = stable.unwrap_or((base_lines.len(), mine_lines.len(), theirs_lines.len()));
+        let base_chunk = &base_lines[i..base_end];
+        let mine_chunk = &mine_lines[a..mine_end];
+        let theirs_chunk = &theirs_lines[b..theirs_end];
+        if mine_chunk == base_chunk {
+            out.extend(theirs_chunk);
+        } else if theirs_chunk == base_chunk || mine_chunk == theirs_chunk {
+            out.extend(mine_chunk);
+        } else {
+            conflicts += 1;
+            out.push(MARKER_MINE);
+            out.extend(mine_chunk);
+            out.push(MARKER_BASE);
+            out.extend(base_chunk);
+            out.push(MARKER_SPLIT);
+            out.extend(theirs_chunk);
+            out.push(MARKER_THEIRS);
+        }
+        match stable {
+            Some(_) => {
+                out.push(base_lines[base_end]);
+                i = base_end + 1;
+                a = mine_end + 1;
+                b = theirs_end + 1;
+            }
+            None => break,
+        }
+    }
+    MergeResult {
+        text: out.join("\n"),
+        conflicts: conflicts,
+    }
+}
+
+/// Whether the text still has conflict markers in it
+pub fn has_markers(text: &str) -> bool {
+    text.split('\n').any(|x| x == MARKER_MINE || x == MARKER_THEIRS)
+}
+
+/// Merge the user's version of a note's text with whatever the note has now.
+/// If `theirs` is passed in, we use that instead of the note's current text.
+pub fn preview(turtl: &Turtl, note_id: &String, base: &String, mine: &String, theirs: Option<String>) -> TResult<MergeResult> {
+    let theirs = match theirs {
+        Some(x) => x,
+        None => {
+            let note = match turtl.load_notes(&vec![note_id.clone()])?.pop() {
+                Some(x) => x,
+                None => return TErr!(TError::NotFound(format!("note {} wasn't found", note_id))),
+            };
+            note.text.unwrap_or(String::new())
+        }
+    };
+    Ok(merge3(base.as_str(), mine.as_str(), theirs.as_str()))
+}
+
+/// Save the user's resolved text to the note. Refuses text that still has
+/// conflict markers unless `force` is set.
+pub fn apply(turtl: &Turtl, note_id: &String, text: String, force: bool) -> TResult<Value> {
+    if !force && has_markers(text.as_str()) {
+        return TErr!(TError::BadValue(String::from("the merged text still has conflict markers")));
+    }
+    let mut note = match turtl.load_notes(&vec![note_id.clone()])?.pop() {
+        Some(x) => x,
+        None => return TErr!(TError::NotFound(format!("note {} wasn't found", note_id))),
+    };
+    Board::permission_check(turtl, &note.space_id, note.board_id.as_ref(), &Permission::EditNote)?;
+    note.text = Some(text);
+    note.touch();
+    sync_model::save_model(SyncAction::Edit, turtl, &mut note, false)
+}
+
+/// Stitch note bodies together (in order), skipping empty ones
+fn join_bodies(bodies: &[Option<&String>]) -> String {
+    bodies.iter()
+        .filter_map(|x| *x)
+        .filter(|x| x.trim() != "")
+        .map(|x| x.trim_right())
+        .collect::<Vec<_>>()
+        .join(NOTE_SEPARATOR)
+}
+
+/// Every tag from every note, in the order we first saw it
+fn union_tags(tags: &[Option<&Vec<String>>]) -> Vec<String> {
+    let mut all: Vec<String> = Vec::new();
+    for tag in tags.iter().filter_map(|x| *x).flat_map(|x| x.iter()) {
+        if !all.contains(tag) { all.push(tag.clone()); }
+    }
+    all
+}
+
+/// Merge the given notes (in order) into one. The oldest note is the one that
+/// survives, so the merged note keeps the earliest creation time (and its
+/// space/board). It gets the first note's title, everyone's text and tags, and
+/// whichever attachment one of them had. Notes hold a single attachment, so we
+/// refuse to merge notes where more than one has a file.
+pub fn merge_notes(turtl: &Turtl, note_ids: &Vec<String>) -> TResult<Value> {
+    if note_ids.len() < 2 {
+        return TErr!(TError::BadValue(String::from("need at least two notes to merge")));
+    }
+    for (i, id) in note_ids.iter().enumerate() {
+        if note_ids[0..i].contains(id) {
+            return TErr!(TError::BadValue(format!("note {} is in the list twice", id)));
+        }
+    }
+    let mut loaded = turtl.load_notes(note_ids)?;
+    let mut notes: Vec<Note> = Vec::with_capacity(note_ids.len());
+    for id in note_ids {
+        match loaded.iter().position(|x| x.id() == Some(id)) {
+            Some(idx) => notes.push(loaded.remove(idx)),
+            None => return TErr!(TError::NotFound(format!("note {} wasn't found", id))),
+        }
+    }
+    let mut keep_idx = 0;
+    let mut oldest = model::id_timestamp(&note_ids[0])?;
+    for (i, id) in note_ids.iter().enumerate().skip(1) {
+        let created = model::id_timestamp(id)?;
+        if created < oldest {
+            oldest = created;
+            keep_idx = i;
+        }
+    }
+    let with_files = notes.iter().filter(|x| x.file.is_some()).count();
+    if with_files > 1 {
+        return TErr!(TError::BadValue(format!("{} of these notes have attachments, but a note can only hold one", with_files)));
+    }
+    for (i, note) in notes.iter().enumerate() {
+        let permission = if i == keep_idx { Permission::EditNote } else { Permission::DeleteNote };
+        Board::permission_check(turtl, &note.space_id, note.board_id.as_ref(), &permission)?;
+    }
+
+    let title = notes[0].title.clone();
+    let text = join_bodies(&notes.iter().map(|x| x.text.as_ref()).collect::<Vec<_>>());
+    let tags = union_tags(&notes.iter().map(|x| x.tags.as_ref()).collect::<Vec<_>>());
+    // grab the attachment before we touch anything, so a bad file can't leave
+    // us halfway through
+    let moved_file = match notes.iter().enumerate().find(|&(i, x)| i != keep_idx && x.file.is_some()) {
+        Some((_, source)) => Some((source.file.as_ref().unwrap().clone()?, FileData::load_file(turtl, source)?)),
+        None => None,
+    };
+    let mut sources = notes;
+    let mut note = sources.remove(keep_idx);
+    let mut before = note.data()?;
+    let _ = jedi::remove(&["body"], &mut before);
+    let mut undo_ops = vec![Op::new(SyncAction::Edit, SyncType::Note, before)];
+    let mut redo_ops = Vec::with_capacity(sources.len() + 1);
+
+    note.title = title;
+    note.text = if text == "" { None } else { Some(text) };
+    note.tags = if tags.len() == 0 { None } else { Some(tags) };
+    note.touch();
+    if let Some((ref file, _)) = moved_file {
+        note.file = Some(file.clone()?);
+        // the server manages this one
+        note.has_file = false;
+    }
+    let mut after = sync_model::save_model(SyncAction::Edit, turtl, &mut note, false)?;
+    if let Some((_, data)) = moved_file {
+        let mut filedata = FileData::default();
+        filedata.data = Some(data);
+        filedata.save(turtl, &mut note)?;
+    }
+    let _ = jedi::remove(&["body"], &mut after);
+    redo_ops.push(Op::new(SyncAction::Edit, SyncType::Note, after.clone()));
+
+    // if a delete fails, whatever we already did still goes on the undo stack
+    // so the user can take it back
+    let mut res = Ok(());
+    for source in &sources {
+        let id = source.id_or_else()?;
+        let mut data = source.data()?;
+        let _ = jedi::remove(&["body"], &mut data);
+        match sync_model::delete_model::<Note>(turtl, &id, false) {
+            Ok(_) => {
+                undo_ops.push(Op::new(SyncAction::Add, SyncType::Note, data));
+                redo_ops.push(Op::new(SyncAction::Delete, SyncType::Note, json!({"id": id})));
+            }
+            Err(e) => {
+                res = Err(e);
+                break;
+            }
+        }
+    }
+    undo::record(UndoEntry {
+        label: String::from("merge notes"),
+        undo: undo_ops,
+        redo: redo_ops,
+    });
+    res?;
+    Ok(after)
+}
+
+/// Registers our merge commands
+pub fn register(reg: &mut Registry) {
+    reg.add("note:merge-preview", |turtl, args| {
+        let note_id: String = args.get(2)?;
+        let base: String = args.get_in(&["3", "base"])?;
+        let mine: String = args.get_in(&["3", "mine"])?;
+        let theirs: Option<String> = args.get_in_opt(&["3", "theirs"]);
+        Ok(jedi::to_val(&preview(turtl, &note_id, &base, &mine, theirs)?)?)
+    });
+    reg.add("note:merge-apply", |turtl, args| {
+        let note_id: String = args.get(2)?;
+        let text: String = args.get(3)?;
+        let force: bool = args.get_opt(4).unwrap_or(false);
+        apply(turtl, &note_id, text, force)
+    });
+    reg.add("profile:merge-notes", |turtl, args| {
+        let note_ids: Vec<String> = args.get(2)?;
+        merge_notes(turtl, &note_ids)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merges_three_ways() {
+        let base = "title\none\ntwo\nthree\n";
+        // edits in different places merge cleanly
+        let res = merge3(base, "title\nONE\ntwo\nthree\n", "title\none\ntwo\nthree\nfour\n");
+        assert_eq!(res, MergeResult { text: String::from("title\nONE\ntwo\nthree\nfour\n"), conflicts: 0 });
+        // the same edit on both sides is fine too
+        let res = merge3(base, "title\none\n2\nthree\n", "title\none\n2\nthree\n");
+        assert_eq!(res.text, "title\none\n2\nthree\n");
+        assert_eq!(res.conflicts, 0);
+
+        let res = merge3(base, "title\none\nTWO (mine)\nthree\n", "title\none\nTWO (theirs)\nthree\n");
+        assert_eq!(res.conflicts, 1);
+        assert_eq!(res.text, "title\none\n<<<<<<< mine\nTWO (mine)\n||||||| base\ntwo\n=======\nTWO (theirs)\n>>>>>>> theirs\nthree\n");
+        assert!(has_markers(&res.text));
+        assert!(!has_markers(base));
+    }
+
+    #[test]
+    fn combines_notes() {
+        let (a, b, c) = (String::from("first\n"), String::from("  "), String::from("third"));
+        assert_eq!(join_bodies(&[Some(&a), None, Some(&b), Some(&c)]), "first\n\n---\n\nthird");
+        assert_eq!(join_bodies(&[None, Some(&b)]), "");
+
+        let t1 = vec![String::from("work"), String::from("todo")];
+        let t2 = vec![String::from("todo"), String::from("home")];
+        assert_eq!(union_tags(&[Some(&t1), None, Some(&t2)]), vec!["work", "todo", "home"]);
+    }
+}
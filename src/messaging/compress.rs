@@ -0,0 +1,100 @@
+//! Compression for big messages. Responses for large profiles can run to
+//! several megabytes of JSON, so anything over `messaging.compression.threshold`
+//! bytes gets compressed (if `messaging.compression.encoding` is set) and sent
+//! with a small header in front of it:
+//!
+//!     0x00 'T' 'Z' <encoding>  <compressed message>
+//!
+//! where `<encoding>` is 1 for gzip. Neither JSON nor our MessagePack messages
+//! can start with a zero byte, so UIs can check the first byte to see whether
+//! they need to decompress. UIs can compress their requests the same way.
+
+use ::std::io::{Read, Write};
+use ::flate2::Compression;
+use ::flate2::write::GzEncoder;
+use ::flate2::read::GzDecoder;
+use ::config;
+use ::error::{TResult, TError};
+
+/// What compressed messages start with (followed by the encoding byte)
+const MAGIC: &'static [u8] = b"\x00TZ";
+
+/// The gzip encoding byte
+const GZIP: u8 = 1;
+
+/// The zstd encoding byte. Reserved, but not supported by this build.
+const ZSTD: u8 = 2;
+
+/// Grab the encoding byte from our config (None if we're not compressing)
+fn encoding() -> TResult<Option<u8>> {
+    let name: String = config::get(&["messaging", "compression", "encoding"]).unwrap_or(String::from("none"));
+    match name.as_str() {
+        "none" => Ok(None),
+        "gzip" => Ok(Some(GZIP)),
+        _ => TErr!(TError::BadValue(format!("unsupported messaging.compression.encoding: {}", name))),
+    }
+}
+
+/// Messages smaller than this (in bytes) go out as-is
+fn threshold() -> usize {
+    config::get(&["messaging", "compression", "threshold"]).unwrap_or(262144)
+}
+
+/// Whether a message has our compression header
+pub fn is_compressed(msg: &[u8]) -> bool {
+    msg.len() > MAGIC.len() && msg.starts_with(MAGIC)
+}
+
+/// Compress a message, if compression is on and the message is big enough to
+/// bother with
+pub fn compress(msg: Vec<u8>) -> TResult<Vec<u8>> {
+    if msg.len() < threshold() { return Ok(msg); }
+    let encoding = match encoding()? {
+        Some(x) => x,
+        None => return Ok(msg),
+    };
+    let mut out = MAGIC.to_vec();
+    out.push(encoding);
+    let mut encoder = GzEncoder::new(out, Compression::fast());
+    encoder.write_all(msg.as_slice())?;
+    let out = encoder.finish()?;
+    // some things (already-compressed file data) don't get any smaller
+    if out.len() >= msg.len() { return Ok(msg); }
+    Ok(out)
+}
+
+/// Decompress a message if it has our header, otherwise hand it back as-is
+pub fn decompress(msg: Vec<u8>) -> TResult<Vec<u8>> {
+    if !is_compressed(&msg) { return Ok(msg); }
+    let body = &msg[(MAGIC.len() + 1)..];
+    match msg[MAGIC.len()] {
+        GZIP => {
+            let mut out = Vec::new();
+            GzDecoder::new(body).read_to_end(&mut out)?;
+            Ok(out)
+        }
+        ZSTD => TErr!(TError::NotImplemented),
+        x => TErr!(TError::BadValue(format!("unknown message encoding: {}", x))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compresses_big_messages() {
+        config::set(&["messaging", "compression", "encoding"], &String::from("gzip")).unwrap();
+        config::set(&["messaging", "compression", "threshold"], &1024).unwrap();
+        let small = Vec::from(&br#"["1","ping"]"#[..]);
+        assert_eq!(compress(small.clone()).unwrap(), small);
+        let big = format!(r#"{{"id":"2","e":0,"d":"{}"}}"#, "turtl ".repeat(1000)).into_bytes();
+        let packed = compress(big.clone()).unwrap();
+        assert!(is_compressed(&packed));
+        assert!(packed.len() < big.len());
+        assert_eq!(decompress(packed).unwrap(), big);
+        assert_eq!(decompress(small.clone()).unwrap(), small);
+        assert!(decompress(vec![0, b'T', b'Z', 9, 1, 2, 3]).is_err());
+        config::set(&["messaging", "compression", "encoding"], &String::from("none")).unwrap();
+    }
+}
@@ -0,0 +1,89 @@
+//! Direct mode, for hosts that would rather call into the core than run a loop
+//! on our carrier channels (iOS, say, where background threads get cut off).
+//! The host hands us each message with `send()`, and everything we send out
+//! (responses and events) goes straight to a callback it gave us. Nothing sits
+//! in a channel waiting to be picked up, so the host doesn't need a thread
+//! blocking on `turtlc_recv()`.
+//!
+//! Messages the core sends to itself (`messaging::app_event()`, shutdown) are
+//! routed through here too while direct mode is on.
+
+use ::std::sync::{Arc, RwLock};
+use ::error::{TResult, TError};
+use ::messaging::{self, Transport, SHUTDOWN_MSG, compress};
+
+/// Gets each outgoing message, along with the channel it would have gone out
+/// on (which tells responses and events apart)
+pub type Callback = Box<Fn(&str, &[u8]) + Send + Sync>;
+
+/// Handles an incoming message
+type Process = Arc<Fn(Vec<u8>) + Send + Sync>;
+
+lazy_static! {
+    /// Where incoming messages go, while we're in direct mode
+    static ref PROCESS: RwLock<Option<Process>> = RwLock::new(None);
+}
+
+/// Hands everything we send out to the host's callback
+pub struct CallbackTransport {
+    callback: Callback,
+}
+
+impl CallbackTransport {
+    pub fn new(callback: Callback) -> CallbackTransport {
+        CallbackTransport { callback: callback }
+    }
+}
+
+impl Transport for CallbackTransport {
+    fn send(&self, channel: &str, msg: Vec<u8>) -> TResult<()> {
+        (self.callback)(channel, msg.as_slice());
+        Ok(())
+    }
+}
+
+/// Whether we're in direct mode
+pub fn running() -> bool {
+    lockr!(*PROCESS).is_some()
+}
+
+/// Start direct mode. `process` gets each incoming message (and is dropped
+/// once we stop, so it can own whatever needs to live as long as the app),
+/// and `callback` gets each outgoing one.
+pub fn start<F>(process: F, callback: Callback) -> TResult<()>
+    where F: Fn(Vec<u8>) + Send + Sync + 'static
+{
+    if running() {
+        return TErr!(TError::BadValue(String::from("direct mode is already running")));
+    }
+    messaging::set_transport(Arc::new(CallbackTransport::new(callback)));
+    let process: Process = Arc::new(process);
+    *lockw!(*PROCESS) = Some(process);
+    info!("messaging::direct::start() -- taking messages directly");
+    messaging::ui_event("messaging:ready", &true)
+}
+
+/// Hand a message to the core
+pub fn send(msg: Vec<u8>) -> TResult<()> {
+    let process = match lockr!(*PROCESS).clone() {
+        Some(x) => x,
+        None => return TErr!(TError::MissingData(String::from("direct mode isn't running"))),
+    };
+    let msg = compress::decompress(msg)?;
+    if msg.as_slice() == SHUTDOWN_MSG.as_bytes() {
+        stop();
+        return Ok(());
+    }
+    process(msg);
+    Ok(())
+}
+
+/// Leave direct mode. The host's callback stays in place, so responses to
+/// anything still running (including whatever asked us to stop) reach it.
+pub fn stop() {
+    let process = lockw!(*PROCESS).take();
+    if process.is_none() { return; }
+    info!("messaging::direct::stop() -- shutting down");
+    // dropped here, outside of our lock
+    drop(process);
+}
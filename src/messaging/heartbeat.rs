@@ -0,0 +1,136 @@
+//! Heartbeats, so a UI can tell a busy core from a hung (or dead) one.
+//!
+//! Every `messaging.heartbeat.interval` ms a ticker thread drops a heartbeat
+//! into our incoming channel. When the messaging loop gets to it, we send a
+//! `core:heartbeat` event out, so heartbeats only keep coming while the loop
+//! itself is alive and turning over. UIs that want a round trip through
+//! dispatch can send `["<message id>", "ping", <their timestamp>]`, which
+//! echoes the timestamp back along with ours.
+//!
+//! Each heartbeat also looks at how many messages are sitting unread in our
+//! outgoing channels. If the UI stops reading, they pile up, and once a channel
+//! has `messaging.heartbeat.backlog_warning` or more waiting we send a
+//! `messaging:remote-stalled` event (and `messaging:remote-resumed` once it has
+//! caught back up).
+
+use ::std::thread;
+use ::std::time::Duration;
+use ::std::collections::HashSet;
+use ::std::sync::Mutex;
+use ::std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use ::time;
+use ::jedi;
+use ::config;
+use ::error::TResult;
+use ::messaging::{self, Messenger};
+
+/// What the ticker sends the messaging loop when it's time for a heartbeat
+pub const HEARTBEAT_MSG: &'static str = "turtl:internal:msg:heartbeat";
+
+lazy_static! {
+    /// Bumped each time the ticker is started/stopped, so an old ticker knows
+    /// to quit
+    static ref GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+    /// Whether a heartbeat is waiting on the messaging loop. A stuck loop
+    /// shouldn't get buried in them.
+    static ref QUEUED: AtomicBool = AtomicBool::new(false);
+
+    /// How many heartbeats we've sent
+    static ref SEQ: AtomicUsize = AtomicUsize::new(0);
+
+    /// Channels we've warned the UI about
+    static ref STALLED: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
+
+/// What goes out with each heartbeat
+#[derive(Serialize, Debug)]
+struct Heartbeat {
+    seq: usize,
+    /// Our clock (unix ms)
+    t: i64,
+}
+
+/// The current time, in unix ms
+pub fn now() -> i64 {
+    let ts = time::get_time();
+    (ts.sec * 1000) + (ts.nsec / 1000000) as i64
+}
+
+/// How many heartbeats we've sent so far
+pub fn seq() -> usize {
+    SEQ.load(Ordering::SeqCst)
+}
+
+/// Start sending heartbeats (if they're turned on)
+pub fn start() {
+    let interval: u64 = config::get(&["messaging", "heartbeat", "interval"]).unwrap_or(5000);
+    let generation = GENERATION.fetch_add(1, Ordering::SeqCst) + 1;
+    if interval == 0 { return; }
+    QUEUED.store(false, Ordering::SeqCst);
+    let res = thread::Builder::new().name(String::from("messaging:heartbeat")).spawn(move || {
+        loop {
+            thread::sleep(Duration::from_millis(interval));
+            if GENERATION.load(Ordering::SeqCst) != generation { break; }
+            // the last one hasn't been picked up yet, so the loop is busy (or
+            // stuck). either way, the UI will notice the gap.
+            if QUEUED.swap(true, Ordering::SeqCst) { continue; }
+            match Messenger::new().send_rev(String::from(HEARTBEAT_MSG)) {
+                Ok(_) => {}
+                Err(e) => {
+                    QUEUED.store(false, Ordering::SeqCst);
+                    warn!("messaging::heartbeat -- problem queuing heartbeat: {}", e);
+                }
+            }
+        }
+    });
+    match res {
+        Ok(_) => {}
+        Err(e) => error!("messaging::heartbeat::start() -- error spawning ticker: {}", e),
+    }
+}
+
+/// Stop sending heartbeats
+pub fn stop() {
+    GENERATION.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Send a heartbeat out and check that the UI is keeping up with us. Called by
+/// the messaging loop.
+pub fn beat(messenger: &Messenger) -> TResult<()> {
+    QUEUED.store(false, Ordering::SeqCst);
+    let heartbeat = Heartbeat {
+        seq: SEQ.fetch_add(1, Ordering::SeqCst) + 1,
+        t: now(),
+    };
+    check_backlog(messenger)?;
+    Messenger::event("core:heartbeat", jedi::to_val(&heartbeat)?)
+}
+
+/// Warn the UI about any outgoing channels it has stopped reading
+fn check_backlog(messenger: &Messenger) -> TResult<()> {
+    let limit: u32 = config::get(&["messaging", "heartbeat", "backlog_warning"]).unwrap_or(100);
+    let events: String = config::get(&["messaging", "events"])?;
+    let mut channels = vec![messenger.channel_out.clone(), events.clone()];
+    for client in lockr!(*messaging::CLIENTS).iter() {
+        channels.push(format!("{}:{}", events, client));
+    }
+    let transport = messaging::transport();
+    for channel in channels {
+        let pending = match transport.backlog(&channel) {
+            Some(x) => x,
+            None => continue,
+        };
+        let stalled = lock!(*STALLED).contains(&channel);
+        if pending >= limit && !stalled {
+            warn!("messaging::heartbeat -- {} has {} unread messages. is the UI still reading?", channel, pending);
+            lock!(*STALLED).insert(channel.clone());
+            Messenger::event("messaging:remote-stalled", json!({"channel": channel, "pending": pending}))?;
+        } else if pending < limit && stalled {
+            info!("messaging::heartbeat -- {} caught up", channel);
+            lock!(*STALLED).remove(&channel);
+            Messenger::event("messaging:remote-resumed", json!({"channel": channel, "pending": pending}))?;
+        }
+    }
+    Ok(())
+}
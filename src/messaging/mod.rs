@@ -0,0 +1,794 @@
+//! The messenger is responsible for proxying messages between our remote and 
+//! our main thread.
+//!
+//! This module is essentially the window into the app, essentially acting as an
+//! event bus to/from our remote sender (generally, this is a UI of some sort).
+//!
+//! Messages always come *in* on our carrier channel, but where responses and
+//! events go *out* depends on the transport (`messaging.transport` in the
+//! config): carrier channels for in-process UIs, a WebSocket for UIs that
+//! connect to us over `ws://localhost:PORT`, or stdin/stdout for hosts that run
+//! us as a child process (see `stdio`). The non-carrier transports feed what
+//! they read into our incoming channel.
+//!
+//! Requests, responses, and events are JSON by default. Setting
+//! `messaging.format` to "msgpack" switches the wire format to MessagePack,
+//! which saves UIs from escaping/unescaping big note bodies and file chunks.
+//! Messages the core sends itself (`app_event()`, shutdown) are always JSON.
+//! Big messages can be compressed on the way out (see `compress`).
+//!
+//! More than one UI can talk to the core at once (say, the desktop app and a
+//! CLI tool). A UI that isn't alone names itself by wrapping its requests:
+//!
+//!     {"client": "<client id>", "msg": ["<message id>", "<command>", ...]}
+//!
+//! and its responses then come back on the reqres out channel suffixed with
+//! `:<client id>` (`:<client id>:<message id>` with `reqres_append_mid`).
+//! Events go out on the normal events channel and also on
+//! `<events>:<client id>` for each client we've heard from, until it sends
+//! `app:goodbye`. Unwrapped requests work the same as always.
+//!
+//! Clients get every event until they say which ones they want with
+//! `events:subscribe` (glob patterns, like "sync:*"), after which they only
+//! get the events matching their patterns. An event nobody wants isn't even
+//! serialized. Stream chunks (see `dispatch::stream`) and `messaging:*` events
+//! always go out, since they're answers to something the client asked for.
+//!
+//! Requests are run through a bounded pipeline, so a UI sending more than we
+//! can handle gets slowed down or turned away instead of swamping us (see
+//! `pipeline`).
+//!
+//! While the main loop runs, it sends out a `core:heartbeat` event every so
+//! often and warns the UI if it stops reading (see `heartbeat`).
+//!
+//! Hosts that can't spare a thread for the main loop can skip it and call us
+//! directly instead (see `direct`).
+
+mod websocket;
+mod stdio;
+pub mod pipeline;
+pub mod compress;
+pub mod heartbeat;
+pub mod direct;
+
+use ::std::sync::{Arc, RwLock};
+use ::std::sync::atomic::{AtomicBool, Ordering};
+use ::std::collections::{HashSet, HashMap};
+use ::carrier;
+use ::glob::Pattern;
+use ::rmp_serde;
+use ::jedi::{self, Value, Serialize};
+use ::util;
+use ::config;
+use ::crypto;
+use ::error::{TResult, TError, ErrorCode, CodeValue};
+use ::dispatch::cancel;
+
+/// Defines a container for sending responses to the client. We could use a hash
+/// table, but then the elements might serialize out of order. This allows us to
+/// force our "error" key (`e`) first, and put "data" (`d`) second.
+///
+/// Note that this is more or less a Turtl-enforced RPC system. Each "call" we
+/// run has a response of either error (`e = 1`) or success (`e = 0`) and
+/// any supporting data (the error that occurred, or the data we requested).
+/// Errors also come with a `code` ({"num": 4, "name": "not_found"}) that the
+/// UI can branch on instead of digging through the error itself.
+///
+/// NOTE: this is mainly used by the `Turtl` object
+#[derive(Serialize)]
+#[serde(rename = "res")]
+pub struct Response {
+    /// The message id
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    /// `e > 0` means "error!!!1", `e == 0` means "great success!!"
+    pub e: i64,
+    /// Any data we want to pass back to the UI
+    pub d: Value,
+    /// For errors, what kind of error it was (see `ErrorCode`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<CodeValue>,
+}
+
+impl Response {
+    /// Make a new Response object with a blank id
+    pub fn new(e: i64, d: Value) -> Response {
+        Response { id: None, e: e, d: d, code: None }
+    }
+
+    /// Make a new Response object
+    pub fn new_w_id(id: String, e: i64, d: Value) -> Response {
+        Response { id: Some(id), e: e, d: d, code: None }
+    }
+
+    /// Set the error code for this response
+    pub fn with_code(mut self, code: ErrorCode) -> Response {
+        self.code = Some(code.value());
+        self
+    }
+}
+
+/// Defines a container for sending events to the client. See the `Response`
+/// object for notes.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename = "ev")]
+pub struct Event {
+    /// Our event's name
+    pub e: String,
+    /// Our event's data
+    pub d: Value,
+}
+
+/// Events that go out whether they've been subscribed to or not
+const ALWAYS_SENT: &'static [&'static str] = &["stream:", "messaging:"];
+
+/// Prefixes messages the core sends to its own dispatcher
+const APP_EVENT_PREFIX: &'static [u8] = b"::ev";
+
+/// Tells the main loop to quit
+const SHUTDOWN_MSG: &'static str = "turtl:internal:msg:shutdown";
+
+/// How messages to/from the UI are encoded
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Json,
+    MsgPack,
+}
+
+/// Grab our wire format from the config
+pub fn format() -> Format {
+    let name: String = config::get(&["messaging", "format"]).unwrap_or(String::from("json"));
+    match name.as_str() {
+        "msgpack" => Format::MsgPack,
+        _ => Format::Json,
+    }
+}
+
+/// Encode a message for the UI
+pub fn encode<T: Serialize>(format: Format, val: &T) -> TResult<Vec<u8>> {
+    match format {
+        Format::Json => Ok(jedi::stringify(val)?.into_bytes()),
+        // named, so structs come out as maps (same shape as the JSON)
+        Format::MsgPack => Ok(rmp_serde::to_vec_named(val)?),
+    }
+}
+
+/// Decode a message from the UI
+pub fn decode(format: Format, bytes: &[u8]) -> TResult<Value> {
+    match format {
+        Format::Json => Ok(jedi::parse(&util::decode_text(bytes)?)?),
+        Format::MsgPack => Ok(rmp_serde::from_slice(bytes)?),
+    }
+}
+
+/// Whether a message is one the core sent itself (see `app_event()`)
+pub fn is_app_event(bytes: &[u8]) -> bool {
+    bytes.starts_with(APP_EVENT_PREFIX)
+}
+
+/// Whether a message that came in over a transport is posing as one of our
+/// own app events (compressed or not). Only the core gets to send those, so
+/// transports drop them instead of passing them along.
+pub fn is_forged_event(bytes: &[u8]) -> bool {
+    if is_app_event(bytes) { return true; }
+    if !compress::is_compressed(bytes) { return false; }
+    match compress::decompress(bytes.to_vec()) {
+        Ok(x) => is_app_event(&x),
+        // the dispatcher won't be able to read it either
+        Err(_) => false,
+    }
+}
+
+/// Is this a request that controls the core (cancelling another request, or
+/// shutting down) rather than asking it for something?
+pub fn is_control(bytes: &[u8]) -> bool {
+    let msg = match parse_incoming(bytes) {
+        Ok(Incoming::Request(_, x)) => x,
+        _ => return false,
+    };
+    match jedi::get::<String>(&["1"], &msg) {
+        Ok(cmd) => cmd.starts_with(cancel::PREFIX) || cmd == "app:shutdown",
+        Err(_) => false,
+    }
+}
+
+/// A message that came in on our channel
+pub enum Incoming {
+    /// A request from the UI: `[mid, cmd, args...]`, and the client that sent
+    /// it (if it named itself)
+    Request(Option<String>, Value),
+    /// An event the core sent itself (see `app_event()`)
+    AppEvent(Event),
+}
+
+/// Figure out what a message that came in on our channel is
+pub fn parse_incoming(bytes: &[u8]) -> TResult<Incoming> {
+    if is_app_event(bytes) {
+        let event: Event = jedi::parse(&util::decode_text(&bytes[APP_EVENT_PREFIX.len()..])?)?;
+        return Ok(Incoming::AppEvent(event));
+    }
+    let msg = decode(format(), bytes)?;
+    match msg {
+        Value::Object(_) => {
+            let client: String = jedi::get(&["client"], &msg)?;
+            if client.is_empty() {
+                return TErr!(TError::BadValue(String::from("client id can't be blank")));
+            }
+            Ok(Incoming::Request(Some(client), jedi::get(&["msg"], &msg)?))
+        }
+        _ => Ok(Incoming::Request(None, msg)),
+    }
+}
+
+/// Somewhere we can send messages bound for the UI
+pub trait Transport: Send + Sync {
+    /// Send a message out. `channel` is the carrier channel the message would
+    /// have gone out on, which transports are free to ignore.
+    fn send(&self, channel: &str, msg: Vec<u8>) -> TResult<()>;
+
+    /// How many messages sent on `channel` are still waiting to be picked up,
+    /// if the transport can tell
+    fn backlog(&self, _channel: &str) -> Option<u32> { None }
+
+    /// Stop accepting connections, hang up, etc
+    fn shutdown(&self) {}
+}
+
+/// The default transport: carrier channels, for UIs that load us in-process
+pub struct CarrierTransport;
+
+impl Transport for CarrierTransport {
+    fn send(&self, channel: &str, msg: Vec<u8>) -> TResult<()> {
+        carrier::send(channel, msg)
+            .map_err(|e| From::from(e))
+    }
+
+    fn backlog(&self, channel: &str) -> Option<u32> {
+        Some(carrier::pending(channel))
+    }
+}
+
+lazy_static! {
+    /// Where our outgoing messages go
+    static ref TRANSPORT: RwLock<Arc<dyn Transport>> = RwLock::new(Arc::new(CarrierTransport));
+
+    /// The (named) clients we send events to
+    static ref CLIENTS: RwLock<HashSet<String>> = RwLock::new(HashSet::new());
+
+    /// The event patterns each client has subscribed to (None is the unnamed
+    /// client). Clients that aren't in here get every event.
+    static ref SUBSCRIPTIONS: RwLock<HashMap<Option<String>, Vec<Pattern>>> = RwLock::new(HashMap::new());
+
+    /// Set by `stop()`, so the main loop stops taking new work before it has
+    /// even woken up
+    static ref SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+}
+
+/// Start sending events to a client
+pub fn add_client(client: &String) {
+    if lockr!(*CLIENTS).contains(client) { return; }
+    info!("messaging::add_client() -- hello, {}", client);
+    lockw!(*CLIENTS).insert(client.clone());
+}
+
+/// Stop sending events to a client
+pub fn remove_client(client: &String) -> bool {
+    lockw!(*SUBSCRIPTIONS).remove(&Some(client.clone()));
+    lockw!(*CLIENTS).remove(client)
+}
+
+/// Whether a client wants an event, given everyone's subscriptions
+fn wants(subscriptions: &HashMap<Option<String>, Vec<Pattern>>, client: &Option<String>, event: &str) -> bool {
+    if ALWAYS_SENT.iter().any(|x| event.starts_with(x)) { return true; }
+    match subscriptions.get(client) {
+        Some(patterns) => patterns.iter().any(|x| x.matches(event)),
+        None => true,
+    }
+}
+
+/// Whether anyone wants an event
+pub fn wanted(event: &str) -> bool {
+    let subscriptions = lockr!(*SUBSCRIPTIONS);
+    if subscriptions.is_empty() { return true; }
+    if wants(&subscriptions, &None, event) { return true; }
+    lockr!(*CLIENTS).iter().any(|x| wants(&subscriptions, &Some(x.clone()), event))
+}
+
+/// The patterns a client is subscribed to (None if it gets everything)
+pub fn subscriptions(client: Option<&String>) -> Option<Vec<String>> {
+    lockr!(*SUBSCRIPTIONS).get(&client.cloned())
+        .map(|x| x.iter().map(|p| String::from(p.as_str())).collect())
+}
+
+/// Subscribe a client to the events matching some patterns. The first
+/// subscription replaces the default of getting everything (subscribe to "*"
+/// to get it back).
+pub fn subscribe(client: Option<&String>, patterns: &Vec<String>) -> TResult<Vec<String>> {
+    let mut compiled = Vec::with_capacity(patterns.len());
+    for pattern in patterns {
+        compiled.push(Pattern::new(pattern)?);
+    }
+    {
+        let mut guard = lockw!(*SUBSCRIPTIONS);
+        let existing = guard.entry(client.cloned()).or_insert(Vec::new());
+        for pattern in compiled {
+            if !existing.contains(&pattern) { existing.push(pattern); }
+        }
+    }
+    Ok(subscriptions(client).unwrap_or(Vec::new()))
+}
+
+/// Drop some of a client's patterns (or, with None, all of them, so it gets
+/// no events at all)
+pub fn unsubscribe(client: Option<&String>, patterns: Option<&Vec<String>>) -> Vec<String> {
+    {
+        let mut guard = lockw!(*SUBSCRIPTIONS);
+        let existing = guard.entry(client.cloned()).or_insert(Vec::new());
+        match patterns {
+            Some(patterns) => existing.retain(|x| !patterns.iter().any(|p| p == x.as_str())),
+            None => existing.clear(),
+        }
+    }
+    subscriptions(client).unwrap_or(Vec::new())
+}
+
+/// Grab our current transport
+fn transport() -> Arc<dyn Transport> {
+    lockr!(*TRANSPORT).clone()
+}
+
+/// Swap out the transport our outgoing messages use
+pub fn set_transport(transport: Arc<dyn Transport>) {
+    *lockw!(*TRANSPORT) = transport;
+}
+
+/// Set up the transport named in our config
+fn setup_transport(channel_in: &String) -> TResult<()> {
+    let name: String = config::get(&["messaging", "transport"]).unwrap_or(String::from("carrier"));
+    match name.as_str() {
+        "carrier" => set_transport(Arc::new(CarrierTransport)),
+        "websocket" => {
+            let address: String = config::get(&["messaging", "websocket", "address"]).unwrap_or(String::from("127.0.0.1:7472"));
+            let reqres_append_mid: bool = config::get(&["messaging", "reqres_append_mid"]).unwrap_or(false);
+            if reqres_append_mid {
+                warn!("messaging::setup_transport() -- messaging.reqres_append_mid is on, but websocket clients can only match responses by id");
+            }
+            let origins: Vec<String> = config::get(&["messaging", "websocket", "origins"]).unwrap_or(Vec::new());
+            let token = match config::get::<String>(&["messaging", "websocket", "token"]) {
+                Ok(x) => x,
+                Err(_) => {
+                    if util::paths::is_memory() {
+                        return TErr!(TError::MissingField(String::from("messaging.websocket.token (we have no data folder to write one to)")));
+                    }
+                    let token = crypto::random_hash()?;
+                    websocket::write_token(&token)?;
+                    token
+                }
+            };
+            let auth = websocket::Auth { token: token, origins: origins };
+            let ws = websocket::WebSocketTransport::bind(address.as_str(), channel_in.clone(), format() == Format::MsgPack, auth)?;
+            info!("messaging::setup_transport() -- websocket transport listening on {}", ws.local_addr());
+            set_transport(Arc::new(ws));
+        }
+        "stdio" => {
+            if format() == Format::MsgPack {
+                return TErr!(TError::BadValue(String::from("the stdio transport is line-based, and only speaks json (set messaging.format to \"json\")")));
+            }
+            set_transport(Arc::new(stdio::StdioTransport::start(channel_in.clone())?));
+            info!("messaging::setup_transport() -- stdio transport reading from stdin");
+        }
+        _ => return TErr!(TError::BadValue(format!("unknown messaging.transport: {}", name))),
+    }
+    Ok(())
+}
+
+pub struct Messenger {
+    /// Whether we're bound or not. Kind of vestigial
+    bound: bool,
+
+    /// The channel we're listening to
+    channel_in: String,
+
+    /// The channel we're sending on
+    channel_out: String,
+}
+
+impl Messenger {
+    /// Create a new messenger with a custom (non-config) channel
+    pub fn new_with_channel(channel: String) -> Messenger {
+        Messenger {
+            bound: true,
+            channel_in: format!("{}-core-in", channel),
+            channel_out: format!("{}-core-out", channel),
+        }
+    }
+
+    /// Create a new messenger
+    pub fn new() -> Messenger {
+        // grab our messaging channel name from config
+        let channel: String = match config::get(&["messaging", "reqres"]) {
+            Ok(x) => x,
+            Err(e) => {
+                error!("messaging: problem grabbing address (messaging.reqres) from config, using default: {}", e);
+                String::from("inproc://turtl")
+            }
+        };
+        Messenger::new_with_channel(channel)
+    }
+
+    #[allow(dead_code)]
+    /// Create a new messenger with channel-in/channel-out flipped
+    pub fn new_reversed(channel: String) -> Messenger {
+        let mut messenger = Messenger::new_with_channel(channel);
+        let channtmp = messenger.channel_in;
+        messenger.channel_in = messenger.channel_out;
+        messenger.channel_out = channtmp;
+        messenger
+    }
+
+    /// Send an event out to our UI thread. Note that this is a static method!
+    pub fn event(name: &str, data: Value) -> TResult<()> {
+        let channel: String = config::get(&["messaging", "events"])?;
+        let (to_default, clients) = {
+            let subscriptions = lockr!(*SUBSCRIPTIONS);
+            let clients = lockr!(*CLIENTS).iter()
+                .filter(|x| wants(&subscriptions, &Some((*x).clone()), name))
+                .cloned()
+                .collect::<Vec<_>>();
+            (wants(&subscriptions, &None, name), clients)
+        };
+        if !to_default && clients.is_empty() {
+            trace!("messaging: event: nobody is subscribed to {}", name);
+            return Ok(());
+        }
+        let event = Event {
+            e: String::from(name),
+            d: data,
+        };
+        let msg = compress::compress(encode(format(), &event)?)?;
+        trace!("messaging: event: {} ({})", channel, msg.len());
+        let transport = transport();
+        for client in &clients {
+            match transport.send(format!("{}:{}", channel, client).as_str(), msg.clone()) {
+                Ok(_) => {}
+                Err(e) => warn!("messaging: event: problem sending to client {}: {}", client, e),
+            }
+        }
+        if !to_default { return Ok(()); }
+        transport.send(channel.as_str(), msg)
+    }
+
+    /// Blocking receive
+    pub fn recv(&self) -> TResult<String> {
+        let bytes = self.recv_bytes()?;
+        util::decode_text(bytes.as_slice())
+    }
+
+    /// Blocking receive, without assuming the message is text
+    pub fn recv_bytes(&self) -> TResult<Vec<u8>> {
+        let bytes = carrier::recv(&self.channel_in[..])?;
+        trace!("messaging: recv: {} ({})", self.channel_in, bytes.len());
+        compress::decompress(bytes)
+    }
+
+    /// Non-blocking receive, without assuming the message is text
+    fn recv_bytes_nb(&self) -> TResult<Option<Vec<u8>>> {
+        match carrier::recv_nb(&self.channel_in[..])? {
+            Some(bytes) => {
+                trace!("messaging: recv: {} ({})", self.channel_in, bytes.len());
+                Ok(Some(compress::decompress(bytes)?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    #[allow(dead_code)]
+    /// Non-blocking receive
+    pub fn recv_nb(&self) -> TResult<String> {
+        let maybe_bytes = carrier::recv_nb(&self.channel_in[..])?;
+        match maybe_bytes {
+            Some(x) => {
+                trace!("messaging: recv: {} ({})", self.channel_in, x.len());
+                util::decode_text(x.as_slice())
+            },
+            None => Err(TError::TryAgain),
+        }
+    }
+
+    /// Send a message out
+    pub fn send<T: Into<Vec<u8>>>(&self, msg: T) -> TResult<()> {
+        let msg = compress::compress(msg.into())?;
+        trace!("messaging: send: {} ({})", self.channel_out, msg.len());
+        transport().send(self.channel_out.as_str(), msg)
+    }
+
+    /// Send a message on the out channel, but suffix the channel
+    pub fn send_suffix<T: Into<Vec<u8>>>(&self, suffix: String, msg: T) -> TResult<()> {
+        let msg = compress::compress(msg.into())?;
+        trace!("messaging: send_suffix: {}:{} ({})", self.channel_out, suffix, msg.len());
+        transport().send(format!("{}:{}", &self.channel_out, suffix).as_str(), msg)
+    }
+
+    /// Send a message out on the in channel
+    pub fn send_rev<T: Into<Vec<u8>>>(&self, msg: T) -> TResult<()> {
+        let msg = msg.into();
+        trace!("messaging: send_rev: {}", msg.len());
+        carrier::send(&self.channel_in[..], msg)
+            .map_err(|e| From::from(e))
+    }
+
+    /// Shutdown the bound/connected socket endpoint
+    pub fn shutdown(&mut self) {
+        self.bound = false;
+    }
+
+    /// Are we bound/connected?
+    pub fn is_bound(&self) -> bool {
+        self.bound
+    }
+}
+
+/// Defines our callback type for the messaging system.
+///
+/// NOTE!! I'd love to just use util::Thunk<&mut Messenger> here, however it
+/// bitches about lifetimes and lifetimes are so horribly infectious that I
+/// can't justify rewriting a bunch of shit to satisfy it.
+pub trait MsgThunk: Send + 'static {
+    fn call_box(self: Box<Self>, &mut Messenger);
+}
+impl<F: FnOnce(&mut Messenger) + Send + 'static> MsgThunk for F {
+    fn call_box(self: Box<Self>, messenger: &mut Messenger) {
+        (*self)(messenger);
+    }
+}
+
+/// Start the messaging system. Blocks on incoming messages, running `process`
+/// for each one, until `stop()` is called. Anything still waiting on us once
+/// we've stopped is handed to `reject` (which should answer it with an error)
+/// instead of being left in the channel.
+pub fn start<F, R>(process: F, reject: R) -> TResult<()>
+    where F: Fn(Vec<u8>) + Send + Sync + 'static,
+          R: Fn(Vec<u8>)
+{
+    // create our messenger!
+    let mut messenger = Messenger::new();
+    SHUTTING_DOWN.store(false, Ordering::SeqCst);
+    setup_transport(&messenger.channel_in)?;
+    info!("messaging::start() -- main loop");
+    ui_event("messaging:ready", &true)?;
+    heartbeat::start();
+    run(&mut messenger, process, reject);
+    info!("messaging::start() -- shutting down");
+    heartbeat::stop();
+    transport().shutdown();
+    set_transport(Arc::new(CarrierTransport));
+    Ok(())
+}
+
+/// The main loop. Runs until it gets the shutdown message, then drains
+/// whatever is left in the incoming channel.
+fn run<F, R>(messenger: &mut Messenger, process: F, reject: R)
+    where F: Fn(Vec<u8>),
+          R: Fn(Vec<u8>)
+{
+    while messenger.is_bound() {
+        // sleeps until something comes in
+        let msg = match messenger.recv_bytes() {
+            Ok(x) => x,
+            Err(e) => {
+                error!("messaging: problem receiving from remote: {:?}", e);
+                continue;
+            }
+        };
+        if msg.as_slice() == SHUTDOWN_MSG.as_bytes() {
+            messenger.shutdown();
+        } else if msg.as_slice() == heartbeat::HEARTBEAT_MSG.as_bytes() {
+            match heartbeat::beat(messenger) {
+                Ok(_) => {}
+                Err(e) => warn!("messaging: problem sending heartbeat: {}", e),
+            }
+        } else if SHUTTING_DOWN.load(Ordering::SeqCst) {
+            // stop() has been called, so don't start anything new
+            reject(msg);
+        } else {
+            process(msg);
+        }
+    }
+    let mut drained = 0;
+    loop {
+        match messenger.recv_bytes_nb() {
+            Ok(Some(msg)) => {
+                if msg.as_slice() == SHUTDOWN_MSG.as_bytes() || msg.as_slice() == heartbeat::HEARTBEAT_MSG.as_bytes() {
+                    continue;
+                }
+                drained += 1;
+                reject(msg);
+            }
+            Ok(None) => break,
+            Err(e) => {
+                error!("messaging: problem draining incoming messages: {}", e);
+                break;
+            }
+        }
+    }
+    if drained > 0 {
+        info!("messaging::run() -- rejected {} messages that came in after shutdown", drained);
+    }
+}
+
+/// Whether `stop()` has been called
+pub fn shutting_down() -> bool {
+    SHUTTING_DOWN.load(Ordering::SeqCst)
+}
+
+/// Tell the messaging system to quit. Messages that haven't been picked up by
+/// the time it does are rejected rather than run.
+pub fn stop() {
+    SHUTTING_DOWN.store(true, Ordering::SeqCst);
+    if direct::running() {
+        direct::stop();
+        return;
+    }
+    let messenger = Messenger::new();
+    // the main loop is asleep waiting on the *incoming* channel, so that's
+    // where the shutdown message goes
+    match messenger.send_rev(String::from(SHUTDOWN_MSG)) {
+        Ok(_) => {},
+        Err(e) => error!("messaging::stop() -- error shutting down messaging thread: {}", e),
+    }
+}
+
+/// Send an event to our own dispatch handler
+pub fn ui_event<T: Serialize>(ev: &str, val: &T) -> TResult<()> {
+    info!("messaging::ui_event() -- {}", ev);
+    if !wanted(ev) { return Ok(()); }
+    Messenger::event(ev, jedi::to_val(val)?)
+}
+
+/// Send an event to our own dispatch handler
+pub fn app_event<T: Serialize>(ev: &str, val: &T) -> TResult<()> {
+    let messenger = Messenger::new();
+    let event = Event {
+        e: String::from(ev),
+        d: jedi::to_val(val)?,
+    };
+    let mut msg = APP_EVENT_PREFIX.to_vec();
+    msg.extend(jedi::stringify(&event)?.into_bytes());
+    if direct::running() {
+        return direct::send(msg);
+    }
+    messenger.send_rev(msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use ::error::TError;
+
+    /// given a thread-safe bool, return a copy of the bool
+    fn grab_locked_bool(val: &Arc<Mutex<bool>>) -> bool {
+        let clone = val.clone();
+        let guard = lock!(clone);
+        let copy = (*guard).clone();
+        copy
+    }
+
+    #[test]
+    /// spawns a bind() thread, listens for "ping", sets some shared state vars
+    /// (to confirm it ran) then shuts down the bind thread.
+    ///
+    /// this tests that message passing via the messaging system, well, works.
+    fn can_bind_send_recv() {
+        let pong = Arc::new(Mutex::new(false));
+        let panic = Arc::new(Mutex::new(false));
+
+        let panicref = panic.clone();
+        let pongref = pong.clone();
+        let handle = thread::spawn(move || {
+            let messenger = Messenger::new_with_channel(String::from("inproc://turtltest"));
+            let message = messenger.recv().unwrap();
+
+            let res = match message.as_ref() {
+                "ping" => {
+                    let mut pong = lock!(pongref);
+                    *pong = true;
+                    messenger.send(String::from("pong")).unwrap();
+                    Ok(())
+                },
+                _ => TErr!(TError::Msg(format!("bad command: {}", message))),
+            };
+
+            match res {
+                Ok(_) => (),
+                Err(_) => {
+                    let mut panic = lock!(panicref);
+                    *panic = true;
+                }
+            }
+        });
+
+        let messenger = Messenger::new_reversed(String::from("inproc://turtltest"));
+        messenger.send(String::from("ping")).unwrap();
+        let response = messenger.recv().unwrap();
+        assert_eq!(response, r#"pong"#);
+        assert_eq!(grab_locked_bool(&pong), true);
+        assert_eq!(grab_locked_bool(&panic), false);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn drains_on_shutdown() {
+        let mut messenger = Messenger::new_with_channel(String::from("inproc://turtl-drain"));
+        let remote = Messenger::new_reversed(String::from("inproc://turtl-drain"));
+        remote.send(String::from("one")).unwrap();
+        remote.send(String::from(SHUTDOWN_MSG)).unwrap();
+        remote.send(String::from("two")).unwrap();
+        remote.send(String::from("three")).unwrap();
+
+        let processed = Arc::new(Mutex::new(Vec::new()));
+        let rejected = Arc::new(Mutex::new(Vec::new()));
+        let processed2 = processed.clone();
+        let rejected2 = rejected.clone();
+        run(&mut messenger,
+            move |msg| lock!(processed2).push(String::from_utf8(msg).unwrap()),
+            move |msg| lock!(rejected2).push(String::from_utf8(msg).unwrap()));
+        assert_eq!(*lock!(processed), vec!["one"]);
+        assert_eq!(*lock!(rejected), vec!["two", "three"]);
+        assert!(!messenger.is_bound());
+    }
+
+    #[test]
+    fn encodes_formats() {
+        let res = Response::new_w_id(String::from("12"), 0, json!({"body": "hello \"there\"", "n": 3}));
+        let json = encode(Format::Json, &res).unwrap();
+        assert_eq!(String::from_utf8(json.clone()).unwrap(), r#"{"id":"12","e":0,"d":{"body":"hello \"there\"","n":3}}"#);
+        let packed = encode(Format::MsgPack, &res).unwrap();
+        assert!(packed.len() < json.len());
+        assert_eq!(decode(Format::MsgPack, &packed).unwrap(), decode(Format::Json, &json).unwrap());
+        let err = twrap!(TError::NotFound(String::from("nope")));
+        let res = Response::new_w_id(String::from("13"), 1, json!("nope")).with_code(err.code());
+        assert_eq!(String::from_utf8(encode(Format::Json, &res).unwrap()).unwrap(), r#"{"id":"13","e":1,"d":"nope","code":{"num":4,"name":"not_found"}}"#);
+
+        match parse_incoming(br#"::ev{"e":"sync:connected","d":true}"#).unwrap() {
+            Incoming::AppEvent(ev) => assert_eq!(ev.e, "sync:connected"),
+            Incoming::Request(..) => panic!("expected an app event"),
+        }
+        match parse_incoming(br#"["1","app:connected"]"#).unwrap() {
+            Incoming::Request(client, val) => assert_eq!((client, val), (None, json!(["1", "app:connected"]))),
+            Incoming::AppEvent(_) => panic!("expected a request"),
+        }
+        match parse_incoming(br#"{"client":"cli","msg":["2","ping"]}"#).unwrap() {
+            Incoming::Request(client, val) => assert_eq!((client, val), (Some(String::from("cli")), json!(["2", "ping"]))),
+            Incoming::AppEvent(_) => panic!("expected a request"),
+        }
+        assert!(parse_incoming(br#"{"client":"","msg":["3","ping"]}"#).is_err());
+    }
+
+    #[test]
+    fn filters_events() {
+        let client = String::from("subscriber-test");
+        add_client(&client);
+        assert_eq!(subscriptions(Some(&client)), None);
+        let subs = subscribe(Some(&client), &vec![String::from("sync:*"), String::from("note:edit")]).unwrap();
+        assert_eq!(subs, vec!["sync:*", "note:edit"]);
+        {
+            let guard = lockr!(*SUBSCRIPTIONS);
+            let named = Some(client.clone());
+            assert!(wants(&guard, &named, "sync:update"));
+            assert!(wants(&guard, &named, "note:edit"));
+            assert!(!wants(&guard, &named, "note:delete"));
+            assert!(wants(&guard, &named, "stream:12:chunk"));
+            assert!(wants(&guard, &Some(String::from("someone-else")), "note:delete"));
+        }
+        assert_eq!(unsubscribe(Some(&client), Some(&vec![String::from("sync:*")])), vec!["note:edit"]);
+        assert_eq!(unsubscribe(Some(&client), None), Vec::<String>::new());
+        assert!(!wants(&lockr!(*SUBSCRIPTIONS), &Some(client.clone()), "note:edit"));
+        assert!(subscribe(Some(&client), &vec![String::from("[bad")]).is_err());
+        remove_client(&client);
+        assert_eq!(subscriptions(Some(&client)), None);
+    }
+}
+
@@ -0,0 +1,423 @@
+//! Keeps a flood of requests from swamping the core. Each request gets its own
+//! thread, but only `messaging.pipeline.concurrency` of them run at once and
+//! the rest wait their turn. Once `messaging.pipeline.capacity` requests are
+//! running or waiting, `messaging.pipeline.policy` decides what happens to the
+//! next one:
+//!
+//! - "block": hold on to new requests until there's room. They're set aside
+//!   without tying up whoever handed them to us, so the main loop keeps
+//!   reading (and heartbeats/shutdowns keep working) while we're full. We
+//!   only hold so many (`messaging.pipeline.hold_limit`), past that new
+//!   requests get a `busy` error.
+//! - "drop-oldest": answer the oldest waiting request with a `busy` error and
+//!   queue the new one
+//! - "reject": answer the new request with a `busy` error
+//!
+//! Either way, the UI gets a `core:backpressure` event when we fill up, so it
+//! can ease off. Messages the core sends itself and control requests
+//! (`cancel:*`, `app:shutdown`) skip the line: the first are how the core gets
+//! its own work done, and the UI has to be able to cancel things or shut us
+//! down no matter how busy we are. They still only get
+//! `MAX_URGENT_THREADS` threads between them, and line up for those.
+
+use ::std::collections::VecDeque;
+use ::std::sync::{Arc, Mutex};
+use ::std::thread;
+use ::config;
+use ::error::TError;
+use ::messaging;
+
+/// How many threads messages that skip the line (see above) get between them
+const MAX_URGENT_THREADS: usize = 8;
+
+/// What to do with a request that comes in when we're full
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Policy {
+    Block,
+    DropOldest,
+    Reject,
+}
+
+impl Policy {
+    fn name(&self) -> &'static str {
+        match *self {
+            Policy::Block => "block",
+            Policy::DropOldest => "drop-oldest",
+            Policy::Reject => "reject",
+        }
+    }
+}
+
+/// Runs a message
+type Process = Arc<Fn(Vec<u8>) + Send + Sync>;
+
+/// Answers a message we won't run with an error
+type Reject = Arc<Fn(Vec<u8>, TError) + Send + Sync>;
+
+struct State {
+    running: usize,
+    waiting: VecDeque<Vec<u8>>,
+    /// Requests that came in while we were full (with the "block" policy).
+    /// They don't count against our capacity, and move into `waiting` as
+    /// room opens up.
+    held: VecDeque<Vec<u8>>,
+    /// How many threads are running messages that skipped the line
+    urgent_running: usize,
+    /// Messages that skipped the line, waiting on an urgent thread
+    urgent: VecDeque<Vec<u8>>,
+    /// Whether we've told the UI we're full (and haven't drained since)
+    full: bool,
+}
+
+struct Inner {
+    state: Mutex<State>,
+    concurrency: usize,
+    capacity: usize,
+    /// How many requests we'll hold (or line up to skip the line) before we
+    /// start turning them away
+    hold_limit: usize,
+    policy: Policy,
+    process: Process,
+    reject: Reject,
+}
+
+pub struct Pipeline {
+    inner: Arc<Inner>,
+}
+
+impl Pipeline {
+    /// Create a pipeline with the limits/policy from our config
+    pub fn new<P, R>(process: P, reject: R) -> Pipeline
+        where P: Fn(Vec<u8>) + Send + Sync + 'static,
+              R: Fn(Vec<u8>, TError) + Send + Sync + 'static
+    {
+        let concurrency: usize = config::get(&["messaging", "pipeline", "concurrency"]).unwrap_or(16);
+        let capacity: usize = config::get(&["messaging", "pipeline", "capacity"]).unwrap_or(256);
+        let hold_limit: usize = config::get(&["messaging", "pipeline", "hold_limit"]).unwrap_or(4096);
+        let policy_name: String = config::get(&["messaging", "pipeline", "policy"]).unwrap_or(String::from("block"));
+        let policy = match policy_name.as_str() {
+            "block" => Policy::Block,
+            "drop-oldest" => Policy::DropOldest,
+            "reject" => Policy::Reject,
+            _ => {
+                warn!("messaging::pipeline -- unknown messaging.pipeline.policy ({}), using \"block\"", policy_name);
+                Policy::Block
+            }
+        };
+        Pipeline::with_limits(concurrency, capacity, hold_limit, policy, process, reject)
+    }
+
+    fn with_limits<P, R>(concurrency: usize, capacity: usize, hold_limit: usize, policy: Policy, process: P, reject: R) -> Pipeline
+        where P: Fn(Vec<u8>) + Send + Sync + 'static,
+              R: Fn(Vec<u8>, TError) + Send + Sync + 'static
+    {
+        let concurrency = if concurrency == 0 { 1 } else { concurrency };
+        let inner = Inner {
+            state: Mutex::new(State {
+                running: 0,
+                waiting: VecDeque::new(),
+                held: VecDeque::new(),
+                urgent_running: 0,
+                urgent: VecDeque::new(),
+                full: false,
+            }),
+            concurrency: concurrency,
+            // we always have room for what can run
+            capacity: if capacity < concurrency { concurrency } else { capacity },
+            hold_limit: hold_limit,
+            policy: policy,
+            process: Arc::new(process),
+            reject: Arc::new(reject),
+        };
+        Pipeline { inner: Arc::new(inner) }
+    }
+
+    /// Run a message (or queue it, or turn it away). Never blocks.
+    pub fn submit(&self, msg: Vec<u8>) {
+        let inner = &self.inner;
+        if messaging::is_app_event(msg.as_slice()) {
+            skip_line(inner, msg, false);
+            return;
+        }
+        {
+            let mut state = lock!(inner.state);
+            if state.running < inner.concurrency && state.waiting.is_empty() && state.held.is_empty() {
+                state.running += 1;
+                drop(state);
+                spawn(inner.clone(), msg, true);
+                return;
+            }
+        }
+        // we'd make this one wait, so see if it's allowed to cut in line
+        if messaging::is_control(msg.as_slice()) {
+            skip_line(inner, msg, true);
+            return;
+        }
+        let mut dropped = None;
+        let run_now = {
+            let mut state = lock!(inner.state);
+            // once we're holding requests, new ones get in line behind them
+            let full = state.running + state.waiting.len() >= inner.capacity || !state.held.is_empty();
+            if full {
+                if !state.full {
+                    state.full = true;
+                    warn!("messaging::pipeline -- full ({} running, {} waiting), policy: {}", state.running, state.waiting.len(), inner.policy.name());
+                    let event = json!({
+                        "policy": inner.policy.name(),
+                        "capacity": inner.capacity,
+                        "running": state.running,
+                        "waiting": state.waiting.len(),
+                    });
+                    messaging::ui_event("core:backpressure", &event)
+                        .unwrap_or_else(|e| error!("messaging::pipeline -- error sending backpressure event: {}", e));
+                }
+                match inner.policy {
+                    Policy::Block if state.held.len() < inner.hold_limit => {
+                        state.held.push_back(msg);
+                        return;
+                    }
+                    Policy::DropOldest if !state.waiting.is_empty() => {
+                        dropped = state.waiting.pop_front();
+                    }
+                    _ => {
+                        drop(state);
+                        (inner.reject)(msg, TError::Busy(format!("the core is busy (over {} requests)", inner.capacity)));
+                        return;
+                    }
+                }
+            }
+            if state.running < inner.concurrency {
+                state.running += 1;
+                Some(msg)
+            } else {
+                state.waiting.push_back(msg);
+                None
+            }
+        };
+        if let Some(old) = dropped {
+            (inner.reject)(old, TError::Busy(String::from("the core is busy, and this request waited too long")));
+        }
+        if let Some(msg) = run_now {
+            spawn(inner.clone(), msg, true);
+        }
+    }
+
+    /// How many requests are running, and how many are waiting
+    #[allow(dead_code)]
+    pub fn depth(&self) -> (usize, usize) {
+        let state = lock!(self.inner.state);
+        (state.running, state.waiting.len() + state.held.len())
+    }
+}
+
+/// Run a message that skips the line on an urgent thread, or line it up for
+/// one if they're all busy. Messages the core sends itself always get in line,
+/// but if too many control requests pile up we turn them away.
+fn skip_line(inner: &Arc<Inner>, msg: Vec<u8>, control: bool) {
+    {
+        let mut state = lock!(inner.state);
+        if state.urgent_running < MAX_URGENT_THREADS {
+            state.urgent_running += 1;
+        } else if !control || state.urgent.len() < inner.hold_limit {
+            state.urgent.push_back(msg);
+            return;
+        } else {
+            drop(state);
+            (inner.reject)(msg, TError::Busy(format!("the core is busy (over {} control requests)", inner.hold_limit)));
+            return;
+        }
+    }
+    spawn(inner.clone(), msg, false);
+}
+
+/// Run a message on its own thread. If it's `counted` (took one of our
+/// running slots), the thread picks up waiting messages after it's done, then
+/// gives its slot back. Otherwise it took an urgent thread, and does the same
+/// with messages waiting on those.
+fn spawn(inner: Arc<Inner>, msg: Vec<u8>, counted: bool) {
+    let inner2 = inner.clone();
+    let res = thread::Builder::new().name(String::from("dispatch:msg")).spawn(move || {
+        let inner = inner2;
+        let mut msg = msg;
+        loop {
+            (inner.process)(msg);
+            if !counted {
+                let next = {
+                    let mut state = lock!(inner.state);
+                    let next = state.urgent.pop_front();
+                    if next.is_none() { state.urgent_running -= 1; }
+                    next
+                };
+                match next {
+                    Some(x) => {
+                        msg = x;
+                        continue;
+                    }
+                    None => return,
+                }
+            }
+            let mut cancelled = Vec::new();
+            let next = {
+                let mut state = lock!(inner.state);
+                if messaging::shutting_down() {
+                    // these would have been turned away if they'd stayed in
+                    // the incoming channel, so turn them away here too
+                    cancelled.extend(state.held.drain(..));
+                } else if let Some(held) = state.held.pop_front() {
+                    // we just made room for one
+                    state.waiting.push_back(held);
+                }
+                let next = state.waiting.pop_front();
+                if next.is_none() { state.running -= 1; }
+                if state.full && state.held.is_empty() && state.running + state.waiting.len() <= inner.capacity / 2 {
+                    state.full = false;
+                }
+                next
+            };
+            for held in cancelled {
+                (inner.reject)(held, TError::Cancelled(String::from("the core is shutting down")));
+            }
+            match next {
+                Some(x) => msg = x,
+                None => return,
+            }
+        }
+    });
+    if let Err(e) = res {
+        error!("messaging::pipeline -- error spawning thread: {}", e);
+        let mut state = lock!(inner.state);
+        if counted {
+            state.running -= 1;
+        } else {
+            state.urgent_running -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::std::sync::mpsc;
+    use ::std::time::Duration;
+
+    #[test]
+    fn applies_backpressure() {
+        // requests hold their slot until we let them go
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let release_rx = Arc::new(Mutex::new(release_rx));
+        let (done_tx, done_rx) = mpsc::channel::<Vec<u8>>();
+        let done_tx = Arc::new(Mutex::new(done_tx));
+        let rejected = Arc::new(Mutex::new(Vec::new()));
+        let rejected2 = rejected.clone();
+        let pipeline = Pipeline::with_limits(1, 2, 16, Policy::DropOldest, move |msg| {
+            lock!(release_rx).recv().unwrap();
+            lock!(done_tx).send(msg).unwrap();
+        }, move |msg, _err| {
+            lock!(rejected2).push(msg);
+        });
+        pipeline.submit(vec![1]);
+        pipeline.submit(vec![2]);
+        assert_eq!(pipeline.depth(), (1, 1));
+        // full: 2 (the oldest waiting) gets dropped for 3
+        pipeline.submit(vec![3]);
+        assert_eq!(*lock!(rejected), vec![vec![2]]);
+        assert_eq!(pipeline.depth(), (1, 1));
+        release_tx.send(()).unwrap();
+        release_tx.send(()).unwrap();
+        assert_eq!(done_rx.recv_timeout(Duration::from_secs(5)).unwrap(), vec![1]);
+        assert_eq!(done_rx.recv_timeout(Duration::from_secs(5)).unwrap(), vec![3]);
+
+        let rejected = Arc::new(Mutex::new(Vec::new()));
+        let rejected2 = rejected.clone();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let release_rx = Arc::new(Mutex::new(release_rx));
+        let pipeline = Pipeline::with_limits(1, 1, 16, Policy::Reject, move |_msg| {
+            lock!(release_rx).recv().unwrap();
+        }, move |msg, _err| {
+            lock!(rejected2).push(msg);
+        });
+        pipeline.submit(vec![1]);
+        pipeline.submit(vec![2]);
+        assert_eq!(*lock!(rejected), vec![vec![2]]);
+        release_tx.send(()).unwrap();
+    }
+
+    #[test]
+    fn blocking_doesnt_block_the_caller() {
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let release_rx = Arc::new(Mutex::new(release_rx));
+        let (done_tx, done_rx) = mpsc::channel::<Vec<u8>>();
+        let done_tx = Arc::new(Mutex::new(done_tx));
+        let rejected = Arc::new(Mutex::new(Vec::new()));
+        let rejected2 = rejected.clone();
+        let cancel = Vec::from(&br#"["9","cancel:1"]"#[..]);
+        let cancel2 = cancel.clone();
+        let pipeline = Pipeline::with_limits(1, 1, 16, Policy::Block, move |msg| {
+            // control messages don't wait on anything
+            if msg != cancel2 { lock!(release_rx).recv().unwrap(); }
+            lock!(done_tx).send(msg).unwrap();
+        }, move |msg, _err| {
+            lock!(rejected2).push(msg);
+        });
+        pipeline.submit(vec![1]);
+        // we're full, but these come right back
+        pipeline.submit(vec![2]);
+        pipeline.submit(vec![3]);
+        assert_eq!(pipeline.depth(), (1, 2));
+        // and cancelling cuts in line
+        pipeline.submit(cancel.clone());
+        assert_eq!(done_rx.recv_timeout(Duration::from_secs(5)).unwrap(), cancel);
+
+        for _ in 0..3 { release_tx.send(()).unwrap(); }
+        assert_eq!(done_rx.recv_timeout(Duration::from_secs(5)).unwrap(), vec![1]);
+        assert_eq!(done_rx.recv_timeout(Duration::from_secs(5)).unwrap(), vec![2]);
+        assert_eq!(done_rx.recv_timeout(Duration::from_secs(5)).unwrap(), vec![3]);
+        assert!(lock!(rejected).is_empty());
+    }
+
+    #[test]
+    fn limits_what_it_holds() {
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let release_rx = Arc::new(Mutex::new(release_rx));
+        let (done_tx, done_rx) = mpsc::channel::<Vec<u8>>();
+        let done_tx = Arc::new(Mutex::new(done_tx));
+        let rejected = Arc::new(Mutex::new(Vec::new()));
+        let rejected2 = rejected.clone();
+        let pipeline = Pipeline::with_limits(1, 1, 2, Policy::Block, move |msg| {
+            lock!(release_rx).recv().unwrap();
+            lock!(done_tx).send(msg).unwrap();
+        }, move |msg, _err| {
+            lock!(rejected2).push(msg);
+        });
+        pipeline.submit(vec![1]);
+        pipeline.submit(vec![2]);
+        pipeline.submit(vec![3]);
+        // we only hold on to two
+        pipeline.submit(vec![4]);
+        assert_eq!(*lock!(rejected), vec![vec![4]]);
+        assert_eq!(pipeline.depth(), (1, 2));
+
+        // control requests get a few threads, then line up (to a point)
+        let cancels = (0..(MAX_URGENT_THREADS + 3))
+            .map(|i| format!(r#"["c{}","cancel:1"]"#, i).into_bytes())
+            .collect::<Vec<_>>();
+        for cancel in &cancels { pipeline.submit(cancel.clone()); }
+        assert_eq!(*lock!(rejected), vec![vec![4], cancels[MAX_URGENT_THREADS + 2].clone()]);
+        {
+            let state = lock!(pipeline.inner.state);
+            assert_eq!(state.urgent_running, MAX_URGENT_THREADS);
+            assert_eq!(state.urgent.len(), 2);
+        }
+
+        let mut expected = vec![vec![1], vec![2], vec![3]];
+        expected.extend(cancels[0..(MAX_URGENT_THREADS + 2)].iter().cloned());
+        for _ in 0..expected.len() { release_tx.send(()).unwrap(); }
+        let mut done = Vec::new();
+        for _ in 0..expected.len() {
+            done.push(done_rx.recv_timeout(Duration::from_secs(5)).unwrap());
+        }
+        done.sort();
+        expected.sort();
+        assert_eq!(done, expected);
+    }
+}
@@ -0,0 +1,141 @@
+//! A stdio transport, for driving the core from scripts, tests, or a parent
+//! process (an Electron main process, say) without any carrier bindings.
+//! Requests come in on stdin, one JSON message per line, and responses and
+//! events go out on stdout the same way. Logs go to stderr instead of stdout
+//! while this transport is on, so they don't get mixed in.
+//!
+//! Closing stdin shuts the core down, once whatever is running has answered.
+
+use ::std::io::{self, BufRead, BufReader, Read, Write};
+use ::std::sync::{Arc, Mutex};
+use ::std::sync::atomic::{AtomicBool, Ordering};
+use ::std::thread;
+use ::std::time::Duration;
+use ::carrier;
+use ::error::TResult;
+use ::messaging::{self, Transport, compress};
+use ::dispatch::cancel;
+
+/// How often (ms) we check whether we're done once stdin closes
+const DRAIN_MILLIS: u64 = 50;
+
+pub struct StdioTransport {
+    out: Mutex<Box<Write + Send>>,
+    running: Arc<AtomicBool>,
+}
+
+impl StdioTransport {
+    /// Start reading requests from stdin into `channel_in`
+    pub fn start(channel_in: String) -> TResult<StdioTransport> {
+        StdioTransport::with_io(io::stdin(), io::stdout(), channel_in, true)
+    }
+
+    /// Read requests from `input` and write to `output`. If `stop_on_close` is
+    /// set, we shut the core down once `input` runs out.
+    fn with_io<R, W>(input: R, output: W, channel_in: String, stop_on_close: bool) -> TResult<StdioTransport>
+        where R: Read + Send + 'static,
+              W: Write + Send + 'static
+    {
+        let running = Arc::new(AtomicBool::new(true));
+        let running2 = running.clone();
+        thread::Builder::new().name(String::from("messaging:stdio")).spawn(move || {
+            let mut reader = BufReader::new(input);
+            loop {
+                let mut line = Vec::new();
+                match reader.read_until(b'\n', &mut line) {
+                    Ok(0) => break,
+                    Ok(_) => {}
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => {
+                        error!("messaging::stdio -- error reading stdin: {}", e);
+                        break;
+                    }
+                }
+                while line.last().map(|x| *x == b'\n' || *x == b'\r').unwrap_or(false) {
+                    line.pop();
+                }
+                if line.iter().all(|x| x.is_ascii_whitespace()) { continue; }
+                trace!("messaging::stdio -- recv ({})", line.len());
+                if messaging::is_forged_event(&line) {
+                    warn!("messaging::stdio -- dropping app event sent by the host");
+                    continue;
+                }
+                match carrier::send(channel_in.as_str(), line) {
+                    Ok(_) => {}
+                    Err(e) => error!("messaging::stdio -- error passing message along: {}", e),
+                }
+            }
+            if !stop_on_close || !running2.load(Ordering::SeqCst) { return; }
+            info!("messaging::stdio -- stdin closed, shutting down once we're idle");
+            // let everything that came in before the close finish up, so the
+            // host gets its answers before we hang up
+            let mut idle = 0;
+            while idle < 2 {
+                thread::sleep(Duration::from_millis(DRAIN_MILLIS));
+                if carrier::pending(channel_in.as_str()) == 0 && cancel::running() == 0 {
+                    idle += 1;
+                } else {
+                    idle = 0;
+                }
+            }
+            if running2.load(Ordering::SeqCst) { messaging::stop(); }
+        })?;
+        Ok(StdioTransport {
+            out: Mutex::new(Box::new(output)),
+            running: running,
+        })
+    }
+}
+
+impl Transport for StdioTransport {
+    fn send(&self, _channel: &str, msg: Vec<u8>) -> TResult<()> {
+        // compressed messages are binary, and would break our line framing
+        let mut msg = if compress::is_compressed(&msg) { compress::decompress(msg)? } else { msg };
+        msg.push(b'\n');
+        let mut out = lock!(self.out);
+        trace!("messaging::stdio -- send ({})", msg.len());
+        out.write_all(msg.as_slice())?;
+        out.flush()?;
+        Ok(())
+    }
+
+    fn shutdown(&self) {
+        self.running.store(false, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Lets the test read what the transport wrote
+    #[derive(Clone)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            lock!(self.0).extend(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> { Ok(()) }
+    }
+
+    #[test]
+    fn talks_stdio() {
+        let channel_in = String::from("inproc://turtl-stdio-test-core-in");
+        let input = io::Cursor::new(Vec::from("[\"1\",\"app:api:get-endpoint\"]\r\n\n  \n::ev{\"e\":\"user:edit\",\"d\":{}}\n[\"2\",\"ping\"]"));
+        let output = Shared(Arc::new(Mutex::new(Vec::new())));
+        let transport = StdioTransport::with_io(input, output.clone(), channel_in.clone(), false).unwrap();
+        let msg = carrier::recv(channel_in.as_str()).unwrap();
+        assert_eq!(String::from_utf8(msg).unwrap(), r#"["1","app:api:get-endpoint"]"#);
+        // the forged app event never makes it to the dispatcher
+        let msg = carrier::recv(channel_in.as_str()).unwrap();
+        assert_eq!(String::from_utf8(msg).unwrap(), r#"["2","ping"]"#);
+        assert_eq!(carrier::pending(channel_in.as_str()), 0);
+
+        transport.send("ignored", Vec::from(r#"{"id":"1","e":0,"d":"hi"}"#)).unwrap();
+        transport.send("ignored", Vec::from(r#"{"e":"sync:connected","d":true}"#)).unwrap();
+        assert_eq!(String::from_utf8(lock!(output.0).clone()).unwrap(), "{\"id\":\"1\",\"e\":0,\"d\":\"hi\"}\n{\"e\":\"sync:connected\",\"d\":true}\n");
+        transport.shutdown();
+    }
+}
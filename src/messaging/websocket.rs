@@ -0,0 +1,390 @@
+//! A WebSocket transport, so desktop/web UIs can talk to the core over
+//! `ws://localhost:PORT` instead of loading us in-process.
+//!
+//! Anything on this machine can open a socket to us (including web pages, via
+//! the browser), so connections have to prove themselves during the handshake
+//! or they're turned away before we read a single message from them:
+//!
+//! - they pass this launch's token, as `?token=<token>` on the URL or in an
+//!   `Authorization: Bearer <token>` header. The token is either handed to us
+//!   by whatever launched us (`messaging.websocket.token`) or made up at
+//!   startup and written to `websocket.token` in the data folder, readable
+//!   only by the user running us.
+//! - if they send an `Origin` header (browsers always do), it has to be in
+//!   `messaging.websocket.origins`.
+//!
+//! We talk to one UI at a time: a new connection takes over from the old one,
+//! but only once it's authenticated. Frames from the UI are dropped into our incoming carrier channel, same as
+//! in-process messages, and our responses/events go out as text frames (or
+//! binary frames, if we're speaking MessagePack).
+
+use ::std::io::{self, Write};
+use ::std::fs;
+use ::std::net::{TcpListener, TcpStream, SocketAddr};
+use ::std::sync::{Arc, Mutex};
+use ::std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use ::std::sync::mpsc::{self, Sender, TryRecvError};
+use ::std::thread;
+use ::std::time::Duration;
+use ::tungstenite::{self, Message};
+use ::tungstenite::http::StatusCode;
+use ::tungstenite::handshake::server::{Request, Response, ErrorResponse};
+use ::carrier;
+use ::jedi::Value;
+use ::error::{TResult, TError};
+use ::messaging::{self, Event, Format, Transport, compress};
+use ::util;
+
+/// How long a new connection has to finish its handshake
+const HANDSHAKE_MILLIS: u64 = 10000;
+
+/// How long we wait on the socket before checking for outgoing messages (and
+/// how often we check for new connections)
+const POLL_MILLIS: u64 = 50;
+
+/// Who's allowed to connect
+#[derive(Debug, Clone)]
+pub struct Auth {
+    /// The token connections have to present
+    pub token: String,
+    /// The `Origin`s we accept connections from. Clients that don't send one
+    /// (anything that isn't a browser) only need the token.
+    pub origins: Vec<String>,
+}
+
+impl Auth {
+    /// Look over a handshake request, returning why we're rejecting it (if
+    /// we are)
+    fn check(&self, req: &Request) -> Result<(), (StatusCode, &'static str)> {
+        if let Some(origin) = req.headers().get("origin") {
+            let allowed = origin.to_str()
+                .map(|origin| self.origins.iter().any(|x| x == origin))
+                .unwrap_or(false);
+            if !allowed {
+                return Err((StatusCode::FORBIDDEN, "origin not allowed"));
+            }
+        }
+        let from_query = req.uri().query()
+            .and_then(|query| query.split('&').find(|x| x.starts_with("token=")))
+            .map(|x| &x[6..]);
+        let from_header = req.headers().get("authorization")
+            .and_then(|x| x.to_str().ok())
+            .filter(|x| x.starts_with("Bearer "))
+            .map(|x| &x[7..]);
+        match from_query.or(from_header) {
+            Some(token) if same(token.as_bytes(), self.token.as_bytes()) => Ok(()),
+            _ => Err((StatusCode::UNAUTHORIZED, "bad or missing token")),
+        }
+    }
+}
+
+/// Compare two strings without bailing at the first difference, so the time
+/// it takes doesn't give the token away
+fn same(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() { return false; }
+    a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Write a token we made up to the data folder, so local UIs can find it
+pub fn write_token(token: &String) -> TResult<()> {
+    let path = util::paths::data_path(&["websocket.token"])?;
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use ::std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(&path)?;
+    file.write_all(token.as_bytes())?;
+    Ok(())
+}
+
+pub struct WebSocketTransport {
+    /// The address we're listening on
+    addr: SocketAddr,
+    /// Feeds outgoing messages to the current connection, if there is one
+    outgoing: Arc<Mutex<Option<Sender<Vec<u8>>>>>,
+    running: Arc<AtomicBool>,
+}
+
+impl WebSocketTransport {
+    /// Start listening for UI connections on the given address. Messages from
+    /// the UI are sent into `channel_in`. If `binary` is set, we send binary
+    /// frames instead of text.
+    pub fn bind(address: &str, channel_in: String, binary: bool, auth: Auth) -> TResult<WebSocketTransport> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        let addr = listener.local_addr()?;
+        let outgoing: Arc<Mutex<Option<Sender<Vec<u8>>>>> = Arc::new(Mutex::new(None));
+        let running = Arc::new(AtomicBool::new(true));
+        let outgoing2 = outgoing.clone();
+        let running2 = running.clone();
+        let auth = Arc::new(auth);
+        thread::Builder::new().name(String::from("messaging:websocket")).spawn(move || {
+            let current = Arc::new(AtomicUsize::new(0));
+            let mut next_id = 0;
+            while running2.load(Ordering::SeqCst) {
+                match listener.accept() {
+                    Ok((stream, peer)) => {
+                        next_id += 1;
+                        info!("messaging::websocket -- connection {} from {}", next_id, peer);
+                        let conn = Connection {
+                            id: next_id,
+                            current: current.clone(),
+                            running: running2.clone(),
+                            outgoing: outgoing2.clone(),
+                            channel_in: channel_in.clone(),
+                            binary: binary,
+                            auth: auth.clone(),
+                        };
+                        let res = thread::Builder::new().name(String::from("messaging:websocket:conn")).spawn(move || {
+                            match conn.serve(stream) {
+                                Ok(_) => info!("messaging::websocket -- connection {} closed", conn.id),
+                                Err(e) => error!("messaging::websocket -- connection {} failed: {}", conn.id, e),
+                            }
+                            conn.hang_up();
+                        });
+                        if let Err(e) = res {
+                            error!("messaging::websocket -- error spawning connection thread: {}", e);
+                        }
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        thread::sleep(Duration::from_millis(POLL_MILLIS));
+                    }
+                    Err(e) => {
+                        error!("messaging::websocket -- error accepting connection: {}", e);
+                        thread::sleep(Duration::from_millis(POLL_MILLIS));
+                    }
+                }
+            }
+            info!("messaging::websocket -- no longer listening on {}", addr);
+        })?;
+        Ok(WebSocketTransport {
+            addr: addr,
+            outgoing: outgoing,
+            running: running,
+        })
+    }
+
+    /// Where we're listening
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Transport for WebSocketTransport {
+    fn send(&self, _channel: &str, msg: Vec<u8>) -> TResult<()> {
+        let guard = lock!(self.outgoing);
+        match guard.as_ref() {
+            Some(tx) => {
+                // if the connection just went away, there's nobody to tell
+                if tx.send(msg).is_err() {
+                    debug!("messaging::websocket -- connection closed, dropping message");
+                }
+            }
+            None => debug!("messaging::websocket -- no UI connected, dropping message"),
+        }
+        Ok(())
+    }
+
+    fn shutdown(&self) {
+        self.running.store(false, Ordering::SeqCst);
+    }
+}
+
+/// One UI connection
+struct Connection {
+    id: usize,
+    /// The id of the newest connection. If it's not us, we're done.
+    current: Arc<AtomicUsize>,
+    running: Arc<AtomicBool>,
+    outgoing: Arc<Mutex<Option<Sender<Vec<u8>>>>>,
+    channel_in: String,
+    binary: bool,
+    auth: Arc<Auth>,
+}
+
+impl Connection {
+    fn active(&self) -> bool {
+        self.running.load(Ordering::SeqCst) && self.current.load(Ordering::SeqCst) == self.id
+    }
+
+    /// Wrap an outgoing message in the right kind of frame (compressed
+    /// messages always go out as binary)
+    fn frame(&self, msg: Vec<u8>) -> TResult<Message> {
+        if self.binary || compress::is_compressed(&msg) {
+            Ok(Message::binary(msg))
+        } else {
+            Ok(Message::text(String::from_utf8(msg)?))
+        }
+    }
+
+    /// Pass messages back and forth until one side hangs up (or someone else
+    /// connects)
+    fn serve(&self, stream: TcpStream) -> TResult<()> {
+        // accepted sockets can inherit the listener's non-blocking mode
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(Duration::from_millis(HANDSHAKE_MILLIS)))?;
+        let auth = self.auth.clone();
+        let check = move |req: &Request, res: Response| -> Result<Response, ErrorResponse> {
+            match auth.check(req) {
+                Ok(_) => Ok(res),
+                Err((status, reason)) => {
+                    let mut err = ErrorResponse::new(Some(String::from(reason)));
+                    *err.status_mut() = status;
+                    Err(err)
+                }
+            }
+        };
+        let mut ws = tungstenite::server::accept_hdr(stream, check)
+            .map_err(|e| TError::Msg(format!("websocket handshake failed: {}", e)))?;
+        ws.get_ref().set_read_timeout(Some(Duration::from_millis(POLL_MILLIS)))?;
+        // we're in. take over from whoever was connected before us.
+        let (tx, rx) = mpsc::channel();
+        {
+            let mut outgoing_guard = lock!(self.outgoing);
+            *outgoing_guard = Some(tx);
+            self.current.store(self.id, Ordering::SeqCst);
+        }
+        let ready = Event { e: String::from("messaging:ready"), d: Value::Bool(true) };
+        let format = if self.binary { Format::MsgPack } else { Format::Json };
+        ws.write_message(self.frame(messaging::encode(format, &ready)?)?)?;
+        loop {
+            if !self.active() {
+                let _ = ws.close(None);
+                let _ = ws.write_pending();
+                return Ok(());
+            }
+            match ws.read_message() {
+                Ok(Message::Text(x)) => {
+                    trace!("messaging::websocket -- recv ({})", x.len());
+                    if messaging::is_forged_event(x.as_bytes()) {
+                        warn!("messaging::websocket -- dropping app event sent by a client");
+                        continue;
+                    }
+                    carrier::send_string(self.channel_in.as_str(), x)?;
+                }
+                Ok(Message::Binary(x)) => {
+                    trace!("messaging::websocket -- recv ({})", x.len());
+                    if messaging::is_forged_event(&x) {
+                        warn!("messaging::websocket -- dropping app event sent by a client");
+                        continue;
+                    }
+                    carrier::send(self.channel_in.as_str(), x)?;
+                }
+                Ok(Message::Close(_)) => return Ok(()),
+                Ok(_) => {}
+                Err(tungstenite::Error::Io(ref e)) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => {}
+                Err(tungstenite::Error::ConnectionClosed) | Err(tungstenite::Error::AlreadyClosed) => return Ok(()),
+                Err(e) => return Err(From::from(e)),
+            }
+            loop {
+                match rx.try_recv() {
+                    Ok(msg) => {
+                        trace!("messaging::websocket -- send ({})", msg.len());
+                        ws.write_message(self.frame(msg)?)?;
+                    }
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => return Ok(()),
+                }
+            }
+        }
+    }
+
+    /// Stop routing messages to this connection (unless someone else already
+    /// took over)
+    fn hang_up(&self) {
+        let mut outgoing_guard = lock!(self.outgoing);
+        if self.current.load(Ordering::SeqCst) == self.id {
+            *outgoing_guard = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn auth() -> Auth {
+        Auth {
+            token: String::from("0123456789abcdef"),
+            origins: vec![String::from("http://localhost:8181")],
+        }
+    }
+
+    #[test]
+    fn talks_websocket() {
+        let channel_in = String::from("inproc://turtl-ws-test-core-in");
+        let transport = WebSocketTransport::bind("127.0.0.1:0", channel_in.clone(), false, auth()).unwrap();
+        let url = format!("ws://{}/?token=0123456789abcdef", transport.local_addr());
+        let (mut client, _) = tungstenite::client::connect(url.as_str()).unwrap();
+        assert_eq!(client.read_message().unwrap(), Message::text(r#"{"e":"messaging:ready","d":true}"#));
+
+        client.write_message(Message::text(r#"["1","app:api:get-endpoint"]"#)).unwrap();
+        let msg = carrier::recv(channel_in.as_str()).unwrap();
+        assert_eq!(String::from_utf8(msg).unwrap(), r#"["1","app:api:get-endpoint"]"#);
+
+        transport.send("ignored", Vec::from(r#"{"id":"1","e":0,"d":"hi"}"#)).unwrap();
+        assert_eq!(client.read_message().unwrap(), Message::text(r#"{"id":"1","e":0,"d":"hi"}"#));
+        transport.shutdown();
+    }
+
+    #[test]
+    fn drops_forged_app_events() {
+        let channel_in = String::from("inproc://turtl-ws-test-forged-core-in");
+        let transport = WebSocketTransport::bind("127.0.0.1:0", channel_in.clone(), false, auth()).unwrap();
+        let url = format!("ws://{}/?token=0123456789abcdef", transport.local_addr());
+        let (mut client, _) = tungstenite::client::connect(url.as_str()).unwrap();
+        assert_eq!(client.read_message().unwrap(), Message::text(r#"{"e":"messaging:ready","d":true}"#));
+
+        client.write_message(Message::text(r#"::ev{"e":"user:edit","d":{}}"#)).unwrap();
+        client.write_message(Message::binary(Vec::from(r#"::ev{"e":"space:delete","d":{}}"#))).unwrap();
+        client.write_message(Message::text(r#"["1","ping"]"#)).unwrap();
+        // frames are handled in order, so if the events had gotten through
+        // they'd be waiting ahead of the ping
+        let msg = carrier::recv(channel_in.as_str()).unwrap();
+        assert_eq!(String::from_utf8(msg).unwrap(), r#"["1","ping"]"#);
+        assert_eq!(carrier::pending(channel_in.as_str()), 0);
+        transport.shutdown();
+    }
+
+    #[test]
+    fn turns_away_strangers() {
+        let channel_in = String::from("inproc://turtl-ws-test-auth-core-in");
+        let transport = WebSocketTransport::bind("127.0.0.1:0", channel_in.clone(), false, auth()).unwrap();
+        let base = format!("ws://{}/", transport.local_addr());
+        let connect = |url: String, origin: Option<&str>, bearer: Option<&str>| {
+            let mut req = tungstenite::http::Request::builder().uri(url.as_str());
+            if let Some(origin) = origin { req = req.header("Origin", origin); }
+            if let Some(bearer) = bearer { req = req.header("Authorization", format!("Bearer {}", bearer)); }
+            tungstenite::client::connect(req.body(()).unwrap())
+        };
+        let status = |res: tungstenite::Result<_>| match res {
+            Err(tungstenite::Error::Http(status)) => status,
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("connected when we shouldn't have"),
+        };
+
+        let (mut client, _) = connect(format!("{}?token=0123456789abcdef", base), Some("http://localhost:8181"), None).unwrap();
+        assert_eq!(client.read_message().unwrap(), Message::text(r#"{"e":"messaging:ready","d":true}"#));
+
+        assert_eq!(status(connect(base.clone(), None, None)), StatusCode::UNAUTHORIZED);
+        assert_eq!(status(connect(format!("{}?token=0123456789abcdee", base), None, None)), StatusCode::UNAUTHORIZED);
+        assert_eq!(status(connect(base.clone(), None, Some("0123456789abcde"))), StatusCode::UNAUTHORIZED);
+        assert_eq!(status(connect(format!("{}?token=0123456789abcdef", base), Some("http://evil.example.com"), None)), StatusCode::FORBIDDEN);
+        // a socket that never even finishes the handshake
+        let _lurker = TcpStream::connect(transport.local_addr()).unwrap();
+
+        // none of that knocked our UI off
+        transport.send("ignored", Vec::from(r#"{"id":"2","e":0,"d":"still here"}"#)).unwrap();
+        assert_eq!(client.read_message().unwrap(), Message::text(r#"{"id":"2","e":0,"d":"still here"}"#));
+
+        // but a real client takes over
+        let (mut client2, _) = connect(base.clone(), None, Some("0123456789abcdef")).unwrap();
+        assert_eq!(client2.read_message().unwrap(), Message::text(r#"{"e":"messaging:ready","d":true}"#));
+        transport.send("ignored", Vec::from(r#"{"id":"3","e":0,"d":"new"}"#)).unwrap();
+        assert_eq!(client2.read_message().unwrap(), Message::text(r#"{"id":"3","e":0,"d":"new"}"#));
+        transport.shutdown();
+    }
+}
@@ -1,16 +1,31 @@
-use ::jedi::Value;
+use ::jedi::{self, Value};
 
-use ::error::TResult;
+use ::error::{TResult, TError};
 use ::crypto::Key;
 use ::models::model::Model;
 use ::models::validate::{self, Validate};
 use ::models::protected::{Keyfinder, Protected};
 use ::models::note::Note;
+use ::models::space::Space;
+use ::models::board_member::BoardMember;
+use ::models::invite::{Invite, InviteRequest};
+use ::models::keychain;
+use ::profile::Profile;
 use ::models::keychain::{Keychain, KeyRef, KeyType};
 use ::models::sync_record::{SyncRecord, SyncAction};
 use ::turtl::Turtl;
 use ::sync::sync_model::{self, SyncModel, MemorySaver};
 use ::models::storable::Storable;
+use ::ordering::Sortable;
+use ::messaging;
+use ::lib_permissions::Permission;
+
+/// A column on a kanban-style board
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BoardColumn {
+    pub id: String,
+    pub title: String,
+}
 
 protected! {
     #[derive(Serialize, Deserialize)]
@@ -23,10 +38,27 @@ protected! {
         #[serde(skip_serializing_if = "Option::is_none")]
         #[protected_field(public)]
         pub meta: Option<Value>,
+        /// Users this board has been shared with directly (as opposed to
+        /// through the board's space)
+        #[serde(default)]
+        #[protected_field(public)]
+        pub members: Vec<BoardMember>,
+        #[serde(default)]
+        #[protected_field(public)]
+        pub invites: Vec<Invite>,
 
         #[serde(skip_serializing_if = "Option::is_none")]
         #[protected_field(private)]
         pub title: Option<String>,
+        /// Whether notes in this board may be sent (in plaintext) to outside
+        /// services, such as webhooks
+        #[serde(skip_serializing_if = "Option::is_none")]
+        #[protected_field(private)]
+        pub exportable: Option<bool>,
+        /// Kanban columns, in order. Notes say which one they're in.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        #[protected_field(private)]
+        pub columns: Option<Vec<BoardColumn>>,
     }
 }
 
@@ -42,10 +74,25 @@ impl Validate for Board {
         if self.title.as_ref().map(|x| x == "").unwrap_or(true) {
             errors.push(validate::entry("title", t!("Please give your board a title")));
         }
+        if let Some(ref columns) = self.columns {
+            let mut seen: Vec<&String> = Vec::with_capacity(columns.len());
+            for col in columns {
+                if col.id == "" || seen.contains(&&col.id) {
+                    errors.push(validate::entry("columns", t!("Each board column needs its own id")));
+                    break;
+                }
+                seen.push(&col.id);
+            }
+        }
         errors
     }
 }
 
+impl Sortable for Board {
+    fn sort_id(&self) -> Option<&String> { self.id() }
+    fn sort_title(&self) -> Option<&String> { self.title.as_ref() }
+}
+
 impl Board {
     /// Move a note to a different space
     pub fn move_spaces(&mut self, turtl: &Turtl, new_space_id: String) -> TResult<()> {
@@ -73,6 +120,329 @@ impl Board {
         Ok(())
     }
 
+    /// Move a board and all of its notes to another space as a single unit.
+    ///
+    /// Unlike `move_spaces()`, which saves the board and then each note one at
+    /// a time, we re-wrap every key and encrypt everything up front, then write
+    /// the models and queue their outgoing syncs inside one db transaction. If
+    /// anything fails along the way, nothing gets written. Progress is sent to
+    /// the UI via `board:move-to-space:progress` events.
+    pub fn move_to_space(turtl: &Turtl, board_id: &String, new_space_id: String) -> TResult<Board> {
+        let from_space_id = match Board::get_space_id(turtl, board_id) {
+            Some(id) => id,
+            None => return TErr!(TError::MissingData(format!("cannot find space id for board {}", board_id))),
+        };
+        Space::permission_check(turtl, &from_space_id, &Permission::DeleteBoard)?;
+        Space::permission_check(turtl, &new_space_id, &Permission::AddBoard)?;
+
+        let (mut board, note_ids) = {
+            let db_guard = lock!(turtl.db);
+            let db = match (*db_guard).as_ref() {
+                Some(x) => x,
+                None => return TErr!(TError::MissingField(String::from("Turtl.db"))),
+            };
+            let mut board: Board = match db.get(Board::tablename(), board_id)? {
+                Some(x) => x,
+                None => return TErr!(TError::MissingData(format!("cannot find Board {} in profile", board_id))),
+            };
+            turtl.find_model_key(&mut board)?;
+            board.deserialize()?;
+            let notes: Vec<Note> = db.find("notes", "board_id", &vec![board_id.clone()])?;
+            let note_ids = notes.iter()
+                .filter_map(|x| x.id().map(|id| id.clone()))
+                .collect::<Vec<String>>();
+            (board, note_ids)
+        };
+        if board.space_id == new_space_id {
+            return Ok(board);
+        }
+        let notes = turtl.load_notes(&note_ids)?;
+        let total = notes.len() + 1;
+        let progress = |stage: &str, done: usize| -> TResult<()> {
+            messaging::ui_event("board:move-to-space:progress", &json!({
+                "board_id": board_id,
+                "stage": stage,
+                "done": done,
+                "total": total,
+            }))
+        };
+
+        /// Point a model at its new space: re-wrap its key with the new keyrefs
+        /// and re-encrypt it on the worker pool.
+        fn rewrap<T>(turtl: &Turtl, model: &mut T) -> TResult<()>
+            where T: Protected + Keyfinder + Validate + Sync + Send + 'static
+        {
+            model.do_validate(model.model_type())?;
+            turtl.find_model_key(model)?;
+            let keyrefs = model.get_keyrefs(turtl)?;
+            model.generate_subkeys(&keyrefs)?;
+            let mut model2: T = model.clone()?;
+            let serialized: Value = turtl.work.run(move || Protected::serialize(&mut model2))?;
+            model.merge_fields(&serialized)?;
+            Ok(())
+        }
+
+        // phase 1: re-wrap/encrypt everything in memory. nothing is saved yet,
+        // so bailing out here leaves the profile untouched.
+        board.space_id = new_space_id.clone();
+        rewrap(turtl, &mut board)?;
+        progress("prepare", 1)?;
+        let mut moved: Vec<Note> = Vec::with_capacity(notes.len());
+        for (i, mut note) in notes.into_iter().enumerate() {
+            note.space_id = new_space_id.clone();
+            note.board_id = Some(board_id.clone());
+            rewrap(turtl, &mut note)?;
+            moved.push(note);
+            if (i + 1) % 25 == 0 { progress("prepare", i + 2)?; }
+        }
+        progress("prepare", total)?;
+
+        // phase 2: save the models and queue their syncs all at once. we hold
+        // the db for the whole transaction, so the outgoing syncer never sees
+        // a half-moved board.
+        let user_id = turtl.user_id()?;
+        with_db!{ db, turtl.db,
+            db.transaction(|db| {
+                board.outgoing(SyncAction::MoveSpace, &user_id, db, false)?;
+                for note in &moved {
+                    note.outgoing(SyncAction::MoveSpace, &user_id, db, false)?;
+                }
+                Ok(())
+            })?;
+        };
+        progress("save", total)?;
+
+        // phase 3: the data is safe, now bring our in-mem profile/index up to
+        // date
+        board.clone()?.run_mem_update(turtl, SyncAction::Edit)?;
+        for note in moved {
+            note.run_mem_update(turtl, SyncAction::MoveSpace)?;
+        }
+        Ok(board)
+    }
+
+    /// Move a note into a kanban column on its board, at the given position
+    /// (clamped to the end of the column). The notes in that column are
+    /// renumbered to make room, and every note that changed is saved and
+    /// queued for sync in one db transaction, so other clients never see two
+    /// notes fighting over the same spot.
+    pub fn move_note_column(turtl: &Turtl, board_id: &String, note_id: &String, column_id: &String, position: usize) -> TResult<Vec<Note>> {
+        let space_id = match Board::get_space_id(turtl, board_id) {
+            Some(id) => id,
+            None => return TErr!(TError::MissingData(format!("cannot find space id for board {}", board_id))),
+        };
+        Board::permission_check(turtl, &space_id, Some(board_id), &Permission::EditNote)?;
+        let has_column = {
+            let profile_guard = lockr!(turtl.profile);
+            profile_guard.boards.iter()
+                .filter(|board| board.id() == Some(board_id))
+                .filter_map(|board| board.columns.as_ref())
+                .any(|columns| columns.iter().any(|col| &col.id == column_id))
+        };
+        if !has_column {
+            return TErr!(TError::NotFound(format!("board {} has no column {}", board_id, column_id)));
+        }
+
+        let note_ids = {
+            let db_guard = lock!(turtl.db);
+            let notes: Vec<Note> = match *db_guard {
+                Some(ref db) => db.find("notes", "board_id", &vec![board_id.clone()])?,
+                None => vec![],
+            };
+            notes.iter()
+                .filter_map(|x| x.id().map(|id| id.clone()))
+                .collect::<Vec<String>>()
+        };
+        if !note_ids.contains(note_id) {
+            return TErr!(TError::NotFound(format!("note {} isn't in board {}", note_id, board_id)));
+        }
+        let notes = turtl.load_notes(&note_ids)?;
+        let (mut moving, others): (Vec<Note>, Vec<Note>) = notes.into_iter()
+            .partition(|x| x.id() == Some(note_id));
+        let mut note = match moving.pop() {
+            Some(x) => x,
+            None => return TErr!(TError::NotFound(format!("note {} wasn't found", note_id))),
+        };
+        let mut column = others.into_iter()
+            .filter(|x| x.column.as_ref() == Some(column_id))
+            .collect::<Vec<Note>>();
+        // notes without a position go after the ones that have one
+        column.sort_by_key(|x| (x.column_position.unwrap_or(::std::i64::MAX), x.id().cloned()));
+        note.column = Some(column_id.clone());
+        let position = if position > column.len() { column.len() } else { position };
+        column.insert(position, note);
+
+        let mut changed: Vec<Note> = Vec::new();
+        for (i, mut note) in column.into_iter().enumerate() {
+            let is_moving = note.id() == Some(note_id);
+            if !is_moving && note.column_position == Some(i as i64) { continue; }
+            note.column_position = Some(i as i64);
+            note.do_validate(note.model_type())?;
+            turtl.find_model_key(&mut note)?;
+            let mut note2: Note = note.clone()?;
+            let serialized: Value = turtl.work.run(move || Protected::serialize(&mut note2))?;
+            note.merge_fields(&serialized)?;
+            changed.push(note);
+        }
+
+        let user_id = turtl.user_id()?;
+        with_db!{ db, turtl.db,
+            db.transaction(|db| {
+                for note in &changed {
+                    note.outgoing(SyncAction::Edit, &user_id, db, false)?;
+                }
+                Ok(())
+            })?;
+        };
+        let mut saved = Vec::with_capacity(changed.len());
+        for note in changed {
+            saved.push(note.clone()?);
+            note.run_mem_update(turtl, SyncAction::Edit)?;
+        }
+        Ok(saved)
+    }
+
+    /// Check if the current user has a permission on a board (or on an item in
+    /// that board). Access through the board's space covers everything, but if
+    /// the user only has the board shared with them, we go by their role on the
+    /// board itself.
+    pub fn permission_check(turtl: &Turtl, space_id: &String, board_id: Option<&String>, permission: &Permission) -> TResult<()> {
+        let space_check = Space::permission_check(turtl, space_id, permission);
+        if space_check.is_ok() { return space_check; }
+        let board_id = match board_id {
+            Some(x) => x,
+            None => return space_check,
+        };
+        let user_id = turtl.user_id()?;
+        let profile_guard = lockr!(turtl.profile);
+        let allowed = profile_guard.boards.iter()
+            .filter(|board| board.id() == Some(board_id))
+            .any(|board| board.can_i(&user_id, permission));
+        if allowed { Ok(()) } else { space_check }
+    }
+
+    /// Checks if a user has the given permission via this board's members
+    pub fn can_i(&self, user_id: &String, permission: &Permission) -> bool {
+        self.members.iter()
+            .filter(|member| &member.user_id == user_id)
+            .any(|member| member.role.can(permission))
+    }
+
+    /// Share this board (and only this board) with someone. The invite carries
+    /// the board key, never the space key.
+    pub fn send_invite(turtl: &Turtl, board_id: &String, mut invite_request: InviteRequest) -> TResult<Board> {
+        turtl.assert_connected()?;
+        let space_id = match Board::get_space_id(turtl, board_id) {
+            Some(id) => id,
+            None => return TErr!(TError::MissingData(format!("cannot find space id for board {}", board_id))),
+        };
+        // only members of the space can share its boards
+        Space::permission_check(turtl, &space_id, &Permission::AddSpaceInvite)?;
+        let (user_id, username) = {
+            let user_guard = lockr!(turtl.user);
+            let user_id = user_guard.id_or_else()?;
+            (user_id, user_guard.username.clone())
+        };
+        let mut profile_guard = lockw!(turtl.profile);
+        let board = match Profile::finder(&mut profile_guard.boards, board_id) {
+            Some(b) => b,
+            None => return TErr!(TError::MissingData(format!("couldn't find board {}", board_id))),
+        };
+        if board.members.iter().any(|x| x.username == invite_request.to_user) {
+            return TErr!(TError::BadValue(format!("{} is already a member of this board", invite_request.to_user)));
+        }
+        if board.invites.iter().any(|x| x.to_user == invite_request.to_user) {
+            return TErr!(TError::BadValue(format!("{} is already invited to this board", invite_request.to_user)));
+        }
+        let board_key = board.key_or_else()?;
+        invite_request.space_id = space_id;
+        invite_request.board_id = Some(board_id.clone());
+        let invite = Invite::from_invite_request(&user_id, &username, &board_key, invite_request)?;
+        invite.send(turtl)?;
+        board.invites.push(invite);
+        board.clone()
+    }
+
+    /// Delete an invite on this board
+    pub fn delete_invite(turtl: &Turtl, board_id: &String, invite_id: &String) -> TResult<Board> {
+        turtl.assert_connected()?;
+        let space_id = match Board::get_space_id(turtl, board_id) {
+            Some(id) => id,
+            None => return TErr!(TError::MissingData(format!("cannot find space id for board {}", board_id))),
+        };
+        Space::permission_check(turtl, &space_id, &Permission::DeleteSpaceInvite)?;
+        let mut profile_guard = lockw!(turtl.profile);
+        let board = match Profile::finder(&mut profile_guard.boards, board_id) {
+            Some(b) => b,
+            None => return TErr!(TError::MissingData(format!("couldn't find board {}", board_id))),
+        };
+        match board.invites.iter().find(|x| x.id() == Some(invite_id)) {
+            Some(invite) => invite.delete(turtl)?,
+            None => return TErr!(TError::NotFound(format!("invite {} doesn't exist in this board", invite_id))),
+        }
+        board.invites.retain(|x| x.id() != Some(invite_id));
+        board.clone()
+    }
+
+    /// Remove a member from this board
+    pub fn delete_member(turtl: &Turtl, board_id: &String, member_user_id: &String) -> TResult<Board> {
+        turtl.assert_connected()?;
+        let space_id = match Board::get_space_id(turtl, board_id) {
+            Some(id) => id,
+            None => return TErr!(TError::MissingData(format!("cannot find space id for board {}", board_id))),
+        };
+        // members can always remove themselves (aka leave the board)
+        if member_user_id != &turtl.user_id()? {
+            Space::permission_check(turtl, &space_id, &Permission::DeleteSpaceMember)?;
+        }
+        let mut profile_guard = lockw!(turtl.profile);
+        let board = match Profile::finder(&mut profile_guard.boards, board_id) {
+            Some(b) => b,
+            None => return TErr!(TError::MissingData(format!("couldn't find board {}", board_id))),
+        };
+        match board.members.iter().find(|x| &x.user_id == member_user_id) {
+            Some(member) => member.delete(turtl)?,
+            None => return TErr!(TError::NotFound(format!("user {} is not a member of this board", member_user_id))),
+        }
+        board.members.retain(|x| &x.user_id != member_user_id);
+        board.clone()
+    }
+
+    /// Accept an invite to a board (static)
+    pub fn accept_invite(turtl: &Turtl, invite: &mut Invite, passphrase: Option<String>) -> TResult<Board> {
+        turtl.assert_connected()?;
+        model_getter!(get_field, "Board.accept_invite()");
+        let invite_id = get_field!(invite, id);
+        let board_id = get_field!(invite, board_id);
+        {
+            let user_guard = lockr!(turtl.user);
+            let pubkey = match user_guard.pubkey.as_ref() {
+                Some(k) => k,
+                None => return TErr!(TError::MissingField(String::from("User.pubkey"))),
+            };
+            let privkey = match user_guard.privkey.as_ref() {
+                Some(k) => k,
+                None => return TErr!(TError::MissingField(String::from("User.privkey"))),
+            };
+            invite.open(pubkey, privkey, passphrase)?;
+        }
+        let keyjson = match invite.message.as_ref() {
+            Some(data) => jedi::parse(&String::from_utf8(data.clone())?)?,
+            None => return TErr!(TError::MissingField(String::from("Invite.message"))),
+        };
+        let key: Key = jedi::get(&["board_key"], &keyjson)?;
+        let boarddata = invite.accept(turtl)?;
+        // we don't have access to the board's space, so the keychain is the
+        // only place this key lives. save it before anything else.
+        keychain::save_key(turtl, &board_id, &key, &String::from("board"), false)?;
+        let mut board: Board = jedi::from_val(boarddata)?;
+        board.set_key(Some(key));
+        board.deserialize()?;
+        sync_model::save_model(SyncAction::Add, turtl, &mut board, true)?;
+        sync_model::delete_model::<Invite>(turtl, &invite_id, true)?;
+        Ok(board)
+    }
+
     /// Given a Turtl/board_id, grab that boards's space_id (if it exists)
     pub fn get_space_id(turtl: &Turtl, board_id: &String) -> Option<String> {
         let mut db_guard = lock!(turtl.db);
@@ -149,7 +519,6 @@ impl MemorySaver for Board {
                 profile_guard.boards.push(self);
             }
             SyncAction::Delete => {
-                let mut profile_guard = lockw!(turtl.profile);
                 let board_id = self.id().expect("turtl::Board.mem_update() -- delete -- self.id() is None. HOW CAN I DELETE IT IF ITS NONE?!!");
 
                 let notes: Vec<Note> = {
@@ -169,7 +538,9 @@ impl MemorySaver for Board {
                     };
                     sync_model::delete_model::<Note>(turtl, &note_id, true)?;
                 }
-                // remove the board from memory
+                // remove the board from memory. we grab the profile lock
+                // down here since deleting notes above needs it too
+                let mut profile_guard = lockw!(turtl.profile);
                 profile_guard.boards.retain(|b| b.id() != Some(&board_id));
             }
             _ => {}
@@ -178,3 +549,109 @@ impl MemorySaver for Board {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::error::ErrorCode;
+    use ::lib_permissions::Role;
+    use ::models::sync_record::SyncType;
+
+    const SPACE_ID: &'static str = "015bac22440a4944baee41b88207731eaeb7e2cc5c955fb8a05b028c1409aaf55024f5d26fa3001e";
+
+    /// Put a board in the profile (and the db) that user 51 is a member of
+    fn shared_board(turtl: &Turtl, role: Option<Role>) -> String {
+        let mut board = Board::new();
+        board.generate_id().unwrap();
+        board.space_id = String::from(SPACE_ID);
+        board.user_id = String::from("12");
+        let board_id = board.id().unwrap().clone();
+        if let Some(role) = role {
+            let mut member = BoardMember::default();
+            member.user_id = turtl.user_id().unwrap();
+            member.board_id = board_id.clone();
+            member.role = role;
+            board.members.push(member);
+        }
+        lock!(turtl.db).as_ref().unwrap().save(&board).unwrap();
+        lockw!(turtl.profile).boards.push(board);
+        board_id
+    }
+
+    #[test]
+    fn checks_board_permissions() {
+        let turtl = ::turtl::tests::with_test(true);
+        let space_id = String::from(SPACE_ID);
+        let member_of = shared_board(&turtl, Some(Role::Member));
+        let guest_of = shared_board(&turtl, Some(Role::Guest));
+        let not_in = shared_board(&turtl, None);
+
+        Board::permission_check(&turtl, &space_id, Some(&member_of), &Permission::EditNote).unwrap();
+        Board::permission_check(&turtl, &space_id, Some(&member_of), &Permission::AddNote).unwrap();
+        let denied = |board_id: Option<&String>, permission: Permission| {
+            let err = Board::permission_check(&turtl, &space_id, board_id, &permission).unwrap_err();
+            assert_eq!(err.code(), ErrorCode::PermissionDenied);
+        };
+        denied(Some(&guest_of), Permission::EditNote);
+        denied(Some(&not_in), Permission::EditNote);
+        // board members get nothing on the space itself
+        denied(None, Permission::AddNote);
+        denied(Some(&member_of), Permission::AddSpaceInvite);
+    }
+
+    #[test]
+    fn cant_pull_notes_into_a_shared_board() {
+        let turtl = ::turtl::tests::with_test(true);
+        let member_of = shared_board(&turtl, Some(Role::Member));
+        let not_in = shared_board(&turtl, None);
+        let mut note = Note::new();
+        note.generate_id().unwrap();
+        note.space_id = String::from(SPACE_ID);
+        note.board_id = Some(not_in.clone());
+        note.user_id = String::from("12");
+        let note_id = note.id().unwrap().clone();
+        lock!(turtl.db).as_ref().unwrap().save(&note).unwrap();
+
+        // the note says it's in our board, but it isn't
+        let run = |ty: SyncType, action: SyncAction, data: Value| {
+            let mut sync_record = SyncRecord::default();
+            sync_record.ty = ty;
+            sync_record.action = action;
+            sync_record.data = Some(data);
+            sync_model::dispatch(&turtl, sync_record).unwrap_err().code()
+        };
+        let data = json!({"id": note_id, "space_id": SPACE_ID, "board_id": member_of, "user_id": "12"});
+        assert_eq!(run(SyncType::Note, SyncAction::Edit, data), ErrorCode::PermissionDenied);
+        assert_eq!(run(SyncType::Note, SyncAction::Delete, json!({"id": note_id})), ErrorCode::PermissionDenied);
+        // same goes for claiming a board lives in a space we own
+        let mut space = Space::new();
+        space.generate_id().unwrap();
+        space.user_id = turtl.user_id().unwrap();
+        let data = json!({"id": not_in, "space_id": space.id().unwrap(), "user_id": "12"});
+        lockw!(turtl.profile).spaces.push(space);
+        assert_eq!(run(SyncType::Board, SyncAction::Edit, data), ErrorCode::PermissionDenied);
+    }
+
+    #[test]
+    fn sharing_needs_a_connection() {
+        let turtl = ::turtl::tests::with_test(true);
+        let board_id = shared_board(&turtl, Some(Role::Member));
+        let req = InviteRequest {
+            space_id: String::from(SPACE_ID),
+            board_id: None,
+            to_user: String::from("friend@turtlapp.com"),
+            role: Role::Member,
+            title: String::from("my board"),
+            their_pubkey: None,
+            passphrase: None,
+        };
+        assert_eq!(Board::send_invite(&turtl, &board_id, req).unwrap_err().code(), ErrorCode::Offline);
+        assert_eq!(Board::delete_invite(&turtl, &board_id, &String::from("1234")).unwrap_err().code(), ErrorCode::Offline);
+        assert_eq!(Board::delete_member(&turtl, &board_id, &String::from("51")).unwrap_err().code(), ErrorCode::Offline);
+        // nothing changed locally
+        let profile_guard = lockr!(turtl.profile);
+        let board = profile_guard.boards.iter().find(|x| x.id() == Some(&board_id)).unwrap();
+        assert_eq!(board.members.len(), 1);
+        assert_eq!(board.invites.len(), 0);
+    }
+}
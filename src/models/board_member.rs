@@ -0,0 +1,53 @@
+use ::error::TResult;
+use ::lib_permissions::{Role, Permission};
+use ::turtl::Turtl;
+use ::jedi::{self, Value};
+use ::sync::incoming;
+
+/// Holds information about a member of a shared board. This works just like a
+/// SpaceMember, except it only grants access to one board (and its notes), not
+/// the whole space the board lives in.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct BoardMember {
+    /// Member id
+    #[serde(with = "::util::ser::str_i64_converter")]
+    pub id: i64,
+    /// Member's user_id
+    pub user_id: String,
+    /// The board_id this member belongs to
+    pub board_id: String,
+    /// The email of this member
+    pub username: String,
+    /// The role of this member
+    pub role: Role,
+    /// The permissions this member has
+    #[serde(default)]
+    pub permissions: Vec<Permission>,
+    /// When the membership was created
+    pub created: String,
+    /// When the membership was last updated
+    pub updated: String,
+}
+
+impl BoardMember {
+    /// Save this item
+    pub fn edit(&mut self, turtl: &Turtl, existing_member: Option<&mut BoardMember>) -> TResult<()> {
+        let member_data = jedi::to_val(self)?;
+        let url = format!("/boards/{}/members/{}", self.board_id, self.user_id);
+        let saved_data: Value = turtl.api.put(url.as_str())?.json(&member_data).call()?;
+        incoming::ignore_syncs_maybe(turtl, &saved_data, "BoardMember.edit()");
+        match existing_member {
+            Some(x) => { *x = jedi::from_val(saved_data)?; }
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// Delete this member from the board
+    pub fn delete(&self, turtl: &Turtl) -> TResult<()> {
+        let url = format!("/boards/{}/members/{}", self.board_id, self.user_id);
+        let ret: Value = turtl.api.delete(url.as_str())?.call()?;
+        incoming::ignore_syncs_maybe(turtl, &ret, "BoardMember.delete()");
+        Ok(())
+    }
+}
@@ -0,0 +1,220 @@
+use ::std::collections::HashMap;
+use ::time;
+use ::error::{TResult, TError};
+use ::crypto::Key;
+use ::models::model::Model;
+use ::models::validate::{self, Validate};
+use ::models::protected::{self, Keyfinder, Protected};
+use ::models::keychain::{Keychain, KeyRef, KeyType};
+use ::models::note::Note;
+use ::models::board::Board;
+use ::models::sync_record::SyncAction;
+use ::models::storable::Storable;
+use ::sync::sync_model::{self, SyncModel, MemorySaver};
+use ::turtl::Turtl;
+use ::lib_permissions::Permission;
+
+protected! {
+    /// A comment left on a note in a shared space. Comments are encrypted with
+    /// the key of the space the note lives in.
+    #[derive(Serialize, Deserialize)]
+    pub struct Comment {
+        #[serde(with = "::util::ser::int_converter")]
+        #[protected_field(public)]
+        pub user_id: String,
+        #[protected_field(public)]
+        pub space_id: String,
+        #[protected_field(public)]
+        pub note_id: String,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        #[protected_field(private)]
+        pub body: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        #[protected_field(private)]
+        pub created: Option<i64>,
+    }
+}
+
+make_storable!(Comment, "comments");
+impl SyncModel for Comment {}
+// comments aren't held in memory, they're loaded from the db on demand
+impl MemorySaver for Comment {}
+
+impl Validate for Comment {
+    fn validate(&self) -> Vec<(String, String)> {
+        let mut errors = Vec::new();
+        if self.space_id == "" {
+            errors.push(validate::entry("space_id", t!("Please add a space id to this comment")));
+        }
+        if self.note_id == "" {
+            errors.push(validate::entry("note_id", t!("Please add a note id to this comment")));
+        }
+        if self.body.as_ref().map(|x| x.trim() == "").unwrap_or(true) {
+            errors.push(validate::entry("body", t!("Please write something in your comment")));
+        }
+        errors
+    }
+}
+
+impl Keyfinder for Comment {
+    fn get_key_search(&self, turtl: &Turtl) -> TResult<Keychain> {
+        let mut keychain = Keychain::new();
+        let ty = String::from("space");
+        let profile_guard = lockr!(turtl.profile);
+        for space in &profile_guard.spaces {
+            if space.id() != Some(&self.space_id) { continue; }
+            match space.key() {
+                Some(k) => keychain.upsert_key(turtl, &self.space_id, k, &ty)?,
+                None => {}
+            }
+        }
+        Ok(keychain)
+    }
+
+    fn get_keyrefs(&self, turtl: &Turtl) -> TResult<Vec<KeyRef<Key>>> {
+        let mut refs: Vec<KeyRef<Key>> = Vec::new();
+        let profile_guard = lockr!(turtl.profile);
+        for space in &profile_guard.spaces {
+            if space.id() == Some(&self.space_id) && space.key().is_some() {
+                refs.push(KeyRef {
+                    id: self.space_id.clone(),
+                    ty: KeyType::Space,
+                    k: space.key().expect("turtl::Comment.get_keyrefs() -- space key is None").clone(),
+                });
+            }
+        }
+        Ok(refs)
+    }
+}
+
+impl Comment {
+    /// Leave a comment on a note
+    pub fn add(turtl: &Turtl, note_id: &String, body: String) -> TResult<Comment> {
+        let note = Comment::get_note(turtl, note_id)?;
+        Board::permission_check(turtl, &note.space_id, note.board_id.as_ref(), &Permission::EditNote)?;
+        let mut comment = Comment::new();
+        comment.user_id = turtl.user_id()?;
+        comment.space_id = note.space_id.clone();
+        comment.note_id = note_id.clone();
+        comment.body = Some(body);
+        comment.created = Some(time::get_time().sec as i64);
+        sync_model::save_model(SyncAction::Add, turtl, &mut comment, false)?;
+        Ok(comment)
+    }
+
+    /// Grab all the comments for a note, oldest first
+    pub fn list(turtl: &Turtl, note_id: &String) -> TResult<Vec<Comment>> {
+        let mut comments: Vec<Comment> = with_db!{ db, turtl.db,
+            db.find(Comment::tablename(), "note_id", &vec![note_id.clone()])?
+        };
+        turtl.find_models_keys(&mut comments)?;
+        let mut comments: Vec<Comment> = protected::map_deserialize(turtl, comments)?;
+        comments.sort_by(|a, b| a.created.cmp(&b.created));
+        Ok(comments)
+    }
+
+    /// Delete a comment. Anyone can delete their own comments, otherwise you
+    /// need to be able to delete notes in the comment's space.
+    pub fn delete(turtl: &Turtl, comment_id: &String) -> TResult<()> {
+        let comment: Comment = with_db!{ db, turtl.db,
+            match db.get(Comment::tablename(), comment_id)? {
+                Some(x) => x,
+                None => return TErr!(TError::NotFound(format!("comment {} wasn't found", comment_id))),
+            }
+        };
+        if comment.user_id != turtl.user_id()? {
+            let board_id = Comment::get_note(turtl, &comment.note_id).ok().and_then(|n| n.board_id);
+            Board::permission_check(turtl, &comment.space_id, board_id.as_ref(), &Permission::DeleteNote)?;
+        }
+        sync_model::delete_model::<Comment>(turtl, comment_id, false)
+    }
+
+    /// Count the comments on each of the given notes
+    pub fn counts(turtl: &Turtl, note_ids: &Vec<String>) -> TResult<HashMap<String, usize>> {
+        let comments: Vec<Comment> = with_db!{ db, turtl.db,
+            db.find(Comment::tablename(), "note_id", note_ids)?
+        };
+        let mut counts: HashMap<String, usize> = HashMap::with_capacity(note_ids.len());
+        for comment in comments {
+            *counts.entry(comment.note_id).or_insert(0) += 1;
+        }
+        Ok(counts)
+    }
+
+    fn get_note(turtl: &Turtl, note_id: &String) -> TResult<Note> {
+        let note: Option<Note> = with_db!{ db, turtl.db, db.get(Note::tablename(), note_id)? };
+        match note {
+            Some(x) => Ok(x),
+            None => TErr!(TError::NotFound(format!("note {} wasn't found", note_id))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::error::ErrorCode;
+    use ::models::sync_record::{SyncRecord, SyncType};
+
+    const NOTE_ID: &'static str = "015caf7c5f4d2af6297cf0cc29180f9cc45f4c80e5b30238581f845367f9c404ef3fb8fb0a5a022b";
+
+    /// Run the comment changes one device made through another's incoming sync
+    fn replay(from: &Turtl, to: &Turtl) {
+        let mut records = {
+            let mut db_guard = lock!(from.db);
+            SyncRecord::find(db_guard.as_mut().unwrap(), Some(SyncType::Comment)).unwrap()
+        };
+        let mut db_guard = lock!(to.db);
+        let db = db_guard.as_mut().unwrap();
+        for rec in records.iter_mut() {
+            Comment::new().incoming(db, rec).unwrap();
+        }
+    }
+
+    fn bodies(turtl: &Turtl) -> Vec<String> {
+        let mut bodies = Comment::list(turtl, &String::from(NOTE_ID)).unwrap()
+            .into_iter()
+            .map(|x| x.body.unwrap())
+            .collect::<Vec<_>>();
+        bodies.sort();
+        bodies
+    }
+
+    #[test]
+    fn comments_round_trip() {
+        let turtl = ::turtl::tests::with_test(true);
+        ::turtl::tests::load_test_profile(&turtl);
+        let note_id = String::from(NOTE_ID);
+        let first = Comment::add(&turtl, &note_id, String::from("looks good")).unwrap();
+        Comment::add(&turtl, &note_id, String::from("ship it")).unwrap();
+        assert_eq!(bodies(&turtl), vec!["looks good", "ship it"]);
+        let counts = Comment::counts(&turtl, &vec![note_id.clone(), String::from("1234")]).unwrap();
+        assert_eq!(counts.get(&note_id), Some(&2));
+        assert_eq!(counts.get("1234"), None);
+
+        // another device gets them via sync, and can read them
+        let other = ::turtl::tests::with_test(true);
+        ::turtl::tests::load_test_profile(&other);
+        replay(&turtl, &other);
+        assert_eq!(bodies(&other), vec!["looks good", "ship it"]);
+
+        Comment::delete(&turtl, first.id().unwrap()).unwrap();
+        assert_eq!(bodies(&turtl), vec!["ship it"]);
+        replay(&turtl, &other);
+        assert_eq!(bodies(&other), vec!["ship it"]);
+    }
+
+    #[test]
+    fn checks_comments() {
+        let turtl = ::turtl::tests::with_test(true);
+        ::turtl::tests::load_test_profile(&turtl);
+        let err = Comment::add(&turtl, &String::from(NOTE_ID), String::from("   ")).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::BadRequest);
+        let err = Comment::add(&turtl, &String::from("1234"), String::from("hi")).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::NotFound);
+        let err = Comment::delete(&turtl, &String::from("1234")).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::NotFound);
+        assert_eq!(Comment::list(&turtl, &String::from(NOTE_ID)).unwrap().len(), 0);
+    }
+}
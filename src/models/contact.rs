@@ -0,0 +1,138 @@
+use ::time;
+use ::error::TResult;
+use ::models::model::Model;
+use ::models::protected::{Keyfinder, Protected};
+use ::models::sync_record::{SyncRecord, SyncAction};
+use ::models::validate::Validate;
+use ::sync::sync_model::{self, SyncModel, MemorySaver};
+use ::turtl::Turtl;
+
+protected! {
+    /// A person we've shared with (or who has shared with us). Contacts are
+    /// built up from invites and let the UI autocomplete when inviting people.
+    /// They are encrypted with the user's key, same as the keychain.
+    #[derive(Serialize, Deserialize)]
+    pub struct Contact {
+        #[serde(with = "::util::ser::int_converter")]
+        #[protected_field(public)]
+        pub user_id: String,
+
+        #[serde(default)]
+        #[protected_field(private)]
+        pub email: Option<String>,
+        /// The contact's turtl user id, if we know it
+        #[serde(default)]
+        #[protected_field(private)]
+        pub contact_user_id: Option<String>,
+        /// When we last shared with this contact
+        #[serde(default)]
+        #[protected_field(private)]
+        pub last_shared: Option<i64>,
+    }
+}
+
+make_storable!(Contact, "contacts");
+impl SyncModel for Contact {}
+impl Keyfinder for Contact {}
+impl Validate for Contact {}
+
+impl MemorySaver for Contact {
+    fn mem_update(self, turtl: &Turtl, sync_item: &mut SyncRecord) -> TResult<()> {
+        let action = sync_item.action.clone();
+        match action {
+            SyncAction::Add | SyncAction::Edit => {
+                let mut profile_guard = lockw!(turtl.profile);
+                for contact in &mut profile_guard.contacts {
+                    if contact.id() == self.id() {
+                        contact.merge_fields(&self.data()?)?;
+                        return Ok(());
+                    }
+                }
+                profile_guard.contacts.push(self);
+            }
+            SyncAction::Delete => {
+                let mut profile_guard = lockw!(turtl.profile);
+                let contact_id = self.id_or_else()?;
+                profile_guard.contacts.retain(|c| c.id() != Some(&contact_id));
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Normalize an email so we don't end up with the same person twice
+fn normalize_email(email: &String) -> String {
+    email.trim().to_lowercase()
+}
+
+impl Contact {
+    /// Record that we shared with the given email. Creates the contact if we
+    /// haven't seen it before, otherwise bumps its `last_shared` timestamp.
+    pub fn touch(turtl: &Turtl, email: &String, contact_user_id: Option<String>) -> TResult<()> {
+        let email = normalize_email(email);
+        if email == "" { return Ok(()); }
+        let existing = {
+            let profile_guard = lockr!(turtl.profile);
+            let mut found = None;
+            for contact in &profile_guard.contacts {
+                if contact.email.as_ref().map(|x| normalize_email(x)) == Some(email.clone()) {
+                    found = Some(contact.clone()?);
+                    break;
+                }
+            }
+            found
+        };
+        let (action, mut contact) = match existing {
+            Some(x) => (SyncAction::Edit, x),
+            None => {
+                let mut contact = Contact::new();
+                contact.user_id = turtl.user_id()?;
+                contact.email = Some(email);
+                (SyncAction::Add, contact)
+            }
+        };
+        if contact_user_id.is_some() {
+            contact.contact_user_id = contact_user_id;
+        }
+        contact.last_shared = Some(time::get_time().sec as i64);
+        let user_key = {
+            let user_guard = lockr!(turtl.user);
+            user_guard.key_or_else()?
+        };
+        contact.set_key(Some(user_key));
+        sync_model::save_model(action, turtl, &mut contact, false)?;
+        Ok(())
+    }
+
+    /// Find contacts matching the given (partial) email, most recently shared
+    /// first. Used for invite autocomplete.
+    pub fn find(turtl: &Turtl, prefix: Option<String>) -> TResult<Vec<Contact>> {
+        let prefix = prefix.map(|x| normalize_email(&x)).unwrap_or(String::from(""));
+        let profile_guard = lockr!(turtl.profile);
+        let mut matches: Vec<(bool, Contact)> = Vec::new();
+        for contact in &profile_guard.contacts {
+            let email = match contact.email.as_ref() {
+                Some(x) => normalize_email(x),
+                None => continue,
+            };
+            if !email.contains(prefix.as_str()) { continue; }
+            matches.push((email.starts_with(prefix.as_str()), contact.clone()?));
+        }
+        // prefix matches first, then by most recently shared
+        matches.sort_by(|a, b| {
+            b.0.cmp(&a.0).then_with(|| b.1.last_shared.cmp(&a.1.last_shared))
+        });
+        Ok(matches.into_iter().map(|(_, c)| c).collect::<Vec<_>>())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_emails() {
+        assert_eq!(normalize_email(&String::from("  Andrew@Turtlapp.COM ")), String::from("andrew@turtlapp.com"));
+    }
+}
@@ -0,0 +1,267 @@
+//! The email gateway lets a user email notes into their profile. The API hands
+//! out a unique inbound address, and mail sent to it is sealed with the user's
+//! public key and delivered to us via sync. We open it here and turn it into a
+//! regular (encrypted) note in the user's chosen inbox board.
+
+use ::jedi::{self, Value};
+use ::error::{TResult, TError};
+use ::crypto;
+use ::models::model::Model;
+use ::models::protected::{Keyfinder, Protected};
+use ::models::note::Note;
+use ::models::board::Board;
+use ::models::sync_record::{SyncRecord, SyncAction};
+use ::models::validate::Validate;
+use ::sync::sync_model::{self, SyncModel, MemorySaver};
+use ::sync::incoming;
+use ::turtl::Turtl;
+use ::lib_permissions::Permission;
+
+/// The user setting we keep our gateway info in
+const SETTINGS_KEY: &'static str = "email_gateway";
+
+/// Where the gateway lives and where its notes go. Stored in the user's
+/// (encrypted) settings so all devices agree on the inbox board.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GatewaySettings {
+    pub address: String,
+    pub board_id: String,
+}
+
+protected! {
+    /// A piece of mail captured by the gateway, sealed with our public key
+    #[derive(Serialize, Deserialize)]
+    #[protected_modeltype(email)]
+    pub struct GatewayMail {
+        #[serde(with = "::util::ser::int_converter")]
+        #[protected_field(public)]
+        pub user_id: String,
+        #[serde(with = "::util::ser::base64_converter")]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        #[serde(default)]
+        #[protected_field(public)]
+        pub message: Option<Vec<u8>>,
+    }
+}
+
+/// What we find inside of a sealed message
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct MailBody {
+    #[serde(default)]
+    from: Option<String>,
+    #[serde(default)]
+    subject: Option<String>,
+    #[serde(default)]
+    body: Option<String>,
+}
+
+make_storable!(GatewayMail, "email_gateway");
+impl SyncModel for GatewayMail {}
+impl Validate for GatewayMail {}
+impl Keyfinder for GatewayMail {
+    // sealed with our pubkey, not a symmetric key. don't deserialize.
+    fn should_deserialize_on_mem_update(&self) -> bool {
+        false
+    }
+}
+
+impl MemorySaver for GatewayMail {
+    fn mem_update(self, turtl: &Turtl, sync_item: &mut SyncRecord) -> TResult<()> {
+        match sync_item.action {
+            SyncAction::Add => self.deliver(turtl),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl GatewayMail {
+    /// Open this mail and file it as a note in the inbox board
+    fn deliver(&self, turtl: &Turtl) -> TResult<()> {
+        let mail_id = self.id_or_else()?;
+        let settings = match get_settings(turtl)? {
+            Some(x) => x,
+            None => {
+                warn!("GatewayMail.deliver() -- got mail {} but the gateway isn't set up", mail_id);
+                return Ok(());
+            }
+        };
+        let sealed = match self.message.as_ref() {
+            Some(x) => x.clone(),
+            None => return TErr!(TError::MissingField(String::from("GatewayMail.message"))),
+        };
+        let opened = {
+            let user_guard = lockr!(turtl.user);
+            let pubkey = match user_guard.pubkey.as_ref() {
+                Some(k) => k,
+                None => return TErr!(TError::MissingField(String::from("User.pubkey"))),
+            };
+            let privkey = match user_guard.privkey.as_ref() {
+                Some(k) => k,
+                None => return TErr!(TError::MissingField(String::from("User.privkey"))),
+            };
+            crypto::asym::decrypt(pubkey, privkey, sealed)?
+        };
+        let mail: MailBody = jedi::parse(&String::from_utf8(opened)?)?;
+        let space_id = match Board::get_space_id(turtl, &settings.board_id) {
+            Some(x) => x,
+            None => return TErr!(TError::MissingData(format!("inbox board {} not found", settings.board_id))),
+        };
+        Board::permission_check(turtl, &space_id, Some(&settings.board_id), &Permission::AddNote)?;
+
+        let mut note = Note::new();
+        note.user_id = turtl.user_id()?;
+        note.space_id = space_id;
+        note.board_id = Some(settings.board_id.clone());
+        note.type_ = Some(String::from("text"));
+        note.title = mail.subject;
+        note.text = match (mail.from, mail.body) {
+            (Some(from), Some(body)) => Some(format!("From: {}\n\n{}", from, body)),
+            (None, body) => body,
+            (Some(from), None) => Some(format!("From: {}", from)),
+        };
+        note.touch();
+        sync_model::save_model(SyncAction::Add, turtl, &mut note, false)?;
+
+        // the note is safe, so the mail can go
+        let url = format!("/email-gateway/mail/{}", mail_id);
+        let ret: Value = turtl.api.delete(url.as_str())?.call()?;
+        incoming::ignore_syncs_maybe(turtl, &ret, "GatewayMail.deliver()");
+        with_db!{ db, turtl.db, db.delete(self)? };
+        Ok(())
+    }
+}
+
+/// Grab our current gateway settings, if any
+pub fn get_settings(turtl: &Turtl) -> TResult<Option<GatewaySettings>> {
+    let user_guard = lockr!(turtl.user);
+    let val = user_guard.settings.as_ref().and_then(|s| s.get(SETTINGS_KEY));
+    match val {
+        Some(&Value::Null) | None => Ok(None),
+        Some(x) => Ok(Some(jedi::from_val(x.clone())?)),
+    }
+}
+
+fn save_settings(turtl: &Turtl, settings: Option<&GatewaySettings>) -> TResult<()> {
+    let mut user = {
+        let user_guard = lockr!(turtl.user);
+        user_guard.clone()?
+    };
+    user.set_setting(turtl, SETTINGS_KEY, &settings)
+}
+
+/// Provision an inbound address and send everything it gets into the given
+/// board. If we already have an address, just move the inbox.
+pub fn enable(turtl: &Turtl, board_id: &String) -> TResult<GatewaySettings> {
+    turtl.assert_connected()?;
+    let space_id = match Board::get_space_id(turtl, board_id) {
+        Some(x) => x,
+        None => return TErr!(TError::MissingData(format!("board {} not found", board_id))),
+    };
+    Board::permission_check(turtl, &space_id, Some(board_id), &Permission::AddNote)?;
+    let address = match get_settings(turtl)? {
+        Some(existing) => existing.address,
+        None => {
+            let res: Value = turtl.api.post("/email-gateway")?.call()?;
+            jedi::get(&["address"], &res)?
+        }
+    };
+    let settings = GatewaySettings { address: address, board_id: board_id.clone() };
+    save_settings(turtl, Some(&settings))?;
+    Ok(settings)
+}
+
+/// Get a new inbound address (the old one stops working)
+pub fn rotate(turtl: &Turtl) -> TResult<GatewaySettings> {
+    turtl.assert_connected()?;
+    let mut settings = match get_settings(turtl)? {
+        Some(x) => x,
+        None => return TErr!(TError::MissingData(String::from("the email gateway isn't enabled"))),
+    };
+    let res: Value = turtl.api.post("/email-gateway/rotate")?.call()?;
+    settings.address = jedi::get(&["address"], &res)?;
+    save_settings(turtl, Some(&settings))?;
+    Ok(settings)
+}
+
+/// Shut the gateway off
+pub fn disable(turtl: &Turtl) -> TResult<()> {
+    turtl.assert_connected()?;
+    let _: Value = turtl.api.delete("/email-gateway")?.call()?;
+    save_settings(turtl, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::std::collections::HashMap;
+    use ::error::ErrorCode;
+    use ::models::sync_record::SyncType;
+
+    const BOARD_ID: &'static str = "015bac2244ea4944baee41b88207731eaeb7e2cc5c955fb8a05b028c1409aaf55024f5d26fa30034";
+
+    fn note_count(turtl: &Turtl) -> usize {
+        lock!(turtl.db).as_ref().unwrap().all::<Note>("notes").unwrap().len()
+    }
+
+    fn set_settings(turtl: &Turtl, settings: &GatewaySettings) {
+        let mut map = HashMap::new();
+        map.insert(String::from(SETTINGS_KEY), jedi::to_val(settings).unwrap());
+        lockw!(turtl.user).settings = Some(map);
+    }
+
+    #[test]
+    fn gateway_needs_a_connection() {
+        let turtl = ::turtl::tests::with_test(true);
+        ::turtl::tests::load_test_profile(&turtl);
+        assert!(get_settings(&turtl).unwrap().is_none());
+        assert_eq!(enable(&turtl, &String::from(BOARD_ID)).unwrap_err().code(), ErrorCode::Offline);
+        assert_eq!(rotate(&turtl).unwrap_err().code(), ErrorCode::Offline);
+        assert_eq!(disable(&turtl).unwrap_err().code(), ErrorCode::Offline);
+        assert!(get_settings(&turtl).unwrap().is_none());
+    }
+
+    #[test]
+    fn mail_round_trips() {
+        let turtl = ::turtl::tests::with_test(true);
+        ::turtl::tests::load_test_profile(&turtl);
+        let (pubkey, privkey) = crypto::asym::keygen().unwrap();
+        {
+            let mut user_guard = lockw!(turtl.user);
+            user_guard.pubkey = Some(pubkey.clone());
+            user_guard.privkey = Some(privkey);
+        }
+        let mail = json!({"from": "slippy@turtlapp.com", "subject": "hello", "body": "hi there"});
+        let sealed = crypto::asym::encrypt(&pubkey, jedi::stringify(&mail).unwrap().into_bytes()).unwrap();
+
+        // the server hands us the sealed mail
+        let mail_id = String::from("015f0e6b2c4d4944baee41b88207731eaeb7e2cc5c955fb8a05b028c1409aaf55024f5d26fa30077");
+        let mut rec = SyncRecord::default();
+        rec.action = SyncAction::Add;
+        rec.ty = SyncType::Email;
+        rec.user_id = String::from("51");
+        rec.item_id = mail_id.clone();
+        rec.data = Some(json!({"id": mail_id, "user_id": "51", "message": crypto::to_base64(&sealed).unwrap()}));
+        {
+            let mut db_guard = lock!(turtl.db);
+            GatewayMail::new().incoming(db_guard.as_mut().unwrap(), &mut rec).unwrap();
+        }
+        let stored: GatewayMail = lock!(turtl.db).as_ref().unwrap().get("email_gateway", &mail_id).unwrap().unwrap();
+        assert_eq!(stored.message.as_ref(), Some(&sealed));
+
+        // without a gateway set up, the mail waits
+        let notes = note_count(&turtl);
+        let stored2: GatewayMail = lock!(turtl.db).as_ref().unwrap().get("email_gateway", &mail_id).unwrap().unwrap();
+        stored2.mem_update(&turtl, &mut rec).unwrap();
+        assert_eq!(note_count(&turtl), notes);
+
+        // and an inbox board we don't have is an error, not a note somewhere else
+        set_settings(&turtl, &GatewaySettings {
+            address: String::from("abc123@in.turtlapp.com"),
+            board_id: String::from("1234"),
+        });
+        assert_eq!(stored.mem_update(&turtl, &mut rec).unwrap_err().code(), ErrorCode::NotFound);
+        assert_eq!(note_count(&turtl), notes);
+        let still_there: Option<GatewayMail> = lock!(turtl.db).as_ref().unwrap().get("email_gateway", &mail_id).unwrap();
+        assert!(still_there.is_some());
+    }
+}
@@ -1,6 +1,6 @@
 use ::jedi::Value;
 use ::error::{TResult, TError};
-use ::storage::Storage;
+use ::storage::{self, Storage};
 use ::models::model::Model;
 use ::models::protected::{Keyfinder, Protected};
 use ::models::note::Note;
@@ -9,12 +9,23 @@ use ::models::validate::Validate;
 use ::sync::sync_model::{self, SyncModel, MemorySaver};
 use ::turtl::Turtl;
 use ::std::mem;
+use ::std::cmp;
+use ::std::sync::{Arc, Mutex};
 use ::crypto;
 use ::util;
 use ::std::fs;
 use ::std::io::prelude::*;
 use ::std::path::PathBuf;
 use ::glob;
+use ::scan::{self, ScanResult};
+use ::dispatch::registry::Registry;
+
+lazy_static! {
+    /// The last file we decrypted for ranged reads (note id, note mod, data) so
+    /// playing an audio file back a chunk at a time doesn't decrypt the whole
+    /// thing for each chunk
+    static ref RANGE_CACHE: Mutex<Option<(String, Option<i64>, Arc<Vec<u8>>)>> = Mutex::new(None);
+}
 
 /// Return the location where we store files
 pub fn file_folder() -> TResult<String> {
@@ -39,6 +50,98 @@ protected! {
         #[serde(skip_serializing_if = "Option::is_none")]
         #[protected_field(private)]
         pub meta: Option<Value>,
+        /// How long (in seconds) an audio attachment plays for
+        #[serde(skip_serializing_if = "Option::is_none")]
+        #[protected_field(private)]
+        pub duration: Option<f64>,
+        /// The audio codec (opus, vorbis, mp3, aac, flac, pcm, ...)
+        #[serde(skip_serializing_if = "Option::is_none")]
+        #[protected_field(private)]
+        pub codec: Option<String>,
+        /// What the virus scanner thought of the file, if we have one
+        #[serde(skip_serializing_if = "Option::is_none")]
+        #[protected_field(private)]
+        pub scan: Option<ScanResult>,
+    }
+}
+
+/// Figure out the audio codec from a file's first few bytes
+fn sniff_codec(data: &[u8]) -> Option<&'static str> {
+    let starts = |magic: &[u8]| data.len() >= magic.len() && &data[0..magic.len()] == magic;
+    let head = &data[0..cmp::min(data.len(), 64)];
+    let contains = |needle: &[u8]| head.windows(needle.len()).any(|w| w == needle);
+    if starts(b"OggS") {
+        if contains(b"OpusHead") { Some("opus") } else if contains(b"vorbis") { Some("vorbis") } else { Some("ogg") }
+    } else if starts(b"fLaC") {
+        Some("flac")
+    } else if starts(b"RIFF") && data.len() >= 12 && &data[8..12] == b"WAVE" {
+        Some("pcm")
+    } else if starts(b"#!AMR") {
+        Some("amr")
+    } else if starts(&[0x1a, 0x45, 0xdf, 0xa3]) {
+        Some("webm")
+    } else if data.len() >= 8 && &data[4..8] == b"ftyp" {
+        Some("aac")
+    } else if starts(b"ID3") {
+        Some("mp3")
+    } else if data.len() >= 2 && data[0] == 0xff && (data[1] & 0xe0) == 0xe0 {
+        // frame sync. ADTS (aac) has a layer of 0, mp3 doesn't.
+        if (data[1] & 0x06) == 0 { Some("aac") } else { Some("mp3") }
+    } else {
+        None
+    }
+}
+
+/// Pull the duration out of a WAV file's header (the one format where it's
+/// simple math)
+fn wav_duration(data: &[u8]) -> Option<f64> {
+    let le32 = |at: usize| -> Option<u32> {
+        if data.len() < at + 4 { return None; }
+        Some((data[at] as u32) | (data[at + 1] as u32) << 8 | (data[at + 2] as u32) << 16 | (data[at + 3] as u32) << 24)
+    };
+    let mut byte_rate = None;
+    let mut pos = 12;
+    while pos + 8 <= data.len() {
+        let id = &data[pos..(pos + 4)];
+        let size = le32(pos + 4)? as usize;
+        if id == b"fmt " {
+            byte_rate = le32(pos + 16);
+        } else if id == b"data" {
+            return match byte_rate {
+                Some(rate) if rate > 0 => Some(size as f64 / rate as f64),
+                _ => None,
+            };
+        }
+        // chunks are padded to an even size
+        pos += 8 + size + (size % 2);
+    }
+    None
+}
+
+impl File {
+    /// Whether this is an audio attachment (a voice memo, say)
+    pub fn is_audio(&self) -> bool {
+        match self.ty {
+            Some(ref ty) => ty.to_lowercase().starts_with("audio/"),
+            None => false,
+        }
+    }
+
+    /// Fill in the codec (and duration, when the header tells us) for an audio
+    /// file. Anything the UI already set is left alone, since it's in a better
+    /// position to know (it probably just recorded the thing).
+    pub fn detect_audio_meta(&mut self, data: &[u8]) {
+        if !self.is_audio() { return; }
+        let codec = match sniff_codec(data) {
+            Some(x) => x,
+            None => return,
+        };
+        if self.codec.is_none() {
+            self.codec = Some(String::from(codec));
+        }
+        if self.duration.is_none() && codec == "pcm" {
+            self.duration = wav_duration(data);
+        }
     }
 }
 
@@ -202,8 +305,9 @@ impl FileData {
         Ok(filepath)
     }
 
-    /// Load a note's file, if we have one.
+    /// Load a note's file, if we have one (and it isn't quarantined).
     pub fn load_file(turtl: &Turtl, note: &Note) -> TResult<Vec<u8>> {
+        scan::blocked(note)?;
         let note_id = note.id_or_else()?;
         let note_key = note.key_or_else()?;
 
@@ -220,10 +324,38 @@ impl FileData {
             crypto::decrypt(&note_key, enc)
                 .map_err(|e| From::from(e))
         })?;
+        scan::check_file(turtl, note, data.as_slice())?;
 
         Ok(data)
     }
 
+    /// Load part of a note's file (for progressive playback of audio, etc).
+    /// Returns the chunk and the file's full size.
+    pub fn load_file_range(turtl: &Turtl, note: &Note, offset: u64, length: u64) -> TResult<(Vec<u8>, u64)> {
+        let note_id = note.id_or_else()?;
+        let cached = match *lock!(*RANGE_CACHE) {
+            Some((ref id, ref mod_, ref data)) if id == &note_id && mod_ == &note.mod_ => Some(data.clone()),
+            _ => None,
+        };
+        let data = match cached {
+            Some(x) => x,
+            None => {
+                let data = Arc::new(FileData::load_file(turtl, note)?);
+                *lock!(*RANGE_CACHE) = Some((note_id, note.mod_, data.clone()));
+                data
+            }
+        };
+        let size = data.len() as u64;
+        let start = cmp::min(offset, size) as usize;
+        let end = cmp::min(offset.saturating_add(length), size) as usize;
+        Ok((Vec::from(&data[start..end]), size))
+    }
+
+    /// Forget the file we're holding for ranged reads (it's decrypted)
+    pub fn clear_range_cache() {
+        *lock!(*RANGE_CACHE) = None;
+    }
+
     /// Encrypt/save this file
     pub fn save(&mut self, turtl: &Turtl, note: &mut Note) -> TResult<()> {
         // grab some items we'll need to do our work (user_id/note_id for the
@@ -244,6 +376,7 @@ impl FileData {
             Some(x) => x,
             None => return TErr!(TError::MissingField(format!("FileData.data"))),
         };
+        sync_model::check_size("file", data.len() as u64)?;
 
         // encrypt the file using the turtl standard serialization format
         let enc = turtl.work.run(move || {
@@ -251,7 +384,8 @@ impl FileData {
                 .map_err(|e| From::from(e))
         })?;
 
-        // now, save the encrypted file data to disk
+        // now, save the encrypted file data to disk (assuming we have room)
+        storage::check_free_space(enc.len() as u64)?;
         let mut filepath = PathBuf::from(file_folder()?);
         util::create_dir(&filepath)?;
         filepath.push(FileData::filebuilder(Some(&user_id), Some(&note_id)));
@@ -290,6 +424,35 @@ impl FileData {
     }
 }
 
+/// Registers the commands for grabbing note files
+pub fn register(reg: &mut Registry) {
+    reg.add("profile:note:get-file", |turtl, args| {
+        let note_id = args.get(2)?;
+        let notes: Vec<Note> = turtl.load_notes(&vec![note_id])?;
+        let bin = FileData::load_file(turtl, &notes[0])?;
+        let base64 = crypto::to_base64(&bin)?;
+        Ok(Value::String(base64))
+    });
+    reg.add("profile:note:get-file-range", |turtl, args| {
+        let note_id: String = args.get(2)?;
+        let offset: u64 = args.get_opt(3).unwrap_or(0);
+        let length: u64 = args.get_opt(4).unwrap_or(262144);
+        let notes: Vec<Note> = turtl.load_notes(&vec![note_id.clone()])?;
+        let note = match notes.get(0) {
+            Some(x) => x,
+            None => return TErr!(TError::NotFound(format!("note {} wasn't found", note_id))),
+        };
+        let (chunk, size) = FileData::load_file_range(turtl, note, offset, length)?;
+        let next = offset + chunk.len() as u64;
+        Ok(json!({
+            "data": crypto::to_base64(&chunk)?,
+            "offset": offset,
+            "size": size,
+            "done": next >= size,
+        }))
+    });
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -308,6 +471,38 @@ mod tests {
         assert_eq!(file2.data.as_ref().unwrap(), &filedata);
     }
 
+    #[test]
+    fn detects_audio_meta() {
+        // a 16-bit mono 8kHz wav with a quarter second of silence
+        let mut wav: Vec<u8> = Vec::new();
+        wav.extend_from_slice(b"RIFF\x00\x00\x00\x00WAVEfmt ");
+        wav.extend_from_slice(&[16, 0, 0, 0, 1, 0, 1, 0, 0x40, 0x1f, 0, 0, 0x80, 0x3e, 0, 0, 2, 0, 16, 0]);
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&[0xa0, 0x0f, 0, 0]);
+        wav.extend_from_slice(&vec![0u8; 4000]);
+        let mut file = File::new();
+        file.ty = Some(String::from("audio/wav"));
+        file.detect_audio_meta(&wav);
+        assert_eq!(file.codec, Some(String::from("pcm")));
+        assert_eq!(file.duration, Some(0.25));
+
+        // the UI knows best
+        let mut file = File::new();
+        file.ty = Some(String::from("audio/ogg"));
+        file.duration = Some(12.5);
+        file.detect_audio_meta(b"OggS\x00\x02\x00\x00\x00\x00\x00\x00\x00\x00OpusHead");
+        assert_eq!(file.codec, Some(String::from("opus")));
+        assert_eq!(file.duration, Some(12.5));
+
+        // not audio, not our business
+        let mut file = File::new();
+        file.ty = Some(String::from("image/png"));
+        file.detect_audio_meta(b"ID3");
+        assert_eq!(file.codec, None);
+        assert_eq!(sniff_codec(&[0xff, 0xfb, 0x90]), Some("mp3"));
+        assert_eq!(sniff_codec(&[0xff, 0xf1, 0x50]), Some("aac"));
+    }
+
     #[test]
     fn can_save_and_load_files() {
         let turtl = ::turtl::tests::with_test(true);
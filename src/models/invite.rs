@@ -19,6 +19,10 @@ protected! {
     pub struct Invite {
         #[protected_field(public)]
         pub space_id: String,
+        /// Set if this invite is for a single board instead of a whole space
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        #[protected_field(public)]
+        pub board_id: Option<String>,
         #[serde(with = "::util::ser::int_converter")]
         #[protected_field(public)]
         pub from_user_id: String,
@@ -48,6 +52,9 @@ protected! {
 #[derive(Serialize, Deserialize)]
 pub struct InviteRequest {
     pub space_id: String,
+    /// If set, only this board is shared (not the whole space)
+    #[serde(default)]
+    pub board_id: Option<String>,
     pub to_user: String,
     pub role: Role,
     pub title: String,
@@ -95,9 +102,10 @@ impl MemorySaver for Invite {
 }
 
 impl Invite {
-    /// Convert an invite request+key into an invite, sealed and ready to send
-    pub fn from_invite_request(from_user_id: &String, from_username: &String, space_key: &Key, req: InviteRequest) -> TResult<Self> {
-        let InviteRequest { space_id, to_user, role, title, their_pubkey, passphrase } = req;
+    /// Convert an invite request+key into an invite, sealed and ready to send.
+    /// The key is the space's key, or the board's key for board invites.
+    pub fn from_invite_request(from_user_id: &String, from_username: &String, key: &Key, req: InviteRequest) -> TResult<Self> {
+        let InviteRequest { space_id, board_id, to_user, role, title, their_pubkey, passphrase } = req;
         if title.trim() == "" {
             return TErr!(TError::MissingField(String::from("title")));
         }
@@ -107,6 +115,7 @@ impl Invite {
         let mut invite: Invite = Default::default();
         Model::generate_id(&mut invite)?;
         invite.space_id = space_id;
+        invite.board_id = board_id;
         invite.from_user_id = from_user_id.clone();
         invite.from_username = from_username.clone();
         invite.to_user = to_user;
@@ -115,10 +124,19 @@ impl Invite {
         invite.is_pubkey_protected = false;
         invite.title = title;
         invite.message = None;
-        invite.seal(their_pubkey, passphrase, space_key)?;
+        invite.seal(their_pubkey, passphrase, key)?;
         Ok(invite)
     }
 
+    /// Where this invite lives in the API. Board invites hang off the board,
+    /// everything else off the space.
+    fn url_base(&self) -> String {
+        match self.board_id.as_ref() {
+            Some(board_id) => format!("/boards/{}/invites", board_id),
+            None => format!("/spaces/{}/invites", self.space_id),
+        }
+    }
+
     /// Generate a key for this invite. If it's not passphrase-protected, then
     /// we'll use a standard password (basically, publicly readable). Set a
     /// passphrase, folks.
@@ -134,8 +152,11 @@ impl Invite {
     }
 
     /// Sealed with a kiss
-    pub fn seal(&mut self, their_pubkey: Option<Key>, passphrase: Option<String>, space_key: &Key) -> TResult<()> {
-        let message = jedi::stringify(&json!({"space_key": space_key}))?;
+    pub fn seal(&mut self, their_pubkey: Option<Key>, passphrase: Option<String>, key: &Key) -> TResult<()> {
+        let message = match self.board_id {
+            Some(_) => jedi::stringify(&json!({"board_key": key}))?,
+            None => jedi::stringify(&json!({"space_key": key}))?,
+        };
         let mut message = Vec::from(message.as_bytes());
         if let Some(pubkey) = their_pubkey {
             message = crypto::asym::encrypt(&pubkey, message)?;
@@ -167,7 +188,7 @@ impl Invite {
 
     /// Ship it!
     pub fn send(&self, turtl: &Turtl) -> TResult<()> {
-        let url = format!("/spaces/{}/invites", self.space_id);
+        let url = self.url_base();
         let data = self.data_for_storage()?;
         let invite: Value = turtl.api.post(url.as_str())?.json(&data).call()?;
         incoming::ignore_syncs_maybe(turtl, &invite, "Invite.send()");
@@ -178,7 +199,7 @@ impl Invite {
     pub fn accept(&self, turtl: &Turtl) -> TResult<Value> {
         model_getter!(get_field, "Invite.accept()");
         let invite_id = get_field!(self, id);
-        let url = format!("/spaces/{}/invites/accepted/{}", self.space_id, invite_id);
+        let url = format!("{}/accepted/{}", self.url_base(), invite_id);
         let spacedata: Value = turtl.api.post(url.as_str())?.call()?;
         incoming::ignore_syncs_maybe(turtl, &spacedata, "Invite.accept()");
         Ok(spacedata)
@@ -189,7 +210,7 @@ impl Invite {
         let invite_data = self.data_for_storage()?;
         model_getter!(get_field, "Invite.edit()");
         let invite_id = get_field!(self, id);
-        let url = format!("/spaces/{}/invites/{}", self.space_id, invite_id);
+        let url = format!("{}/{}", self.url_base(), invite_id);
         let saved_data: Value = turtl.api.put(url.as_str())?.json(&invite_data).call()?;
         incoming::ignore_syncs_maybe(turtl, &saved_data, "Invite.edit()");
         match existing_invite {
@@ -203,7 +224,7 @@ impl Invite {
     pub fn delete(&self, turtl: &Turtl) -> TResult<()> {
         model_getter!(get_field, "Invite.delete()");
         let invite_id = get_field!(self, id);
-        let url = format!("/spaces/{}/invites/{}", self.space_id, invite_id);
+        let url = format!("{}/{}", self.url_base(), invite_id);
         let ret: Value = turtl.api.delete(url.as_str())?.call()?;
         incoming::ignore_syncs_maybe(turtl, &ret, "Invite.delete()");
         Ok(())
@@ -196,6 +196,28 @@ impl Keychain {
         Ok(())
     }
 
+    /// Replace a whole batch of entries at once. This does the same thing as
+    /// calling `replace_entry()` for each entry, but without walking the
+    /// keychain once per entry (which gets slow on large accounts).
+    pub fn replace_entries(&mut self, entries: Vec<KeychainEntry>) {
+        // if we get the same item_id more than once, the last one wins
+        let mut positions: HashMap<String, usize> = HashMap::with_capacity(entries.len());
+        let mut incoming: Vec<KeychainEntry> = Vec::with_capacity(entries.len());
+        for entry in entries {
+            match positions.get(&entry.item_id) {
+                Some(&idx) => {
+                    incoming[idx] = entry;
+                    continue;
+                }
+                None => {}
+            }
+            positions.insert(entry.item_id.clone(), incoming.len());
+            incoming.push(entry);
+        }
+        self.entries.retain(|entry| !positions.contains_key(&entry.item_id));
+        self.entries.append(&mut incoming);
+    }
+
     /// Remove a keychain entry
     pub fn remove_entry(&mut self, item_id: &String, sync_save: Option<(&Turtl, bool)>) -> TResult<()> {
         match sync_save {
@@ -353,5 +375,25 @@ pub mod tests {
         let entry_b_id = kc.find_entry(&item1_id).unwrap().id().unwrap().clone();
         assert_eq!(entry_a_id, entry_b_id);
     }
+
+    #[test]
+    fn replaces_entries_in_bulk() {
+        fn entry(item_id: &str, key: &Key) -> KeychainEntry {
+            let mut entry = KeychainEntry::new();
+            entry.item_id = String::from(item_id);
+            entry.k = Some(key.clone());
+            entry
+        }
+        let key1 = Key::new(vec![1; 32]);
+        let key2 = Key::new(vec![2; 32]);
+        let key3 = Key::new(vec![3; 32]);
+        let mut kc = Keychain::new();
+        kc.replace_entries(vec![entry("1234", &key1), entry("5678", &key1)]);
+        kc.replace_entries(vec![entry("5678", &key2), entry("9999", &key2), entry("9999", &key3)]);
+        assert_eq!(kc.entries.len(), 3);
+        assert_eq!(kc.find_key(&String::from("1234")).unwrap().data(), key1.data());
+        assert_eq!(kc.find_key(&String::from("5678")).unwrap().data(), key2.data());
+        assert_eq!(kc.find_key(&String::from("9999")).unwrap().data(), key3.data());
+    }
 }
 
@@ -14,9 +14,16 @@ pub mod user;
 pub mod keychain;
 pub mod space;
 pub mod space_member;
+pub mod board_member;
 pub mod board;
 pub mod note;
 pub mod file;
 pub mod invite;
+pub mod contact;
+pub mod comment;
+pub mod reaction;
+pub mod receipt;
+pub mod time_entry;
+pub mod email_gateway;
 pub mod feedback;
 
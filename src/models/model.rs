@@ -18,6 +18,9 @@ lazy_static! {
     /// create a static/global cid counter
     static ref CID_COUNTER: RwLock<u32> = RwLock::new(0);
 
+    /// the timestamp and counter of the last cid `cid()` handed out
+    static ref CID_STAMP: RwLock<(u64, u32)> = RwLock::new((0, 0));
+
     /// holds our app's client id
     static ref CLIENT_ID: RwLock<Option<String>> = RwLock::new(None);
 }
@@ -68,15 +71,40 @@ pub fn set_client_id(id: String) -> TResult<()> {
     Ok(())
 }
 
-/// Create a new cid with the given timestamp
-pub fn cid_w_timestamp(millis: u64) -> TResult<String> {
+/// How many cids we can make in one millisecond (the counter is 4 hex chars)
+const CID_COUNTER_MAX: u32 = 65536;
+
+/// Given the last (timestamp, counter) pair we used and the current time, find
+/// the next pair. Timestamps never go backwards (if the clock does, we keep
+/// using the last one) and a full counter bumps us into the next millisecond,
+/// so every cid we make sorts after the one before it.
+fn next_stamp(last: (u64, u32), millis: u64) -> (u64, u32) {
+    let (last_millis, last_counter) = last;
+    if millis > last_millis {
+        (millis, 0)
+    } else if last_counter + 1 < CID_COUNTER_MAX {
+        (last_millis, last_counter + 1)
+    } else {
+        (last_millis + 1, 0)
+    }
+}
+
+/// Make sure we never hand out a cid timestamped at or before the given one.
+/// We set this from the newest id in storage on login, which keeps us from
+/// reusing ids if the clock was set back since the last run.
+pub fn set_cid_floor(millis: u64) {
+    let mut stamp_guard = lockw!((*CID_STAMP));
+    if millis >= stamp_guard.0 {
+        *stamp_guard = (millis, CID_COUNTER_MAX - 1);
+    }
+}
+
+/// Build a cid out of its parts
+fn build_cid(millis: u64, counter: u32) -> TResult<String> {
     let client_id = match get_client_id() {
         Some(ref x) => x.clone(),
         None => return TErr!(TError::MissingData(format!("CLIENT_ID missing"))),
     };
-    let mut counter_guard = lockw!((*CID_COUNTER));
-    let counter: u32 = counter_guard.clone();
-    (*counter_guard) += 1;
     let mut cid = format!("{:01$x}", millis, 12);
     let counter_str = format!("{:01$x}", (counter & 65535), 4);
     cid.push_str(&client_id[..]);
@@ -84,11 +112,33 @@ pub fn cid_w_timestamp(millis: u64) -> TResult<String> {
     Ok(cid)
 }
 
-/// Create a turtl object id from a client id
+/// Create a new cid with the given timestamp. Used to keep an object's create
+/// date when giving it a new id, so unlike `cid()` this doesn't care what order
+/// ids are made in.
+pub fn cid_w_timestamp(millis: u64) -> TResult<String> {
+    let counter = {
+        let mut counter_guard = lockw!((*CID_COUNTER));
+        let counter: u32 = counter_guard.clone();
+        (*counter_guard) += 1;
+        counter
+    };
+    build_cid(millis, counter)
+}
+
+/// Create a turtl object id from a client id.
+///
+/// Cids are all hex (timestamp, client id, counter) so they're safe to show
+/// anywhere without spelling anything rude, and sorting cids from this client
+/// sorts them by when they were made.
 pub fn cid() -> TResult<String> {
     let now = time::get_time();
     let millis = ((now.sec as u64) * 1000) + ((now.nsec as u64) / 1000000);
-    cid_w_timestamp(millis)
+    let (millis, counter) = {
+        let mut stamp_guard = lockw!((*CID_STAMP));
+        *stamp_guard = next_stamp(*stamp_guard, millis);
+        *stamp_guard
+    };
+    build_cid(millis, counter)
 }
 
 /// Given a cid and a client id, replace the cid's client id with the given one.
@@ -251,6 +301,16 @@ mod tests {
         assert!(rabbit.id.is_some());
     }
 
+    #[test]
+    fn cid_stamps() {
+        assert_eq!(next_stamp((0, 0), 1000), (1000, 0));
+        assert_eq!(next_stamp((1000, 0), 1000), (1000, 1));
+        // clock went backwards
+        assert_eq!(next_stamp((1000, 1), 900), (1000, 2));
+        assert_eq!(next_stamp((1000, CID_COUNTER_MAX - 1), 1000), (1001, 0));
+        assert_eq!(next_stamp((1000, 7), 1002), (1002, 0));
+    }
+
     #[test]
     fn blank() {
         let rabbit = Rabbit::new();
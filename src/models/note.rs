@@ -1,15 +1,51 @@
 use ::turtl::Turtl;
 use ::error::TResult;
+use ::regex::{self, Regex};
+use ::time;
+use ::url::Url;
 use ::models::model::Model;
 use ::models::validate::{self, Validate};
-use ::models::protected::{Keyfinder, Protected};
+use ::models::protected::{self, Keyfinder, Protected};
+use ::models::board::Board;
 use ::models::keychain::{Keychain, KeyRef, KeyType};
 use ::models::file::{File, FileData};
 use ::models::sync_record::{SyncRecord, SyncAction};
-use ::crypto::Key;
+use ::crypto::{self, Key};
+use ::config;
 use ::sync::sync_model::{self, SyncModel, MemorySaver};
 use ::std::fs;
 use ::models::storable::Storable;
+use ::ordering::Sortable;
+use ::jedi;
+use ::messaging;
+use ::webhook;
+use ::geo;
+use ::analyzer;
+use ::sanitize;
+use ::lib_permissions::Permission;
+
+/// The kv key we store our watched note ids under
+const WATCH_KEY: &'static str = "watched_notes";
+
+/// The kv key we store the ids of notes we've been told we're mentioned in
+const MENTIONED_KEY: &'static str = "mentioned_notes";
+
+/// Someone @mentioned in a note
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Mention {
+    /// What was typed after the "@"
+    pub handle: String,
+    /// The space member the handle resolved to, if any
+    #[serde(default)]
+    pub user_id: Option<String>,
+}
+
+/// Where a note was taken
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GeoPoint {
+    pub lat: f64,
+    pub lon: f64,
+}
 
 protected! {
     #[derive(Serialize, Deserialize)]
@@ -59,7 +95,161 @@ protected! {
         #[serde(skip_serializing_if = "Option::is_none")]
         #[protected_field(private)]
         pub color: Option<i64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        #[protected_field(private)]
+        pub mentions: Option<Vec<Mention>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        #[protected_field(private)]
+        pub location: Option<GeoPoint>,
+        /// When this note is due (YYYY-MM-DD)
+        #[serde(skip_serializing_if = "Option::is_none")]
+        #[protected_field(private)]
+        pub due: Option<String>,
+        /// The kanban column (see `Board.columns`) this note is in, and where
+        #[serde(skip_serializing_if = "Option::is_none")]
+        #[protected_field(private)]
+        pub column: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        #[protected_field(private)]
+        pub column_position: Option<i64>,
+        /// The readable text of the page a bookmark points to (see
+        /// `read_later::extract()`)
+        #[serde(skip_serializing_if = "Option::is_none")]
+        #[protected_field(private)]
+        pub article: Option<String>,
+        /// Whether this note has been read, for the read-later queue
+        #[serde(skip_serializing_if = "Option::is_none")]
+        #[protected_field(private)]
+        pub read: Option<bool>,
+    }
+}
+
+/// Pull the #hashtags out of some text, in order, without the "#" and without
+/// duplicates. A "#" only starts a tag at the beginning of a word, so things
+/// like "C#" and "page#anchor" are left alone, as are markdown "# headings".
+pub fn parse_hashtags(text: &str) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+    let mut prev: Option<char> = None;
+    let mut chars = text.char_indices().peekable();
+    while let Some((idx, c)) = chars.next() {
+        let starts_word = prev.map(|p| p.is_whitespace() || "([{,;".contains(p)).unwrap_or(true);
+        prev = Some(c);
+        if c != '#' || !starts_word { continue; }
+        let start = idx + 1;
+        let mut end = start;
+        while let Some(&(i, n)) = chars.peek() {
+            if !(n.is_alphanumeric() || n == '-' || n == '_') { break; }
+            end = i + n.len_utf8();
+            prev = Some(n);
+            chars.next();
+        }
+        let tag = text[start..end].trim_end_matches(|c| c == '-' || c == '_');
+        // "#123" is more likely an issue number than a tag
+        if tag.len() == 0 || tag.chars().all(|c| c.is_numeric()) { continue; }
+        let tag = String::from(tag);
+        if !tags.contains(&tag) { tags.push(tag); }
+    }
+    tags
+}
+
+/// Pull the @mentions out of some text, in order, without the "@" and without
+/// duplicates. Handles can be usernames ("@andrew") or full emails
+/// ("@andrew@turtlapp.com"). Like hashtags, a mention has to start a word, so
+/// email addresses in the text aren't mentions.
+pub fn parse_mentions(text: &str) -> Vec<String> {
+    let mut mentions: Vec<String> = Vec::new();
+    let mut prev: Option<char> = None;
+    let mut chars = text.char_indices().peekable();
+    while let Some((idx, c)) = chars.next() {
+        let starts_word = prev.map(|p| p.is_whitespace() || "([{,;".contains(p)).unwrap_or(true);
+        prev = Some(c);
+        if c != '@' || !starts_word { continue; }
+        let start = idx + 1;
+        let mut end = start;
+        while let Some(&(i, n)) = chars.peek() {
+            if !(n.is_alphanumeric() || "._-+@".contains(n)) { break; }
+            end = i + n.len_utf8();
+            prev = Some(n);
+            chars.next();
+        }
+        // "@andrew." at the end of a sentence
+        let handle = text[start..end].trim_end_matches(|c| "._-+@".contains(c)).to_lowercase();
+        if handle.len() == 0 { continue; }
+        if !mentions.contains(&handle) { mentions.push(handle); }
     }
+    mentions
+}
+
+/// Pull the http(s) URLs out of some text, in order and without duplicates.
+/// Trailing punctuation (and the closing paren of a markdown link) is left off.
+pub fn parse_urls(text: &str) -> Vec<String> {
+    let mut urls: Vec<String> = Vec::new();
+    let mut rest = text;
+    while let Some(idx) = rest.find("http") {
+        let candidate = &rest[idx..];
+        if !candidate.starts_with("http://") && !candidate.starts_with("https://") {
+            rest = &rest[(idx + 4)..];
+            continue;
+        }
+        let end = candidate.find(|c: char| c.is_whitespace() || "<>\"'`".contains(c)).unwrap_or(candidate.len());
+        let mut url = candidate[0..end].trim_end_matches(|c| ".,;:!?".contains(c));
+        // only keep a closing paren if the url opened one
+        while url.ends_with(')') && url.matches('(').count() < url.matches(')').count() {
+            url = url[0..(url.len() - 1)].trim_end_matches(|c| ".,;:!?".contains(c));
+        }
+        if Url::parse(url).is_ok() && !urls.iter().any(|x| x == url) {
+            urls.push(String::from(url));
+        }
+        rest = &candidate[end..];
+    }
+    urls
+}
+
+/// Normalize a URL so the same page saved twice compares equal (lowercase
+/// host, no "www.", no #fragment, no trailing slash), returning it along with
+/// its domain.
+pub fn normalize_url(url: &str) -> Option<(String, String)> {
+    let mut parsed = Url::parse(url.trim()).ok()?;
+    if parsed.scheme() != "http" && parsed.scheme() != "https" { return None; }
+    parsed.set_fragment(None);
+    let host = String::from(parsed.host_str()?);
+    let domain = String::from(host.trim_start_matches("www."));
+    let port = parsed.port().map(|x| format!(":{}", x)).unwrap_or(String::new());
+    let path = parsed.path().trim_end_matches('/');
+    let query = parsed.query().map(|x| format!("?{}", x)).unwrap_or(String::new());
+    Some((format!("{}{}{}{}", domain, port, path, query), domain))
+}
+
+/// Options for finding text inside a note
+#[derive(Deserialize, Debug, Default)]
+pub struct FindTextOptions {
+    /// Treat the query as a regular expression instead of plain text
+    #[serde(default)]
+    pub regex: bool,
+    #[serde(default)]
+    pub case_sensitive: bool,
+}
+
+impl FindTextOptions {
+    /// Build the regex that finds `query` with these options
+    pub fn compile(&self, query: &str) -> TResult<Regex> {
+        let pattern = if self.regex { String::from(query) } else { regex::quote(query) };
+        let pattern = if self.case_sensitive { pattern } else { format!("(?i){}", pattern) };
+        Ok(Regex::new(&pattern)?)
+    }
+}
+
+/// Where a piece of text matched inside a note
+#[derive(Serialize, Debug, PartialEq)]
+pub struct TextMatch {
+    /// "title" or "text"
+    pub field: String,
+    /// Byte offsets of the match (end is exclusive)
+    pub start: usize,
+    pub end: usize,
+    /// Char offsets of the match, for UIs that index strings by character
+    pub char_start: usize,
+    pub char_end: usize,
 }
 
 make_storable!(Note, "notes");
@@ -74,10 +264,27 @@ impl Validate for Note {
         if self.type_.as_ref().map(|x| x == "").unwrap_or(true) {
             errors.push(validate::entry("type", t!("This note is missing the `type` field")));
         }
+        if let Some(ref loc) = self.location {
+            if !geo::valid(loc.lat, loc.lon) {
+                errors.push(validate::entry("location", t!("This note's location isn't a real place")));
+            }
+        }
+        if let Some(ref due) = self.due {
+            match analyzer::parse_date(due) {
+                Some((low, high)) if low == high => {}
+                _ => errors.push(validate::entry("due", t!("Please give a due date in the form YYYY-MM-DD"))),
+            }
+        }
         errors
     }
 }
 
+impl Sortable for Note {
+    fn sort_id(&self) -> Option<&String> { self.id() }
+    fn sort_title(&self) -> Option<&String> { self.title.as_ref() }
+    fn sort_mod(&self) -> Option<i64> { self.mod_ }
+}
+
 impl Note {
     /// Remove the files attached to this note, if any.
     fn clear_files(&self) -> TResult<()> {
@@ -98,6 +305,74 @@ impl Note {
         Ok(())
     }
 
+    /// Find which of the given (encrypted) notes were saved with an older
+    /// version of our crypto format.
+    pub fn find_outdated(notes: &Vec<Note>) -> Vec<String> {
+        notes.iter()
+            .filter(|note| {
+                match note.body_version() {
+                    Ok(Some(version)) => version < crypto::CRYPTO_VERSION,
+                    _ => false,
+                }
+            })
+            .filter_map(|note| note.id().map(|x| x.clone()))
+            .collect::<Vec<_>>()
+    }
+
+    /// Queue up notes that were encrypted with an old format to be re-saved,
+    /// so they get re-encrypted with the current one (and synced out to the API
+    /// through the usual outgoing sync). The saves happen in `upgrade()` once
+    /// whatever loaded the notes has answered, so loading doesn't wait on them.
+    ///
+    /// We only upgrade a handful of notes per call (`crypto.lazy_upgrade_batch`)
+    /// so a big profile upgrades a little bit at a time as it's used instead of
+    /// stalling the app and flooding the outgoing syncer all at once. Notes we
+    /// can't edit (shared with us read-only) are left alone.
+    pub fn lazy_upgrade(turtl: &Turtl, notes: &Vec<Note>, outdated: &Vec<String>) -> TResult<()> {
+        if outdated.len() == 0 { return Ok(()); }
+        let batch = config::get::<usize>(&["crypto", "lazy_upgrade_batch"]).unwrap_or(5);
+        let note_ids = notes.iter()
+            .filter(|note| note.id().map(|id| outdated.contains(id)).unwrap_or(false))
+            .filter(|note| note.can_edit(turtl))
+            .take(batch)
+            .filter_map(|note| note.id().cloned())
+            .collect::<Vec<_>>();
+        if note_ids.len() == 0 { return Ok(()); }
+        messaging::app_event("notes:upgrade", &note_ids)
+    }
+
+    /// Re-save whichever of the given notes are still in an old crypto format
+    /// (see `lazy_upgrade()`)
+    pub fn upgrade(turtl: &Turtl, note_ids: &Vec<String>) -> TResult<()> {
+        let vals: Vec<jedi::Value> = with_db!{ db, turtl.db, db.values_by_id(Note::tablename(), note_ids)? };
+        let mut notes: Vec<Note> = Vec::with_capacity(vals.len());
+        for val in vals {
+            // archived stubs get upgraded once they're rehydrated
+            if jedi::get_opt::<String>(&["archived"], &val).is_some() { continue; }
+            notes.push(jedi::from_val(val)?);
+        }
+        // another load might have beaten us to some of them
+        let outdated = Note::find_outdated(&notes);
+        notes.retain(|note| note.id().map(|id| outdated.contains(id)).unwrap_or(false));
+        if notes.len() == 0 { return Ok(()); }
+        turtl.find_models_keys(&mut notes)?;
+        let notes: Vec<Note> = protected::map_deserialize(turtl, notes)?;
+        for mut note in notes {
+            let note_id = note.id_or_else()?;
+            if !note.can_edit(turtl) { continue; }
+            match sync_model::save_model(SyncAction::Edit, turtl, &mut note, false) {
+                Ok(_) => info!("Note::upgrade() -- upgraded note {} to crypto v{}", note_id, crypto::CRYPTO_VERSION),
+                Err(e) => warn!("Note::upgrade() -- problem upgrading note {}: {}", note_id, e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the current user is allowed to edit this note
+    fn can_edit(&self, turtl: &Turtl) -> bool {
+        Board::permission_check(turtl, &self.space_id, self.board_id.as_ref(), &Permission::EditNote).is_ok()
+    }
+
     /// Given a Turtl/note_id, grab that note's space_id (if it exists)
     pub fn get_space_id(turtl: &Turtl, note_id: &String) -> Option<String> {
         let mut db_guard = lock!(turtl.db);
@@ -111,6 +386,190 @@ impl Note {
             None => None,
         }
     }
+
+    /// Grab the ids of the notes we're watching
+    pub fn watched(turtl: &Turtl) -> TResult<Vec<String>> {
+        let watched = with_db!{ db, turtl.db, db.kv_get(WATCH_KEY)? };
+        match watched {
+            Some(x) => Ok(jedi::parse(&x)?),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Watch (or stop watching) a note. Watches are local to this device and
+    /// are not synced.
+    pub fn set_watch(turtl: &Turtl, note_id: &String, watch: bool) -> TResult<Vec<String>> {
+        let mut watched = Note::watched(turtl)?;
+        watched.retain(|x| x != note_id);
+        if watch {
+            watched.push(note_id.clone());
+        }
+        let serialized = jedi::stringify(&watched)?;
+        with_db!{ db, turtl.db, db.kv_set(WATCH_KEY, &serialized)? };
+        Ok(watched)
+    }
+
+    /// Pull #hashtags and @mentions out of the (decrypted) title/body. Hashtags
+    /// are merged into our tags, and mentions are resolved against the members
+    /// of our space. Call before saving.
+    pub fn parse_fields(&mut self, turtl: &Turtl) -> TResult<()> {
+        if self.title.is_none() && self.text.is_none() { return Ok(()); }
+        let body = format!("{}\n{}", self.title.as_ref().map(|x| x.as_str()).unwrap_or(""), self.text.as_ref().map(|x| x.as_str()).unwrap_or(""));
+
+        let mut tags = self.tags.clone().unwrap_or(Vec::new());
+        for tag in parse_hashtags(&body) {
+            let lower = tag.to_lowercase();
+            if !tags.iter().any(|t| t.to_lowercase() == lower) { tags.push(tag); }
+        }
+        self.tags = Some(tags);
+
+        let handles = parse_mentions(&body);
+        if handles.len() == 0 {
+            self.mentions = None;
+            return Ok(());
+        }
+        // (lowercased username, user_id) for everyone in the space
+        let members = {
+            let profile_guard = lockr!(turtl.profile);
+            profile_guard.spaces.iter()
+                .filter(|space| space.id() == Some(&self.space_id))
+                .flat_map(|space| space.members.iter())
+                .map(|m| (m.username.to_lowercase(), m.user_id.clone()))
+                .collect::<Vec<_>>()
+        };
+        let mentions = handles.into_iter()
+            .map(|handle| {
+                let user_id = members.iter()
+                    .find(|&&(ref username, _)| {
+                        username == &handle || username.split('@').next() == Some(handle.as_str())
+                    })
+                    .map(|&(_, ref user_id)| user_id.clone());
+                Mention { handle: handle, user_id: user_id }
+            })
+            .collect::<Vec<_>>();
+        self.mentions = Some(mentions);
+        Ok(())
+    }
+
+    /// Clean up any HTML in the note (see `sanitize`). The embed and article
+    /// always get cleaned, the text only for note types that hold HTML. Call
+    /// before saving.
+    pub fn sanitize(&mut self) {
+        if !sanitize::enabled() { return; }
+        let html_type = match self.type_.as_ref() {
+            Some(ty) => sanitize::html_note_types().contains(ty),
+            None => false,
+        };
+        if html_type {
+            self.text = self.text.as_ref().map(|x| sanitize::html(x));
+        }
+        self.embed = self.embed.as_ref().map(|x| sanitize::html(x));
+        self.article = self.article.as_ref().map(|x| sanitize::html(x));
+    }
+
+    /// Mark the note as changed just now. The UI sets `mod` on the notes it
+    /// edits, so anything in here that changes a note on its own should call
+    /// this before saving.
+    pub fn touch(&mut self) {
+        self.mod_ = Some(time::get_time().sec as i64);
+    }
+
+    /// Every URL this note points at: its url field plus any in the body
+    pub fn links(&self) -> Vec<String> {
+        let mut links = Vec::new();
+        if let Some(ref url) = self.url {
+            if url.trim() != "" { links.push(String::from(url.trim())); }
+        }
+        if let Some(ref text) = self.text {
+            for url in parse_urls(text) {
+                if !links.contains(&url) { links.push(url); }
+            }
+        }
+        links
+    }
+
+    /// Find every place the given text (or regex) matches in this note's title
+    /// and body. The note must already be decrypted.
+    pub fn find_text(&self, query: &str, options: &FindTextOptions) -> TResult<Vec<TextMatch>> {
+        let re = options.compile(query)?;
+        let mut matches = Vec::new();
+        let fields = vec![("title", self.title.as_ref()), ("text", self.text.as_ref())];
+        for (field, val) in fields {
+            let val = match val {
+                Some(x) => x,
+                None => continue,
+            };
+            for (start, end) in re.find_iter(val) {
+                // empty matches (think "a*") don't highlight anything
+                if start == end { continue; }
+                let char_start = val[0..start].chars().count();
+                matches.push(TextMatch {
+                    field: String::from(field),
+                    start: start,
+                    end: end,
+                    char_start: char_start,
+                    char_end: char_start + val[start..end].chars().count(),
+                });
+            }
+        }
+        Ok(matches)
+    }
+
+    /// Called after an incoming note is saved. If someone else mentioned us in
+    /// it (and we haven't already said so), let the UI know.
+    pub fn check_mentioned(turtl: &Turtl, note_id: &String, editor_id: &String) -> TResult<()> {
+        let user_id = turtl.user_id()?;
+        if editor_id == &user_id { return Ok(()); }
+        let notes = turtl.load_notes(&vec![note_id.clone()])?;
+        let mentioned = match notes.get(0) {
+            Some(note) => note.mentions.as_ref()
+                .map(|m| m.iter().any(|x| x.user_id.as_ref() == Some(&user_id)))
+                .unwrap_or(false),
+            None => return Ok(()),
+        };
+        let seen = with_db!{ db, turtl.db, db.kv_get(MENTIONED_KEY)? };
+        let mut seen: Vec<String> = match seen {
+            Some(x) => jedi::parse(&x)?,
+            None => Vec::new(),
+        };
+        let already = seen.contains(note_id);
+        if mentioned == already { return Ok(()); }
+        if mentioned {
+            messaging::ui_event("note:mentioned", &json!({
+                "note_id": note_id,
+                "user_id": editor_id,
+            }))?;
+            seen.push(note_id.clone());
+        } else {
+            // if they mention us again later, we want to hear about it
+            seen.retain(|x| x != note_id);
+        }
+        let serialized = jedi::stringify(&seen)?;
+        with_db!{ db, turtl.db, db.kv_set(MENTIONED_KEY, &serialized)? };
+        Ok(())
+    }
+
+    /// Called for incoming sync records. If the record changes a note we're
+    /// watching and the change was made by someone else, let the UI know.
+    pub fn check_watched(turtl: &Turtl, sync_item: &SyncRecord) -> TResult<()> {
+        match sync_item.action {
+            SyncAction::Edit | SyncAction::Delete | SyncAction::MoveSpace => {}
+            _ => return Ok(()),
+        }
+        if sync_item.user_id == turtl.user_id()? { return Ok(()); }
+        let watched = Note::watched(turtl)?;
+        if !watched.contains(&sync_item.item_id) { return Ok(()); }
+        messaging::ui_event("note:watched-changed", &json!({
+            "note_id": &sync_item.item_id,
+            "action": &sync_item.action,
+            "user_id": &sync_item.user_id,
+        }))?;
+        // no use watching a note that isn't there anymore
+        if sync_item.action == SyncAction::Delete {
+            Note::set_watch(turtl, &sync_item.item_id, false)?;
+        }
+        Ok(())
+    }
 }
 
 impl Keyfinder for Note {
@@ -203,6 +662,10 @@ impl MemorySaver for Note {
                 if notes.len() == 0 { return Ok(()); }
                 let note = &notes[0];
                 sync_item.data = Some(note.data()?);
+                match webhook::note_changed(turtl, note, &action) {
+                    Ok(_) => {}
+                    Err(e) => warn!("Note.mem_update() -- error sending webhooks: {}", e),
+                }
                 let mut search_guard = lock!(turtl.search);
                 match search_guard.as_mut() {
                     Some(ref mut search) => {
@@ -228,3 +691,55 @@ impl MemorySaver for Note {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_hashtags() {
+        assert_eq!(parse_hashtags("#todo call mom about #birthday-party, #todo"), vec!["todo", "birthday-party"]);
+        assert_eq!(parse_hashtags("# heading\nC# and page#anchor (#work) #123 #café_"), vec!["work", "café"]);
+        assert_eq!(parse_hashtags(""), Vec::<String>::new());
+    }
+
+    #[test]
+    fn parses_mentions() {
+        assert_eq!(parse_mentions("hey @Andrew, ask @lisa@turtlapp.com. (@andrew)"), vec!["andrew", "lisa@turtlapp.com"]);
+        assert_eq!(parse_mentions("mail me at andrew@turtlapp.com or @ me"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn parses_urls() {
+        let text = "see https://turtlapp.com/docs. and [this](http://en.wikipedia.org/wiki/Turtle_(disambiguation)) or (https://a.com/x) httpfoo https://turtlapp.com/docs";
+        assert_eq!(parse_urls(text), vec![
+            "https://turtlapp.com/docs",
+            "http://en.wikipedia.org/wiki/Turtle_(disambiguation)",
+            "https://a.com/x",
+        ]);
+        assert_eq!(normalize_url("HTTPS://WWW.TurtlApp.com/docs/?a=1#top"), Some((String::from("turtlapp.com/docs?a=1"), String::from("turtlapp.com"))));
+        assert_eq!(normalize_url("http://turtlapp.com:8080/"), Some((String::from("turtlapp.com:8080"), String::from("turtlapp.com"))));
+        assert_eq!(normalize_url("ftp://turtlapp.com/"), None);
+        assert_eq!(normalize_url("not a url"), None);
+    }
+
+    #[test]
+    fn finds_text_in_notes() {
+        let mut note = Note::new();
+        note.title = Some(String::from("Café list"));
+        note.text = Some(String::from("café au lait, then another CAFÉ"));
+        let found = note.find_text("café", &FindTextOptions::default()).unwrap();
+        assert_eq!(found.len(), 3);
+        assert_eq!((found[0].start, found[0].end, found[0].char_end), (0, 5, 4));
+        assert_eq!(found[2].field, "text");
+        assert_eq!((found[2].char_start, found[2].char_end), (27, 31));
+        assert_eq!(found[2].start, 28);
+
+        let options = FindTextOptions { regex: true, case_sensitive: true };
+        let found = note.find_text("caf. (au|list)", &options).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].field, "text");
+        assert!(note.find_text("a.b", &FindTextOptions::default()).unwrap().is_empty());
+        assert!(note.find_text("(unclosed", &options).is_err());
+    }
+}
@@ -80,6 +80,7 @@ pub fn map_deserialize<T>(turtl: &Turtl, vec: Vec<T>) -> TResult<Vec<T>>
             // run the deserialize, return the result into our future chain
             let fut = work.run_async(move || model_clone.deserialize())
                 .and_then(move |item_mapped: Value| -> TFutureResult<DeserializeResult<T>> {
+                    model.mark_unknown_public();
                     ftry!(model.merge_fields(&item_mapped));
                     FOk!(DeserializeResult::Model(model))
                 })
@@ -162,6 +163,18 @@ pub trait Protected: Model + fmt::Debug {
     /// Grab the private fields for this model
     fn private_fields(&self) -> Vec<&'static str>;
 
+    /// Grab the names of every field this model knows about
+    fn known_fields(&self) -> Vec<&'static str>;
+
+    /// Grab the fields we got that this model doesn't know about
+    fn unknown_fields(&self) -> &JsonMap<String, Value>;
+
+    /// Grab the names of the unknown fields that are public
+    fn unknown_public(&self) -> Option<&Vec<String>>;
+
+    /// Set which unknown fields are public
+    fn set_unknown_public(&mut self, public: Option<Vec<String>>);
+
     /// Grab the fields names of any child models this model has
     fn submodel_fields(&self) -> Vec<&'static str>;
 
@@ -236,9 +249,32 @@ pub trait Protected: Model + fmt::Debug {
                 Err(..) => {},
             }
         }
+        // unknown fields go wherever they came from. if we don't know where
+        // that was, they go everywhere (serialize() makes sure that doesn't
+        // leak anything).
+        let known = self.known_fields();
+        for (field, val) in self.unknown_fields() {
+            if map.contains_key(field) || known.contains(&field.as_str()) { continue; }
+            let include = match self.unknown_public() {
+                Some(public) => public.contains(field) != private,
+                None => true,
+            };
+            if include {
+                map.insert(field.clone(), val.clone());
+            }
+        }
         Ok(Value::Object(map))
     }
 
+    /// Note that the unknown fields we have right now came from outside of
+    /// `body`. Call this before merging in decrypted data.
+    fn mark_unknown_public(&mut self) {
+        if self.unknown_public().is_none() {
+            let fields = self.unknown_fields().keys().map(|x| x.clone()).collect::<Vec<_>>();
+            self.set_unknown_public(Some(fields));
+        }
+    }
+
     /// Grab all public fields for this model as a json Value
     ///
     /// NOTE: Don't use this directly. Use `data_for_storage()` instead!
@@ -293,6 +329,11 @@ pub trait Protected: Model + fmt::Debug {
         if self.key().is_none() {
             return TErr!(TError::MissingField(format!("model {:?} missing `key`", self.id())));
         }
+        // unknown fields we can't place (ie, the UI sent them in) get
+        // encrypted. they might be private, and we're not about to guess.
+        if self.unknown_public().is_none() {
+            self.set_unknown_public(Some(Vec::new()));
+        }
         self.serialize_submodels()?;
         let body;
         {
@@ -351,10 +392,27 @@ pub trait Protected: Model + fmt::Debug {
                 return TErr!(err);
             },
         };
+        self.mark_unknown_public();
         self.merge_fields(&parsed)?;
         Ok(self._private_data()?)
     }
 
+    /// Grab the crypto serialization version our `body` was encrypted with, if
+    /// we have a body. We only decode the first few bytes of the body here, so
+    /// this is cheap enough to run on a big pile of models.
+    fn body_version(&self) -> TResult<Option<u16>> {
+        let body = match self.get_body() {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+        // 4 base64 chars == 3 bytes, which covers our 2-byte version
+        if body.len() < 4 {
+            return TErr!(TError::BadValue(format!("model {:?} ({}) has a malformed `body`", self.id(), self.model_type())));
+        }
+        let header = crypto::from_base64(&String::from(&body[0..4]))?;
+        Ok(Some(crypto::payload_version(header.as_slice())?))
+    }
+
     /// Given a set of keydata, replace the self.keys object
     fn generate_subkeys(&mut self, keydata: &Vec<KeyRef<Key>>) -> TResult<()> {
         if self.key().is_none() {
@@ -401,6 +459,16 @@ macro_rules! protected {
                 #[protected_field(public)]
                 body: Option<String>, 
 
+                /// Fields we don't know about (probably from a newer version
+                /// of turtl). We hold onto them so saving the model doesn't
+                /// quietly throw them away.
+                #[serde(flatten)]
+                _unknown: ::jedi::Map<String, ::jedi::Value>,
+                /// Which of our unknown fields live outside of `body`. None
+                /// means we don't know where they came from.
+                #[serde(skip)]
+                _unknown_public: Option<Vec<String>>,
+
                 $( $inner )*
             }
         }
@@ -548,6 +616,39 @@ mod tests {
         assert_eq!(dog2.tags.unwrap(), vec!["flappy", "noisy"]);
     }
 
+    #[test]
+    fn keeps_unknown_fields() {
+        // a dog from a newer client, with a new public field (color) and a
+        // new private field (nickname)
+        let mut newdog: Dog = jedi::parse(&String::from(r#"{"id":"1","size":12,"name":"fido","nickname":"fifi"}"#)).unwrap();
+        let key = crypto::Key::random().unwrap();
+        newdog.set_key(Some(key.clone()));
+        // we can't tell where nickname came from, so it stays private
+        let serialized = newdog.serialize().unwrap();
+        assert_eq!(jedi::get_opt::<String>(&["nickname"], &serialized), None);
+        let mut stored = serialized.clone();
+        jedi::set(&["color"], &mut stored, &String::from("brown")).unwrap();
+
+        // load it the way we'd load it from storage, then decrypt it
+        let mut dog: Dog = jedi::from_val(stored).unwrap();
+        dog.set_key(Some(key.clone()));
+        dog.deserialize().unwrap();
+        assert_eq!(dog.name, Some(String::from("fido")));
+        assert_eq!(jedi::get::<String>(&["nickname"], &dog.data().unwrap()).unwrap(), "fifi");
+
+        // edit and save it again. nothing gets lost, and nothing moves.
+        dog.size = Some(13);
+        let serialized = dog.serialize().unwrap();
+        assert_eq!(jedi::get::<String>(&["color"], &serialized).unwrap(), "brown");
+        assert_eq!(jedi::get_opt::<String>(&["nickname"], &serialized), None);
+        let mut dog2: Dog = jedi::from_val(serialized).unwrap();
+        dog2.set_key(Some(key.clone()));
+        let private = dog2.deserialize().unwrap();
+        assert_eq!(jedi::get::<String>(&["nickname"], &private).unwrap(), "fifi");
+        assert_eq!(jedi::get_opt::<String>(&["color"], &private), None);
+        assert_eq!(dog2.size, Some(13));
+    }
+
     #[test]
     fn decrypts_utf8() {
         let mut note: Note = jedi::parse(&String::from(r#"{"id":"015ce7ea7f742af6297cf0cc29180f9cc45f4c80e5b30238581f845367f9c404ef3fb8fb0a5a00aa","space_id":"015bac22440a4944baee41b88207731eaeb7e2cc5c955fb8a05b028c1409aaf55024f5d26fa3001e","board_id":"015bac2244ea4944baee41b88207731eaeb7e2cc5c955fb8a05b028c1409aaf55024f5d26fa30034","user_id":51,"file":{},"keys":[{"s":"015bac22440a4944baee41b88207731eaeb7e2cc5c955fb8a05b028c1409aaf55024f5d26fa3001e","k":"AAYBAAyAjxgMehPHn+xMYOAW8/aGgxRrQN8FvB/lQoI2uX7khX8eQi2un4eFa73kboM6UAiCvSKGnmX9DNIwGk4="},{"b":"015bac2244ea4944baee41b88207731eaeb7e2cc5c955fb8a05b028c1409aaf55024f5d26fa30034","k":"AAYBAAy/IWmcQN42Iva4LqNg0eDAIU4slpoAZ/8487NJxXjISkd4HmOLxBPg/Lbf7pa5E/MB7pOsTHGLENcDoWw="},{"s":"015bac22440a4944baee41b88207731eaeb7e2cc5c955fb8a05b028c1409aaf55024f5d26fa3001e","k":"AAYBAAyAjxgMehPHn+xMYOAW8/aGgxRrQN8FvB/lQoI2uX7khX8eQi2un4eFa73kboM6UAiCvSKGnmX9DNIwGk4="},{"b":"015bac2244ea4944baee41b88207731eaeb7e2cc5c955fb8a05b028c1409aaf55024f5d26fa30034","k":"AAYBAAy/IWmcQN42Iva4LqNg0eDAIU4slpoAZ/8487NJxXjISkd4HmOLxBPg/Lbf7pa5E/MB7pOsTHGLENcDoWw="}],"mod":1498539524,"body":"AAYBAAw/xkOg209rBB+kSM2o8aKTvzsDuY0bcwN7W5zuwf+kFPCAEH/ERnxIbO1SOE4+Z3+WUwRDhsOSx9VR2gTON9bcMCWUiS1DP5oNWhLZ9HZxvF1dlpN6jnfTokeE7Aw0uVjIrSma3AW7vaA3tTokZdW9j7fpqzBYGZXrZT6+1/RAsKrHiayVGZdR//4iKoRZeysgsu8Hn6aaMhgJ+tSV9Kz7MZeKHJb2fxWVr1BTZQeRWoXKhjU="}"#)).unwrap();
@@ -0,0 +1,225 @@
+use ::std::collections::HashMap;
+use ::jedi::{self, Value};
+use ::error::{TResult, TError};
+use ::crypto::Key;
+use ::models::model::Model;
+use ::models::validate::{self, Validate};
+use ::models::protected::{self, Keyfinder, Protected};
+use ::models::keychain::{Keychain, KeyRef, KeyType};
+use ::models::note::Note;
+use ::models::board::Board;
+use ::models::sync_record::SyncAction;
+use ::models::storable::Storable;
+use ::sync::sync_model::{self, SyncModel, MemorySaver};
+use ::turtl::Turtl;
+use ::lib_permissions::Permission;
+
+protected! {
+    /// An emoji reaction someone left on a note. Like comments, these are
+    /// encrypted with the key of the space the note lives in.
+    #[derive(Serialize, Deserialize)]
+    pub struct Reaction {
+        #[serde(with = "::util::ser::int_converter")]
+        #[protected_field(public)]
+        pub user_id: String,
+        #[protected_field(public)]
+        pub space_id: String,
+        #[protected_field(public)]
+        pub note_id: String,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        #[protected_field(private)]
+        pub emoji: Option<String>,
+    }
+}
+
+/// Summarizes the reactions for one emoji on a note
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ReactionSummary {
+    pub emoji: String,
+    pub count: usize,
+    /// Whether or not the current user is one of the reactors
+    pub mine: bool,
+}
+
+make_storable!(Reaction, "reactions");
+impl SyncModel for Reaction {}
+// reactions aren't held in memory, they're loaded from the db on demand
+impl MemorySaver for Reaction {}
+
+impl Validate for Reaction {
+    fn validate(&self) -> Vec<(String, String)> {
+        let mut errors = Vec::new();
+        if self.note_id == "" {
+            errors.push(validate::entry("note_id", t!("Please add a note id to this reaction")));
+        }
+        if self.emoji.as_ref().map(|x| x == "").unwrap_or(true) {
+            errors.push(validate::entry("emoji", t!("Please pick an emoji")));
+        }
+        errors
+    }
+}
+
+impl Keyfinder for Reaction {
+    fn get_key_search(&self, turtl: &Turtl) -> TResult<Keychain> {
+        let mut keychain = Keychain::new();
+        let ty = String::from("space");
+        let profile_guard = lockr!(turtl.profile);
+        for space in &profile_guard.spaces {
+            if space.id() != Some(&self.space_id) { continue; }
+            match space.key() {
+                Some(k) => keychain.upsert_key(turtl, &self.space_id, k, &ty)?,
+                None => {}
+            }
+        }
+        Ok(keychain)
+    }
+
+    fn get_keyrefs(&self, turtl: &Turtl) -> TResult<Vec<KeyRef<Key>>> {
+        let mut refs: Vec<KeyRef<Key>> = Vec::new();
+        let profile_guard = lockr!(turtl.profile);
+        for space in &profile_guard.spaces {
+            if space.id() == Some(&self.space_id) && space.key().is_some() {
+                refs.push(KeyRef {
+                    id: self.space_id.clone(),
+                    ty: KeyType::Space,
+                    k: space.key().expect("turtl::Reaction.get_keyrefs() -- space key is None").clone(),
+                });
+            }
+        }
+        Ok(refs)
+    }
+}
+
+/// Roll a set of (decrypted) reactions up into per-emoji summaries, most
+/// popular first.
+fn summarize(reactions: &Vec<Reaction>, user_id: &String) -> Vec<ReactionSummary> {
+    let mut summaries: Vec<ReactionSummary> = Vec::new();
+    for reaction in reactions {
+        let emoji = match reaction.emoji.as_ref() {
+            Some(x) => x,
+            None => continue,
+        };
+        let mine = &reaction.user_id == user_id;
+        match summaries.iter_mut().position(|s| &s.emoji == emoji) {
+            Some(idx) => {
+                summaries[idx].count += 1;
+                summaries[idx].mine = summaries[idx].mine || mine;
+            }
+            None => {
+                summaries.push(ReactionSummary {
+                    emoji: emoji.clone(),
+                    count: 1,
+                    mine: mine,
+                });
+            }
+        }
+    }
+    // stable sort, so ties stay in the order they were first seen
+    summaries.sort_by(|a, b| b.count.cmp(&a.count));
+    summaries
+}
+
+impl Reaction {
+    /// React to a note. Reacting twice with the same emoji is a no-op.
+    pub fn react(turtl: &Turtl, note_id: &String, emoji: String) -> TResult<()> {
+        let user_id = turtl.user_id()?;
+        if Reaction::find_mine(turtl, note_id, &emoji)?.is_some() {
+            return Ok(());
+        }
+        let note: Option<Note> = with_db!{ db, turtl.db, db.get(Note::tablename(), note_id)? };
+        let note = match note {
+            Some(x) => x,
+            None => return TErr!(TError::NotFound(format!("note {} wasn't found", note_id))),
+        };
+        Board::permission_check(turtl, &note.space_id, note.board_id.as_ref(), &Permission::EditNote)?;
+        let mut reaction = Reaction::new();
+        reaction.user_id = user_id;
+        reaction.space_id = note.space_id.clone();
+        reaction.note_id = note_id.clone();
+        reaction.emoji = Some(emoji);
+        sync_model::save_model(SyncAction::Add, turtl, &mut reaction, false)?;
+        Ok(())
+    }
+
+    /// Take back a reaction
+    pub fn unreact(turtl: &Turtl, note_id: &String, emoji: &String) -> TResult<()> {
+        match Reaction::find_mine(turtl, note_id, emoji)? {
+            Some(id) => sync_model::delete_model::<Reaction>(turtl, &id, false),
+            None => Ok(()),
+        }
+    }
+
+    /// Load and decrypt all reactions for the given notes
+    fn load(turtl: &Turtl, note_ids: &Vec<String>) -> TResult<Vec<Reaction>> {
+        let mut reactions: Vec<Reaction> = with_db!{ db, turtl.db,
+            db.find(Reaction::tablename(), "note_id", note_ids)?
+        };
+        turtl.find_models_keys(&mut reactions)?;
+        protected::map_deserialize(turtl, reactions)
+    }
+
+    /// Find the id of our reaction with the given emoji on the given note
+    fn find_mine(turtl: &Turtl, note_id: &String, emoji: &String) -> TResult<Option<String>> {
+        let user_id = turtl.user_id()?;
+        let reactions = Reaction::load(turtl, &vec![note_id.clone()])?;
+        Ok(reactions.into_iter()
+            .filter(|r| r.user_id == user_id && r.emoji.as_ref() == Some(emoji))
+            .filter_map(|r| r.id().map(|x| x.clone()))
+            .next())
+    }
+
+    /// Given a set of notes, return their serialized data with a `reactions`
+    /// summary attached to each.
+    pub fn attach(turtl: &Turtl, notes: &Vec<Note>) -> TResult<Vec<Value>> {
+        let note_ids = notes.iter()
+            .filter_map(|n| n.id().map(|x| x.clone()))
+            .collect::<Vec<_>>();
+        let user_id = turtl.user_id()?;
+        let mut grouped: HashMap<String, Vec<Reaction>> = HashMap::new();
+        for reaction in Reaction::load(turtl, &note_ids)? {
+            grouped.entry(reaction.note_id.clone()).or_insert_with(Vec::new).push(reaction);
+        }
+        let mut vals = Vec::with_capacity(notes.len());
+        for note in notes {
+            let mut val = jedi::to_val(note)?;
+            let summary = match note.id().and_then(|id| grouped.get(id)) {
+                Some(reactions) => summarize(reactions, &user_id),
+                None => Vec::new(),
+            };
+            jedi::set(&["reactions"], &mut val, &summary)?;
+            vals.push(val);
+        }
+        Ok(vals)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reaction(user_id: &str, emoji: &str) -> Reaction {
+        let mut reaction = Reaction::new();
+        reaction.user_id = String::from(user_id);
+        reaction.note_id = String::from("1234");
+        reaction.emoji = Some(String::from(emoji));
+        reaction
+    }
+
+    #[test]
+    fn summarizes_reactions() {
+        let reactions = vec![
+            reaction("1", "tada"),
+            reaction("2", "+1"),
+            reaction("3", "+1"),
+            reaction("2", "tada"),
+            reaction("3", "eyes"),
+        ];
+        let summary = summarize(&reactions, &String::from("1"));
+        assert_eq!(summary, vec![
+            ReactionSummary { emoji: String::from("tada"), count: 2, mine: true },
+            ReactionSummary { emoji: String::from("+1"), count: 2, mine: false },
+            ReactionSummary { emoji: String::from("eyes"), count: 1, mine: false },
+        ]);
+    }
+}
@@ -0,0 +1,201 @@
+use ::time;
+use ::error::{TResult, TError};
+use ::crypto::Key;
+use ::models::model::Model;
+use ::models::validate::{self, Validate};
+use ::models::protected::{self, Keyfinder, Protected};
+use ::models::keychain::{Keychain, KeyRef, KeyType};
+use ::models::note::Note;
+use ::models::space::Space;
+use ::models::sync_record::SyncAction;
+use ::models::storable::Storable;
+use ::sync::sync_model::{self, SyncModel, MemorySaver};
+use ::turtl::Turtl;
+
+protected! {
+    /// Records that a member of a space has seen a note. We only keep one of
+    /// these per user per note, and only for spaces that opted into read
+    /// receipts.
+    #[derive(Serialize, Deserialize)]
+    pub struct Receipt {
+        #[serde(with = "::util::ser::int_converter")]
+        #[protected_field(public)]
+        pub user_id: String,
+        #[protected_field(public)]
+        pub space_id: String,
+        #[protected_field(public)]
+        pub note_id: String,
+
+        /// When the note was (last) seen
+        #[serde(skip_serializing_if = "Option::is_none")]
+        #[protected_field(private)]
+        pub seen: Option<i64>,
+    }
+}
+
+/// What we hand back to the UI for `note:seen-by`
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SeenBy {
+    pub user_id: String,
+    pub seen: i64,
+}
+
+make_storable!(Receipt, "receipts");
+impl SyncModel for Receipt {}
+// receipts aren't held in memory, they're loaded from the db on demand
+impl MemorySaver for Receipt {}
+
+impl Validate for Receipt {
+    fn validate(&self) -> Vec<(String, String)> {
+        let mut errors = Vec::new();
+        if self.note_id == "" {
+            errors.push(validate::entry("note_id", t!("Please add a note id to this receipt")));
+        }
+        errors
+    }
+}
+
+impl Keyfinder for Receipt {
+    fn get_key_search(&self, turtl: &Turtl) -> TResult<Keychain> {
+        let mut keychain = Keychain::new();
+        let ty = String::from("space");
+        let profile_guard = lockr!(turtl.profile);
+        for space in &profile_guard.spaces {
+            if space.id() != Some(&self.space_id) { continue; }
+            match space.key() {
+                Some(k) => keychain.upsert_key(turtl, &self.space_id, k, &ty)?,
+                None => {}
+            }
+        }
+        Ok(keychain)
+    }
+
+    fn get_keyrefs(&self, turtl: &Turtl) -> TResult<Vec<KeyRef<Key>>> {
+        let mut refs: Vec<KeyRef<Key>> = Vec::new();
+        let profile_guard = lockr!(turtl.profile);
+        for space in &profile_guard.spaces {
+            if space.id() == Some(&self.space_id) && space.key().is_some() {
+                refs.push(KeyRef {
+                    id: self.space_id.clone(),
+                    ty: KeyType::Space,
+                    k: space.key().expect("turtl::Receipt.get_keyrefs() -- space key is None").clone(),
+                });
+            }
+        }
+        Ok(refs)
+    }
+}
+
+impl Receipt {
+    /// Load and decrypt all receipts for a note
+    fn load(turtl: &Turtl, note_id: &String) -> TResult<Vec<Receipt>> {
+        let mut receipts: Vec<Receipt> = with_db!{ db, turtl.db,
+            db.find(Receipt::tablename(), "note_id", &vec![note_id.clone()])?
+        };
+        turtl.find_models_keys(&mut receipts)?;
+        protected::map_deserialize(turtl, receipts)
+    }
+
+    /// Mark a note as seen by the current user. Does nothing if the note's
+    /// space hasn't opted into read receipts.
+    pub fn mark_seen(turtl: &Turtl, note_id: &String) -> TResult<()> {
+        let note: Option<Note> = with_db!{ db, turtl.db, db.get(Note::tablename(), note_id)? };
+        let note = match note {
+            Some(x) => x,
+            None => return TErr!(TError::NotFound(format!("note {} wasn't found", note_id))),
+        };
+        if !Space::read_receipts_enabled(turtl, &note.space_id) {
+            return Ok(());
+        }
+        let user_id = turtl.user_id()?;
+        let existing = Receipt::load(turtl, note_id)?
+            .into_iter()
+            .filter(|r| r.user_id == user_id)
+            .next();
+        let (action, mut receipt) = match existing {
+            Some(x) => (SyncAction::Edit, x),
+            None => {
+                let mut receipt = Receipt::new();
+                receipt.user_id = user_id;
+                receipt.space_id = note.space_id.clone();
+                receipt.note_id = note_id.clone();
+                (SyncAction::Add, receipt)
+            }
+        };
+        receipt.seen = Some(time::get_time().sec as i64);
+        sync_model::save_model(action, turtl, &mut receipt, false)?;
+        Ok(())
+    }
+
+    /// Grab who has seen a note (and when), most recent first
+    pub fn seen_by(turtl: &Turtl, note_id: &String) -> TResult<Vec<SeenBy>> {
+        let mut seen = Receipt::load(turtl, note_id)?
+            .into_iter()
+            .filter_map(|r| r.seen.map(|s| SeenBy { user_id: r.user_id.clone(), seen: s }))
+            .collect::<Vec<_>>();
+        seen.sort_by(|a, b| b.seen.cmp(&a.seen));
+        Ok(seen)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::error::ErrorCode;
+    use ::models::sync_record::{SyncRecord, SyncType};
+
+    const NOTE_ID: &'static str = "015caf7c5f4d2af6297cf0cc29180f9cc45f4c80e5b30238581f845367f9c404ef3fb8fb0a5a022b";
+    const SPACE_ID: &'static str = "015bac22440a4944baee41b88207731eaeb7e2cc5c955fb8a05b028c1409aaf55024f5d26fa3001e";
+
+    fn enable_receipts(turtl: &Turtl) {
+        let mut profile_guard = lockw!(turtl.profile);
+        let space = profile_guard.spaces.iter_mut()
+            .find(|x| x.id() == Some(&String::from(SPACE_ID)))
+            .unwrap();
+        space.read_receipts = Some(true);
+    }
+
+    fn sync_records(turtl: &Turtl) -> Vec<SyncRecord> {
+        let mut db_guard = lock!(turtl.db);
+        SyncRecord::find(db_guard.as_mut().unwrap(), Some(SyncType::Receipt)).unwrap()
+    }
+
+    #[test]
+    fn receipts_round_trip() {
+        let turtl = ::turtl::tests::with_test(true);
+        ::turtl::tests::load_test_profile(&turtl);
+        let note_id = String::from(NOTE_ID);
+
+        // spaces have to opt in
+        Receipt::mark_seen(&turtl, &note_id).unwrap();
+        assert_eq!(Receipt::seen_by(&turtl, &note_id).unwrap().len(), 0);
+        assert_eq!(sync_records(&turtl).len(), 0);
+
+        enable_receipts(&turtl);
+        Receipt::mark_seen(&turtl, &note_id).unwrap();
+        Receipt::mark_seen(&turtl, &note_id).unwrap();
+        let seen = Receipt::seen_by(&turtl, &note_id).unwrap();
+        assert_eq!(seen.len(), 1);
+        assert_eq!(seen[0].user_id, "51");
+        let mut records = sync_records(&turtl);
+        assert_eq!(records.iter().map(|x| x.action.clone()).collect::<Vec<_>>(), vec![SyncAction::Add, SyncAction::Edit]);
+
+        // another device in the space sees who has seen the note
+        let other = ::turtl::tests::with_test(true);
+        ::turtl::tests::load_test_profile(&other);
+        {
+            let mut db_guard = lock!(other.db);
+            let db = db_guard.as_mut().unwrap();
+            for rec in records.iter_mut() {
+                Receipt::new().incoming(db, rec).unwrap();
+            }
+        }
+        let theirs = Receipt::seen_by(&other, &note_id).unwrap();
+        assert_eq!(theirs.len(), 1);
+        assert_eq!(theirs[0].user_id, "51");
+        assert_eq!(theirs[0].seen, seen[0].seen);
+
+        let err = Receipt::mark_seen(&turtl, &String::from("1234")).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::NotFound);
+    }
+}
@@ -8,6 +8,7 @@ use ::models::space_member::SpaceMember;
 use ::models::sync_record::{SyncRecord, SyncAction};
 use ::models::validate::{self, Validate};
 use ::models::keychain;
+use ::ordering::Sortable;
 use ::sync::sync_model::{self, SyncModel, MemorySaver};
 use ::turtl::Turtl;
 use ::lib_permissions::{Role, Permission};
@@ -39,6 +40,11 @@ protected! {
         #[serde(skip_serializing_if = "Option::is_none")]
         #[protected_field(private)]
         pub color: Option<String>,
+
+        /// Whether members of this space share when they've seen a note
+        #[serde(skip_serializing_if = "Option::is_none")]
+        #[protected_field(private)]
+        pub read_receipts: Option<bool>,
     }
 }
 
@@ -100,6 +106,11 @@ impl Validate for Space {
     }
 }
 
+impl Sortable for Space {
+    fn sort_id(&self) -> Option<&String> { self.id() }
+    fn sort_title(&self) -> Option<&String> { self.title.as_ref() }
+}
+
 impl Keyfinder for Space {
     // We definitely want to save space keys to the keychain
     fn add_to_keychain(&self) -> bool {
@@ -162,6 +173,14 @@ impl MemorySaver for Space {
 }
 
 impl Space {
+    /// Check if the given space has opted into read receipts
+    pub fn read_receipts_enabled(turtl: &Turtl, space_id: &String) -> bool {
+        let profile_guard = lockr!(turtl.profile);
+        profile_guard.spaces.iter()
+            .filter(|space| space.id() == Some(space_id))
+            .any(|space| space.read_receipts == Some(true))
+    }
+
     /// Given a Turtl, a space_id, and a Permission, check if the current user
     /// has the rights to that permission.
     pub fn permission_check(turtl: &Turtl, space_id: &String, permission: &Permission) -> TResult<()> {
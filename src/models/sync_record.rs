@@ -50,6 +50,18 @@ pub enum SyncType {
     FileOutgoing,
     #[serde(rename = "invite")]
     Invite,
+    #[serde(rename = "contact")]
+    Contact,
+    #[serde(rename = "comment")]
+    Comment,
+    #[serde(rename = "reaction")]
+    Reaction,
+    #[serde(rename = "receipt")]
+    Receipt,
+    #[serde(rename = "email")]
+    Email,
+    #[serde(rename = "time_entry")]
+    TimeEntry,
 }
 
 impl SyncType {
@@ -0,0 +1,300 @@
+use ::std::collections::HashMap;
+use ::time;
+use ::jedi::{self, Value};
+use ::error::{TResult, TError};
+use ::crypto::Key;
+use ::models::model::Model;
+use ::models::validate::{self, Validate};
+use ::models::protected::{self, Keyfinder, Protected};
+use ::models::keychain::{Keychain, KeyRef, KeyType};
+use ::models::note::Note;
+use ::models::board::Board;
+use ::models::sync_record::SyncAction;
+use ::models::storable::Storable;
+use ::sync::sync_model::{self, SyncModel, MemorySaver};
+use ::search::DateRange;
+use ::turtl::Turtl;
+use ::lib_permissions::Permission;
+use ::dispatch::registry::Registry;
+
+protected! {
+    /// Time someone spent on a note. Like reactions, these are encrypted with
+    /// the key of the space the note lives in. An entry without an `end` is a
+    /// running timer, and each user gets one of those at most.
+    #[derive(Serialize, Deserialize)]
+    #[protected_modeltype(time_entry)]
+    pub struct TimeEntry {
+        #[serde(with = "::util::ser::int_converter")]
+        #[protected_field(public)]
+        pub user_id: String,
+        #[protected_field(public)]
+        pub space_id: String,
+        #[protected_field(public)]
+        pub note_id: String,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        #[protected_field(private)]
+        pub start: Option<i64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        #[protected_field(private)]
+        pub end: Option<i64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        #[protected_field(private)]
+        pub description: Option<String>,
+    }
+}
+
+/// How much time went where
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct TimeReport {
+    /// Total seconds tracked in the range
+    pub total: i64,
+    /// Seconds per board id (notes without a board are under "")
+    pub boards: HashMap<String, i64>,
+    /// Seconds per tag. A note with two tags counts toward both.
+    pub tags: HashMap<String, i64>,
+}
+
+make_storable!(TimeEntry, "time_entries");
+impl SyncModel for TimeEntry {}
+// time entries aren't held in memory, they're loaded from the db on demand
+impl MemorySaver for TimeEntry {}
+
+impl Validate for TimeEntry {
+    fn validate(&self) -> Vec<(String, String)> {
+        let mut errors = Vec::new();
+        if self.note_id == "" {
+            errors.push(validate::entry("note_id", t!("Please add a note id to this time entry")));
+        }
+        match (self.start, self.end) {
+            (None, _) => errors.push(validate::entry("start", t!("Please give this time entry a start time"))),
+            (Some(start), Some(end)) if end < start => {
+                errors.push(validate::entry("end", t!("A time entry can't end before it starts")));
+            }
+            _ => {}
+        }
+        errors
+    }
+}
+
+impl Keyfinder for TimeEntry {
+    fn get_key_search(&self, turtl: &Turtl) -> TResult<Keychain> {
+        let mut keychain = Keychain::new();
+        let ty = String::from("space");
+        let profile_guard = lockr!(turtl.profile);
+        for space in &profile_guard.spaces {
+            if space.id() != Some(&self.space_id) { continue; }
+            match space.key() {
+                Some(k) => keychain.upsert_key(turtl, &self.space_id, k, &ty)?,
+                None => {}
+            }
+        }
+        Ok(keychain)
+    }
+
+    fn get_keyrefs(&self, turtl: &Turtl) -> TResult<Vec<KeyRef<Key>>> {
+        let mut refs: Vec<KeyRef<Key>> = Vec::new();
+        let profile_guard = lockr!(turtl.profile);
+        for space in &profile_guard.spaces {
+            if space.id() == Some(&self.space_id) && space.key().is_some() {
+                refs.push(KeyRef {
+                    id: self.space_id.clone(),
+                    ty: KeyType::Space,
+                    k: space.key().expect("turtl::TimeEntry.get_keyrefs() -- space key is None").clone(),
+                });
+            }
+        }
+        Ok(refs)
+    }
+}
+
+fn now() -> i64 {
+    time::get_time().sec as i64
+}
+
+/// The (local) YYYYMMDD day a timestamp falls on, to match `DateRange`
+fn day_of(ts: i64) -> i32 {
+    let tm = time::at(time::Timespec::new(ts, 0));
+    (tm.tm_year + 1900) * 10000 + (tm.tm_mon + 1) * 100 + tm.tm_mday
+}
+
+/// Add up time entries per board and tag. Entries are counted on the day they
+/// started, and running timers count up to `now`. `notes` maps note ids to
+/// their (board id, tags).
+fn tally(entries: &Vec<TimeEntry>, notes: &HashMap<String, (String, Vec<String>)>, low: i32, high: i32, now: i64) -> TimeReport {
+    let mut report = TimeReport::default();
+    for entry in entries {
+        let start = match entry.start {
+            Some(x) => x,
+            None => continue,
+        };
+        let day = day_of(start);
+        if day < low || day > high { continue; }
+        let secs = entry.end.unwrap_or(now) - start;
+        if secs <= 0 { continue; }
+        report.total += secs;
+        if let Some(&(ref board_id, ref tags)) = notes.get(&entry.note_id) {
+            *report.boards.entry(board_id.clone()).or_insert(0) += secs;
+            for tag in tags {
+                *report.tags.entry(tag.clone()).or_insert(0) += secs;
+            }
+        }
+    }
+    report
+}
+
+impl TimeEntry {
+    /// Load and decrypt all of the given user's time entries
+    fn load_for_user(turtl: &Turtl, user_id: &String) -> TResult<Vec<TimeEntry>> {
+        let mut entries: Vec<TimeEntry> = with_db!{ db, turtl.db,
+            db.find(TimeEntry::tablename(), "user_id", &vec![user_id.clone()])?
+        };
+        turtl.find_models_keys(&mut entries)?;
+        protected::map_deserialize(turtl, entries)
+    }
+
+    /// Load and decrypt the time entries for the given notes
+    pub fn load(turtl: &Turtl, note_ids: &Vec<String>) -> TResult<Vec<TimeEntry>> {
+        let mut entries: Vec<TimeEntry> = with_db!{ db, turtl.db,
+            db.find(TimeEntry::tablename(), "note_id", note_ids)?
+        };
+        turtl.find_models_keys(&mut entries)?;
+        protected::map_deserialize(turtl, entries)
+    }
+
+    /// Find the current user's running timer, if any
+    pub fn running(turtl: &Turtl) -> TResult<Option<TimeEntry>> {
+        let user_id = turtl.user_id()?;
+        Ok(TimeEntry::load_for_user(turtl, &user_id)?
+            .into_iter()
+            .find(|x| x.end.is_none()))
+    }
+
+    /// Start a timer on a note. Fails if the user already has one going.
+    pub fn start_timer(turtl: &Turtl, note_id: &String, description: Option<String>) -> TResult<TimeEntry> {
+        if let Some(running) = TimeEntry::running(turtl)? {
+            return TErr!(TError::BadValue(format!("a timer is already running on note {}", running.note_id)));
+        }
+        let note: Option<Note> = with_db!{ db, turtl.db, db.get(Note::tablename(), note_id)? };
+        let note = match note {
+            Some(x) => x,
+            None => return TErr!(TError::NotFound(format!("note {} wasn't found", note_id))),
+        };
+        Board::permission_check(turtl, &note.space_id, note.board_id.as_ref(), &Permission::EditNote)?;
+        let mut entry = TimeEntry::new();
+        entry.user_id = turtl.user_id()?;
+        entry.space_id = note.space_id.clone();
+        entry.note_id = note_id.clone();
+        entry.start = Some(now());
+        entry.description = description;
+        sync_model::save_model(SyncAction::Add, turtl, &mut entry, false)?;
+        Ok(entry)
+    }
+
+    /// Stop the user's running timer
+    pub fn stop_timer(turtl: &Turtl) -> TResult<TimeEntry> {
+        let mut entry = match TimeEntry::running(turtl)? {
+            Some(x) => x,
+            None => return TErr!(TError::NotFound(String::from("no timer is running"))),
+        };
+        entry.end = Some(now());
+        sync_model::save_model(SyncAction::Edit, turtl, &mut entry, false)?;
+        Ok(entry)
+    }
+
+    /// Total up the time spent per board/tag over a date range
+    pub fn report(turtl: &Turtl, range: &DateRange) -> TResult<TimeReport> {
+        let (low, high) = range.bounds()?;
+        let mut entries: Vec<TimeEntry> = with_db!{ db, turtl.db, db.all(TimeEntry::tablename())? };
+        turtl.find_models_keys(&mut entries)?;
+        let entries: Vec<TimeEntry> = protected::map_deserialize(turtl, entries)?;
+        let mut note_ids = entries.iter()
+            .map(|x| x.note_id.clone())
+            .collect::<Vec<_>>();
+        note_ids.sort();
+        note_ids.dedup();
+        let notes = turtl.load_notes(&note_ids)?
+            .into_iter()
+            .filter_map(|note| {
+                let id = note.id()?.clone();
+                let board_id = note.board_id.clone().unwrap_or(String::new());
+                Some((id, (board_id, note.tags.clone().unwrap_or(Vec::new()))))
+            })
+            .collect::<HashMap<_, _>>();
+        Ok(tally(&entries, &notes, low, high, now()))
+    }
+}
+
+/// Registers our time tracking commands
+pub fn register(reg: &mut Registry) {
+    reg.add("note:timer:start", |turtl, args| {
+        let note_id: String = args.get(2)?;
+        let description: Option<String> = args.get_opt(3);
+        let entry = TimeEntry::start_timer(turtl, &note_id, description)?;
+        Ok(entry.data()?)
+    });
+    reg.add("note:timer:stop", |turtl, _args| {
+        let entry = TimeEntry::stop_timer(turtl)?;
+        Ok(entry.data()?)
+    });
+    reg.add("note:timer:get", |turtl, _args| {
+        match TimeEntry::running(turtl)? {
+            Some(entry) => Ok(entry.data()?),
+            None => Ok(Value::Null),
+        }
+    });
+    reg.add("note:get-time-entries", |turtl, args| {
+        let note_id: String = args.get(2)?;
+        let entries = TimeEntry::load(turtl, &vec![note_id])?;
+        Ok(jedi::to_val(&entries)?)
+    });
+    reg.add("profile:time-report", |turtl, args| {
+        let range: DateRange = match args.get_opt(2) {
+            Some(Value::Null) | None => Default::default(),
+            Some(x) => match jedi::from_val(x) {
+                Ok(x) => x,
+                Err(e) => return TErr!(TError::BadValue(format!("error deserializing date range: {}", e))),
+            },
+        };
+        let report = TimeEntry::report(turtl, &range)?;
+        Ok(jedi::to_val(&report)?)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(note_id: &str, start: i64, end: Option<i64>) -> TimeEntry {
+        let mut entry = TimeEntry::new();
+        entry.note_id = String::from(note_id);
+        entry.start = Some(start);
+        entry.end = end;
+        entry
+    }
+
+    #[test]
+    fn tallies_time() {
+        let mut notes = HashMap::new();
+        notes.insert(String::from("1111"), (String::from("b1"), vec![String::from("work"), String::from("urgent")]));
+        notes.insert(String::from("2222"), (String::new(), vec![String::from("work")]));
+        // noon-ish, so timezones don't push anything into another day
+        let day = 1709294400;
+        let entries = vec![
+            entry("1111", day, Some(day + 600)),
+            entry("2222", day + 1000, Some(day + 1300)),
+            entry("1111", day + 2000, None),
+            // way outside the range
+            entry("2222", day - (86400 * 30), Some(day - (86400 * 30) + 50)),
+        ];
+        let report = tally(&entries, &notes, day_of(day), day_of(day), day + 2100);
+        assert_eq!(report.total, 1000);
+        assert_eq!(report.boards.get("b1"), Some(&700));
+        assert_eq!(report.boards.get(""), Some(&300));
+        assert_eq!(report.tags.get("work"), Some(&1000));
+        assert_eq!(report.tags.get("urgent"), Some(&700));
+
+        assert_eq!(entry("1111", 10, Some(5)).validate().len(), 1);
+        assert_eq!(entry("1111", 10, None).validate().len(), 0);
+    }
+}
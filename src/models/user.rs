@@ -408,19 +408,19 @@ impl User {
             Ok(id)
         }
 
-        let personal_space_id = save_space(turtl, &user_id, t!("Personal"), "#408080")?;
-        save_space(turtl, &user_id, t!("Work"), "#439645")?;
-        save_space(turtl, &user_id, t!("Home"), "#800000")?;
-        save_board(turtl, &user_id, &personal_space_id, t!("Bookmarks"))?;
-        save_board(turtl, &user_id, &personal_space_id, t!("Photos"))?;
-        save_board(turtl, &user_id, &personal_space_id, t!("Passwords"))?;
+        let personal_space_id = save_space(turtl, &user_id, &t!("Personal"), "#408080")?;
+        save_space(turtl, &user_id, &t!("Work"), "#439645")?;
+        save_space(turtl, &user_id, &t!("Home"), "#800000")?;
+        save_board(turtl, &user_id, &personal_space_id, &t!("Bookmarks"))?;
+        save_board(turtl, &user_id, &personal_space_id, &t!("Photos"))?;
+        save_board(turtl, &user_id, &personal_space_id, &t!("Passwords"))?;
 
         // the user's default space id. might change if we have import data
         let mut default_space_id = personal_space_id.clone();
 
         if let Some(migration) = migrate_data {
             let MigrateResult { boards, notes } = migration;
-            let migrate_space_id = save_space(turtl, &user_id, t!("Imported"), "#b7479b")?;
+            let migrate_space_id = save_space(turtl, &user_id, &t!("Imported"), "#b7479b")?;
             // if we're importing data, set the space holding the migration data
             // as the default
             default_space_id = migrate_space_id.clone();
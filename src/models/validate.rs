@@ -24,8 +24,9 @@ pub trait Validate {
 }
 
 /// Create an error entry
-pub fn entry<T>(field: T, message: T) -> (String, String)
-    where T: Into<String>
+pub fn entry<T, M>(field: T, message: M) -> (String, String)
+    where T: Into<String>,
+          M: Into<String>
 {
     (field.into(), message.into())
 }
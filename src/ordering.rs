@@ -0,0 +1,158 @@
+//! Sort contracts for list responses. Storage hands things back in whatever
+//! order it likes (and that order isn't the same from one device to the next),
+//! so lists get sorted here right before they go out the door.
+//!
+//! Each command has a default order, which can be changed in the config
+//! (`ordering.<command>`) or per call with `{"sort": "<order>"}`. Ties are
+//! always broken by id, so the same data comes back in the same order
+//! everywhere.
+
+use ::std::cmp::Ordering;
+use ::std::collections::HashMap;
+use ::config;
+use ::models::model;
+
+/// How we sort a list
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Order {
+    /// Oldest first
+    Created,
+    /// Most recently modified first
+    Mod,
+    /// Alphabetical (ignoring case). Untitled items go last.
+    Title,
+    /// The order the caller asked for things in
+    User,
+    /// Most used first. Only means anything for tags (other lists sort oldest
+    /// first).
+    Count,
+}
+
+/// Anything we can sort in a list response
+pub trait Sortable {
+    /// The item's id (our tie-breaker)
+    fn sort_id(&self) -> Option<&String>;
+
+    /// The item's title, if it has one
+    fn sort_title(&self) -> Option<&String>;
+
+    /// When the item was last changed (unix seconds). Defaults to when it was
+    /// created.
+    fn sort_mod(&self) -> Option<i64> {
+        created(self).map(|x| x / 1000)
+    }
+}
+
+impl<'a, T: Sortable> Sortable for &'a T {
+    fn sort_id(&self) -> Option<&String> { T::sort_id(*self) }
+    fn sort_title(&self) -> Option<&String> { T::sort_title(*self) }
+    fn sort_mod(&self) -> Option<i64> { T::sort_mod(*self) }
+}
+
+/// Grab an item's create time (in ms) from its id
+fn created<T: Sortable + ?Sized>(item: &T) -> Option<i64> {
+    item.sort_id().and_then(|id| model::id_timestamp(id).ok())
+}
+
+/// Figure out which order a command's list should come back in: what the
+/// caller asked for, otherwise what's in the config, otherwise the default.
+pub fn order_for(command: &str, requested: Option<Order>, default: Order) -> Order {
+    match requested {
+        Some(x) => x,
+        None => config::get(&["ordering", command]).unwrap_or(default),
+    }
+}
+
+/// Sort a list of items. `user_order` is a list of ids, and is only used for
+/// `Order::User` (anything not in it goes at the end, oldest first).
+pub fn sort<T: Sortable>(items: &mut Vec<T>, order: Order, user_order: &Vec<String>) {
+    let positions = user_order.iter()
+        .enumerate()
+        .map(|(i, id)| (id.clone(), i))
+        .collect::<HashMap<_, _>>();
+    let position = |item: &T| item.sort_id().and_then(|id| positions.get(id)).map(|x| *x);
+    let title = |item: &T| item.sort_title().map(|x| x.to_lowercase());
+    items.sort_by(|a, b| {
+        let ord = match order {
+            Order::Created | Order::Count => Ordering::Equal,
+            Order::Mod => b.sort_mod().cmp(&a.sort_mod()),
+            Order::Title => match (title(a), title(b)) {
+                (Some(x), Some(y)) => x.cmp(&y),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            },
+            Order::User => match (position(a), position(b)) {
+                (Some(x), Some(y)) => x.cmp(&y),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            },
+        };
+        ord.then_with(|| created(a).cmp(&created(b)))
+            .then_with(|| a.sort_id().cmp(&b.sort_id()))
+    });
+}
+
+/// Sort a tag list (tag, count). `Order::Title` sorts by name, anything else
+/// puts the most-used tags first (tags don't have ids or mod times to go by).
+pub fn sort_tags(tags: &mut Vec<(String, i32)>, order: Order) {
+    match order {
+        Order::Title => tags.sort_by(|a, b| a.0.to_lowercase().cmp(&b.0.to_lowercase()).then_with(|| a.0.cmp(&b.0))),
+        _ => tags.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Thing {
+        id: Option<String>,
+        title: Option<String>,
+        mod_: Option<i64>,
+    }
+
+    impl Sortable for Thing {
+        fn sort_id(&self) -> Option<&String> { self.id.as_ref() }
+        fn sort_title(&self) -> Option<&String> { self.title.as_ref() }
+        fn sort_mod(&self) -> Option<i64> { self.mod_ }
+    }
+
+    fn thing(ts: u64, title: Option<&str>, mod_: i64) -> Thing {
+        let id = format!("{:012x}{}0000", ts, "c0f4c762af6c42e4079cced2dfe16b4d010b190ad75ade9d83ff8cee0e96586d");
+        Thing { id: Some(id), title: title.map(|x| String::from(x)), mod_: Some(mod_) }
+    }
+
+    fn titles<T: Sortable>(items: &Vec<T>) -> Vec<String> {
+        items.iter().map(|x| x.sort_title().cloned().unwrap_or(String::from("-"))).collect()
+    }
+
+    #[test]
+    fn sorts_lists() {
+        let things = vec![
+            thing(3000, Some("banana"), 10),
+            thing(1000, Some("Cherry"), 30),
+            thing(2000, None, 20),
+            thing(4000, Some("apple"), 20),
+        ];
+        let mut list = things.iter().collect::<Vec<_>>();
+        sort(&mut list, Order::Created, &vec![]);
+        assert_eq!(titles(&list), vec!["Cherry", "-", "banana", "apple"]);
+        sort(&mut list, Order::Title, &vec![]);
+        assert_eq!(titles(&list), vec!["apple", "banana", "Cherry", "-"]);
+        // ties on mod fall back to creation order
+        sort(&mut list, Order::Mod, &vec![]);
+        assert_eq!(titles(&list), vec!["Cherry", "-", "apple", "banana"]);
+        let user_order = vec![things[3].id.clone().unwrap(), things[0].id.clone().unwrap()];
+        sort(&mut list, Order::User, &user_order);
+        assert_eq!(titles(&list), vec!["apple", "banana", "Cherry", "-"]);
+
+        let mut tags = vec![(String::from("b"), 1), (String::from("C"), 2), (String::from("a"), 1)];
+        sort_tags(&mut tags, Order::Title);
+        assert_eq!(tags.iter().map(|x| x.0.as_str()).collect::<Vec<_>>(), vec!["a", "b", "C"]);
+        sort_tags(&mut tags, Order::Count);
+        assert_eq!(tags.iter().map(|x| x.0.as_str()).collect::<Vec<_>>(), vec!["C", "a", "b"]);
+    }
+}
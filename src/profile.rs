@@ -17,14 +17,19 @@ use ::models::board::Board;
 use ::models::note::Note;
 use ::models::file::FileData;
 use ::models::invite::Invite;
+use ::models::contact::Contact;
 use ::models::protected::{self, Protected};
 use ::models::sync_record::{SyncRecord, SyncAction, SyncType};
 use ::models::storable::Storable;
 use ::sync::sync_model;
+use ::storage;
+use ::search::Query;
 use ::lib_permissions::Permission;
 use ::config;
 use ::crypto;
 use ::messaging;
+use ::jobs;
+use ::archive;
 
 /// A structure holding a collection of objects that represent's a user's
 /// Turtl data profile.
@@ -33,6 +38,102 @@ pub struct Profile {
     pub spaces: Vec<Space>,
     pub boards: Vec<Board>,
     pub invites: Vec<Invite>,
+    pub contacts: Vec<Contact>,
+    /// The last fields we told the UI about for each space/board/note, so we
+    /// can send `profile:changed` diffs
+    snapshots: HashMap<String, Value>,
+}
+
+/// What sort of change a `profile:changed` event describes
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    /// space_id/board_id changed
+    Moved,
+    /// only the title changed
+    Renamed,
+    Updated,
+}
+
+/// A minimal diff of one model, sent out as `profile:changed`
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ProfileChange {
+    #[serde(rename = "type")]
+    pub ty: String,
+    pub id: String,
+    pub change: ChangeKind,
+    /// new values of the fields that changed
+    pub fields: HashMap<String, Value>,
+    /// old values of the fields that changed
+    pub previous: HashMap<String, Value>,
+}
+
+/// The fields we track for each type. Anything else changing doesn't get a
+/// diff (although a note's `mod` changing means its contents changed).
+fn tracked_fields(ty: &SyncType) -> Option<&'static [&'static str]> {
+    match *ty {
+        SyncType::Space => Some(&["title", "color"]),
+        SyncType::Board => Some(&["title", "space_id"]),
+        SyncType::Note => Some(&["title", "space_id", "board_id", "tags", "color", "mod"]),
+        _ => None,
+    }
+}
+
+/// Pull our tracked fields out of a model's data
+fn snapshot(fields: &[&str], data: &Value) -> Value {
+    let mut snap = json!({});
+    for field in fields {
+        if let Some(val) = jedi::get_opt::<Value>(&[*field], data) {
+            if val != Value::Null {
+                jedi::set(&[*field], &mut snap, &val).unwrap_or(());
+            }
+        }
+    }
+    snap
+}
+
+/// Compare two snapshots of the same model. Returns None if nothing we track
+/// changed.
+fn diff_snapshots(ty: &SyncType, id: &String, before: Option<&Value>, after: Option<&Value>) -> Option<ProfileChange> {
+    let fields = match tracked_fields(ty) {
+        Some(x) => x,
+        None => return None,
+    };
+    let mut change = ProfileChange {
+        ty: match ::util::enum_to_string(ty) { Ok(x) => x, Err(_) => return None },
+        id: id.clone(),
+        change: ChangeKind::Updated,
+        fields: HashMap::new(),
+        previous: HashMap::new(),
+    };
+    let empty = json!({});
+    change.change = match (before, after) {
+        (None, None) => return None,
+        (None, Some(_)) => ChangeKind::Added,
+        (Some(_), None) => ChangeKind::Removed,
+        (Some(_), Some(_)) => ChangeKind::Updated,
+    };
+    for field in fields {
+        let old = jedi::get_opt::<Value>(&[*field], before.unwrap_or(&empty)).unwrap_or(Value::Null);
+        let new = jedi::get_opt::<Value>(&[*field], after.unwrap_or(&empty)).unwrap_or(Value::Null);
+        if old == new { continue; }
+        if after.is_some() { change.fields.insert(String::from(*field), new); }
+        if before.is_some() { change.previous.insert(String::from(*field), old); }
+    }
+    if change.change == ChangeKind::Updated {
+        if change.fields.len() == 0 { return None; }
+        let moved = change.fields.contains_key("space_id") || change.fields.contains_key("board_id");
+        change.change = if moved {
+            ChangeKind::Moved
+        } else if change.fields.len() == 1 && change.fields.contains_key("title") {
+            ChangeKind::Renamed
+        } else {
+            ChangeKind::Updated
+        };
+    }
+    Some(change)
 }
 
 /// A struct for holding a profile export
@@ -51,6 +152,36 @@ pub struct ImportResult {
     actions: Vec<SyncRecord>,
 }
 
+/// Holds the ids of the sample content we created for a new profile
+#[derive(Serialize, Default)]
+pub struct SeedResult {
+    space_id: String,
+    board_id: String,
+    notes: Vec<String>,
+}
+
+/// Counts for a single space or board, built from public fields only
+#[derive(Serialize, Default)]
+pub struct ContainerCounts {
+    id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    space_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    boards: Option<u64>,
+    notes: u64,
+    /// The latest `mod` of any note in this container
+    last_mod: Option<i64>,
+}
+
+/// A lightweight view of the profile: how many things live where, without any
+/// bodies (so nothing has to be decrypted)
+#[derive(Serialize, Default)]
+pub struct ProfileCounts {
+    spaces: Vec<ContainerCounts>,
+    boards: Vec<ContainerCounts>,
+    invites: u64,
+}
+
 /// This lets us know how an import should be processed.
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub enum ImportMode {
@@ -72,6 +203,8 @@ impl Profile {
             spaces: Vec::new(),
             boards: Vec::new(),
             invites: Vec::new(),
+            contacts: Vec::new(),
+            snapshots: HashMap::new(),
         }
     }
 
@@ -81,6 +214,129 @@ impl Profile {
         self.spaces = Vec::new();
         self.boards = Vec::new();
         self.invites = Vec::new();
+        self.contacts = Vec::new();
+        self.snapshots = HashMap::new();
+    }
+
+    /// Remember what our notes look like (called once they're decrypted at
+    /// login) so the first change to each one gets a real diff
+    pub fn seed_snapshots(&mut self, notes: &Vec<Note>) {
+        let fields = tracked_fields(&SyncType::Note).unwrap_or(&[]);
+        for note in notes {
+            let id = match note.id() {
+                Some(x) => x.clone(),
+                None => continue,
+            };
+            match note.data() {
+                Ok(data) => { self.snapshots.insert(id, snapshot(fields, &data)); }
+                Err(e) => warn!("Profile.seed_snapshots() -- problem grabbing note data: {}", e),
+            }
+        }
+    }
+
+    /// Grab what we last knew about a model, before it changes. Spaces and
+    /// boards we haven't seen change yet come straight from the profile.
+    pub fn snapshot_before(turtl: &Turtl, ty: &SyncType, id: &String) -> Option<Value> {
+        let fields = match tracked_fields(ty) {
+            Some(x) => x,
+            None => return None,
+        };
+        let profile_guard = lockr!(turtl.profile);
+        if let Some(snap) = profile_guard.snapshots.get(id) {
+            return Some(snap.clone());
+        }
+        let data = match *ty {
+            SyncType::Space => profile_guard.spaces.iter().find(|x| x.id() == Some(id)).map(|x| x.data()),
+            SyncType::Board => profile_guard.boards.iter().find(|x| x.id() == Some(id)).map(|x| x.data()),
+            _ => None,
+        };
+        match data {
+            Some(Ok(x)) => Some(snapshot(fields, &x)),
+            _ => None,
+        }
+    }
+
+    /// A model changed in memory: diff it against what it looked like before
+    /// and send a `profile:changed` event if anything we track is different.
+    pub fn record_change(turtl: &Turtl, sync_item: &SyncRecord, before: Option<Value>) -> TResult<()> {
+        let fields = match tracked_fields(&sync_item.ty) {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+        let after = match sync_item.action {
+            SyncAction::Delete => None,
+            _ => sync_item.data.as_ref().map(|x| snapshot(fields, x)),
+        };
+        let change = diff_snapshots(&sync_item.ty, &sync_item.item_id, before.as_ref(), after.as_ref());
+        {
+            let mut profile_guard = lockw!(turtl.profile);
+            match after {
+                Some(x) => { profile_guard.snapshots.insert(sync_item.item_id.clone(), x); }
+                None => { profile_guard.snapshots.remove(&sync_item.item_id); }
+            }
+        }
+        if let Some(change) = change {
+            if turtl.sync_ready() {
+                messaging::ui_event("profile:changed", &change)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Tally up notes/boards per space and notes per board straight from the
+    /// raw (still-encrypted) db records.
+    pub fn counts(turtl: &Turtl) -> TResult<ProfileCounts> {
+        let (spaces, boards, notes, invites) = with_db!{ db, turtl.db,
+            (
+                db.all_values(Space::tablename())?,
+                db.all_values(Board::tablename())?,
+                db.all_values(Note::tablename())?,
+                db.all_values(Invite::tablename())?,
+            )
+        };
+        let mut counts = ProfileCounts::default();
+        counts.invites = invites.len() as u64;
+        let mut space_idx: HashMap<String, usize> = HashMap::new();
+        for space in &spaces {
+            let id: String = match jedi::get_opt(&["id"], space) {
+                Some(x) => x,
+                None => continue,
+            };
+            space_idx.insert(id.clone(), counts.spaces.len());
+            counts.spaces.push(ContainerCounts { id: id, boards: Some(0), ..Default::default() });
+        }
+        let mut board_idx: HashMap<String, usize> = HashMap::new();
+        for board in &boards {
+            let id: String = match jedi::get_opt(&["id"], board) {
+                Some(x) => x,
+                None => continue,
+            };
+            let space_id: Option<String> = jedi::get_opt(&["space_id"], board);
+            if let Some(idx) = space_id.as_ref().and_then(|x| space_idx.get(x)) {
+                let space = &mut counts.spaces[*idx];
+                space.boards = space.boards.map(|x| x + 1);
+            }
+            board_idx.insert(id.clone(), counts.boards.len());
+            counts.boards.push(ContainerCounts { id: id, space_id: space_id, ..Default::default() });
+        }
+        fn bump(container: &mut ContainerCounts, note_mod: Option<i64>) {
+            container.notes += 1;
+            if note_mod > container.last_mod {
+                container.last_mod = note_mod;
+            }
+        }
+        for note in &notes {
+            let note_mod: Option<i64> = jedi::get_opt(&["mod"], note);
+            let space_id: Option<String> = jedi::get_opt(&["space_id"], note);
+            let board_id: Option<String> = jedi::get_opt(&["board_id"], note);
+            if let Some(idx) = space_id.as_ref().and_then(|x| space_idx.get(x)) {
+                bump(&mut counts.spaces[*idx], note_mod);
+            }
+            if let Some(idx) = board_id.as_ref().and_then(|x| board_idx.get(x)) {
+                bump(&mut counts.boards[*idx], note_mod);
+            }
+        }
+        Ok(counts)
     }
 
     /// Find a model by id in a collection of items
@@ -92,11 +348,28 @@ impl Profile {
             .next()
     }
 
-    /// Export the current Turtl profile
-    pub fn export(turtl: &Turtl) -> TResult<Export> {
-        info!("Profile::export() -- running export");
+    /// Export the current Turtl profile. If given a search query, only the
+    /// notes matching that query (and the space/boards they live in) are
+    /// exported.
+    pub fn export(turtl: &Turtl, query: Option<Query>) -> TResult<Export> {
+        info!("Profile::export() -- running export (filtered: {})", query.is_some());
         let mut export = Export::default();
         export.schema_version = 2;
+        let filter = match query {
+            Some(mut query) => {
+                // we want every match, not just one page's worth
+                query.page = 1;
+                query.per_page = 99999;
+                let search_guard = lock!(turtl.search);
+                let search = match search_guard.as_ref() {
+                    Some(x) => x,
+                    None => return TErr!(TError::MissingField(String::from("turtl.search"))),
+                };
+                let (note_ids, _) = search.find(&query)?;
+                Some((query.space_id, note_ids))
+            }
+            None => None,
+        };
         let profile_guard = lockr!(turtl.profile);
         let mut db_guard = lock!(turtl.db);
         let db = match db_guard.as_mut() {
@@ -122,11 +395,26 @@ impl Profile {
             })
             .collect::<Vec<_>>();
         export.boards = cloner(&profile_guard.boards)?;
-        let mut notes_encrypted = db.all(Note::tablename())?;
+        let mut notes_encrypted: Vec<Note> = match filter.as_ref() {
+            Some(&(_, ref note_ids)) => db.by_id(Note::tablename(), note_ids)?,
+            None => db.all(Note::tablename())?,
+        };
         turtl.find_models_keys(&mut notes_encrypted)?;
         export.notes = protected::map_deserialize(turtl, notes_encrypted)?;
+        // archived notes are only stubs in the notes table
+        archive::fill_stubs(turtl, db, &mut export.notes)?;
+        if let Some((space_id, _)) = filter {
+            let board_ids = export.notes.iter()
+                .filter_map(|n| n.board_id.clone())
+                .collect::<Vec<_>>();
+            export.spaces.retain(|s| s.id() == Some(&space_id));
+            export.boards.retain(|b| b.id().map(|id| board_ids.contains(id)).unwrap_or(false));
+        }
         export.files = Vec::with_capacity(export.notes.len());
-        for note in &export.notes {
+        let total = export.notes.len() as u64;
+        for (i, note) in export.notes.iter().enumerate() {
+            jobs::progress(i as u64 + 1, Some(total));
+            jobs::check_cancelled()?;
             match FileData::load_file(turtl, note) {
                 Ok(binary) => {
                     let mut filedata = FileData::default();
@@ -155,6 +443,9 @@ impl Profile {
             crypto::to_hex(&crypto::sha256(key.as_bytes())?)?
         };
         info!("Profile::import() -- running import (mode: {}, cid: {})", jedi::stringify(&mode)?, client_id);
+        // make sure we have room for everything we're about to write. the
+        // serialized export is a decent stand-in for how much space we need.
+        storage::check_free_space(jedi::stringify(&export)?.len() as u64)?;
         // the import result details what changed
         let mut result = ImportResult::default();
 
@@ -211,7 +502,7 @@ impl Profile {
         // ok, now that we got rid of that dead weight, let's start our import.
         let Export { spaces, boards, notes, files, .. } = export;
 
-        struct Counter { count: u32 }
+        struct Counter { count: u32, total: u64 }
         
         // define a function that runs our sync dispatcher for the incoming
         // import models. note that this runs all of our permission checks for
@@ -258,6 +549,8 @@ impl Profile {
                 // tally ho, good chap
                 counter.count += 1;
                 messaging::ui_event("profile:import:tally", &counter.count)?;
+                jobs::progress(counter.count as u64, Some(counter.total));
+                jobs::check_cancelled()?;
             }
             Ok(())
         }
@@ -286,7 +579,8 @@ impl Profile {
             Ok(())
         }
 
-        let mut counter = Counter { count: 0 };
+        let total = (spaces.len() + boards.len() + notes.len()) as u64;
+        let mut counter = Counter { count: 0, total: total };
         saver(turtl, &mode, &client_id, spaces, SyncType::Space, |x, _map, _old_id| { x.data() }, &mut id_change_map, &mut result, &mut counter)?;
         saver(turtl, &mode, &client_id, boards, SyncType::Board, |x, id_change_map, _old_id| {
             let mut data = x.data()?;
@@ -307,5 +601,83 @@ impl Profile {
         }, &mut id_change_map, &mut result, &mut counter)?;
         Ok(result)
     }
+    /// Create some sample content (a welcome space, a board, and a few notes)
+    /// for a new user so they have something to poke at. This all runs through
+    /// the normal model/sync path, so it's exactly the same as if the user had
+    /// created it themselves.
+    pub fn seed_sample_content(turtl: &Turtl) -> TResult<SeedResult> {
+        let user_id = turtl.user_id()?;
+        let mut result = SeedResult::default();
+
+        let mut space: Space = Default::default();
+        space.generate_key()?;
+        space.user_id = user_id.clone();
+        space.title = Some(String::from(t!("Welcome")));
+        space.color = Some(String::from("#408080"));
+        let val = sync_model::save_model(SyncAction::Add, turtl, &mut space, false)?;
+        result.space_id = jedi::get(&["id"], &val)?;
+
+        let mut board: Board = Default::default();
+        board.generate_key()?;
+        board.user_id = user_id.clone();
+        board.space_id = result.space_id.clone();
+        board.title = Some(String::from(t!("Getting started")));
+        let val = sync_model::save_model(SyncAction::Add, turtl, &mut board, false)?;
+        result.board_id = jedi::get(&["id"], &val)?;
+
+        let samples = vec![
+            (t!("Welcome to Turtl"), t!("Turtl lets you take notes, bookmark websites, and store documents for sensitive projects. Everything is encrypted before it leaves your device.")),
+            (t!("Spaces and boards"), t!("Spaces keep different areas of your life separate (work, home, etc). Boards let you organize notes inside of a space.")),
+            (t!("Sharing"), t!("You can invite other people to a space. They'll be able to see (and, depending on their permissions, edit) everything in it.")),
+        ];
+        for (title, text) in samples {
+            let mut note: Note = Default::default();
+            note.generate_key()?;
+            note.user_id = user_id.clone();
+            note.space_id = result.space_id.clone();
+            note.board_id = Some(result.board_id.clone());
+            note.type_ = Some(String::from("text"));
+            note.title = Some(String::from(title));
+            note.text = Some(String::from(text));
+            let val = sync_model::save_model(SyncAction::Add, turtl, &mut note, false)?;
+            result.notes.push(jedi::get(&["id"], &val)?);
+        }
+        info!("Profile::seed_sample_content() -- created space {} with {} notes", result.space_id, result.notes.len());
+        Ok(result)
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diffs_profile_changes() {
+        let id = String::from("1234");
+        let before = json!({"title": "Recipes", "space_id": "s1"});
+        let renamed = json!({"title": "Food", "space_id": "s1"});
+        let moved = json!({"title": "Food", "space_id": "s2"});
+
+        let change = diff_snapshots(&SyncType::Board, &id, Some(&before), Some(&renamed)).unwrap();
+        assert_eq!(change.change, ChangeKind::Renamed);
+        assert_eq!(change.fields.get("title"), Some(&json!("Food")));
+        assert_eq!(change.previous.get("title"), Some(&json!("Recipes")));
+
+        let change = diff_snapshots(&SyncType::Board, &id, Some(&renamed), Some(&moved)).unwrap();
+        assert_eq!(change.change, ChangeKind::Moved);
+        assert_eq!(change.fields.len(), 1);
+
+        let change = diff_snapshots(&SyncType::Board, &id, None, Some(&before)).unwrap();
+        assert_eq!(change.change, ChangeKind::Added);
+        assert_eq!(change.previous.len(), 0);
+        let change = diff_snapshots(&SyncType::Board, &id, Some(&before), None).unwrap();
+        assert_eq!(change.change, ChangeKind::Removed);
+        assert_eq!(change.fields.len(), 0);
+
+        assert_eq!(diff_snapshots(&SyncType::Board, &id, Some(&before), Some(&before)), None);
+        assert_eq!(diff_snapshots(&SyncType::Invite, &id, None, Some(&before)), None);
+
+        let note = json!({"title": "hi", "text": "ignored", "mod": 5});
+        assert_eq!(snapshot(tracked_fields(&SyncType::Note).unwrap(), &note), json!({"title": "hi", "mod": 5}));
+    }
+}
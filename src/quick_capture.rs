@@ -0,0 +1,151 @@
+//! Quick capture is for tray icons and global hotkeys: the UI hands us a blob
+//! of text (and maybe a URL) and we do the rest. We pick the board, pull tags
+//! out of any #hashtags (and resolve @mentions), save the note, and queue it for sync, all in one call.
+
+use ::jedi::{self, Value};
+use ::error::{TResult, TError};
+use ::turtl::Turtl;
+use ::models::model::Model;
+use ::models::protected::Protected;
+use ::models::board::Board;
+use ::models::note::Note;
+use ::models::email_gateway;
+use ::models::sync_record::SyncAction;
+use ::sync::sync_model;
+use ::lib_permissions::Permission;
+use ::dispatch::registry::Registry;
+
+/// The user setting we keep our capture board in
+const SETTINGS_KEY: &'static str = "quick_capture";
+
+/// How long a title pulled from the first line of text can get
+const MAX_TITLE_CHARS: usize = 80;
+
+/// Where captured notes go. Stored in the user's settings so every device
+/// captures into the same place.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct CaptureSettings {
+    pub board_id: Option<String>,
+}
+
+pub fn get_settings(turtl: &Turtl) -> TResult<CaptureSettings> {
+    let user_guard = lockr!(turtl.user);
+    let val = user_guard.settings.as_ref().and_then(|s| s.get(SETTINGS_KEY));
+    match val {
+        Some(&Value::Null) | None => Ok(CaptureSettings::default()),
+        Some(x) => Ok(jedi::from_val(x.clone())?),
+    }
+}
+
+/// Set (or clear) the board captured notes go into
+pub fn set_board(turtl: &Turtl, board_id: Option<String>) -> TResult<CaptureSettings> {
+    if let Some(ref board_id) = board_id {
+        let space_id = match Board::get_space_id(turtl, board_id) {
+            Some(x) => x,
+            None => return TErr!(TError::MissingData(format!("board {} not found", board_id))),
+        };
+        Board::permission_check(turtl, &space_id, Some(board_id), &Permission::AddNote)?;
+    }
+    let settings = CaptureSettings { board_id: board_id };
+    let mut user = {
+        let user_guard = lockr!(turtl.user);
+        user_guard.clone()?
+    };
+    user.set_setting(turtl, SETTINGS_KEY, &settings)?;
+    Ok(settings)
+}
+
+/// Figure out which board (and space) a captured note goes into. We use the
+/// capture board if one is set, then the email gateway's inbox, then the first
+/// board we're allowed to add notes to.
+fn inbox(turtl: &Turtl) -> TResult<(String, String)> {
+    let mut candidates = Vec::new();
+    if let Some(board_id) = get_settings(turtl)?.board_id {
+        candidates.push(board_id);
+    }
+    if let Some(gateway) = email_gateway::get_settings(turtl)? {
+        candidates.push(gateway.board_id);
+    }
+    {
+        let profile_guard = lockr!(turtl.profile);
+        let mut boards = profile_guard.boards.iter()
+            .filter_map(|b| b.id().map(|id| (b.title.clone().unwrap_or(String::new()), id.clone())))
+            .collect::<Vec<_>>();
+        boards.sort();
+        candidates.extend(boards.into_iter().map(|(_, id)| id));
+    }
+    for board_id in candidates {
+        let space_id = match Board::get_space_id(turtl, &board_id) {
+            Some(x) => x,
+            None => continue,
+        };
+        if Board::permission_check(turtl, &space_id, Some(&board_id), &Permission::AddNote).is_ok() {
+            return Ok((board_id, space_id));
+        }
+    }
+    TErr!(TError::MissingData(String::from("there's no board to capture notes into")))
+}
+
+/// Use the first line of the text as a title
+fn title_from(text: &str) -> Option<String> {
+    let line = text.lines().map(|x| x.trim()).find(|x| x.len() > 0)?;
+    let mut title = line.chars().take(MAX_TITLE_CHARS).collect::<String>();
+    if line.chars().count() > MAX_TITLE_CHARS { title.push('…'); }
+    Some(title)
+}
+
+/// Capture a note, returning it
+pub fn capture(turtl: &Turtl, text: String, url: Option<String>) -> TResult<Note> {
+    let text = String::from(text.trim());
+    let url = url.map(|x| String::from(x.trim())).and_then(|x| if x == "" { None } else { Some(x) });
+    if text == "" && url.is_none() {
+        return TErr!(TError::MissingData(String::from("nothing to capture")));
+    }
+    let (board_id, space_id) = inbox(turtl)?;
+
+    let mut note = Note::new();
+    note.user_id = turtl.user_id()?;
+    note.space_id = space_id;
+    note.board_id = Some(board_id);
+    note.type_ = Some(String::from(if text == "" { "link" } else { "text" }));
+    note.title = title_from(&text);
+    note.url = url;
+    note.text = if text == "" { None } else { Some(text) };
+    note.parse_fields(turtl)?;
+    note.sanitize();
+    note.touch();
+    sync_model::save_model(SyncAction::Add, turtl, &mut note, false)?;
+    Ok(note)
+}
+
+/// Registers our quick capture commands
+pub fn register(reg: &mut Registry) {
+    reg.add("profile:quick-capture", |turtl, args| {
+        let text: String = args.get_opt(2).unwrap_or(String::new());
+        let url: Option<String> = args.get_opt(3);
+        let note = capture(turtl, text, url)?;
+        Ok(note.data()?)
+    });
+    reg.add("profile:quick-capture:set-board", |turtl, args| {
+        let board_id: Option<String> = args.get_opt(2);
+        let settings = set_board(turtl, board_id)?;
+        Ok(jedi::to_val(&settings)?)
+    });
+    reg.add("profile:quick-capture:get", |turtl, _args| {
+        let settings = get_settings(turtl)?;
+        Ok(jedi::to_val(&settings)?)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn titles_from_text() {
+        assert_eq!(title_from("\n  buy milk  \nand eggs"), Some(String::from("buy milk")));
+        assert_eq!(title_from("   \n "), None);
+        let long = "x".repeat(100);
+        assert_eq!(title_from(&long).unwrap().chars().count(), MAX_TITLE_CHARS + 1);
+    }
+}
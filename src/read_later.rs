@@ -0,0 +1,69 @@
+//! Read-later support for bookmarks. We can pull the readable text out of the
+//! page a note points to (stored encrypted on the note, like everything else
+//! the user writes) and track whether the note has been read, so the UI can
+//! show an unread queue with `profile:find-notes` and `{"read": false}`.
+
+use ::jedi::Value;
+use ::error::{TResult, TError};
+use ::config;
+use ::clippo;
+use ::turtl::Turtl;
+use ::models::board::Board;
+use ::models::note::Note;
+use ::models::sync_record::SyncAction;
+use ::sync::sync_model;
+use ::lib_permissions::Permission;
+use ::dispatch::registry::Registry;
+
+/// Load a note we're about to edit
+fn load_for_edit(turtl: &Turtl, note_id: &String) -> TResult<Note> {
+    let note = match turtl.load_notes(&vec![note_id.clone()])?.pop() {
+        Some(x) => x,
+        None => return TErr!(TError::NotFound(format!("note {} wasn't found", note_id))),
+    };
+    Board::permission_check(turtl, &note.space_id, note.board_id.as_ref(), &Permission::EditNote)?;
+    Ok(note)
+}
+
+/// Download the page a note links to and save its article text on the note.
+/// The note's title is filled in from the page if it doesn't have one.
+pub fn extract(turtl: &Turtl, note_id: &String) -> TResult<Value> {
+    let mut note = load_for_edit(turtl, note_id)?;
+    let url = match note.url.as_ref().map(|x| String::from(x.trim())) {
+        Some(ref x) if x != "" => x.clone(),
+        _ => return TErr!(TError::MissingField(format!("note {} doesn't have a url", note_id))),
+    };
+    let proxy_cfg: Option<String> = config::get(&["api", "proxy"]).unwrap_or(None);
+    let article = clippo::extract_article(&url, proxy_cfg)?;
+    if article.text == "" {
+        return TErr!(TError::NotFound(format!("couldn't find an article at {}", url)));
+    }
+    if note.title.as_ref().map(|x| x.trim() == "").unwrap_or(true) {
+        note.title = article.title;
+    }
+    note.article = Some(article.text);
+    note.sanitize();
+    note.touch();
+    sync_model::save_model(SyncAction::Edit, turtl, &mut note, false)
+}
+
+/// Mark a note as read (or unread)
+pub fn set_read(turtl: &Turtl, note_id: &String, read: bool) -> TResult<Value> {
+    let mut note = load_for_edit(turtl, note_id)?;
+    note.read = Some(read);
+    note.touch();
+    sync_model::save_model(SyncAction::Edit, turtl, &mut note, false)
+}
+
+/// Registers our read-later commands
+pub fn register(reg: &mut Registry) {
+    reg.add("note:extract-article", |turtl, args| {
+        let note_id: String = args.get(2)?;
+        extract(turtl, &note_id)
+    });
+    reg.add("note:set-read", |turtl, args| {
+        let note_id: String = args.get(2)?;
+        let read: bool = args.get_opt(3).unwrap_or(true);
+        set_read(turtl, &note_id, read)
+    });
+}
@@ -0,0 +1,443 @@
+//! Recurring notes: rules that make a copy of a template note on a schedule
+//! (weekly meeting notes, a monthly budget check, etc). Rules live in the
+//! user's settings so every device sees the same ones, along with when each
+//! last ran so devices don't both make the same note.
+//!
+//! Like folder sync, a watcher thread pokes the dispatch thread every so often
+//! and we create whatever notes have come due since each rule last ran. The UI
+//! can also kick off a run with `profile:recurrences:run` (which can be run as
+//! a job).
+//!
+//! Schedules are worked out in UTC.
+
+use ::std::sync::{Arc, RwLock};
+use ::std::sync::atomic::{AtomicBool, Ordering};
+use ::std::thread;
+use ::std::time::Duration;
+use ::time;
+use ::jedi::{self, Value};
+use ::error::{TResult, TError};
+use ::config;
+use ::messaging;
+use ::turtl::Turtl;
+use ::models::model::{self, Model};
+use ::models::board::Board;
+use ::models::note::Note;
+use ::models::sync_record::SyncAction;
+use ::sync::sync_model;
+use ::lib_permissions::Permission;
+use ::dispatch::registry::Registry;
+
+/// The user setting we keep our rules in
+const SETTINGS_KEY: &'static str = "recurrences";
+
+/// If we've been away a while, only make this many of the notes we missed (per
+/// rule, per run). Nobody wants forty copies of the daily standup note.
+const MAX_CATCHUP: usize = 5;
+
+const DAY: i64 = 86400;
+
+lazy_static! {
+    /// Set while our watcher thread is running. Flip it to stop the thread.
+    static ref WATCHER: RwLock<Option<Arc<AtomicBool>>> = RwLock::new(None);
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Freq {
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+fn default_interval() -> u32 { 1 }
+fn default_true() -> bool { true }
+
+/// A rule for making notes on a schedule (a small subset of RRULE)
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Recurrence {
+    #[serde(default)]
+    pub id: String,
+    /// The note we copy
+    pub template_id: String,
+    /// Where the copies go
+    pub board_id: String,
+    pub freq: Freq,
+    /// Every `interval` days/weeks/months
+    #[serde(default = "default_interval")]
+    pub interval: u32,
+    /// For weekly rules, the days of the week (0 = sunday) to make notes on.
+    /// Empty means the weekday `start` falls on.
+    #[serde(default)]
+    pub by_day: Vec<u32>,
+    /// The first occurrence (unix time). Later ones land at the same time of
+    /// day, and monthly ones on the same day of the month (or the last day, if
+    /// the month is too short).
+    pub start: i64,
+    /// No occurrences after this time
+    #[serde(default)]
+    pub until: Option<i64>,
+    /// No more than this many occurrences, ever
+    #[serde(default)]
+    pub count: Option<u32>,
+    /// The last occurrence we made a note for
+    #[serde(default)]
+    pub last_run: Option<i64>,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+}
+
+/// Days since 1970-01-01 for a date (proleptic gregorian)
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = if y >= 0 { y } else { y - 399 } / 400;
+    let yoe = y - era * 400;
+    let m = month as i64;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// The date for a number of days since 1970-01-01
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = if z >= 0 { z } else { z - 146096 } / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    let (next_year, next_month) = if month == 12 { (year + 1, 1) } else { (year, month + 1) };
+    (days_from_civil(next_year, next_month, 1) - days_from_civil(year, month, 1)) as u32
+}
+
+/// Turn a timestamp into a YYYY-MM-DD date
+fn ymd(ts: i64) -> String {
+    let (year, month, day) = civil_from_days(ts.div_euclid(DAY));
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+impl Recurrence {
+    /// Make sure the rule makes sense
+    fn check(&self) -> TResult<()> {
+        if self.template_id == "" {
+            return TErr!(TError::MissingField(String::from("template_id")));
+        }
+        if self.board_id == "" {
+            return TErr!(TError::MissingField(String::from("board_id")));
+        }
+        if self.interval < 1 {
+            return TErr!(TError::BadValue(String::from("interval must be at least 1")));
+        }
+        if self.by_day.iter().any(|x| *x > 6) {
+            return TErr!(TError::BadValue(String::from("by_day values must be 0 (sunday) - 6 (saturday)")));
+        }
+        Ok(())
+    }
+
+    /// Every occurrence of this rule up to (and including) `upto`, in order
+    pub fn occurrences(&self, upto: i64) -> Vec<i64> {
+        let mut out = Vec::new();
+        let start_day = self.start.div_euclid(DAY);
+        let time_of_day = self.start.rem_euclid(DAY);
+        let interval = self.interval.max(1) as i64;
+        let limit = match self.until {
+            Some(until) if until < upto => until,
+            _ => upto,
+        };
+        let full = |out: &Vec<i64>| self.count.map(|c| out.len() >= c as usize).unwrap_or(false);
+        let mut step: i64 = 0;
+        loop {
+            if full(&out) { break; }
+            let candidates: Vec<i64> = match self.freq {
+                Freq::Daily => vec![(start_day + step * interval) * DAY + time_of_day],
+                Freq::Weekly => {
+                    // 1970-01-01 was a thursday
+                    let weekday = (start_day + 4).rem_euclid(7);
+                    let week = start_day - weekday + (step * interval * 7);
+                    let mut days = if self.by_day.len() == 0 { vec![weekday as u32] } else { self.by_day.clone() };
+                    days.sort();
+                    days.dedup();
+                    days.into_iter().map(|d| (week + d as i64) * DAY + time_of_day).collect()
+                }
+                Freq::Monthly => {
+                    let (year, month, day) = civil_from_days(start_day);
+                    let months = (month as i64 - 1) + (step * interval);
+                    let year = year + months.div_euclid(12);
+                    let month = (months.rem_euclid(12) + 1) as u32;
+                    let day = day.min(days_in_month(year, month));
+                    vec![days_from_civil(year, month, day) * DAY + time_of_day]
+                }
+            };
+            if candidates.iter().all(|x| *x > limit) { break; }
+            for ts in candidates {
+                if ts < self.start || ts > limit || full(&out) { continue; }
+                out.push(ts);
+            }
+            step += 1;
+        }
+        out
+    }
+
+    /// The occurrences we haven't made notes for yet, as of `now`
+    pub fn due(&self, now: i64) -> Vec<i64> {
+        let after = self.last_run.unwrap_or(::std::i64::MIN);
+        let due = self.occurrences(now)
+            .into_iter()
+            .filter(|x| *x > after)
+            .collect::<Vec<_>>();
+        let skip = if due.len() > MAX_CATCHUP { due.len() - MAX_CATCHUP } else { 0 };
+        due.into_iter().skip(skip).collect()
+    }
+}
+
+/// Grab our rules
+pub fn list(turtl: &Turtl) -> TResult<Vec<Recurrence>> {
+    let user_guard = lockr!(turtl.user);
+    let val = user_guard.settings.as_ref().and_then(|s| s.get(SETTINGS_KEY));
+    match val {
+        Some(&Value::Null) | None => Ok(Vec::new()),
+        Some(x) => Ok(jedi::from_val(x.clone())?),
+    }
+}
+
+fn save(turtl: &Turtl, rules: &Vec<Recurrence>) -> TResult<()> {
+    let mut user = {
+        let user_guard = lockr!(turtl.user);
+        user_guard.clone()?
+    };
+    user.set_setting(turtl, SETTINGS_KEY, rules)
+}
+
+/// Make sure the template and board exist and that we can add notes
+fn check_access(turtl: &Turtl, rule: &Recurrence) -> TResult<()> {
+    rule.check()?;
+    let space_id = match Board::get_space_id(turtl, &rule.board_id) {
+        Some(x) => x,
+        None => return TErr!(TError::MissingData(format!("board {} not found", rule.board_id))),
+    };
+    Board::permission_check(turtl, &space_id, Some(&rule.board_id), &Permission::AddNote)?;
+    if turtl.load_notes(&vec![rule.template_id.clone()])?.len() == 0 {
+        return TErr!(TError::NotFound(format!("template note {} wasn't found", rule.template_id)));
+    }
+    Ok(())
+}
+
+/// Add a rule, returning it (with its id)
+pub fn add(turtl: &Turtl, mut rule: Recurrence) -> TResult<Recurrence> {
+    check_access(turtl, &rule)?;
+    rule.id = model::cid()?;
+    rule.last_run = None;
+    let mut rules = list(turtl)?;
+    rules.push(rule.clone());
+    save(turtl, &rules)?;
+    Ok(rule)
+}
+
+/// Update a rule. We keep track of when it last ran ourselves, so whatever the
+/// UI sends for `last_run` is ignored.
+pub fn edit(turtl: &Turtl, rule: Recurrence) -> TResult<Recurrence> {
+    check_access(turtl, &rule)?;
+    let mut rules = list(turtl)?;
+    let rule = match rules.iter_mut().find(|x| x.id == rule.id) {
+        Some(existing) => {
+            let last_run = existing.last_run;
+            *existing = rule;
+            existing.last_run = last_run;
+            existing.clone()
+        }
+        None => return TErr!(TError::NotFound(format!("recurrence {} wasn't found", rule.id))),
+    };
+    save(turtl, &rules)?;
+    Ok(rule)
+}
+
+/// Remove a rule. The notes it made stay put.
+pub fn delete(turtl: &Turtl, rule_id: &String) -> TResult<()> {
+    let mut rules = list(turtl)?;
+    let count = rules.len();
+    rules.retain(|x| &x.id != rule_id);
+    if rules.len() == count {
+        return TErr!(TError::NotFound(format!("recurrence {} wasn't found", rule_id)));
+    }
+    save(turtl, &rules)
+}
+
+/// Copy a template note into the rule's board for the given occurrence. A
+/// `{date}` in the title or text becomes the occurrence's date.
+fn instantiate(turtl: &Turtl, template: &Note, rule: &Recurrence, occurrence: i64) -> TResult<String> {
+    let space_id = match Board::get_space_id(turtl, &rule.board_id) {
+        Some(x) => x,
+        None => return TErr!(TError::MissingData(format!("board {} not found", rule.board_id))),
+    };
+    let date = ymd(occurrence);
+    let fill = |x: &Option<String>| x.as_ref().map(|x| x.replace("{date}", date.as_str()));
+    let mut note = Note::new();
+    note.user_id = turtl.user_id()?;
+    note.space_id = space_id;
+    note.board_id = Some(rule.board_id.clone());
+    note.type_ = template.type_.clone();
+    note.title = fill(&template.title);
+    note.text = fill(&template.text);
+    note.tags = template.tags.clone();
+    note.url = template.url.clone();
+    note.color = template.color;
+    note.parse_fields(turtl)?;
+    note.touch();
+    sync_model::save_model(SyncAction::Add, turtl, &mut note, false)?;
+    note.id_or_else()
+}
+
+/// Make the notes for every rule that has come due, returning their ids
+pub fn run(turtl: &Turtl) -> TResult<Vec<String>> {
+    let now = time::get_time().sec as i64;
+    let mut rules = list(turtl)?;
+    let mut created = Vec::new();
+    let mut changed = false;
+    for rule in rules.iter_mut().filter(|x| x.enabled) {
+        let due = rule.due(now);
+        if due.len() == 0 { continue; }
+        let template = match turtl.load_notes(&vec![rule.template_id.clone()])?.pop() {
+            Some(x) => x,
+            None => {
+                warn!("recurrence::run() -- template {} for rule {} is gone, disabling the rule", rule.template_id, rule.id);
+                rule.enabled = false;
+                changed = true;
+                continue;
+            }
+        };
+        for occurrence in due {
+            match instantiate(turtl, &template, rule, occurrence) {
+                Ok(id) => created.push(id),
+                Err(e) => {
+                    error!("recurrence::run() -- error creating note for rule {}: {}", rule.id, e);
+                    break;
+                }
+            }
+            rule.last_run = Some(occurrence);
+            changed = true;
+        }
+    }
+    if changed {
+        save(turtl, &rules)?;
+    }
+    if created.len() > 0 {
+        info!("recurrence::run() -- created {} notes", created.len());
+        messaging::ui_event("profile:recurrences:created", &created)?;
+    }
+    Ok(created)
+}
+
+/// Start checking our rules every `recurrence.check_interval` seconds (see
+/// `run()`, which the "recurrence:run" app event calls)
+pub fn start_watcher() {
+    stop_watcher();
+    let running = Arc::new(AtomicBool::new(true));
+    {
+        let mut guard = lockw!(*WATCHER);
+        *guard = Some(running.clone());
+    }
+    let interval: u64 = config::get(&["recurrence", "check_interval"]).unwrap_or(300);
+    let spawned = thread::Builder::new().name(String::from("recurrence")).spawn(move || {
+        loop {
+            if !running.load(Ordering::SeqCst) { break; }
+            match messaging::app_event("recurrence:run", &()) {
+                Ok(_) => {}
+                Err(e) => error!("recurrence::watcher -- error sending run event: {}", e),
+            }
+            thread::sleep(Duration::new(interval, 0));
+        }
+    });
+    match spawned {
+        Ok(_) => {}
+        Err(e) => error!("recurrence::start_watcher() -- error spawning watcher: {}", e),
+    }
+}
+
+/// Stop checking our rules
+pub fn stop_watcher() {
+    let mut guard = lockw!(*WATCHER);
+    if let Some(running) = guard.take() {
+        running.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Registers our recurrence commands
+pub fn register(reg: &mut Registry) {
+    reg.add("profile:recurrences:list", |turtl, _args| {
+        Ok(jedi::to_val(&list(turtl)?)?)
+    });
+    reg.add("profile:recurrences:add", |turtl, args| {
+        let rule: Recurrence = args.get(2)?;
+        Ok(jedi::to_val(&add(turtl, rule)?)?)
+    });
+    reg.add("profile:recurrences:edit", |turtl, args| {
+        let rule: Recurrence = args.get(2)?;
+        Ok(jedi::to_val(&edit(turtl, rule)?)?)
+    });
+    reg.add("profile:recurrences:delete", |turtl, args| {
+        let rule_id: String = args.get(2)?;
+        delete(turtl, &rule_id)?;
+        Ok(json!({}))
+    });
+    reg.add("profile:recurrences:run", |turtl, _args| {
+        let created = run(turtl)?;
+        Ok(json!(created))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(freq: Freq, start: &str) -> Recurrence {
+        jedi::parse(&format!(r#"{{"template_id":"1111","board_id":"2222","freq":"{}","start":{}}}"#,
+            match freq { Freq::Daily => "daily", Freq::Weekly => "weekly", Freq::Monthly => "monthly" },
+            start)).unwrap()
+    }
+
+    #[test]
+    fn schedules_occurrences() {
+        assert_eq!(civil_from_days(days_from_civil(2024, 2, 29)), (2024, 2, 29));
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_in_month(2023, 2), 28);
+        assert_eq!(days_in_month(2024, 2), 29);
+
+        // 2024-01-31 09:00 UTC, a wednesday
+        let start = days_from_civil(2024, 1, 31) * DAY + 9 * 3600;
+        let until = days_from_civil(2024, 5, 1) * DAY;
+        let dates = |rule: &Recurrence| rule.occurrences(until).into_iter().map(ymd).collect::<Vec<_>>();
+
+        let monthly = rule(Freq::Monthly, &start.to_string());
+        assert_eq!(dates(&monthly), vec!["2024-01-31", "2024-02-29", "2024-03-31", "2024-04-30"]);
+
+        let mut weekly = rule(Freq::Weekly, &start.to_string());
+        weekly.interval = 4;
+        assert_eq!(dates(&weekly), vec!["2024-01-31", "2024-02-28", "2024-03-27", "2024-04-24"]);
+        // mondays and wednesdays, but not the monday before we started
+        weekly.interval = 1;
+        weekly.by_day = vec![3, 1];
+        weekly.count = Some(3);
+        assert_eq!(dates(&weekly), vec!["2024-01-31", "2024-02-05", "2024-02-07"]);
+
+        let mut daily = rule(Freq::Daily, &start.to_string());
+        daily.until = Some(start + (2 * DAY));
+        assert_eq!(dates(&daily), vec!["2024-01-31", "2024-02-01", "2024-02-02"]);
+        daily.until = None;
+        daily.last_run = Some(start + DAY);
+        assert_eq!(daily.due(start + (3 * DAY)).into_iter().map(ymd).collect::<Vec<_>>(), vec!["2024-02-02", "2024-02-03"]);
+        daily.last_run = None;
+        assert_eq!(daily.due(start + (30 * DAY)).len(), MAX_CATCHUP);
+
+        assert!(rule(Freq::Daily, "0").check().is_ok());
+        let mut bad = rule(Freq::Weekly, "0");
+        bad.by_day = vec![7];
+        assert!(bad.check().is_err());
+    }
+}
@@ -0,0 +1,344 @@
+//! Renders notes into standalone documents (HTML, and PDF if the embedding app
+//! gives us a way to make one) for printing and sharing outside of Turtl.
+//!
+//! The HTML we generate is built entirely from escaped note data, so there's no
+//! way for a note to inject markup/script into the output. The only external
+//! resources allowed are `data:` images, which is how we inline attachments.
+//!
+//! We also project notes into plain text (markdown stripped, checklists as
+//! `[x]`/`[ ]`) for screen readers and voice assistants.
+
+use ::std::collections::HashMap;
+use ::std::sync::{RwLock, Mutex};
+use ::regex::Regex;
+use ::error::{TResult, TError};
+use ::turtl::Turtl;
+use ::models::model::Model;
+use ::models::file::FileData;
+use ::models::note::Note;
+use ::crypto;
+use ::dispatch::registry::Registry;
+
+/// A function that takes a rendered HTML document and returns PDF bytes
+pub type PdfRenderer = Box<Fn(&String) -> TResult<Vec<u8>> + Send + Sync>;
+
+/// How many plaintext projections we hold onto
+const PLAINTEXT_CACHE_SIZE: usize = 500;
+
+lazy_static! {
+    /// Set by the embedding app if it knows how to turn HTML into PDF
+    static ref PDF_RENDERER: RwLock<Option<PdfRenderer>> = RwLock::new(None);
+    /// note id -> (note mod, plaintext), so we don't redo the work every time a
+    /// screen reader asks
+    static ref PLAINTEXT_CACHE: Mutex<HashMap<String, (Option<i64>, String)>> = Mutex::new(HashMap::new());
+    static ref RE_IMAGE: Regex = Regex::new(r"!\[([^\]]*)\]\([^)]*\)").expect("render -- failed to compile image regex");
+    static ref RE_LINK: Regex = Regex::new(r"\[([^\]]+)\]\([^)]*\)").expect("render -- failed to compile link regex");
+}
+
+/// Set (or unset) the function we use to turn HTML into a PDF
+pub fn set_pdf_renderer(renderer: Option<PdfRenderer>) {
+    let mut guard = lockw!(*PDF_RENDERER);
+    *guard = renderer;
+}
+
+/// Escape a string so it can be dropped into HTML text or attribute values
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Turn plain note text into paragraphs. Blank lines split paragraphs, single
+/// newlines become line breaks.
+fn paragraphs(text: &str) -> String {
+    let normalized = text.replace("\r\n", "\n");
+    normalized.split("\n\n")
+        .map(|p| p.trim())
+        .filter(|p| p.len() > 0)
+        .map(|p| {
+            let lines = p.split('\n').map(|l| escape(l)).collect::<Vec<_>>();
+            format!("<p>{}</p>", lines.join("<br>"))
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Only inline images with mime types we know to be images (no svg, which can
+/// carry script)
+fn inline_image_type(ty: Option<&String>) -> Option<String> {
+    match ty.map(|x| x.to_lowercase()) {
+        Some(ref x) if x == "image/png" || x == "image/jpeg" || x == "image/gif" || x == "image/webp" => Some(x.clone()),
+        _ => None,
+    }
+}
+
+/// Build the HTML document for a note. `image` is the (decrypted) attachment
+/// data, if it's an image we want to inline.
+fn build_html(note: &Note, image: Option<(String, Vec<u8>)>) -> TResult<String> {
+    let title = note.title.as_ref().map(|x| x.as_str()).unwrap_or("");
+    let mut body: Vec<String> = Vec::new();
+    if title != "" {
+        body.push(format!("<h1>{}</h1>", escape(title)));
+    }
+    if let Some(ref url) = note.url {
+        let lower = url.trim().to_lowercase();
+        // only link out to things that are actually web urls
+        if lower.starts_with("http://") || lower.starts_with("https://") {
+            body.push(format!("<p class=\"url\"><a href=\"{}\">{}</a></p>", escape(url.trim()), escape(url.trim())));
+        } else {
+            body.push(format!("<p class=\"url\">{}</p>", escape(url)));
+        }
+    }
+    if let Some((ty, data)) = image {
+        let alt = note.file.as_ref().and_then(|f| f.name.as_ref()).map(|x| x.as_str()).unwrap_or("");
+        body.push(format!("<p class=\"image\"><img src=\"data:{};base64,{}\" alt=\"{}\"></p>", ty, crypto::to_base64(&data)?, escape(alt)));
+    }
+    if let Some(ref text) = note.text {
+        body.push(paragraphs(text));
+    }
+    if let Some(ref tags) = note.tags {
+        if tags.len() > 0 {
+            let tags = tags.iter().map(|t| format!("<span class=\"tag\">{}</span>", escape(t))).collect::<Vec<_>>();
+            body.push(format!("<p class=\"tags\">{}</p>", tags.join(" ")));
+        }
+    }
+    Ok(format!(concat!(
+        "<!DOCTYPE html>\n",
+        "<html>\n",
+        "<head>\n",
+        "<meta charset=\"utf-8\">\n",
+        "<meta http-equiv=\"Content-Security-Policy\" content=\"default-src 'none'; img-src data:; style-src 'unsafe-inline'\">\n",
+        "<title>{}</title>\n",
+        "<style>body {{ font-family: sans-serif; max-width: 40em; margin: 2em auto; }} img {{ max-width: 100%; }} .tag {{ color: #666; }}</style>\n",
+        "</head>\n",
+        "<body>\n{}\n</body>\n",
+        "</html>\n"
+    ), escape(title), body.join("\n")))
+}
+
+/// Strip inline markdown: links/images become their text, and emphasis/code
+/// markers go away. A single `*` or `_` only counts as emphasis at the edge of
+/// a word, so snake_case and "2 * 3" come through fine.
+fn strip_inline(line: &str) -> String {
+    let line = RE_IMAGE.replace_all(line, "$1");
+    let line = RE_LINK.replace_all(&line, "$1");
+    let line = line.replace("**", "").replace("__", "").replace("~~", "").replace("`", "");
+    let chars = line.chars().collect::<Vec<_>>();
+    let mut out = String::with_capacity(line.len());
+    for (i, &c) in chars.iter().enumerate() {
+        if c == '*' || c == '_' {
+            let prev = if i > 0 { Some(chars[i - 1]) } else { None };
+            let next = chars.get(i + 1).cloned();
+            let opens = prev.map(|x| !x.is_alphanumeric()).unwrap_or(true) && next.map(|x| !x.is_whitespace()).unwrap_or(false);
+            let closes = prev.map(|x| !x.is_whitespace()).unwrap_or(false) && next.map(|x| !x.is_alphanumeric()).unwrap_or(true);
+            if opens || closes { continue; }
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Turn one line of markdown into plain text. Returns None for lines that are
+/// pure formatting (rules, code fences).
+fn plaintext_line(line: &str) -> Option<String> {
+    let trimmed = line.trim();
+    if trimmed.starts_with("```") { return None; }
+    if trimmed.len() >= 3 && (trimmed.chars().all(|c| c == '-') || trimmed.chars().all(|c| c == '*') || trimmed.chars().all(|c| c == '_')) {
+        return None;
+    }
+    let indent = &line[0..(line.len() - line.trim_start().len())];
+    let mut rest = trimmed;
+    while rest.starts_with('>') {
+        rest = rest[1..].trim_start();
+    }
+    let rest = rest.trim_start_matches('#').trim_start();
+    let (prefix, rest) = if rest.starts_with("- ") || rest.starts_with("* ") || rest.starts_with("+ ") {
+        let item = &rest[2..];
+        let lower = item.to_lowercase();
+        if lower.starts_with("[x] ") || lower == "[x]" {
+            ("[x] ", item[3..].trim_start())
+        } else if item.starts_with("[ ] ") || item == "[ ]" {
+            ("[ ] ", item[3..].trim_start())
+        } else {
+            ("• ", item)
+        }
+    } else {
+        ("", rest)
+    };
+    Some(format!("{}{}{}", indent, prefix, strip_inline(rest)))
+}
+
+/// Project a note into plain text: title, url, then the body with markdown
+/// stripped. Runs of blank lines are collapsed to one.
+pub fn plaintext(note: &Note) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    if let Some(ref title) = note.title {
+        if title.trim() != "" { lines.push(String::from(title.trim())); }
+    }
+    if let Some(ref url) = note.url {
+        if url.trim() != "" { lines.push(String::from(url.trim())); }
+    }
+    if let Some(ref text) = note.text {
+        if lines.len() > 0 { lines.push(String::new()); }
+        let mut in_code = false;
+        for line in text.replace("\r\n", "\n").split('\n') {
+            if line.trim().starts_with("```") {
+                in_code = !in_code;
+                continue;
+            }
+            if in_code {
+                lines.push(String::from(line));
+                continue;
+            }
+            match plaintext_line(line) {
+                Some(x) => {
+                    let blank = x.trim() == "";
+                    if blank && lines.last().map(|l| l.trim() == "").unwrap_or(true) { continue; }
+                    lines.push(if blank { String::new() } else { String::from(x.trim_end()) });
+                }
+                None => {}
+            }
+        }
+    }
+    String::from(lines.join("\n").trim())
+}
+
+/// Grab a note's plaintext projection, using the cached copy if the note
+/// hasn't changed since we made it
+pub fn note_plaintext(note: &Note) -> TResult<String> {
+    let id = note.id_or_else()?;
+    let mut cache = lock!(*PLAINTEXT_CACHE);
+    if let Some(&(ref mod_, ref text)) = cache.get(&id) {
+        if mod_ == &note.mod_ { return Ok(text.clone()); }
+    }
+    let text = plaintext(note);
+    if cache.len() >= PLAINTEXT_CACHE_SIZE { cache.clear(); }
+    cache.insert(id, (note.mod_, text.clone()));
+    Ok(text)
+}
+
+/// Forget our cached plaintext (it's decrypted note data, so on logout)
+pub fn clear_cache() {
+    lock!(*PLAINTEXT_CACHE).clear();
+}
+
+/// Render a (decrypted) note as a standalone HTML document
+pub fn note_html(turtl: &Turtl, note: &Note) -> TResult<String> {
+    let image_type = note.file.as_ref().and_then(|f| inline_image_type(f.ty.as_ref()));
+    let image = match image_type {
+        Some(ty) if note.has_file => {
+            match FileData::load_file(turtl, note) {
+                Ok(data) => Some((ty, data)),
+                Err(e) => {
+                    warn!("render::note_html() -- couldn't load image for note {:?}: {}", note.id(), e);
+                    None
+                }
+            }
+        }
+        _ => None,
+    };
+    build_html(note, image)
+}
+
+/// Render a note as a PDF. Needs the embedding app to have set a renderer.
+pub fn note_pdf(turtl: &Turtl, note: &Note) -> TResult<Vec<u8>> {
+    let html = note_html(turtl, note)?;
+    let guard = lockr!(*PDF_RENDERER);
+    match guard.as_ref() {
+        Some(renderer) => renderer(&html),
+        None => TErr!(TError::NotImplemented),
+    }
+}
+
+/// Registers our note rendering commands
+pub fn register(reg: &mut Registry) {
+    reg.add("note:plaintext", |turtl, args| {
+        let note_id: String = args.get(2)?;
+        let notes: Vec<Note> = turtl.load_notes(&vec![note_id.clone()])?;
+        let note = match notes.get(0) {
+            Some(x) => x,
+            None => return TErr!(TError::NotFound(format!("note {} wasn't found", note_id))),
+        };
+        Ok(json!({"text": note_plaintext(note)?}))
+    });
+    reg.add("note:render", |turtl, args| {
+        let note_id: String = args.get(2)?;
+        let format: String = args.get_opt(3).unwrap_or(String::from("html"));
+        let notes: Vec<Note> = turtl.load_notes(&vec![note_id.clone()])?;
+        let note = match notes.get(0) {
+            Some(x) => x,
+            None => return TErr!(TError::NotFound(format!("note {} wasn't found", note_id))),
+        };
+        match format.as_ref() {
+            "html" => Ok(json!({"html": note_html(turtl, note)?})),
+            "pdf" => Ok(json!({"pdf": crypto::to_base64(&note_pdf(turtl, note)?)?})),
+            _ => TErr!(TError::BadValue(format!("unknown render format: {}", format))),
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_note_content() {
+        let mut note = Note::new();
+        note.title = Some(String::from("<script>alert('hi')</script>"));
+        note.url = Some(String::from("javascript:alert(1)"));
+        note.text = Some(String::from("hello <b>there</b>\nfriend\n\nnew & improved"));
+        note.tags = Some(vec![String::from("\"quoted\"")]);
+        let html = build_html(&note, None).unwrap();
+        assert!(!html.contains("<script>"));
+        assert!(!html.contains("href=\"javascript"));
+        assert!(html.contains("<h1>&lt;script&gt;alert(&#39;hi&#39;)&lt;/script&gt;</h1>"));
+        assert!(html.contains("<p>hello &lt;b&gt;there&lt;/b&gt;<br>friend</p>\n<p>new &amp; improved</p>"));
+        assert!(html.contains("<span class=\"tag\">&quot;quoted&quot;</span>"));
+    }
+
+    #[test]
+    fn projects_plaintext() {
+        let mut note = Note::new();
+        note.title = Some(String::from("Groceries"));
+        note.text = Some(String::from(concat!(
+            "# Things to **buy**\n",
+            "\n",
+            "\n",
+            "- [x] eggs\n",
+            "- [ ] *fresh* milk\n",
+            "* see [the list](https://example.com) or ![pic](data:x)\n",
+            "---\n",
+            "> keep `snake_case` and 2 * 3\n",
+            "```\n",
+            "**raw**\n",
+            "```\n",
+        )));
+        assert_eq!(plaintext(&note), concat!(
+            "Groceries\n",
+            "\n",
+            "Things to buy\n",
+            "\n",
+            "[x] eggs\n",
+            "[ ] fresh milk\n",
+            "• see the list or pic\n",
+            "keep snake_case and 2 * 3\n",
+            "**raw**",
+        ));
+    }
+
+    #[test]
+    fn only_inlines_safe_images() {
+        assert_eq!(inline_image_type(Some(&String::from("image/PNG"))), Some(String::from("image/png")));
+        assert_eq!(inline_image_type(Some(&String::from("image/svg+xml"))), None);
+        assert_eq!(inline_image_type(None), None);
+    }
+}
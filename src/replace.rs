@@ -0,0 +1,304 @@
+//! Find-and-replace across notes. The UI runs a search query and a find string
+//! through `profile:replace-text` once to get a preview (how many matches, and
+//! a few snippets from each note), then again with the preview's `token` to
+//! make the change. The token covers the notes as they were when we previewed
+//! them, so if any of them changed in the meantime we refuse and the UI has to
+//! preview again rather than rewriting text the user never saw.
+//!
+//! The whole change is one undo entry, and if a note fails to save partway
+//! through, we put back the ones we already saved.
+
+use ::regex::Regex;
+use ::jedi::{self, Value};
+use ::error::{TResult, TError};
+use ::turtl::Turtl;
+use ::crypto;
+use ::search::Query;
+use ::models::model::Model;
+use ::models::protected::Protected;
+use ::models::note::{Note, FindTextOptions};
+use ::models::board::Board;
+use ::models::sync_record::{SyncAction, SyncType};
+use ::sync::sync_model;
+use ::lib_permissions::Permission;
+use ::undo::{self, Op, UndoEntry};
+use ::dispatch::registry::Registry;
+
+/// How many snippets we send back per note
+const MAX_SNIPPETS: usize = 5;
+
+/// How much text (in chars) we show on either side of a match
+const SNIPPET_CONTEXT: usize = 30;
+
+/// Options for `profile:replace-text`
+#[derive(Deserialize, Debug, Default)]
+pub struct ReplaceOptions {
+    /// Treat `find` as a regular expression (`replace` can then use $1 etc)
+    #[serde(default)]
+    pub regex: bool,
+    #[serde(default)]
+    pub case_sensitive: bool,
+    /// The token from a preview. Passing it makes the change.
+    #[serde(default)]
+    pub confirm: Option<String>,
+}
+
+/// A match, with a bit of the text around it
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Snippet {
+    /// "title" or "text"
+    pub field: String,
+    pub before: String,
+    #[serde(rename = "match")]
+    pub matched: String,
+    pub after: String,
+}
+
+/// The matches in one note
+#[derive(Serialize, Debug)]
+pub struct NoteMatches {
+    pub note_id: String,
+    pub title: Option<String>,
+    pub count: usize,
+    pub snippets: Vec<Snippet>,
+}
+
+/// What a replace would do
+#[derive(Serialize, Debug)]
+pub struct Preview {
+    /// Pass this back as `options.confirm` to make the change
+    pub token: String,
+    /// How many matches, over all notes
+    pub total: usize,
+    pub notes: Vec<NoteMatches>,
+}
+
+/// Cut a snippet out of `val` around the match at `start..end` (byte offsets)
+fn snippet(field: &str, val: &str, start: usize, end: usize) -> Snippet {
+    let mut before = val[0..start].chars().rev().take(SNIPPET_CONTEXT).collect::<Vec<_>>();
+    before.reverse();
+    Snippet {
+        field: String::from(field),
+        before: before.into_iter().collect(),
+        matched: String::from(&val[start..end]),
+        after: val[end..].chars().take(SNIPPET_CONTEXT).collect(),
+    }
+}
+
+/// Replace every (non-empty) match of `re` in `val`, returning the new text
+/// and how many matches we replaced. With `expand`, the replacement can
+/// reference capture groups ($1, $name).
+fn replace_in(re: &Regex, val: &str, replacement: &str, expand: bool) -> (String, usize) {
+    let mut out = String::with_capacity(val.len());
+    let mut last = 0;
+    let mut count = 0;
+    for caps in re.captures_iter(val) {
+        let (start, end) = match caps.pos(0) {
+            Some(x) => x,
+            None => continue,
+        };
+        // same as Note::find_text(), empty matches don't count
+        if start == end { continue; }
+        out.push_str(&val[last..start]);
+        if expand {
+            out.push_str(&caps.expand(replacement));
+        } else {
+            out.push_str(replacement);
+        }
+        last = end;
+        count += 1;
+    }
+    out.push_str(&val[last..]);
+    (out, count)
+}
+
+/// Find the notes the query turns up that actually contain `find`
+fn find_notes(turtl: &Turtl, query: &Query, find: &str, options: &FindTextOptions) -> TResult<Vec<(Note, Vec<Snippet>, usize)>> {
+    let mut query = query.clone();
+    query.page = 1;
+    query.per_page = 99999;
+    let note_ids = {
+        let search_guard = lock!(turtl.search);
+        let search = match search_guard.as_ref() {
+            Some(x) => x,
+            None => return TErr!(TError::MissingField(String::from("turtl.search"))),
+        };
+        let (note_ids, _) = search.find(&query)?;
+        note_ids
+    };
+    let mut found = Vec::new();
+    for note in turtl.load_notes(&note_ids)? {
+        let matches = note.find_text(find, options)?;
+        if matches.len() == 0 { continue; }
+        let snippets = matches.iter()
+            .take(MAX_SNIPPETS)
+            .map(|x| {
+                let val = if x.field == "title" { note.title.as_ref() } else { note.text.as_ref() };
+                snippet(&x.field, val.map(|x| x.as_str()).unwrap_or(""), x.start, x.end)
+            })
+            .collect::<Vec<_>>();
+        let count = matches.len();
+        found.push((note, snippets, count));
+    }
+    found.sort_by(|a, b| a.0.id().cmp(&b.0.id()));
+    Ok(found)
+}
+
+/// A hash over the replace and the current state of every note it touches
+fn token(find: &str, replace: &str, options: &FindTextOptions, notes: &Vec<(Note, Vec<Snippet>, usize)>) -> TResult<String> {
+    let mut parts = vec![
+        String::from(find),
+        String::from(replace),
+        format!("{}:{}", options.regex, options.case_sensitive),
+    ];
+    for &(ref note, _, _) in notes {
+        parts.push(note.id().cloned().unwrap_or(String::new()));
+        parts.push(note.title.clone().unwrap_or(String::new()));
+        parts.push(note.text.clone().unwrap_or(String::new()));
+    }
+    let data = jedi::stringify(&parts)?;
+    Ok(crypto::to_hex(&crypto::sha256(data.as_bytes())?)?)
+}
+
+/// Put notes back the way they were, after a replace fails partway through
+fn roll_back(turtl: &Turtl, saved: Vec<(Note, Option<String>, Option<String>)>) {
+    for (mut note, title, text) in saved {
+        note.title = title;
+        note.text = text;
+        note.touch();
+        match sync_model::save_model(SyncAction::Edit, turtl, &mut note, false) {
+            Ok(_) => {}
+            Err(e) => error!("replace::roll_back() -- couldn't put back note {:?}: {}", note.id(), e),
+        }
+    }
+}
+
+/// Preview replacing `find` with `replace` in the notes `query` finds, or (if
+/// `options.confirm` has the preview's token) do it
+pub fn replace_text(turtl: &Turtl, query: &Query, find: &String, replace: &String, options: &ReplaceOptions) -> TResult<Value> {
+    if find.len() == 0 {
+        return TErr!(TError::MissingField(String::from("find")));
+    }
+    let find_options = FindTextOptions {
+        regex: options.regex,
+        case_sensitive: options.case_sensitive,
+    };
+    let re = find_options.compile(find)?;
+    let found = find_notes(turtl, query, find, &find_options)?;
+    let token = token(find, replace, &find_options, &found)?;
+    let confirm = match options.confirm.as_ref() {
+        Some(x) => x,
+        None => {
+            let total: usize = found.iter().map(|x| x.2).sum();
+            let notes = found.into_iter()
+                .map(|(note, snippets, count)| NoteMatches {
+                    note_id: note.id().cloned().unwrap_or(String::new()),
+                    title: note.title.clone(),
+                    count: count,
+                    snippets: snippets,
+                })
+                .collect::<Vec<_>>();
+            return Ok(jedi::to_val(&Preview { token: token, total: total, notes: notes })?);
+        }
+    };
+    if confirm != &token {
+        return TErr!(TError::BadValue(String::from("the matching notes changed since the preview, please preview again")));
+    }
+
+    // make sure we can edit all of them before we touch any of them
+    for &(ref note, _, _) in &found {
+        Board::permission_check(turtl, &note.space_id, note.board_id.as_ref(), &Permission::EditNote)?;
+    }
+    let mut saved: Vec<(Note, Option<String>, Option<String>)> = Vec::with_capacity(found.len());
+    let mut undo_ops = Vec::with_capacity(found.len());
+    let mut redo_ops = Vec::with_capacity(found.len());
+    let mut total = 0;
+    for (mut note, _, _) in found {
+        let mut before = note.data()?;
+        let _ = jedi::remove(&["body"], &mut before);
+        let old_title = note.title.clone();
+        let old_text = note.text.clone();
+        let mut count = 0;
+        if let Some(title) = old_title.as_ref() {
+            let (title, replaced) = replace_in(&re, title, replace, options.regex);
+            note.title = Some(title);
+            count += replaced;
+        }
+        if let Some(text) = old_text.as_ref() {
+            let (text, replaced) = replace_in(&re, text, replace, options.regex);
+            note.text = Some(text);
+            count += replaced;
+        }
+        note.touch();
+        match sync_model::save_model(SyncAction::Edit, turtl, &mut note, false) {
+            Ok(mut after) => {
+                let _ = jedi::remove(&["body"], &mut after);
+                undo_ops.push(Op::new(SyncAction::Edit, SyncType::Note, before));
+                redo_ops.push(Op::new(SyncAction::Edit, SyncType::Note, after));
+                saved.push((note, old_title, old_text));
+                total += count;
+            }
+            Err(e) => {
+                warn!("replace::replace_text() -- error saving note {:?}, rolling back {} notes: {}", note.id(), saved.len(), e);
+                roll_back(turtl, saved);
+                return Err(e);
+            }
+        }
+    }
+    let notes = saved.len();
+    if notes > 0 {
+        undo::record(UndoEntry {
+            label: String::from("replace text"),
+            undo: undo_ops,
+            redo: redo_ops,
+        });
+    }
+    Ok(json!({"notes": notes, "replaced": total}))
+}
+
+/// Registers our find-and-replace command
+pub fn register(reg: &mut Registry) {
+    reg.add("profile:replace-text", |turtl, args| {
+        let query: Query = args.get(2)?;
+        let find: String = args.get(3)?;
+        let replace: String = args.get(4)?;
+        let options: ReplaceOptions = args.get_opt(5).unwrap_or(Default::default());
+        replace_text(turtl, &query, &find, &replace, &options)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_text() {
+        let re = FindTextOptions::default().compile("cat").unwrap();
+        let (out, count) = replace_in(&re, "Cat, cat, concatenate", "dog", false);
+        assert_eq!(out, "dog, dog, condogenate");
+        assert_eq!(count, 3);
+        // literal replacements leave $ alone
+        let (out, _) = replace_in(&re, "a cat", "$1 dog", false);
+        assert_eq!(out, "a $1 dog");
+
+        let options = FindTextOptions { regex: true, case_sensitive: true };
+        let re = options.compile(r"(\w+)@example\.com").unwrap();
+        let (out, count) = replace_in(&re, "mail bob@example.com or Al@example.com", "$1@example.org", true);
+        assert_eq!(out, "mail bob@example.org or Al@example.org");
+        assert_eq!(count, 2);
+        // empty matches don't insert anything
+        let re = options.compile("x*").unwrap();
+        assert_eq!(replace_in(&re, "abc", "-", true), (String::from("abc"), 0));
+    }
+
+    #[test]
+    fn cuts_snippets() {
+        let text = "héllo wörld, and then some more text that goes on for a while after the match";
+        let start = text.find("wörld").unwrap();
+        let snip = snippet("text", text, start, start + "wörld".len());
+        assert_eq!(snip.before, "héllo ");
+        assert_eq!(snip.matched, "wörld");
+        assert_eq!(snip.after.chars().count(), SNIPPET_CONTEXT);
+        assert!(snip.after.starts_with(", and then"));
+    }
+}
@@ -0,0 +1,296 @@
+//! Cleans up HTML that came from somewhere we don't control (the clipper,
+//! imports, article extraction) before it gets saved on a note. We work off an
+//! allow-list: known-safe tags and attributes are kept, everything else goes.
+//! Scripts, styles, frames, and the like are removed along with their content,
+//! event handler attributes are dropped, and links/images can only point at
+//! http(s)/mailto (or relative) URLs.
+//!
+//! Text between tags is left exactly as it was, so running markdown or plain
+//! text through here doesn't mangle it.
+
+use ::config;
+use ::render;
+
+/// Tags we keep
+const ALLOWED_TAGS: &'static [&'static str] = &[
+    "a", "abbr", "b", "blockquote", "br", "caption", "code", "dd", "del", "div",
+    "dl", "dt", "em", "h1", "h2", "h3", "h4", "h5", "h6", "hr", "i", "img",
+    "ins", "kbd", "li", "mark", "ol", "p", "pre", "q", "s", "small", "span",
+    "strike", "strong", "sub", "sup", "table", "tbody", "td", "tfoot", "th",
+    "thead", "tr", "u", "ul",
+];
+
+/// Tags that are removed along with everything inside them
+const DROPPED_TAGS: &'static [&'static str] = &[
+    "script", "style", "iframe", "frame", "frameset", "object", "embed",
+    "applet", "noscript", "noembed", "template", "textarea", "title", "xmp",
+];
+
+/// Attributes we keep, by tag ("*" is any tag)
+const ALLOWED_ATTRS: &'static [(&'static str, &'static [&'static str])] = &[
+    ("*", &["title", "lang", "dir"]),
+    ("a", &["href", "name"]),
+    ("img", &["src", "alt", "width", "height"]),
+    ("blockquote", &["cite"]),
+    ("q", &["cite"]),
+    ("ol", &["start"]),
+    ("td", &["colspan", "rowspan"]),
+    ("th", &["colspan", "rowspan"]),
+];
+
+/// Attributes that hold a URL
+const URL_ATTRS: &'static [&'static str] = &["href", "src", "cite"];
+
+/// URL schemes we let through
+const ALLOWED_SCHEMES: &'static [&'static str] = &["http", "https", "mailto"];
+
+/// Whether we sanitize at all
+pub fn enabled() -> bool {
+    config::get(&["sanitize", "enabled"]).unwrap_or(true)
+}
+
+/// The note types whose text holds HTML (and gets sanitized)
+pub fn html_note_types() -> Vec<String> {
+    config::get(&["sanitize", "note_types"]).unwrap_or(vec![String::from("link"), String::from("image")])
+}
+
+/// Decode the HTML entities in an attribute value. Unknown entities are left
+/// as they are.
+fn decode_entities(val: &str) -> String {
+    let mut decoded = String::with_capacity(val.len());
+    let mut rest = val;
+    while let Some(idx) = rest.find('&') {
+        decoded.push_str(&rest[0..idx]);
+        rest = &rest[idx..];
+        // numeric entities end at the first non-digit, semicolon or not
+        let (skip, radix) = if rest.starts_with("&#x") || rest.starts_with("&#X") {
+            (3, 16)
+        } else if rest.starts_with("&#") {
+            (2, 10)
+        } else {
+            (1, 0)
+        };
+        let end = rest[skip..].find(|c: char| if radix == 0 { !c.is_ascii_alphanumeric() } else { !c.is_digit(radix) })
+            .map(|x| x + skip)
+            .unwrap_or(rest.len());
+        let entity = &rest[skip..end];
+        let chr = if radix != 0 {
+            u32::from_str_radix(entity, radix).ok().and_then(::std::char::from_u32)
+        } else {
+            match entity.to_lowercase().as_str() {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "colon" => Some(':'),
+                "tab" => Some('\t'),
+                "newline" => Some('\n'),
+                _ => None,
+            }
+        };
+        match chr {
+            Some(c) => {
+                decoded.push(c);
+                rest = &rest[end..];
+                if rest.starts_with(';') { rest = &rest[1..]; }
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+/// Whether a (decoded) URL is one we're ok linking to. Browsers ignore
+/// whitespace and control characters in a scheme, so we do too before looking
+/// at it.
+fn safe_url(url: &str, tag: &str) -> bool {
+    let squashed = url.chars()
+        .filter(|c| !c.is_whitespace() && !c.is_control())
+        .collect::<String>()
+        .to_lowercase();
+    let scheme_end = match squashed.find(|c: char| c == ':' || c == '/' || c == '?' || c == '#') {
+        Some(idx) if squashed[idx..].starts_with(':') => idx,
+        // no scheme, so it's relative
+        _ => return true,
+    };
+    let scheme = &squashed[0..scheme_end];
+    if ALLOWED_SCHEMES.contains(&scheme) { return true; }
+    // inline images are fine, but not svg (which can carry script)
+    tag == "img" && scheme == "data" && squashed.starts_with("data:image/") && !squashed.starts_with("data:image/svg")
+}
+
+/// Whether we keep an attribute on a tag
+fn allowed_attr(tag: &str, attr: &str) -> bool {
+    ALLOWED_ATTRS.iter()
+        .filter(|&&(t, _)| t == "*" || t == tag)
+        .any(|&(_, attrs)| attrs.contains(&attr))
+}
+
+/// A tag we pulled out of the HTML
+struct Tag {
+    name: String,
+    closing: bool,
+    /// (name, decoded value)
+    attrs: Vec<(String, String)>,
+    /// How many bytes of the input the tag took up
+    len: usize,
+}
+
+/// Parse the tag at the start of `html` (which starts with "<"). Returns None
+/// if the tag never ends.
+fn parse_tag(html: &str) -> Option<Tag> {
+    let bytes = html.as_bytes();
+    let is_space = |b: u8| b == b' ' || b == b'\t' || b == b'\n' || b == b'\r' || b == 0x0c;
+    let mut i = 1;
+    let closing = bytes.get(1) == Some(&b'/');
+    if closing { i += 1; }
+    let start = i;
+    while i < bytes.len() && !is_space(bytes[i]) && bytes[i] != b'/' && bytes[i] != b'>' { i += 1; }
+    let name = html[start..i].to_ascii_lowercase();
+    let mut attrs = Vec::new();
+    loop {
+        while i < bytes.len() && (is_space(bytes[i]) || bytes[i] == b'/') { i += 1; }
+        if i >= bytes.len() { return None; }
+        if bytes[i] == b'>' { break; }
+        let start = i;
+        i += 1;
+        while i < bytes.len() && !is_space(bytes[i]) && bytes[i] != b'/' && bytes[i] != b'>' && bytes[i] != b'=' { i += 1; }
+        let attr = html[start..i].to_ascii_lowercase();
+        while i < bytes.len() && is_space(bytes[i]) { i += 1; }
+        let mut val = String::new();
+        if i < bytes.len() && bytes[i] == b'=' {
+            i += 1;
+            while i < bytes.len() && is_space(bytes[i]) { i += 1; }
+            if i >= bytes.len() { return None; }
+            let quote = bytes[i];
+            if quote == b'"' || quote == b'\'' {
+                let end = html[(i + 1)..].find(quote as char)? + i + 1;
+                val = decode_entities(&html[(i + 1)..end]);
+                i = end + 1;
+            } else {
+                let start = i;
+                while i < bytes.len() && !is_space(bytes[i]) && bytes[i] != b'>' { i += 1; }
+                val = decode_entities(&html[start..i]);
+            }
+        }
+        attrs.push((attr, val));
+    }
+    Some(Tag { name: name, closing: closing, attrs: attrs, len: i + 1 })
+}
+
+/// Write out a tag we're keeping, minus anything we don't allow on it
+fn write_tag(out: &mut String, tag: &Tag) {
+    if tag.closing {
+        out.push_str(&format!("</{}>", tag.name));
+        return;
+    }
+    out.push('<');
+    out.push_str(&tag.name);
+    for &(ref attr, ref val) in &tag.attrs {
+        if !allowed_attr(&tag.name, attr) { continue; }
+        if URL_ATTRS.contains(&attr.as_str()) && !safe_url(val, &tag.name) { continue; }
+        out.push_str(&format!(" {}=\"{}\"", attr, render::escape(val)));
+    }
+    out.push('>');
+}
+
+/// Sanitize a chunk of HTML
+pub fn html(html: &str) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(idx) = rest.find('<') {
+        out.push_str(&rest[0..idx]);
+        rest = &rest[idx..];
+        let next = rest[1..].chars().next();
+        if rest.starts_with("<!--") {
+            // comments can hide things (conditional comments) so they go
+            rest = match rest[4..].find("-->") {
+                Some(end) => &rest[(end + 7)..],
+                None => "",
+            };
+        } else if rest.starts_with("<!") || rest.starts_with("<?") {
+            // doctypes, CDATA, processing instructions
+            rest = match rest.find('>') {
+                Some(end) => &rest[(end + 1)..],
+                None => "",
+            };
+        } else if next.map(|c| c.is_ascii_alphabetic() || c == '/').unwrap_or(false) {
+            let tag = match parse_tag(rest) {
+                Some(x) => x,
+                // an unfinished tag at the end is dropped, same as a browser
+                None => {
+                    rest = "";
+                    break;
+                }
+            };
+            rest = &rest[tag.len..];
+            if tag.name.is_empty() { continue; }
+            if DROPPED_TAGS.contains(&tag.name.as_str()) {
+                if tag.closing { continue; }
+                let close = format!("</{}", tag.name);
+                rest = match rest.to_ascii_lowercase().find(&close) {
+                    Some(end) => match rest[end..].find('>') {
+                        Some(gt) => &rest[(end + gt + 1)..],
+                        None => "",
+                    },
+                    None => "",
+                };
+            } else if ALLOWED_TAGS.contains(&tag.name.as_str()) {
+                write_tag(&mut out, &tag);
+            }
+        } else {
+            // a "<" that doesn't start a tag is just text
+            out.push('<');
+            rest = &rest[1..];
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_xss_vectors() {
+        assert_eq!(html("hi<script>alert(1)</script> there"), "hi there");
+        assert_eq!(html("<SCRIPT SRC=//evil.com/x.js></SCRIPT>ok"), "ok");
+        assert_eq!(html("<scr<script>alert(1)</script>ipt>"), "alert(1)ipt>");
+        assert_eq!(html("<img src=x onerror=alert(1)>"), "<img src=\"x\">");
+        assert_eq!(html("<img/src=\"x\"/onerror=\"alert(1)\">"), "<img src=\"x\">");
+        assert_eq!(html("<svg onload=alert(1)><b>bold</b></svg>"), "<b>bold</b>");
+        assert_eq!(html("<a href=\"javascript:alert(1)\">x</a>"), "<a>x</a>");
+        assert_eq!(html("<a href=\"JaVaScRiPt:alert(1)\">x</a>"), "<a>x</a>");
+        assert_eq!(html("<a href=\" java\tscript:alert(1)\">x</a>"), "<a>x</a>");
+        assert_eq!(html("<a href=\"&#106;avascript&colon;alert(1)\">x</a>"), "<a>x</a>");
+        assert_eq!(html("<a href=\"&#106avascript:alert(1)\">x</a>"), "<a>x</a>");
+        assert_eq!(html("<a href=\"&#x6A;avascript:alert(1)\">x</a>"), "<a>x</a>");
+        assert_eq!(html("<a href=\"data:text/html,<script>alert(1)</script>\">x</a>"), "<a>x</a>");
+        assert_eq!(html("<iframe src=\"https://evil.com\">hidden</iframe>shown"), "shown");
+        assert_eq!(html("<style>body { display: none }</style><p style=\"x\">hi</p>"), "<p>hi</p>");
+        assert_eq!(html("<!--[if IE]><script>alert(1)</script><![endif]-->ok"), "ok");
+        assert_eq!(html("<div onclick='alert(1)' title='a \"b\"'>x</div>"), "<div title=\"a &quot;b&quot;\">x</div>");
+        assert_eq!(html("fine <img src=x onerror=alert(1)"), "fine ");
+    }
+
+    #[test]
+    fn keeps_safe_html() {
+        assert_eq!(
+            html("<p>See <a href=\"https://turtlapp.com/?a=1&amp;b=2\">this</a></p>"),
+            "<p>See <a href=\"https://turtlapp.com/?a=1&amp;b=2\">this</a></p>"
+        );
+        assert_eq!(html("<a href=\"/notes/123\">rel</a>"), "<a href=\"/notes/123\">rel</a>");
+        assert_eq!(html("<a href=\"mailto:andrew@turtlapp.com\">mail</a>"), "<a href=\"mailto:andrew@turtlapp.com\">mail</a>");
+        assert_eq!(html("<img src=\"data:image/png;base64,AAAA\">"), "<img src=\"data:image/png;base64,AAAA\">");
+        assert_eq!(html("<img src=\"data:image/svg+xml;base64,AAAA\">"), "<img>");
+        // text (and markdown) is left alone
+        let text = "# notes\n\nif a < b && b > c then **yay** [link](https://turtlapp.com) &amp; done";
+        assert_eq!(html(text), text);
+    }
+}
@@ -0,0 +1,132 @@
+//! Virus scanning for attachments. The core doesn't ship a scanner: the
+//! embedding app hands us one (see `turtlc_set_scanner()`) and we call it
+//! before a file gets anywhere near the user. Files are scanned when they're
+//! attached to a note, and files that come down from the server without a scan
+//! on them get scanned the first time we decrypt them.
+//!
+//! Results live on the note's file (`file.scan`). A file the scanner flags is
+//! quarantined: it stays on disk (encrypted, so it can't do anything there)
+//! but we refuse to hand it out, and the UI gets a `files:quarantined` event.
+
+use ::std::sync::RwLock;
+use ::time;
+use ::error::{TResult, TError};
+use ::turtl::Turtl;
+use ::messaging;
+use ::models::model::Model;
+use ::models::protected::Protected;
+use ::models::note::Note;
+use ::models::sync_record::SyncAction;
+use ::sync::sync_model;
+
+/// Scans a file's contents (and gets its name, if it has one). Returns None if
+/// the file is clean, or the name of what it found if not.
+pub type Scanner = Box<Fn(&[u8], Option<&str>) -> TResult<Option<String>> + Send + Sync>;
+
+lazy_static! {
+    /// Set by the embedding app if it can scan files
+    static ref SCANNER: RwLock<Option<Scanner>> = RwLock::new(None);
+}
+
+/// Set (or unset) the function we use to scan files
+pub fn set_scanner(scanner: Option<Scanner>) {
+    let mut guard = lockw!(*SCANNER);
+    *guard = scanner;
+}
+
+/// What the scanner thought of a file
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ScanResult {
+    /// When we scanned it
+    pub scanned: i64,
+    /// What the scanner found, if anything
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub threat: Option<String>,
+}
+
+impl ScanResult {
+    pub fn clean(&self) -> bool {
+        self.threat.is_none()
+    }
+}
+
+/// Scan a file. Returns None if we don't have a scanner.
+pub fn scan(data: &[u8], name: Option<&str>) -> TResult<Option<ScanResult>> {
+    let guard = lockr!(*SCANNER);
+    let scanner = match guard.as_ref() {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    let threat = scanner(data, name)?;
+    if let Some(ref threat) = threat {
+        warn!("scan::scan() -- scanner flagged {:?}: {}", name, threat);
+    }
+    Ok(Some(ScanResult {
+        scanned: time::get_time().sec as i64,
+        threat: threat,
+    }))
+}
+
+/// Let the UI know a note's file got quarantined
+pub fn quarantined(note: &Note) {
+    let file = match note.file.as_ref() {
+        Some(x) => x,
+        None => return,
+    };
+    let event = json!({
+        "note_id": note.id(),
+        "name": file.name,
+        "threat": file.scan.as_ref().and_then(|x| x.threat.clone()),
+    });
+    messaging::ui_event("files:quarantined", &event)
+        .unwrap_or_else(|e| error!("scan::quarantined() -- error sending event: {}", e));
+}
+
+/// Errors if a note's file has been quarantined
+pub fn blocked(note: &Note) -> TResult<()> {
+    let threat = note.file.as_ref()
+        .and_then(|x| x.scan.as_ref())
+        .and_then(|x| x.threat.as_ref());
+    match threat {
+        Some(threat) => TErr!(TError::Quarantined(format!("the file on note {} was quarantined ({})", note.id().map(|x| x.as_str()).unwrap_or("?"), threat))),
+        None => Ok(()),
+    }
+}
+
+/// Make sure a note's (decrypted) file is OK to hand out, scanning it first if
+/// it hasn't been yet. The result only gets saved on this device: nothing
+/// about the note changed, so there's nothing to sync.
+pub fn check_file(turtl: &Turtl, note: &Note, data: &[u8]) -> TResult<()> {
+    let unscanned = note.file.as_ref().map(|x| x.scan.is_none()).unwrap_or(false);
+    if unscanned {
+        let name = note.file.as_ref().and_then(|x| x.name.clone());
+        if let Some(result) = scan(data, name.as_ref().map(|x| x.as_str()))? {
+            let mut note = note.clone()?;
+            let clean = result.clean();
+            if let Some(file) = note.file.as_mut() { file.scan = Some(result); }
+            sync_model::save_model(SyncAction::Edit, turtl, &mut note, true)?;
+            if !clean { quarantined(&note); }
+            return blocked(&note);
+        }
+    }
+    blocked(note)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scans_files() {
+        assert_eq!(scan(b"hello", None).unwrap(), None);
+        set_scanner(Some(Box::new(|data: &[u8], _name: Option<&str>| {
+            let found = data.windows(5).any(|x| x == b"EICAR");
+            Ok(if found { Some(String::from("Eicar-Test-Signature")) } else { None })
+        })));
+        assert!(scan(b"hello", Some("hi.txt")).unwrap().unwrap().clean());
+        let res = scan(b"X5O!P%@AP EICAR", Some("bad.com")).unwrap().unwrap();
+        assert_eq!(res.threat, Some(String::from("Eicar-Test-Signature")));
+        set_scanner(None);
+        assert_eq!(scan(b"X5O!P%@AP EICAR", None).unwrap(), None);
+    }
+}
@@ -10,12 +10,38 @@ pub fn get_schema() -> Value {
     // are in each object pffft). this also makes data upgrades (new tables/new
     // indexes) seamless since the storage system is so generic.
     json!({
+        // cold storage for notes nobody has touched in a while. see archive.rs
+        "archive": {},
         "boards": {
             "indexes": [
                 {"fields": ["space_id"]},
                 {"fields": ["user_id"]}
             ]
         },
+        "comments": {
+            "indexes": [
+                {"fields": ["note_id"]}
+            ]
+        },
+        "contacts": {},
+        "webhooks": {},
+        "reactions": {
+            "indexes": [
+                {"fields": ["note_id"]}
+            ]
+        },
+        "receipts": {
+            "indexes": [
+                {"fields": ["note_id"]}
+            ]
+        },
+        "time_entries": {
+            "indexes": [
+                {"fields": ["note_id"]},
+                {"fields": ["user_id"]}
+            ]
+        },
+        "email_gateway": {},
         "invites": {},
         "keychain": {
             "indexes": [
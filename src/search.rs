@@ -5,16 +5,30 @@
 //!
 //! Note that this module only returns note IDs when returning search results.
 
+use ::std::fs;
+use ::std::thread;
+use ::std::sync::Mutex;
+use ::std::collections::HashMap;
+use ::time;
 use ::rusqlite::NO_PARAMS;
 use ::rusqlite::types::ToSql;
 
 use ::clouseau::Clouseau;
 use ::dumpy::SearchVal;
 
+use ::jedi;
 use ::error::{TResult, TError};
+use ::util;
 use ::models::model;
-use ::models::note::Note;
+use ::models::note::{self, Note};
 use ::models::file::File;
+use ::analyzer::{self, Analyzer, AnalyzerConfig};
+use ::geo;
+use ::messaging;
+use ::models::comment::Comment;
+use ::ordering::{self, Order};
+use ::jobs;
+use ::dispatch::registry::Registry;
 
 /// A query builder
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -29,11 +43,28 @@ pub struct Query {
     pub tags: Vec<String>,
     #[serde(default)]
     pub exclude_tags: Vec<String>,
+    /// Only notes that @mention all of these user ids
+    #[serde(default)]
+    pub mentions: Vec<String>,
     #[serde(rename = "type")]
     pub type_: Option<String>,
     pub url: Option<String>,
+    /// Only notes that do (or don't) link to anything
+    pub has_url: Option<bool>,
+    /// Only notes linking to this domain (or its subdomains)
+    pub domain: Option<String>,
     pub has_file: Option<bool>,
+    /// Only notes with (or without) an audio attachment
+    pub audio: Option<bool>,
+    /// Only notes that have (or haven't) been marked read
+    pub read: Option<bool>,
     pub color: Option<i32>,
+    /// Only notes mentioning a number in this range
+    pub amount: Option<AmountRange>,
+    /// Only notes mentioning a date in this range
+    pub date: Option<DateRange>,
+    /// Only notes taken within some distance of a point
+    pub near: Option<GeoRange>,
     #[serde(default)]
     pub sort: String,
     #[serde(default)]
@@ -44,11 +75,134 @@ pub struct Query {
     pub per_page: i32,
 }
 
+/// A range of numbers. Either end can be left open.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct AmountRange {
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+/// A circle on the map: everything within `km` of a point
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GeoRange {
+    pub lat: f64,
+    pub lon: f64,
+    pub km: f64,
+}
+
+/// A range of dates (YYYY-MM-DD or YYYY-MM). Either end can be left open.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct DateRange {
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
+
+impl DateRange {
+    /// Turn this range into the (low, high) YYYYMMDD bounds we index dates with
+    pub fn bounds(&self) -> TResult<(i32, i32)> {
+        let parse = |date: &String| -> TResult<(i32, i32)> {
+            match analyzer::parse_date(date) {
+                Some(x) => Ok(x),
+                None => TErr!(TError::BadValue(format!("bad date: {}", date))),
+            }
+        };
+        let low = match self.from { Some(ref x) => parse(x)?.0, None => 0 };
+        let high = match self.to { Some(ref x) => parse(x)?.1, None => 99999999 };
+        Ok((low, high))
+    }
+}
+
+/// How many index/unindex operations we let pile up on a memory-mapped index
+/// before kicking off a background compaction
+const COMPACT_EVERY: u32 = 500;
+
+/// How many entries we keep in each of our query caches before clearing them
+const QUERY_CACHE_SIZE: usize = 128;
+
+/// Caches query results so UIs doing search-as-you-type don't make us re-run
+/// the same queries over and over. Everything in here is tied to an index
+/// generation, and gets thrown out as soon as the index changes.
+#[derive(Default)]
+struct QueryCache {
+    /// The index generation the cached data was built from
+    generation: u64,
+    /// Full-text matches, keyed by normalized query text. These are the
+    /// expensive part of most queries and are shared between queries that
+    /// only differ by their filters.
+    text: HashMap<String, Vec<String>>,
+    /// Full results, keyed by normalized query
+    results: HashMap<String, (Vec<String>, i32)>,
+}
+
+impl QueryCache {
+    /// Make sure our cache matches the given index generation, dumping it if
+    /// it doesn't.
+    fn sync_generation(&mut self, generation: u64) {
+        if self.generation == generation { return; }
+        self.text.clear();
+        self.results.clear();
+        self.generation = generation;
+    }
+}
+
+/// Normalize query text so that the same query typed slightly differently
+/// (extra spaces, etc) hits the same cache entry.
+fn normalize_text(text: &String) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// A summary of what's in the index
+#[derive(Serialize, Debug, Default)]
+pub struct IndexStats {
+    pub notes: i64,
+    /// Distinct full-text terms
+    pub terms: i64,
+    pub tags: i64,
+    pub spaces: i64,
+    /// space id -> how many of its notes are indexed
+    pub space_counts: HashMap<String, i64>,
+    pub generation: u64,
+    /// "memory" or "mmap"
+    pub storage: String,
+    /// How much disk the index is using (0 for in-memory indexes)
+    pub disk_size: u64,
+    /// When the index was last rebuilt from scratch (unix time)
+    pub built: i64,
+}
+
+/// How a single part of a query did against a note
+#[derive(Serialize, Debug)]
+pub struct ExplainCheck {
+    /// The query field this check is for (`text`, `tags`, etc)
+    pub field: String,
+    pub passed: bool,
+    pub detail: String,
+}
+
+/// Why a note did (or didn't) match a query
+#[derive(Serialize, Debug, Default)]
+pub struct Explanation {
+    pub note_id: String,
+    pub indexed: bool,
+    pub matched: bool,
+    pub checks: Vec<ExplainCheck>,
+}
+
 /// Holds the state for our search
 pub struct Search {
     /// Our main index, driven by Clouseau. Mainly for full-text search, but is
     /// used for other indexed searches as well.
     idx: Clouseau,
+    /// Counts writes since our last compaction (only used for mapped indexes)
+    writes_since_compact: u32,
+    /// Bumped every time the index changes. Used to invalidate query caches.
+    generation: u64,
+    /// When this index was created
+    built: i64,
+    /// Turns note text (and queries) into index tokens
+    analyzer: Analyzer,
+    /// Our cached queries
+    cache: Mutex<QueryCache>,
 }
 
 unsafe impl Send for Search {}
@@ -57,14 +211,80 @@ unsafe impl Sync for Search {}
 impl Search {
     /// Create a new Search object
     pub fn new() -> TResult<Search> {
-        let idx = Clouseau::new()?;
-        idx.conn.execute("CREATE TABLE IF NOT EXISTS notes (id VARCHAR(64) PRIMARY KEY, space_id VARCHAR(96), board_id VARCHAR(96), has_file BOOL, created INTEGER, mod INTEGER, type VARCHAR(32), color INTEGER, url VARCHAR(256))", NO_PARAMS)?;
+        Search::init(Clouseau::new()?)
+    }
+
+    /// Create a new Search object backed by a memory-mapped file instead of
+    /// the heap. The index is rebuilt on each login, so anything already
+    /// sitting at `location` gets blown away first.
+    ///
+    /// NOTE: the index holds decrypted note data, so we remove the file when
+    /// the Search object is dropped.
+    pub fn new_mapped(location: &String, mmap_size: i64) -> TResult<Search> {
+        remove_index_files(location);
+        Search::init(Clouseau::open(location.as_str(), mmap_size)?)
+    }
+
+    /// Set up our Turtl-specific tables on a Clouseau index
+    fn init(idx: Clouseau) -> TResult<Search> {
+        idx.conn.execute("CREATE TABLE IF NOT EXISTS notes (id VARCHAR(64) PRIMARY KEY, space_id VARCHAR(96), board_id VARCHAR(96), has_file BOOL, audio BOOL, read BOOL, created INTEGER, mod INTEGER, type VARCHAR(32), color INTEGER, url VARCHAR(256))", NO_PARAMS)?;
         idx.conn.execute("CREATE TABLE IF NOT EXISTS notes_tags (id ROWID, note_id VARCHAR(64), tag VARCHAR(128))", NO_PARAMS)?;
+        idx.conn.execute("CREATE TABLE IF NOT EXISTS notes_mentions (id ROWID, note_id VARCHAR(64), user_id VARCHAR(64))", NO_PARAMS)?;
+        idx.conn.execute("CREATE TABLE IF NOT EXISTS notes_links (id ROWID, note_id VARCHAR(64), url VARCHAR(256), domain VARCHAR(256))", NO_PARAMS)?;
+        idx.conn.execute("CREATE TABLE IF NOT EXISTS notes_values (id ROWID, note_id VARCHAR(64), kind VARCHAR(16), low REAL, high REAL)", NO_PARAMS)?;
+        idx.conn.execute("CREATE TABLE IF NOT EXISTS notes_geo (id ROWID, note_id VARCHAR(64), geohash VARCHAR(12), lat REAL, lon REAL)", NO_PARAMS)?;
+        idx.conn.execute("CREATE INDEX IF NOT EXISTS notes_geo_hash ON notes_geo (geohash)", NO_PARAMS)?;
         Ok(Search {
             idx: idx,
+            writes_since_compact: 0,
+            generation: 0,
+            built: time::get_time().sec,
+            analyzer: Analyzer::default(),
+            cache: Mutex::new(QueryCache::default()),
         })
     }
 
+    /// Compact our index in a background thread (if it's file-backed). This
+    /// merges the full-text segments and truncates the WAL so the mapped file
+    /// doesn't grow forever.
+    pub fn compact(&mut self) {
+        self.writes_since_compact = 0;
+        let location = match self.idx.location() {
+            Some(x) => x.clone(),
+            None => return,
+        };
+        thread::spawn(move || {
+            match Clouseau::compact_file(location.as_str()) {
+                Ok(_) => debug!("Search.compact() -- compacted {}", location),
+                Err(e) => warn!("Search.compact() -- problem compacting index: {}", e),
+            }
+        });
+    }
+
+    /// Set the analyzer used for note text and queries. Anything already in
+    /// the index was analyzed with the old one, so you'll want to reindex.
+    pub fn set_analyzer(&mut self, analyzer: Analyzer) {
+        self.analyzer = analyzer;
+        self.generation += 1;
+    }
+
+    /// Grab the current index generation. This changes every time a note is
+    /// indexed or unindexed.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Note that we wrote to the index, invalidating our query caches and
+    /// compacting if enough writes piled up
+    fn track_write(&mut self) {
+        self.generation += 1;
+        if self.idx.location().is_none() { return; }
+        self.writes_since_compact += 1;
+        if self.writes_since_compact >= COMPACT_EVERY {
+            self.compact();
+        }
+    }
+
     /// Index a note
     pub fn index_note(&mut self, note: &Note) -> TResult<()> {
         model_getter!(get_field, "Search.index_note()");
@@ -80,30 +300,64 @@ impl Search {
         let board_id = get_field!(note, board_id, String::from(""));
         let board_id = if board_id == "" { None } else { Some(board_id) };
         let has_file = note.has_file;
+        let audio = note.file.as_ref().map(|x| x.is_audio()).unwrap_or(false);
+        let read = note.read.unwrap_or(false);
         let mod_ = note.mod_;
         let type_ = get_field!(note, type_, String::from("text"));
         let color = get_field!(note, color, 0);
         self.idx.conn.execute(
-            "INSERT INTO notes (id, space_id, board_id, has_file, created, mod, type, color, url) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
-            params![id, space_id, board_id, has_file, id_mod, mod_, type_, color, note.url]
+            "INSERT INTO notes (id, space_id, board_id, has_file, audio, read, created, mod, type, color, url) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            params![id, space_id, board_id, has_file, audio, read, id_mod, mod_, type_, color, note.url]
         )?;
 
         let tags = get_field!(note, tags, Vec::new());
         for tag in tags {
             self.idx.conn.execute("INSERT INTO notes_tags (note_id, tag) VALUES (?, ?)", &[&id, &tag])?;
         }
+        let mentions = get_field!(note, mentions, Vec::new());
+        for user_id in mentions.into_iter().filter_map(|m| m.user_id) {
+            self.idx.conn.execute("INSERT INTO notes_mentions (note_id, user_id) VALUES (?, ?)", &[&id, &user_id])?;
+        }
         let note_body = [
             get_field!(note, title, String::from("")),
             get_field!(note, text, String::from("")),
             get_field!(note, tags, Vec::new()).as_slice().join(" "),
             get_field!(note, url, String::from("")),
+            get_field!(note, article, String::from("")),
             {
                 let fakefile = File::new();
                 let file = get_field!(note, file, &fakefile);
                 get_field!(file, name, String::from(""))
             },
         ].join(" ");
-        self.idx.index(&id, &note_body)?;
+        for link in note.links() {
+            if let Some((url, domain)) = note::normalize_url(&link) {
+                self.idx.conn.execute("INSERT INTO notes_links (note_id, url, domain) VALUES (?, ?, ?)", &[&id, &url, &domain])?;
+            }
+        }
+        let typed_body = format!("{} {}", get_field!(note, title, String::from("")), get_field!(note, text, String::from("")));
+        for val in self.analyzer.typed_values(&typed_body) {
+            let (low, high) = val.range();
+            self.idx.conn.execute(
+                "INSERT INTO notes_values (note_id, kind, low, high) VALUES (?, ?, ?, ?)",
+                params![id, val.kind(), low, high]
+            )?;
+        }
+        if let Some((due, _)) = note.due.as_ref().and_then(|x| analyzer::parse_date(x)) {
+            self.idx.conn.execute(
+                "INSERT INTO notes_values (note_id, kind, low, high) VALUES (?, 'due', ?, ?)",
+                params![id, due, due]
+            )?;
+        }
+        if let Some(ref loc) = note.location {
+            let hash = geo::encode(loc.lat, loc.lon, geo::MAX_PRECISION);
+            self.idx.conn.execute(
+                "INSERT INTO notes_geo (note_id, geohash, lat, lon) VALUES (?, ?, ?, ?)",
+                params![id, hash, loc.lat, loc.lon]
+            )?;
+        }
+        self.idx.index(&id, &self.analyzer.analyze(&note_body))?;
+        self.track_write();
         Ok(())
     }
 
@@ -113,7 +367,12 @@ impl Search {
         let id = get_field!(note, id);
         self.idx.conn.execute("DELETE FROM notes WHERE id = ?", &[&id])?;
         self.idx.conn.execute("DELETE FROM notes_tags where note_id = ?", &[&id])?;
+        self.idx.conn.execute("DELETE FROM notes_mentions where note_id = ?", &[&id])?;
+        self.idx.conn.execute("DELETE FROM notes_links where note_id = ?", &[&id])?;
+        self.idx.conn.execute("DELETE FROM notes_values where note_id = ?", &[&id])?;
+        self.idx.conn.execute("DELETE FROM notes_geo where note_id = ?", &[&id])?;
         self.idx.unindex(&id)?;
+        self.track_write();
         Ok(())
     }
 
@@ -123,6 +382,275 @@ impl Search {
         self.index_note(note)
     }
 
+    /// Remove every note in a space from the index, returning the ids removed
+    pub fn unindex_space(&mut self, space_id: &String) -> TResult<Vec<String>> {
+        let ids = {
+            let mut qry = self.idx.conn.prepare("SELECT id FROM notes WHERE space_id = ?")?;
+            let rows = qry.query_map(&[space_id], |row| row.get(0))?;
+            let mut ids: Vec<String> = Vec::new();
+            for id in rows { ids.push(id?); }
+            ids
+        };
+        for id in &ids {
+            self.idx.conn.execute("DELETE FROM notes WHERE id = ?", &[id])?;
+            self.idx.conn.execute("DELETE FROM notes_tags where note_id = ?", &[id])?;
+            self.idx.conn.execute("DELETE FROM notes_mentions where note_id = ?", &[id])?;
+            self.idx.conn.execute("DELETE FROM notes_links where note_id = ?", &[id])?;
+            self.idx.conn.execute("DELETE FROM notes_values where note_id = ?", &[id])?;
+            self.idx.conn.execute("DELETE FROM notes_geo where note_id = ?", &[id])?;
+            self.idx.unindex(id)?;
+        }
+        self.track_write();
+        Ok(ids)
+    }
+
+    /// Get some stats on what's in the index
+    pub fn stats(&self) -> TResult<IndexStats> {
+        let count = |qry: &str| -> TResult<i64> {
+            Ok(self.idx.conn.query_row(qry, NO_PARAMS, |row| row.get(0))?)
+        };
+        let mut space_counts = HashMap::new();
+        {
+            let mut qry = self.idx.conn.prepare("SELECT space_id, COUNT(*) FROM notes GROUP BY space_id")?;
+            let rows = qry.query_map(NO_PARAMS, |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)))?;
+            for row in rows {
+                let (space_id, count) = row?;
+                space_counts.insert(space_id, count);
+            }
+        }
+        Ok(IndexStats {
+            notes: count("SELECT COUNT(*) FROM notes")?,
+            terms: self.idx.term_count()?,
+            tags: count("SELECT COUNT(DISTINCT tag) FROM notes_tags")?,
+            spaces: space_counts.len() as i64,
+            space_counts: space_counts,
+            generation: self.generation,
+            storage: String::from(if self.idx.location().is_some() { "mmap" } else { "memory" }),
+            disk_size: self.idx.disk_size(),
+            built: self.built,
+        })
+    }
+
+    /// Debug helper that runs each part of a query against a single note and
+    /// reports which parts passed. Mirrors the logic in `find_uncached()`.
+    pub fn explain(&self, note_id: &String, query: &Query) -> TResult<Explanation> {
+        let mut explanation = Explanation::default();
+        explanation.note_id = note_id.clone();
+        let row = {
+            let mut qry = self.idx.conn.prepare("SELECT space_id, board_id, has_file, type, color, url, audio, read FROM notes WHERE id = ?")?;
+            let mut rows = qry.query_map(&[note_id], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, Option<String>>(1)?,
+                    row.get::<_, bool>(2)?,
+                    row.get::<_, Option<String>>(3)?,
+                    row.get::<_, Option<i32>>(4)?,
+                    row.get::<_, Option<String>>(5)?,
+                    row.get::<_, bool>(6)?,
+                    row.get::<_, bool>(7)?,
+                ))
+            })?;
+            match rows.next() {
+                Some(x) => x?,
+                None => return Ok(explanation),
+            }
+        };
+        explanation.indexed = true;
+        let (space_id, board_id, has_file, type_, color, url, audio, read) = row;
+        let tags = {
+            let mut qry = self.idx.conn.prepare("SELECT tag FROM notes_tags WHERE note_id = ?")?;
+            let rows = qry.query_map(&[note_id], |row| row.get::<_, String>(0))?;
+            let mut tags = Vec::new();
+            for tag in rows { tags.push(tag?); }
+            tags
+        };
+        let mut checks = Vec::new();
+        {
+            let mut check = |field: &str, passed: bool, detail: String| {
+                checks.push(ExplainCheck { field: String::from(field), passed: passed, detail: detail });
+            };
+            check("space_id", space_id == query.space_id, format!("note is in space {}", space_id));
+            if let Some(ref text) = query.text {
+                let passed = self.find_text(text)?.contains(note_id);
+                // check each term on its own so it's obvious which ones missed
+                let mut missed = Vec::new();
+                for term in text.split_whitespace() {
+                    let analyzed = self.analyzer.analyze_query(term);
+                    if analyzed == "" { continue; }
+                    match self.idx.find(&analyzed) {
+                        Ok(ids) => if !ids.contains(note_id) { missed.push(term); },
+                        // not every piece of a query is a valid query
+                        Err(_) => {}
+                    }
+                }
+                let detail = if missed.len() > 0 {
+                    format!("terms not found in note: {}", missed.join(", "))
+                } else {
+                    String::from("all terms found in note")
+                };
+                check("text", passed, detail);
+            }
+            if query.notes.len() > 0 {
+                check("notes", query.notes.contains(note_id), String::from("note must be one of the given notes"));
+            }
+            if query.boards.len() > 0 {
+                let passed = board_id.as_ref().map(|b| query.boards.contains(b)).unwrap_or(false);
+                check("boards", passed, format!("note is in board {:?}", board_id));
+            }
+            if query.tags.len() > 0 {
+                let missing = query.tags.iter().filter(|t| !tags.contains(t)).map(|x| x.clone()).collect::<Vec<_>>();
+                check("tags", missing.len() == 0, format!("missing tags: {:?}", missing));
+            }
+            if query.exclude_tags.len() > 0 {
+                let present = query.exclude_tags.iter().filter(|t| tags.contains(t)).map(|x| x.clone()).collect::<Vec<_>>();
+                check("exclude_tags", present.len() == 0, format!("excluded tags present: {:?}", present));
+            }
+            if query.mentions.len() > 0 {
+                let mentioned = {
+                    let mut qry = self.idx.conn.prepare("SELECT user_id FROM notes_mentions WHERE note_id = ?")?;
+                    let rows = qry.query_map(&[note_id], |row| row.get::<_, String>(0))?;
+                    let mut mentioned = Vec::new();
+                    for user_id in rows { mentioned.push(user_id?); }
+                    mentioned
+                };
+                let missing = query.mentions.iter().filter(|u| !mentioned.contains(u)).map(|x| x.clone()).collect::<Vec<_>>();
+                check("mentions", missing.len() == 0, format!("missing mentions: {:?}", missing));
+            }
+            if let Some(ref qtype) = query.type_ {
+                check("type", type_.as_ref() == Some(qtype), format!("note type is {:?}", type_));
+            }
+            if let Some(ref qurl) = query.url {
+                check("url", url.as_ref() == Some(qurl), format!("note url is {:?}", url));
+            }
+            if query.has_url.is_some() || query.domain.is_some() {
+                let domains = {
+                    let mut qry = self.idx.conn.prepare("SELECT domain FROM notes_links WHERE note_id = ?")?;
+                    let rows = qry.query_map(&[note_id], |row| row.get::<_, String>(0))?;
+                    let mut domains = Vec::new();
+                    for domain in rows { domains.push(domain?); }
+                    domains
+                };
+                if let Some(qhas_url) = query.has_url {
+                    check("has_url", (domains.len() > 0) == qhas_url, format!("note links to {} url(s)", domains.len()));
+                }
+                if let Some(ref qdomain) = query.domain {
+                    let qdomain = String::from(qdomain.trim().to_lowercase().trim_start_matches("www."));
+                    let passed = domains.iter().any(|d| d == &qdomain || d.ends_with(&format!(".{}", qdomain)));
+                    check("domain", passed, format!("note links to domains {:?}", domains));
+                }
+            }
+            if let Some(qfile) = query.has_file {
+                check("has_file", has_file == qfile, format!("note has_file is {}", has_file));
+            }
+            if let Some(qaudio) = query.audio {
+                check("audio", audio == qaudio, format!("note has audio: {}", audio));
+            }
+            if let Some(qread) = query.read {
+                check("read", read == qread, format!("note read is {}", read));
+            }
+            if let Some(qcolor) = query.color {
+                check("color", color == Some(qcolor), format!("note color is {:?}", color));
+            }
+            if let Some(ref amount) = query.amount {
+                let low = amount.min.unwrap_or(::std::f64::MIN);
+                let high = amount.max.unwrap_or(::std::f64::MAX);
+                let passed = self.has_value(note_id, "number", low, high)?;
+                check("amount", passed, format!("note mentions a number in {} - {}: {}", low, high, passed));
+            }
+            if let Some(ref date) = query.date {
+                let (low, high) = date.bounds()?;
+                let passed = self.has_value(note_id, "date", low as f64, high as f64)?;
+                check("date", passed, format!("note mentions a date in {} - {}: {}", low, high, passed));
+            }
+            if let Some(ref near) = query.near {
+                let located: Option<(f64, f64)> = {
+                    let mut qry = self.idx.conn.prepare("SELECT lat, lon FROM notes_geo WHERE note_id = ?")?;
+                    let mut rows = qry.query_map(&[note_id], |row| Ok((row.get(0)?, row.get(1)?)))?;
+                    match rows.next() {
+                        Some(x) => Some(x?),
+                        None => None,
+                    }
+                };
+                match located {
+                    Some((lat, lon)) => {
+                        let dist = geo::distance_km(near.lat, near.lon, lat, lon);
+                        check("near", dist <= near.km, format!("note is {:.2}km away", dist));
+                    }
+                    None => check("near", false, String::from("note has no location")),
+                }
+            }
+        }
+        explanation.matched = checks.iter().all(|c| c.passed);
+        explanation.checks = checks;
+        Ok(explanation)
+    }
+
+    /// Find notes (in any space) that link to the given URL, ignoring
+    /// differences like "www." or a trailing slash
+    pub fn find_by_url(&self, url: &String) -> TResult<Vec<String>> {
+        let normalized = match note::normalize_url(url) {
+            Some((x, _)) => x,
+            None => return TErr!(TError::BadValue(format!("bad url: {}", url))),
+        };
+        let mut qry = self.idx.conn.prepare("SELECT DISTINCT note_id FROM notes_links WHERE url = ? ORDER BY note_id DESC")?;
+        let rows = qry.query_map(&[&normalized], |row| row.get(0))?;
+        let mut ids = Vec::new();
+        for id in rows { ids.push(id?); }
+        Ok(ids)
+    }
+
+    /// Find the notes within a given distance of a point. We grab everything
+    /// under the geohash prefixes covering the circle, then check the real
+    /// distance on those.
+    fn find_near(&self, near: &GeoRange) -> TResult<Vec<String>> {
+        let prefixes = geo::covering_prefixes(near.lat, near.lon, near.km);
+        let mut geo_qry: Vec<&str> = Vec::with_capacity(prefixes.len() + 1);
+        geo_qry.push("SELECT note_id, lat, lon FROM notes_geo WHERE ");
+        let mut qry_vals: Vec<SearchVal> = Vec::with_capacity(prefixes.len());
+        for (i, prefix) in prefixes.iter().enumerate() {
+            geo_qry.push(if i == 0 { "geohash LIKE ?" } else { " OR geohash LIKE ?" });
+            qry_vals.push(SearchVal::String(format!("{}%", prefix)));
+        }
+        let final_query = geo_qry.as_slice().join("");
+        let mut prepared_qry = self.idx.conn.prepare(final_query.as_str())?;
+        let mut values: Vec<&dyn ToSql> = Vec::with_capacity(qry_vals.len());
+        for val in &qry_vals {
+            let ts: &dyn ToSql = val;
+            values.push(ts);
+        }
+        let rows = prepared_qry.query_map(values.as_slice(), |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, f64>(1)?, row.get::<_, f64>(2)?))
+        })?;
+        let mut ids = Vec::new();
+        for row in rows {
+            let (id, lat, lon) = row?;
+            if geo::distance_km(near.lat, near.lon, lat, lon) <= near.km {
+                ids.push(id);
+            }
+        }
+        Ok(ids)
+    }
+
+    /// Find notes (in any space) due within the given YYYYMMDD bounds, soonest
+    /// first
+    pub fn find_due(&self, low: i32, high: i32) -> TResult<Vec<String>> {
+        let mut qry = self.idx.conn.prepare("SELECT note_id FROM notes_values WHERE kind = 'due' AND low >= ? AND low <= ? ORDER BY low ASC, note_id ASC")?;
+        let rows = qry.query_map(params![low, high], |row| row.get(0))?;
+        let mut ids = Vec::new();
+        for id in rows { ids.push(id?); }
+        Ok(ids)
+    }
+
+    /// Whether a note mentions a number/date that overlaps the given range
+    fn has_value(&self, note_id: &String, kind: &str, low: f64, high: f64) -> TResult<bool> {
+        let count: i64 = self.idx.conn.query_row(
+            "SELECT COUNT(*) FROM notes_values WHERE note_id = ? AND kind = ? AND low <= ? AND high >= ?",
+            params![note_id, kind, high, low],
+            |row| row.get(0)
+        )?;
+        Ok(count > 0)
+    }
+
     /// Search for notes. Returns the note IDs only. Loading them from the db
     /// and decrypting are up to you...OR YOUR MOM.
     ///
@@ -132,6 +660,53 @@ impl Search {
     /// bunch of separate queries. There may be a more efficient way to do this,
     /// however since this is all in-memory anyway, it's probably fine.
     pub fn find(&self, query: &Query) -> TResult<(Vec<String>, i32)> {
+        let key = {
+            let mut keyquery = query.clone();
+            keyquery.text = query.text.as_ref().map(|x| normalize_text(x));
+            jedi::stringify(&keyquery)?
+        };
+        {
+            let mut cache_guard = lock!(self.cache);
+            cache_guard.sync_generation(self.generation);
+            if let Some(res) = cache_guard.results.get(&key) {
+                return Ok(res.clone());
+            }
+        }
+        jobs::check_cancelled()?;
+        let res = self.find_uncached(query)?;
+        let mut cache_guard = lock!(self.cache);
+        cache_guard.sync_generation(self.generation);
+        if cache_guard.results.len() >= QUERY_CACHE_SIZE {
+            cache_guard.results.clear();
+        }
+        cache_guard.results.insert(key, res.clone());
+        Ok(res)
+    }
+
+    /// Run a full-text search, using our cache if we can
+    fn find_text(&self, text: &String) -> TResult<Vec<String>> {
+        let text = normalize_text(text);
+        {
+            let mut cache_guard = lock!(self.cache);
+            cache_guard.sync_generation(self.generation);
+            if let Some(ids) = cache_guard.text.get(&text) {
+                return Ok(ids.clone());
+            }
+        }
+        let analyzed = self.analyzer.analyze_query(&text);
+        // a query of nothing but stopwords
+        let ids = if analyzed == "" { Vec::new() } else { self.idx.find(&analyzed)? };
+        let mut cache_guard = lock!(self.cache);
+        cache_guard.sync_generation(self.generation);
+        if cache_guard.text.len() >= QUERY_CACHE_SIZE {
+            cache_guard.text.clear();
+        }
+        cache_guard.text.insert(text, ids.clone());
+        Ok(ids)
+    }
+
+    /// Does the actual work for `find()`, skipping the results cache
+    fn find_uncached(&self, query: &Query) -> TResult<(Vec<String>, i32)> {
         let mut queries: Vec<String> = Vec::new();
         let mut exclude_queries: Vec<String> = Vec::new();
         let mut qry_vals: Vec<SearchVal> = Vec::new();
@@ -145,7 +720,7 @@ impl Search {
         //   SELECT id FROM notes WHERE id IN (id1, id2)
         // there's probably a much better way, but this is easiest for now
         if query.text.is_some() {
-            let ft_note_ids = self.idx.find(query.text.as_ref().expect("turtl::Search.find() -- query.text is None. This is so strange. I do not know how this could happen. But rest assured, I will make sure it DOES NOT HAPPEN AGAIN."))?;
+            let ft_note_ids = self.find_text(query.text.as_ref().expect("turtl::Search.find() -- query.text is None. This is so strange. I do not know how this could happen. But rest assured, I will make sure it DOES NOT HAPPEN AGAIN."))?;
             let mut ft_qry: Vec<&str> = Vec::with_capacity(ft_note_ids.len() + 2);
             ft_qry.push("SELECT id FROM notes WHERE id IN (");
             for id in &ft_note_ids {
@@ -206,6 +781,22 @@ impl Search {
             queries.push(tag_qry.as_slice().join(""));
         }
 
+        if query.mentions.len() > 0 {
+            let mut mention_qry: Vec<&str> = Vec::with_capacity(query.mentions.len() + 2);
+            mention_qry.push("SELECT note_id FROM notes_mentions WHERE user_id IN (");
+            for user_id in &query.mentions {
+                if user_id == &query.mentions[query.mentions.len() - 1] {
+                    mention_qry.push("?");
+                } else {
+                    mention_qry.push("?,");
+                }
+                qry_vals.push(SearchVal::String(user_id.clone()));
+            }
+            mention_qry.push(") GROUP BY note_id HAVING COUNT(DISTINCT user_id) = ?");
+            qry_vals.push(SearchVal::Int(query.mentions.len() as i32));
+            queries.push(mention_qry.as_slice().join(""));
+        }
+
         if query.exclude_tags.len() > 0 {
             let mut excluded_tag_qry: Vec<&str> = Vec::with_capacity(query.exclude_tags.len() + 2);
             excluded_tag_qry.push("SELECT note_id FROM notes_tags WHERE tag IN (");
@@ -231,16 +822,69 @@ impl Search {
             qry_vals.push(SearchVal::String(query.url.as_ref().expect("turtl::Search.find() -- query.url is None").clone()));
         }
 
+        match query.has_url {
+            Some(true) => queries.push(String::from("SELECT note_id FROM notes_links")),
+            Some(false) => exclude_queries.push(String::from("SELECT note_id FROM notes_links")),
+            None => {}
+        }
+
+        if let Some(ref domain) = query.domain {
+            let domain = String::from(domain.trim().to_lowercase().trim_start_matches("www."));
+            queries.push(String::from("SELECT note_id FROM notes_links WHERE domain = ? OR domain LIKE ?"));
+            qry_vals.push(SearchVal::String(domain.clone()));
+            qry_vals.push(SearchVal::String(format!("%.{}", domain)));
+        }
+
         if query.has_file.is_some() {
             queries.push(String::from("SELECT id FROM notes WHERE has_file = ?"));
             qry_vals.push(SearchVal::Bool(query.has_file.as_ref().expect("turtl::Search.find() -- query.has_file is None").clone()));
         }
 
+        if let Some(audio) = query.audio {
+            queries.push(String::from("SELECT id FROM notes WHERE audio = ?"));
+            qry_vals.push(SearchVal::Bool(audio));
+        }
+
+        if let Some(read) = query.read {
+            queries.push(String::from("SELECT id FROM notes WHERE read = ?"));
+            qry_vals.push(SearchVal::Bool(read));
+        }
+
         if query.color.is_some() {
             queries.push(String::from("SELECT id FROM notes WHERE color = ?"));
             qry_vals.push(SearchVal::Int(query.color.as_ref().expect("turtl::Search.find() -- query.color is None").clone()));
         }
 
+        if let Some(ref amount) = query.amount {
+            queries.push(String::from("SELECT note_id FROM notes_values WHERE kind = 'number' AND low <= ? AND high >= ?"));
+            qry_vals.push(SearchVal::Float(amount.max.unwrap_or(::std::f64::MAX)));
+            qry_vals.push(SearchVal::Float(amount.min.unwrap_or(::std::f64::MIN)));
+        }
+
+        if let Some(ref date) = query.date {
+            let (low, high) = date.bounds()?;
+            queries.push(String::from("SELECT note_id FROM notes_values WHERE kind = 'date' AND low <= ? AND high >= ?"));
+            qry_vals.push(SearchVal::Int(high));
+            qry_vals.push(SearchVal::Int(low));
+        }
+
+        if let Some(ref near) = query.near {
+            // sqlite can't do trig, so we find these ourselves and pass in ids
+            let near_ids = self.find_near(near)?;
+            if near_ids.len() == 0 {
+                queries.push(String::from("SELECT id FROM notes WHERE 0"));
+            } else {
+                let mut near_qry: Vec<&str> = Vec::with_capacity(near_ids.len() + 2);
+                near_qry.push("SELECT id FROM notes WHERE id IN (");
+                for (i, note_id) in near_ids.iter().enumerate() {
+                    near_qry.push(if i == 0 { "?" } else { ",?" });
+                    qry_vals.push(SearchVal::String(note_id.clone()));
+                }
+                near_qry.push(")");
+                queries.push(near_qry.as_slice().join(""));
+            }
+        }
+
         let filter_query = if queries.len() > 0 && exclude_queries.len() > 0 {
             let include = queries.as_slice().join(" intersect ");
             let exclude = exclude_queries.as_slice().join(" union ");
@@ -337,19 +981,134 @@ impl Search {
 
 impl Drop for Search {
     fn drop(&mut self) {
+        let location = self.idx.location().map(|x| x.clone());
         match self.idx.close() {
             Ok(_) => {},
             Err(e) => {
                 warn!("Search.drop() -- problem closing search index, oh well... {}", e);
             }
         }
+        if let Some(location) = location {
+            remove_index_files(&location);
+        }
+    }
+}
+
+/// Remove a mapped index and its WAL/shm files, if they exist
+fn remove_index_files(location: &String) {
+    for ext in &["", "-wal", "-shm"] {
+        let path = format!("{}{}", location, ext);
+        if fs::metadata(&path).is_err() { continue; }
+        match util::secure_delete(&path) {
+            Ok(_) => {},
+            Err(e) => warn!("search::remove_index_files() -- problem removing {}: {}", path, e),
+        }
     }
 }
 
+/// Registers our search commands
+pub fn register(reg: &mut Registry) {
+    reg.add("profile:find-by-url", |turtl, args| {
+        let url: String = args.get(2)?;
+        let note_ids = {
+            let search_guard = lock!(turtl.search);
+            match search_guard.as_ref() {
+                Some(search) => search.find_by_url(&url)?,
+                None => return TErr!(TError::MissingField(format!("turtl is missing `search` object"))),
+            }
+        };
+        let notes: Vec<Note> = turtl.load_notes(&note_ids)?;
+        Ok(jedi::to_val(&notes)?)
+    });
+    reg.add("profile:find-notes", |turtl, args| {
+        let qry: Query = match args.get(2) {
+            Ok(x) => x,
+            Err(e) => {
+                return TErr!(TError::BadValue(format!("error deserializing search query: {}", e)));
+            }
+        };
+        let search_guard = lock!(turtl.search);
+        if search_guard.is_none() {
+            return TErr!(TError::MissingField(format!("turtl is missing `search` object")));
+        }
+        let search = search_guard.as_ref().expect("turtl::search::register() -- profile:find-notes -- search_guard is none");
+        let (note_ids, total) = search.find(&qry)?;
+        let notes: Vec<Note> = turtl.load_notes(&note_ids)?;
+        let tags: Vec<(String, i32)> = search.find_tags(&qry)?;
+        let comment_counts = Comment::counts(turtl, &note_ids)?;
+        Ok(json!({
+            "notes": notes,
+            "tags": tags,
+            "total": total,
+            "comment_counts": comment_counts,
+        }))
+    });
+    reg.add("search:stats", |turtl, _args| {
+        let search_guard = lock!(turtl.search);
+        let search = match search_guard.as_ref() {
+            Some(x) => x,
+            None => return TErr!(TError::MissingField(format!("turtl is missing `search` object"))),
+        };
+        Ok(jedi::to_val(&search.stats()?)?)
+    });
+    reg.add("search:explain", |turtl, args| {
+        let note_id: String = args.get(2)?;
+        let qry: Query = match args.get(3) {
+            Ok(x) => x,
+            Err(e) => {
+                return TErr!(TError::BadValue(format!("error deserializing search query: {}", e)));
+            }
+        };
+        let search_guard = lock!(turtl.search);
+        let search = match search_guard.as_ref() {
+            Some(x) => x,
+            None => return TErr!(TError::MissingField(format!("turtl is missing `search` object"))),
+        };
+        Ok(jedi::to_val(&search.explain(&note_id, &qry)?)?)
+    });
+    reg.add("search:get-language", |turtl, _args| {
+        let config = with_db!{ db, turtl.db, analyzer::load_config(db)? };
+        Ok(jedi::to_val(&config)?)
+    });
+    reg.add("search:set-language", |turtl, args| {
+        let config: AnalyzerConfig = args.get(2)?;
+        with_db!{ db, turtl.db, analyzer::save_config(db, &config)? };
+        // rebuild the index once we've responded
+        messaging::app_event("search:reindex", &())?;
+        Ok(json!({}))
+    });
+    reg.add("profile:reindex", |turtl, args| {
+        let space_id: Option<String> = args.get_opt(2);
+        let stats = turtl.reindex(space_id.as_ref())?;
+        Ok(jedi::to_val(&stats)?)
+    });
+    reg.add("profile:find-tags", |turtl, args| {
+        let qry: Query = match args.get(2) {
+            Ok(x) => x,
+            Err(e) => {
+                return TErr!(TError::BadValue(format!("error deserializing search query: {}", e)));
+            }
+        };
+        let search_guard = lock!(turtl.search);
+        if search_guard.is_none() {
+            return TErr!(TError::MissingField(format!("turtl is missing `search` object")));
+        }
+        let search = search_guard.as_ref().expect("turtl::search::register() -- profile:find-tags -- search_guard is none");
+        let mut tags: Vec<(String, i32)> = search.find_tags(&qry)?;
+        let order = ordering::order_for("profile:find-tags", args.get_in_opt(&["3", "sort"]), Order::Count);
+        ordering::sort_tags(&mut tags, order);
+        Ok(json!({
+            "tags": tags,
+        }))
+    });
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use ::std::env;
+    use ::crypto;
     use ::jedi;
     use ::models::note::Note;
 
@@ -359,6 +1118,175 @@ mod tests {
         Search::new().unwrap();
     }
 
+    #[test]
+    fn caches_queries_until_index_changes() {
+        let mut search = Search::new().unwrap();
+        let note1: Note = jedi::parse(&String::from(r#"{"id":"1111","space_id":"4455","user_id":69,"type":"text","title":"happy birthday","tags":[]}"#)).unwrap();
+        let note2: Note = jedi::parse(&String::from(r#"{"id":"2222","space_id":"4455","user_id":69,"type":"text","title":"birthday cake","tags":[]}"#)).unwrap();
+        search.index_note(&note1).unwrap();
+        let qry: Query = jedi::parse(&String::from(r#"{"space_id":"4455","text":"birthday"}"#)).unwrap();
+        let qry_spaces: Query = jedi::parse(&String::from(r#"{"space_id":"4455","text":"  birthday "}"#)).unwrap();
+        let gen = search.generation();
+        assert_eq!(search.find(&qry).unwrap().0, vec![String::from("1111")]);
+        assert_eq!(search.find(&qry_spaces).unwrap().0, vec![String::from("1111")]);
+        assert_eq!(lock!(search.cache).results.len(), 1);
+        search.index_note(&note2).unwrap();
+        assert!(search.generation() > gen);
+        assert_eq!(search.find(&qry).unwrap().0, vec![String::from("2222"), String::from("1111")]);
+        search.unindex_note(&note2).unwrap();
+        assert_eq!(search.find(&qry_spaces).unwrap().0, vec![String::from("1111")]);
+    }
+
+    #[test]
+    fn unindexes_spaces() {
+        let mut search = Search::new().unwrap();
+        let note1: Note = jedi::parse(&String::from(r#"{"id":"1111","space_id":"4455","user_id":69,"type":"text","title":"happy birthday","tags":["party"]}"#)).unwrap();
+        let note2: Note = jedi::parse(&String::from(r#"{"id":"2222","space_id":"6969","user_id":69,"type":"text","title":"birthday cake","tags":["food","party"]}"#)).unwrap();
+        search.index_note(&note1).unwrap();
+        search.index_note(&note2).unwrap();
+        let stats = search.stats().unwrap();
+        assert_eq!((stats.notes, stats.tags, stats.spaces), (2, 2, 2));
+        assert_eq!(search.unindex_space(&String::from("4455")).unwrap(), vec![String::from("1111")]);
+        let stats = search.stats().unwrap();
+        assert_eq!((stats.notes, stats.tags, stats.spaces), (1, 2, 1));
+        assert_eq!(stats.space_counts.get("6969"), Some(&1));
+        assert_eq!(stats.terms, 4);
+        let qry: Query = jedi::parse(&String::from(r#"{"space_id":"4455","text":"birthday"}"#)).unwrap();
+        assert_eq!(search.find(&qry).unwrap().0.len(), 0);
+    }
+
+    #[test]
+    fn explains_matches() {
+        let mut search = Search::new().unwrap();
+        let note: Note = jedi::parse(&String::from(r#"{"id":"1111","space_id":"4455","user_id":69,"type":"text","title":"happy birthday","tags":["party"]}"#)).unwrap();
+        search.index_note(&note).unwrap();
+        let note_id = String::from("1111");
+        let qry: Query = jedi::parse(&String::from(r#"{"space_id":"4455","text":"birthday","tags":["party"]}"#)).unwrap();
+        let explanation = search.explain(&note_id, &qry).unwrap();
+        assert!(explanation.indexed);
+        assert!(explanation.matched);
+        assert_eq!(explanation.checks.len(), 3);
+
+        let qry: Query = jedi::parse(&String::from(r#"{"space_id":"4455","text":"birthday cake","tags":["party","food"]}"#)).unwrap();
+        let explanation = search.explain(&note_id, &qry).unwrap();
+        assert!(!explanation.matched);
+        let failed = explanation.checks.iter().filter(|c| !c.passed).map(|c| c.field.clone()).collect::<Vec<_>>();
+        assert_eq!(failed, vec!["text", "tags"]);
+        assert!(explanation.checks[1].detail.contains("cake"));
+
+        let explanation = search.explain(&String::from("2222"), &qry).unwrap();
+        assert!(!explanation.indexed);
+        assert!(!explanation.matched);
+    }
+
+    #[test]
+    fn filters_mentions() {
+        let mut search = Search::new().unwrap();
+        let note1: Note = jedi::parse(&String::from(r#"{"id":"1111","space_id":"4455","user_id":69,"type":"text","text":"@andrew @lisa","mentions":[{"handle":"andrew","user_id":"51"},{"handle":"lisa","user_id":"52"}]}"#)).unwrap();
+        let note2: Note = jedi::parse(&String::from(r#"{"id":"2222","space_id":"4455","user_id":69,"type":"text","text":"@andrew @bob","mentions":[{"handle":"andrew","user_id":"51"},{"handle":"bob"}]}"#)).unwrap();
+        search.index_note(&note1).unwrap();
+        search.index_note(&note2).unwrap();
+        let qry: Query = jedi::parse(&String::from(r#"{"space_id":"4455","mentions":["51"]}"#)).unwrap();
+        assert_eq!(search.find(&qry).unwrap().0, vec!["2222", "1111"]);
+        let qry: Query = jedi::parse(&String::from(r#"{"space_id":"4455","mentions":["51","52"]}"#)).unwrap();
+        assert_eq!(search.find(&qry).unwrap().0, vec!["1111"]);
+        search.unindex_note(&note1).unwrap();
+        assert_eq!(search.find(&qry).unwrap().0.len(), 0);
+    }
+
+    #[test]
+    fn indexes_links() {
+        let mut search = Search::new().unwrap();
+        let note1: Note = jedi::parse(&String::from(r#"{"id":"1111","space_id":"4455","user_id":69,"type":"link","url":"https://www.turtlapp.com/docs/"}"#)).unwrap();
+        let note2: Note = jedi::parse(&String::from(r#"{"id":"2222","space_id":"6969","user_id":69,"type":"text","text":"read https://turtlapp.com/docs and https://blog.turtlapp.com/a"}"#)).unwrap();
+        let note3: Note = jedi::parse(&String::from(r#"{"id":"3333","space_id":"4455","user_id":69,"type":"text","text":"no links here"}"#)).unwrap();
+        search.index_note(&note1).unwrap();
+        search.index_note(&note2).unwrap();
+        search.index_note(&note3).unwrap();
+        assert_eq!(search.find_by_url(&String::from("http://turtlapp.com/docs#intro")).unwrap(), vec!["2222", "1111"]);
+        assert!(search.find_by_url(&String::from("nope")).is_err());
+        let qry: Query = jedi::parse(&String::from(r#"{"space_id":"4455","has_url":false}"#)).unwrap();
+        assert_eq!(search.find(&qry).unwrap().0, vec!["3333"]);
+        let qry: Query = jedi::parse(&String::from(r#"{"space_id":"6969","domain":"turtlapp.com"}"#)).unwrap();
+        assert_eq!(search.find(&qry).unwrap().0, vec!["2222"]);
+        let qry: Query = jedi::parse(&String::from(r#"{"space_id":"4455","domain":"blog.turtlapp.com"}"#)).unwrap();
+        assert_eq!(search.find(&qry).unwrap().0.len(), 0);
+    }
+
+    #[test]
+    fn filters_numbers_and_dates() {
+        let mut search = Search::new().unwrap();
+        let note1: Note = jedi::parse(&String::from(r#"{"id":"1111","space_id":"4455","user_id":69,"type":"text","title":"rent","text":"paid $1,450.00 on 2024-03-01"}"#)).unwrap();
+        let note2: Note = jedi::parse(&String::from(r#"{"id":"2222","space_id":"4455","user_id":69,"type":"text","title":"groceries","text":"spent 82.17 on 2024-04-12"}"#)).unwrap();
+        search.index_note(&note1).unwrap();
+        search.index_note(&note2).unwrap();
+        let find = |json: &str| -> Vec<String> {
+            let qry: Query = jedi::parse(&json.replacen("{", r#"{"space_id":"4455","#, 1)).unwrap();
+            search.find(&qry).unwrap().0
+        };
+        assert_eq!(find(r#"{"text":"2024-03"}"#), vec!["1111"]);
+        assert_eq!(find(r#"{"text":"2024"}"#), vec!["2222", "1111"]);
+        assert_eq!(find(r#"{"text":"1450"}"#), vec!["1111"]);
+        assert_eq!(find(r#"{"amount":{"min":50,"max":100}}"#), vec!["2222"]);
+        assert_eq!(find(r#"{"amount":{"min":1000}}"#), vec!["1111"]);
+        assert_eq!(find(r#"{"date":{"from":"2024-04"}}"#), vec!["2222"]);
+        assert_eq!(find(r#"{"date":{"from":"2024-02-15","to":"2024-03"}}"#), vec!["1111"]);
+        assert_eq!(find(r#"{"amount":{"max":100},"date":{"to":"2024-03-31"}}"#).len(), 0);
+    }
+
+    #[test]
+    fn filters_by_location() {
+        let mut search = Search::new().unwrap();
+        // two cafes in portland, and one in seattle
+        let note1: Note = jedi::parse(&String::from(r#"{"id":"1111","space_id":"4455","user_id":69,"type":"text","text":"coffee","location":{"lat":45.5231,"lon":-122.6765}}"#)).unwrap();
+        let note2: Note = jedi::parse(&String::from(r#"{"id":"2222","space_id":"4455","user_id":69,"type":"text","text":"more coffee","location":{"lat":45.5122,"lon":-122.6587}}"#)).unwrap();
+        let note3: Note = jedi::parse(&String::from(r#"{"id":"3333","space_id":"4455","user_id":69,"type":"text","text":"rain","location":{"lat":47.6062,"lon":-122.3321}}"#)).unwrap();
+        let note4: Note = jedi::parse(&String::from(r#"{"id":"4444","space_id":"4455","user_id":69,"type":"text","text":"nowhere"}"#)).unwrap();
+        for note in &[&note1, &note2, &note3, &note4] { search.index_note(note).unwrap(); }
+        let find = |json: &str| -> Vec<String> {
+            let qry: Query = jedi::parse(&json.replacen("{", r#"{"space_id":"4455","#, 1)).unwrap();
+            search.find(&qry).unwrap().0
+        };
+        assert_eq!(find(r#"{"near":{"lat":45.5231,"lon":-122.6765,"km":0.5}}"#), vec!["1111"]);
+        assert_eq!(find(r#"{"near":{"lat":45.5231,"lon":-122.6765,"km":3}}"#), vec!["2222", "1111"]);
+        assert_eq!(find(r#"{"near":{"lat":45.5231,"lon":-122.6765,"km":300}}"#), vec!["3333", "2222", "1111"]);
+        assert_eq!(find(r#"{"near":{"lat":0,"lon":0,"km":10}}"#).len(), 0);
+        let qry: Query = jedi::parse(&String::from(r#"{"space_id":"4455","near":{"lat":47.6,"lon":-122.33,"km":1}}"#)).unwrap();
+        assert!(search.explain(&String::from("3333"), &qry).unwrap().matched);
+        assert!(!search.explain(&String::from("4444"), &qry).unwrap().matched);
+        search.unindex_note(&note3).unwrap();
+        assert_eq!(search.find(&qry).unwrap().0.len(), 0);
+    }
+
+    #[test]
+    fn filters_read_later() {
+        let mut search = Search::new().unwrap();
+        let note1: Note = jedi::parse(&String::from(r#"{"id":"1111","space_id":"4455","user_id":69,"type":"link","url":"https://turtlapp.com/a","article":"the snapping turtle is misunderstood","read":true}"#)).unwrap();
+        let note2: Note = jedi::parse(&String::from(r#"{"id":"2222","space_id":"4455","user_id":69,"type":"link","url":"https://turtlapp.com/b","read":false}"#)).unwrap();
+        let note3: Note = jedi::parse(&String::from(r#"{"id":"3333","space_id":"4455","user_id":69,"type":"link","url":"https://turtlapp.com/c"}"#)).unwrap();
+        for note in &[&note1, &note2, &note3] { search.index_note(note).unwrap(); }
+        let find = |json: &str| -> Vec<String> {
+            let qry: Query = jedi::parse(&json.replacen("{", r#"{"space_id":"4455","#, 1)).unwrap();
+            search.find(&qry).unwrap().0
+        };
+        assert_eq!(find(r#"{"read":true}"#), vec!["1111"]);
+        assert_eq!(find(r#"{"read":false}"#), vec!["3333", "2222"]);
+        assert_eq!(find(r#"{"text":"misunderstood"}"#), vec!["1111"]);
+    }
+
+    #[test]
+    fn mapped_search_cleans_up() {
+        let location = env::temp_dir().join(format!("turtl-search-mapped-{}.idx", crypto::random_hash().unwrap()));
+        let location = String::from(location.to_string_lossy());
+        let search = Search::new_mapped(&location, 1024 * 1024).unwrap();
+        assert!(fs::metadata(&location).is_ok());
+        // the index cleans up after itself
+        drop(search);
+        for ext in &["", "-wal", "-shm"] {
+            assert!(fs::metadata(format!("{}{}", location, ext)).is_err());
+        }
+    }
+
     #[test]
     fn index_unindex_filter() {
         fn parserrr(json: &str) -> Query {
@@ -0,0 +1,179 @@
+//! Checks in on the Turtl server: is it up, what version is it running, and
+//! can we talk to it. `app:api:info` asks the server's info endpoint
+//! (`api.info.resource`) and holds onto the answer for a while, so UIs can ask
+//! as often as they like. If the server is older than `api.info.min_version`
+//! (or newer than `api.info.max_version`) we send an `api:incompatible` event
+//! so the UI can tell the user to upgrade.
+
+use ::std::cmp::Ordering;
+use ::std::sync::RwLock;
+use ::std::time::{Duration, Instant};
+use ::jedi::{self, Value};
+use ::time;
+use ::config;
+use ::error::TResult;
+use ::api::ApiReq;
+use ::messaging;
+use ::turtl::Turtl;
+use ::dispatch::registry::Registry;
+
+lazy_static! {
+    /// Our last answer, and when we got it
+    static ref CACHE: RwLock<Option<(Instant, ServerInfo)>> = RwLock::new(None);
+}
+
+/// What we know about the server
+#[derive(Serialize, Debug, Clone)]
+pub struct ServerInfo {
+    pub endpoint: String,
+    /// Whether the server answered
+    pub healthy: bool,
+    pub version: Option<String>,
+    /// Whatever else the server told us
+    pub info: Value,
+    /// None if we couldn't tell (no version, or no answer)
+    pub compatible: Option<bool>,
+    pub min_version: Option<String>,
+    pub max_version: Option<String>,
+    pub latency_ms: u64,
+    /// When we checked (unix seconds)
+    pub checked: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Turn "v0.7.2-beta" into [0, 7, 2]. Anything after the numbers (a
+/// pre-release tag, build info) is ignored.
+fn parse_version(version: &str) -> Option<Vec<u64>> {
+    let version = version.trim().trim_start_matches('v');
+    let numbers = version.split(|c| c == '-' || c == '+').next().unwrap_or("");
+    numbers.split('.')
+        .map(|x| x.parse::<u64>().ok())
+        .collect::<Option<Vec<_>>>()
+}
+
+/// Compare two versions, treating missing parts as 0 (so "1.2" == "1.2.0").
+/// None if either one doesn't parse.
+fn compare_versions(a: &str, b: &str) -> Option<Ordering> {
+    let a = parse_version(a)?;
+    let b = parse_version(b)?;
+    for i in 0..a.len().max(b.len()) {
+        let cmp = a.get(i).unwrap_or(&0).cmp(b.get(i).unwrap_or(&0));
+        if cmp != Ordering::Equal { return Some(cmp); }
+    }
+    Some(Ordering::Equal)
+}
+
+/// Whether a server version falls within the range we support. If it doesn't,
+/// says which way it's off ("too_old"/"too_new").
+fn check_version(version: &str, min: Option<&String>, max: Option<&String>) -> Option<Result<(), &'static str>> {
+    if let Some(min) = min {
+        if compare_versions(version, min)? == Ordering::Less { return Some(Err("too_old")); }
+    }
+    if let Some(max) = max {
+        if compare_versions(version, max)? == Ordering::Greater { return Some(Err("too_new")); }
+    }
+    Some(Ok(()))
+}
+
+/// Ask the server about itself
+fn fetch(turtl: &Turtl) -> ServerInfo {
+    let endpoint: String = config::get(&["api", "endpoint"]).unwrap_or(String::new());
+    let resource: String = config::get(&["api", "info", "resource"]).unwrap_or(String::from("/health"));
+    let timeout: u64 = config::get(&["api", "info", "timeout"]).unwrap_or(10);
+    let min_version: Option<String> = config::get(&["api", "info", "min_version"]).unwrap_or(None);
+    let max_version: Option<String> = config::get(&["api", "info", "max_version"]).unwrap_or(None);
+    let start = Instant::now();
+    let res = turtl.api.get(resource.as_str())
+        .and_then(|req| req.call_opt::<Value>(ApiReq::new().timeout(timeout)));
+    let elapsed = start.elapsed();
+    let mut info = ServerInfo {
+        endpoint: endpoint,
+        healthy: false,
+        version: None,
+        info: Value::Null,
+        compatible: None,
+        min_version: min_version,
+        max_version: max_version,
+        latency_ms: elapsed.as_secs() * 1000 + (elapsed.subsec_nanos() / 1000000) as u64,
+        checked: time::get_time().sec,
+        error: None,
+    };
+    match res {
+        Ok(val) => {
+            info.healthy = true;
+            info.version = jedi::get_opt(&["version"], &val);
+            info.info = val;
+        }
+        Err(e) => {
+            warn!("server_info::fetch() -- problem reaching the server: {}", e);
+            info.error = Some(format!("{}", e));
+        }
+    }
+    if let Some(ref version) = info.version {
+        match check_version(version, info.min_version.as_ref(), info.max_version.as_ref()) {
+            Some(Ok(_)) => info.compatible = Some(true),
+            Some(Err(reason)) => {
+                info.compatible = Some(false);
+                warn!("server_info::fetch() -- server version {} is {}", version, reason);
+                let event = json!({
+                    "version": version,
+                    "min_version": info.min_version,
+                    "max_version": info.max_version,
+                    "reason": reason,
+                });
+                match messaging::ui_event("api:incompatible", &event) {
+                    Ok(_) => {}
+                    Err(e) => error!("server_info::fetch() -- problem sending api:incompatible: {}", e),
+                }
+            }
+            None => warn!("server_info::fetch() -- can't make sense of server version {}", version),
+        }
+    }
+    info
+}
+
+/// Grab what we know about the server, asking it again if our last answer is
+/// too old (or `force` is set)
+pub fn get(turtl: &Turtl, force: bool) -> ServerInfo {
+    let ttl: u64 = config::get(&["api", "info", "cache_seconds"]).unwrap_or(300);
+    if !force {
+        let cache_guard = lockr!(*CACHE);
+        if let Some((ref when, ref info)) = *cache_guard {
+            if when.elapsed() < Duration::new(ttl, 0) { return info.clone(); }
+        }
+    }
+    let info = fetch(turtl);
+    *lockw!(*CACHE) = Some((Instant::now(), info.clone()));
+    info
+}
+
+/// Registers our server info command
+pub fn register(reg: &mut Registry) {
+    reg.add("app:api:info", |turtl, args| {
+        let force: bool = args.get_in_opt(&["2", "force"]).unwrap_or(false);
+        Ok(jedi::to_val(&get(turtl, force))?)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compares_versions() {
+        assert_eq!(parse_version("v0.7.2-beta1"), Some(vec![0, 7, 2]));
+        assert_eq!(parse_version("0.7.x"), None);
+        assert_eq!(compare_versions("1.2", "1.2.0"), Some(Ordering::Equal));
+        assert_eq!(compare_versions("0.10.0", "0.9.3"), Some(Ordering::Greater));
+        assert_eq!(compare_versions("garbage", "0.9.3"), None);
+
+        let min = String::from("0.7.0");
+        let max = String::from("1.0.0");
+        assert_eq!(check_version("0.7.1", Some(&min), Some(&max)), Some(Ok(())));
+        assert_eq!(check_version("0.6.9", Some(&min), Some(&max)), Some(Err("too_old")));
+        assert_eq!(check_version("1.1.0", Some(&min), Some(&max)), Some(Err("too_new")));
+        assert_eq!(check_version("1.1.0", Some(&min), None), Some(Ok(())));
+        assert_eq!(check_version("who knows", Some(&min), None), None);
+    }
+}
@@ -0,0 +1,259 @@
+//! Spell checking for note bodies. The core doesn't ship dictionaries: the
+//! embedding app hands us a spellchecker (usually the platform's, see
+//! `turtlc_set_spellchecker()`), and we do the rest. We split the note into
+//! words (skipping URLs, emails, #tags/@mentions, code, and anything with
+//! digits in it), drop words in the user's personal dictionary, and ask the
+//! spellchecker about whatever's left.
+//!
+//! The personal dictionary lives in the user's settings, so it syncs to every
+//! device.
+
+use ::std::collections::{HashMap, HashSet};
+use ::std::sync::RwLock;
+use ::jedi::{self, Value};
+use ::error::{TResult, TError};
+use ::turtl::Turtl;
+use ::models::note::Note;
+use ::dispatch::registry::Registry;
+
+/// Checks one word, returning None if it's spelled right, or suggestions for
+/// fixing it if not
+pub type SpellChecker = Box<Fn(&str) -> TResult<Option<Vec<String>>> + Send + Sync>;
+
+/// The user setting our personal dictionary lives in
+const SETTINGS_KEY: &'static str = "spellcheck_dictionary";
+
+lazy_static! {
+    /// Set by the embedding app if it can check spelling
+    static ref SPELLCHECKER: RwLock<Option<SpellChecker>> = RwLock::new(None);
+}
+
+/// Set (or unset) the function we use to check words
+pub fn set_spellchecker(checker: Option<SpellChecker>) {
+    let mut guard = lockw!(*SPELLCHECKER);
+    *guard = checker;
+}
+
+/// A word, by character (not byte) offsets into the text it came from
+#[derive(Debug, PartialEq)]
+struct Word<'a> {
+    text: &'a str,
+    start: usize,
+    end: usize,
+}
+
+/// A word the spellchecker didn't like
+#[derive(Serialize, Debug)]
+pub struct Misspelling {
+    /// "title" or "text"
+    pub field: &'static str,
+    /// Character offsets of the word in the field
+    pub start: usize,
+    pub end: usize,
+    pub word: String,
+    pub suggestions: Vec<String>,
+}
+
+/// Whether a whole (whitespace-separated) token is something other than prose
+fn skip_token(token: &str) -> bool {
+    token.contains("://") ||
+        token.starts_with("www.") ||
+        token.contains('@') ||
+        token.starts_with('#') ||
+        token.chars().any(|c| c.is_numeric())
+}
+
+/// Pull the words we want checked out of some text
+fn words<'a>(text: &'a str) -> Vec<Word<'a>> {
+    let chars = text.char_indices().collect::<Vec<_>>();
+    let byte_at = |idx: usize| if idx < chars.len() { chars[idx].0 } else { text.len() };
+    let is_apostrophe = |c: char| c == '\'' || c == '’';
+    let mut words = Vec::new();
+    let mut in_code = false;
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i].1.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        let token_start = i;
+        while i < chars.len() && !chars[i].1.is_whitespace() { i += 1; }
+        let token = &text[byte_at(token_start)..byte_at(i)];
+        // `inline code` and ``` fences
+        if token.contains('`') {
+            if token.matches('`').count() % 2 == 1 { in_code = !in_code; }
+            continue;
+        }
+        if in_code || skip_token(token) { continue; }
+        let mut j = token_start;
+        while j < i {
+            if !chars[j].1.is_alphabetic() {
+                j += 1;
+                continue;
+            }
+            let start = j;
+            while j < i {
+                let c = chars[j].1;
+                let inner_apostrophe = is_apostrophe(c) && j > start && j + 1 < i && chars[j + 1].1.is_alphabetic();
+                if !c.is_alphabetic() && !inner_apostrophe { break; }
+                j += 1;
+            }
+            if j - start > 1 {
+                words.push(Word { text: &text[byte_at(start)..byte_at(j)], start: start, end: j });
+            }
+        }
+    }
+    words
+}
+
+/// Check the words in one field of a note. `cache` keeps us from asking about
+/// the same word twice.
+fn check_field<F>(field: &'static str, text: &str, dictionary: &HashSet<String>, check: &F, cache: &mut HashMap<String, Option<Vec<String>>>) -> TResult<Vec<Misspelling>>
+    where F: Fn(&str) -> TResult<Option<Vec<String>>>
+{
+    let mut misspelled = Vec::new();
+    for word in words(text) {
+        if dictionary.contains(&word.text.to_lowercase()) { continue; }
+        if !cache.contains_key(word.text) {
+            let res = check(word.text)?;
+            cache.insert(String::from(word.text), res);
+        }
+        if let Some(&Some(ref suggestions)) = cache.get(word.text) {
+            misspelled.push(Misspelling {
+                field: field,
+                start: word.start,
+                end: word.end,
+                word: String::from(word.text),
+                suggestions: suggestions.clone(),
+            });
+        }
+    }
+    Ok(misspelled)
+}
+
+/// Grab the user's personal dictionary
+pub fn dictionary(turtl: &Turtl) -> TResult<Vec<String>> {
+    let user_guard = lockr!(turtl.user);
+    let val = user_guard.settings.as_ref().and_then(|s| s.get(SETTINGS_KEY));
+    match val {
+        Some(&Value::Null) | None => Ok(Vec::new()),
+        Some(x) => Ok(jedi::from_val(x.clone())?),
+    }
+}
+
+/// Save the user's personal dictionary (sorted, without duplicates)
+fn save_dictionary(turtl: &Turtl, mut words: Vec<String>) -> TResult<Vec<String>> {
+    words.sort_by_key(|x| x.to_lowercase());
+    words.dedup_by_key(|x| x.to_lowercase());
+    let mut user = {
+        let user_guard = lockr!(turtl.user);
+        user_guard.clone()?
+    };
+    user.set_setting(turtl, SETTINGS_KEY, &words)?;
+    Ok(words)
+}
+
+/// Add a word to the user's personal dictionary
+pub fn add_word(turtl: &Turtl, word: &String) -> TResult<Vec<String>> {
+    let word = word.trim();
+    if word == "" || word.contains(char::is_whitespace) {
+        return TErr!(TError::BadValue(format!("can't add {:?} to the dictionary (one word at a time)", word)));
+    }
+    let mut words = dictionary(turtl)?;
+    words.push(String::from(word));
+    save_dictionary(turtl, words)
+}
+
+/// Remove a word from the user's personal dictionary
+pub fn remove_word(turtl: &Turtl, word: &String) -> TResult<Vec<String>> {
+    let lower = word.trim().to_lowercase();
+    let mut words = dictionary(turtl)?;
+    words.retain(|x| x.to_lowercase() != lower);
+    save_dictionary(turtl, words)
+}
+
+/// Find the misspelled words in a note's title and body
+pub fn check_note(turtl: &Turtl, note: &Note) -> TResult<Vec<Misspelling>> {
+    let guard = lockr!(*SPELLCHECKER);
+    let checker = match guard.as_ref() {
+        Some(x) => x,
+        None => return TErr!(TError::NotImplemented),
+    };
+    let dictionary = dictionary(turtl)?.into_iter()
+        .map(|x| x.to_lowercase())
+        .collect::<HashSet<_>>();
+    let mut cache = HashMap::new();
+    let mut misspelled = Vec::new();
+    if let Some(ref title) = note.title {
+        misspelled.append(&mut check_field("title", title, &dictionary, &|word: &str| checker(word), &mut cache)?);
+    }
+    if let Some(ref text) = note.text {
+        misspelled.append(&mut check_field("text", text, &dictionary, &|word: &str| checker(word), &mut cache)?);
+    }
+    Ok(misspelled)
+}
+
+/// Registers our spellcheck commands
+pub fn register(reg: &mut Registry) {
+    reg.add("note:spellcheck", |turtl, args| {
+        let note_id: String = args.get(2)?;
+        let notes: Vec<Note> = turtl.load_notes(&vec![note_id.clone()])?;
+        let note = match notes.get(0) {
+            Some(x) => x,
+            None => return TErr!(TError::NotFound(format!("note {} wasn't found", note_id))),
+        };
+        Ok(jedi::to_val(&check_note(turtl, note)?)?)
+    });
+    reg.add("profile:spellcheck:dictionary", |turtl, _args| {
+        Ok(jedi::to_val(&dictionary(turtl)?)?)
+    });
+    reg.add("profile:spellcheck:add-word", |turtl, args| {
+        let word: String = args.get(2)?;
+        Ok(jedi::to_val(&add_word(turtl, &word)?)?)
+    });
+    reg.add("profile:spellcheck:remove-word", |turtl, args| {
+        let word: String = args.get(2)?;
+        Ok(jedi::to_val(&remove_word(turtl, &word)?)?)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_words() {
+        let found = words("Héllo there, don't `skip me` visit https://turtlapp.com or #tags and @andrew 2nd");
+        let found = found.iter().map(|w| (w.text, w.start, w.end)).collect::<Vec<_>>();
+        assert_eq!(found, vec![
+            ("Héllo", 0, 5),
+            ("there", 6, 11),
+            ("don't", 13, 18),
+            ("visit", 29, 34),
+            ("or", 56, 58),
+            ("and", 65, 68),
+        ]);
+        assert_eq!(words("```\nlet x = fn();\n```\nafter").iter().map(|w| w.text).collect::<Vec<_>>(), vec!["after"]);
+    }
+
+    #[test]
+    fn checks_words() {
+        let check = |word: &str| -> TResult<Option<Vec<String>>> {
+            match word {
+                "teh" => Ok(Some(vec![String::from("the")])),
+                "Turtl" => Ok(Some(vec![String::from("Turtle")])),
+                _ => Ok(None),
+            }
+        };
+        let mut dictionary = HashSet::new();
+        dictionary.insert(String::from("turtl"));
+        let mut cache = HashMap::new();
+        let found = check_field("text", "teh Turtl app, teh end", &dictionary, &check, &mut cache).unwrap();
+        let found = found.iter().map(|m| (m.word.as_str(), m.start, m.suggestions.clone())).collect::<Vec<_>>();
+        assert_eq!(found, vec![
+            ("teh", 0, vec![String::from("the")]),
+            ("teh", 15, vec![String::from("the")]),
+        ]);
+        assert_eq!(cache.len(), 3);
+    }
+}
@@ -0,0 +1,226 @@
+//! Splits a long markdown note into smaller ones at its headings. Each section
+//! becomes its own note (same space/board/tags, with a link back), and the
+//! original keeps whatever came before the first heading plus links to the
+//! new notes. With `dry_run` set we just say how the note would be split.
+//!
+//! Either all of it happens or none of it does, and `edit:undo` takes the whole
+//! thing back.
+
+use ::jedi::{self, Value};
+use ::error::{TResult, TError};
+use ::turtl::Turtl;
+use ::models::model::Model;
+use ::models::protected::Protected;
+use ::models::note::Note;
+use ::models::board::Board;
+use ::models::sync_record::{SyncAction, SyncType};
+use ::sync::sync_model;
+use ::lib_permissions::Permission;
+use ::undo::{self, Op, UndoEntry};
+use ::dispatch::registry::Registry;
+
+/// How notes link to each other
+const NOTE_LINK: &'static str = "turtl://note/";
+
+/// Options for `note:split`
+#[derive(Deserialize, Debug, Default)]
+pub struct SplitOptions {
+    /// Say what we'd do, but don't do it
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// A heading and everything under it (up to the next heading we split on)
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Section {
+    pub title: String,
+    pub text: String,
+}
+
+/// How deep a markdown heading is (1 for "# x"), if the line is one
+fn heading_level(line: &str) -> Option<usize> {
+    let trimmed = line.trim_left_matches(' ');
+    if line.len() - trimmed.len() > 3 { return None; }
+    let hashes = trimmed.chars().take_while(|x| *x == '#').count();
+    if hashes == 0 || hashes > 6 { return None; }
+    let rest = &trimmed[hashes..];
+    if rest.len() > 0 && !rest.starts_with(' ') && !rest.starts_with('\t') { return None; }
+    Some(hashes)
+}
+
+/// Split markdown at headings of the given level (or bigger ones, so an
+/// `# h1` between `## h2`s doesn't end up inside one of them). Returns the
+/// text before the first heading, and the sections. Headings inside fenced
+/// code don't count.
+fn split_sections(text: &str, level: usize) -> (String, Vec<Section>) {
+    let mut intro: Vec<&str> = Vec::new();
+    let mut sections: Vec<(String, Vec<&str>)> = Vec::new();
+    let mut fence: Option<&str> = None;
+    for line in text.split('\n') {
+        let trimmed = line.trim_left();
+        if let Some(marker) = fence {
+            if trimmed.starts_with(marker) { fence = None; }
+        } else if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            fence = Some(&trimmed[0..3]);
+        } else if let Some(depth) = heading_level(line) {
+            if depth <= level {
+                let title = trimmed.trim_left_matches('#').trim().trim_right_matches('#').trim();
+                sections.push((String::from(title), Vec::new()));
+                continue;
+            }
+        }
+        match sections.last_mut() {
+            Some(section) => section.1.push(line),
+            None => intro.push(line),
+        }
+    }
+    let sections = sections.into_iter()
+        .map(|(title, lines)| Section {
+            title: title,
+            text: String::from(lines.join("\n").trim_matches('\n')),
+        })
+        .collect::<Vec<_>>();
+    (String::from(intro.join("\n").trim_matches('\n')), sections)
+}
+
+/// A markdown link to a note
+fn link(title: &str, note_id: &str) -> String {
+    let title = if title == "" { "untitled" } else { title };
+    format!("[{}]({}{})", title, NOTE_LINK, note_id)
+}
+
+/// Get rid of the notes we made, after something went wrong
+fn roll_back(turtl: &Turtl, created: &Vec<String>) {
+    for id in created {
+        match sync_model::delete_model::<Note>(turtl, id, false) {
+            Ok(_) => {}
+            Err(e) => error!("split::roll_back() -- couldn't remove note {}: {}", id, e),
+        }
+    }
+}
+
+/// Save a new note, returning its id and data
+fn add_note(turtl: &Turtl, note: &mut Note) -> TResult<(String, Value)> {
+    note.parse_fields(turtl)?;
+    note.sanitize();
+    note.touch();
+    let data = sync_model::save_model(SyncAction::Add, turtl, note, false)?;
+    Ok((note.id_or_else()?, data))
+}
+
+/// Split a note at headings of the given level (1-6)
+pub fn split(turtl: &Turtl, note_id: &String, level: usize, options: &SplitOptions) -> TResult<Value> {
+    if level < 1 || level > 6 {
+        return TErr!(TError::BadValue(format!("heading level must be 1-6 (got {})", level)));
+    }
+    let mut note = match turtl.load_notes(&vec![note_id.clone()])?.pop() {
+        Some(x) => x,
+        None => return TErr!(TError::NotFound(format!("note {} wasn't found", note_id))),
+    };
+    let (intro, sections) = {
+        let text = note.text.as_ref().map(|x| x.as_str()).unwrap_or("");
+        split_sections(text, level)
+    };
+    if sections.len() == 0 {
+        return TErr!(TError::BadValue(format!("note {} has no level {} headings to split on", note_id, level)));
+    }
+    if options.dry_run {
+        return Ok(json!({
+            "dry_run": true,
+            "intro": intro,
+            "sections": sections,
+        }));
+    }
+    Board::permission_check(turtl, &note.space_id, note.board_id.as_ref(), &Permission::EditNote)?;
+    Board::permission_check(turtl, &note.space_id, note.board_id.as_ref(), &Permission::AddNote)?;
+
+    let mut before = note.data()?;
+    let _ = jedi::remove(&["body"], &mut before);
+    let backlink = format!("Split from {}", link(note.title.as_ref().map(|x| x.as_str()).unwrap_or(""), note_id));
+    let mut created: Vec<String> = Vec::with_capacity(sections.len());
+    let mut undo_ops = vec![Op::new(SyncAction::Edit, SyncType::Note, before)];
+    let mut redo_ops = Vec::with_capacity(sections.len() + 1);
+    let mut links = Vec::with_capacity(sections.len());
+    let user_id = turtl.user_id()?;
+    for section in &sections {
+        let mut part = Note::new();
+        part.user_id = user_id.clone();
+        part.space_id = note.space_id.clone();
+        part.board_id = note.board_id.clone();
+        part.type_ = note.type_.clone();
+        part.title = if section.title == "" { None } else { Some(section.title.clone()) };
+        part.text = Some(if section.text == "" { backlink.clone() } else { format!("{}\n\n{}", section.text, backlink) });
+        part.tags = note.tags.clone();
+        part.color = note.color;
+        match add_note(turtl, &mut part) {
+            Ok((id, mut data)) => {
+                let _ = jedi::remove(&["body"], &mut data);
+                links.push(format!("- {}", link(&section.title, &id)));
+                undo_ops.push(Op::new(SyncAction::Delete, SyncType::Note, json!({"id": id})));
+                redo_ops.push(Op::new(SyncAction::Add, SyncType::Note, data));
+                created.push(id);
+            }
+            Err(e) => {
+                roll_back(turtl, &created);
+                return Err(e);
+            }
+        }
+    }
+
+    let links = links.join("\n");
+    note.text = Some(if intro == "" { links } else { format!("{}\n\n{}", intro, links) });
+    note.touch();
+    let mut after = match sync_model::save_model(SyncAction::Edit, turtl, &mut note, false) {
+        Ok(x) => x,
+        Err(e) => {
+            roll_back(turtl, &created);
+            return Err(e);
+        }
+    };
+    let res = after.clone();
+    let _ = jedi::remove(&["body"], &mut after);
+    redo_ops.push(Op::new(SyncAction::Edit, SyncType::Note, after));
+    undo::record(UndoEntry {
+        label: String::from("split note"),
+        undo: undo_ops,
+        redo: redo_ops,
+    });
+    Ok(json!({
+        "note": res,
+        "notes": created,
+    }))
+}
+
+/// Registers our note splitting command
+pub fn register(reg: &mut Registry) {
+    reg.add("note:split", |turtl, args| {
+        let note_id: String = args.get(2)?;
+        let level: usize = args.get(3)?;
+        let options: SplitOptions = args.get_opt(4).unwrap_or(Default::default());
+        split(turtl, &note_id, level, &options)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_at_headings() {
+        let text = "intro\n\n## One\nfirst\n### Sub\nstill first\n```\n## not a heading\n```\n\n# Big\nbig\n##Nope\n## Two ##\nsecond\n";
+        let (intro, sections) = split_sections(text, 2);
+        assert_eq!(intro, "intro");
+        assert_eq!(sections, vec![
+            Section { title: String::from("One"), text: String::from("first\n### Sub\nstill first\n```\n## not a heading\n```") },
+            Section { title: String::from("Big"), text: String::from("big\n##Nope") },
+            Section { title: String::from("Two"), text: String::from("second") },
+        ]);
+
+        let (intro, sections) = split_sections("no headings here", 1);
+        assert_eq!(intro, "no headings here");
+        assert!(sections.is_empty());
+        assert_eq!(heading_level("    # code"), None);
+        assert_eq!(heading_level("   ### ok"), Some(3));
+        assert_eq!(heading_level("#"), Some(1));
+    }
+}
@@ -3,33 +3,71 @@
 
 use ::std::sync::{Arc, RwLock};
 use ::std::mem;
+use ::std::fs;
+use ::std::process;
+use ::std::io::prelude::*;
+use ::std::io;
 
 use ::crypto;
-use ::rusqlite::{self, Connection};
+use ::std::collections::HashMap;
+use ::rusqlite::{self, Connection, ErrorCode, NO_PARAMS};
 use ::jedi::{self, Value};
-use ::dumpy::Dumpy;
+use ::dumpy::{Dumpy, DError};
+use ::time;
 use ::config;
+use ::fs2::{self, FileExt};
+use ::messaging;
+use ::util::{self, paths};
 
 use ::models::model::{self};
 use ::models::protected::Protected;
 use ::models::storable::Storable;
 
-use ::error::TResult;
+use ::error::{TResult, TError};
 
 /// Given a db filename, return the foll path we'll use for the db file
 pub fn db_location(db_name: &String) -> TResult<String> {
     if cfg!(test) {
         return Ok(String::from(":memory:"))
     }
-    let data_folder = config::get::<String>(&["data_folder"])?;
-    let db_location = if data_folder == ":memory:" {
-        String::from(":memory:")
+    let db_location = if paths::is_memory() {
+        String::from(paths::MEMORY)
     } else {
-        format!("{}/{}.sqlite", data_folder, db_name)
+        paths::data_path(&[&format!("{}.sqlite", db_name)])?.to_string_lossy().into_owned()
     };
     Ok(db_location)
 }
 
+/// Make sure we have room on disk for `bytes` more bytes before we write them.
+///
+/// A full disk is a great way to end up with a corrupted database, so we keep
+/// `storage.min_free_space` bytes free at all times and return a DiskFull error
+/// if a write would eat into that. If we're getting close (under
+/// `storage.low_space_warning`) we let the UI know so it can nag the user.
+pub fn check_free_space(bytes: u64) -> TResult<()> {
+    if paths::is_memory() { return Ok(()); }
+    let data_folder = paths::raw_data_folder()?;
+    let available = match fs2::available_space(&data_folder) {
+        Ok(x) => x,
+        Err(e) => {
+            // not worth failing a write over
+            warn!("storage::check_free_space() -- unable to stat {}: {}", data_folder, e);
+            return Ok(());
+        }
+    };
+    let min_free: u64 = config::get(&["storage", "min_free_space"]).unwrap_or(10485760);
+    let warn_at: u64 = config::get(&["storage", "low_space_warning"]).unwrap_or(104857600);
+    let remaining = available.saturating_sub(bytes);
+    if remaining < min_free {
+        return TErr!(TError::DiskFull(format!("need {} bytes, but only {} are available (keeping {} free)", bytes, available, min_free)));
+    }
+    if remaining < warn_at {
+        messaging::ui_event("app:disk-low", &json!({"available": available, "threshold": warn_at}))
+            .unwrap_or_else(|e| error!("storage::check_free_space() -- error sending ui event: {}", e));
+    }
+    Ok(())
+}
+
 /// Make sure we have a client ID, and sync it with the model system
 pub fn setup_client_id(storage: Arc<RwLock<Storage>>) -> TResult<()> {
     let storage_guard = lockr!(storage);
@@ -46,15 +84,134 @@ pub fn setup_client_id(storage: Arc<RwLock<Storage>>) -> TResult<()> {
     model::set_client_id(id)
 }
 
+/// Where we keep the lock file for a given db location
+fn lock_location(location: &String) -> String {
+    format!("{}.lock", location)
+}
+
+/// Grab an exclusive lock on a db file. Two cores writing to the same db at
+/// the same time is a one-way ticket to corruption town, so if someone else
+/// has the lock we return a ProfileLocked error.
+///
+/// The lock itself is the flock on the lock file, which the OS lets go of when
+/// its process dies, so the file stays put for good. What's in it is just the
+/// pid of whoever has it open, for the error messages (and to tell that a core
+/// didn't shut down cleanly, see `force_unlock()`). Along with the lock we
+/// return whether there was a pid left over, meaning whoever had the db last
+/// didn't close it.
+fn acquire_lock(location: &String) -> TResult<(Option<fs::File>, bool)> {
+    if location == paths::MEMORY { return Ok((None, false)); }
+    let lock_path = lock_location(location);
+    let mut lockfile = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&lock_path)?;
+    match lockfile.try_lock_exclusive() {
+        Ok(_) => {}
+        Err(e) => {
+            warn!("storage::acquire_lock() -- {} is locked: {}", lock_path, e);
+            return TErr!(TError::ProfileLocked(format!("{} is in use by another process{}", location, lock_owner(&lock_path))));
+        }
+    }
+    let unclean = lockfile.metadata()?.len() > 0;
+    lockfile.set_len(0)?;
+    lockfile.write_all(process::id().to_string().as_bytes())?;
+    lockfile.sync_all()?;
+    Ok((Some(lockfile), unclean))
+}
+
+/// Who (going by the lock file) has a db open, for error messages
+fn lock_owner(lock_path: &String) -> String {
+    let mut contents = String::new();
+    let read = fs::File::open(lock_path)
+        .and_then(|mut file| file.read_to_string(&mut contents));
+    match (read, contents.trim().parse::<u32>()) {
+        (Ok(_), Ok(pid)) => format!(" (pid {})", pid),
+        _ => String::new(),
+    }
+}
+
+/// Clear out a lock file left behind by a core that crashed or was killed.
+/// There's no lock to actually take over (the OS already let go of it), so
+/// this just errors if someone has the db open right now and otherwise wipes
+/// the stale pid. The lock file itself is never removed: someone else could
+/// be opening it as we speak. Returns true if there was a stale pid.
+pub fn force_unlock(location: &String) -> TResult<bool> {
+    if location == paths::MEMORY { return Ok(false); }
+    let lock_path = lock_location(location);
+    let lockfile = match fs::OpenOptions::new().read(true).write(true).open(&lock_path) {
+        Ok(x) => x,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(toterr!(e)),
+    };
+    if lockfile.try_lock_exclusive().is_err() {
+        return TErr!(TError::ProfileLocked(format!("{} is in use by a running process{}", location, lock_owner(&lock_path))));
+    }
+    let stale = lockfile.metadata()?.len() > 0;
+    if stale {
+        info!("storage::force_unlock() -- clearing stale lock {}", lock_path);
+        lockfile.set_len(0)?;
+    }
+    lockfile.unlock()?;
+    Ok(stale)
+}
+
+/// Securely delete a db, along with its journal and lock file. We take the
+/// db's lock first, so if a core has it open we get a ProfileLocked error
+/// instead of pulling the file out from under it.
+pub fn delete(location: &String) -> TResult<()> {
+    if location == paths::MEMORY { return Ok(()); }
+    let (lock, _) = acquire_lock(location)?;
+    for path in &[location.clone(), format!("{}-journal", location)] {
+        if fs::metadata(path).is_err() { continue; }
+        info!("storage::delete() -- removing {}", path);
+        util::secure_delete(path)?;
+    }
+    // there's no db left to guard, so the lock file can go too (we let go of
+    // it first, some platforms won't remove open files)
+    drop(lock);
+    fs::remove_file(lock_location(location))?;
+    Ok(())
+}
+
+/// The kv key we store our row count manifest under
+const MANIFEST_KEY: &'static str = "storage:manifest";
+
+/// Whether or not a sqlite error means the db file is damaged
+fn is_corruption(err: &rusqlite::Error) -> bool {
+    match *err {
+        rusqlite::Error::SqliteFailure(ref e, _) => {
+            e.code == ErrorCode::DatabaseCorrupt || e.code == ErrorCode::NotADatabase
+        }
+        _ => false,
+    }
+}
+
+/// Move a (damaged) db out of the way so we can start fresh. We keep it around
+/// instead of deleting it in case someone wants to try and salvage it later.
+pub fn quarantine(location: &String) -> TResult<String> {
+    let dest = format!("{}.corrupt.{}", location, time::now_utc().to_timespec().sec);
+    fs::rename(location, &dest)?;
+    warn!("storage::quarantine() -- moved {} to {}", location, dest);
+    Ok(dest)
+}
+
 /// This structure holds state for persisting (encrypted) data to disk.
 pub struct Storage {
     pub conn: Connection,
     pub dumpy: Dumpy,
+    /// Our exclusive lock on the db file (None for in-memory dbs)
+    lock: Option<fs::File>,
+    /// Where our db lives
+    location: String,
 }
 
 impl Storage {
     /// Make a Storage lol
     pub fn new(location: &String, schema: Value) -> TResult<Storage> {
+        let (lock, unclean) = acquire_lock(location)?;
         // open in multi-threaded mode: we can have the same db open in multiple
         // threads as long as each thread has its own connection:
         //   https://www.sqlite.org/threadsafe.html
@@ -69,14 +226,99 @@ impl Storage {
             Connection::open_with_flags(location, flags)
         }?;
 
-        // set up dumpy
+        // set up dumpy. if sqlite can't even read the file, it's toast.
         let dumpy = Dumpy::new(schema);
-        dumpy.init(&conn)?;
+        match dumpy.init(&conn) {
+            Ok(_) => {}
+            Err(DError::SqlError(ref e)) if is_corruption(e) => {
+                return TErr!(TError::StorageCorrupt(format!("{}: {}", location, e)));
+            }
+            Err(e) => return Err(From::from(e)),
+        }
 
-        Ok(Storage {
+        let storage = Storage {
             conn: conn,
             dumpy: dumpy,
-        })
+            lock: lock,
+            location: location.clone(),
+        };
+        // a quick_check reads the whole db, so we only bother when the last
+        // session didn't close the db properly. otherwise our manifest will do.
+        if location != paths::MEMORY {
+            let problem = if unclean {
+                warn!("Storage::new() -- {} wasn't closed cleanly, checking it", location);
+                storage.check_integrity()?
+            } else {
+                None
+            };
+            let problem = match problem {
+                Some(x) => Some(x),
+                None => storage.check_manifest()?,
+            };
+            if let Some(reason) = problem {
+                return TErr!(TError::StorageCorrupt(format!("{}: {}", location, reason)));
+            }
+        }
+        Ok(storage)
+    }
+
+    /// Count how many objects we have in each table
+    fn row_counts(&self) -> TResult<HashMap<String, i64>> {
+        let mut query = self.conn.prepare("SELECT table_name, COUNT(*) AS num FROM dumpy_objects GROUP BY table_name")?;
+        let rows = query.query_map(NO_PARAMS, |row| -> rusqlite::Result<(String, i64)> {
+            Ok((row.get("table_name")?, row.get("num")?))
+        })?;
+        let mut counts = HashMap::new();
+        for row in rows {
+            let (table, num) = row?;
+            counts.insert(table, num);
+        }
+        Ok(counts)
+    }
+
+    /// Save a manifest of our row counts. We check this the next time the db
+    /// is opened, and if things don't match up, something happened to the db
+    /// while we weren't looking.
+    fn write_manifest(&self) -> TResult<()> {
+        let counts = self.row_counts()?;
+        self.kv_set(MANIFEST_KEY, &jedi::stringify(&counts)?)
+    }
+
+    /// Run a quick check on the db. Returns a description of what's wrong if
+    /// we find a problem, otherwise None.
+    pub fn check_integrity(&self) -> TResult<Option<String>> {
+        let check: String = match self.conn.query_row("PRAGMA quick_check", NO_PARAMS, |row| row.get(0)) {
+            Ok(x) => x,
+            Err(ref e) if is_corruption(e) => return Ok(Some(format!("{}", e))),
+            Err(e) => return Err(From::from(e)),
+        };
+        if check != "ok" {
+            return Ok(Some(format!("quick_check failed: {}", check)));
+        }
+        Ok(None)
+    }
+
+    /// Compare our row counts to the manifest we left ourselves on close (if
+    /// any), same deal as `check_integrity()`.
+    ///
+    /// The manifest is removed once checked: if we crash before the next clean
+    /// close, there won't be a (possibly out of date) manifest to trip over.
+    fn check_manifest(&self) -> TResult<Option<String>> {
+        let manifest: Option<HashMap<String, i64>> = match self.kv_get(MANIFEST_KEY)? {
+            Some(x) => jedi::parse(&x).ok(),
+            None => None,
+        };
+        if let Some(manifest) = manifest {
+            let counts = self.row_counts()?;
+            for (table, num) in &manifest {
+                let actual = counts.get(table).map(|x| *x).unwrap_or(0);
+                if actual != *num {
+                    return Ok(Some(format!("table {} has {} rows (expected {})", table, actual, num)));
+                }
+            }
+            self.kv_delete(MANIFEST_KEY)?;
+        }
+        Ok(None)
     }
 
     /// Save a model to our db. Make sure it's serialized before handing it in.
@@ -146,6 +388,84 @@ impl Storage {
         Ok(jedi::from_val(Value::Array(self.dumpy.by_id(&self.conn, &String::from(table), &ids)?))?)
     }
 
+    /// Run a set of storage operations inside a single transaction. If `run`
+    /// returns an error, everything it did gets rolled back.
+    pub fn transaction<F, T>(&mut self, run: F) -> TResult<T>
+        where F: FnOnce(&mut Storage) -> TResult<T>
+    {
+        self.conn.execute_batch("BEGIN IMMEDIATE")?;
+        match run(self) {
+            Ok(x) => {
+                self.conn.execute_batch("COMMIT")?;
+                Ok(x)
+            }
+            Err(e) => {
+                match self.conn.execute_batch("ROLLBACK") {
+                    Ok(_) => {},
+                    Err(e2) => error!("Storage.transaction() -- problem rolling back: {}", e2),
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Store a raw JSON value in a "table". This is for data that isn't a
+    /// model (or for models with local-only data attached). The value must
+    /// have an `id` field.
+    pub fn store_value(&self, table: &str, val: &Value) -> TResult<()> {
+        Ok(self.dumpy.store(&self.conn, &String::from(table), val)?)
+    }
+
+    /// Grab the raw JSON values from a "table" with the given IDs
+    pub fn values_by_id(&self, table: &str, ids: &Vec<String>) -> TResult<Vec<Value>> {
+        Ok(self.dumpy.by_id(&self.conn, &String::from(table), ids)?)
+    }
+
+    /// Grab all raw JSON values from a "table" ordered by id ASC
+    pub fn all_values(&self, table: &str) -> TResult<Vec<Value>> {
+        Ok(self.dumpy.all(&self.conn, &String::from(table))?)
+    }
+
+    /// Delete a raw value from a "table" by id
+    pub fn delete_value(&self, table: &str, id: &String) -> TResult<()> {
+        Ok(self.dumpy.delete(&self.conn, &String::from(table), id)?)
+    }
+
+    /// Check if any object in the db (in any table) has the given id
+    pub fn id_exists(&self, id: &String) -> TResult<bool> {
+        let count: i64 = self.conn.query_row("SELECT COUNT(*) FROM dumpy_objects WHERE id = $1", &[id], |row| row.get(0))?;
+        Ok(count > 0)
+    }
+
+    /// Make sure the ids we hand out come after any this client has already
+    /// saved, even if the clock was set back since they were made.
+    pub fn seed_cid_floor(&self) -> TResult<()> {
+        let client_id = match model::get_client_id() {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+        let newest: Option<String> = self.conn.query_row(
+            "SELECT MAX(substr(id, 1, 12)) FROM dumpy_objects WHERE length(id) = 80 AND substr(id, 13, 64) = $1",
+            &[&client_id],
+            |row| row.get(0)
+        )?;
+        if let Some(ts) = newest {
+            model::set_cid_floor(u64::from_str_radix(&ts, 16)?);
+        }
+        Ok(())
+    }
+
+    /// Make a new cid that isn't used by anything in the db. Ids are unique by
+    /// construction, so this is a belt-and-suspenders check.
+    pub fn new_id(&self) -> TResult<String> {
+        for _ in 0..8 {
+            let id = model::cid()?;
+            if !self.id_exists(&id)? { return Ok(id); }
+            warn!("Storage.new_id() -- id {} is taken, trying again", id);
+        }
+        TErr!(TError::Msg(String::from("couldn't generate an unused id")))
+    }
+
     /// Grab a value from our dumpy k/v store
     pub fn kv_get(&self, key: &str) -> TResult<Option<String>> {
         Ok(self.dumpy.kv_get(&self.conn, key)?)
@@ -162,9 +482,20 @@ impl Storage {
 
     /// Close the db connection
     pub fn close(&mut self) -> TResult<()> {
+        if self.location != paths::MEMORY {
+            self.write_manifest()
+                .unwrap_or_else(|e| warn!("Storage.close() -- problem writing manifest: {}", e));
+        }
         let mut conn = Connection::open_in_memory()?;
         mem::swap(&mut self.conn, &mut conn);
         conn.close()?;
+        // let go of our lock now that we're done with the db. the file stays,
+        // but with no pid in it so nobody thinks we crashed.
+        if let Some(lock) = self.lock.take() {
+            lock.set_len(0)
+                .unwrap_or_else(|e| warn!("Storage.close() -- problem clearing lock file: {}", e));
+            drop(lock);
+        }
         Ok(())
     }
 }
@@ -179,6 +510,8 @@ mod tests {
 
     use ::jedi::{self, Value};
     use ::rusqlite::types::Value as SqlValue;
+    use ::std::env;
+    use ::std::path::Path;
 
     use ::error::TResult;
     use ::models::model::{self, Model};
@@ -261,6 +594,23 @@ mod tests {
         assert!(sheeb.is_none());
     }
 
+    #[test]
+    fn rolls_back_transactions() {
+        let mut storage = pretest();
+        let mut model = Shiba::new_with_id().unwrap();
+        model.generate_key().unwrap();
+        model.name = Some(String::from("Kofi"));
+        model.serialize().unwrap();
+        let res: TResult<()> = storage.transaction(|db| {
+            db.save(&model)?;
+            TErr!(TError::BadValue(String::from("nope")))
+        });
+        assert!(res.is_err());
+        assert_eq!(storage.all::<Shiba>("shibas").unwrap().len(), 0);
+        storage.transaction(|db| db.save(&model)).unwrap();
+        assert_eq!(storage.all::<Shiba>("shibas").unwrap().len(), 1);
+    }
+
     #[test]
     fn kv_stuff() {
         // ^kv stuff? were the midterms hard?
@@ -271,5 +621,131 @@ mod tests {
         storage.kv_delete("get a job").unwrap();
         assert_eq!(storage.kv_get("get a job").unwrap(), None);
     }
-}
 
+    #[test]
+    fn locks_db_files() {
+        let location = env::temp_dir().join(format!("turtl-storage-lock-{}.sqlite", crypto::random_hash().unwrap()));
+        let location = String::from(location.to_string_lossy());
+        let mut storage = Storage::new(&location, json!({})).unwrap();
+        match Storage::new(&location, json!({})) {
+            Ok(_) => panic!("opened a locked db"),
+            Err(e) => match e.shed() {
+                TError::ProfileLocked(_) => {}
+                e => panic!("unexpected error: {}", e),
+            },
+        }
+        // we're alive and holding the lock, so no forcing it
+        assert!(force_unlock(&location).is_err());
+        storage.close().unwrap();
+        // the lock file sticks around, but a clean close leaves nothing stale
+        let lock_path = lock_location(&location);
+        assert!(Path::new(&lock_path).exists());
+        assert_eq!(force_unlock(&location).unwrap(), false);
+        let mut storage2 = Storage::new(&location, json!({})).unwrap();
+        storage2.close().unwrap();
+        // a core that died without closing leaves its pid behind
+        fs::write(&lock_path, "99999999").unwrap();
+        assert_eq!(force_unlock(&location).unwrap(), true);
+        assert!(Path::new(&lock_path).exists());
+        assert_eq!(fs::metadata(&lock_path).unwrap().len(), 0);
+        fs::remove_file(&location).unwrap();
+        fs::remove_file(&lock_path).unwrap();
+    }
+
+    #[test]
+    fn deletes_db_files() {
+        let location = env::temp_dir().join(format!("turtl-storage-delete-{}.sqlite", crypto::random_hash().unwrap()));
+        let location = String::from(location.to_string_lossy());
+        let journal = format!("{}-journal", location);
+        let mut storage = Storage::new(&location, json!({})).unwrap();
+        fs::write(&journal, "half a transaction").unwrap();
+        // someone has it open, so it stays put
+        match delete(&location) {
+            Ok(_) => panic!("deleted a db that was in use"),
+            Err(e) => match e.shed() {
+                TError::ProfileLocked(_) => {}
+                e => panic!("unexpected error: {}", e),
+            },
+        }
+        assert!(Path::new(&location).exists());
+        storage.close().unwrap();
+        delete(&location).unwrap();
+        assert!(!Path::new(&location).exists());
+        assert!(!Path::new(&journal).exists());
+        assert!(!Path::new(&lock_location(&location)).exists());
+        // nothing there is fine too
+        delete(&location).unwrap();
+    }
+
+    /// Somewhere on disk for a test db, all its own
+    fn disk_db(name: &str) -> String {
+        let location = env::temp_dir().join(format!("turtl-storage-{}-{}.sqlite", name, crypto::random_hash().unwrap()));
+        String::from(location.to_string_lossy())
+    }
+
+    fn save_shibas(storage: &Storage, count: usize) {
+        model::set_client_id(String::from("c0f4c762af6c42e4079cced2dfe16b4d010b190ad75ade9d83ff8cee0e96586d")).unwrap();
+        for _ in 0..count {
+            let mut model = Shiba::new_with_id().unwrap();
+            model.generate_key().unwrap();
+            model.color = Some(String::from("sesame"));
+            model.serialize().unwrap();
+            storage.save(&model).unwrap();
+        }
+    }
+
+    fn assert_corrupt(res: TResult<Storage>) {
+        match res {
+            Ok(_) => panic!("opened a corrupt db"),
+            Err(e) => match e.shed() {
+                TError::StorageCorrupt(_) => {}
+                e => panic!("unexpected error: {}", e),
+            },
+        }
+    }
+
+    #[test]
+    fn quarantines_dbs_missing_rows() {
+        let location = disk_db("manifest");
+        let mut storage = Storage::new(&location, json!({})).unwrap();
+        save_shibas(&storage, 3);
+        storage.close().unwrap();
+        // a row goes missing while we're not looking
+        let conn = Connection::open(&location).unwrap();
+        conn.execute("DELETE FROM dumpy_objects WHERE rowid IN (SELECT rowid FROM dumpy_objects WHERE table_name = 'shibas' LIMIT 1)", NO_PARAMS).unwrap();
+        drop(conn);
+        assert_corrupt(Storage::new(&location, json!({})));
+
+        let moved_to = quarantine(&location).unwrap();
+        assert!(!Path::new(&location).exists());
+        assert!(Path::new(&moved_to).exists());
+        let mut storage = Storage::new(&location, json!({})).unwrap();
+        assert_eq!(storage.all::<Shiba>("shibas").unwrap().len(), 0);
+        storage.close().unwrap();
+        fs::remove_file(&location).unwrap();
+        fs::remove_file(&moved_to).unwrap();
+        fs::remove_file(lock_location(&location)).unwrap();
+    }
+
+    #[test]
+    fn checks_dbs_that_werent_closed() {
+        let location = disk_db("unclean");
+        let mut storage = Storage::new(&location, json!({})).unwrap();
+        save_shibas(&storage, 50);
+        storage.close().unwrap();
+        assert_eq!(acquire_lock(&location).unwrap().1, false);
+        // the lock we just took never got closed, same as if we'd crashed
+        assert_eq!(acquire_lock(&location).unwrap().1, true);
+
+        // scribble over everything past the first page
+        let size = fs::metadata(&location).unwrap().len();
+        assert!(size > 4096);
+        let mut file = fs::OpenOptions::new().write(true).open(&location).unwrap();
+        file.seek(io::SeekFrom::Start(4096)).unwrap();
+        file.write_all(&vec![0xffu8; (size - 4096) as usize]).unwrap();
+        drop(file);
+        assert_corrupt(Storage::new(&location, json!({})));
+        fs::remove_file(&location).unwrap();
+        fs::remove_file(lock_location(&location)).unwrap();
+    }
+}
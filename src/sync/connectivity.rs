@@ -0,0 +1,146 @@
+//! When we lose the API, figure out why so the UI can say something better
+//! than "offline". We look at the error we got and, if that doesn't tell us
+//! enough, make a probe request to a known URL (one that returns a 204) to see
+//! whether we have internet at all or someone (a hotel wifi page, say) is
+//! intercepting our requests.
+
+use ::std::net::ToSocketAddrs;
+use ::std::time::Duration;
+use ::reqwest::{self, Url, Proxy};
+use ::error::{TResult, TError};
+use ::config;
+
+/// What we think our connection looks like
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Connectivity {
+    /// talking to the API just fine
+    Online,
+    /// can't reach anything (or can't look anything up)
+    NoInternet,
+    /// our probe got answered by something that isn't the internet
+    CaptivePortal,
+    /// the internet works, the turtl server doesn't
+    ServerDown,
+    /// we haven't checked (or aren't allowed to)
+    Unknown,
+}
+
+impl Default for Connectivity {
+    fn default() -> Self { Connectivity::Unknown }
+}
+
+/// How our probe request went
+#[derive(Debug, PartialEq)]
+enum Probe {
+    /// we got back exactly what we expected
+    Expected,
+    /// something answered, but not with our 204
+    Intercepted,
+    /// couldn't resolve the probe host
+    DnsFailed,
+    /// couldn't connect at all
+    Failed,
+    /// probing is turned off
+    Disabled,
+}
+
+/// Whether the API itself answered (with an error status), looking through
+/// any wrapping
+fn api_answered(err: &TError) -> bool {
+    match *err {
+        TError::Api(..) => true,
+        TError::Wrapped(_, _, _, ref inner) => api_answered(inner),
+        _ => false,
+    }
+}
+
+/// Turn what we know into a determination
+fn classify(api_answered: bool, probe: Probe) -> Connectivity {
+    if api_answered { return Connectivity::ServerDown; }
+    match probe {
+        Probe::Expected => Connectivity::ServerDown,
+        Probe::Intercepted => Connectivity::CaptivePortal,
+        Probe::DnsFailed | Probe::Failed => Connectivity::NoInternet,
+        Probe::Disabled => Connectivity::Unknown,
+    }
+}
+
+/// Hit our probe URL without following redirects (portals love redirects)
+fn probe() -> Probe {
+    let probe_url: Option<String> = config::get(&["sync", "connectivity", "probe_url"]).unwrap_or(None);
+    let probe_url = match probe_url {
+        Some(x) => x,
+        None => return Probe::Disabled,
+    };
+    let run = || -> TResult<Probe> {
+        let url = Url::parse(probe_url.as_str())?;
+        let host = match url.host_str() {
+            Some(x) => String::from(x),
+            None => return TErr!(TError::BadValue(format!("bad probe url: {}", probe_url))),
+        };
+        let port = url.port_or_known_default().unwrap_or(80);
+        let resolves = (host.as_str(), port).to_socket_addrs()
+            .map(|mut addrs| addrs.next().is_some())
+            .unwrap_or(false);
+        if !resolves { return Ok(Probe::DnsFailed); }
+
+        let mut client_builder = reqwest::blocking::Client::builder()
+            .timeout(Duration::new(5, 0))
+            .redirect(reqwest::redirect::Policy::none());
+        if let Ok(Some(proxy_cfg)) = config::get::<Option<String>>(&["api", "proxy"]) {
+            client_builder = client_builder.proxy(Proxy::all(proxy_cfg.as_str())?);
+        }
+        let client = client_builder.build()?;
+        let res = match client.get(url).send() {
+            Ok(x) => x,
+            Err(e) => {
+                debug!("connectivity::probe() -- probe failed: {}", e);
+                return Ok(Probe::Failed);
+            }
+        };
+        if res.status().as_u16() == 204 {
+            Ok(Probe::Expected)
+        } else {
+            debug!("connectivity::probe() -- probe intercepted: {}", res.status());
+            Ok(Probe::Intercepted)
+        }
+    };
+    match run() {
+        Ok(x) => x,
+        Err(e) => {
+            warn!("connectivity::probe() -- error probing: {}", e);
+            Probe::Failed
+        }
+    }
+}
+
+/// Figure out why an API call failed
+pub fn diagnose(err: &TError) -> Connectivity {
+    let answered = api_answered(err);
+    let probe_res = if answered { Probe::Disabled } else { probe() };
+    let connectivity = classify(answered, probe_res);
+    info!("connectivity::diagnose() -- {:?}", connectivity);
+    connectivity
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::api::StatusCode;
+    use ::jedi::Value;
+
+    #[test]
+    fn classifies_connectivity() {
+        assert_eq!(classify(true, Probe::Disabled), Connectivity::ServerDown);
+        assert_eq!(classify(false, Probe::Expected), Connectivity::ServerDown);
+        assert_eq!(classify(false, Probe::Intercepted), Connectivity::CaptivePortal);
+        assert_eq!(classify(false, Probe::DnsFailed), Connectivity::NoInternet);
+        assert_eq!(classify(false, Probe::Failed), Connectivity::NoInternet);
+        assert_eq!(classify(false, Probe::Disabled), Connectivity::Unknown);
+
+        let err = twrap!(TError::Api(StatusCode::BAD_GATEWAY, Value::Null));
+        assert!(api_answered(&err));
+        assert!(!api_answered(&TError::Msg(String::from("connection refused"))));
+    }
+}
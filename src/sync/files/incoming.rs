@@ -1,8 +1,8 @@
 use ::std::sync::{Arc, RwLock, Mutex};
-use ::sync::{SyncConfig, Syncer};
+use ::sync::{SyncConfig, Syncer, metrics};
 use ::sync::sync_model::SyncModel;
 use ::storage::Storage;
-use ::api::{Api, Method};
+use ::api::{self, Api, Method};
 use ::messaging;
 use ::error::{TResult, TError};
 use ::models::sync_record::{SyncType, SyncRecord};
@@ -94,17 +94,9 @@ impl FileSyncIncoming {
             let file_url: String = self.api.get(&url[..])?.call()?;
             info!("FileSyncIncoming.download_file() -- grabbing file at URL {}", file_url);
 
-            let mut client_builder = reqwest::blocking::Client::builder()
+            let client = api::client()?;
+            let req = client.request(Method::GET, reqwest::Url::parse(file_url.as_str())?)
                 .timeout(Duration::new(30, 0));
-            match config::get::<Option<String>>(&["api", "proxy"]) {
-                Ok(Some(proxy_cfg)) => {
-                    client_builder = client_builder.proxy(reqwest::Proxy::http(format!("http://{}", proxy_cfg).as_str())?);
-                }
-                Ok(None) => {}
-                Err(_) => {}
-            }
-            let client = client_builder.build()?;
-            let req = client.request(Method::GET, reqwest::Url::parse(file_url.as_str())?);
             // only add our auth junk if we're calling back to the turtl api!
             let turtl_api_url: String = config::get(&["api", "endpoint"])?;
             let req = if file_url.contains(turtl_api_url.as_str()) {
@@ -128,6 +120,7 @@ impl FileSyncIncoming {
                 let read = res.read(&mut buf[..])?;
                 // all done! (EOF)
                 if read <= 0 { break; }
+                metrics::bytes_down(read as u64);
                 let (read_bytes, _) = buf.split_at(read);
                 let written = file.write(read_bytes)?;
                 if read != written {
@@ -138,7 +131,7 @@ impl FileSyncIncoming {
         };
 
         match download(&note_id, &user_id) {
-            Ok(_) => {}
+            Ok(_) => metrics::pulled(1),
             Err(e) => {
                 // our download failed? send to our sync failure handler
                 with_db!{ db, self.db,
@@ -1,5 +1,5 @@
 use ::std::sync::{Arc, RwLock, Mutex};
-use ::sync::{SyncConfig, Syncer};
+use ::sync::{SyncConfig, Syncer, metrics};
 use ::sync::sync_model::SyncModel;
 use ::sync::incoming::SyncIncoming;
 use ::storage::Storage;
@@ -92,6 +92,7 @@ impl FileSyncOutgoing {
             // open our local file. we should test if it's readable/exists
             // before making API calls
             let file = fs::File::open(&file)?;
+            metrics::bytes_up(file.metadata()?.len());
             // start our API call to the note file attachment endpoint
             let url = format!("/notes/{}/attachment", note_id);
             self.api.put(&url[..])?
@@ -102,6 +103,7 @@ impl FileSyncOutgoing {
 
         match upload(&note_id) {
             Ok(res) => {
+                metrics::pushed(1);
                 match res.sync_ids.as_ref() {
                     Some(ids) => {
                         with_db!{ db, self.db,
@@ -2,7 +2,8 @@ use ::std::sync::{Arc, RwLock, Mutex};
 use ::std::io::ErrorKind;
 use ::jedi::{self, Value};
 use ::error::{TResult, TError};
-use ::sync::{SyncConfig, Syncer};
+use ::sync::{SyncConfig, Syncer, metrics};
+use ::sync::connectivity::{self, Connectivity};
 use ::sync::sync_model::{SyncModel, MemorySaver};
 use ::storage::Storage;
 use ::rusqlite::NO_PARAMS;
@@ -15,6 +16,12 @@ use ::models::user::User;
 use ::models::keychain::KeychainEntry;
 use ::models::space::Space;
 use ::models::invite::Invite;
+use ::models::contact::Contact;
+use ::models::comment::Comment;
+use ::models::reaction::Reaction;
+use ::models::receipt::Receipt;
+use ::models::time_entry::TimeEntry;
+use ::models::email_gateway::GatewayMail;
 use ::models::board::Board;
 use ::models::note::Note;
 use ::models::file::FileData;
@@ -26,6 +33,61 @@ use ::util;
 
 const SYNC_IGNORE_KEY: &'static str = "sync:incoming:ignore";
 
+/// Where we keep track of how far into a batch of incoming syncs we got
+const CHECKPOINT_KEY: &'static str = "sync:incoming:checkpoint";
+
+/// How many incoming records we apply per transaction. We only stop for a
+/// shutdown between transactions, so this is also how long a shutdown can
+/// wait on us.
+const CHECKPOINT_EVERY: usize = 50;
+
+/// Where we keep gaps in the sync ids we've applied that we haven't gone back
+/// for yet
+const GAPS_KEY: &'static str = "sync:incoming:gaps";
+
+/// A range of sync ids (inclusive) we expected to see but didn't
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SyncGap {
+    pub from: i64,
+    pub to: i64,
+}
+
+/// Given the sync_id a batch was requested after and the record ids in the
+/// batch, find any runs of ids that are missing
+fn find_gaps(after: i64, ids: &Vec<i64>) -> Vec<SyncGap> {
+    let mut ids = ids.iter().filter(|x| **x > after).map(|x| *x).collect::<Vec<_>>();
+    ids.sort();
+    ids.dedup();
+    let mut gaps = Vec::new();
+    let mut expected = after + 1;
+    for id in ids {
+        if id > expected {
+            gaps.push(SyncGap { from: expected, to: id - 1 });
+        }
+        expected = id + 1;
+    }
+    gaps
+}
+
+/// Records how much of a batch of incoming syncs we've applied. A batch is
+/// everything the API gave us for a given `sync_id`, so if we get cut off
+/// (shutdown, crash) we can ask for the same batch again and pick up right
+/// after the last record we committed. We go by record id rather than by
+/// position, since the batch we get the second time around won't necessarily
+/// line up with the first.
+#[derive(Serialize, Deserialize, Debug)]
+struct SyncCheckpoint {
+    /// The sync_id we requested the batch with
+    from: String,
+    /// The id of the last record in the batch we applied
+    applied_through: i64,
+}
+
+/// A record's sync id, as a number
+fn record_sync_id(rec: &SyncRecord) -> Option<i64> {
+    rec.id().and_then(|id| id.parse::<i64>().ok())
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SyncResponseExtra {
     #[serde(default)]
@@ -55,6 +117,12 @@ struct Handlers {
     note: models::note::Note,
     file: models::file::FileData,
     invite: models::invite::Invite,
+    contact: models::contact::Contact,
+    comment: models::comment::Comment,
+    reaction: models::reaction::Reaction,
+    receipt: models::receipt::Receipt,
+    time_entry: models::time_entry::TimeEntry,
+    email: models::email_gateway::GatewayMail,
 }
 
 /// Lets the server know why we are asking for an incoming sync.
@@ -108,6 +176,10 @@ pub struct SyncIncoming {
     /// long-poll (if connected).
     connected: bool,
 
+    /// Why we think we're (dis)connected, and when we last went and checked
+    connectivity: Connectivity,
+    last_probe: i64,
+
     /// Stores our syn run version
     run_version: i64,
 }
@@ -123,6 +195,12 @@ impl SyncIncoming {
             note: models::note::Note::new(),
             file: models::file::FileData::new(),
             invite: models::invite::Invite::new(),
+            contact: models::contact::Contact::new(),
+            comment: models::comment::Comment::new(),
+            reaction: models::reaction::Reaction::new(),
+            receipt: models::receipt::Receipt::new(),
+            time_entry: models::time_entry::TimeEntry::new(),
+            email: models::email_gateway::GatewayMail::new(),
         };
 
         SyncIncoming {
@@ -131,6 +209,8 @@ impl SyncIncoming {
             db: db,
             handlers: handlers,
             connected: false,
+            connectivity: Connectivity::Unknown,
+            last_probe: 0,
             run_version: 0,
         }
     }
@@ -198,6 +278,8 @@ impl SyncIncoming {
             Err(e) => {
                 let e = e.shed();
                 match e {
+                    // long poll came back empty. no big deal.
+                    TError::Timeout(..) => return Ok(()),
                     TError::Io(io) => {
                         match io.kind() {
                             ErrorKind::TimedOut => return Ok(()),
@@ -207,14 +289,21 @@ impl SyncIncoming {
                             ErrorKind::WouldBlock => return Ok(()),
                             _ => {
                                 info!("SyncIncoming.sync_from_api() -- unknown IO error kind: {:?}", io.kind());
-                                self.set_connected(false);
-                                return TErr!(TError::Io(io));
+                                let err = TError::Io(io);
+                                self.set_disconnected(&err);
+                                return TErr!(err);
                             }
                         }
                     }
                     TError::Api(status, msg) => {
-                        self.set_connected(false);
-                        return TErr!(TError::Api(status, msg));
+                        let err = TError::Api(status, msg);
+                        self.set_disconnected(&err);
+                        return TErr!(err);
+                    }
+                    // connection refused, dns, tls...
+                    TError::Boxed(_) => {
+                        self.set_disconnected(&e);
+                        return Err(e);
                     }
                     _ => return Err(e),
                 }
@@ -222,7 +311,7 @@ impl SyncIncoming {
         };
 
         self.set_connected(true);
-        self.update_local_db_from_api_sync(syncdata, reason != SyncReason::Poll)
+        self.update_local_db_from_api_sync(syncdata, Some(sync_id), reason != SyncReason::Poll)
     }
 
     /// Load the user's entire profile. The API gives us back a set of sync
@@ -231,12 +320,166 @@ impl SyncIncoming {
     fn load_full_profile(&mut self) -> TResult<()> {
         let syncdata = self.api.get("/sync/full")?.call_opt(ApiReq::new().timeout(120))?;
         self.set_connected(true);
-        self.update_local_db_from_api_sync(syncdata, true)
+        self.update_local_db_from_api_sync(syncdata, None, true)
+    }
+
+    /// Find the last record of the batch requested with `from` we already
+    /// applied, if any. A checkpoint for any other batch is stale (our sync_id
+    /// moved on) and gets tossed.
+    fn resume_point(&self, from: Option<&String>) -> TResult<Option<i64>> {
+        let checkpoint: Option<SyncCheckpoint> = match with_db!{ db, self.db, db.kv_get(CHECKPOINT_KEY)? } {
+            // a checkpoint we can't read just means starting the batch over
+            Some(x) => jedi::parse(&x).ok(),
+            None => return Ok(None),
+        };
+        match (checkpoint, from) {
+            (Some(ref checkpoint), Some(from)) if &checkpoint.from == from => {
+                info!("SyncIncoming.resume_point() -- resuming batch {} after record {}", from, checkpoint.applied_through);
+                Ok(Some(checkpoint.applied_through))
+            }
+            _ => {
+                with_db!{ db, self.db, db.kv_delete(CHECKPOINT_KEY)? };
+                Ok(None)
+            }
+        }
+    }
+
+    fn get_gaps(&self) -> TResult<Vec<SyncGap>> {
+        match with_db!{ db, self.db, db.kv_get(GAPS_KEY)? } {
+            Some(x) => Ok(jedi::parse(&x)?),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn set_gaps(&self, gaps: &Vec<SyncGap>) -> TResult<()> {
+        let serialized = jedi::stringify(gaps)?;
+        with_db!{ db, self.db, db.kv_set(GAPS_KEY, &serialized) }
+    }
+
+    /// Look for holes in the sync ids of a batch we just applied. Anything
+    /// missing gets queued up for a reconciliation pull on our next run.
+    fn check_gaps(&self, from: &String, batch_ids: &Vec<i64>, sync_id: i64) -> TResult<()> {
+        if !config::get(&["sync", "detect_gaps"]).unwrap_or(false) { return Ok(()); }
+        let after: i64 = match from.parse() {
+            Ok(x) => x,
+            Err(_) => return Ok(()),
+        };
+        let found = find_gaps(after, batch_ids);
+        if found.len() == 0 { return Ok(()); }
+        let mut gaps = self.get_gaps()?;
+        for gap in found {
+            warn!("SyncIncoming.check_gaps() -- missing sync ids {} - {} (batch after {})", gap.from, gap.to, after);
+            messaging::ui_event("sync:gap-detected", &json!({
+                "from": gap.from,
+                "to": gap.to,
+                "batch_after": after,
+                "batch_sync_id": sync_id,
+                "batch_size": batch_ids.len(),
+            }))?;
+            if !gaps.contains(&gap) { gaps.push(gap); }
+        }
+        self.set_gaps(&gaps)
+    }
+
+    /// Go back to the server for the oldest gap we know about, applying any
+    /// records in it we never got. Sync ids are shared with other users, so
+    /// whatever is still missing after this wasn't ours to begin with, and
+    /// the gap is considered closed either way.
+    fn fill_gap(&mut self) -> TResult<bool> {
+        let mut gaps = self.get_gaps()?;
+        if gaps.len() == 0 { return Ok(false); }
+        let gap = gaps.remove(0);
+        let url = format!("/sync?sync_id={}&type={}", gap.from - 1, util::enum_to_string(&SyncReason::Reconnect)?);
+        let syncdata: SyncResponse = self.api.get(url.as_str())?.call_opt(ApiReq::new().timeout(10))?;
+        let mut records = syncdata.records.into_iter()
+            .filter(|rec| {
+                record_sync_id(rec)
+                    .map(|id| id >= gap.from && id <= gap.to)
+                    .unwrap_or(false)
+            })
+            .collect::<Vec<_>>();
+        info!("SyncIncoming.fill_gap() -- found {} records in {} - {}", records.len(), gap.from, gap.to);
+        for chunk in records.chunks_mut(CHECKPOINT_EVERY) {
+            self.apply_chunk(chunk, None, None)?;
+        }
+        self.set_gaps(&gaps)?;
+        if records.len() > 0 {
+            self.queue_for_turtl(records)?;
+        }
+        Ok(true)
+    }
+
+    /// Send applied records into a queue that the Turtl/dispatch thread can
+    /// read and process. The purpose is to run MemorySaver for the syncs which
+    /// can only happen if we have access to Turtl, which we DO NOT at this
+    /// particular juncture.
+    fn queue_for_turtl(&self, records: Vec<SyncRecord>) -> TResult<()> {
+        let sync_incoming_queue = {
+            let conf = self.get_config();
+            let sync_config_guard = lockr!(conf);
+            sync_config_guard.incoming_sync.clone()
+        };
+        metrics::pulled(records.len() as u64);
+        // queue em
+        for rec in records { sync_incoming_queue.push(rec); }
+        // this is what tells our dispatch thread to load the queued incoming
+        // syncs and process them
+        messaging::app_event("sync:incoming", &())?;
+        Ok(())
+    }
+
+    /// Apply records that came from somewhere other than the API (a peer on
+    /// the network, say) the same way we would ones from a sync call
+    pub fn apply_external(&self, mut records: Vec<SyncRecord>) -> TResult<()> {
+        self.apply_chunk(records.as_mut_slice(), None, None)?;
+        self.queue_for_turtl(records)
+    }
+
+    /// Apply a chunk of records (and update our checkpoint) in one transaction
+    fn apply_chunk(&self, records: &mut [SyncRecord], checkpoint: Option<&SyncCheckpoint>, final_sync_id: Option<i64>) -> TResult<()> {
+        with_db!{ db, self.db,
+            db.conn.execute("BEGIN TRANSACTION", NO_PARAMS)?;
+            let res = (|| -> TResult<()> {
+                for rec in records.iter_mut() {
+                    self.run_sync_item(db, rec)?;
+                }
+                match final_sync_id {
+                    Some(sync_id) => {
+                        // done with the batch. save our sync id
+                        db.kv_set("sync_id", &sync_id.to_string())?;
+                        db.kv_delete(CHECKPOINT_KEY)?;
+                    }
+                    None => {
+                        if let Some(checkpoint) = checkpoint {
+                            db.kv_set(CHECKPOINT_KEY, &jedi::stringify(checkpoint)?)?;
+                        }
+                    }
+                }
+                Ok(())
+            })();
+            match res {
+                Ok(_) => {
+                    db.conn.execute("COMMIT TRANSACTION", NO_PARAMS)?;
+                    Ok(())
+                }
+                Err(e) => {
+                    match db.conn.execute("ROLLBACK TRANSACTION", NO_PARAMS) {
+                        Ok(_) => {}
+                        Err(e) => error!("SyncIncoming.apply_chunk() -- error rolling back: {}", e),
+                    }
+                    Err(e)
+                }
+            }
+        }
     }
 
     /// Take sync data we got from the API and update our local database with
     /// it. Kewl.
-    fn update_local_db_from_api_sync(&self, syncdata: SyncResponse, force: bool) -> TResult<()> {
+    ///
+    /// Records are applied in chunks, each in its own transaction along with a
+    /// checkpoint, and we stop between chunks if we're shutting down. Our
+    /// sync_id only moves once the whole batch is in.
+    fn update_local_db_from_api_sync(&self, syncdata: SyncResponse, from: Option<&String>, force: bool) -> TResult<()> {
         // sometimes the sync call takes a while, and it's possible we've quit
         // mid-call. if this is the case, throw out our sync result.
         if self.should_quit() && !force { return Ok(()); }
@@ -246,61 +489,95 @@ impl SyncIncoming {
         // destructure our response
         let SyncResponse { sync_id, records, extra } = syncdata;
 
+        // every sync id in the batch (even the ones we skip), for gap checking
+        let batch_ids = records.iter()
+            .filter_map(record_sync_id)
+            .collect::<Vec<_>>();
+
+        // skip anything we applied before getting cut off last time
+        let resume_after = self.resume_point(from)?;
+        let total = records.len();
+        let mut records = records.into_iter()
+            .filter(|rec| {
+                match (resume_after, record_sync_id(rec)) {
+                    (Some(last), Some(id)) => id > last,
+                    _ => true,
+                }
+            })
+            .collect::<Vec<_>>();
+        let resumed = total - records.len();
+
         // grab sync ids we're ignoring
         let ignored = self.get_ignored()?;
         let mut ignore_count = 0;
-        // filter out ignored records
-        let mut records = records
-            .into_iter()
-            .filter(|rec| {
-                match rec.id() {
-                    Some(id) => {
-                        if ignored.contains(id) {
-                            debug!("SyncIncoming.update_local_db_from_api_sync() -- ignoring {}", id);
-                            ignore_count += 1;
-                            false
-                        } else {
-                            true
-                        }
+        // filter out ignored records. they still count toward our checkpoints.
+        let mut through = resume_after;
+        let mut chunk_ends = Vec::with_capacity(records.len());
+        records.retain(|rec| {
+            if let Some(id) = record_sync_id(rec) {
+                through = Some(::std::cmp::max(id, through.unwrap_or(id)));
+            }
+            let keep = match rec.id() {
+                Some(id) => {
+                    if ignored.contains(id) {
+                        debug!("SyncIncoming.update_local_db_from_api_sync() -- ignoring {}", id);
+                        ignore_count += 1;
+                        false
+                    } else {
+                        true
                     }
-                    None => { true }
                 }
-            })
-            .collect::<Vec<_>>();
+                None => { true }
+            };
+            // the last record id we'll have seen once this one is applied
+            if keep { chunk_ends.push(through); }
+            keep
+        });
 
         info!("SyncIncoming.update_local_db_from_api_sync() -- ignored {} incoming syncs", ignore_count);
-        with_db!{ db, self.db,
-            // start a transaction. running incoming sync is all or nothing.
-            db.conn.execute("BEGIN TRANSACTION", NO_PARAMS)?;
-            for rec in &mut records {
-                self.run_sync_item(db, rec)?;
+        let mut applied = 0;
+        let mut interrupted = false;
+        loop {
+            let end = ::std::cmp::min(applied + CHECKPOINT_EVERY, records.len());
+            let finished = end == records.len();
+            // everything up to (and including) the last record in this chunk,
+            // counting ones we ignored
+            let applied_through = if end > 0 { chunk_ends[end - 1] } else { through };
+            let checkpoint = match (from, applied_through) {
+                (Some(from), Some(id)) => Some(SyncCheckpoint {
+                    from: from.clone(),
+                    applied_through: id,
+                }),
+                _ => None,
+            };
+            self.apply_chunk(&mut records[applied..end], checkpoint.as_ref(), if finished { Some(sync_id) } else { None })?;
+            applied = end;
+            if finished { break; }
+            if self.should_quit() {
+                info!("SyncIncoming.update_local_db_from_api_sync() -- shutting down after {}/{} records", resumed + applied, total);
+                interrupted = true;
+                break;
             }
-            // save our sync id
-            db.kv_set("sync_id", &sync_id.to_string())?;
-            // ok, commit
-            db.conn.execute("COMMIT TRANSACTION", NO_PARAMS)?;
         }
-
-        // send our incoming syncs into a queue that the Turtl/dispatch thread
-        // can read and process. The purpose is to run MemorySaver for the syncs
-        // which can only happen if we have access to Turtl, which we DO NOT
-        // at this particular juncture.
-        let sync_incoming_queue = {
-            let conf = self.get_config();
-            let sync_config_guard = lockr!(conf);
-            sync_config_guard.incoming_sync.clone()
-        };
-        // queue em
-        for rec in records { sync_incoming_queue.push(rec); }
-        // this is what tells our dispatch thread to load the queued incoming
-        // syncs and process them
-        messaging::app_event("sync:incoming", &())?;
+        records.truncate(applied);
+        self.queue_for_turtl(records)?;
 
         // if we have extra sync data, send it off to the ui
         if let Some(extra) = extra.as_ref() {
             messaging::ui_event("sync:incoming:extra", extra)?;
         }
 
+        // the rest of the batch (and the ignore list that goes with it) is
+        // waiting for us next time
+        if interrupted { return Ok(()); }
+
+        if let Some(from) = from {
+            match self.check_gaps(from, &batch_ids, sync_id) {
+                Ok(_) => {}
+                Err(e) => error!("SyncIncoming.update_local_db_from_api_sync() -- error checking for gaps: {}", e),
+            }
+        }
+
         // clear out the sync ignore list
         match self.clear_ignored() {
             Ok(_) => {},
@@ -336,6 +613,12 @@ impl SyncIncoming {
             SyncType::Note => self.handlers.note.incoming(db, sync_item),
             SyncType::File | SyncType::FileIncoming => self.handlers.file.incoming(db, sync_item),
             SyncType::Invite => self.handlers.invite.incoming(db, sync_item),
+            SyncType::Contact => self.handlers.contact.incoming(db, sync_item),
+            SyncType::Comment => self.handlers.comment.incoming(db, sync_item),
+            SyncType::Reaction => self.handlers.reaction.incoming(db, sync_item),
+            SyncType::Receipt => self.handlers.receipt.incoming(db, sync_item),
+            SyncType::TimeEntry => self.handlers.time_entry.incoming(db, sync_item),
+            SyncType::Email => self.handlers.email.incoming(db, sync_item),
             SyncType::FileOutgoing => Ok(()),
         }?;
 
@@ -345,6 +628,27 @@ impl SyncIncoming {
     fn set_connected(&mut self, yesno: bool) {
         self.connected = yesno;
         self.connected(yesno);
+        if yesno { self.set_connectivity(Connectivity::Online); }
+    }
+
+    /// We lost the API. Mark ourselves disconnected and (every so often) go
+    /// figure out why.
+    fn set_disconnected(&mut self, err: &TError) {
+        self.set_connected(false);
+        let now = ::time::get_time().sec;
+        let interval: i64 = config::get(&["sync", "connectivity", "probe_interval"]).unwrap_or(30);
+        if self.connectivity != Connectivity::Online && now - self.last_probe < interval { return; }
+        self.last_probe = now;
+        let connectivity = connectivity::diagnose(err);
+        self.set_connectivity(connectivity);
+    }
+
+    /// Let the main thread know if our connectivity changed
+    fn set_connectivity(&mut self, connectivity: Connectivity) {
+        if self.connectivity == connectivity { return; }
+        self.connectivity = connectivity;
+        messaging::app_event("sync:connectivity", &connectivity)
+            .unwrap_or_else(|e| error!("SyncIncoming.set_connectivity() -- error sending connectivity app event: {}", e));
     }
 }
 
@@ -391,6 +695,12 @@ impl Syncer for SyncIncoming {
         // after being previously disconnected, we can update our state
         // immediately instead of waiting 60s or w/e until the sync goes through
         let reason = if self.connected { SyncReason::Poll } else { SyncReason::Reconnect };
+        // patch up any holes before long-polling for new stuff
+        match self.fill_gap() {
+            Ok(true) => return Ok(()),
+            Ok(false) => {}
+            Err(e) => warn!("SyncIncoming.run_sync() -- error filling sync gap: {}", e),
+        }
         let res = match sync_id {
             Some(ref x) => self.sync_from_api(x, reason),
             None => return TErr!(TError::MissingData(String::from("no sync_id present"))),
@@ -445,9 +755,30 @@ pub fn process_incoming_sync(turtl: &Turtl) -> TResult<()> {
             SyncType::Keychain => mem_save::<KeychainEntry>(turtl, sync_item)?,
             SyncType::Space => mem_save::<Space>(turtl, sync_item)?,
             SyncType::Board => mem_save::<Board>(turtl, sync_item)?,
-            SyncType::Note => mem_save::<Note>(turtl, sync_item)?,
+            SyncType::Note => {
+                match Note::check_watched(turtl, &sync_item) {
+                    Ok(_) => {}
+                    Err(e) => warn!("sync::incoming::process_incoming_sync() -- error checking watched notes: {}", e),
+                }
+                let note_id = sync_item.item_id.clone();
+                let editor_id = sync_item.user_id.clone();
+                let check_mentions = sync_item.action == SyncAction::Add || sync_item.action == SyncAction::Edit;
+                mem_save::<Note>(turtl, sync_item)?;
+                if check_mentions {
+                    match Note::check_mentioned(turtl, &note_id, &editor_id) {
+                        Ok(_) => {}
+                        Err(e) => warn!("sync::incoming::process_incoming_sync() -- error checking mentions: {}", e),
+                    }
+                }
+            }
             SyncType::File => mem_save::<FileData>(turtl, sync_item)?,
             SyncType::Invite => mem_save::<Invite>(turtl, sync_item)?,
+            SyncType::Contact => mem_save::<Contact>(turtl, sync_item)?,
+            SyncType::Comment => mem_save::<Comment>(turtl, sync_item)?,
+            SyncType::Reaction => mem_save::<Reaction>(turtl, sync_item)?,
+            SyncType::Receipt => mem_save::<Receipt>(turtl, sync_item)?,
+            SyncType::TimeEntry => mem_save::<TimeEntry>(turtl, sync_item)?,
+            SyncType::Email => mem_save::<GatewayMail>(turtl, sync_item)?,
             _ => (),
         }
         drop(sync_incoming_lock);
@@ -456,3 +787,121 @@ pub fn process_incoming_sync(turtl: &Turtl) -> TResult<()> {
 }
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPACE_ID: &'static str = "015bac22440a4944baee41b88207731eaeb7e2cc5c955fb8a05b028c1409aaf55024f5d26fa3001e";
+
+    fn incoming(turtl: &Turtl) -> SyncIncoming {
+        SyncIncoming::new(turtl.sync_config.clone(), turtl.api.clone(), turtl.db.clone())
+    }
+
+    fn note_id(sync_id: i64) -> String {
+        format!("incoming-note-{}", sync_id)
+    }
+
+    /// A batch of note edits from the API, one per sync id
+    fn batch(ids: ::std::ops::Range<i64>, sync_id: i64) -> SyncResponse {
+        let records = ids
+            .map(|id| {
+                let mut rec = SyncRecord::default();
+                rec.set_id(id.to_string());
+                rec.action = SyncAction::Edit;
+                rec.user_id = String::from("51");
+                rec.ty = SyncType::Note;
+                rec.item_id = note_id(id);
+                rec.data = Some(json!({
+                    "id": note_id(id),
+                    "space_id": SPACE_ID,
+                    "user_id": "51",
+                    "keys": [],
+                    "body": "AAAA",
+                }));
+                rec
+            })
+            .collect::<Vec<_>>();
+        SyncResponse { records: records, sync_id: sync_id, extra: None }
+    }
+
+    fn has_note(turtl: &Turtl, sync_id: i64) -> bool {
+        let note: Option<Note> = lock!(turtl.db).as_ref().unwrap().get("notes", &note_id(sync_id)).unwrap();
+        note.is_some()
+    }
+
+    fn kv(turtl: &Turtl, key: &str) -> Option<String> {
+        lock!(turtl.db).as_ref().unwrap().kv_get(key).unwrap()
+    }
+
+    /// Grab the sync ids of the records handed off to the main thread
+    fn handed_off(turtl: &Turtl) -> Vec<i64> {
+        let queue = lockr!(turtl.sync_config).incoming_sync.clone();
+        let mut ids = Vec::new();
+        while let Some(rec) = queue.try_pop() {
+            ids.push(record_sync_id(&rec).unwrap());
+        }
+        ids
+    }
+
+    #[test]
+    fn resumes_after_a_shutdown() {
+        let turtl = ::turtl::tests::with_test(true);
+        let sync = incoming(&turtl);
+        let from = String::from("100");
+
+        // shut down partway through
+        lockw!(turtl.sync_config).quit = true;
+        sync.update_local_db_from_api_sync(batch(101..161, 160), Some(&from), true).unwrap();
+        assert_eq!(handed_off(&turtl), (101..(101 + CHECKPOINT_EVERY as i64)).collect::<Vec<_>>());
+        assert!(has_note(&turtl, 150));
+        assert!(!has_note(&turtl, 151));
+        assert_eq!(kv(&turtl, "sync_id"), None);
+
+        // the batch comes back different the second time around, and we pick
+        // up after the last record we applied, not after the first 50
+        lockw!(turtl.sync_config).quit = false;
+        sync.update_local_db_from_api_sync(batch(141..171, 170), Some(&from), true).unwrap();
+        assert_eq!(handed_off(&turtl), (151..171).collect::<Vec<_>>());
+        assert!(has_note(&turtl, 170));
+        assert_eq!(kv(&turtl, "sync_id"), Some(String::from("170")));
+        assert_eq!(kv(&turtl, CHECKPOINT_KEY), None);
+    }
+
+    #[test]
+    fn tosses_checkpoints_for_other_batches() {
+        let turtl = ::turtl::tests::with_test(true);
+        let sync = incoming(&turtl);
+        lockw!(turtl.sync_config).quit = true;
+        sync.update_local_db_from_api_sync(batch(101..161, 160), Some(&String::from("100")), true).unwrap();
+        lockw!(turtl.sync_config).quit = false;
+        handed_off(&turtl);
+        // our sync_id moved on some other way, so the checkpoint doesn't apply
+        sync.update_local_db_from_api_sync(batch(131..141, 140), Some(&String::from("130")), true).unwrap();
+        assert_eq!(handed_off(&turtl), (131..141).collect::<Vec<_>>());
+        assert_eq!(kv(&turtl, CHECKPOINT_KEY), None);
+    }
+
+    #[test]
+    fn finds_gaps() {
+        assert_eq!(find_gaps(100, &vec![101, 102, 105, 104, 108, 99]), vec![
+            SyncGap { from: 103, to: 103 },
+            SyncGap { from: 106, to: 107 },
+        ]);
+        assert_eq!(find_gaps(100, &vec![101, 102, 103]), Vec::<SyncGap>::new());
+        // we can't know about anything after the last record
+        assert_eq!(find_gaps(100, &vec![]), Vec::<SyncGap>::new());
+
+        let turtl = ::turtl::tests::with_test(true);
+        let sync = incoming(&turtl);
+        config::set(&["sync", "detect_gaps"], &true).unwrap();
+        sync.check_gaps(&String::from("100"), &vec![101, 104], 104).unwrap();
+        sync.check_gaps(&String::from("100"), &vec![101, 104], 104).unwrap();
+        sync.check_gaps(&String::from("200"), &vec![202], 202).unwrap();
+        config::set(&["sync", "detect_gaps"], &false).unwrap();
+        assert_eq!(sync.get_gaps().unwrap(), vec![
+            SyncGap { from: 102, to: 103 },
+            SyncGap { from: 201, to: 201 },
+        ]);
+    }
+}
@@ -0,0 +1,148 @@
+//! Keeps running counters for each syncer (bytes up/down, items pushed/pulled,
+//! errors) so the UI can show sync activity. Each syncer runs in its own
+//! thread, so numbers for the current cycle are tracked thread-locally and
+//! folded into the totals when the cycle ends.
+//!
+//! Counting only happens inside a sync cycle, so API calls made from dispatch
+//! threads (logging in, etc) don't show up here.
+
+use ::std::cell::RefCell;
+use ::std::collections::HashMap;
+use ::std::sync::RwLock;
+use ::time;
+use ::messaging;
+
+/// Counters for a syncer (or one cycle of it)
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct SyncMetrics {
+    pub bytes_up: u64,
+    pub bytes_down: u64,
+    pub items_pushed: u64,
+    pub items_pulled: u64,
+    pub errors: u64,
+    /// How many cycles did something (not counted for a single cycle)
+    pub cycles: u64,
+    /// When the last cycle that did something finished
+    pub last_cycle: Option<i64>,
+}
+
+impl SyncMetrics {
+    /// Whether anything happened at all
+    fn is_idle(&self) -> bool {
+        self.bytes_up == 0 && self.bytes_down == 0 && self.items_pushed == 0 && self.items_pulled == 0 && self.errors == 0
+    }
+
+    fn add(&mut self, cycle: &SyncMetrics) {
+        self.bytes_up += cycle.bytes_up;
+        self.bytes_down += cycle.bytes_down;
+        self.items_pushed += cycle.items_pushed;
+        self.items_pulled += cycle.items_pulled;
+        self.errors += cycle.errors;
+        self.cycles += 1;
+        self.last_cycle = cycle.last_cycle;
+    }
+}
+
+lazy_static! {
+    /// Totals, by syncer name
+    static ref TOTALS: RwLock<HashMap<String, SyncMetrics>> = RwLock::new(HashMap::new());
+}
+
+thread_local! {
+    /// The cycle running on this thread, if any
+    static CYCLE: RefCell<Option<SyncMetrics>> = RefCell::new(None);
+}
+
+fn with_cycle<F>(cb: F)
+    where F: FnOnce(&mut SyncMetrics)
+{
+    CYCLE.with(|x| {
+        if let Some(cycle) = x.borrow_mut().as_mut() { cb(cycle); }
+    });
+}
+
+/// Start counting a cycle on this thread
+pub fn start_cycle() {
+    CYCLE.with(|x| *x.borrow_mut() = Some(Default::default()));
+}
+
+/// Stop counting on this thread, add the cycle into our totals, and let the
+/// UI know what happened (if anything did).
+pub fn finish_cycle(syncer: &str) {
+    let mut cycle = match CYCLE.with(|x| x.borrow_mut().take()) {
+        Some(x) => x,
+        None => return,
+    };
+    if cycle.is_idle() { return; }
+    cycle.last_cycle = Some(time::get_time().sec);
+    {
+        let mut totals_guard = lockw!(*TOTALS);
+        totals_guard.entry(String::from(syncer)).or_insert(Default::default()).add(&cycle);
+    }
+    match messaging::ui_event("sync:cycle-complete", &json!({"syncer": syncer, "metrics": cycle})) {
+        Ok(_) => {}
+        Err(e) => error!("metrics::finish_cycle() -- error sending cycle event: {}", e),
+    }
+}
+
+pub fn bytes_up(bytes: u64) {
+    with_cycle(|x| x.bytes_up += bytes);
+}
+
+pub fn bytes_down(bytes: u64) {
+    with_cycle(|x| x.bytes_down += bytes);
+}
+
+pub fn pushed(items: u64) {
+    with_cycle(|x| x.items_pushed += items);
+}
+
+pub fn pulled(items: u64) {
+    with_cycle(|x| x.items_pulled += items);
+}
+
+pub fn errors(count: u64) {
+    with_cycle(|x| x.errors += count);
+}
+
+/// Grab our totals, by syncer name
+pub fn totals() -> HashMap<String, SyncMetrics> {
+    lockr!(*TOTALS).clone()
+}
+
+/// Start over (on logout, say)
+pub fn reset() {
+    lockw!(*TOTALS).clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_cycles() {
+        // not in a cycle, so nothing counts
+        bytes_down(100);
+        start_cycle();
+        finish_cycle("test:idle");
+        start_cycle();
+        bytes_up(10);
+        bytes_down(20);
+        pushed(2);
+        errors(1);
+        finish_cycle("test:busy");
+        start_cycle();
+        pulled(3);
+        finish_cycle("test:busy");
+
+        let totals = totals();
+        assert!(totals.get("test:idle").is_none());
+        let busy = totals.get("test:busy").unwrap();
+        assert_eq!(busy.bytes_up, 10);
+        assert_eq!(busy.bytes_down, 20);
+        assert_eq!(busy.items_pushed, 2);
+        assert_eq!(busy.items_pulled, 3);
+        assert_eq!(busy.errors, 1);
+        assert_eq!(busy.cycles, 2);
+    }
+}
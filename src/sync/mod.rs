@@ -18,6 +18,10 @@ mod macros;
 pub mod incoming;
 pub mod outgoing;
 pub mod files;
+pub mod reconcile;
+pub mod metrics;
+pub mod connectivity;
+pub mod peer;
 #[macro_use]
 pub mod sync_model;
 
@@ -35,6 +39,8 @@ use ::storage::Storage;
 use ::api::Api;
 use ::messaging;
 use ::crossbeam::sync::MsQueue;
+use ::jedi::{self, Value};
+use ::dispatch::registry::Registry;
 
 /// This holds the configuration for the sync system (whether it's enabled, the
 /// current user id/api endpoint, and any other information we need to make
@@ -186,7 +192,10 @@ pub trait Syncer {
 
         info!("sync::runner() -- {} init (run {})", self.get_name(), self.get_run_version());
 
+        metrics::start_cycle();
         let init_res = self.init();
+        if init_res.is_err() { metrics::errors(1); }
+        metrics::finish_cycle(self.get_name());
         macro_rules! send_or_return {
             ($sendex:expr) => {
                 match $sendex {
@@ -214,10 +223,15 @@ pub trait Syncer {
         while !self.should_quit() {
             let delay = self.get_delay();
             if self.is_enabled() {
+                metrics::start_cycle();
                 match self.run_sync() {
-                    Err(e) => error!("sync::runner() -- {}: main loop: {}", self.get_name(), e),
+                    Err(e) => {
+                        error!("sync::runner() -- {}: main loop: {}", self.get_name(), e);
+                        metrics::errors(1);
+                    }
                     _ => (),
                 }
+                metrics::finish_cycle(self.get_name());
                 util::sleep(delay);
             } else {
                 util::sleep(delay);
@@ -353,6 +367,58 @@ pub fn start(config: Arc<RwLock<SyncConfig>>, api: Arc<Api>, db: Arc<Mutex<Optio
     })
 }
 
+/// Registers our sync commands
+pub fn register(reg: &mut Registry) {
+    reg.add("sync:start", |turtl, _args| {
+        turtl.sync_start()?;
+        Ok(json!({}))
+    });
+    reg.add("sync:pause", |turtl, _args| {
+        turtl.sync_pause();
+        Ok(json!({}))
+    });
+    reg.add("sync:resume", |turtl, _args| {
+        turtl.sync_resume();
+        Ok(json!({}))
+    });
+    reg.add("sync:status", |turtl, args| {
+        let detailed: bool = args.get_in_opt(&["2", "detailed"]).unwrap_or(false);
+        if detailed {
+            Ok(json!({
+                "running": turtl.sync_running(),
+                "connected": *lockr!(turtl.connected),
+                "connectivity": *lockr!(turtl.connectivity),
+            }))
+        } else {
+            Ok(Value::Bool(turtl.sync_running()))
+        }
+    });
+    reg.add("sync:shutdown", |turtl, args| {
+        let wait: bool = args.get_opt(2).unwrap_or(true);
+        turtl.sync_shutdown(wait)?;
+        Ok(json!({}))
+    });
+    reg.add("sync:reconcile", |turtl, args| {
+        let dry_run: bool = args.get_in_opt(&["2", "dry_run"]).unwrap_or(false);
+        let report = reconcile::reconcile(turtl, dry_run)?;
+        Ok(jedi::to_val(&report)?)
+    });
+    reg.add("sync:get-pending", |turtl, _args| {
+        let pending = SyncRecord::get_all_pending(turtl)?;
+        Ok(jedi::to_val(&pending)?)
+    });
+    reg.add("sync:unfreeze-item", |turtl, args| {
+        let sync_id: String = args.get(2)?;
+        SyncRecord::kick_frozen_sync(turtl, &sync_id)?;
+        Ok(json!({}))
+    });
+    reg.add("sync:delete-item", |turtl, args| {
+        let sync_id: String = args.get(2)?;
+        SyncRecord::delete_sync_item(turtl, &sync_id)?;
+        Ok(json!({}))
+    });
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
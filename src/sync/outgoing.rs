@@ -1,11 +1,12 @@
 use ::std::sync::{Arc, RwLock, Mutex};
+use ::std::collections::{HashMap, HashSet};
 use ::error::TResult;
-use ::sync::{SyncConfig, Syncer};
+use ::sync::{SyncConfig, Syncer, metrics};
 use ::sync::incoming::{SyncIncoming, SyncResponseExtra};
 use ::storage::Storage;
 use ::api::{Api, ApiReq};
 use ::messaging;
-use ::models::sync_record::{SyncType, SyncRecord};
+use ::models::sync_record::{SyncType, SyncAction, SyncRecord};
 
 #[derive(Deserialize, Debug)]
 struct SyncResponse {
@@ -23,6 +24,79 @@ struct SyncResponse {
     extra: Option<SyncResponseExtra>,
 }
 
+/// The result of collapsing our pending outgoing records
+struct Coalesced {
+    /// what actually goes out, in order
+    send: Vec<SyncRecord>,
+    /// ids of records in `send` that took on newer data and need saving
+    updated: HashSet<String>,
+    /// records that are no longer needed
+    superseded: Vec<SyncRecord>,
+}
+
+/// Collapse pending records for the same item so we only send its latest
+/// state. An add followed by edits becomes one add (in the add's spot, so
+/// anything that depends on the item still comes after it), a run of edits
+/// becomes the last edit, an edit followed by a delete becomes just the
+/// delete, and an add followed by a delete cancels out entirely. Anything
+/// else (move-space, change-password) is sent as-is and starts a new run.
+fn coalesce(syncs: Vec<SyncRecord>) -> Coalesced {
+    let mut send: Vec<Option<SyncRecord>> = Vec::with_capacity(syncs.len());
+    let mut updated = HashSet::new();
+    let mut superseded = Vec::new();
+    // item key -> index in `send` of the record standing in for that item
+    let mut runs: HashMap<String, usize> = HashMap::new();
+    for sync in syncs {
+        let key = format!("{:?}:{}", sync.ty, sync.item_id);
+        match sync.action {
+            SyncAction::Add | SyncAction::Edit | SyncAction::Delete => {}
+            _ => {
+                runs.remove(&key);
+                send.push(Some(sync));
+                continue;
+            }
+        }
+        let idx = match runs.get(&key) {
+            Some(x) => *x,
+            None => {
+                runs.insert(key, send.len());
+                send.push(Some(sync));
+                continue;
+            }
+        };
+        let mut prev = send[idx].take().expect("turtl::sync::outgoing::coalesce() -- missing record for run");
+        match (&prev.action, &sync.action) {
+            (&SyncAction::Add, &SyncAction::Edit) => {
+                prev.data = sync.data.clone();
+                if let Some(id) = prev.id.as_ref() { updated.insert(id.clone()); }
+                send[idx] = Some(prev);
+                superseded.push(sync);
+            }
+            (&SyncAction::Add, &SyncAction::Delete) => {
+                if let Some(id) = prev.id.as_ref() { updated.remove(id); }
+                superseded.push(prev);
+                superseded.push(sync);
+                runs.remove(&key);
+            }
+            (&SyncAction::Edit, &SyncAction::Edit) | (&SyncAction::Edit, &SyncAction::Delete) => {
+                superseded.push(prev);
+                runs.insert(key, send.len());
+                send.push(Some(sync));
+            }
+            _ => {
+                send[idx] = Some(prev);
+                runs.insert(key, send.len());
+                send.push(Some(sync));
+            }
+        }
+    }
+    Coalesced {
+        send: send.into_iter().filter_map(|x| x).collect(),
+        updated: updated,
+        superseded: superseded,
+    }
+}
+
 /// Holds the state for data going from turtl -> API (outgoing sync data).
 pub struct SyncOutgoing {
     /// Holds our sync config. Note that this is shared between the sync system
@@ -52,7 +126,8 @@ impl SyncOutgoing {
         }
     }
 
-    /// Grab all non-file outgoing sync items, in order
+    /// Grab all non-file outgoing sync items, in order, with multiple records
+    /// for the same item coalesced into one (see `coalesce()`)
     fn get_outgoing_syncs(&self) -> TResult<Vec<SyncRecord>> {
         let syncs = with_db!{ db, self.db,
             SyncRecord::allbut(db, &vec![SyncType::FileOutgoing, SyncType::FileIncoming])
@@ -65,7 +140,22 @@ impl SyncOutgoing {
             if sync.frozen { break; }
             final_syncs.push(sync);
         }
-        Ok(final_syncs)
+
+        let Coalesced { send, updated, superseded } = coalesce(final_syncs);
+        if superseded.len() > 0 {
+            debug!("SyncOutgoing.get_outgoing_syncs() -- coalesced away {} sync items", superseded.len());
+            with_db!{ db, self.db,
+                for sync in &send {
+                    if sync.id.as_ref().map(|id| updated.contains(id)).unwrap_or(false) {
+                        db.save(sync)?;
+                    }
+                }
+                for sync in &superseded {
+                    db.delete(sync)?;
+                }
+            }
+        }
+        Ok(send)
     }
 
     /// Delete a sync record from sync (like, when we send it to the API and it
@@ -127,6 +217,8 @@ impl Syncer for SyncOutgoing {
             .json(&syncs)
             .call_opt(ApiReq::new().timeout(120))?;
         info!("SyncOutgoing.run_sync() -- got {} successes, {} failed, {} blocked syncs", sync_result.success.len(), sync_result.failures.len(), sync_result.blocked.len());
+        metrics::pushed(sync_result.success.len() as u64);
+        metrics::errors(sync_result.failures.len() as u64);
 
         // clear out the successful syncs
         let mut err: TResult<()> = Ok(());
@@ -205,6 +297,53 @@ mod tests {
         assert_eq!(outgoing.len(), 2);
     }
 
+    #[test]
+    fn coalesces_syncs_per_item() {
+        let sync_config = Arc::new(RwLock::new(SyncConfig::new()));
+        let api = Arc::new(Api::new());
+        let db = Storage::new(&String::from(":memory:"), schema::get_schema()).unwrap();
+        let db = Arc::new(Mutex::new(Some(db)));
+
+        let recs = vec![
+            json!({"id": "1", "action": "add", "item_id": "n1", "user_id": 12, "type": "note", "data": {"body": "a"}}),
+            json!({"id": "2", "action": "add", "item_id": "b1", "user_id": 12, "type": "board", "data": {"body": "x"}}),
+            json!({"id": "3", "action": "edit", "item_id": "n1", "user_id": 12, "type": "note", "data": {"body": "b"}}),
+            json!({"id": "4", "action": "edit", "item_id": "n2", "user_id": 12, "type": "note", "data": {"body": "c"}}),
+            json!({"id": "5", "action": "edit", "item_id": "n1", "user_id": 12, "type": "note", "data": {"body": "d"}}),
+            json!({"id": "6", "action": "edit", "item_id": "n2", "user_id": 12, "type": "note", "data": {"body": "e"}}),
+            json!({"id": "7", "action": "delete", "item_id": "n2", "user_id": 12, "type": "note", "data": {"id": "n2"}}),
+            json!({"id": "8", "action": "add", "item_id": "n3", "user_id": 12, "type": "note", "data": {"body": "f"}}),
+            json!({"id": "9", "action": "delete", "item_id": "n3", "user_id": 12, "type": "note", "data": {"id": "n3"}}),
+        ];
+        {
+            let mut db_guard = lock!(db);
+            let dbo = db_guard.as_mut().unwrap();
+            for rec in recs {
+                let sync: SyncRecord = jedi::from_val(rec).unwrap();
+                dbo.save(&sync).unwrap();
+            }
+        }
+
+        let sync_outgoing = SyncOutgoing::new(sync_config, api, db.clone());
+        let outgoing = sync_outgoing.get_outgoing_syncs().unwrap();
+        let summary = outgoing.iter()
+            .map(|x| (x.id.clone().unwrap(), x.action.clone(), x.data.clone().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(summary, vec![
+            (String::from("1"), SyncAction::Add, json!({"body": "d"})),
+            (String::from("2"), SyncAction::Add, json!({"body": "x"})),
+            (String::from("7"), SyncAction::Delete, json!({"id": "n2"})),
+        ]);
+
+        // the db matches what we sent
+        let mut db_guard = lock!(db);
+        let dbo = db_guard.as_mut().unwrap();
+        let stored = SyncRecord::find(dbo, None).unwrap();
+        assert_eq!(stored.len(), 3);
+        let add = stored.iter().find(|x| x.id == Some(String::from("1"))).unwrap();
+        assert_eq!(add.data, Some(json!({"body": "d"})));
+    }
+
     #[test]
     fn deserializes_sync_response() {
         let typical_mac_user = String::from(r#"{
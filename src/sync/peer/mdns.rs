@@ -0,0 +1,296 @@
+//! Just enough mDNS (RFC 6762) for Turtl devices to find each other. Each
+//! device announces itself as `<instance>._turtl-sync._tcp.local`: a PTR record
+//! under our service name pointing at the instance, and a TXT record on the
+//! instance with what a peer needs to reach it (`peer=`, `port=`, `user=`).
+//! We skip SRV/A records and use the address the announcement came from, which
+//! is all a peer on the same network needs.
+
+use ::std::collections::HashMap;
+use ::error::{TResult, TError};
+
+/// The service we announce under
+pub const SERVICE: &'static str = "_turtl-sync._tcp.local";
+
+/// The mDNS multicast group/port
+pub const GROUP: [u8; 4] = [224, 0, 0, 251];
+pub const PORT: u16 = 5353;
+
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+/// Set on records that replace (rather than add to) what peers have cached
+const CACHE_FLUSH: u16 = 0x8000;
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_AUTHORITATIVE: u16 = 0x0400;
+
+/// How long (seconds) peers should remember an announcement
+pub const TTL: u32 = 120;
+
+/// What a peer tells us about itself
+#[derive(Debug, Clone, PartialEq)]
+pub struct Announcement {
+    pub peer_id: String,
+    pub port: u16,
+    /// Tells apart devices on the same account from everyone else's
+    pub user: String,
+    /// 0 means the peer is going away
+    pub ttl: u32,
+}
+
+/// The packets we care about
+#[derive(Debug, PartialEq)]
+pub enum Packet {
+    /// Someone is looking for Turtl peers
+    Query,
+    Announce(Vec<Announcement>),
+}
+
+fn write_u16(buf: &mut Vec<u8>, x: u16) {
+    buf.push((x >> 8) as u8);
+    buf.push(x as u8);
+}
+
+fn write_u32(buf: &mut Vec<u8>, x: u32) {
+    write_u16(buf, (x >> 16) as u16);
+    write_u16(buf, x as u16);
+}
+
+fn write_name(buf: &mut Vec<u8>, name: &str) -> TResult<()> {
+    for label in name.split('.') {
+        if label.len() == 0 || label.len() > 63 {
+            return TErr!(TError::BadValue(format!("bad dns label in {}", name)));
+        }
+        buf.push(label.len() as u8);
+        buf.extend(label.as_bytes());
+    }
+    buf.push(0);
+    Ok(())
+}
+
+fn write_header(buf: &mut Vec<u8>, flags: u16, questions: u16, answers: u16) {
+    write_u16(buf, 0);
+    write_u16(buf, flags);
+    write_u16(buf, questions);
+    write_u16(buf, answers);
+    write_u16(buf, 0);
+    write_u16(buf, 0);
+}
+
+/// Write a record, filling in its rdata length once `rdata` has written it
+fn write_record<F>(buf: &mut Vec<u8>, name: &str, ty: u16, class: u16, ttl: u32, rdata: F) -> TResult<()>
+    where F: FnOnce(&mut Vec<u8>) -> TResult<()>
+{
+    write_name(buf, name)?;
+    write_u16(buf, ty);
+    write_u16(buf, class);
+    write_u32(buf, ttl);
+    let len_at = buf.len();
+    write_u16(buf, 0);
+    rdata(buf)?;
+    let len = buf.len() - len_at - 2;
+    if len > 0xffff {
+        return TErr!(TError::BadValue(format!("dns record for {} is too big", name)));
+    }
+    buf[len_at] = (len >> 8) as u8;
+    buf[len_at + 1] = len as u8;
+    Ok(())
+}
+
+/// The name a peer announces itself under. Peer ids are longer than a dns
+/// label can be, so we only use the start of it (the TXT record has the rest).
+fn instance_name(peer_id: &str) -> String {
+    let short = peer_id.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .take(16)
+        .collect::<String>();
+    format!("turtl-{}.{}", short, SERVICE)
+}
+
+/// Ask who's out there
+pub fn query() -> Vec<u8> {
+    let mut buf = Vec::with_capacity(64);
+    write_header(&mut buf, 0, 1, 0);
+    write_name(&mut buf, SERVICE).expect("turtl::sync::peer::mdns::query() -- bad service name");
+    write_u16(&mut buf, TYPE_PTR);
+    write_u16(&mut buf, CLASS_IN);
+    buf
+}
+
+/// Tell everyone about ourselves (or, with a ttl of 0, that we're leaving)
+pub fn announce(ann: &Announcement) -> TResult<Vec<u8>> {
+    let instance = instance_name(&ann.peer_id);
+    let mut buf = Vec::with_capacity(256);
+    write_header(&mut buf, FLAG_RESPONSE | FLAG_AUTHORITATIVE, 0, 2);
+    write_record(&mut buf, SERVICE, TYPE_PTR, CLASS_IN, ann.ttl, |buf| write_name(buf, &instance))?;
+    write_record(&mut buf, &instance, TYPE_TXT, CLASS_IN | CACHE_FLUSH, ann.ttl, |buf| {
+        let entries = vec![
+            format!("peer={}", ann.peer_id),
+            format!("port={}", ann.port),
+            format!("user={}", ann.user),
+        ];
+        for entry in entries {
+            if entry.len() > 255 {
+                return TErr!(TError::BadValue(format!("txt entry too long: {}", entry)));
+            }
+            buf.push(entry.len() as u8);
+            buf.extend(entry.as_bytes());
+        }
+        Ok(())
+    })?;
+    Ok(buf)
+}
+
+fn read_u16(buf: &[u8], pos: usize) -> TResult<u16> {
+    if pos + 2 > buf.len() {
+        return TErr!(TError::BadValue(String::from("truncated dns packet")));
+    }
+    Ok(((buf[pos] as u16) << 8) | buf[pos + 1] as u16)
+}
+
+fn read_u32(buf: &[u8], pos: usize) -> TResult<u32> {
+    Ok(((read_u16(buf, pos)? as u32) << 16) | read_u16(buf, pos + 2)? as u32)
+}
+
+/// Read a (possibly compressed) name, returning it and where the record
+/// continues after it
+fn read_name(buf: &[u8], start: usize) -> TResult<(String, usize)> {
+    let mut labels: Vec<String> = Vec::new();
+    let mut pos = start;
+    let mut next = None;
+    let mut jumps = 0;
+    loop {
+        if pos >= buf.len() {
+            return TErr!(TError::BadValue(String::from("truncated dns name")));
+        }
+        let len = buf[pos] as usize;
+        if len == 0 {
+            pos += 1;
+            break;
+        }
+        if len & 0xc0 == 0xc0 {
+            let target = (read_u16(buf, pos)? & 0x3fff) as usize;
+            if next.is_none() { next = Some(pos + 2); }
+            jumps += 1;
+            if jumps > 16 || target >= buf.len() {
+                return TErr!(TError::BadValue(String::from("bad dns name pointer")));
+            }
+            pos = target;
+            continue;
+        }
+        if pos + 1 + len > buf.len() {
+            return TErr!(TError::BadValue(String::from("truncated dns label")));
+        }
+        labels.push(String::from_utf8_lossy(&buf[(pos + 1)..(pos + 1 + len)]).into_owned());
+        pos += 1 + len;
+    }
+    Ok((labels.join("."), next.unwrap_or(pos)))
+}
+
+/// Pull the key=value pairs out of a TXT record
+fn read_txt(data: &[u8]) -> HashMap<String, String> {
+    let mut entries = HashMap::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let len = data[pos] as usize;
+        let end = ::std::cmp::min(pos + 1 + len, data.len());
+        let entry = String::from_utf8_lossy(&data[(pos + 1)..end]).into_owned();
+        let mut parts = entry.splitn(2, '=');
+        let key = parts.next().unwrap_or("").to_lowercase();
+        let val = parts.next().unwrap_or("");
+        entries.insert(key, String::from(val));
+        pos = end;
+    }
+    entries
+}
+
+/// Make sense of a packet, if it has anything to do with us
+pub fn parse(buf: &[u8]) -> TResult<Option<Packet>> {
+    if buf.len() < 12 {
+        return TErr!(TError::BadValue(String::from("truncated dns header")));
+    }
+    let flags = read_u16(buf, 2)?;
+    let questions = read_u16(buf, 4)?;
+    let records = read_u16(buf, 6)? as usize + read_u16(buf, 8)? as usize + read_u16(buf, 10)? as usize;
+    let mut pos = 12;
+    let mut asked = false;
+    for _ in 0..questions {
+        let (name, next) = read_name(buf, pos)?;
+        let ty = read_u16(buf, next)?;
+        pos = next + 4;
+        if name.eq_ignore_ascii_case(SERVICE) && (ty == TYPE_PTR || ty == TYPE_ANY) {
+            asked = true;
+        }
+    }
+    if flags & FLAG_RESPONSE == 0 {
+        return Ok(if asked { Some(Packet::Query) } else { None });
+    }
+    let suffix = format!(".{}", SERVICE);
+    let mut announcements = Vec::new();
+    for _ in 0..records {
+        let (name, next) = read_name(buf, pos)?;
+        let ty = read_u16(buf, next)?;
+        let ttl = read_u32(buf, next + 4)?;
+        let len = read_u16(buf, next + 8)? as usize;
+        pos = next + 10;
+        if pos + len > buf.len() {
+            return TErr!(TError::BadValue(String::from("truncated dns record")));
+        }
+        if ty == TYPE_TXT && name.to_lowercase().ends_with(&suffix) {
+            let txt = read_txt(&buf[pos..(pos + len)]);
+            let port = txt.get("port").and_then(|x| x.parse::<u16>().ok());
+            match (txt.get("peer"), port, txt.get("user")) {
+                (Some(peer_id), Some(port), Some(user)) => {
+                    announcements.push(Announcement {
+                        peer_id: peer_id.clone(),
+                        port: port,
+                        user: user.clone(),
+                        ttl: ttl,
+                    });
+                }
+                _ => {}
+            }
+        }
+        pos += len;
+    }
+    Ok(if announcements.is_empty() { None } else { Some(Packet::Announce(announcements)) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_packets() {
+        let ann = Announcement {
+            peer_id: String::from("7f3a0c9e2b1d4e5f60718293a4b5c6d7e8f90a1b2c3d4e5f60718293a4b5c6d7"),
+            port: 41234,
+            user: String::from("a1b2c3d4e5f60718"),
+            ttl: TTL,
+        };
+        assert_eq!(parse(&announce(&ann).unwrap()).unwrap(), Some(Packet::Announce(vec![ann.clone()])));
+        assert_eq!(parse(&query()).unwrap(), Some(Packet::Query));
+        // someone else's query
+        let mut other = Vec::new();
+        write_header(&mut other, 0, 1, 0);
+        write_name(&mut other, "_http._tcp.local").unwrap();
+        write_u16(&mut other, TYPE_PTR);
+        write_u16(&mut other, CLASS_IN);
+        assert_eq!(parse(&other).unwrap(), None);
+        // garbage shouldn't panic
+        assert!(parse(&[0, 0, 0x84, 0, 0, 0, 0, 1, 0, 0, 0, 0, 5, b'a']).is_err());
+        assert!(parse(&[1, 2, 3]).is_err());
+    }
+
+    #[test]
+    fn reads_compressed_names() {
+        // 12-byte header, then "local" at 12, then "x" + pointer to 12
+        let mut buf = vec![0; 12];
+        write_name(&mut buf, "local").unwrap();
+        buf.extend(&[1, b'x', 0xc0, 12]);
+        assert_eq!(read_name(&buf, 19).unwrap(), (String::from("x.local"), 23));
+        // pointer loops are an error, not a hang
+        let looped = vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xc0, 12];
+        assert!(read_name(&looped, 12).is_err());
+    }
+}
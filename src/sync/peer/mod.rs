@@ -0,0 +1,805 @@
+//! Peer sync (experimental): two devices on the same network syncing with each
+//! other directly, for people who don't want to run (or use) a Turtl server.
+//!
+//! Once started (`sync:peer:start`), we listen for peers on a TCP port and
+//! announce ourselves over mDNS (see `mdns`). Devices logged into the same
+//! account find each other and, every `sync.peer.sync_interval` seconds, the
+//! one with the lower peer id connects to the other. A connection goes:
+//!
+//! 1. hello: both sides trade peer ids and random nonces, and each proves it
+//!    holds the account's key by sending an HMAC of both nonces under it.
+//!    Anyone without the key gets hung up on.
+//! 2. both sides derive a session key from the nonces, and everything after
+//!    this point is encrypted with it.
+//! 3. each side sends the sync records it hasn't given this peer yet (the
+//!    same records we'd send the server: model data is already encrypted) and
+//!    applies the ones it gets like any other incoming sync.
+//! 4. each side acks, at which point the items it sent are marked as
+//!    delivered to that peer (at the version it sent them).
+//!
+//! Each connection gets its own thread, so a slow or silent peer can't hold
+//! up the rest, and until a peer has proven itself it gets a short timeout
+//! and only a few KB to say it in.
+//!
+//! Only local changes are passed along (records we got from one peer aren't
+//! forwarded to another), and file bodies aren't synced at all yet.
+
+pub mod mdns;
+
+use ::std::thread;
+use ::std::io::{self, Read, Write};
+use ::std::net::{TcpListener, TcpStream, UdpSocket, SocketAddr, ToSocketAddrs, Ipv4Addr};
+use ::std::collections::{HashMap, HashSet};
+use ::std::sync::{Arc, RwLock, Mutex};
+use ::std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use ::std::time::{Duration, Instant};
+use ::net2::UdpBuilder;
+use ::jedi::{self, Value, Serialize, DeserializeOwned};
+use ::time;
+use ::config;
+use ::error::{TResult, TError};
+use ::crypto::{self, Key, CryptoOp};
+use ::api::Api;
+use ::storage::Storage;
+use ::messaging;
+use ::turtl::Turtl;
+use ::models::model::{self, Model};
+use ::models::protected::Protected;
+use ::models::sync_record::{SyncRecord, SyncAction, SyncType};
+use ::sync::SyncConfig;
+use ::sync::incoming::SyncIncoming;
+use ::dispatch::registry::Registry;
+use self::mdns::{Announcement, Packet};
+
+/// Bump this if the conversation between peers changes
+const PROTOCOL_VERSION: u8 = 1;
+
+/// The most we'll read in one frame
+const MAX_FRAME: usize = 128 * 1024 * 1024;
+
+/// The most we'll read in one frame before the other side has authenticated.
+/// Hellos and proofs are tiny.
+const MAX_HELLO_FRAME: usize = 4 * 1024;
+
+/// How many peer connections we'll have going at once
+const MAX_CONNECTIONS: usize = 8;
+
+/// How long we wait on accept/recv before checking whether we should quit
+const POLL_MILLIS: u64 = 250;
+
+lazy_static! {
+    /// Set while peer sync is running. Flipped off to stop our threads.
+    static ref RUNNING: RwLock<Option<(Arc<AtomicBool>, u16)>> = RwLock::new(None);
+
+    /// Peers we've heard from, by peer id
+    static ref PEERS: RwLock<HashMap<String, Peer>> = RwLock::new(HashMap::new());
+
+    /// Keeps us from syncing with the same peer twice at once
+    static ref EXCHANGING: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+
+    /// How many peer connections are open right now
+    static ref CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+}
+
+/// A device we found on the network
+#[derive(Serialize, Debug, Clone)]
+pub struct Peer {
+    pub id: String,
+    pub address: String,
+    /// When we last heard from it (unix seconds)
+    pub last_seen: i64,
+    /// When we last synced with it (unix seconds)
+    pub last_sync: Option<i64>,
+}
+
+/// How a sync with a peer went
+#[derive(Serialize, Debug)]
+pub struct Exchange {
+    pub peer_id: String,
+    pub sent: usize,
+    pub received: usize,
+}
+
+/// Whether peer sync is running, and who we know about
+#[derive(Serialize, Debug)]
+pub struct Status {
+    pub running: bool,
+    pub peer_id: Option<String>,
+    pub port: Option<u16>,
+    pub peers: Vec<Peer>,
+}
+
+/// The first thing each side sends
+#[derive(Serialize, Deserialize, Debug)]
+struct Hello {
+    v: u8,
+    peer_id: String,
+    nonce: String,
+    /// Only the listening side sends its proof with its hello
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    proof: Option<String>,
+}
+
+/// Everything a peer connection needs. Like the sync threads, it gets its own
+/// handles to the db and config instead of the whole Turtl object.
+#[derive(Clone)]
+struct Context {
+    peer_id: String,
+    key: Key,
+    config: Arc<RwLock<SyncConfig>>,
+    api: Arc<Api>,
+    db: Arc<Mutex<Option<Storage>>>,
+}
+
+impl Context {
+    fn new(turtl: &Turtl) -> TResult<Context> {
+        let key = {
+            let user_guard = lockr!(turtl.user);
+            user_guard.key_or_else()?
+        };
+        let peer_id = match model::get_client_id() {
+            Some(x) => x,
+            None => return TErr!(TError::MissingData(String::from("no client id yet (is the user logged in?)"))),
+        };
+        Ok(Context {
+            peer_id: peer_id,
+            key: key,
+            config: turtl.sync_config.clone(),
+            api: turtl.api.clone(),
+            db: turtl.db.clone(),
+        })
+    }
+
+    /// HMAC some values with the account key, under a label so a value made
+    /// for one purpose can't stand in for another
+    fn mac(&self, label: &str, parts: &[&str]) -> TResult<Vec<u8>> {
+        let mut data = Vec::from(label.as_bytes());
+        for part in parts {
+            data.push(b'|');
+            data.extend(part.as_bytes());
+        }
+        Ok(crypto::hmac(self.key.data().as_slice(), data.as_slice())?)
+    }
+
+    /// A tag for the account that's safe to announce on the network. Devices
+    /// with the same tag are worth trying to sync with.
+    fn account_tag(&self) -> TResult<String> {
+        let tag = crypto::to_hex(&self.mac("turtl-peer:account", &[])?)?;
+        Ok(String::from(&tag[0..16]))
+    }
+}
+
+/// Send a length-prefixed frame
+fn write_frame(stream: &mut TcpStream, data: &[u8]) -> TResult<()> {
+    if data.len() > MAX_FRAME {
+        return TErr!(TError::BadValue(format!("frame too big ({} bytes)", data.len())));
+    }
+    let len = data.len() as u32;
+    stream.write_all(&[(len >> 24) as u8, (len >> 16) as u8, (len >> 8) as u8, len as u8])?;
+    stream.write_all(data)?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// Read a length-prefixed frame of up to `max` bytes
+fn read_frame(stream: &mut TcpStream, max: usize) -> TResult<Vec<u8>> {
+    let mut header = [0u8; 4];
+    stream.read_exact(&mut header)?;
+    let len = header.iter().fold(0usize, |acc, x| (acc << 8) | (*x as usize));
+    if len > max {
+        return TErr!(TError::TooLarge(String::from("peer frame"), max as u64, len as u64));
+    }
+    let mut data = vec![0u8; len];
+    stream.read_exact(&mut data)?;
+    Ok(data)
+}
+
+fn send_json<T: Serialize>(stream: &mut TcpStream, val: &T) -> TResult<()> {
+    write_frame(stream, jedi::stringify(val)?.as_bytes())
+}
+
+/// Read one of the (unencrypted) handshake messages
+fn recv_json<T: DeserializeOwned>(stream: &mut TcpStream) -> TResult<T> {
+    Ok(jedi::parse_bytes(read_frame(stream, MAX_HELLO_FRAME)?.as_slice())?)
+}
+
+/// Send something encrypted with the session key
+fn send_sealed<T: Serialize>(stream: &mut TcpStream, session: &Key, val: &T) -> TResult<()> {
+    let sealed = crypto::encrypt(session, jedi::stringify(val)?.into_bytes(), CryptoOp::new("chacha20poly1305")?)?;
+    write_frame(stream, sealed.as_slice())
+}
+
+fn recv_sealed<T: DeserializeOwned>(stream: &mut TcpStream, session: &Key) -> TResult<T> {
+    let opened = crypto::decrypt(session, read_frame(stream, MAX_FRAME)?)?;
+    Ok(jedi::parse_bytes(opened.as_slice())?)
+}
+
+/// Check a proof the other side sent us
+fn verify(ctx: &Context, label: &str, nonces: &[&str], proof: Option<&String>) -> TResult<()> {
+    let expected = ctx.mac(label, nonces)?;
+    let given = match proof {
+        Some(x) => crypto::from_hex(x).unwrap_or(Vec::new()),
+        None => Vec::new(),
+    };
+    if !crypto::secure_compare(expected.as_slice(), given.as_slice())? {
+        return TErr!(TError::PermissionDenied(String::from("peer couldn't prove it has the account key")));
+    }
+    Ok(())
+}
+
+fn session_key(ctx: &Context, nonce_client: &str, nonce_server: &str) -> TResult<Key> {
+    Ok(Key::new(ctx.mac("turtl-peer:session", &[nonce_client, nonce_server])?))
+}
+
+fn set_timeout(stream: &TcpStream, secs: u64) -> TResult<()> {
+    stream.set_read_timeout(Some(Duration::new(secs, 0)))?;
+    stream.set_write_timeout(Some(Duration::new(secs, 0)))?;
+    Ok(())
+}
+
+/// Set up a connection we've been handed (either way) for the handshake
+fn prepare(stream: &TcpStream) -> TResult<()> {
+    let timeout: u64 = config::get(&["sync", "peer", "handshake_timeout"]).unwrap_or(5);
+    stream.set_nonblocking(false)?;
+    set_timeout(stream, timeout)
+}
+
+/// Give a peer that's authenticated the normal timeout
+fn authenticated(stream: &TcpStream) -> TResult<()> {
+    let timeout: u64 = config::get(&["sync", "peer", "timeout"]).unwrap_or(30);
+    set_timeout(stream, timeout)
+}
+
+/// The key we keep what we've delivered to a peer under
+fn delivered_key(peer_id: &str) -> String {
+    format!("peer_sync:{}", peer_id)
+}
+
+/// Which version of each item (by item id) a peer has gotten from us
+fn delivered(db: &Storage, peer_id: &str) -> TResult<HashMap<String, String>> {
+    match db.kv_get(&delivered_key(peer_id))? {
+        Some(x) => Ok(jedi::parse(&x)?),
+        None => Ok(HashMap::new()),
+    }
+}
+
+/// The version of an item a record carries: a hash of what it does to the
+/// item. Record ids don't work here, since the same item can be changed back
+/// and forth and records come and go as the server takes them.
+fn version(rec: &SyncRecord) -> TResult<String> {
+    let data = match rec.data.as_ref() {
+        Some(x) => jedi::stringify(x)?,
+        None => String::new(),
+    };
+    let versioned = format!("{}|{}", jedi::stringify(&rec.action)?, data);
+    Ok(crypto::to_hex(&crypto::sha256(versioned.as_bytes())?)?)
+}
+
+/// Whether a record is something we pass along to peers
+fn shareable(rec: &SyncRecord) -> bool {
+    let action_ok = match rec.action {
+        SyncAction::Add | SyncAction::Edit | SyncAction::Delete => true,
+        _ => false,
+    };
+    let type_ok = match rec.ty {
+        SyncType::File | SyncType::FileIncoming | SyncType::FileOutgoing => false,
+        _ => true,
+    };
+    action_ok && type_ok && !rec.frozen
+}
+
+/// Grab the records for items this peer doesn't have the latest version of,
+/// along with the versions they carry. Only the newest record for an item
+/// goes out.
+fn pending_for(ctx: &Context, peer_id: &str) -> TResult<Vec<(SyncRecord, String)>> {
+    with_db!{ db, ctx.db,
+        let delivered = delivered(db, peer_id)?;
+        let records = SyncRecord::find(db, None)?.into_iter()
+            .filter(|rec| shareable(rec))
+            .collect::<Vec<_>>();
+        let mut newest: HashMap<String, usize> = HashMap::new();
+        for (i, rec) in records.iter().enumerate() {
+            newest.insert(rec.item_id.clone(), i);
+        }
+        let mut pending = Vec::new();
+        for (i, mut rec) in records.into_iter().enumerate() {
+            if newest.get(&rec.item_id) != Some(&i) { continue; }
+            let version = version(&rec)?;
+            if delivered.get(&rec.item_id) == Some(&version) { continue; }
+            rec.error = None;
+            rec.errcount = 0;
+            pending.push((rec, version));
+        }
+        Ok(pending)
+    }
+}
+
+/// Remember which version of these items a peer has now
+fn mark_delivered(ctx: &Context, peer_id: &str, sent: Vec<(String, String)>) -> TResult<()> {
+    with_db!{ db, ctx.db,
+        let mut delivered = delivered(db, peer_id)?;
+        delivered.extend(sent);
+        db.kv_set(&delivered_key(peer_id), &jedi::stringify(&delivered)?)?;
+        Ok(())
+    }
+}
+
+/// Apply the records a peer sent us, same as if they came from the server
+fn apply(ctx: &Context, records: Vec<SyncRecord>) -> TResult<usize> {
+    let records = records.into_iter()
+        .filter(|rec| shareable(rec))
+        .map(|mut rec| {
+            rec.sync_ids = None;
+            rec
+        })
+        .collect::<Vec<_>>();
+    let count = records.len();
+    if count == 0 { return Ok(0); }
+    let incoming = SyncIncoming::new(ctx.config.clone(), ctx.api.clone(), ctx.db.clone());
+    incoming.apply_external(records)?;
+    Ok(count)
+}
+
+/// Trade records with a peer we've authenticated with. The side that
+/// connected goes first.
+fn exchange(ctx: &Context, stream: &mut TcpStream, session: &Key, peer_id: &String, initiator: bool) -> TResult<Exchange> {
+    {
+        let mut guard = lock!(*EXCHANGING);
+        if guard.contains(peer_id) {
+            return TErr!(TError::TryAgain);
+        }
+        guard.insert(peer_id.clone());
+    }
+    let res = (|| -> TResult<Exchange> {
+        let (outgoing, versions): (Vec<SyncRecord>, Vec<String>) = pending_for(ctx, peer_id)?.into_iter().unzip();
+        let sent = outgoing.iter()
+            .map(|rec| rec.item_id.clone())
+            .zip(versions)
+            .collect::<Vec<_>>();
+        let incoming: Vec<SyncRecord> = if initiator {
+            send_sealed(stream, session, &outgoing)?;
+            recv_sealed(stream, session)?
+        } else {
+            let incoming = recv_sealed(stream, session)?;
+            send_sealed(stream, session, &outgoing)?;
+            incoming
+        };
+        let received = apply(ctx, incoming)?;
+        let ack = json!({"received": received});
+        let _their_ack: Value = if initiator {
+            send_sealed(stream, session, &ack)?;
+            recv_sealed(stream, session)?
+        } else {
+            let their_ack = recv_sealed(stream, session)?;
+            send_sealed(stream, session, &ack)?;
+            their_ack
+        };
+        let count = sent.len();
+        mark_delivered(ctx, peer_id, sent)?;
+        Ok(Exchange {
+            peer_id: peer_id.clone(),
+            sent: count,
+            received: received,
+        })
+    })();
+    lock!(*EXCHANGING).remove(peer_id);
+    if res.is_ok() {
+        if let Some(peer) = lockw!(*PEERS).get_mut(peer_id) {
+            peer.last_sync = Some(time::get_time().sec);
+        }
+    }
+    res
+}
+
+/// Connect to a peer and sync with it
+fn connect(ctx: &Context, address: &str) -> TResult<Exchange> {
+    let timeout: u64 = config::get(&["sync", "peer", "timeout"]).unwrap_or(30);
+    let addr = match address.to_socket_addrs()?.next() {
+        Some(x) => x,
+        None => return TErr!(TError::BadValue(format!("can't find peer address {}", address))),
+    };
+    let mut stream = TcpStream::connect_timeout(&addr, Duration::new(timeout, 0))?;
+    prepare(&stream)?;
+    let nonce = crypto::random_hash()?;
+    send_json(&mut stream, &Hello {
+        v: PROTOCOL_VERSION,
+        peer_id: ctx.peer_id.clone(),
+        nonce: nonce.clone(),
+        proof: None,
+    })?;
+    let theirs: Hello = recv_json(&mut stream)?;
+    if theirs.v != PROTOCOL_VERSION {
+        return TErr!(TError::Incompatible(format!("peer speaks version {}, we speak {}", theirs.v, PROTOCOL_VERSION)));
+    }
+    verify(ctx, "turtl-peer:server", &[&nonce, &theirs.nonce], theirs.proof.as_ref())?;
+    let proof = crypto::to_hex(&ctx.mac("turtl-peer:client", &[&nonce, &theirs.nonce])?)?;
+    send_json(&mut stream, &json!({"proof": proof}))?;
+    let session = session_key(ctx, &nonce, &theirs.nonce)?;
+    authenticated(&stream)?;
+    info!("sync::peer::connect() -- authenticated with {} ({})", theirs.peer_id, address);
+    exchange(ctx, &mut stream, &session, &theirs.peer_id, true)
+}
+
+/// Handle a peer that connected to us
+fn serve(ctx: &Context, mut stream: TcpStream) -> TResult<Exchange> {
+    prepare(&stream)?;
+    let theirs: Hello = recv_json(&mut stream)?;
+    if theirs.v != PROTOCOL_VERSION {
+        return TErr!(TError::Incompatible(format!("peer speaks version {}, we speak {}", theirs.v, PROTOCOL_VERSION)));
+    }
+    let nonce = crypto::random_hash()?;
+    let proof = crypto::to_hex(&ctx.mac("turtl-peer:server", &[&theirs.nonce, &nonce])?)?;
+    send_json(&mut stream, &Hello {
+        v: PROTOCOL_VERSION,
+        peer_id: ctx.peer_id.clone(),
+        nonce: nonce.clone(),
+        proof: Some(proof),
+    })?;
+    let their_proof: Value = recv_json(&mut stream)?;
+    verify(ctx, "turtl-peer:client", &[&theirs.nonce, &nonce], jedi::get_opt::<String>(&["proof"], &their_proof).as_ref())?;
+    let session = session_key(ctx, &theirs.nonce, &nonce)?;
+    authenticated(&stream)?;
+    info!("sync::peer::serve() -- authenticated with {}", theirs.peer_id);
+    exchange(ctx, &mut stream, &session, &theirs.peer_id, false)
+}
+
+/// Let the UI know how a sync went
+fn synced(res: TResult<Exchange>) {
+    match res {
+        Ok(ex) => {
+            info!("sync::peer -- synced with {}: sent {}, got {}", ex.peer_id, ex.sent, ex.received);
+            messaging::ui_event("sync:peer:synced", &ex)
+                .unwrap_or_else(|e| error!("sync::peer -- error sending ui event: {}", e));
+        }
+        Err(e) => {
+            match e.shed() {
+                TError::TryAgain => debug!("sync::peer -- already syncing with that peer"),
+                e => warn!("sync::peer -- problem syncing with peer: {}", e),
+            }
+        }
+    }
+}
+
+/// Frees up a connection slot when a peer thread is done with it
+struct Slot;
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        CONNECTIONS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Talk to a peer on its own thread. If we've already got our hands full,
+/// the connection gets dropped (the peer will try again later).
+fn spawn_sync<F>(run: F)
+    where F: FnOnce() -> TResult<Exchange> + Send + 'static
+{
+    if CONNECTIONS.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+        CONNECTIONS.fetch_sub(1, Ordering::SeqCst);
+        warn!("sync::peer -- too many peer connections, dropping one");
+        return;
+    }
+    let slot = Slot;
+    let res = thread::Builder::new().name(String::from("sync:peer:conn")).spawn(move || {
+        let _slot = slot;
+        synced(run());
+    });
+    if let Err(e) = res {
+        error!("sync::peer -- couldn't start a peer thread: {}", e);
+    }
+}
+
+/// Join the mDNS group. Other mDNS responders are probably already bound to
+/// the port, so we share it with them.
+fn mdns_socket() -> TResult<UdpSocket> {
+    let socket = UdpBuilder::new_v4()?
+        .reuse_address(true)?
+        .bind((Ipv4Addr::new(0, 0, 0, 0), mdns::PORT))?;
+    let group = Ipv4Addr::new(mdns::GROUP[0], mdns::GROUP[1], mdns::GROUP[2], mdns::GROUP[3]);
+    socket.join_multicast_v4(&group, &Ipv4Addr::new(0, 0, 0, 0))?;
+    socket.set_multicast_loop_v4(true)?;
+    socket.set_read_timeout(Some(Duration::from_millis(POLL_MILLIS)))?;
+    Ok(socket)
+}
+
+/// Find peers on the network (and let them find us), syncing with the ones
+/// it's our turn to sync with
+fn discover(ctx: Context, socket: UdpSocket, port: u16, running: Arc<AtomicBool>) -> TResult<()> {
+    let announce_interval: u64 = config::get(&["sync", "peer", "announce_interval"]).unwrap_or(60);
+    let sync_interval: u64 = config::get(&["sync", "peer", "sync_interval"]).unwrap_or(30);
+    let group = SocketAddr::from((mdns::GROUP, mdns::PORT));
+    let mut ann = Announcement {
+        peer_id: ctx.peer_id.clone(),
+        port: port,
+        user: ctx.account_tag()?,
+        ttl: mdns::TTL,
+    };
+    let mut last_announce: Option<Instant> = None;
+    let mut last_sync = Instant::now();
+    let mut buf = [0u8; 9000];
+    while running.load(Ordering::SeqCst) {
+        if last_announce.map(|x| x.elapsed() >= Duration::new(announce_interval, 0)).unwrap_or(true) {
+            socket.send_to(&mdns::query(), group)?;
+            socket.send_to(&mdns::announce(&ann)?, group)?;
+            last_announce = Some(Instant::now());
+        }
+        if sync_interval > 0 && last_sync.elapsed() >= Duration::new(sync_interval, 0) {
+            last_sync = Instant::now();
+            // both of us would connect otherwise
+            let ours = lockr!(*PEERS).values()
+                .filter(|peer| peer.id > ctx.peer_id)
+                .cloned()
+                .collect::<Vec<_>>();
+            for peer in ours {
+                let ctx = ctx.clone();
+                spawn_sync(move || connect(&ctx, &peer.address));
+            }
+        }
+        let (len, from) = match socket.recv_from(&mut buf) {
+            Ok(x) => x,
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => continue,
+            Err(e) => return Err(toterr!(e)),
+        };
+        let packet = match mdns::parse(&buf[0..len]) {
+            Ok(Some(x)) => x,
+            Ok(None) => continue,
+            Err(e) => {
+                trace!("sync::peer::discover() -- bad mdns packet from {}: {}", from, e);
+                continue;
+            }
+        };
+        match packet {
+            Packet::Query => {
+                socket.send_to(&mdns::announce(&ann)?, group)?;
+            }
+            Packet::Announce(anns) => {
+                for found in anns {
+                    if found.user != ann.user || found.peer_id == ctx.peer_id { continue; }
+                    if found.ttl == 0 {
+                        if lockw!(*PEERS).remove(&found.peer_id).is_some() {
+                            messaging::ui_event("sync:peer:lost", &json!({"id": found.peer_id}))?;
+                        }
+                        continue;
+                    }
+                    let address = format!("{}:{}", from.ip(), found.port);
+                    let mut peers_guard = lockw!(*PEERS);
+                    let is_new = !peers_guard.contains_key(&found.peer_id);
+                    let last_sync = peers_guard.get(&found.peer_id).and_then(|x| x.last_sync);
+                    let peer = Peer {
+                        id: found.peer_id.clone(),
+                        address: address,
+                        last_seen: time::get_time().sec,
+                        last_sync: last_sync,
+                    };
+                    peers_guard.insert(found.peer_id.clone(), peer.clone());
+                    drop(peers_guard);
+                    if is_new {
+                        info!("sync::peer::discover() -- found peer {} at {}", peer.id, peer.address);
+                        messaging::ui_event("sync:peer:found", &peer)?;
+                    }
+                }
+            }
+        }
+    }
+    // say goodbye so peers drop us right away
+    ann.ttl = 0;
+    socket.send_to(&mdns::announce(&ann)?, group)?;
+    Ok(())
+}
+
+/// Take connections from peers
+fn listen(ctx: Context, listener: TcpListener, running: Arc<AtomicBool>) {
+    while running.load(Ordering::SeqCst) {
+        match listener.accept() {
+            Ok((stream, from)) => {
+                info!("sync::peer::listen() -- connection from {}", from);
+                let ctx = ctx.clone();
+                spawn_sync(move || serve(&ctx, stream));
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(Duration::from_millis(POLL_MILLIS));
+            }
+            Err(e) => {
+                error!("sync::peer::listen() -- error accepting connection: {}", e);
+                thread::sleep(Duration::from_millis(POLL_MILLIS));
+            }
+        }
+    }
+}
+
+/// Start listening for (and looking for) peers
+pub fn start(turtl: &Turtl) -> TResult<Status> {
+    if lockr!(*RUNNING).is_some() { return Ok(status()); }
+    let ctx = Context::new(turtl)?;
+    let port: u16 = config::get(&["sync", "peer", "port"]).unwrap_or(0);
+    let listener = TcpListener::bind((Ipv4Addr::new(0, 0, 0, 0), port))?;
+    listener.set_nonblocking(true)?;
+    let port = listener.local_addr()?.port();
+    let running = Arc::new(AtomicBool::new(true));
+
+    let ctx2 = ctx.clone();
+    let running2 = running.clone();
+    thread::Builder::new().name(String::from("sync:peer:listen")).spawn(move || {
+        listen(ctx2, listener, running2);
+        info!("sync::peer -- listener shut down");
+    })?;
+    // without discovery, peers can still be synced with by address
+    match mdns_socket() {
+        Ok(socket) => {
+            let running3 = running.clone();
+            thread::Builder::new().name(String::from("sync:peer:discover")).spawn(move || {
+                match discover(ctx, socket, port, running3) {
+                    Ok(_) => info!("sync::peer -- discovery shut down"),
+                    Err(e) => error!("sync::peer -- discovery stopped: {}", e),
+                }
+            })?;
+        }
+        Err(e) => warn!("sync::peer::start() -- can't use mdns, so peers won't be found automatically: {}", e),
+    }
+    *lockw!(*RUNNING) = Some((running, port));
+    info!("sync::peer::start() -- listening for peers on port {}", port);
+    Ok(status())
+}
+
+/// Stop peer sync
+pub fn stop() {
+    if let Some((running, _)) = lockw!(*RUNNING).take() {
+        running.store(false, Ordering::SeqCst);
+    }
+    lockw!(*PEERS).clear();
+}
+
+/// Where peer sync is at
+pub fn status() -> Status {
+    let running = lockr!(*RUNNING).as_ref().map(|x| x.1);
+    let mut peers = lockr!(*PEERS).values().cloned().collect::<Vec<_>>();
+    peers.sort_by(|a, b| a.id.cmp(&b.id));
+    Status {
+        running: running.is_some(),
+        peer_id: model::get_client_id(),
+        port: running,
+        peers: peers,
+    }
+}
+
+/// Sync with a peer right now. `target` is a peer id we've found, or a
+/// host:port for a peer mDNS can't see.
+pub fn sync_with(turtl: &Turtl, target: &String) -> TResult<Exchange> {
+    let ctx = Context::new(turtl)?;
+    let address = lockr!(*PEERS).get(target).map(|x| x.address.clone());
+    let address = address.unwrap_or(target.clone());
+    connect(&ctx, &address)
+}
+
+/// Registers our peer sync commands
+pub fn register(reg: &mut Registry) {
+    reg.add("sync:peer:start", |turtl, _args| {
+        Ok(jedi::to_val(&start(turtl)?)?)
+    });
+    reg.add("sync:peer:stop", |_turtl, _args| {
+        stop();
+        Ok(jedi::to_val(&status())?)
+    });
+    reg.add("sync:peer:status", |_turtl, _args| {
+        Ok(jedi::to_val(&status())?)
+    });
+    reg.add("sync:peer:sync", |turtl, args| {
+        let target: String = args.get(2)?;
+        Ok(jedi::to_val(&sync_with(turtl, &target)?)?)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::error::ErrorCode;
+    use ::models::note::Note;
+
+    const SPACE_ID: &'static str = "015bac22440a4944baee41b88207731eaeb7e2cc5c955fb8a05b028c1409aaf55024f5d26fa3001e";
+
+    fn context(turtl: &Turtl, peer_id: &str, key: Key) -> Context {
+        Context {
+            peer_id: String::from(peer_id),
+            key: key,
+            config: turtl.sync_config.clone(),
+            api: turtl.api.clone(),
+            db: turtl.db.clone(),
+        }
+    }
+
+    fn user_key(turtl: &Turtl) -> Key {
+        lockr!(turtl.user).key_or_else().unwrap()
+    }
+
+    /// Queue an outgoing edit to a note, same as saving it would
+    fn edit_note(turtl: &Turtl, note_id: &str, body: &str) {
+        let mut rec = SyncRecord::default();
+        rec.generate_id().unwrap();
+        rec.action = SyncAction::Edit;
+        rec.user_id = String::from("51");
+        rec.ty = SyncType::Note;
+        rec.item_id = String::from(note_id);
+        rec.data = Some(json!({
+            "id": note_id,
+            "space_id": SPACE_ID,
+            "user_id": "51",
+            "keys": [],
+            "body": body,
+        }));
+        lock!(turtl.db).as_ref().unwrap().save(&rec).unwrap();
+    }
+
+    /// Have `client` connect to `server` over loopback and sync
+    fn sync_over_loopback(client: &Context, server: &Context) -> (TResult<Exchange>, Result<Exchange, ErrorCode>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = server.clone();
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            serve(&server, stream).map_err(|e| e.code())
+        });
+        let res = connect(client, &address);
+        (res, handle.join().unwrap())
+    }
+
+    #[test]
+    fn syncs_with_a_peer() {
+        let turtl_a = ::turtl::tests::with_test(true);
+        let turtl_b = ::turtl::tests::with_test(true);
+        let a = context(&turtl_a, "loopback-a", user_key(&turtl_a));
+        let b = context(&turtl_b, "loopback-b", user_key(&turtl_b));
+        let note_id = String::from("015ce7ea7f742af6297cf0cc29180f9cc45f4c80e5b30238581f845367f9c404ef3fb8fb0a5a00aa");
+        let sync = || {
+            let (client, server) = sync_over_loopback(&a, &b);
+            let client = client.unwrap();
+            let server = server.unwrap();
+            assert_eq!(client.peer_id, "loopback-b");
+            assert_eq!(server.peer_id, "loopback-a");
+            assert_eq!(client.sent, server.received);
+            client.sent
+        };
+
+        edit_note(&turtl_a, &note_id, "AAAA");
+        assert_eq!(sync(), 1);
+        let note: Option<Note> = lock!(turtl_b.db).as_ref().unwrap().get("notes", &note_id).unwrap();
+        assert!(note.is_some());
+        // already delivered
+        assert_eq!(sync(), 0);
+        // only the newest version of an item goes out...
+        edit_note(&turtl_a, &note_id, "BBBB");
+        edit_note(&turtl_a, &note_id, "CCCC");
+        assert_eq!(sync(), 1);
+        assert_eq!(sync(), 0);
+        // ...and going back to a version the peer had before still counts
+        edit_note(&turtl_a, &note_id, "AAAA");
+        assert_eq!(sync(), 1);
+    }
+
+    #[test]
+    fn turns_away_strangers() {
+        let turtl_a = ::turtl::tests::with_test(true);
+        let turtl_b = ::turtl::tests::with_test(true);
+        let a = context(&turtl_a, "stranger-a", Key::random().unwrap());
+        let b = context(&turtl_b, "stranger-b", user_key(&turtl_b));
+        edit_note(&turtl_a, "0001", "AAAA");
+        let (client, server) = sync_over_loopback(&a, &b);
+        assert_eq!(client.unwrap_err().code(), ErrorCode::PermissionDenied);
+        assert!(server.is_err());
+        let note: Option<Note> = lock!(turtl_b.db).as_ref().unwrap().get("notes", &String::from("0001")).unwrap();
+        assert!(note.is_none());
+    }
+
+    #[test]
+    fn caps_frames_before_auth() {
+        let turtl = ::turtl::tests::with_test(true);
+        let ctx = context(&turtl, "capped", user_key(&turtl));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (conn, _) = listener.accept().unwrap();
+        // a hello that says it's a megabyte long
+        stream.write_all(&[0, 0x10, 0, 0]).unwrap();
+        let err = serve(&ctx, conn).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::TooLarge);
+    }
+}
@@ -0,0 +1,294 @@
+//! Reconciliation is the recovery path for when incremental sync has drifted
+//! (missed records, a botched restore, a server-side fix). We grab every
+//! space/board/note the server has for us, compare ids and mod times with
+//! what's in local storage, and queue whatever downloads/uploads/deletes it
+//! takes to make the two agree.
+//!
+//! Anything with an outgoing sync still pending is left alone: outgoing sync
+//! will take care of it, and we don't want to stomp on local changes.
+
+use ::std::collections::{HashMap, HashSet};
+use ::std::mem;
+use ::jedi::{self, Value};
+use ::error::{TResult, TError};
+use ::api::ApiReq;
+use ::storage::Storage;
+use ::rusqlite::NO_PARAMS;
+use ::sync::sync_model::SyncModel;
+use ::sync::incoming;
+use ::models::model::Model;
+use ::models::space::Space;
+use ::models::board::Board;
+use ::models::note::Note;
+use ::models::sync_record::{SyncType, SyncAction, SyncRecord};
+use ::turtl::Turtl;
+use ::jobs;
+use ::util;
+
+/// The types we reconcile, along with the table they live in
+const RECONCILE_TYPES: &'static [(SyncType, &'static str)] = &[
+    (SyncType::Space, "spaces"),
+    (SyncType::Board, "boards"),
+    (SyncType::Note, "notes"),
+];
+
+/// What we know about one item (on either side) when diffing
+#[derive(Debug, Clone, Default)]
+struct ItemState {
+    id: String,
+    mod_: Option<i64>,
+    body: Option<String>,
+    user_id: Option<String>,
+}
+
+impl ItemState {
+    fn from_val(val: &Value) -> Option<ItemState> {
+        let id: String = match jedi::get_opt(&["id"], val) {
+            Some(x) => x,
+            None => return None,
+        };
+        Some(ItemState {
+            id: id,
+            mod_: jedi::get_opt(&["mod"], val),
+            body: jedi::get_opt(&["body"], val),
+            user_id: jedi::get_opt(&["user_id"], val),
+        })
+    }
+}
+
+/// What it takes to converge one type
+#[derive(Debug, Default, PartialEq)]
+struct Plan {
+    download: Vec<String>,
+    upload: Vec<(String, SyncAction)>,
+    delete: Vec<String>,
+}
+
+/// Figure out what to do to make our local items match the server's. The
+/// server wins ties, except when we've got a newer mod time or an item we
+/// created that the server never heard of.
+fn diff(local: &Vec<ItemState>, remote: &Vec<ItemState>, pending: &HashSet<String>, user_id: &String) -> Plan {
+    let mut plan = Plan::default();
+    let local_idx = local.iter()
+        .map(|x| (x.id.clone(), x))
+        .collect::<HashMap<_, _>>();
+    let remote_ids = remote.iter()
+        .map(|x| x.id.clone())
+        .collect::<HashSet<_>>();
+    for rem in remote {
+        if pending.contains(&rem.id) { continue; }
+        let loc = match local_idx.get(&rem.id) {
+            Some(x) => x,
+            None => {
+                plan.download.push(rem.id.clone());
+                continue;
+            }
+        };
+        match (loc.mod_, rem.mod_) {
+            (Some(lmod), Some(rmod)) if lmod > rmod => {
+                plan.upload.push((rem.id.clone(), SyncAction::Edit));
+            }
+            (Some(lmod), Some(rmod)) if lmod < rmod => {
+                plan.download.push(rem.id.clone());
+            }
+            _ => {
+                if loc.body != rem.body {
+                    plan.download.push(rem.id.clone());
+                }
+            }
+        }
+    }
+    for loc in local {
+        if remote_ids.contains(&loc.id) || pending.contains(&loc.id) { continue; }
+        if loc.user_id.as_ref() == Some(user_id) {
+            // ours, and the server lost it. send it back up.
+            plan.upload.push((loc.id.clone(), SyncAction::Add));
+        } else {
+            // someone else's item we can't see anymore (deleted, or we were
+            // kicked from the space)
+            plan.delete.push(loc.id.clone());
+        }
+    }
+    plan
+}
+
+/// What reconciling did (or, for a dry run, would do)
+#[derive(Serialize, Debug, Default)]
+pub struct ReconcileReport {
+    pub dry_run: bool,
+    pub downloaded: Vec<String>,
+    pub uploaded: Vec<String>,
+    pub deleted: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct FullProfile {
+    records: Vec<SyncRecord>,
+}
+
+/// Apply one type's plan to the db, returning records for the in-memory
+/// side of things (see `incoming::process_incoming_sync()`).
+fn apply<T>(db: &mut Storage, table: &str, ty: &SyncType, plan: &Plan, remote: &mut HashMap<String, SyncRecord>, user_id: &String) -> TResult<Vec<SyncRecord>>
+    where T: SyncModel
+{
+    let mut mem_records = Vec::new();
+    let handler: T = Default::default();
+    for id in &plan.download {
+        let mut rec = match remote.remove(id) {
+            Some(x) => x,
+            None => continue,
+        };
+        let data = rec.data.clone();
+        handler.incoming(db, &mut rec)?;
+        // incoming() takes the data, so put it back for the mem update
+        rec.data = data;
+        mem_records.push(rec);
+    }
+    for &(ref id, ref action) in &plan.upload {
+        let val = match db.values_by_id(table, &vec![id.clone()])?.pop() {
+            Some(x) => x,
+            None => continue,
+        };
+        let model: T = jedi::from_val(val)?;
+        model.outgoing(action.clone(), user_id, db, false)?;
+    }
+    for id in &plan.delete {
+        let mut model: T = Default::default();
+        model.set_id(id.clone());
+        model.db_delete(db, None)?;
+        let mut rec = SyncRecord::default();
+        rec.action = SyncAction::Delete;
+        rec.ty = ty.clone();
+        rec.item_id = id.clone();
+        rec.user_id = user_id.clone();
+        mem_records.push(rec);
+    }
+    Ok(mem_records)
+}
+
+/// Diff our local spaces/boards/notes against the server and converge. With
+/// `dry_run`, we just report what we'd do.
+pub fn reconcile(turtl: &Turtl, dry_run: bool) -> TResult<ReconcileReport> {
+    turtl.assert_connected()?;
+    let user_id = turtl.user_id()?;
+    let FullProfile { records } = turtl.api.get("/sync/full")?.call_opt(ApiReq::new().timeout(120))?;
+    jobs::check_cancelled()?;
+
+    // index the server's items by type
+    let mut remote_records: HashMap<String, HashMap<String, SyncRecord>> = HashMap::new();
+    let mut remote_states: HashMap<String, Vec<ItemState>> = HashMap::new();
+    for rec in records {
+        if rec.action == SyncAction::Delete { continue; }
+        let state = match rec.data.as_ref().and_then(|x| ItemState::from_val(x)) {
+            Some(x) => x,
+            None => continue,
+        };
+        let ty = util::enum_to_string(&rec.ty)?;
+        remote_states.entry(ty.clone()).or_insert(Vec::new()).push(state.clone());
+        remote_records.entry(ty).or_insert(HashMap::new()).insert(state.id, rec);
+    }
+
+    let mut report = ReconcileReport::default();
+    report.dry_run = dry_run;
+    let mut mem_records = Vec::new();
+    with_db!{ db, turtl.db,
+        let pending = SyncRecord::allbut(db, &vec![SyncType::FileOutgoing, SyncType::FileIncoming])?
+            .into_iter()
+            .map(|x| x.item_id)
+            .collect::<HashSet<_>>();
+        let total = RECONCILE_TYPES.len() as u64;
+        for (idx, &(ref ty, table)) in RECONCILE_TYPES.iter().enumerate() {
+            jobs::check_cancelled()?;
+            let ty_s = util::enum_to_string(ty)?;
+            let local = db.all_values(table)?
+                .iter()
+                .filter_map(|x| ItemState::from_val(x))
+                .collect::<Vec<_>>();
+            let remote = remote_states.remove(&ty_s).unwrap_or(Vec::new());
+            let plan = diff(&local, &remote, &pending, &user_id);
+            report.downloaded.extend(plan.download.iter().cloned());
+            report.uploaded.extend(plan.upload.iter().map(|x| x.0.clone()));
+            report.deleted.extend(plan.delete.iter().cloned());
+            if !dry_run {
+                let mut remote_recs = remote_records.remove(&ty_s).unwrap_or(HashMap::new());
+                db.conn.execute("BEGIN TRANSACTION", NO_PARAMS)?;
+                let res = match *ty {
+                    SyncType::Space => apply::<Space>(db, table, ty, &plan, &mut remote_recs, &user_id),
+                    SyncType::Board => apply::<Board>(db, table, ty, &plan, &mut remote_recs, &user_id),
+                    SyncType::Note => apply::<Note>(db, table, ty, &plan, &mut remote_recs, &user_id),
+                    _ => Ok(Vec::new()),
+                };
+                match res {
+                    Ok(recs) => {
+                        db.conn.execute("COMMIT TRANSACTION", NO_PARAMS)?;
+                        mem_records.extend(recs);
+                    }
+                    Err(e) => {
+                        match db.conn.execute("ROLLBACK TRANSACTION", NO_PARAMS) {
+                            Ok(_) => {}
+                            Err(e) => error!("reconcile::reconcile() -- error rolling back: {}", e),
+                        }
+                        return Err(e);
+                    }
+                }
+            }
+            jobs::progress(idx as u64 + 1, Some(total));
+        }
+    }
+    if dry_run { return Ok(report); }
+
+    // run our mem updates on this thread (we have the Turtl, after all)
+    {
+        let sync_config_guard = lockr!(turtl.sync_config);
+        for rec in mem_records.iter_mut() {
+            sync_config_guard.incoming_sync.push(mem::replace(rec, SyncRecord::default()));
+        }
+    }
+    incoming::process_incoming_sync(turtl)?;
+    info!("reconcile::reconcile() -- downloaded {}, uploaded {}, deleted {}", report.downloaded.len(), report.uploaded.len(), report.deleted.len());
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(id: &str, mod_: Option<i64>, body: &str, user_id: &str) -> ItemState {
+        ItemState {
+            id: String::from(id),
+            mod_: mod_,
+            body: Some(String::from(body)),
+            user_id: Some(String::from(user_id)),
+        }
+    }
+
+    #[test]
+    fn diffs_local_and_remote() {
+        let me = String::from("51");
+        let local = vec![
+            item("same", Some(10), "a", "51"),
+            item("stale", Some(10), "a", "51"),
+            item("newer", Some(20), "b", "51"),
+            item("nomod", None, "a", "51"),
+            item("mine", Some(10), "a", "51"),
+            item("theirs", Some(10), "a", "77"),
+            item("pending", Some(10), "a", "51"),
+        ];
+        let remote = vec![
+            item("same", Some(10), "a", "51"),
+            item("stale", Some(15), "c", "51"),
+            item("newer", Some(10), "a", "51"),
+            item("nomod", None, "z", "51"),
+            item("new", Some(10), "a", "77"),
+        ];
+        let mut pending = HashSet::new();
+        pending.insert(String::from("pending"));
+        let plan = diff(&local, &remote, &pending, &me);
+        assert_eq!(plan.download, vec![String::from("stale"), String::from("nomod"), String::from("new")]);
+        assert_eq!(plan.upload, vec![
+            (String::from("newer"), SyncAction::Edit),
+            (String::from("mine"), SyncAction::Add),
+        ]);
+        assert_eq!(plan.delete, vec![String::from("theirs")]);
+    }
+}
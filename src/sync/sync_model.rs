@@ -20,9 +20,12 @@ use ::models::file::FileData;
 use ::lib_permissions::Permission;
 use ::jedi::{self, Value};
 use ::turtl::Turtl;
+use ::profile::Profile;
 use ::std::mem;
 use ::time;
 use ::messaging;
+use ::scan;
+use ::config;
 
 pub trait SyncModel: Protected + Storable + Keyfinder + Sync + Send + 'static {
     /// Allows a model to handle an incoming sync item for its type.
@@ -143,14 +146,34 @@ pub trait MemorySaver: Protected {
         sync_item.item_id = self.id_or_else()?;
         sync_item.ty = SyncType::from_string(self.model_type())?;
         sync_item.data = Some(self.data()?);
+        let before = Profile::snapshot_before(turtl, &sync_item.ty, &sync_item.item_id);
         self.mem_update(turtl, &mut sync_item)?;
         if turtl.sync_ready() {
             messaging::ui_event("sync:update", &sync_item)?;
         }
+        match Profile::record_change(turtl, &sync_item, before) {
+            Ok(_) => {}
+            Err(e) => warn!("MemorySaver.run_mem_update() -- error sending profile change: {}", e),
+        }
         Ok(())
     }
 }
 
+/// Grab the size limit (in bytes) for a kind of object (`note`, `file`) from
+/// the `limits` config. 0 means no limit.
+pub fn size_limit(what: &str) -> u64 {
+    config::get(&["limits", what]).unwrap_or(0)
+}
+
+/// Make sure an object isn't over its size limit
+pub fn check_size(what: &str, size: u64) -> TResult<()> {
+    let limit = size_limit(what);
+    if limit > 0 && size > limit {
+        return TErr!(TError::TooLarge(String::from(what), limit, size));
+    }
+    Ok(())
+}
+
 /// Serialize this model and save it to the local db
 pub fn save_model<T>(action: SyncAction, turtl: &Turtl, model: &mut T, skip_remote_sync: bool) -> TResult<Value>
     where T: Protected + Storable + Keyfinder + SyncModel + MemorySaver + Validate + Sync + Send
@@ -191,6 +214,15 @@ pub fn save_model<T>(action: SyncAction, turtl: &Turtl, model: &mut T, skip_remo
     let keyrefs = model.get_keyrefs(&turtl)?;
     model.generate_subkeys(&keyrefs)?;
 
+    // TODO: is there a way around all the horrible cloning?
+    let mut model2: T = model.clone()?;
+    let serialized: Value = turtl.work.run(move || Protected::serialize(&mut model2))?;
+    // the encrypted body is what gets uploaded, so that's what we limit
+    if let Some(body) = jedi::get_opt::<String>(&["body"], &serialized) {
+        check_size(model.model_type(), body.len() as u64)?;
+    }
+    model.merge_fields(&serialized)?;
+
     if model.add_to_keychain() {
         keychain::save_key(
             turtl,
@@ -201,11 +233,6 @@ pub fn save_model<T>(action: SyncAction, turtl: &Turtl, model: &mut T, skip_remo
         )?;
     }
 
-    // TODO: is there a way around all the horrible cloning?
-    let mut model2: T = model.clone()?;
-    let serialized: Value = turtl.work.run(move || Protected::serialize(&mut model2))?;
-    model.merge_fields(&serialized)?;
-
     {
         let user_id = turtl.user_id()?;
         let mut db_guard = lock!(turtl.db);
@@ -248,6 +275,22 @@ pub fn delete_model<T>(turtl: &Turtl, id: &String, skip_remote_sync: bool) -> TR
     Ok(())
 }
 
+/// Grab a model as it's currently stored locally (so we can check permissions
+/// against where it *is* and not where the UI says it is)
+fn get_model<T>(turtl: &Turtl, id: &String) -> TResult<T>
+    where T: Protected + Storable
+{
+    let mut db_guard = lock!(turtl.db);
+    let db = match db_guard.as_mut() {
+        Some(x) => x,
+        None => return TErr!(TError::MissingField(format!("turtl is missing `db` object"))),
+    };
+    match db.get::<T>(T::tablename(), id)? {
+        Some(x) => Ok(x),
+        None => return TErr!(TError::NotFound(format!("that {} model wasn't found", T::tablename()))),
+    }
+}
+
 /// Given a sync record, dispatch it into the sync system, calling the
 /// appropriate functions and running any permissions checks.
 pub fn dispatch(turtl: &Turtl, sync_record: SyncRecord) -> TResult<Value> {
@@ -310,7 +353,15 @@ pub fn dispatch(turtl: &Turtl, sync_record: SyncRecord) -> TResult<Value> {
                         &SyncAction::Edit => Permission::EditBoard,
                         _ => return TErr!(TError::BadValue(format!("couldn't find permission for {:?}/{:?}", ty, action))),
                     };
-                    Space::permission_check(turtl, &model.space_id, &permission)?;
+                    match &action {
+                        // only space members can add boards to a space
+                        &SyncAction::Add => Space::permission_check(turtl, &model.space_id, &permission)?,
+                        _ => {
+                            // check against the space the board is actually in
+                            let stored = get_model::<Board>(turtl, &model.id_or_else()?)?;
+                            Board::permission_check(turtl, &stored.space_id, model.id(), &permission)?;
+                        }
+                    }
                     if action == SyncAction::Add {
                         model.user_id = turtl.user_id()?;
                     }
@@ -328,10 +379,29 @@ pub fn dispatch(turtl: &Turtl, sync_record: SyncRecord) -> TResult<Value> {
                         &SyncAction::Edit => Permission::EditNote,
                         _ => return TErr!(TError::BadValue(format!("couldn't find permission for {:?}/{:?}", ty, action))),
                     };
-                    Space::permission_check(turtl, &note.space_id, &permission)?;
+                    Board::permission_check(turtl, &note.space_id, note.board_id.as_ref(), &permission)?;
+                    if action == SyncAction::Edit {
+                        // being able to edit notes in the target board isn't
+                        // enough, we also need to be able to edit the note
+                        // where it lives now (otherwise a board member could
+                        // pull any note in the space into their board)
+                        let stored = get_model::<Note>(turtl, &note.id_or_else()?)?;
+                        Board::permission_check(turtl, &stored.space_id, stored.board_id.as_ref(), &Permission::EditNote)?;
+                    }
+                    // check the attachment before saving the note, so we
+                    // don't end up with a note that's missing its file
+                    if let Some(data) = filemebbe.as_ref().and_then(|x| x.data.as_ref()) {
+                        check_size("file", data.len() as u64)?;
+                    }
                     if action == SyncAction::Add {
                         note.user_id = turtl.user_id()?;
                     }
+                    note.parse_fields(turtl)?;
+                    note.sanitize();
+                    if let (Some(filedata), Some(file)) = (filemebbe.as_ref().and_then(|x| x.data.as_ref()), note.file.as_mut()) {
+                        file.detect_audio_meta(filedata);
+                        file.scan = scan::scan(filedata, file.name.as_ref().map(|x| x.as_str()))?;
+                    }
                     // always set to false. this is a public field that
                     // we let the server manage for us
                     note.has_file = false;
@@ -341,6 +411,9 @@ pub fn dispatch(turtl: &Turtl, sync_record: SyncRecord) -> TResult<Value> {
                     match filemebbe {
                         Some(mut file) => {
                             file.save(turtl, &mut note)?;
+                            if note.file.as_ref().and_then(|x| x.scan.as_ref()).map(|x| !x.clean()).unwrap_or(false) {
+                                scan::quarantined(&note);
+                            }
                         }
                         None => {}
                     }
@@ -354,19 +427,6 @@ pub fn dispatch(turtl: &Turtl, sync_record: SyncRecord) -> TResult<Value> {
         }
         SyncAction::Delete => {
             let id: String = jedi::get(&["id"], &modeldata)?;
-            fn get_model<T>(turtl: &Turtl, id: &String) -> TResult<T>
-                where T: Protected + Storable
-            {
-                let mut db_guard = lock!(turtl.db);
-                let db = match db_guard.as_mut() {
-                    Some(x) => x,
-                    None => return TErr!(TError::MissingField(format!("turtl is missing `db` object"))),
-                };
-                match db.get::<T>(T::tablename(), id)? {
-                    Some(x) => Ok(x),
-                    None => return TErr!(TError::NotFound(format!("that {} model wasn't found", T::tablename()))),
-                }
-            }
             match ty {
                 SyncType::Space => {
                     Space::permission_check(turtl, &id, &Permission::DeleteSpace)?;
@@ -374,17 +434,17 @@ pub fn dispatch(turtl: &Turtl, sync_record: SyncRecord) -> TResult<Value> {
                 }
                 SyncType::Board => {
                     let model = get_model::<Board>(turtl, &id)?;
-                    Space::permission_check(turtl, &model.space_id, &Permission::DeleteBoard)?;
+                    Board::permission_check(turtl, &model.space_id, Some(&id), &Permission::DeleteBoard)?;
                     delete_model::<Board>(turtl, &id, false)?;
                 }
                 SyncType::Note => {
                     let model = get_model::<Note>(turtl, &id)?;
-                    Space::permission_check(turtl, &model.space_id, &Permission::DeleteNote)?;
+                    Board::permission_check(turtl, &model.space_id, model.board_id.as_ref(), &Permission::DeleteNote)?;
                     delete_model::<Note>(turtl, &id, false)?;
                 }
                 SyncType::File => {
                     let model = get_model::<Note>(turtl, &id)?;
-                    Space::permission_check(turtl, &model.space_id, &Permission::EditNote)?;
+                    Board::permission_check(turtl, &model.space_id, model.board_id.as_ref(), &Permission::EditNote)?;
                     delete_model::<FileData>(turtl, &id, false)?;
                 }
                 _ => {
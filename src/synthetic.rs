@@ -0,0 +1,253 @@
+//! Generates made-up profiles (spaces, boards, and a pile of notes of all
+//! types, some with files) for testing how the app holds up as profiles grow.
+//! Everything is saved through the same path the UI uses, so a generated
+//! profile indexes, syncs, and exports just like a real one.
+//!
+//! Profiles come from a seed: the same seed and sizes give the same profile
+//! (apart from ids and keys).
+//!
+//! Built with the `bench` feature (for `app:benchmark`) or the
+//! `debug-commands` feature, which adds
+//!
+//!     ["<message id>", "debug:generate-profile", {"notes": 5000, ...}]
+//!
+//! to fill the logged-in profile.
+
+use ::jedi::{self, Value};
+use ::time;
+use ::error::{TResult, TError};
+use ::turtl::Turtl;
+use ::jobs;
+use ::crypto;
+use ::models::space::Space;
+use ::models::board::Board;
+use ::models::sync_record::{SyncAction, SyncType, SyncRecord};
+use ::sync::sync_model;
+#[cfg(feature = "debug-commands")]
+use ::dispatch::registry::Registry;
+
+/// Words we build titles, text, and tags out of
+const WORDS: &'static [&'static str] = &[
+    "turtle", "garden", "recipe", "invoice", "meeting", "travel", "budget",
+    "project", "river", "mountain", "coffee", "lantern", "harbor", "violet",
+    "engine", "pocket", "summer", "winter", "ledger", "quartz", "window",
+    "market", "letter", "signal", "forest", "candle", "bridge", "orbit",
+    "pepper", "saddle", "thunder", "velvet", "castle", "marble", "anchor",
+];
+
+/// Space colors
+const COLORS: &'static [&'static str] = &["#408080", "#a05050", "#5070b0", "#70a040", "#b08030"];
+
+/// How big a profile to make
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct GenerateOptions {
+    pub spaces: u32,
+    pub boards_per_space: u32,
+    /// Notes in total (spread over the boards)
+    pub notes: u32,
+    /// How many of the notes get a file attached
+    pub attachments: u32,
+    /// Size of each attachment, in bytes
+    pub attachment_size: usize,
+    /// Leave out for a different profile each time
+    pub seed: Option<u64>,
+}
+
+impl Default for GenerateOptions {
+    fn default() -> GenerateOptions {
+        GenerateOptions {
+            spaces: 3,
+            boards_per_space: 4,
+            notes: 1000,
+            attachments: 20,
+            attachment_size: 64 * 1024,
+            seed: None,
+        }
+    }
+}
+
+/// What we made
+#[derive(Serialize, Debug, Default)]
+pub struct Generated {
+    pub seed: u64,
+    pub space_ids: Vec<String>,
+    pub board_ids: Vec<String>,
+    pub notes: u32,
+    pub attachments: u32,
+}
+
+/// A tiny (xorshift) rng, so the same seed gets the same profile
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng(if seed == 0 { 1 } else { seed })
+    }
+
+    pub fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// A number in `0..max`
+    pub fn below(&mut self, max: u64) -> u64 {
+        if max == 0 { 0 } else { self.next() % max }
+    }
+
+    pub fn word(&mut self) -> &'static str {
+        WORDS[self.below(WORDS.len() as u64) as usize]
+    }
+
+    pub fn words(&mut self, count: usize) -> String {
+        (0..count).map(|_| self.word()).collect::<Vec<_>>().join(" ")
+    }
+}
+
+/// Make up the data for one note, the way the UI would send it
+fn note_data(rng: &mut Rng, space_id: &String, board_id: &String) -> TResult<Value> {
+    let mut tags = (0..rng.below(4)).map(|_| rng.word()).collect::<Vec<_>>();
+    tags.sort();
+    tags.dedup();
+    let mut data = json!({
+        "space_id": space_id,
+        "board_id": board_id,
+        "title": rng.words(1 + rng.below(5) as usize),
+        "tags": tags,
+    });
+    let domain = rng.word();
+    let path = rng.word();
+    let (ty, extra) = match rng.below(10) {
+        x if x < 6 => ("text", json!({"text": paragraphs(rng)})),
+        6 | 7 => ("link", json!({
+            "url": format!("https://{}.example.com/{}", domain, path),
+            "text": rng.words(10 + rng.below(30) as usize),
+        })),
+        8 => ("password", json!({
+            "username": format!("{}@example.com", domain),
+            "password": rng.words(3).replace(" ", "-"),
+            "url": format!("https://{}.example.com/login", domain),
+        })),
+        _ => ("text", json!({"text": paragraphs(rng), "color": rng.below(7) as i64})),
+    };
+    jedi::set(&["type"], &mut data, &ty)?;
+    if let Value::Object(extra) = extra {
+        for (key, val) in extra {
+            jedi::set(&[key.as_str()], &mut data, &val)?;
+        }
+    }
+    Ok(data)
+}
+
+/// A few paragraphs of nonsense, markdown-ish
+fn paragraphs(rng: &mut Rng) -> String {
+    (0..(1 + rng.below(4)))
+        .map(|_| {
+            let len = 10 + rng.below(80) as usize;
+            match rng.below(6) {
+                0 => format!("## {}", rng.words(3)),
+                1 => (0..3).map(|_| format!("- {}", rng.words(4))).collect::<Vec<_>>().join("\n"),
+                _ => rng.words(len),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// Fill the logged-in profile with made-up spaces, boards, and notes
+pub fn generate(turtl: &Turtl, options: &GenerateOptions) -> TResult<Generated> {
+    if options.spaces == 0 || options.boards_per_space == 0 {
+        return TErr!(TError::BadValue(String::from("need at least one space and one board per space")));
+    }
+    let seed = match options.seed {
+        Some(x) => x,
+        None => time::precise_time_ns(),
+    };
+    let mut rng = Rng::new(seed);
+    let mut generated = Generated { seed: seed, ..Default::default() };
+    let user_id = turtl.user_id()?;
+    let total = (options.spaces * (options.boards_per_space + 1) + options.notes) as u64;
+    let mut count = 0;
+    let mut boards = Vec::new();
+    for _ in 0..options.spaces {
+        let mut space: Space = Default::default();
+        space.user_id = user_id.clone();
+        space.title = Some(rng.words(1 + rng.below(2) as usize));
+        space.color = Some(String::from(COLORS[rng.below(COLORS.len() as u64) as usize]));
+        let val = sync_model::save_model(SyncAction::Add, turtl, &mut space, false)?;
+        let space_id: String = jedi::get(&["id"], &val)?;
+        count += 1;
+        for _ in 0..options.boards_per_space {
+            let mut board: Board = Default::default();
+            board.user_id = user_id.clone();
+            board.space_id = space_id.clone();
+            board.title = Some(rng.words(1 + rng.below(2) as usize));
+            let val = sync_model::save_model(SyncAction::Add, turtl, &mut board, false)?;
+            let board_id: String = jedi::get(&["id"], &val)?;
+            generated.board_ids.push(board_id.clone());
+            boards.push((space_id.clone(), board_id));
+            count += 1;
+        }
+        generated.space_ids.push(space_id);
+        jobs::progress(count, Some(total));
+    }
+    for i in 0..options.notes {
+        jobs::check_cancelled()?;
+        // some boards get a lot more notes than others
+        let idx = rng.below(boards.len() as u64).min(rng.below(boards.len() as u64)) as usize;
+        let (ref space_id, ref board_id) = boards[idx];
+        let mut data = note_data(&mut rng, space_id, board_id)?;
+        if i < options.attachments {
+            let filedata = (0..options.attachment_size).map(|_| rng.next() as u8).collect::<Vec<_>>();
+            let file = json!({
+                "name": format!("{}.bin", rng.word()),
+                "type": "application/octet-stream",
+                "size": filedata.len(),
+                "filedata": {"data": crypto::to_base64(&filedata)?},
+            });
+            jedi::set(&["file"], &mut data, &file)?;
+            generated.attachments += 1;
+        }
+        let mut record = SyncRecord::default();
+        record.action = SyncAction::Add;
+        record.ty = SyncType::Note;
+        record.data = Some(data);
+        sync_model::dispatch(turtl, record)?;
+        generated.notes += 1;
+        count += 1;
+        jobs::progress(count, Some(total));
+    }
+    info!("synthetic::generate() -- made {} spaces, {} boards, {} notes (seed {})", generated.space_ids.len(), generated.board_ids.len(), generated.notes, seed);
+    Ok(generated)
+}
+
+/// Registers our generator command
+#[cfg(feature = "debug-commands")]
+pub fn register(reg: &mut Registry) {
+    reg.add("debug:generate-profile", |turtl, args| {
+        let options: GenerateOptions = args.get_opt(2).unwrap_or(Default::default());
+        Ok(jedi::to_val(&generate(turtl, &options)?)?)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn makes_up_notes() {
+        let space_id = String::from("s1");
+        let board_id = String::from("b1");
+        let mut rng1 = Rng::new(42);
+        let mut rng2 = Rng::new(42);
+        for _ in 0..50 {
+            let note = note_data(&mut rng1, &space_id, &board_id).unwrap();
+            assert_eq!(note, note_data(&mut rng2, &space_id, &board_id).unwrap());
+            let ty: String = jedi::get(&["type"], &note).unwrap();
+            assert!(["text", "link", "password"].contains(&ty.as_str()));
+            assert_eq!(jedi::get::<String>(&["board_id"], &note).unwrap(), "b1");
+        }
+    }
+}
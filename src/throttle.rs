@@ -0,0 +1,131 @@
+//! Slows down password guessing on this device. After a few failed logins, each
+//! new failure locks logins out for twice as long as the last one (up to a
+//! limit). The state lives in our kv store so restarting the app doesn't reset
+//! it, and the UI gets a `user:login-throttled` event telling it how long to
+//! wait.
+//!
+//! Only bad passwords count. Network errors and the like don't.
+
+use ::std::cmp;
+use ::time;
+use ::jedi;
+use ::error::{TResult, TError};
+use ::api::StatusCode;
+use ::config;
+use ::messaging;
+use ::turtl::Turtl;
+
+/// The kv key we keep our throttle state under
+const STATE_KEY: &'static str = "login_throttle";
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct Throttle {
+    /// Failed attempts since the last successful login
+    pub failures: u32,
+    /// No attempts allowed until this time
+    pub locked_until: i64,
+}
+
+fn now() -> i64 {
+    time::get_time().sec as i64
+}
+
+/// How long (in seconds) we lock logins for after `failures` failed attempts
+fn delay_for(failures: u32, free_attempts: u32, base_delay: i64, max_delay: i64) -> i64 {
+    if failures < free_attempts { return 0; }
+    let doublings = cmp::min(failures - free_attempts, 30);
+    cmp::min(base_delay.saturating_mul(1i64 << doublings), max_delay)
+}
+
+/// Whether an error means the password was wrong (as opposed to us not being
+/// able to ask)
+fn is_bad_login(err: &TError) -> bool {
+    match *err {
+        TError::Api(ref status, _) => *status == StatusCode::UNAUTHORIZED,
+        TError::Wrapped(_, _, _, ref inner) => is_bad_login(inner),
+        _ => false,
+    }
+}
+
+fn load(turtl: &Turtl) -> TResult<Throttle> {
+    let kv_guard = lockr!(turtl.kv);
+    match kv_guard.kv_get(STATE_KEY)? {
+        Some(x) => Ok(jedi::parse(&x)?),
+        None => Ok(Default::default()),
+    }
+}
+
+fn save(turtl: &Turtl, throttle: &Throttle) -> TResult<()> {
+    let kv_guard = lockr!(turtl.kv);
+    kv_guard.kv_set(STATE_KEY, &jedi::stringify(throttle)?)
+}
+
+/// Let the UI know how long it has to wait
+fn notify(throttle: &Throttle) {
+    let wait = throttle.locked_until - now();
+    messaging::ui_event("user:login-throttled", &json!({
+        "failures": throttle.failures,
+        "locked_until": throttle.locked_until,
+        "wait": if wait > 0 { wait } else { 0 },
+    })).unwrap_or_else(|e| error!("throttle::notify() -- error sending throttle event: {}", e));
+}
+
+/// Make sure we're allowed to try a login right now
+pub fn check(turtl: &Turtl) -> TResult<()> {
+    let throttle = load(turtl)?;
+    let wait = throttle.locked_until - now();
+    if wait > 0 {
+        notify(&throttle);
+        return TErr!(TError::PermissionDenied(format!("too many failed login attempts. try again in {} seconds", wait)));
+    }
+    Ok(())
+}
+
+/// Record how a login attempt went
+pub fn record(turtl: &Turtl, res: &TResult<()>) -> TResult<()> {
+    match *res {
+        Ok(_) => {
+            let kv_guard = lockr!(turtl.kv);
+            kv_guard.kv_delete(STATE_KEY)
+        }
+        Err(ref e) if is_bad_login(e) => {
+            let mut throttle = load(turtl)?;
+            throttle.failures += 1;
+            let delay = delay_for(
+                throttle.failures,
+                config::get(&["login", "throttle", "free_attempts"]).unwrap_or(3),
+                config::get(&["login", "throttle", "base_delay"]).unwrap_or(5),
+                config::get(&["login", "throttle", "max_delay"]).unwrap_or(3600),
+            );
+            if delay > 0 {
+                throttle.locked_until = now() + delay;
+                warn!("throttle::record() -- {} failed logins, locking for {}s", throttle.failures, delay);
+            }
+            save(turtl, &throttle)?;
+            if delay > 0 { notify(&throttle); }
+            Ok(())
+        }
+        Err(_) => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::jedi::Value;
+
+    #[test]
+    fn backs_off() {
+        assert_eq!(delay_for(0, 3, 5, 3600), 0);
+        assert_eq!(delay_for(2, 3, 5, 3600), 0);
+        assert_eq!(delay_for(3, 3, 5, 3600), 5);
+        assert_eq!(delay_for(4, 3, 5, 3600), 10);
+        assert_eq!(delay_for(6, 3, 5, 3600), 40);
+        assert_eq!(delay_for(20, 3, 5, 3600), 3600);
+        assert_eq!(delay_for(500, 3, 5, 3600), 3600);
+
+        assert!(is_bad_login(&twrap!(TError::Api(StatusCode::UNAUTHORIZED, Value::Null))));
+        assert!(!is_bad_login(&TError::Api(StatusCode::BAD_GATEWAY, Value::Null)));
+        assert!(!is_bad_login(&TError::Msg(String::from("connection refused"))));
+    }
+}
@@ -7,11 +7,12 @@ use ::std::ops::Drop;
 use ::std::fs;
 use ::regex::Regex;
 use ::num_cpus;
+use ::futures::{future, Future};
 use ::jedi::{self, Value};
 use ::config;
-use ::error::{TResult, TError};
+use ::error::{TResult, TError, ErrorCode};
 use ::crypto::Key;
-use ::util;
+use ::util::{self, paths};
 use ::util::thredder::Thredder;
 use ::storage::{self, Storage};
 use ::api::Api;
@@ -22,6 +23,7 @@ use ::models::user::{self, User};
 use ::models::space::Space;
 use ::models::board::Board;
 use ::models::invite::Invite;
+use ::models::contact::Contact;
 use ::models::keychain::KeychainEntry;
 use ::models::note::Note;
 use ::models::file::FileData;
@@ -29,25 +31,42 @@ use ::models::sync_record::{SyncRecord, SyncAction};
 use ::messaging::{self, Messenger, Response};
 use ::sync::{self, SyncConfig, SyncState};
 use ::sync::sync_model::MemorySaver;
-use ::search::Search;
+use ::sync::connectivity::Connectivity;
+use ::search::{Search, IndexStats};
+use ::archive;
+use ::render;
+use ::jobs;
+use ::undo;
+use ::throttle;
+use ::wipe::{self, WipeReport};
+use ::analyzer::{self, Analyzer};
+use ::folder_sync;
+use ::recurrence;
+use ::backup;
 use ::schema;
 use ::migrate::{self, MigrateResult};
 use ::std::collections::HashMap;
 
-pub fn data_folder() -> TResult<String> {
-    let integration = config::get::<String>(&["integration_tests", "data_folder"])?;
-    if cfg!(test) {
-        return Ok(integration);
-    }
-    let data_folder = config::get::<String>(&["data_folder"])?;
-    let final_folder = if data_folder == ":memory:" {
-        integration
-    } else {
-        data_folder
-    };
-    Ok(final_folder)
+/// Where we keep cached data (thumbnails, decrypted bits, etc). Anything in
+/// here can be rebuilt, so it's safe to blow away at any time.
+pub fn cache_folder() -> TResult<String> {
+    Ok(paths::data_path(&["cache"])?.to_string_lossy().into_owned())
 }
 
+/// Make sure a user id is what the API hands out (digits, or hex) before it
+/// goes anywhere near a file path or glob.
+fn check_user_id(user_id: &String) -> TResult<()> {
+    if user_id.is_empty() || !user_id.chars().all(|c| c.is_ascii_hexdigit()) {
+        return TErr!(TError::BadValue(format!("invalid user id: {:?}", user_id)));
+    }
+    Ok(())
+}
+
+/// How many models' keys we hand to each worker at once when bulk-decrypting
+/// keys in `find_models_keys()`. Key decryption is cheap, so sending them off
+/// one at a time spends more on the handoff than on the crypto.
+const KEY_DECRYPT_BATCH: usize = 64;
+
 /// Defines a container for our app's state. Note that most operations the user
 /// has access to via messaging get this object passed to them.
 pub struct Turtl {
@@ -95,6 +114,11 @@ pub struct Turtl {
     pub incoming_sync_lock: Mutex<()>,
     /// Whether or not we're connected to the API
     pub connected: RwLock<bool>,
+    /// Our best guess as to why we are (or aren't) connected
+    pub connectivity: RwLock<Connectivity>,
+    /// Whether we're a throwaway Turtl (see `new_scratch()`). Scratch Turtls
+    /// don't shut the app down when they're dropped.
+    scratch: bool,
 }
 
 impl Turtl {
@@ -121,11 +145,31 @@ impl Turtl {
             sync_config: Arc::new(RwLock::new(SyncConfig::new())),
             sync_state: Arc::new(RwLock::new(None)),
             connected: RwLock::new(false),
+            connectivity: RwLock::new(Connectivity::Unknown),
             incoming_sync_lock: Mutex::new(()),
+            scratch: false,
         };
         Ok(turtl)
     }
 
+    /// Create a throwaway Turtl with a made-up user logged in and an in-memory
+    /// user db, for running commands that shouldn't touch the real profile
+    /// (like replaying a journal). Nothing it does is synced or saved.
+    pub fn new_scratch() -> TResult<Turtl> {
+        let mut turtl = Turtl::new()?;
+        turtl.scratch = true;
+        let mut user = User::default();
+        user.id = Some(String::from("scratch"));
+        user.username = String::from("scratch@localhost");
+        user.do_login(Key::random()?, String::new());
+        *lockw!(turtl.user) = user;
+        turtl.set_user_id();
+        let db = Storage::new(&String::from(paths::MEMORY), schema::get_schema())?;
+        db.seed_cid_floor()?;
+        *lock!(turtl.db) = Some(db);
+        Ok(turtl)
+    }
+
     /// Create/open a new KV store connection
     pub fn open_kv() -> TResult<Storage> {
         let kv_location = storage::db_location(&String::from("turtl-kv"))?;
@@ -133,30 +177,42 @@ impl Turtl {
     }
 
     /// Send a message to (presumably) our UI.
-    pub fn remote_send(&self, id: Option<String>, msg: String) -> TResult<()> {
+    pub fn remote_send(&self, id: Option<String>, msg: Vec<u8>) -> TResult<()> {
         match id {
             Some(id) => self.msg.send_suffix(id, msg),
             None => self.msg.send(msg),
         }
     }
 
-    /// Send a success response to a remote request
-    pub fn msg_success(&self, mid: &String, data: Value) -> TResult<()> {
+    /// Send a response to a remote request. Responses for a named client go out
+    /// on that client's channel (see `messaging`).
+    fn respond(&self, client: Option<&String>, mid: &String, e: i64, data: Value, code: Option<ErrorCode>) -> TResult<()> {
         let reqres_append_mid: bool = config::get(&["messaging", "reqres_append_mid"])?;
-        if reqres_append_mid {
-            let res = Response::new(0, data);
-            let msg = jedi::stringify(&res)?;
-            self.remote_send(Some(mid.clone()), msg)
+        let (res, suffix) = if reqres_append_mid {
+            (Response::new(e, data), Some(mid.clone()))
         } else {
-            let res = Response::new_w_id(mid.clone(), 0, data);
-            let msg = jedi::stringify(&res)?;
-            self.remote_send(None, msg)
-        }
+            (Response::new_w_id(mid.clone(), e, data), None)
+        };
+        let res = match code {
+            Some(code) => res.with_code(code),
+            None => res,
+        };
+        let suffix = match (client, suffix) {
+            (Some(client), Some(mid)) => Some(format!("{}:{}", client, mid)),
+            (Some(client), None) => Some(client.clone()),
+            (None, suffix) => suffix,
+        };
+        let msg = messaging::encode(messaging::format(), &res)?;
+        self.remote_send(suffix, msg)
     }
 
-    /// Send an error response to a remote request
-    pub fn msg_error(&self, mid: &String, err: &TError) -> TResult<()> {
-        let reqres_append_mid: bool = config::get(&["messaging", "reqres_append_mid"])?;
+    /// Send a success response to a remote request
+    pub fn msg_success(&self, client: Option<&String>, mid: &String, data: Value) -> TResult<()> {
+        self.respond(client, mid, 0, data, None)
+    }
+
+    /// Turn an error into the value we send back to the UI
+    pub fn error_value(err: &TError) -> TResult<Value> {
         let mut errval = util::json_or_string(format!("{}", err));
         let wrapped = match jedi::get_opt::<bool>(&["wrapped"], &errval) {
             Some(x) => x,
@@ -169,15 +225,13 @@ impl Turtl {
         if !wrap_errors && wrapped {
             errval = jedi::get(&["err"], &errval)?;
         }
-        if reqres_append_mid {
-            let res = Response::new(1, errval);
-            let msg = jedi::stringify(&res)?;
-            self.remote_send(Some(mid.clone()), msg)
-        } else {
-            let res = Response::new_w_id(mid.clone(), 1, errval);
-            let msg = jedi::stringify(&res)?;
-            self.remote_send(None, msg)
-        }
+        Ok(errval)
+    }
+
+    /// Send an error response to a remote request
+    pub fn msg_error(&self, client: Option<&String>, mid: &String, err: &TError) -> TResult<()> {
+        let errval = Turtl::error_value(err)?;
+        self.respond(client, mid, 1, errval, Some(err.code()))
     }
 
     /// If the `turtl.user` object has a valid ID, set it into `turtl.user_id`
@@ -229,7 +283,10 @@ impl Turtl {
 
     /// Log a user in
     pub fn login(&self, username: String, password: String) -> TResult<()> {
-        User::login(self, username, password, user::CURRENT_AUTH_VERSION)?;
+        throttle::check(self)?;
+        let res = User::login(self, username, password, user::CURRENT_AUTH_VERSION);
+        throttle::record(self, &res)?;
+        res?;
         self.post_login()
     }
 
@@ -274,8 +331,66 @@ impl Turtl {
         self.do_join(new_username, new_password, Some(migrate_data))
     }
 
+    /// The server stopped taking our credentials mid-session (password changed
+    /// on another device, account suspended, etc). Pause sync, forget our keys,
+    /// and ask the UI for the password so we can pick back up with `reauth()`.
+    pub fn auth_invalidated(&self) -> TResult<()> {
+        let username = {
+            let mut user_guard = lockw!(self.user);
+            // already handled (or we were never logged in to begin with)
+            if !user_guard.logged_in { return Ok(()); }
+            user_guard.do_logout();
+            user_guard.username.clone()
+        };
+        warn!("turtl.auth_invalidated() -- the api rejected our auth, pausing sync");
+        self.sync_pause();
+        self.api.clear_auth();
+        lockw!(self.profile).wipe();
+        messaging::ui_event("user:reauth-required", &json!({"username": username}))?;
+        Ok(())
+    }
+
+    /// Log back in after our auth was invalidated, reload the profile, and
+    /// resume syncing. If the username changed too, pass in the new one.
+    pub fn reauth(&self, username: Option<String>, password: String) -> TResult<()> {
+        let (user_id, cur_username) = {
+            let user_guard = lockr!(self.user);
+            if user_guard.logged_in {
+                return TErr!(TError::BadValue(String::from("already logged in")));
+            }
+            (user_guard.id.clone(), user_guard.username.clone())
+        };
+        if user_id.is_none() {
+            return TErr!(TError::MissingField(String::from("Turtl.user.id")));
+        }
+        throttle::check(self)?;
+        let res = User::login(self, username.unwrap_or(cur_username), password, user::CURRENT_AUTH_VERSION);
+        throttle::record(self, &res)?;
+        res?;
+        if lockr!(self.user).id != user_id {
+            // these credentials are for someone else. don't mix their data in
+            // with ours.
+            self.logout()?;
+            return TErr!(TError::PermissionDenied(String::from("those credentials belong to a different account")));
+        }
+        self.load_profile()?;
+        messaging::ui_event("profile:loaded", &())?;
+        self.sync_resume();
+        messaging::ui_event("user:reauth-complete", &())?;
+        Ok(())
+    }
+
     /// Log a user out
     pub fn logout(&self) -> TResult<()> {
+        folder_sync::stop_watcher();
+        recurrence::stop_watcher();
+        backup::stop_watcher();
+        sync::peer::stop();
+        jobs::clear();
+        undo::clear();
+        render::clear_cache();
+        FileData::clear_range_cache();
+        sync::metrics::reset();
         {
             let mut profile_guard = lockw!(self.profile);
             profile_guard.wipe();
@@ -363,6 +478,16 @@ impl Turtl {
         // our heroic db, error out ='[
         self.check_db_exists()?;
 
+        // in safe mode, we load what's in the local db and that's it. no sync
+        // threads, no search index, no folder watchers (any of which might be
+        // what's crashing the profile).
+        if util::safe_mode() {
+            warn!("turtl.sync_start() -- safe mode: loading profile without sync/search");
+            self.load_profile()?;
+            messaging::ui_event("profile:loaded", &())?;
+            return Ok(());
+        }
+
         // increment our run version to catch rogue sync threads
         {
             let mut sync_config_guard = lockw!(self.sync_config);
@@ -383,6 +508,16 @@ impl Turtl {
         messaging::ui_event("profile:loaded", &())?;
         self.index_notes()?;
         messaging::ui_event("profile:indexed", &())?;
+        if config::get::<bool>(&["archive", "enabled"]).unwrap_or(false) {
+            match archive::prune(self).and_then(|_| archive::archive_cold_notes(self)) {
+                Ok(_) => {},
+                Err(e) => warn!("turtl.post_login() -- problem archiving cold notes: {}", e),
+            }
+        }
+
+        folder_sync::start_if_enabled(self);
+        recurrence::start_watcher();
+        backup::start_if_enabled(self);
 
         // wipe our incoming sync queue. we're about to synchronize all our
         // in-mem state with what's in the DB, so we don't really need to run
@@ -427,6 +562,7 @@ impl Turtl {
         // set connected to false on sync shutdown
         let mut connguard = lockw!(self.connected);
         *connguard = false;
+        *lockw!(self.connectivity) = Connectivity::Unknown;
         Ok(())
     }
 
@@ -460,11 +596,55 @@ impl Turtl {
     }
 
     /// Create a new per-user database for the current user.
+    ///
+    /// If the db turns out to be corrupt, we let the UI know (`storage:corrupt`)
+    /// and move the damaged db out of the way. The UI can then call
+    /// `app:storage:recover` to rebuild everything from the server.
     pub fn create_user_db(&self) -> TResult<Storage> {
         let user_id = self.user_id()?;
         let db_location = self.get_user_db_location(&user_id)?;
         let dumpy_schema = schema::get_schema();
-        Storage::new(&db_location, dumpy_schema)
+        let db = Storage::new(&db_location, dumpy_schema)
+            .or_else(|e| {
+                let e = e.shed();
+                match e {
+                    TError::StorageCorrupt(msg) => {
+                        error!("turtl.create_user_db() -- corrupt db: {}", msg);
+                        let moved_to = storage::quarantine(&db_location)?;
+                        messaging::ui_event("storage:corrupt", &json!({"reason": msg, "moved_to": moved_to}))?;
+                        TErr!(TError::StorageCorrupt(msg))
+                    }
+                    _ => Err(e),
+                }
+            })?;
+        db.seed_cid_floor()?;
+        Ok(db)
+    }
+
+    /// Run a full integrity check on the current user's db. We only do this on
+    /// our own when the db wasn't closed cleanly, so this is for when the UI
+    /// suspects something's off. Returns what's wrong, if anything.
+    pub fn check_storage(&self) -> TResult<Option<String>> {
+        with_db!{ db, self.db, db.check_integrity() }
+    }
+
+    /// Throw out the current user's local db and start fresh. Everything gets
+    /// rebuilt from the server once the sync starts back up (call `sync:start`
+    /// after this), although any changes that hadn't synced yet are lost.
+    pub fn recover_storage(&self) -> TResult<()> {
+        let user_id = self.user_id()?;
+        self.sync_shutdown(false)?;
+        self.close_user_db()?;
+        self.close_search();
+        let db_location = self.get_user_db_location(&user_id)?;
+        if db_location != ":memory:" && fs::metadata(&db_location).is_ok() {
+            storage::quarantine(&db_location)?;
+        }
+        {
+            let mut profile_guard = lockw!(self.profile);
+            profile_guard.wipe();
+        }
+        self.post_login()
     }
 
     /// Close the per-user database.
@@ -477,6 +657,23 @@ impl Turtl {
         Ok(())
     }
 
+    /// Clear out any stale storage locks (our kv store and, if given, a user's
+    /// db). This is for when a core crashed or was killed without cleaning up
+    /// after itself. Errors if a live process has one of them open.
+    pub fn force_unlock_storage(&self, user_id: Option<&String>) -> TResult<Vec<String>> {
+        let mut locations = vec![storage::db_location(&String::from("turtl-kv"))?];
+        if let Some(user_id) = user_id {
+            locations.push(self.get_user_db_location(user_id)?);
+        }
+        let mut unlocked = Vec::new();
+        for location in locations {
+            if storage::force_unlock(&location)? {
+                unlocked.push(location);
+            }
+        }
+        Ok(unlocked)
+    }
+
     /// Shut down the search system
     pub fn close_search(&self) {
         let mut search_guard = lock!(self.search);
@@ -489,6 +686,7 @@ impl Turtl {
         lazy_static! {
             static ref RE_API_FORMAT: Regex = Regex::new(r"(?i)[^a-z0-9]").expect("turtl::Turtl.get_user_db_location() -- failed to compile regex");
         }
+        check_user_id(user_id)?;
         let api_endpoint = config::get::<String>(&["api", "endpoint"])?;
         let server = RE_API_FORMAT.replace_all(&api_endpoint, "");
         let user_db = format!("turtl-user-{}-srv-{}", user_id, server);
@@ -517,9 +715,10 @@ impl Turtl {
 
         // the user object is encrypted with the master key.
         //
-        // keychain entries are always encrypted using the user's key, so we
-        // skip the song and dance of searching and just set it in here.
-        if (model.model_type() == "user" && model.id_or_else()? == self.user_id()?) || model.model_type() == "keychain" {
+        // keychain entries (and contacts) are always encrypted using the
+        // user's key, so we skip the song and dance of searching and just set
+        // it in here.
+        if (model.model_type() == "user" && model.id_or_else()? == self.user_id()?) || model.model_type() == "keychain" || model.model_type() == "contact" {
             let user_key = {
                 let user_guard = lockr!(self.user);
                 user_guard.key_or_else()?
@@ -619,13 +818,125 @@ impl Turtl {
         notfound
     }
 
+    /// Build a lookup of item id -> keys from everything we currently know
+    /// about: the keychain, the spaces/boards in the profile, and the user's
+    /// key. Keychain entries come first, matching the search order used in
+    /// `find_model_key()`.
+    ///
+    /// Returns (keychain lookup, full search lookup).
+    fn key_lookup(&self) -> TResult<(HashMap<String, Key>, HashMap<String, Vec<Key>>)> {
+        let mut keychain_keys: HashMap<String, Key> = HashMap::new();
+        let mut search: HashMap<String, Vec<Key>> = HashMap::new();
+        {
+            let profile_guard = lockr!(self.profile);
+            for entry in &profile_guard.keychain.entries {
+                let key = match entry.k.as_ref() {
+                    Some(x) => x,
+                    None => continue,
+                };
+                if !keychain_keys.contains_key(&entry.item_id) {
+                    keychain_keys.insert(entry.item_id.clone(), key.clone());
+                }
+                search.entry(entry.item_id.clone()).or_insert_with(Vec::new).push(key.clone());
+            }
+            for space in &profile_guard.spaces {
+                if let (Some(id), Some(key)) = (space.id(), space.key()) {
+                    search.entry(id.clone()).or_insert_with(Vec::new).push(key.clone());
+                }
+            }
+            for board in &profile_guard.boards {
+                if let (Some(id), Some(key)) = (board.id(), board.key()) {
+                    search.entry(id.clone()).or_insert_with(Vec::new).push(key.clone());
+                }
+            }
+        }
+        let user_guard = lockr!(self.user);
+        if let (Some(id), Some(key)) = (user_guard.id(), user_guard.key()) {
+            search.entry(id.clone()).or_insert_with(Vec::new).push(key.clone());
+        }
+        Ok((keychain_keys, search))
+    }
+
     /// Given a model vector that we suspect we have a key entry for, find those
     /// models' keys and set it into the models.
+    ///
+    /// This is the bulk version of `find_model_key()`. Instead of locking the
+    /// profile and building a key search per model, we build one lookup for
+    /// the whole batch, then decrypt the models' keys in chunks on the worker
+    /// pool. Anything we can't resolve that way (or that needs special
+    /// handling, like the user/keychain) falls back to `find_model_key()`.
     pub fn find_models_keys<T>(&self, models: &mut Vec<T>) -> TResult<()>
         where T: Protected + Keyfinder
     {
+        let (keychain_keys, search) = self.key_lookup()?;
+        let mut fallback: Vec<usize> = Vec::new();
+        let mut pending: Vec<(usize, Vec<(Key, String)>)> = Vec::new();
+        for (idx, model) in models.iter_mut().enumerate() {
+            if model.key().is_some() { continue; }
+            if model.model_type() == "user" || model.model_type() == "keychain" || model.model_type() == "contact" {
+                fallback.push(idx);
+                continue;
+            }
+            // the keychain has this model's key directly, no decryption needed
+            let direct = model.id().and_then(|id| keychain_keys.get(id)).map(|k| k.clone());
+            if let Some(key) = direct {
+                model.set_key(Some(key));
+                continue;
+            }
+            let mut candidates: Vec<(Key, String)> = Vec::new();
+            if let Some(keyrefs) = model.get_keys() {
+                for keyref in keyrefs {
+                    if let Some(keys) = search.get(&keyref.id) {
+                        for key in keys {
+                            candidates.push((key.clone(), keyref.k.clone()));
+                        }
+                    }
+                }
+            }
+            if candidates.len() == 0 {
+                fallback.push(idx);
+                continue;
+            }
+            pending.push((idx, candidates));
+        }
+
+        let mut batches: Vec<Vec<(usize, Vec<(Key, String)>)>> = Vec::new();
+        for job in pending {
+            let full = batches.last().map(|x| x.len() >= KEY_DECRYPT_BATCH).unwrap_or(true);
+            if full {
+                batches.push(Vec::with_capacity(KEY_DECRYPT_BATCH));
+            }
+            batches.last_mut().expect("turtl::Turtl.find_models_keys() -- batches is empty").push(job);
+        }
+        let ref work = self.work;
+        let futures = batches.into_iter()
+            .map(|batch| {
+                work.run_async(move || {
+                    let mut found: Vec<(usize, Option<Key>)> = Vec::with_capacity(batch.len());
+                    for (idx, candidates) in batch {
+                        // first key that decrypts wins, same as find_model_key()
+                        let key = candidates.iter()
+                            .filter_map(|&(ref decrypting_key, ref encrypted_key)| {
+                                protected::decrypt_key(decrypting_key, encrypted_key).ok()
+                            })
+                            .next();
+                        found.push((idx, key));
+                    }
+                    Ok(found)
+                })
+            })
+            .collect::<Vec<_>>();
+        let decrypted = future::join_all(futures).wait()?;
+        for (idx, key) in decrypted.into_iter().flat_map(|x| x) {
+            match key {
+                Some(key) => models[idx].set_key(Some(key)),
+                None => fallback.push(idx),
+            }
+        }
+
         let mut errcount = 0;
-        for model in models {
+        for idx in fallback {
+            let model = &mut models[idx];
             match self.find_model_key(model) {
                 Ok(_) => {},
                 Err(_) => {
@@ -654,15 +965,22 @@ impl Turtl {
         let mut spaces: Vec<Space> = db.all("spaces")?;
         let mut boards: Vec<Board> = db.all("boards")?;
         let invites: Vec<Invite> = db.all("invites")?;
+        let mut contacts: Vec<Contact> = db.all("contacts")?;
 
         // decrypt the keychain
         self.find_models_keys(&mut keychain)?;
         let keychain: Vec<KeychainEntry> = protected::map_deserialize(self, keychain)?;
+        if keychain.iter().any(|x| x.k.is_none()) {
+            return TErr!(TError::MissingField(String::from("Keychain.k")));
+        }
+        // set the keychain in one shot rather than mem_update()ing each entry,
+        // which locks the profile and walks the keychain once per entry
+        {
+            let mut profile_guard = lockw!(self.profile);
+            profile_guard.keychain.replace_entries(keychain);
+        }
         let mut sync_item = SyncRecord::default();
         sync_item.action = SyncAction::Add;
-        for entry in keychain {
-            entry.mem_update(self, &mut sync_item)?;
-        }
 
         // now decrypt the spaces
         self.find_models_keys(&mut spaces)?;
@@ -684,6 +1002,13 @@ impl Turtl {
             invite.mem_update(self, &mut sync_item)?;
         }
 
+        // contacts use the user key, so no keychain lookups needed
+        self.find_models_keys(&mut contacts)?;
+        let contacts: Vec<Contact> = protected::map_deserialize(self, contacts)?;
+        for contact in contacts {
+            contact.mem_update(self, &mut sync_item)?;
+        }
+
         let mut user_guard = lockw!(self.user);
         user_guard.deserialize()?;
         Ok(())
@@ -697,6 +1022,8 @@ impl Turtl {
             None => return TErr!(TError::MissingField(String::from("Turtl.db"))),
         };
 
+        // pull any archived notes out of cold storage before loading
+        let rehydrated = archive::rehydrate(self, db, note_ids)?;
         let notes: Vec<Note> = db.by_id("notes", note_ids)?;
         // make sure notes are ordered based on the ids we passed
         let mut notes = {
@@ -712,8 +1039,54 @@ impl Turtl {
             }
             tmp
         };
+        drop(db_guard);
+        // decrypting is the slow part, so give the caller a chance to bail
+        jobs::check_cancelled()?;
+        let outdated = Note::find_outdated(&notes);
         self.find_models_keys(&mut notes)?;
-        protected::map_deserialize(self, notes)
+        let notes = protected::map_deserialize(self, notes)?;
+        jobs::check_cancelled()?;
+        // the upgrade saves run after we answer (and never for a scratch
+        // profile, since they'd go to the real one)
+        if !self.scratch {
+            match Note::lazy_upgrade(self, &notes, &outdated) {
+                Ok(_) => {}
+                Err(e) => warn!("turtl.load_notes() -- problem queuing note upgrades: {}", e),
+            }
+        }
+        if rehydrated.len() > 0 {
+            // the index only has the archived notes' titles/tags. if search is
+            // busy (say, we're loading notes for a search result) we skip this
+            // and the full notes get picked up on the next index_notes()
+            if let Ok(mut search_guard) = self.search.try_lock() {
+                if let Some(search) = search_guard.as_mut() {
+                    for note in &notes {
+                        if !note.id().map(|id| rehydrated.contains(id)).unwrap_or(false) { continue; }
+                        match search.reindex_note(note) {
+                            Ok(_) => {},
+                            Err(e) => warn!("turtl.load_notes() -- problem reindexing rehydrated note {:?}: {}", note.id(), e),
+                        }
+                    }
+                }
+            }
+        }
+        Ok(notes)
+    }
+
+    /// Create an empty search index, either in memory or memory-mapped from
+    /// the cache folder depending on `search.storage`.
+    fn new_search(&self) -> TResult<Search> {
+        let storage = config::get::<String>(&["search", "storage"])
+            .unwrap_or(String::from("memory"));
+        if storage != "mmap" || paths::is_memory() {
+            return Search::new();
+        }
+        let mmap_size = config::get::<i64>(&["search", "mmap_size"])
+            .unwrap_or(64 * 1024 * 1024);
+        fs::create_dir_all(cache_folder()?)?;
+        let location = paths::data_path(&["cache", &format!("search-{}.idx", self.user_id()?)])?
+            .to_string_lossy().into_owned();
+        Search::new_mapped(&location, mmap_size)
     }
 
     /// Take all the (encrypted) notes in our profile data then decrypt, index,
@@ -725,6 +1098,7 @@ impl Turtl {
             return TErr!(TError::MissingData(String::from("Turtl.db")));
         }
         let db = db_guard.as_ref().expect("turtl::Turtl::index_notes() -- db is None");
+        let analyzer_config = analyzer::load_config(db)?;
         let mut notes: Vec<Note> = db.all("notes")?;
         self.find_models_keys(&mut notes)?;
         let notes: Vec<Note> = protected::map_deserialize(self, notes)
@@ -732,7 +1106,8 @@ impl Turtl {
                 error!("turtl.index_notes() -- there was a problem indexing notes: {}", e);
                 Err(e)
             })?;
-        let mut search = Search::new()?;
+        let mut search = self.new_search()?;
+        search.set_analyzer(Analyzer::new(analyzer_config));
         for note in &notes {
             match search.index_note(note) {
                 Ok(_) => {},
@@ -740,48 +1115,92 @@ impl Turtl {
                 Err(e) => error!("turtl.index_notes() -- problem indexing note {:?}: {}", note.id(), e),
             }
         }
-        let mut search_guard = lock!(self.search);
-        *search_guard = Some(search);
+        search.compact();
+        {
+            let mut search_guard = lock!(self.search);
+            *search_guard = Some(search);
+        }
+        // the profile lock goes before the db lock everywhere else
+        drop(db_guard);
+        lockw!(self.profile).seed_snapshots(&notes);
         Ok(())
     }
 
-    /// Log out the current user (if logged in) and wipe ALL local SQL databases
-    /// from our data folder.
-    pub fn wipe_app_data(&self) -> TResult<()> {
+    /// Re-tokenize the notes in the given space (or rebuild the entire index if
+    /// no space is given), sending `profile:reindex:progress` events as we go.
+    pub fn reindex(&self, space_id: Option<&String>) -> TResult<IndexStats> {
+        let space_id = match space_id {
+            Some(x) => x,
+            None => {
+                self.index_notes()?;
+                let search_guard = lock!(self.search);
+                return match search_guard.as_ref() {
+                    Some(search) => {
+                        let stats = search.stats()?;
+                        messaging::ui_event("profile:reindex:progress", &json!({"done": stats.notes, "total": stats.notes}))?;
+                        Ok(stats)
+                    }
+                    None => TErr!(TError::MissingField(String::from("Turtl.search"))),
+                };
+            }
+        };
+        // decrypt before grabbing the search lock so searches can run while we
+        // work
+        let mut notes: Vec<Note> = with_db!{ db, self.db, db.find("notes", "space_id", &vec![space_id.clone()])? };
+        self.find_models_keys(&mut notes)?;
+        let notes: Vec<Note> = protected::map_deserialize(self, notes)?;
+        let total = notes.len();
+        let mut search_guard = lock!(self.search);
+        let search = match search_guard.as_mut() {
+            Some(x) => x,
+            None => return TErr!(TError::MissingField(String::from("Turtl.search"))),
+        };
+        search.unindex_space(space_id)?;
+        for (i, note) in notes.iter().enumerate() {
+            match search.index_note(note) {
+                Ok(_) => {},
+                Err(e) => error!("turtl.reindex() -- problem indexing note {:?}: {}", note.id(), e),
+            }
+            if (i + 1) % 100 == 0 || i + 1 == total {
+                messaging::ui_event("profile:reindex:progress", &json!({"done": i + 1, "total": total}))?;
+                // NOTE: no cancel check here. half an index is worse than a
+                // slow one.
+                jobs::progress((i + 1) as u64, Some(total as u64));
+            }
+        }
+        search.compact();
+        Ok(search.stats()?)
+    }
+
+    /// Returns true if the app has never completed its first run (as in, the
+    /// UI hasn't told us it finished onboarding yet).
+    pub fn is_first_run(&self) -> TResult<bool> {
+        let kv_guard = lockr!(self.kv);
+        Ok(kv_guard.kv_get("first_run_complete")?.is_none())
+    }
+
+    /// Mark the first run as completed so we don't onboard the user again
+    pub fn set_first_run_complete(&self) -> TResult<()> {
+        let kv_guard = lockr!(self.kv);
+        kv_guard.kv_set("first_run_complete", &String::from("true"))
+    }
+
+    /// Log out the current user (if logged in) and wipe ALL local data
+    /// (databases, files, caches, logs) from our data folder, returning a report
+    /// of what we removed.
+    pub fn wipe_app_data(&self) -> TResult<WipeReport> {
         self.sync_shutdown(false)?;
         util::sleep(5000);
         self.logout()?;
 
         let mut kv_guard = lockw!(self.kv);
         kv_guard.close()?;
-        let data_folder = data_folder()?;
-        debug!("turtl.wipe_app_data() -- wiping everything in {}", data_folder);
-        fs::create_dir_all(&data_folder)?;
-        let paths = fs::read_dir(&data_folder)?;
-        // wipe all databases
-        for entry in paths {
-            let entry = entry?;
-            let path = entry.path();
-            if path.is_dir() { continue; }
-            let filename = entry.file_name();
-            let filename_str = match filename.to_str() {
-                Some(x) => x,
-                None => return TErr!(TError::Msg(format!("error converting OsString into &str"))),
-            };
-            if &filename_str[0..6] != "turtl-" { continue; }
-            fs::remove_file(&path)?;
-            info!("turtl.wipe_app_data() -- removing {}", path.display());
-        }
-
-        // wipe all note files
-        let files = FileData::file_finder_all(None, None)?;
-        for file in files {
-            fs::remove_file(&file)?;
-            info!("turtl.wipe_app_data() -- removing {}", file.display());
-        }
+        debug!("turtl.wipe_app_data() -- wiping everything in {}", paths::data_folder()?);
+        let report = wipe::wipe_local_data()?;
+        info!("turtl.wipe_app_data() -- removed {} files ({} bytes), verified: {}", report.removed.len(), report.bytes, report.verified);
 
         (*kv_guard) = Turtl::open_kv()?;
-        Ok(())
+        Ok(report)
     }
 
     /// Wipe any local database(s) for the current user (and log them out)
@@ -790,19 +1209,70 @@ impl Turtl {
         self.sync_shutdown(false)?;
         util::sleep(5000);
         self.logout()?;
+        self.wipe_local_user_files(&user_id)
+    }
 
-        let db_loc = self.get_user_db_location(&user_id)?;
-        if db_loc != ":memory:" {
-            info!("turtl.wipe_user_data() -- removing {}", db_loc);
-            fs::remove_file(&db_loc)?;
+    /// Wipe the local data for a specific user. If the user is the one logged
+    /// in, this is the same as `wipe_user_data()`, otherwise we leave the
+    /// current session alone and just remove the other user's db/files.
+    pub fn wipe_user_data_for(&self, user_id: &String) -> TResult<()> {
+        let is_current = match lockr!(self.user_id).as_ref() {
+            Some(x) => x == user_id,
+            None => false,
+        };
+        if is_current {
+            return self.wipe_user_data();
         }
+        self.wipe_local_user_files(user_id)
+    }
+
+    /// Remove a user's local database and files. Doesn't touch the session, so
+    /// make sure the db is closed before calling this on the current user.
+    fn wipe_local_user_files(&self, user_id: &String) -> TResult<()> {
+        // the id ends up in a file glob, so "*" would take everyone with it
+        check_user_id(user_id)?;
+        let db_loc = self.get_user_db_location(user_id)?;
+        storage::delete(&db_loc)?;
 
-        let files = FileData::file_finder_all(Some(&user_id), None)?;
+        let files = FileData::file_finder_all(Some(user_id), None)?;
         for file in files {
-            fs::remove_file(&file)?;
-            info!("turtl.wipe_user_data() -- removing {}", file.display());
+            util::secure_delete(&file)?;
+            info!("turtl.wipe_local_user_files() -- removing {}", file.display());
         }
+        Ok(())
+    }
 
+    /// Wipe our local caches (search index, cached/decrypted data) but leave
+    /// the user's databases and files alone. If we're logged in, the search
+    /// index gets rebuilt from the local db so everything keeps working.
+    pub fn wipe_cache(&self) -> TResult<()> {
+        // decrypted data we hang onto in memory (rendered note text, the file
+        // we're streaming ranges from, search results)
+        render::clear_cache();
+        FileData::clear_range_cache();
+        self.close_search();
+        // and on disk (the mapped search index lives in the cache folder)
+        self.wipe_cache_files()?;
+        let has_db = lock!(self.db).is_some();
+        if has_db {
+            self.index_notes()?;
+        }
+        Ok(())
+    }
+
+    /// Remove everything in our cache folder
+    fn wipe_cache_files(&self) -> TResult<()> {
+        let cache_folder = cache_folder()?;
+        if fs::metadata(&cache_folder).is_err() { return Ok(()); }
+        for entry in fs::read_dir(&cache_folder)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                fs::remove_dir_all(&path)?;
+            } else {
+                util::secure_delete(&path)?;
+            }
+            info!("turtl.wipe_cache_files() -- removing {}", path.display());
+        }
         Ok(())
     }
 
@@ -818,6 +1288,9 @@ impl Turtl {
 // would happen anyway if Turtl is dropped, but whatever.
 impl Drop for Turtl {
     fn drop(&mut self) {
+        // logging out would clear the app's jobs, watchers, etc out from under
+        // the real profile
+        if self.scratch { return; }
         match self.shutdown() {
             Err(e) => error!("Turt::drop() -- error shutting down Turtl: {}", e),
             _ => (),
@@ -830,6 +1303,7 @@ pub mod tests {
     use super::*;
 
     use ::std::sync::{RwLock, Mutex};
+    use ::std::path::Path;
 
     use ::jedi;
 
@@ -944,10 +1418,10 @@ pub mod tests {
         }
     }
 
-    #[test]
-    fn loads_profile_search_notes() {
-        let turtl = with_test(true);
-
+    /// Load a small profile (three spaces, three boards, a few notes) into a
+    /// logged-in test Turtl. The notes in the "Bookmarks" board are
+    /// 015caf7c5f4d... and 015ce7ea7f74...
+    pub fn load_test_profile(turtl: &Turtl) {
         // load our profile from a few big JSON blobs. we do this out of scope
         // so's not to be tempted to use them later on...we want the profile to
         // load itself completely from the DB and deserialize successfully w/o
@@ -982,6 +1456,13 @@ pub mod tests {
         }
 
         turtl.load_profile().unwrap();
+    }
+
+    #[test]
+    fn loads_profile_search_notes() {
+        let turtl = with_test(true);
+        load_test_profile(&turtl);
+
         let profile_guard = lockr!(turtl.profile);
         assert_eq!(profile_guard.keychain.entries.len(), 5);
         assert_eq!(profile_guard.spaces.len(), 3);
@@ -1135,5 +1616,60 @@ pub mod tests {
         assert_eq!(syncs[0].ty, SyncType::Keychain);
         assert_eq!(syncs[1].ty, SyncType::Space);
     }
-}
 
+    #[test]
+    fn tracks_first_run() {
+        let turtl = with_test(false);
+        assert_eq!(turtl.is_first_run().unwrap(), true);
+        turtl.set_first_run_complete().unwrap();
+        assert_eq!(turtl.is_first_run().unwrap(), false);
+    }
+
+    #[test]
+    fn wipes_cache() {
+        let turtl = with_test(true);
+        let folder = cache_folder().unwrap();
+        fs::create_dir_all(&folder).unwrap();
+        let cached = Path::new(&folder).join("search-51.idx");
+        fs::write(&cached, "decrypted stuff").unwrap();
+        fs::create_dir_all(Path::new(&folder).join("thumbs")).unwrap();
+        turtl.wipe_cache().unwrap();
+        assert!(fs::metadata(&cached).is_err());
+        assert!(fs::metadata(Path::new(&folder).join("thumbs")).is_err());
+        // the index gets rebuilt since we're logged in
+        assert!(lock!(turtl.search).is_some());
+
+        // no cache folder at all is fine too
+        let turtl = with_test(false);
+        let _ = fs::remove_dir_all(&folder);
+        turtl.wipe_cache().unwrap();
+        assert!(lock!(turtl.search).is_none());
+    }
+
+    #[test]
+    fn wipes_another_users_data() {
+        let turtl = with_test(true);
+        let folder = ::models::file::file_folder().unwrap();
+        fs::create_dir_all(&folder).unwrap();
+        let theirs = Path::new(&folder).join("u_7701.n_0158a2.enc");
+        let mine = Path::new(&folder).join("u_7702.n_0158a2.enc");
+        fs::write(&theirs, "attachment").unwrap();
+        fs::write(&mine, "attachment").unwrap();
+
+        // wildcards (or anything else that isn't an id) don't get anywhere
+        for bad in &["*", "", "7701/../51", "u_7702"] {
+            let err = turtl.wipe_user_data_for(&String::from(*bad)).unwrap_err();
+            assert_eq!(err.code(), ErrorCode::BadRequest);
+        }
+        assert!(fs::metadata(&theirs).is_ok());
+        assert!(fs::metadata(&mine).is_ok());
+
+        turtl.wipe_user_data_for(&String::from("7701")).unwrap();
+        assert!(fs::metadata(&theirs).is_err());
+        assert!(fs::metadata(&mine).is_ok());
+        // and the logged-in user is left alone
+        assert_eq!(turtl.user_id().unwrap(), "51");
+        assert!(lock!(turtl.db).is_some());
+        fs::remove_file(&mine).unwrap();
+    }
+}
@@ -0,0 +1,314 @@
+//! Undo/redo for changes the UI makes through `profile:sync:model`. Before a
+//! space/board/note gets saved, moved, or deleted we grab what it looked like,
+//! and keep the operations that would put it back. Undoing replays those
+//! operations through the regular sync dispatcher, so an undo is just another
+//! save as far as permissions, sync, and other clients are concerned.
+//!
+//! Deleting a space or board takes its boards/notes with it, so we hold onto
+//! those as well (file attachments don't come back). History lives in memory
+//! and goes away on logout.
+
+use ::std::collections::VecDeque;
+use ::std::sync::Mutex;
+use ::jedi::{self, Value};
+use ::error::{TResult, TError};
+use ::models::model::Model;
+use ::models::protected::Protected;
+use ::models::note::Note;
+use ::models::sync_record::{SyncAction, SyncType, SyncRecord};
+use ::sync::sync_model;
+use ::turtl::Turtl;
+use ::dispatch::registry::Registry;
+
+/// How many changes we remember
+const MAX_HISTORY: usize = 50;
+
+/// One operation to replay through `sync_model::dispatch()`
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Op {
+    pub action: SyncAction,
+    #[serde(rename = "type")]
+    pub ty: SyncType,
+    pub data: Value,
+}
+
+impl Op {
+    pub fn new(action: SyncAction, ty: SyncType, data: Value) -> Self {
+        Op { action: action, ty: ty, data: data }
+    }
+}
+
+/// A change, and how to take it back (or do it again)
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct UndoEntry {
+    /// what happened, eg "edit note"
+    pub label: String,
+    pub undo: Vec<Op>,
+    pub redo: Vec<Op>,
+}
+
+#[derive(Default)]
+struct History {
+    undo: VecDeque<UndoEntry>,
+    redo: Vec<UndoEntry>,
+}
+
+impl History {
+    /// Remember a new change. Anything we could have redone is gone now.
+    fn push(&mut self, entry: UndoEntry) {
+        self.redo.clear();
+        self.undo.push_back(entry);
+        while self.undo.len() > MAX_HISTORY {
+            self.undo.pop_front();
+        }
+    }
+}
+
+lazy_static! {
+    static ref HISTORY: Mutex<History> = Mutex::new(Default::default());
+}
+
+/// Grab the current (decrypted) data for a model, without its body
+fn current_data(turtl: &Turtl, ty: &SyncType, id: &String) -> TResult<Value> {
+    let data = match *ty {
+        SyncType::Space => {
+            let profile_guard = lockr!(turtl.profile);
+            match profile_guard.spaces.iter().find(|x| x.id() == Some(id)) {
+                Some(x) => x.data()?,
+                None => return TErr!(TError::NotFound(format!("space {} wasn't found", id))),
+            }
+        }
+        SyncType::Board => {
+            let profile_guard = lockr!(turtl.profile);
+            match profile_guard.boards.iter().find(|x| x.id() == Some(id)) {
+                Some(x) => x.data()?,
+                None => return TErr!(TError::NotFound(format!("board {} wasn't found", id))),
+            }
+        }
+        SyncType::Note => {
+            match turtl.load_notes(&vec![id.clone()])?.pop() {
+                Some(x) => x.data()?,
+                None => return TErr!(TError::NotFound(format!("note {} wasn't found", id))),
+            }
+        }
+        _ => return TErr!(TError::BadValue(format!("can't undo changes to {:?}", ty))),
+    };
+    let mut data = data;
+    let _ = jedi::remove(&["body"], &mut data);
+    Ok(data)
+}
+
+/// Ops that bring back a deleted model, along with anything that got deleted
+/// with it
+fn restore_ops(turtl: &Turtl, ty: &SyncType, id: &String) -> TResult<Vec<Op>> {
+    let mut ops = vec![Op::new(SyncAction::Add, ty.clone(), current_data(turtl, ty, id)?)];
+    let (board_ids, note_index) = match *ty {
+        SyncType::Space => {
+            let board_ids = lockr!(turtl.profile).boards.iter()
+                .filter(|x| &x.space_id == id)
+                .filter_map(|x| x.id().map(|x| x.clone()))
+                .collect::<Vec<_>>();
+            (board_ids, "space_id")
+        }
+        SyncType::Board => (vec![], "board_id"),
+        _ => return Ok(ops),
+    };
+    for board_id in &board_ids {
+        ops.push(Op::new(SyncAction::Add, SyncType::Board, current_data(turtl, &SyncType::Board, board_id)?));
+    }
+    let note_ids = with_db!{ db, turtl.db,
+        db.find::<Note>("notes", note_index, &vec![id.clone()])?
+            .into_iter()
+            .filter_map(|x| x.id().map(|x| x.clone()))
+            .collect::<Vec<_>>()
+    };
+    for note in turtl.load_notes(&note_ids)? {
+        let mut data = note.data()?;
+        let _ = jedi::remove(&["body"], &mut data);
+        ops.push(Op::new(SyncAction::Add, SyncType::Note, data));
+    }
+    Ok(ops)
+}
+
+/// Run a sync record through `sync_model::dispatch()`, remembering how to undo
+/// it if it's something we know how to undo.
+pub fn dispatch(turtl: &Turtl, sync_record: SyncRecord) -> TResult<Value> {
+    let ty = sync_record.ty.clone();
+    let action = sync_record.action.clone();
+    let undoable = match ty {
+        SyncType::Space | SyncType::Board | SyncType::Note => true,
+        _ => false,
+    };
+    if !undoable {
+        return sync_model::dispatch(turtl, sync_record);
+    }
+    let data = match sync_record.data.as_ref() {
+        Some(x) => x.clone(),
+        None => return sync_model::dispatch(turtl, sync_record),
+    };
+    let id: Option<String> = jedi::get_opt(&["id"], &data);
+    // figure out what things look like before the change
+    let before = match (&action, id.as_ref()) {
+        (&SyncAction::Edit, Some(id)) => vec![Op::new(SyncAction::Edit, ty.clone(), current_data(turtl, &ty, id)?)],
+        (&SyncAction::Delete, Some(id)) => restore_ops(turtl, &ty, id)?,
+        (&SyncAction::MoveSpace, Some(id)) => {
+            let cur = current_data(turtl, &ty, id)?;
+            vec![Op::new(SyncAction::MoveSpace, ty.clone(), json!({
+                "id": id,
+                "space_id": jedi::get_opt::<String>(&["space_id"], &cur),
+                "board_id": jedi::get_opt::<String>(&["board_id"], &cur),
+            }))]
+        }
+        _ => vec![],
+    };
+    let res = sync_model::dispatch(turtl, sync_record)?;
+
+    let entry = match action {
+        SyncAction::Add => {
+            let new_id: String = match jedi::get_opt(&["id"], &res) {
+                Some(x) => x,
+                None => return Ok(res),
+            };
+            let mut redo = res.clone();
+            let _ = jedi::remove(&["body"], &mut redo);
+            UndoEntry {
+                label: format!("add {}", ::util::enum_to_string(&ty)?),
+                undo: vec![Op::new(SyncAction::Delete, ty.clone(), json!({"id": new_id}))],
+                redo: vec![Op::new(SyncAction::Add, ty.clone(), redo)],
+            }
+        }
+        SyncAction::Edit | SyncAction::MoveSpace | SyncAction::Delete if before.len() > 0 => {
+            let label = match action {
+                SyncAction::Edit => "edit",
+                SyncAction::MoveSpace => "move",
+                _ => "delete",
+            };
+            UndoEntry {
+                label: format!("{} {}", label, ::util::enum_to_string(&ty)?),
+                undo: before,
+                redo: vec![Op::new(action.clone(), ty.clone(), data)],
+            }
+        }
+        _ => return Ok(res),
+    };
+    lock!(*HISTORY).push(entry);
+    Ok(res)
+}
+
+/// Remember a change that didn't go through `dispatch()` (a bulk edit, say) so
+/// it can be undone like any other
+pub fn record(entry: UndoEntry) {
+    lock!(*HISTORY).push(entry);
+}
+
+/// Replay a set of ops (without recording them)
+fn replay(turtl: &Turtl, ops: &Vec<Op>) -> TResult<Vec<Value>> {
+    let mut results = Vec::with_capacity(ops.len());
+    for op in ops {
+        let mut sync_record = SyncRecord::default();
+        sync_record.action = op.action.clone();
+        sync_record.ty = op.ty.clone();
+        sync_record.data = Some(op.data.clone());
+        results.push(sync_model::dispatch(turtl, sync_record)?);
+    }
+    Ok(results)
+}
+
+/// Take back the last change. If it doesn't go through, it stays on the undo
+/// stack.
+pub fn undo(turtl: &Turtl) -> TResult<Value> {
+    let entry = match lock!(*HISTORY).undo.pop_back() {
+        Some(x) => x,
+        None => return TErr!(TError::NotFound(String::from("nothing to undo"))),
+    };
+    match replay(turtl, &entry.undo) {
+        Ok(results) => {
+            let label = entry.label.clone();
+            lock!(*HISTORY).redo.push(entry);
+            Ok(json!({"label": label, "results": results}))
+        }
+        Err(e) => {
+            lock!(*HISTORY).undo.push_back(entry);
+            Err(e)
+        }
+    }
+}
+
+/// Do the last undone change again
+pub fn redo(turtl: &Turtl) -> TResult<Value> {
+    let entry = match lock!(*HISTORY).redo.pop() {
+        Some(x) => x,
+        None => return TErr!(TError::NotFound(String::from("nothing to redo"))),
+    };
+    match replay(turtl, &entry.redo) {
+        Ok(results) => {
+            let label = entry.label.clone();
+            lock!(*HISTORY).undo.push_back(entry);
+            Ok(json!({"label": label, "results": results}))
+        }
+        Err(e) => {
+            lock!(*HISTORY).redo.push(entry);
+            Err(e)
+        }
+    }
+}
+
+/// What we can undo/redo (most recent first), for the UI's menus
+pub fn history() -> Value {
+    let history = lock!(*HISTORY);
+    json!({
+        "undo": history.undo.iter().rev().map(|x| x.label.clone()).collect::<Vec<_>>(),
+        "redo": history.redo.iter().rev().map(|x| x.label.clone()).collect::<Vec<_>>(),
+    })
+}
+
+/// Forget everything (history holds decrypted data, so we do this on logout)
+pub fn clear() {
+    let mut history = lock!(*HISTORY);
+    history.undo.clear();
+    history.redo.clear();
+}
+
+/// Registers our undo/redo commands
+pub fn register(reg: &mut Registry) {
+    reg.add("edit:undo", |turtl, _args| {
+        undo(turtl)
+    });
+    reg.add("edit:redo", |turtl, _args| {
+        redo(turtl)
+    });
+    reg.add("edit:history", |_turtl, _args| {
+        Ok(history())
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(label: &str) -> UndoEntry {
+        UndoEntry {
+            label: String::from(label),
+            undo: vec![Op::new(SyncAction::Delete, SyncType::Note, json!({"id": label}))],
+            redo: vec![],
+        }
+    }
+
+    #[test]
+    fn bounds_history() {
+        let mut history = History::default();
+        for i in 0..(MAX_HISTORY + 5) {
+            history.push(entry(&format!("n{}", i)));
+        }
+        assert_eq!(history.undo.len(), MAX_HISTORY);
+        assert_eq!(history.undo.front().unwrap().label, "n5");
+
+        // a new change wipes out anything we could've redone
+        let undone = history.undo.pop_back().unwrap();
+        history.redo.push(undone);
+        assert_eq!(history.redo.len(), 1);
+        history.push(entry("new"));
+        assert_eq!(history.redo.len(), 0);
+        assert_eq!(history.undo.back().unwrap().label, "new");
+    }
+}
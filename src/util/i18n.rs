@@ -1,10 +1,111 @@
 //! Turtl's internationalization library.
+//!
+//! Strings we generate in the core (validation errors, default content, etc)
+//! get wrapped in `t!()`, which looks the string up in the message catalog for
+//! the current locale (`config.locale`). If we don't have a translation, the
+//! original (english) string is returned as-is.
+//!
+//! We ship a few catalogs with the core, but the UI can load its own via
+//! `app:set-locale` if it has better/more translations than we do.
 
-// stub this out for now.
+use ::std::collections::HashMap;
+use ::std::sync::RwLock;
+use ::jedi;
+use ::config;
+use ::error::TResult;
+
+/// Our default locale. Also the locale all `t!()` strings are written in.
+pub const DEFAULT_LOCALE: &'static str = "en";
+
+/// Maps locale -> (english string -> translated string)
+type Catalogs = HashMap<String, HashMap<String, String>>;
+
+lazy_static! {
+    /// Holds our loaded message catalogs
+    static ref CATALOGS: RwLock<Catalogs> = RwLock::new(builtin_catalogs());
+}
+
+/// Load the catalogs that are compiled into the core
+fn builtin_catalogs() -> Catalogs {
+    let mut catalogs = HashMap::new();
+    let builtin: Vec<(&str, &str)> = vec![
+        ("es", include_str!("../../locales/es.yaml")),
+    ];
+    for (locale, yaml) in builtin {
+        let parsed = jedi::parse_yaml(&String::from(yaml))
+            .and_then(|x| jedi::from_val::<HashMap<String, String>>(x));
+        match parsed {
+            Ok(catalog) => { catalogs.insert(String::from(locale), catalog); }
+            Err(e) => error!("i18n::builtin_catalogs() -- error parsing catalog {}: {}", locale, e),
+        }
+    }
+    catalogs
+}
+
+/// Normalize a locale string ("es_MX" -> "es-mx")
+fn normalize(locale: &str) -> String {
+    locale.replace("_", "-").to_lowercase()
+}
+
+/// Get the currently-configured locale
+pub fn get_locale() -> String {
+    config::get::<String>(&["locale"])
+        .map(|x| normalize(&x))
+        .unwrap_or(String::from(DEFAULT_LOCALE))
+}
+
+/// Set the current locale, optionally loading a message catalog for it
+pub fn set_locale(locale: &String, catalog: Option<HashMap<String, String>>) -> TResult<()> {
+    let locale = normalize(locale);
+    if let Some(catalog) = catalog {
+        let mut catalogs_guard = lockw!(*CATALOGS);
+        catalogs_guard.insert(locale.clone(), catalog);
+    }
+    config::set(&["locale"], &locale)?;
+    Ok(())
+}
+
+/// Translate a string into the given locale. If the exact locale (es-mx) has
+/// no translation, we try the language (es) before giving up and returning
+/// the string we were given.
+pub fn translate_to(locale: &String, label: &str) -> String {
+    let catalogs_guard = lockr!(*CATALOGS);
+    let lang = locale.split('-').next().unwrap_or(DEFAULT_LOCALE);
+    for loc in &[locale.as_str(), lang] {
+        if let Some(translated) = catalogs_guard.get(*loc).and_then(|c| c.get(label)) {
+            return translated.clone();
+        }
+    }
+    String::from(label)
+}
+
+/// Translate a string into the current locale
+pub fn translate(label: &str) -> String {
+    let locale = get_locale();
+    if locale == DEFAULT_LOCALE { return String::from(label); }
+    translate_to(&locale, label)
+}
+
+/// Mark a string as translatable, and translate it into the current locale
 #[macro_export]
 macro_rules! t {
     ($label:expr) => {
-        $label
+        ::util::i18n::translate($label)
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn translates_with_fallback() {
+        set_locale(&String::from("fr"), Some(vec![(String::from("Work"), String::from("Travail"))].into_iter().collect())).unwrap();
+        assert_eq!(translate_to(&String::from("fr"), "Work"), "Travail");
+        assert_eq!(translate_to(&String::from("fr-ca"), "Work"), "Travail");
+        assert_eq!(translate_to(&String::from("fr"), "Home"), "Home");
+        assert_eq!(translate_to(&String::from("es"), "Home"), "Casa");
+        assert_eq!(translate_to(&String::from("de"), "Home"), "Home");
+        config::set(&["locale"], &DEFAULT_LOCALE).unwrap();
+    }
+}
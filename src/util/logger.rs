@@ -21,19 +21,8 @@ pub fn get_logfile() -> Option<String> {
         Ok(x) => x,
         Err(_) => return None,
     };
-    // if our log file doesn't start with "/" or "x:" then we assume a path
-    // relative to the data_folder
-    let has_slash = filedest.find("/").unwrap_or(999) == 0;
-    let has_colon = filedest.find(":").unwrap_or(999) < 2;
-    let filedest = if !has_slash && !has_colon {
-        if let Ok(data_folder) = config::get::<String>(&["data_folder"]) {
-            format!("{}/{}", data_folder, filedest)
-        } else {
-            filedest
-        }
-    } else {
-        filedest
-    };
+    // relative log files live in the data_folder
+    let filedest = ::util::paths::resolve(&filedest);
     Some(filedest)
 }
 
@@ -158,6 +147,17 @@ fn prune_logfile() -> TResult<()> {
     rotate(&logfile)
 }
 
+/// where our console logs go. STDOUT, unless we're using it to talk to the UI
+/// (the stdio transport)
+fn console_output() -> fern::Output {
+    let transport: String = config::get(&["messaging", "transport"]).unwrap_or(String::from("carrier"));
+    if transport == "stdio" {
+        std::io::stderr().into()
+    } else {
+        std::io::stdout().into()
+    }
+}
+
 /// a simple wrapper (pretty much direct from documentation) that sets up
 /// logging to STDOUT (and file if config allows) via fern/log
 pub fn setup_logger() -> TResult<()> {
@@ -202,7 +202,7 @@ pub fn setup_logger() -> TResult<()> {
         .level_for("want", non_verbose_level.clone())
         .level_for("jni", non_verbose_level.clone())
         .level_for("html5ever", non_verbose_level.clone())
-        .chain(std::io::stdout());
+        .chain(console_output());
     if let Some(filedest) = get_logfile() {
         config = config.chain(fern::log_file(filedest)?);
     }
@@ -4,10 +4,11 @@ use ::error::{TResult, TError};
 use ::std::io;
 use ::std::fs;
 use ::std::path::Path;
+use ::std::io::prelude::*;
 use ::std::fmt::Debug;
 use ::jedi::{self, Value, Serialize};
-use ::config;
 use ::encoding_rs;
+use ::config;
 
 macro_rules! do_lock {
     ($lock:expr) => {{
@@ -37,6 +38,7 @@ macro_rules! lockw {
 }
 
 pub mod logger;
+pub mod paths;
 pub mod thredder;
 #[macro_use]
 pub mod ser;
@@ -48,23 +50,23 @@ pub fn sleep(millis: u64) {
     thread::sleep(Duration::from_millis(millis));
 }
 
+/// Whether we started in safe mode (no sync, no search, only the commands it
+/// takes to get data out or fix things). See `safe_mode` in the config.
+pub fn safe_mode() -> bool {
+    config::get(&["safe_mode"]).unwrap_or(false)
+}
+
 /// Get the app's file folder. This can be different depending on whether we're
 /// running tests or not, so tries to be mindful of that.
 pub fn file_folder(suffix: Option<&str>) -> TResult<String> {
-    let integration = config::get::<String>(&["integration_tests", "data_folder"])?;
-    if cfg!(test) {
-        return Ok(integration);
+    if cfg!(test) || paths::is_memory() {
+        return paths::data_folder();
     }
-    let data_folder = config::get::<String>(&["data_folder"])?;
-    let file_folder = if data_folder == ":memory:" {
-        integration
-    } else {
-        match suffix {
-            Some(x) => format!("{}/{}", data_folder, x),
-            None => data_folder,
-        }
+    let path = match suffix {
+        Some(x) => paths::data_path(&[x])?,
+        None => paths::data_path(&[])?,
     };
-    Ok(file_folder)
+    Ok(path.to_string_lossy().into_owned())
 }
 
 /// Create a directory if it doesn't exist
@@ -116,3 +118,30 @@ pub fn decode_text(bytes: &[u8]) -> TResult<String> {
     Err(TError::BadValue(format!("unable to decode bytes to string")))
 }
 
+
+/// Overwrite a file's contents with zeros (and flush to disk) before removing
+/// it. This is not a guarantee the data is gone (journaling filesystems, SSD
+/// wear leveling, etc etc) but it's a lot better than just unlinking. If we
+/// can't open the file for writing, we fall back to a plain old delete.
+pub fn secure_delete<P: AsRef<Path>>(path: P) -> TResult<()> {
+    let path = path.as_ref();
+    let overwrite = || -> TResult<()> {
+        let len = fs::metadata(path)?.len();
+        let mut file = fs::OpenOptions::new().write(true).open(path)?;
+        let zeros = [0u8; 4096];
+        let mut written: u64 = 0;
+        while written < len {
+            let chunk = ::std::cmp::min(len - written, zeros.len() as u64) as usize;
+            file.write_all(&zeros[0..chunk])?;
+            written += chunk as u64;
+        }
+        file.sync_all()?;
+        Ok(())
+    };
+    match overwrite() {
+        Ok(_) => {},
+        Err(e) => warn!("util::secure_delete() -- unable to overwrite {}, removing anyway: {}", path.display(), e),
+    }
+    fs::remove_file(path)?;
+    Ok(())
+}
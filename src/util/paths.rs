@@ -0,0 +1,83 @@
+//! Keeps track of where the core is allowed to put things on disk.
+//!
+//! Mobile platforms hand each app a sandboxed folder that can move around
+//! between installs/upgrades, so we can't hardcode anything or rely on the
+//! current working directory. Instead, the embedder tells us where our data
+//! folder lives at startup (via `data_folder` in the runtime config passed to
+//! `turtlc_start()`) and *everything* that touches the disk (dbs, attachments,
+//! logs, caches) builds its paths from here.
+
+use ::std::path::{Path, PathBuf};
+use ::config;
+use ::error::{TResult, TError};
+use ::util;
+
+/// The special data folder value that means "don't touch the disk"
+pub const MEMORY: &'static str = ":memory:";
+
+/// Grab the data folder exactly as the embedder set it (might be `:memory:`)
+pub fn raw_data_folder() -> TResult<String> {
+    Ok(config::get::<String>(&["data_folder"])?)
+}
+
+/// Returns true if we're running without a data folder
+pub fn is_memory() -> bool {
+    match raw_data_folder() {
+        Ok(x) => x == MEMORY,
+        Err(_) => false,
+    }
+}
+
+/// Set (and create) our data folder. Relative paths aren't allowed since they'd
+/// depend on whatever directory the app happened to be launched from.
+pub fn set_data_folder(folder: &String) -> TResult<()> {
+    if folder != MEMORY {
+        if !Path::new(folder).is_absolute() {
+            return TErr!(TError::BadValue(format!("data_folder must be an absolute path (got {})", folder)));
+        }
+        util::create_dir(folder)?;
+    }
+    config::set(&["data_folder"], folder)?;
+    Ok(())
+}
+
+/// Get the folder we can actually write files to. In tests (or when running
+/// in-memory) this points at the integration test folder.
+pub fn data_folder() -> TResult<String> {
+    let integration = config::get::<String>(&["integration_tests", "data_folder"])?;
+    if cfg!(test) {
+        return Ok(integration);
+    }
+    let data_folder = raw_data_folder()?;
+    let final_folder = if data_folder == MEMORY {
+        integration
+    } else {
+        data_folder
+    };
+    Ok(final_folder)
+}
+
+/// Build a path inside of our data folder
+pub fn data_path(parts: &[&str]) -> TResult<PathBuf> {
+    let mut path = PathBuf::from(data_folder()?);
+    for part in parts {
+        path.push(part);
+    }
+    Ok(path)
+}
+
+/// Turn a (possibly relative) path from the config into an absolute one. Any
+/// relative path is treated as relative to the data folder.
+pub fn resolve(path: &String) -> String {
+    if Path::new(path).is_absolute() {
+        return path.clone();
+    }
+    match raw_data_folder() {
+        Ok(folder) => {
+            let mut resolved = PathBuf::from(folder);
+            resolved.push(path);
+            resolved.to_string_lossy().into_owned()
+        }
+        Err(_) => path.clone(),
+    }
+}
@@ -0,0 +1,376 @@
+//! Outbound webhooks. Users can point a webhook at an external endpoint and
+//! pick a set of boards, and whenever a note in one of those boards is created
+//! or updated we POST the note to the endpoint as signed JSON.
+//!
+//! This sends *plaintext* note data off the device, so we only ever do it for
+//! boards that have been explicitly marked `exportable`. Webhooks themselves
+//! (including their secrets) are encrypted with the user's key and are local to
+//! this device.
+//!
+//! Deliveries are signed with an HMAC (sha512/256) of the body, keyed with the
+//! sha256 of the webhook secret, and sent in the `X-Turtl-Signature` header as
+//! hex. Failed deliveries are retried a few times with backoff, and the outcome
+//! of each delivery is written to a delivery log.
+//!
+//! Endpoints have to be https, so the note (and signature) can't be read or
+//! changed on the way there. Plain http is only allowed for localhost, which is
+//! handy for testing a receiver.
+
+use ::std::thread;
+use ::std::time::Duration;
+use ::std::sync::{Arc, Mutex};
+use ::reqwest::blocking::Client;
+use ::url::{Url, Host};
+use ::time;
+use ::jedi::{self, Value};
+use ::error::{TResult, TError};
+use ::config;
+use ::crypto;
+use ::turtl::Turtl;
+use ::storage::Storage;
+use ::models::model::{self, Model};
+use ::models::protected::Protected;
+use ::models::note::Note;
+use ::models::sync_record::SyncAction;
+use ::models::storable::Storable;
+use ::models::validate::{self, Validate};
+use ::dispatch::registry::Registry;
+
+/// The (raw) table we keep our delivery log in
+const LOG_TABLE: &'static str = "webhook_log";
+
+protected! {
+    #[derive(Serialize, Deserialize)]
+    pub struct Webhook {
+        #[serde(with = "::util::ser::int_converter")]
+        #[protected_field(public)]
+        pub user_id: String,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        #[protected_field(private)]
+        pub url: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        #[protected_field(private)]
+        pub secret: Option<String>,
+        #[serde(default)]
+        #[protected_field(private)]
+        pub board_ids: Vec<String>,
+        #[serde(default)]
+        #[protected_field(private)]
+        pub enabled: bool,
+    }
+}
+
+make_storable!(Webhook, "webhooks");
+
+/// Whether we're willing to send notes to this address: https anywhere, or
+/// http to this machine
+fn allowed_url(url: &str) -> bool {
+    let parsed = match Url::parse(url.trim()) {
+        Ok(x) => x,
+        Err(_) => return false,
+    };
+    match parsed.scheme() {
+        "https" => parsed.host().is_some(),
+        "http" => match parsed.host() {
+            Some(Host::Domain(domain)) => domain.eq_ignore_ascii_case("localhost"),
+            Some(Host::Ipv4(ip)) => ip.is_loopback(),
+            Some(Host::Ipv6(ip)) => ip.is_loopback(),
+            None => false,
+        },
+        _ => false,
+    }
+}
+
+impl Validate for Webhook {
+    fn validate(&self) -> Vec<(String, String)> {
+        let mut errors = Vec::new();
+        if !self.url.as_ref().map(|x| allowed_url(x)).unwrap_or(false) {
+            errors.push(validate::entry("url", t!("Please enter a valid web address for this webhook")));
+        }
+        if self.secret.as_ref().map(|x| x == "").unwrap_or(true) {
+            errors.push(validate::entry("secret", t!("Please give this webhook a secret")));
+        }
+        errors
+    }
+}
+
+/// An entry in our delivery log
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Delivery {
+    pub id: String,
+    pub webhook_id: String,
+    pub note_id: String,
+    pub event: String,
+    /// The HTTP status we got back (if we got one at all)
+    pub status: Option<u16>,
+    pub attempts: u32,
+    pub error: Option<String>,
+    pub sent: i64,
+}
+
+fn user_key(turtl: &Turtl) -> TResult<crypto::Key> {
+    let user_guard = lockr!(turtl.user);
+    user_guard.key_or_else()
+}
+
+/// Load and decrypt all of our webhooks
+pub fn list(turtl: &Turtl) -> TResult<Vec<Webhook>> {
+    let key = user_key(turtl)?;
+    let hooks: Vec<Webhook> = with_db!{ db, turtl.db, db.all(Webhook::tablename())? };
+    let mut decrypted = Vec::with_capacity(hooks.len());
+    for mut hook in hooks {
+        hook.set_key(Some(key.clone()));
+        match hook.deserialize() {
+            Ok(_) => decrypted.push(hook),
+            Err(e) => warn!("webhook::list() -- error decrypting webhook {:?}: {}", hook.id(), e),
+        }
+    }
+    Ok(decrypted)
+}
+
+/// Create or update a webhook
+pub fn save(turtl: &Turtl, mut hook: Webhook) -> TResult<Webhook> {
+    let errors = hook.validate();
+    if errors.len() > 0 {
+        return TErr!(TError::Validation(hook.model_type(), errors));
+    }
+    hook.user_id = turtl.user_id()?;
+    if hook.id().is_none() {
+        hook.generate_id()?;
+    }
+    hook.set_key(Some(user_key(turtl)?));
+    hook.serialize()?;
+    with_db!{ db, turtl.db, db.save(&hook)? };
+    Ok(hook)
+}
+
+/// Delete a webhook
+pub fn delete(turtl: &Turtl, webhook_id: &String) -> TResult<()> {
+    let mut hook = Webhook::new();
+    hook.id = Some(webhook_id.clone());
+    with_db!{ db, turtl.db, db.delete(&hook)? };
+    Ok(())
+}
+
+/// Grab our delivery log, newest first
+pub fn log(turtl: &Turtl) -> TResult<Vec<Delivery>> {
+    let entries = with_db!{ db, turtl.db, db.all_values(LOG_TABLE)? };
+    let mut log = entries.into_iter()
+        .filter_map(|x| jedi::from_val::<Delivery>(x).ok())
+        .collect::<Vec<_>>();
+    log.sort_by(|a, b| b.sent.cmp(&a.sent));
+    Ok(log)
+}
+
+/// Sign a payload with a webhook's secret
+fn sign(secret: &String, body: &String) -> TResult<String> {
+    let key = crypto::sha256(secret.as_bytes())?;
+    let sig = crypto::hmac(key.as_slice(), body.as_bytes())?;
+    Ok(crypto::to_hex(&sig)?)
+}
+
+/// Write a delivery to the log, trimming the log if it gets too big
+fn write_log(db: &Arc<Mutex<Option<Storage>>>, delivery: &Delivery) -> TResult<()> {
+    let max: usize = config::get(&["webhooks", "log_size"]).unwrap_or(200);
+    let mut db_guard = lock!(db);
+    let db = match db_guard.as_mut() {
+        Some(x) => x,
+        // logged out while we were delivering. nowhere to log to.
+        None => return Ok(()),
+    };
+    db.store_value(LOG_TABLE, &jedi::to_val(delivery)?)?;
+    let mut entries = db.all_values(LOG_TABLE)?
+        .into_iter()
+        .filter_map(|x| jedi::from_val::<Delivery>(x).ok())
+        .collect::<Vec<_>>();
+    if entries.len() > max {
+        entries.sort_by(|a, b| a.sent.cmp(&b.sent));
+        let remove = entries.len() - max;
+        for entry in entries.iter().take(remove) {
+            db.delete_value(LOG_TABLE, &entry.id)?;
+        }
+    }
+    Ok(())
+}
+
+/// POST a payload, retrying with backoff if it doesn't go through. Runs in its
+/// own thread so we never hold up a save waiting on someone else's server.
+fn deliver(db: Arc<Mutex<Option<Storage>>>, url: String, signature: String, body: String, mut delivery: Delivery) {
+    let retries: u32 = config::get(&["webhooks", "retries"]).unwrap_or(3);
+    let timeout: u64 = config::get(&["webhooks", "timeout"]).unwrap_or(10);
+    let spawned = thread::Builder::new().name(String::from("webhook")).spawn(move || {
+        let client = match Client::builder().timeout(Duration::new(timeout, 0)).build() {
+            Ok(x) => x,
+            Err(e) => {
+                error!("webhook::deliver() -- error building client: {}", e);
+                return;
+            }
+        };
+        loop {
+            delivery.attempts += 1;
+            let res = client.post(url.as_str())
+                .header("Content-Type", "application/json")
+                .header("X-Turtl-Signature", signature.clone())
+                .header("X-Turtl-Delivery", delivery.id.clone())
+                .body(body.clone())
+                .send();
+            match res {
+                Ok(res) => {
+                    delivery.status = Some(res.status().as_u16());
+                    delivery.error = None;
+                    // 4xx errors aren't going to get any better by retrying
+                    if res.status().is_success() || res.status().is_client_error() { break; }
+                }
+                Err(e) => {
+                    delivery.status = None;
+                    delivery.error = Some(format!("{}", e));
+                }
+            }
+            if delivery.attempts > retries { break; }
+            thread::sleep(Duration::new(2u64.pow(delivery.attempts), 0));
+        }
+        match write_log(&db, &delivery) {
+            Ok(_) => {}
+            Err(e) => error!("webhook::deliver() -- error writing delivery log: {}", e),
+        }
+    });
+    match spawned {
+        Ok(_) => {}
+        Err(e) => error!("webhook::deliver() -- error spawning delivery thread: {}", e),
+    }
+}
+
+/// Called whenever a (decrypted) note is added/edited. If the note lives in an
+/// exportable board that any of our webhooks are watching, send it off.
+pub fn note_changed(turtl: &Turtl, note: &Note, action: &SyncAction) -> TResult<()> {
+    let event = match *action {
+        SyncAction::Add => "note.created",
+        SyncAction::Edit => "note.updated",
+        _ => return Ok(()),
+    };
+    let board_id = match note.board_id.as_ref() {
+        Some(x) => x.clone(),
+        None => return Ok(()),
+    };
+    let exportable = {
+        let profile_guard = lockr!(turtl.profile);
+        profile_guard.boards.iter()
+            .filter(|b| b.id() == Some(&board_id))
+            .any(|b| b.exportable == Some(true))
+    };
+    if !exportable { return Ok(()); }
+    let note_id = note.id_or_else()?;
+    let now = time::get_time().sec as i64;
+    for hook in list(turtl)? {
+        if !hook.enabled || !hook.board_ids.contains(&board_id) { continue; }
+        let (url, secret) = match (hook.url.as_ref(), hook.secret.as_ref()) {
+            (Some(u), Some(s)) => (u.clone(), s.clone()),
+            _ => continue,
+        };
+        // hooks saved before we required https don't get anything
+        if !allowed_url(&url) {
+            warn!("webhook::note_changed() -- skipping webhook {:?}, it isn't https", hook.id());
+            continue;
+        }
+        let delivery = Delivery {
+            id: model::cid()?,
+            webhook_id: hook.id_or_else()?,
+            note_id: note_id.clone(),
+            event: String::from(event),
+            status: None,
+            attempts: 0,
+            error: None,
+            sent: now,
+        };
+        let payload: Value = json!({
+            "event": event,
+            "delivery_id": &delivery.id,
+            "sent": now,
+            "note": {
+                "id": &note_id,
+                "space_id": &note.space_id,
+                "board_id": &board_id,
+                "type": &note.type_,
+                "title": &note.title,
+                "text": &note.text,
+                "url": &note.url,
+                "tags": &note.tags,
+                "mod": &note.mod_,
+            },
+        });
+        let body = jedi::stringify(&payload)?;
+        let signature = sign(&secret, &body)?;
+        deliver(turtl.db.clone(), url, signature, body, delivery);
+    }
+    Ok(())
+}
+
+/// Registers our webhook commands
+pub fn register(reg: &mut Registry) {
+    reg.add("profile:webhook:list", |turtl, _args| {
+        let hooks = list(turtl)?;
+        let mut vals = Vec::with_capacity(hooks.len());
+        for hook in &hooks {
+            vals.push(hook.data()?);
+        }
+        Ok(Value::Array(vals))
+    });
+    reg.add("profile:webhook:save", |turtl, args| {
+        let hook: Webhook = args.get(2)?;
+        let hook = save(turtl, hook)?;
+        Ok(hook.data()?)
+    });
+    reg.add("profile:webhook:delete", |turtl, args| {
+        let webhook_id: String = args.get(2)?;
+        delete(turtl, &webhook_id)?;
+        Ok(json!({}))
+    });
+    reg.add("profile:webhook:log", |turtl, _args| {
+        let entries = log(turtl)?;
+        Ok(jedi::to_val(&entries)?)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signs_payloads() {
+        let secret = String::from("shhh");
+        let body = String::from("{\"event\":\"note.created\"}");
+        let sig1 = sign(&secret, &body).unwrap();
+        let sig2 = sign(&secret, &body).unwrap();
+        assert_eq!(sig1, sig2);
+        assert_eq!(sig1.len(), 64);
+        assert!(sign(&String::from("shhhh"), &body).unwrap() != sig1);
+    }
+
+    #[test]
+    fn validates_webhooks() {
+        let mut hook = Webhook::new();
+        hook.url = Some(String::from("ftp://lol"));
+        assert_eq!(hook.validate().len(), 2);
+        hook.url = Some(String::from("https://example.com/hook"));
+        hook.secret = Some(String::from("shhh"));
+        assert_eq!(hook.validate().len(), 0);
+        hook.url = Some(String::from("http://example.com/hook"));
+        assert_eq!(hook.validate().len(), 1);
+    }
+
+    #[test]
+    fn only_allows_https() {
+        assert!(allowed_url("https://example.com/hook"));
+        assert!(allowed_url("HTTPS://example.com"));
+        assert!(allowed_url("http://localhost:8080/hook"));
+        assert!(allowed_url("http://127.0.0.1/hook"));
+        assert!(allowed_url("http://[::1]:3000/"));
+        assert!(!allowed_url("http://example.com/hook"));
+        assert!(!allowed_url("http://localhost.example.com/"));
+        assert!(!allowed_url("http://10.0.0.1/hook"));
+        assert!(!allowed_url("ftp://localhost/"));
+        assert!(!allowed_url("https://"));
+        assert!(!allowed_url("not a url"));
+    }
+}
@@ -0,0 +1,161 @@
+//! Wiping all local data (see `Turtl::wipe_app_data()`). We find everything in
+//! the data folder that could hold profile data (databases, attachments, the
+//! cache/search index, logs, saved logins, corrupt db backups), securely
+//! delete it, and then look again to make sure nothing got left behind. The
+//! caller gets back a report listing what went and how big it was.
+//!
+//! Directories the user picked themselves (markdown export, folder sync) are
+//! theirs, and we leave them alone.
+
+use ::std::fs;
+use ::std::path::{Path, PathBuf};
+use ::error::TResult;
+use ::util::{self, logger, paths};
+use ::models::file;
+use ::turtl;
+
+/// The kinds of things we wipe
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ArtifactKind {
+    Database,
+    Backup,
+    Attachment,
+    Cache,
+    Log,
+    Login,
+}
+
+/// One file we removed
+#[derive(Serialize, Debug)]
+pub struct WipedFile {
+    pub path: String,
+    pub kind: ArtifactKind,
+    pub size: u64,
+}
+
+/// What a wipe did
+#[derive(Serialize, Debug, Default)]
+pub struct WipeReport {
+    pub removed: Vec<WipedFile>,
+    /// How many bytes we removed, total
+    pub bytes: u64,
+    /// Anything we should have removed but found still lying around after
+    pub leftovers: Vec<String>,
+    /// True if we looked again after wiping and found nothing
+    pub verified: bool,
+}
+
+/// Figure out what a file at the top of the data folder is (if it's anything
+/// we care about)
+fn classify(filename: &str) -> Option<ArtifactKind> {
+    if filename.starts_with("turtl-") {
+        if filename.contains(".corrupt.") {
+            Some(ArtifactKind::Backup)
+        } else {
+            Some(ArtifactKind::Database)
+        }
+    } else if filename.ends_with(".login") {
+        Some(ArtifactKind::Login)
+    } else {
+        None
+    }
+}
+
+/// Every file under a directory (recursively)
+fn files_under(dir: &Path, into: &mut Vec<PathBuf>) -> TResult<()> {
+    if fs::metadata(dir).is_err() { return Ok(()); }
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            files_under(&path, into)?;
+        } else {
+            into.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Find everything in the data folder (and our logs) that holds profile data
+fn find_artifacts() -> TResult<Vec<(PathBuf, ArtifactKind)>> {
+    let mut found = Vec::new();
+    let data_folder = PathBuf::from(paths::data_folder()?);
+    if fs::metadata(&data_folder).is_ok() {
+        for entry in fs::read_dir(&data_folder)? {
+            let entry = entry?;
+            let path = entry.path();
+            if path.is_dir() { continue; }
+            let kind = match entry.file_name().to_str().and_then(classify) {
+                Some(x) => x,
+                None => continue,
+            };
+            found.push((path, kind));
+        }
+    }
+    let mut attachments = Vec::new();
+    files_under(Path::new(&file::file_folder()?), &mut attachments)?;
+    found.extend(attachments.into_iter().map(|x| (x, ArtifactKind::Attachment)));
+    let mut cached = Vec::new();
+    files_under(Path::new(&turtl::cache_folder()?), &mut cached)?;
+    found.extend(cached.into_iter().map(|x| (x, ArtifactKind::Cache)));
+    if let Some(logfile) = logger::get_logfile() {
+        // the log plus any rotated copies (core.log.1, core.log.2, ...)
+        for path in ::glob::glob(format!("{}*", logfile).as_str())? {
+            found.push((path?, ArtifactKind::Log));
+        }
+    }
+    Ok(found)
+}
+
+/// Securely delete everything holding profile data, then make sure it's gone.
+/// Make sure all the databases are closed first.
+pub fn wipe_local_data() -> TResult<WipeReport> {
+    let mut report = WipeReport::default();
+    let active_log = logger::get_logfile().map(PathBuf::from);
+    for (path, kind) in find_artifacts()? {
+        let size = fs::metadata(&path).map(|x| x.len()).unwrap_or(0);
+        match util::secure_delete(&path) {
+            Ok(_) => {}
+            // some platforms won't remove a file that's open, and the logger
+            // has its file open. if we can't remove it, empty it out.
+            Err(_) if Some(&path) == active_log.as_ref() => {
+                fs::OpenOptions::new().write(true).open(&path)?.set_len(0)?;
+            }
+            Err(e) => return Err(e),
+        }
+        info!("wipe::wipe_local_data() -- removed {} ({:?}, {} bytes)", path.display(), kind, size);
+        report.bytes += size;
+        report.removed.push(WipedFile {
+            path: path.to_string_lossy().into_owned(),
+            kind: kind,
+            size: size,
+        });
+    }
+
+    // now look again. the active log doesn't count: we just wrote to it.
+    report.leftovers = find_artifacts()?
+        .into_iter()
+        .filter(|&(ref path, _)| Some(path) != active_log.as_ref())
+        .map(|(path, _)| path.to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+    report.verified = report.leftovers.len() == 0;
+    if !report.verified {
+        error!("wipe::wipe_local_data() -- files left after wipe: {:?}", report.leftovers);
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_artifacts() {
+        assert_eq!(classify("turtl-kv.sqlite"), Some(ArtifactKind::Database));
+        assert_eq!(classify("turtl-user-51.sqlite-journal"), Some(ArtifactKind::Database));
+        assert_eq!(classify("turtl-user-51.sqlite.corrupt.1500000000"), Some(ArtifactKind::Backup));
+        assert_eq!(classify("51.login"), Some(ArtifactKind::Login));
+        assert_eq!(classify("run.lock"), None);
+        assert_eq!(classify("turtl"), None);
+    }
+}